@@ -0,0 +1,75 @@
+//! Terminal dashboard for the nori-observe event stream.
+//!
+//! [`Dashboard`] folds [`VizEvent`](nori_observe::VizEvent)s into four
+//! panels: a timeline of segment rolls, a sparkline of fsync latencies,
+//! recovery and corruption alerts, and activity per node. [`run`] draws them
+//! in the terminal until `q` or Esc is pressed.
+//!
+//! Events come from an [`EventSource`]: a [`Subscriber`] of an in-process
+//! [`EventBus`], or a [`JsonlTail`] following the file a
+//! `nori-observe-jsonl` meter writes, which is what the `nori-viz` binary
+//! does.
+//!
+//! ```no_run
+//! use nori_observe::EventBus;
+//! use std::sync::Arc;
+//!
+//! let bus = EventBus::new(4096);
+//! let mut events = bus.subscribe();
+//! // Hand `Arc::new(bus)` to the WAL and other components, then
+//! nori_viz::run(&mut events)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`Subscriber`]: nori_observe::Subscriber
+//! [`EventBus`]: nori_observe::EventBus
+
+mod source;
+mod state;
+mod ui;
+
+pub use source::{EventSource, JsonlTail};
+pub use state::{describe, Alert, Dashboard, NodeActivity, Roll};
+pub use ui::draw;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::DefaultTerminal;
+use std::io;
+use std::time::Duration;
+
+/// Time between redraws, and the longest a key press waits to be seen.
+const TICK: Duration = Duration::from_millis(250);
+/// Events applied per redraw at most, so a flood can't freeze the screen.
+const EVENTS_PER_TICK: usize = 10_000;
+
+/// Takes over the terminal and shows the dashboard until `q` or Esc is
+/// pressed, then restores it.
+pub fn run(source: &mut dyn EventSource) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_in(&mut terminal, source);
+    ratatui::restore();
+    result
+}
+
+fn run_in(terminal: &mut DefaultTerminal, source: &mut dyn EventSource) -> io::Result<()> {
+    let mut dashboard = Dashboard::default();
+    loop {
+        for _ in 0..EVENTS_PER_TICK {
+            match source.try_next()? {
+                Some(evt) => dashboard.apply(&evt),
+                None => break,
+            }
+        }
+        terminal.draw(|frame| draw(frame, &dashboard))?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press
+                    && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+//! `nori-viz [--from-start] <telemetry.jsonl>`: shows the events a
+//! nori-observe-jsonl meter writes to `telemetry.jsonl` as they arrive.
+
+use nori_viz::JsonlTail;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: nori-viz [--from-start] <telemetry.jsonl>
+
+Follows a file written by nori-observe-jsonl and shows its events live.
+  --from-start   replay the events already in the file first";
+
+fn main() -> ExitCode {
+    let mut from_start = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--from-start" => from_start = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let tail = if from_start {
+        Ok(JsonlTail::from_start(&path))
+    } else {
+        JsonlTail::open(&path)
+    };
+    let result = tail.and_then(|mut tail| nori_viz::run(&mut tail));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nori-viz: {}: {}", path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Where the dashboard gets its events from.
+
+use nori_observe::{Subscriber, VizEvent};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// A stream of events the dashboard polls between frames.
+pub trait EventSource {
+    /// Returns the next event if one is available, without blocking.
+    fn try_next(&mut self) -> io::Result<Option<VizEvent>>;
+}
+
+/// Reads an in-process [`EventBus`](nori_observe::EventBus).
+impl EventSource for Subscriber {
+    fn try_next(&mut self) -> io::Result<Option<VizEvent>> {
+        Ok(self.try_recv())
+    }
+}
+
+/// Follows a file written by nori-observe-jsonl, like `tail -f`.
+///
+/// Only event lines are returned; metric lines and lines that don't parse
+/// are skipped. When the file shrinks, because it was rotated or truncated,
+/// reading starts over at its beginning.
+pub struct JsonlTail {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    /// Bytes of the current file consumed, up to the last full line.
+    pos: u64,
+    /// A line read before its newline was written.
+    partial: String,
+}
+
+impl JsonlTail {
+    /// Follows `path`, starting at its end so only new events are shown.
+    /// The file doesn't have to exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let mut tail = Self::from_start(path);
+        tail.reopen_if_needed()?;
+        if let Some(reader) = &mut tail.reader {
+            tail.pos = reader.seek(SeekFrom::End(0))?;
+        }
+        Ok(tail)
+    }
+
+    /// Follows `path` from its first line, replaying what is already there.
+    pub fn from_start(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            reader: None,
+            pos: 0,
+            partial: String::new(),
+        }
+    }
+
+    /// Opens the file if it isn't yet, or again if it was rotated or
+    /// truncated. Leaves `reader` empty if the file doesn't exist.
+    fn reopen_if_needed(&mut self) -> io::Result<()> {
+        let len = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.reader = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if len < self.pos {
+            self.reader = None;
+        }
+        if self.reader.is_none() {
+            self.reader = Some(BufReader::new(File::open(&self.path)?));
+            self.pos = 0;
+            self.partial.clear();
+        }
+        Ok(())
+    }
+}
+
+impl EventSource for JsonlTail {
+    fn try_next(&mut self) -> io::Result<Option<VizEvent>> {
+        loop {
+            self.reopen_if_needed()?;
+            let Some(reader) = &mut self.reader else {
+                return Ok(None);
+            };
+            let read = reader.read_line(&mut self.partial)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            if !self.partial.ends_with('\n') {
+                // The writer is mid-line; the rest comes on a later poll
+                continue;
+            }
+            self.pos += self.partial.len() as u64;
+            let line = std::mem::take(&mut self.partial);
+            if let Some(evt) = parse_event(&line) {
+                return Ok(Some(evt));
+            }
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Option<VizEvent> {
+    let mut value: serde_json::Value = serde_json::from_str(line).ok()?;
+    if value.get("type")?.as_str()? != "event" {
+        return None;
+    }
+    serde_json::from_value(value.get_mut("event")?.take()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const ROLL: &str = r#"{"ts_ms":1,"type":"event","event":{"Wal":{"node":1,"seg":4,"kind":{"SegmentRoll":{"bytes":64}}}}}"#;
+    const COUNTER: &str =
+        r#"{"ts_ms":2,"type":"counter","metric":"appends","labels":{},"value":1}"#;
+
+    #[test]
+    fn test_follows_appends_and_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nori.jsonl");
+        let mut tail = JsonlTail::open(&path).unwrap();
+        assert!(tail.try_next().unwrap().is_none());
+
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}", COUNTER).unwrap();
+        write!(file, "{}", &ROLL[..20]).unwrap();
+        file.flush().unwrap();
+        assert!(tail.try_next().unwrap().is_none());
+
+        writeln!(file, "{}", &ROLL[20..]).unwrap();
+        file.flush().unwrap();
+        let evt = tail.try_next().unwrap().unwrap();
+        assert!(matches!(evt, VizEvent::Wal(e) if e.seg == 4));
+        assert!(tail.try_next().unwrap().is_none());
+
+        // Rotated: a new, shorter file takes its place
+        std::fs::write(&path, format!("{}\n", ROLL)).unwrap();
+        assert!(tail.try_next().unwrap().is_some());
+    }
+}
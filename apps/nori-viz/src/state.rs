@@ -0,0 +1,304 @@
+//! What the panels show, folded from the event stream.
+
+use nori_observe::{CompKind, RaftKind, Severity, VizEvent, WalKind};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Segment rolls kept for the timeline.
+const MAX_ROLLS: usize = 256;
+/// Fsync latencies kept for the sparkline.
+const MAX_FSYNCS: usize = 512;
+/// Alerts kept for the alert list.
+const MAX_ALERTS: usize = 128;
+
+/// A segment roll, for the timeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Roll {
+    /// Time since the dashboard started.
+    pub at: Duration,
+    pub node: u32,
+    /// The segment that was sealed.
+    pub seg: u64,
+    pub bytes: u64,
+}
+
+/// An event worth a line in the alert panel: anything at warn or above,
+/// plus recoveries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert {
+    /// Time since the dashboard started.
+    pub at: Duration,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Events seen from one node, by component.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeActivity {
+    pub wal: u64,
+    pub compaction: u64,
+    pub raft: u64,
+    pub swim: u64,
+    /// When the node was last heard of, since the dashboard started.
+    pub last_seen: Duration,
+}
+
+/// Recent history of the event stream, bounded in size.
+#[derive(Debug)]
+pub struct Dashboard {
+    started: Instant,
+    events: u64,
+    rolls: VecDeque<Roll>,
+    fsync_ms: VecDeque<u64>,
+    alerts: VecDeque<Alert>,
+    nodes: BTreeMap<u32, NodeActivity>,
+}
+
+impl Default for Dashboard {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            events: 0,
+            rolls: VecDeque::new(),
+            fsync_ms: VecDeque::new(),
+            alerts: VecDeque::new(),
+            nodes: BTreeMap::new(),
+        }
+    }
+}
+
+/// Appends to a bounded queue, dropping the oldest entry when full.
+fn push_bounded<T>(queue: &mut VecDeque<T>, max: usize, item: T) {
+    if queue.len() == max {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+impl Dashboard {
+    /// Folds one event into the panels.
+    pub fn apply(&mut self, evt: &VizEvent) {
+        let at = self.started.elapsed();
+        self.events += 1;
+
+        match evt {
+            VizEvent::Wal(e) => {
+                self.node(e.node, at).wal += 1;
+                match e.kind {
+                    WalKind::SegmentRoll { bytes } => push_bounded(
+                        &mut self.rolls,
+                        MAX_ROLLS,
+                        Roll {
+                            at,
+                            node: e.node,
+                            seg: e.seg,
+                            bytes,
+                        },
+                    ),
+                    WalKind::Fsync { ms } => {
+                        push_bounded(&mut self.fsync_ms, MAX_FSYNCS, u64::from(ms))
+                    }
+                    _ => {}
+                }
+            }
+            VizEvent::Compaction(e) => self.node(e.node, at).compaction += 1,
+            VizEvent::Raft(e) => match e.kind {
+                RaftKind::VoteReq { from } | RaftKind::VoteGranted { from } => {
+                    self.node(from, at).raft += 1
+                }
+                RaftKind::LeaderElected { node } => self.node(node, at).raft += 1,
+                RaftKind::StepDown => {}
+            },
+            VizEvent::Swim(e) => self.node(e.node, at).swim += 1,
+            _ => {}
+        }
+
+        let severity = evt.severity();
+        let recovered = matches!(
+            evt,
+            VizEvent::Wal(e) if matches!(e.kind, WalKind::RecoveryCompleted { .. })
+        );
+        if severity >= Severity::Warn || recovered {
+            let alert = Alert {
+                at,
+                severity,
+                message: describe(evt),
+            };
+            push_bounded(&mut self.alerts, MAX_ALERTS, alert);
+        }
+    }
+
+    fn node(&mut self, node: u32, at: Duration) -> &mut NodeActivity {
+        let activity = self.nodes.entry(node).or_default();
+        activity.last_seen = at;
+        activity
+    }
+
+    /// Events applied so far.
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    /// Recent segment rolls, oldest first.
+    pub fn rolls(&self) -> &VecDeque<Roll> {
+        &self.rolls
+    }
+
+    /// Recent fsync latencies in milliseconds, oldest first.
+    pub fn fsync_ms(&self) -> &VecDeque<u64> {
+        &self.fsync_ms
+    }
+
+    /// Recent alerts, oldest first.
+    pub fn alerts(&self) -> &VecDeque<Alert> {
+        &self.alerts
+    }
+
+    /// Activity per node, by node id.
+    pub fn nodes(&self) -> &BTreeMap<u32, NodeActivity> {
+        &self.nodes
+    }
+
+    /// Time since the dashboard started.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// One-line description of an event for the alert panel.
+pub fn describe(evt: &VizEvent) -> String {
+    match evt {
+        VizEvent::Wal(e) => {
+            let what = match &e.kind {
+                WalKind::CorruptionTruncated {
+                    offset,
+                    bytes,
+                    cause,
+                } => format!(
+                    "truncated {} bytes at offset {} ({:?} failure)",
+                    bytes, offset, cause
+                ),
+                WalKind::CorruptionDetected { offset } => {
+                    format!("corruption found at offset {}", offset)
+                }
+                WalKind::SegmentVerifyFailed { error } => {
+                    format!("sealed segment unreadable: {}", error)
+                }
+                WalKind::UnsyncedDrop { bytes } => {
+                    format!("dropped with {} unsynced bytes", bytes)
+                }
+                WalKind::AppendBackpressure { queued_bytes } => {
+                    format!("appends backed up, {} bytes queued", queued_bytes)
+                }
+                WalKind::SlowFsync { ms, threshold_ms } => {
+                    format!("fsync took {}ms (threshold {}ms)", ms, threshold_ms)
+                }
+                WalKind::RecoveryCompleted { ms, records } => {
+                    format!("recovered {} records in {}ms", records, ms)
+                }
+                WalKind::LatencySloBurn {
+                    op,
+                    threshold_ms,
+                    burn_rate,
+                } => format!(
+                    "{} latency SLO ({}ms) burning at {:.1}x",
+                    op.as_str(),
+                    threshold_ms,
+                    burn_rate
+                ),
+                WalKind::LatencySloRecovered { op, burn_rate } => format!(
+                    "{} latency SLO recovered, burning at {:.1}x",
+                    op.as_str(),
+                    burn_rate
+                ),
+                other => format!("{:?}", other),
+            };
+            format!("node {} wal seg {}: {}", e.node, e.seg, what)
+        }
+        VizEvent::Compaction(e) => match e.kind {
+            CompKind::Finish {
+                in_bytes,
+                out_bytes,
+            } => format!(
+                "node {} L{} compaction: {} -> {} bytes",
+                e.node, e.level, in_bytes, out_bytes
+            ),
+            ref other => format!("node {} L{} compaction: {:?}", e.node, e.level, other),
+        },
+        VizEvent::Raft(e) => format!("shard {} term {}: {:?}", e.shard, e.term, e.kind),
+        VizEvent::Swim(e) => format!("node {} swim: {:?}", e.node, e.kind),
+        VizEvent::Shard(e) => format!("shard {}: {:?}", e.shard, e.kind),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{CorruptionCause, SwimEvt, SwimKind, WalEvt};
+
+    fn wal(node: u32, seg: u64, kind: WalKind) -> VizEvent {
+        VizEvent::Wal(WalEvt { node, seg, kind })
+    }
+
+    #[test]
+    fn test_folds_events_into_panels() {
+        let mut dashboard = Dashboard::default();
+        dashboard.apply(&wal(1, 3, WalKind::SegmentRoll { bytes: 4096 }));
+        dashboard.apply(&wal(1, 4, WalKind::Fsync { ms: 2 }));
+        dashboard.apply(&wal(2, 0, WalKind::Fsync { ms: 7 }));
+        dashboard.apply(&wal(
+            2,
+            0,
+            WalKind::CorruptionTruncated {
+                offset: 128,
+                bytes: 40,
+                cause: CorruptionCause::Crc,
+            },
+        ));
+        dashboard.apply(&wal(
+            2,
+            0,
+            WalKind::RecoveryCompleted { ms: 12, records: 9 },
+        ));
+        dashboard.apply(&VizEvent::Swim(SwimEvt {
+            node: 3,
+            kind: SwimKind::Alive,
+        }));
+
+        assert_eq!(dashboard.events(), 6);
+        let rolls: Vec<_> = dashboard.rolls().iter().map(|r| (r.node, r.seg)).collect();
+        assert_eq!(rolls, [(1, 3)]);
+        assert_eq!(
+            dashboard.fsync_ms().iter().copied().collect::<Vec<_>>(),
+            [2, 7]
+        );
+
+        let alerts: Vec<_> = dashboard.alerts().iter().collect();
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(alerts[0].severity, Severity::Error);
+        assert_eq!(
+            alerts[0].message,
+            "node 2 wal seg 0: truncated 40 bytes at offset 128 (Crc failure)"
+        );
+        assert_eq!(
+            alerts[1].message,
+            "node 2 wal seg 0: recovered 9 records in 12ms"
+        );
+
+        let nodes = dashboard.nodes();
+        assert_eq!(nodes[&1].wal, 2);
+        assert_eq!(nodes[&2].wal, 3);
+        assert_eq!(nodes[&3].swim, 1);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut dashboard = Dashboard::default();
+        for ms in 0..MAX_FSYNCS as u32 + 10 {
+            dashboard.apply(&wal(1, 0, WalKind::Fsync { ms }));
+        }
+        assert_eq!(dashboard.fsync_ms().len(), MAX_FSYNCS);
+        assert_eq!(dashboard.fsync_ms().front(), Some(&10));
+    }
+}
@@ -0,0 +1,170 @@
+//! Drawing the panels.
+
+use crate::state::Dashboard;
+use nori_observe::Severity;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+use std::time::Duration;
+
+/// Draws the whole dashboard: a header, then rolls and fsyncs on the left,
+/// alerts and nodes on the right.
+pub fn draw(frame: &mut Frame, dashboard: &Dashboard) {
+    let [header, body] =
+        Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(frame.area());
+    let [left, right] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(body);
+    let [rolls, fsyncs] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+    let [alerts, nodes] =
+        Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(right);
+
+    let title = format!(
+        " nori-viz  {} events  up {}  (q to quit)",
+        dashboard.events(),
+        clock(dashboard.uptime())
+    );
+    frame.render_widget(Paragraph::new(title).bold(), header);
+    draw_rolls(frame, rolls, dashboard);
+    draw_fsyncs(frame, fsyncs, dashboard);
+    draw_alerts(frame, alerts, dashboard);
+    draw_nodes(frame, nodes, dashboard);
+}
+
+/// Formats a time since start as `h:mm:ss`.
+fn clock(at: Duration) -> String {
+    let secs = at.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn draw_rolls(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    // Newest first, as many as fit
+    let visible = area.height.saturating_sub(3) as usize;
+    let rows = dashboard.rolls().iter().rev().take(visible).map(|roll| {
+        Row::new([
+            clock(roll.at),
+            roll.node.to_string(),
+            roll.seg.to_string(),
+            format!("{:.1} MiB", roll.bytes as f64 / (1024.0 * 1024.0)),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(9),
+            Constraint::Length(6),
+            Constraint::Length(8),
+            Constraint::Min(10),
+        ],
+    )
+    .header(Row::new(["time", "node", "segment", "size"]).bold())
+    .block(Block::bordered().title(" Segment rolls "));
+    frame.render_widget(table, area);
+}
+
+fn draw_fsyncs(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let samples = dashboard.fsync_ms();
+    // One bar per column, so show only the latest that fit
+    let width = area.width.saturating_sub(2) as usize;
+    let recent: Vec<u64> = samples
+        .iter()
+        .skip(samples.len().saturating_sub(width))
+        .copied()
+        .collect();
+    let title = match (recent.last(), recent.iter().max()) {
+        (Some(last), Some(max)) => format!(" Fsync latency  last {}ms  max {}ms ", last, max),
+        _ => " Fsync latency ".to_string(),
+    };
+    let sparkline = Sparkline::default()
+        .data(&recent)
+        .style(Style::new().fg(Color::Cyan))
+        .block(Block::bordered().title(title));
+    frame.render_widget(sparkline, area);
+}
+
+fn draw_alerts(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let visible = area.height.saturating_sub(2) as usize;
+    let items: Vec<ListItem> = dashboard
+        .alerts()
+        .iter()
+        .rev()
+        .take(visible)
+        .map(|alert| {
+            let color = match alert.severity {
+                Severity::Error => Color::Red,
+                Severity::Warn => Color::Yellow,
+                Severity::Info | Severity::Debug => Color::Green,
+            };
+            ListItem::new(Line::from(vec![
+                Span::raw(format!("{} ", clock(alert.at))),
+                Span::styled(alert.message.clone(), Style::new().fg(color)),
+            ]))
+        })
+        .collect();
+    let list = List::new(items).block(Block::bordered().title(" Recovery and corruption "));
+    frame.render_widget(list, area);
+}
+
+fn draw_nodes(frame: &mut Frame, area: Rect, dashboard: &Dashboard) {
+    let now = dashboard.uptime();
+    let rows = dashboard.nodes().iter().map(|(node, activity)| {
+        Row::new([
+            node.to_string(),
+            activity.wal.to_string(),
+            activity.compaction.to_string(),
+            activity.raft.to_string(),
+            activity.swim.to_string(),
+            format!("{}s ago", now.saturating_sub(activity.last_seen).as_secs()),
+        ])
+    });
+    let table = Table::new(rows, [Constraint::Length(8); 6])
+        .header(Row::new(["node", "wal", "compact", "raft", "swim", "seen"]).bold())
+        .block(Block::bordered().title(" Nodes "));
+    frame.render_widget(table, area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{CorruptionCause, VizEvent, WalEvt, WalKind};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_draws_every_panel() {
+        let mut dashboard = Dashboard::default();
+        for kind in [
+            WalKind::SegmentRoll { bytes: 128 << 20 },
+            WalKind::Fsync { ms: 3 },
+            WalKind::CorruptionTruncated {
+                offset: 64,
+                bytes: 10,
+                cause: CorruptionCause::Framing,
+            },
+        ] {
+            dashboard.apply(&VizEvent::Wal(WalEvt {
+                node: 7,
+                seg: 2,
+                kind,
+            }));
+        }
+
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| draw(frame, &dashboard)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+
+        assert!(screen.contains("3 events"));
+        assert!(screen.contains("128.0 MiB"));
+        assert!(screen.contains("last 3ms"));
+        assert!(screen.contains("truncated 10 bytes at offset 64"));
+        assert!(screen.contains(" Nodes "));
+    }
+}
@@ -0,0 +1,767 @@
+//! The store: a WAL and memtable in front of leveled SSTables.
+
+use crate::error::DbError;
+use crate::snapshot::Snapshot;
+use crate::transfer::TRANSFER_DIR;
+use bytes::Bytes;
+use nori_lsm::{
+    table_file_name, Compaction, CompactionConfig, Compactor, Manifest, ManifestConfig, TableMeta,
+    Version, VersionEdit, NUM_LEVELS,
+};
+use nori_memtable::{MemEntry, Memtable, MemtableConfig};
+use nori_sstable::{write_table, BlockCache, CacheConfig, SstReader};
+use nori_wal::{Record, Wal, WalConfig, WalTail};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+/// Subdirectory of the store holding the WAL.
+pub const WAL_DIR: &str = "wal";
+
+/// Settings for a [`Db`].
+#[derive(Debug, Clone)]
+pub struct DbConfig {
+    /// WAL settings. `dir` is replaced by the store's `wal` subdirectory
+    /// (default: `purge_on_checkpoint` enabled).
+    pub wal: WalConfig,
+    /// The memtable is flushed to an L0 table once full.
+    pub memtable: MemtableConfig,
+    pub manifest: ManifestConfig,
+    /// Compaction settings; `compaction.sst` is also used for flushed
+    /// tables.
+    pub compaction: CompactionConfig,
+    /// Block cache shared by every table.
+    pub cache: CacheConfig,
+    /// Compact L0 into L1 once it holds this many tables (default: 4).
+    pub l0_compaction_trigger: usize,
+    /// Size L1 may reach before its tables move down (default: 256 MiB).
+    pub level_base_bytes: u64,
+    /// Each deeper level may hold this many times the one above (default: 10).
+    pub level_multiplier: u64,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            wal: WalConfig {
+                purge_on_checkpoint: true,
+                ..Default::default()
+            },
+            memtable: MemtableConfig::default(),
+            manifest: ManifestConfig::default(),
+            compaction: CompactionConfig::default(),
+            cache: CacheConfig::default(),
+            l0_compaction_trigger: 4,
+            level_base_bytes: 256 * 1024 * 1024,
+            level_multiplier: 10,
+        }
+    }
+}
+
+/// What reads see: the memtable and the live tables, swapped as a whole
+/// when a flush or compaction installs new tables.
+///
+/// The tables never change, and the memtable only gains writes newer than
+/// any sequence taken while the state was current, so a reader holding a
+/// state reads consistently as of such a sequence.
+pub(crate) struct State {
+    memtable: Arc<Memtable>,
+    version: Arc<Version>,
+    tables: HashMap<u64, Arc<SstReader>>,
+}
+
+impl State {
+    /// Returns the value of `key` as of sequence `seq`.
+    pub(crate) fn get(&self, key: &[u8], seq: u64) -> Result<Option<Bytes>, DbError> {
+        let now = SystemTime::now();
+        if let Some(entry) = self.memtable.entry_as_of(key, seq) {
+            return Ok(entry.value_at(now).cloned());
+        }
+        for table in self.tables_for(key) {
+            if let Some(entry) = table.get(key)? {
+                return Ok(entry.value_at(now).cloned());
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the keys in the range with their values as of sequence
+    /// `seq`, in key order.
+    pub(crate) fn scan(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        seq: u64,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        let entries = self.scan_entries(start, end, seq)?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|(key, entry)| Some((key, entry.value?)))
+            .collect())
+    }
+
+    /// Like [`State::scan`], returning the entries with their TTL expiry.
+    /// Deletes and expired values are left out.
+    pub(crate) fn scan_entries(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        seq: u64,
+    ) -> Result<Vec<(Bytes, MemEntry)>, DbError> {
+        let now = SystemTime::now();
+
+        // Oldest first, so newer entries replace older ones
+        let mut merged: BTreeMap<Bytes, MemEntry> = BTreeMap::new();
+        for table in self.tables_in(start, end) {
+            for next in table.iter() {
+                let (key, entry) = next?;
+                if after(&key, end) {
+                    break;
+                }
+                if !before(&key, start) {
+                    merged.insert(key, entry);
+                }
+            }
+        }
+        merged.extend(self.memtable.range_as_of(start, end, seq));
+
+        merged.retain(|_, entry| entry.value_at(now).is_some());
+        Ok(merged.into_iter().collect())
+    }
+
+    /// Tables that may hold `key`, newest first.
+    fn tables_for<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = &'a Arc<SstReader>> {
+        let l0 = self.version.level(0).iter().rev();
+        let deeper = (1..NUM_LEVELS).flat_map(|level| self.version.level(level));
+        l0.chain(deeper)
+            .filter(move |t| t.overlaps(key, key))
+            .map(|t| &self.tables[&t.id])
+    }
+
+    /// Tables that may hold keys in the range, oldest first.
+    fn tables_in<'a>(
+        &'a self,
+        start: Bound<&'a [u8]>,
+        end: Bound<&'a [u8]>,
+    ) -> impl Iterator<Item = &'a Arc<SstReader>> {
+        let deeper = (1..NUM_LEVELS)
+            .rev()
+            .flat_map(|level| self.version.level(level));
+        deeper
+            .chain(self.version.level(0))
+            .filter(move |t| !before(&t.largest_key, start) && !after(&t.smallest_key, end))
+            .map(|t| &self.tables[&t.id])
+    }
+}
+
+/// An embedded key-value store.
+///
+/// Writes go to the WAL first and are then applied to the memtable, so a
+/// write is never visible before it is logged. A full memtable is written
+/// to an L0 table, recorded in the manifest together with the WAL position
+/// it covers, and only then checkpointed in the WAL; after a crash the
+/// tables in the manifest plus the WAL from that position hold every write.
+/// Whether a write survives a crash once `put` returns depends on the WAL's
+/// `fsync_policy`; [`Db::sync`] makes everything written so far durable.
+///
+/// Every write gets the next sequence number, which is also its WAL LSN.
+/// [`Db::snapshot`] reads as of the sequence it was taken at, however many
+/// writes, flushes and compactions follow.
+///
+/// Writes are serialized, and a write that fills the memtable also runs the
+/// flush and any compactions it triggers. Reads never wait for writes, but
+/// may block on disk reads.
+pub struct Db {
+    path: PathBuf,
+    config: DbConfig,
+    wal: Wal,
+    manifest: Arc<Manifest>,
+    compactor: Arc<Compactor>,
+    cache: Arc<BlockCache>,
+    state: RwLock<Arc<State>>,
+    write_lock: Mutex<()>,
+    /// Sequence of the latest write visible to reads.
+    last_seq: AtomicU64,
+    /// Sequences of open snapshots, with how many are open at each.
+    snapshots: std::sync::Mutex<BTreeMap<u64, usize>>,
+}
+
+impl Db {
+    /// Opens or creates the store at `path` with default settings.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, DbError> {
+        Self::open_with_config(path, DbConfig::default()).await
+    }
+
+    /// Opens or creates the store at `path`.
+    ///
+    /// The memtable is rebuilt from the WAL records after the manifest's
+    /// checkpoint, and table files left behind by an interrupted flush or
+    /// compaction are deleted, as are the links of unfinished snapshot
+    /// transfers.
+    pub async fn open_with_config(
+        path: impl Into<PathBuf>,
+        config: DbConfig,
+    ) -> Result<Self, DbError> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        let manifest = Manifest::open(&path, config.manifest.clone())?;
+        let version = manifest.current();
+        remove_orphans(&path, &version)?;
+        // Tables linked for transfers that never finished
+        match std::fs::remove_dir_all(path.join(TRANSFER_DIR)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+
+        // Records before the checkpoint are already in tables
+        let checkpoint = version.wal_checkpoint();
+        let memtable = Memtable::with_config(config.memtable.clone());
+        let wal_config = WalConfig {
+            dir: path.join(WAL_DIR),
+            ..config.wal.clone()
+        };
+        let (wal, _) = Wal::open_with_replay(wal_config, |record, position| {
+            if checkpoint.map_or(true, |checkpoint| position >= checkpoint) {
+                memtable.apply(&record);
+            }
+        })
+        .await?;
+
+        let cache = Arc::new(BlockCache::new(config.cache.clone()));
+        let mut tables = HashMap::new();
+        for table in version.tables() {
+            let reader =
+                SstReader::open_with_cache(path.join(table_file_name(table.id)), cache.clone())?;
+            tables.insert(table.id, Arc::new(reader));
+        }
+        // The WAL may have purged every record, so the manifest remembers
+        // the sequence too
+        let last_seq = (wal.next_lsn() - 1)
+            .max(version.last_sequence())
+            .max(memtable.max_lsn().unwrap_or(0));
+        let state = State {
+            memtable: Arc::new(memtable),
+            version,
+            tables,
+        };
+
+        Ok(Self {
+            compactor: Arc::new(Compactor::new(&path, config.compaction.clone())),
+            manifest: Arc::new(manifest),
+            path,
+            config,
+            wal,
+            cache,
+            state: RwLock::new(Arc::new(state)),
+            write_lock: Mutex::new(()),
+            last_seq: AtomicU64::new(last_seq),
+            snapshots: std::sync::Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// Returns the sequence number of the latest write visible to reads.
+    pub fn sequence(&self) -> u64 {
+        self.last_seq.load(Ordering::Acquire)
+    }
+
+    /// Takes a snapshot of the store as of the latest write.
+    ///
+    /// Reads through it see neither later writes nor the effect of later
+    /// flushes and compactions. While it is open the memtable keeps the
+    /// versions it needs and the tables it reads stay open, so long-lived
+    /// snapshots hold on to memory and disk space.
+    pub async fn snapshot(&self) -> Snapshot<'_> {
+        // Held so no write lands between reading the sequence and lowering
+        // the memtable's floor to it
+        let _guard = self.write_lock.lock().await;
+        let seq = self.sequence();
+        let mut snapshots = self.snapshots.lock().unwrap();
+        *snapshots.entry(seq).or_default() += 1;
+        let state = self.state();
+        state
+            .memtable
+            .set_snapshot_floor(snapshots.keys().next().copied());
+        Snapshot::new(self, seq, state)
+    }
+
+    /// Returns the store's directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the WAL, for metrics, replication or backups.
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    /// Returns the current set of tables.
+    pub fn version(&self) -> Arc<Version> {
+        self.state().version.clone()
+    }
+
+    /// Returns the block cache shared by the tables.
+    pub fn cache(&self) -> &BlockCache {
+        &self.cache
+    }
+
+    /// Sets `key` to `value`.
+    pub async fn put(&self, key: impl Into<Bytes>, value: impl Into<Bytes>) -> Result<(), DbError> {
+        self.write(Record::put(key, value)).await
+    }
+
+    /// Sets `key` to `value` until `ttl` has passed, after which it reads as
+    /// deleted.
+    pub async fn put_with_ttl(
+        &self,
+        key: impl Into<Bytes>,
+        value: impl Into<Bytes>,
+        ttl: Duration,
+    ) -> Result<(), DbError> {
+        self.write(Record::put_with_ttl(key, value, ttl)).await
+    }
+
+    /// Deletes `key`.
+    pub async fn delete(&self, key: impl Into<Bytes>) -> Result<(), DbError> {
+        self.write(Record::delete(key)).await
+    }
+
+    /// Applies a put or delete record, such as one read from another
+    /// store's WAL.
+    ///
+    /// The record gets the next sequence number in place of its LSN. Its
+    /// timestamp, if it has one, is kept, so a TTL expires when it would
+    /// have at the source.
+    pub async fn apply(&self, record: Record) -> Result<(), DbError> {
+        self.write(record).await
+    }
+
+    /// Returns the value of `key`, if it is set.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>, DbError> {
+        self.state().get(key, u64::MAX)
+    }
+
+    /// Returns the keys in the range with their values, in key order.
+    pub fn scan(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        self.state().scan(start, end, u64::MAX)
+    }
+
+    /// Makes every write so far durable.
+    pub async fn sync(&self) -> Result<(), DbError> {
+        Ok(self.wal.sync().await?)
+    }
+
+    /// Writes the memtable to a table, if it holds anything, and runs any
+    /// compactions that makes due.
+    pub async fn flush(&self) -> Result<(), DbError> {
+        let _guard = self.write_lock.lock().await;
+        self.flush_locked().await
+    }
+
+    /// Syncs and closes the WAL. Unflushed writes are replayed on the next
+    /// open.
+    pub async fn close(self) -> Result<(), DbError> {
+        Ok(self.wal.close().await?)
+    }
+
+    /// Flushes, then hard-links every live table into `dir` so compactions
+    /// cannot delete them, and opens a WAL tail just after the flushed
+    /// writes. Returns the sequence the tables cover, with their version.
+    pub(crate) async fn link_tables(
+        &self,
+        dir: &Path,
+    ) -> Result<(u64, Arc<Version>, WalTail), DbError> {
+        let _guard = self.write_lock.lock().await;
+        self.flush_locked().await?;
+        let seq = self.sequence();
+        let version = self.version();
+        std::fs::create_dir_all(dir)?;
+        for table in version.tables() {
+            let name = table_file_name(table.id);
+            std::fs::hard_link(self.path.join(&name), dir.join(&name))?;
+        }
+        let tail = self.wal.tail_from_lsn(seq + 1).await?;
+        Ok((seq, version, tail))
+    }
+
+    fn state(&self) -> Arc<State> {
+        self.state.read().unwrap().clone()
+    }
+
+    fn open_table(&self, id: u64) -> Result<Arc<SstReader>, DbError> {
+        let path = self.path.join(table_file_name(id));
+        Ok(Arc::new(SstReader::open_with_cache(
+            path,
+            self.cache.clone(),
+        )?))
+    }
+
+    async fn write(&self, record: Record) -> Result<(), DbError> {
+        let _guard = self.write_lock.lock().await;
+        // Stamped here rather than by the WAL, so replay rebuilds the same
+        // entry, TTL included
+        let seq = self.wal.next_lsn().max(self.sequence() + 1);
+        let timestamp = record.timestamp.unwrap_or_else(SystemTime::now);
+        let record = record.with_lsn(seq).with_timestamp(timestamp);
+        self.wal.append(&record).await?;
+
+        let memtable = self.state().memtable.clone();
+        memtable.apply(&record);
+        self.last_seq.store(seq, Ordering::Release);
+        if memtable.is_full() {
+            self.flush_locked().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush_locked(&self) -> Result<(), DbError> {
+        let state = self.state();
+        if state.memtable.is_empty() {
+            return Ok(());
+        }
+
+        // Writes are held off, so the memtable holds exactly the records
+        // before this position
+        let checkpoint = self.wal.current_position().await;
+        let id = self.manifest.new_table_id();
+        let entries = state.memtable.entries();
+        let edit_base = VersionEdit {
+            wal_checkpoint: Some(checkpoint),
+            last_sequence: state.memtable.max_lsn(),
+            ..Default::default()
+        };
+        let table_path = self.path.join(table_file_name(id));
+        let sst = self.config.compaction.sst.clone();
+        let manifest = self.manifest.clone();
+        let version = tokio::task::spawn_blocking(move || -> Result<_, DbError> {
+            let info = write_table(table_path, sst, entries)?;
+            let edit = VersionEdit {
+                added: vec![TableMeta::from_info(id, 0, &info)],
+                ..edit_base
+            };
+            Ok(manifest.apply(edit)?)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+
+        let mut tables = state.tables.clone();
+        tables.insert(id, self.open_table(id)?);
+        let memtable = Memtable::with_config(self.config.memtable.clone());
+        {
+            // Under the snapshot lock, so a snapshot closing meanwhile
+            // cannot leave the new memtable with a stale floor
+            let snapshots = self.snapshots.lock().unwrap();
+            memtable.set_snapshot_floor(snapshots.keys().next().copied());
+            self.install(State {
+                memtable: Arc::new(memtable),
+                version,
+                tables,
+            });
+        }
+
+        // Only now that the manifest covers them may the WAL drop the records
+        self.wal.checkpoint(checkpoint).await?;
+        self.compact_locked().await
+    }
+
+    async fn compact_locked(&self) -> Result<(), DbError> {
+        while let Some(compaction) = pick_compaction(&self.state().version, &self.config) {
+            let compactor = self.compactor.clone();
+            let manifest = self.manifest.clone();
+            let stats = tokio::task::spawn_blocking(move || compactor.run(&manifest, &compaction))
+                .await
+                .map_err(std::io::Error::other)??;
+
+            let state = self.state();
+            let version = self.manifest.current();
+            let mut tables: HashMap<_, _> = state
+                .tables
+                .iter()
+                .filter(|(id, _)| version.tables().any(|t| t.id == **id))
+                .map(|(id, table)| (*id, table.clone()))
+                .collect();
+            for output in &stats.outputs {
+                tables.insert(output.id, self.open_table(output.id)?);
+            }
+            self.install(State {
+                memtable: state.memtable.clone(),
+                version,
+                tables,
+            });
+        }
+        Ok(())
+    }
+
+    fn install(&self, state: State) {
+        *self.state.write().unwrap() = Arc::new(state);
+    }
+
+    /// Closes a snapshot taken at `seq`, letting the memtable drop versions
+    /// only it needed.
+    pub(crate) fn release_snapshot(&self, seq: u64) {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if let Some(count) = snapshots.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                snapshots.remove(&seq);
+            }
+        }
+        let floor = snapshots.keys().next().copied();
+        self.state().memtable.set_snapshot_floor(floor);
+    }
+}
+
+impl std::fmt::Debug for Db {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Db").field("path", &self.path).finish()
+    }
+}
+
+/// Picks the next compaction: all of L0 once it has too many tables, else
+/// the largest table of the first level over its size budget.
+fn pick_compaction(version: &Version, config: &DbConfig) -> Option<Compaction> {
+    let l0 = version.level(0);
+    if !l0.is_empty() && l0.len() >= config.l0_compaction_trigger {
+        return Some(compaction_into(version, l0.to_vec(), 1));
+    }
+
+    let mut max_bytes = config.level_base_bytes;
+    for level in 1..NUM_LEVELS - 1 {
+        let tables = version.level(level);
+        if tables.iter().map(|t| t.file_size).sum::<u64>() > max_bytes {
+            let table = tables.iter().max_by_key(|t| t.file_size)?;
+            return Some(compaction_into(
+                version,
+                vec![table.clone()],
+                level as u8 + 1,
+            ));
+        }
+        max_bytes = max_bytes.saturating_mul(config.level_multiplier);
+    }
+    None
+}
+
+/// A compaction of `inputs` into `level`, with the tables there they overlap.
+fn compaction_into(version: &Version, mut inputs: Vec<TableMeta>, level: u8) -> Compaction {
+    let smallest = inputs.iter().map(|t| t.smallest_key.clone()).min();
+    let largest = inputs.iter().map(|t| t.largest_key.clone()).max();
+    if let (Some(smallest), Some(largest)) = (smallest, largest) {
+        let overlapping = version
+            .level(level as usize)
+            .iter()
+            .filter(|t| t.overlaps(&smallest, &largest));
+        inputs.extend(overlapping.cloned());
+    }
+    Compaction {
+        inputs,
+        output_level: level,
+    }
+}
+
+/// Deletes table files that are not live: outputs of a flush or compaction
+/// that never made it into the manifest, and inputs of one that did but
+/// were not yet removed.
+fn remove_orphans(dir: &Path, version: &Version) -> Result<(), DbError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let orphan = match name.strip_suffix(".sst") {
+            Some(id) => id
+                .parse::<u64>()
+                .is_ok_and(|id| !version.tables().any(|t| t.id == id)),
+            None => name.ends_with(".sst.tmp"),
+        };
+        if orphan {
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if `key` sorts before the range starting at `start`.
+fn before(key: &[u8], start: Bound<&[u8]>) -> bool {
+    match start {
+        Bound::Included(start) => key < start,
+        Bound::Excluded(start) => key <= start,
+        Bound::Unbounded => false,
+    }
+}
+
+/// Returns true if `key` sorts after the range ending at `end`.
+fn after(key: &[u8], end: Bound<&[u8]>) -> bool {
+    match end {
+        Bound::Included(end) => key > end,
+        Bound::Excluded(end) => key >= end,
+        Bound::Unbounded => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn small_config() -> DbConfig {
+        DbConfig {
+            memtable: MemtableConfig {
+                max_size_bytes: 16 * 1024,
+            },
+            l0_compaction_trigger: 2,
+            ..Default::default()
+        }
+    }
+
+    fn key(i: u32) -> Bytes {
+        Bytes::from(format!("key{:05}", i))
+    }
+
+    #[tokio::test]
+    async fn test_put_get_delete_scan() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+
+        db.put("a", "1").await.unwrap();
+        db.put("b", "2").await.unwrap();
+        db.put("c", "3").await.unwrap();
+        db.put("a", "4").await.unwrap();
+        db.delete("b").await.unwrap();
+        db.put_with_ttl("d", "5", Duration::ZERO).await.unwrap();
+
+        assert_eq!(db.get(b"a").unwrap(), Some(Bytes::from("4")));
+        assert_eq!(db.get(b"b").unwrap(), None);
+        assert_eq!(db.get(b"d").unwrap(), None);
+        assert_eq!(db.get(b"missing").unwrap(), None);
+        let all = db.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(
+            all,
+            vec![
+                (Bytes::from("a"), Bytes::from("4")),
+                (Bytes::from("c"), Bytes::from("3")),
+            ]
+        );
+        let from_b = db
+            .scan(
+                Bound::Included(b"b".as_slice()),
+                Bound::Excluded(b"c".as_slice()),
+            )
+            .unwrap();
+        assert!(from_b.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_recovers_tables_and_wal() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open_with_config(dir.path(), small_config())
+            .await
+            .unwrap();
+        for i in 0..1_000 {
+            db.put(key(i), format!("value{}", i)).await.unwrap();
+        }
+        for i in (0..1_000).step_by(3) {
+            db.delete(key(i)).await.unwrap();
+        }
+        let version = db.version();
+        assert!(version.tables().count() > 0);
+        assert!(version.level(0).len() < 2, "L0 should have been compacted");
+        let before = db.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        db.close().await.unwrap();
+
+        // Flushed records are read back from tables, the rest from the WAL
+        let db = Db::open_with_config(dir.path(), small_config())
+            .await
+            .unwrap();
+        assert_eq!(db.scan(Bound::Unbounded, Bound::Unbounded).unwrap(), before);
+        assert_eq!(before.len(), 666);
+        assert_eq!(db.get(&key(0)).unwrap(), None);
+        assert_eq!(db.get(&key(1)).unwrap(), Some(Bytes::from("value1")));
+        assert_eq!(db.get(&key(998)).unwrap(), Some(Bytes::from("value998")));
+        let range = db
+            .scan(Bound::Included(&key(10)), Bound::Excluded(&key(20)))
+            .unwrap();
+        let keys: Vec<_> = range.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, [10, 11, 13, 14, 16, 17, 19].map(key));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_survives_writes_flushes_and_compactions() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open_with_config(dir.path(), small_config())
+            .await
+            .unwrap();
+        for i in 0..100 {
+            db.put(key(i), "old").await.unwrap();
+        }
+        let snapshot = db.snapshot().await;
+        assert_eq!(snapshot.seq(), 100);
+
+        for i in 0..100 {
+            db.put(key(i), "new").await.unwrap();
+        }
+        db.delete(key(7)).await.unwrap();
+        db.put(key(500), "new").await.unwrap();
+        // Enough overwrites to flush and compact several times
+        for round in 0..20 {
+            for i in 100..200 {
+                db.put(key(i), format!("round{}", round)).await.unwrap();
+            }
+        }
+        assert!(db.version().tables().count() > 0);
+
+        assert_eq!(snapshot.get(&key(7)).unwrap(), Some(Bytes::from("old")));
+        assert_eq!(snapshot.get(&key(500)).unwrap(), None);
+        assert_eq!(snapshot.get(&key(150)).unwrap(), None);
+        let seen = snapshot.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(seen.len(), 100);
+        assert!(seen.iter().all(|(_, value)| value == "old"));
+
+        assert_eq!(db.get(&key(7)).unwrap(), None);
+        assert_eq!(db.get(&key(8)).unwrap(), Some(Bytes::from("new")));
+        drop(snapshot);
+
+        let seq = db.sequence();
+        assert_eq!(seq, 100 + 100 + 2 + 20 * 100);
+        db.close().await.unwrap();
+        let db = Db::open_with_config(dir.path(), small_config())
+            .await
+            .unwrap();
+        assert_eq!(db.sequence(), seq);
+        db.put("after", "reopen").await.unwrap();
+        assert_eq!(db.snapshot().await.seq(), seq + 1);
+    }
+
+    #[tokio::test]
+    async fn test_flush_checkpoints_wal_and_removes_orphans() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        db.put("k", "v").await.unwrap();
+        db.flush().await.unwrap();
+        let version = db.version();
+        assert_eq!(version.level(0).len(), 1);
+        assert_eq!(
+            version.wal_checkpoint(),
+            db.wal().last_checkpoint().await.map(|c| c.position)
+        );
+        db.close().await.unwrap();
+
+        // A table written by a flush that crashed before its manifest edit
+        let orphan = dir.path().join(table_file_name(1_000));
+        std::fs::copy(
+            dir.path().join(table_file_name(version.level(0)[0].id)),
+            &orphan,
+        )
+        .unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        assert!(!orphan.exists());
+        assert_eq!(db.get(b"k").unwrap(), Some(Bytes::from("v")));
+        assert!(db.state().memtable.is_empty());
+    }
+}
@@ -0,0 +1,18 @@
+//! Store errors.
+
+use thiserror::Error;
+
+/// Errors from a [`Db`](crate::Db).
+#[derive(Debug, Error)]
+pub enum DbError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("WAL error: {0}")]
+    Wal(#[from] nori_wal::SegmentError),
+    #[error("SSTable error: {0}")]
+    Sst(#[from] nori_sstable::SstError),
+    #[error("LSM error: {0}")]
+    Lsm(#[from] nori_lsm::LsmError),
+    #[error("Snapshot transfer error: {0}")]
+    Transfer(String),
+}
@@ -0,0 +1,53 @@
+//! Embedded key-value store on nori-wal, nori-memtable, nori-sstable and
+//! nori-lsm.
+//!
+//! [`Db`] wires the pieces together behind `put`, `get`, `delete` and
+//! `scan`:
+//! - Every write is appended to the WAL, then applied to the memtable
+//! - A full memtable is flushed to an L0 table; the manifest records the
+//!   table and the WAL position it covers before the WAL is checkpointed
+//! - L0 is compacted into L1 once it has a few tables, and each deeper level
+//!   into the next once over its size budget, dropping deletes and expired
+//!   values that hide nothing
+//! - On open, the memtable is rebuilt from the WAL after the checkpoint
+//! - Each write gets a sequence number (its LSN), and [`Db::snapshot`] reads
+//!   as of one without blocking writers
+//! - A new replica is bootstrapped by copying the tables with
+//!   [`SnapshotSender`] and [`SnapshotReceiver`], then applying the source's
+//!   WAL tail
+//!
+//! A crash at any point leaves the tables in the manifest and the WAL after
+//! its checkpoint, which together hold every logged write.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_db::Db;
+//! use std::ops::Bound;
+//!
+//! # async fn example() -> Result<(), nori_db::DbError> {
+//! let db = Db::open("/data/db").await?;
+//! db.put("user:1", "alice").await?;
+//! db.put("user:2", "bob").await?;
+//! db.delete("user:2").await?;
+//!
+//! assert_eq!(db.get(b"user:1")?, Some("alice".into()));
+//! let users = db.scan(Bound::Included(b"user:".as_slice()), Bound::Excluded(b"user;".as_slice()))?;
+//! assert_eq!(users.len(), 1);
+//! db.close().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod db;
+pub mod error;
+pub mod snapshot;
+pub mod transfer;
+
+pub use db::{Db, DbConfig, WAL_DIR};
+pub use error::DbError;
+pub use snapshot::Snapshot;
+pub use transfer::{
+    Chunk, ChunkPosition, SnapshotManifest, SnapshotReceiver, SnapshotSender, TransferConfig,
+    RECEIVING_FILE, TRANSFER_DIR,
+};
@@ -0,0 +1,67 @@
+//! Consistent reads as of a sequence number.
+
+use crate::db::{Db, State};
+use crate::error::DbError;
+use bytes::Bytes;
+use nori_memtable::MemEntry;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// A read-only view of a [`Db`] as of one sequence number.
+///
+/// Taken with [`Db::snapshot`]; reads through it are unaffected by writes,
+/// flushes and compactions that happen after it was taken, and never wait
+/// for them. Dropping it lets the store reclaim what only it needed.
+pub struct Snapshot<'a> {
+    db: &'a Db,
+    seq: u64,
+    state: Arc<State>,
+}
+
+impl<'a> Snapshot<'a> {
+    pub(crate) fn new(db: &'a Db, seq: u64, state: Arc<State>) -> Self {
+        Self { db, seq, state }
+    }
+
+    /// Returns the sequence number of the latest write the snapshot sees.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Returns the value of `key`, if it was set when the snapshot was
+    /// taken. TTLs are checked against the current time.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>, DbError> {
+        self.state.get(key, self.seq)
+    }
+
+    /// Returns the keys in the range with their values, in key order.
+    pub fn scan(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        self.state.scan(start, end, self.seq)
+    }
+
+    /// Like [`Snapshot::scan`], returning each live entry with its LSN and
+    /// TTL expiry, as needed to copy it elsewhere.
+    pub fn scan_entries(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<Vec<(Bytes, MemEntry)>, DbError> {
+        self.state.scan_entries(start, end, self.seq)
+    }
+}
+
+impl Drop for Snapshot<'_> {
+    fn drop(&mut self) {
+        self.db.release_snapshot(self.seq);
+    }
+}
+
+impl std::fmt::Debug for Snapshot<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot").field("seq", &self.seq).finish()
+    }
+}
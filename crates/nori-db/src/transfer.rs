@@ -0,0 +1,614 @@
+//! Copying a whole store to a new replica.
+//!
+//! Bootstrapping a replica takes two steps: copy the store's tables, then
+//! apply the writes made since from the source's WAL.
+//!
+//! - [`SnapshotSender::prepare`] flushes the source, hard-links its live
+//!   tables into a private directory so compactions cannot delete them, and
+//!   opens a WAL tail just after the writes the tables cover. The
+//!   [`SnapshotManifest`] describes the tables; [`SnapshotSender::read_chunk`]
+//!   reads them as CRC-checked [`Chunk`]s.
+//! - [`SnapshotReceiver::open`] starts receiving a manifest into an empty
+//!   directory, or resumes an interrupted transfer of the same snapshot:
+//!   progress is saved after every chunk, and [`SnapshotReceiver::position`]
+//!   says where the sender should carry on.
+//!   [`SnapshotReceiver::install`] then writes the manifest and opens the
+//!   store.
+//! - Records from [`SnapshotSender::tail`] applied with [`Db::apply`] bring
+//!   the replica up to date, and keep it there for as long as it follows.
+//!
+//! Manifests and chunks have a byte encoding for sending them over any
+//! stream. The tail starts at the first record after the flush, which the
+//! WAL keeps until the next checkpoint: if the source flushes again before
+//! catch-up reads those records, the tail reports them purged and the
+//! transfer has to start over.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_db::{Db, DbConfig, SnapshotReceiver, SnapshotSender, TransferConfig};
+//! use tokio_stream::StreamExt;
+//!
+//! # async fn example(source: &Db) -> Result<(), nori_db::DbError> {
+//! let mut sender = SnapshotSender::prepare(source, TransferConfig::default()).await?;
+//! let mut receiver = SnapshotReceiver::open("/data/replica", sender.manifest().clone())?;
+//! while let Some(chunk) = sender.read_chunk(receiver.position())? {
+//!     receiver.write_chunk(&chunk)?;
+//! }
+//! let replica = receiver.install(DbConfig::default()).await?;
+//!
+//! // Catch up, then keep following
+//! while let Some(next) = sender.tail().next().await {
+//!     let (record, _) = next?;
+//!     replica.apply(record).await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::db::{Db, DbConfig};
+use crate::error::DbError;
+use bytes::{Buf, BufMut, Bytes};
+use nori_lsm::{table_file_name, Manifest, TableMeta, VersionEdit, CURRENT_FILE};
+use nori_wal::WalTail;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Subdirectory of the store holding the tables linked for transfers.
+pub const TRANSFER_DIR: &str = "transfer";
+
+/// File in a receiving directory holding the manifest and the progress.
+pub const RECEIVING_FILE: &str = "RECEIVING";
+
+/// Magic number opening an encoded [`SnapshotManifest`] ("NSNP").
+const MANIFEST_MAGIC: u32 = 0x4E53_4E50;
+
+/// Version of the manifest and chunk encodings.
+const FORMAT_VERSION: u8 = 1;
+
+/// Bytes before the data of an encoded [`Chunk`].
+const CHUNK_HEADER_LEN: usize = 4 + 8 + 4 + 4;
+
+/// Distinguishes the link directories of transfers started by one process.
+static NEXT_TRANSFER: AtomicU64 = AtomicU64::new(0);
+
+/// Settings for a [`SnapshotSender`].
+#[derive(Debug, Clone)]
+pub struct TransferConfig {
+    /// Most bytes of table data in one chunk (default: 1 MiB). The receiver
+    /// fsyncs after each chunk.
+    pub chunk_size: u32,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+/// What a snapshot holds: the tables, and the sequence they cover.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    /// Sequence of the latest write in the tables. Catch-up starts after it.
+    pub sequence: u64,
+    /// Most bytes in one chunk.
+    pub chunk_size: u32,
+    /// The tables, in the order they are sent.
+    pub tables: Vec<TableMeta>,
+}
+
+impl SnapshotManifest {
+    /// Returns the total size of the tables.
+    pub fn total_bytes(&self) -> u64 {
+        self.tables.iter().map(|t| t.file_size).sum()
+    }
+
+    /// Encodes the manifest (little-endian): magic, format version,
+    /// sequence and chunk size, then the tables as a version edit.
+    pub fn encode(&self) -> Bytes {
+        let edit = VersionEdit {
+            added: self.tables.clone(),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        buf.put_u32_le(MANIFEST_MAGIC);
+        buf.put_u8(FORMAT_VERSION);
+        buf.put_u64_le(self.sequence);
+        buf.put_u32_le(self.chunk_size);
+        buf.put_slice(&edit.encode());
+        buf.into()
+    }
+
+    /// Decodes a manifest written by [`SnapshotManifest::encode`].
+    pub fn decode(mut data: &[u8]) -> Result<Self, DbError> {
+        if data.len() < 4 + 1 + 8 + 4 || data.get_u32_le() != MANIFEST_MAGIC {
+            return Err(transfer_error("not a snapshot manifest"));
+        }
+        let version = data.get_u8();
+        if version != FORMAT_VERSION {
+            return Err(transfer_error(format!(
+                "unsupported snapshot format {}",
+                version
+            )));
+        }
+        let sequence = data.get_u64_le();
+        let chunk_size = data.get_u32_le();
+        let edit = VersionEdit::decode(data)?;
+        Ok(Self {
+            sequence,
+            chunk_size,
+            tables: edit.added,
+        })
+    }
+
+    fn table(&self, position: ChunkPosition) -> Option<&TableMeta> {
+        self.tables.get(position.table as usize)
+    }
+
+    /// Moves a position at the end of a table to the start of the next.
+    fn normalize(&self, mut position: ChunkPosition) -> ChunkPosition {
+        while self
+            .table(position)
+            .is_some_and(|t| position.offset >= t.file_size)
+        {
+            position = ChunkPosition {
+                table: position.table + 1,
+                offset: 0,
+            };
+        }
+        position
+    }
+}
+
+/// Where a chunk starts: a table of the manifest and an offset in it.
+///
+/// Ordered the way chunks are sent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChunkPosition {
+    /// Index of the table in [`SnapshotManifest::tables`].
+    pub table: u32,
+    pub offset: u64,
+}
+
+/// A piece of a table file, with the CRC32C of its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub position: ChunkPosition,
+    pub data: Bytes,
+    pub crc: u32,
+}
+
+impl Chunk {
+    /// Encodes the chunk (little-endian): table, offset, data length, CRC,
+    /// then the data.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = Vec::with_capacity(CHUNK_HEADER_LEN + self.data.len());
+        buf.put_u32_le(self.position.table);
+        buf.put_u64_le(self.position.offset);
+        buf.put_u32_le(self.data.len() as u32);
+        buf.put_u32_le(self.crc);
+        buf.put_slice(&self.data);
+        buf.into()
+    }
+
+    /// Decodes a chunk written by [`Chunk::encode`]. The CRC is checked when
+    /// the chunk is written.
+    pub fn decode(mut data: &[u8]) -> Result<Self, DbError> {
+        if data.len() < CHUNK_HEADER_LEN {
+            return Err(transfer_error("truncated chunk"));
+        }
+        let position = ChunkPosition {
+            table: data.get_u32_le(),
+            offset: data.get_u64_le(),
+        };
+        let len = data.get_u32_le() as usize;
+        let crc = data.get_u32_le();
+        if data.len() != len {
+            return Err(transfer_error(format!(
+                "chunk holds {} bytes, header says {}",
+                data.len(),
+                len
+            )));
+        }
+        Ok(Self {
+            position,
+            data: Bytes::copy_from_slice(data),
+            crc,
+        })
+    }
+}
+
+/// The sending side of a transfer: a snapshot of a store's tables, and a
+/// tail of its WAL from just after them.
+///
+/// Dropping it removes the links, letting the source delete tables it has
+/// compacted since.
+pub struct SnapshotSender {
+    dir: PathBuf,
+    manifest: SnapshotManifest,
+    tail: WalTail,
+}
+
+impl SnapshotSender {
+    /// Flushes `db` and takes a snapshot of its tables.
+    ///
+    /// Writes are held off while the memtable is flushed and the tables
+    /// linked, which takes as long as a flush.
+    pub async fn prepare(db: &Db, config: TransferConfig) -> Result<Self, DbError> {
+        if config.chunk_size == 0 {
+            return Err(transfer_error("chunk_size must be at least 1"));
+        }
+        let id = NEXT_TRANSFER.fetch_add(1, Ordering::Relaxed);
+        let dir = db
+            .path()
+            .join(TRANSFER_DIR)
+            .join(format!("{}-{}", std::process::id(), id));
+        let (sequence, version, tail) = match db.link_tables(&dir).await {
+            Ok(linked) => linked,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        };
+        let manifest = SnapshotManifest {
+            sequence,
+            chunk_size: config.chunk_size,
+            tables: version.tables().cloned().collect(),
+        };
+        Ok(Self {
+            dir,
+            manifest,
+            tail,
+        })
+    }
+
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Reads the chunk starting at `position`, or returns `None` past the
+    /// last table.
+    pub fn read_chunk(&self, position: ChunkPosition) -> Result<Option<Chunk>, DbError> {
+        let position = self.manifest.normalize(position);
+        let Some(table) = self.manifest.table(position) else {
+            return Ok(None);
+        };
+        let len = (table.file_size - position.offset).min(self.manifest.chunk_size as u64);
+        let mut file = File::open(self.dir.join(table_file_name(table.id)))?;
+        file.seek(SeekFrom::Start(position.offset))?;
+        let mut data = vec![0; len as usize];
+        file.read_exact(&mut data)?;
+        Ok(Some(Chunk {
+            position,
+            crc: crc32c::crc32c(&data),
+            data: data.into(),
+        }))
+    }
+
+    /// Returns the tail of the source WAL, starting at the first write the
+    /// tables do not hold.
+    pub fn tail(&mut self) -> &mut WalTail {
+        &mut self.tail
+    }
+}
+
+impl Drop for SnapshotSender {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl std::fmt::Debug for SnapshotSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotSender")
+            .field("dir", &self.dir)
+            .field("sequence", &self.manifest.sequence)
+            .finish()
+    }
+}
+
+/// The receiving side of a transfer, writing tables into the directory of
+/// a new store.
+#[derive(Debug)]
+pub struct SnapshotReceiver {
+    path: PathBuf,
+    manifest: SnapshotManifest,
+    position: ChunkPosition,
+}
+
+impl SnapshotReceiver {
+    /// Starts receiving `manifest` into `path`, or resumes where an earlier
+    /// receiver of the same manifest stopped.
+    ///
+    /// The tables of an interrupted transfer of another snapshot are
+    /// deleted. Fails if `path` already holds a store.
+    pub fn open(path: impl Into<PathBuf>, manifest: SnapshotManifest) -> Result<Self, DbError> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        if path.join(CURRENT_FILE).exists() {
+            return Err(transfer_error(format!(
+                "{} already holds a store",
+                path.display()
+            )));
+        }
+
+        let mut position = ChunkPosition::default();
+        if let Some((previous, at)) = read_progress(&path)? {
+            if previous == manifest {
+                position = at;
+            } else {
+                for table in &previous.tables {
+                    remove_if_exists(&path.join(table_file_name(table.id)))?;
+                }
+            }
+        }
+
+        // Anything past the saved position may not have reached the disk
+        let receiver = Self {
+            position: manifest.normalize(position),
+            path,
+            manifest,
+        };
+        if let Some(table) = receiver.manifest.table(receiver.position) {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(receiver.path.join(table_file_name(table.id)))?;
+            file.set_len(receiver.position.offset)?;
+        }
+        receiver.save_progress()?;
+        Ok(receiver)
+    }
+
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Returns where the next chunk must start.
+    pub fn position(&self) -> ChunkPosition {
+        self.position
+    }
+
+    /// Returns how many bytes of table data have been received.
+    pub fn received_bytes(&self) -> u64 {
+        let done = self.manifest.tables[..self.position.table as usize]
+            .iter()
+            .map(|t| t.file_size)
+            .sum::<u64>();
+        done + self.position.offset
+    }
+
+    /// Returns true once every table has been received.
+    pub fn is_complete(&self) -> bool {
+        self.manifest.table(self.position).is_none()
+    }
+
+    /// Writes `chunk` and durably records the progress.
+    ///
+    /// A chunk before the current position, resent after a reconnect, is
+    /// ignored. One that does not start at the current position or fails
+    /// its CRC is an error, and changes nothing.
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), DbError> {
+        if chunk.position < self.position {
+            return Ok(());
+        }
+        if chunk.position != self.position {
+            return Err(transfer_error(format!(
+                "expected chunk at {:?}, got {:?}",
+                self.position, chunk.position
+            )));
+        }
+        if crc32c::crc32c(&chunk.data) != chunk.crc {
+            return Err(transfer_error(format!(
+                "CRC mismatch in chunk at {:?}",
+                chunk.position
+            )));
+        }
+        let table = self.manifest.table(self.position).expect("not complete");
+        let end = self.position.offset + chunk.data.len() as u64;
+        if chunk.data.is_empty() || end > table.file_size {
+            return Err(transfer_error(format!(
+                "chunk at {:?} does not fit table {} of {} bytes",
+                chunk.position, table.id, table.file_size
+            )));
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(self.path.join(table_file_name(table.id)))?;
+        file.write_all(&chunk.data)?;
+        file.sync_data()?;
+
+        let next = self.manifest.normalize(ChunkPosition {
+            table: self.position.table,
+            offset: end,
+        });
+        if let Some(table) = self.manifest.table(next).filter(|_| next.offset == 0) {
+            File::create(self.path.join(table_file_name(table.id)))?;
+        }
+        self.position = next;
+        self.save_progress()
+    }
+
+    /// Records the tables in a new manifest and opens the store.
+    ///
+    /// The store's sequence starts at the snapshot's, so records from the
+    /// sender's tail applied in order get the sequence numbers they had at
+    /// the source.
+    pub async fn install(self, config: DbConfig) -> Result<Db, DbError> {
+        if !self.is_complete() {
+            return Err(transfer_error(format!(
+                "{} of {} bytes received",
+                self.received_bytes(),
+                self.manifest.total_bytes()
+            )));
+        }
+        File::open(&self.path)?.sync_all()?;
+        let manifest = Manifest::open(&self.path, config.manifest.clone())?;
+        manifest.apply(VersionEdit {
+            added: self.manifest.tables.clone(),
+            last_sequence: Some(self.manifest.sequence),
+            ..Default::default()
+        })?;
+        drop(manifest);
+        std::fs::remove_file(self.path.join(RECEIVING_FILE))?;
+        Db::open_with_config(self.path, config).await
+    }
+
+    /// Replaces the progress file with the manifest and current position.
+    fn save_progress(&self) -> Result<(), DbError> {
+        let mut buf = Vec::new();
+        buf.put_u32_le(self.position.table);
+        buf.put_u64_le(self.position.offset);
+        buf.put_slice(&self.manifest.encode());
+
+        let tmp = self.path.join(format!("{}.tmp", RECEIVING_FILE));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, self.path.join(RECEIVING_FILE))?;
+        File::open(&self.path)?.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Reads the manifest and position saved by an earlier receiver.
+fn read_progress(path: &Path) -> Result<Option<(SnapshotManifest, ChunkPosition)>, DbError> {
+    let buf = match std::fs::read(path.join(RECEIVING_FILE)) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut data = buf.as_slice();
+    if data.len() < 4 + 8 {
+        return Err(transfer_error("truncated progress file"));
+    }
+    let position = ChunkPosition {
+        table: data.get_u32_le(),
+        offset: data.get_u64_le(),
+    };
+    Ok(Some((SnapshotManifest::decode(data)?, position)))
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), DbError> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn transfer_error(message: impl Into<String>) -> DbError {
+    DbError::Transfer(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_memtable::MemtableConfig;
+    use std::ops::Bound;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    fn small_config() -> DbConfig {
+        DbConfig {
+            memtable: MemtableConfig {
+                max_size_bytes: 16 * 1024,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_resumes_then_catches_up() {
+        let dir = TempDir::new().unwrap();
+        let source = Db::open_with_config(dir.path().join("source"), small_config())
+            .await
+            .unwrap();
+        for i in 0..2_000u32 {
+            source
+                .put(format!("key{:05}", i), format!("value{}", i))
+                .await
+                .unwrap();
+        }
+
+        let config = TransferConfig { chunk_size: 4096 };
+        let mut sender = SnapshotSender::prepare(&source, config).await.unwrap();
+        let manifest = sender.manifest().clone();
+        assert_eq!(manifest.sequence, 2_000);
+        assert!(manifest.tables.len() > 1);
+        assert_eq!(
+            SnapshotManifest::decode(&manifest.encode()).unwrap(),
+            manifest
+        );
+
+        // Writes and a flush after the snapshot
+        for i in 0..300u32 {
+            source.put(format!("key{:05}", i), "updated").await.unwrap();
+        }
+        source.delete("key01999").await.unwrap();
+        source.flush().await.unwrap();
+
+        // Interrupted after a few chunks, then resumed by a new receiver
+        let target = dir.path().join("target");
+        let mut receiver = SnapshotReceiver::open(&target, manifest.clone()).unwrap();
+        for _ in 0..3 {
+            let chunk = sender.read_chunk(receiver.position()).unwrap().unwrap();
+            receiver
+                .write_chunk(&Chunk::decode(&chunk.encode()).unwrap())
+                .unwrap();
+        }
+        let (position, received) = (receiver.position(), receiver.received_bytes());
+        drop(receiver);
+        let mut receiver = SnapshotReceiver::open(&target, manifest.clone()).unwrap();
+        assert_eq!(receiver.position(), position);
+        assert_eq!(receiver.received_bytes(), received);
+        assert!(received > 0 && received <= 3 * 4096);
+
+        let mut corrupt = sender.read_chunk(position).unwrap().unwrap();
+        corrupt.crc ^= 1;
+        assert!(matches!(
+            receiver.write_chunk(&corrupt),
+            Err(DbError::Transfer(_))
+        ));
+        while let Some(chunk) = sender.read_chunk(receiver.position()).unwrap() {
+            receiver.write_chunk(&chunk).unwrap();
+        }
+        assert_eq!(receiver.received_bytes(), manifest.total_bytes());
+        let replica = receiver.install(small_config()).await.unwrap();
+        assert_eq!(replica.sequence(), 2_000);
+        assert_eq!(
+            replica.get(b"key00010").unwrap(),
+            Some(Bytes::from("value10"))
+        );
+
+        // Catch up from the tail
+        source.sync().await.unwrap();
+        while replica.sequence() < source.sequence() {
+            let next = tokio::time::timeout(Duration::from_secs(5), sender.tail().next())
+                .await
+                .unwrap()
+                .unwrap();
+            replica.apply(next.unwrap().0).await.unwrap();
+        }
+        assert_eq!(replica.sequence(), source.sequence());
+        assert_eq!(
+            replica.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            source.scan(Bound::Unbounded, Bound::Unbounded).unwrap()
+        );
+        assert!(matches!(
+            SnapshotReceiver::open(&target, manifest),
+            Err(DbError::Transfer(_))
+        ));
+
+        // The links go with the sender
+        drop(sender);
+        let links = dir.path().join("source").join(TRANSFER_DIR);
+        assert_eq!(std::fs::read_dir(links).unwrap().count(), 0);
+    }
+}
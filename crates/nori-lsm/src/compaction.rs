@@ -0,0 +1,614 @@
+//! Merging tables, and dropping what no reader can see any more.
+//!
+//! A compaction merges a set of input tables into new tables in an output
+//! level. For each key only the newest entry survives: L0 tables are newer
+//! than deeper ones, and among L0 tables the later one wins.
+//!
+//! The surviving entry is also garbage collected:
+//! - A tombstone only exists to hide older values of its key. Once no table
+//!   outside the compaction, at or below its shallowest input level, can
+//!   hold the key, there is nothing left to hide and it is dropped.
+//! - A value whose TTL has passed reads as deleted. It is dropped under the
+//!   same condition as a tombstone; otherwise it is rewritten as a
+//!   tombstone, keeping it from uncovering an older value while freeing its
+//!   bytes.
+//!
+//! Reclaimed bytes (keys and values dropped, and values of expired entries)
+//! are counted in `lsm_compaction_reclaimed_bytes_total`, with
+//! `lsm_compaction_tombstones_dropped_total` and
+//! `lsm_compaction_expired_total` for the entries.
+
+use crate::error::LsmError;
+use crate::manifest::Manifest;
+use crate::version::{table_file_name, TableMeta, Version, VersionEdit, NUM_LEVELS};
+use bytes::Bytes;
+use nori_observe::{obs_emit, CompEvt, CompKind, Meter, NoopMeter, VizEvent};
+use nori_sstable::{SstConfig, SstIter, SstReader, SstWriter};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use nori_memtable::MemEntry;
+
+/// Settings for a [`Compactor`].
+#[derive(Debug, Clone)]
+pub struct CompactionConfig {
+    /// Output is split into tables of about this size.
+    pub target_file_size: u64,
+    /// Settings for the output tables.
+    pub sst: SstConfig,
+    /// Node reported in compaction events.
+    pub node_id: u32,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            target_file_size: 64 * 1024 * 1024,
+            sst: SstConfig::default(),
+            node_id: 0,
+        }
+    }
+}
+
+/// Tables to merge and where to put the result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compaction {
+    pub inputs: Vec<TableMeta>,
+    pub output_level: u8,
+}
+
+/// What a compaction did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Tables written.
+    pub outputs: Vec<TableMeta>,
+    pub input_entries: u64,
+    pub output_entries: u64,
+    /// Entries hidden by a newer entry for the same key.
+    pub superseded: u64,
+    pub tombstones_dropped: u64,
+    /// Expired values dropped or turned into tombstones.
+    pub expired: u64,
+    /// Key and value bytes no longer stored.
+    pub reclaimed_bytes: u64,
+}
+
+/// Runs compactions on the tables of a directory.
+pub struct Compactor {
+    dir: PathBuf,
+    config: CompactionConfig,
+    meter: Arc<dyn Meter>,
+}
+
+impl Compactor {
+    /// Creates a compactor for the tables in `dir`.
+    pub fn new(dir: impl Into<PathBuf>, config: CompactionConfig) -> Self {
+        Self::with_meter(dir, config, Arc::new(NoopMeter))
+    }
+
+    /// Like [`Compactor::new`], reporting through `meter`.
+    pub fn with_meter(
+        dir: impl Into<PathBuf>,
+        config: CompactionConfig,
+        meter: Arc<dyn Meter>,
+    ) -> Self {
+        Self {
+            dir: dir.into(),
+            config,
+            meter,
+        }
+    }
+
+    /// Runs `compaction`, applies the result to `manifest` and deletes the
+    /// input files.
+    pub fn run(
+        &self,
+        manifest: &Manifest,
+        compaction: &Compaction,
+    ) -> Result<CompactionStats, LsmError> {
+        self.run_at(manifest, compaction, SystemTime::now())
+    }
+
+    /// Like [`Compactor::run`], treating TTLs as of `now`.
+    pub fn run_at(
+        &self,
+        manifest: &Manifest,
+        compaction: &Compaction,
+        now: SystemTime,
+    ) -> Result<CompactionStats, LsmError> {
+        let version = manifest.current();
+        check(&version, compaction)?;
+        obs_emit!(
+            self.meter,
+            VizEvent::Compaction(CompEvt {
+                node: self.config.node_id,
+                level: compaction.output_level,
+                kind: CompKind::Start,
+            })
+        );
+
+        // Newest first, so the first entry seen for a key wins
+        let mut inputs = compaction.inputs.clone();
+        inputs.sort_by_key(|table| (table.level, Reverse(table.id)));
+        let readers = inputs
+            .iter()
+            .map(|table| SstReader::open(self.dir.join(table_file_name(table.id))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut merge = Merge::new(readers.iter().map(SstReader::iter).collect())?;
+
+        let mut stats = CompactionStats::default();
+        let mut output = Output::new(self, manifest, compaction.output_level);
+        while let Some((key, entry, superseded)) = merge.next()? {
+            stats.input_entries += 1 + superseded;
+            stats.superseded += superseded;
+            let Some(entry) = self.collect(&version, compaction, &key, entry, now, &mut stats)
+            else {
+                continue;
+            };
+            output.add(&key, &entry, &mut stats)?;
+        }
+        output.finish(&mut stats)?;
+
+        let edit = VersionEdit {
+            added: stats.outputs.clone(),
+            removed: inputs.iter().map(|t| (t.level, t.id)).collect(),
+            ..Default::default()
+        };
+        manifest.apply(edit)?;
+        for table in &inputs {
+            let _ = std::fs::remove_file(self.dir.join(table_file_name(table.id)));
+        }
+
+        self.meter
+            .counter("lsm_compaction_reclaimed_bytes_total", &[])
+            .inc(stats.reclaimed_bytes);
+        self.meter
+            .counter("lsm_compaction_tombstones_dropped_total", &[])
+            .inc(stats.tombstones_dropped);
+        self.meter
+            .counter("lsm_compaction_expired_total", &[])
+            .inc(stats.expired);
+        obs_emit!(
+            self.meter,
+            VizEvent::Compaction(CompEvt {
+                node: self.config.node_id,
+                level: compaction.output_level,
+                kind: CompKind::Finish {
+                    in_bytes: inputs.iter().map(|t| t.file_size).sum(),
+                    out_bytes: stats.outputs.iter().map(|t| t.file_size).sum(),
+                },
+            })
+        );
+        Ok(stats)
+    }
+
+    /// Applies garbage collection to the surviving entry for `key`,
+    /// returning what to write, if anything.
+    fn collect(
+        &self,
+        version: &Version,
+        compaction: &Compaction,
+        key: &Bytes,
+        mut entry: MemEntry,
+        now: SystemTime,
+        stats: &mut CompactionStats,
+    ) -> Option<MemEntry> {
+        let expired = entry.value.is_some() && entry.value_at(now).is_none();
+        if entry.value.is_some() && !expired {
+            return Some(entry);
+        }
+
+        let value_len = entry.value.as_ref().map_or(0, Bytes::len) as u64;
+        if expired {
+            stats.expired += 1;
+        }
+        if !may_exist_elsewhere(version, compaction, key) {
+            if !expired {
+                stats.tombstones_dropped += 1;
+            }
+            stats.reclaimed_bytes += key.len() as u64 + value_len;
+            return None;
+        }
+        stats.reclaimed_bytes += value_len;
+        entry.value = None;
+        entry.expires_at = None;
+        Some(entry)
+    }
+}
+
+/// Checks that a compaction's inputs are live and that its output cannot
+/// overlap a table left in the output level.
+fn check(version: &Version, compaction: &Compaction) -> Result<(), LsmError> {
+    let level = compaction.output_level as usize;
+    if level >= NUM_LEVELS || compaction.inputs.is_empty() {
+        return Err(LsmError::InvalidEdit(format!(
+            "bad compaction into L{} with {} inputs",
+            level,
+            compaction.inputs.len()
+        )));
+    }
+    for input in &compaction.inputs {
+        if !version.level(input.level as usize).contains(input) {
+            return Err(LsmError::InvalidEdit(format!(
+                "table {} is not live in L{}",
+                input.id, input.level
+            )));
+        }
+    }
+    let smallest = compaction
+        .inputs
+        .iter()
+        .map(|t| &t.smallest_key)
+        .min()
+        .unwrap();
+    let largest = compaction
+        .inputs
+        .iter()
+        .map(|t| &t.largest_key)
+        .max()
+        .unwrap();
+    let overlapping = version
+        .level(level)
+        .iter()
+        .find(|t| !compaction.inputs.contains(t) && t.overlaps(smallest, largest));
+    if let (true, Some(table)) = (level > 0, overlapping) {
+        return Err(LsmError::InvalidEdit(format!(
+            "table {} in L{} overlaps the compaction but is not an input",
+            table.id, level
+        )));
+    }
+    Ok(())
+}
+
+/// Returns true if a table that is not an input and may be older than the
+/// inputs could hold `key`. Shallower levels only hold newer entries, so
+/// the search starts at the shallowest input level.
+fn may_exist_elsewhere(version: &Version, compaction: &Compaction, key: &[u8]) -> bool {
+    let top = compaction.inputs.iter().map(|t| t.level).min().unwrap_or(0) as usize;
+    (top..NUM_LEVELS).any(|level| {
+        version
+            .level(level)
+            .iter()
+            .any(|t| t.overlaps(key, key) && !compaction.inputs.contains(t))
+    })
+}
+
+/// K-way merge of table iterators, ordered newest first.
+struct Merge<'a> {
+    iters: Vec<SstIter<'a>>,
+    /// Next key of each iterator, with its index as the tie-breaker.
+    heap: BinaryHeap<Reverse<(Bytes, usize)>>,
+    pending: Vec<Option<MemEntry>>,
+}
+
+impl<'a> Merge<'a> {
+    fn new(iters: Vec<SstIter<'a>>) -> Result<Self, LsmError> {
+        let mut merge = Self {
+            pending: vec![None; iters.len()],
+            iters,
+            heap: BinaryHeap::new(),
+        };
+        for i in 0..merge.iters.len() {
+            merge.advance(i)?;
+        }
+        Ok(merge)
+    }
+
+    fn advance(&mut self, i: usize) -> Result<(), LsmError> {
+        if let Some(next) = self.iters[i].next() {
+            let (key, entry) = next?;
+            self.pending[i] = Some(entry);
+            self.heap.push(Reverse((key, i)));
+        }
+        Ok(())
+    }
+
+    /// Returns the next key, its newest entry and how many older entries for
+    /// it were skipped.
+    fn next(&mut self) -> Result<Option<(Bytes, MemEntry, u64)>, LsmError> {
+        let Some(Reverse((key, i))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        let entry = self.pending[i].take().expect("entry for heap item");
+        self.advance(i)?;
+        let mut superseded = 0;
+        while let Some(Reverse((next_key, j))) = self.heap.peek() {
+            if *next_key != key {
+                break;
+            }
+            let j = *j;
+            self.heap.pop();
+            self.pending[j] = None;
+            self.advance(j)?;
+            superseded += 1;
+        }
+        Ok(Some((key, entry, superseded)))
+    }
+}
+
+/// Output tables, cut at the target size.
+struct Output<'a> {
+    compactor: &'a Compactor,
+    manifest: &'a Manifest,
+    level: u8,
+    writer: Option<(u64, SstWriter)>,
+}
+
+impl<'a> Output<'a> {
+    fn new(compactor: &'a Compactor, manifest: &'a Manifest, level: u8) -> Self {
+        Self {
+            compactor,
+            manifest,
+            level,
+            writer: None,
+        }
+    }
+
+    fn add(
+        &mut self,
+        key: &[u8],
+        entry: &MemEntry,
+        stats: &mut CompactionStats,
+    ) -> Result<(), LsmError> {
+        if let Some((_, writer)) = &self.writer {
+            if writer.estimated_size() >= self.compactor.config.target_file_size {
+                self.finish(stats)?;
+            }
+        }
+        let (_, writer) = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let id = self.manifest.new_table_id();
+                let path = self.compactor.dir.join(table_file_name(id));
+                let writer = SstWriter::create(path, self.compactor.config.sst.clone())?;
+                self.writer.insert((id, writer))
+            }
+        };
+        writer.add(key, entry)?;
+        stats.output_entries += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, stats: &mut CompactionStats) -> Result<(), LsmError> {
+        if let Some((id, writer)) = self.writer.take() {
+            let info = writer.finish()?;
+            stats
+                .outputs
+                .push(TableMeta::from_info(id, self.level, &info));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestConfig;
+    use nori_observe::TestMeter;
+    use nori_sstable::write_table;
+    use std::path::Path;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn put(value: &str, lsn: u64) -> MemEntry {
+        MemEntry {
+            value: Some(Bytes::copy_from_slice(value.as_bytes())),
+            lsn: Some(lsn),
+            expires_at: None,
+        }
+    }
+
+    fn delete(lsn: u64) -> MemEntry {
+        MemEntry {
+            value: None,
+            lsn: Some(lsn),
+            expires_at: None,
+        }
+    }
+
+    fn add_table(
+        manifest: &Manifest,
+        dir: &Path,
+        level: u8,
+        entries: Vec<(&str, MemEntry)>,
+    ) -> TableMeta {
+        let id = manifest.new_table_id();
+        let entries = entries
+            .into_iter()
+            .map(|(key, entry)| (Bytes::copy_from_slice(key.as_bytes()), entry));
+        let info =
+            write_table(dir.join(table_file_name(id)), SstConfig::default(), entries).unwrap();
+        let table = TableMeta::from_info(id, level, &info);
+        manifest
+            .apply(VersionEdit {
+                added: vec![table.clone()],
+                ..Default::default()
+            })
+            .unwrap();
+        table
+    }
+
+    fn read_all(dir: &Path, tables: &[TableMeta]) -> Vec<(Bytes, MemEntry)> {
+        tables
+            .iter()
+            .flat_map(|t| {
+                let reader = SstReader::open(dir.join(table_file_name(t.id))).unwrap();
+                reader.iter().map(Result::unwrap).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_newest_entry_wins() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        let old = add_table(
+            &manifest,
+            dir.path(),
+            1,
+            vec![("a", put("1", 1)), ("b", put("1", 2))],
+        );
+        let l0_old = add_table(&manifest, dir.path(), 0, vec![("a", put("2", 3))]);
+        let l0_new = add_table(
+            &manifest,
+            dir.path(),
+            0,
+            vec![("a", put("3", 4)), ("c", put("3", 5))],
+        );
+
+        let compactor = Compactor::new(dir.path(), CompactionConfig::default());
+        let compaction = Compaction {
+            inputs: vec![old.clone(), l0_old.clone(), l0_new.clone()],
+            output_level: 1,
+        };
+        let stats = compactor.run(&manifest, &compaction).unwrap();
+        assert_eq!(stats.input_entries, 5);
+        assert_eq!(stats.superseded, 2);
+
+        let version = manifest.current();
+        assert!(version.level(0).is_empty());
+        assert_eq!(version.level(1), &stats.outputs[..]);
+        let entries = read_all(dir.path(), &stats.outputs);
+        let expected = vec![
+            (Bytes::from("a"), put("3", 4)),
+            (Bytes::from("b"), put("1", 2)),
+            (Bytes::from("c"), put("3", 5)),
+        ];
+        assert_eq!(entries, expected);
+        for table in [old, l0_old, l0_new] {
+            assert!(!dir.path().join(table_file_name(table.id)).exists());
+        }
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_keeps_tombstones_that_hide_older_tables() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        add_table(&manifest, dir.path(), 2, vec![("a", put("old", 1))]);
+        let l0 = add_table(
+            &manifest,
+            dir.path(),
+            0,
+            vec![("a", delete(2)), ("z", delete(3))],
+        );
+
+        let meter = Arc::new(TestMeter::new());
+        let compactor =
+            Compactor::with_meter(dir.path(), CompactionConfig::default(), meter.clone());
+        let compaction = Compaction {
+            inputs: vec![l0],
+            output_level: 1,
+        };
+        let stats = compactor.run(&manifest, &compaction).unwrap();
+
+        // "a" still has a value in L2; nothing below holds "z"
+        assert_eq!(stats.tombstones_dropped, 1);
+        assert_eq!(stats.reclaimed_bytes, 1);
+        assert_eq!(
+            read_all(dir.path(), &stats.outputs),
+            vec![(Bytes::from("a"), delete(2))]
+        );
+        assert_eq!(
+            meter.counter_total("lsm_compaction_tombstones_dropped_total"),
+            1
+        );
+        assert_eq!(
+            meter.counter_total("lsm_compaction_reclaimed_bytes_total"),
+            1
+        );
+        assert!(meter.events().iter().any(|e| matches!(
+            e,
+            VizEvent::Compaction(CompEvt {
+                level: 1,
+                kind: CompKind::Finish { .. },
+                ..
+            })
+        )));
+    }
+
+    #[test]
+    fn test_prunes_expired_values() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let expiring = |value: &str, lsn, secs| MemEntry {
+            expires_at: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+            ..put(value, lsn)
+        };
+        add_table(&manifest, dir.path(), 2, vec![("b", put("old", 1))]);
+        let l1 = add_table(
+            &manifest,
+            dir.path(),
+            1,
+            vec![
+                ("a", expiring("gone", 2, 999)),
+                ("b", expiring("hidden", 3, 500)),
+                ("c", expiring("live", 4, 2_000)),
+            ],
+        );
+
+        let meter = Arc::new(TestMeter::new());
+        let compactor =
+            Compactor::with_meter(dir.path(), CompactionConfig::default(), meter.clone());
+        let compaction = Compaction {
+            inputs: vec![l1],
+            output_level: 1,
+        };
+        let stats = compactor.run_at(&manifest, &compaction, now).unwrap();
+
+        // "a" is dropped outright; "b" must keep hiding its older value in L2
+        assert_eq!(stats.expired, 2);
+        assert_eq!(stats.tombstones_dropped, 0);
+        assert_eq!(stats.reclaimed_bytes, 5 + 6);
+        let expected = vec![
+            (Bytes::from("b"), delete(3)),
+            (Bytes::from("c"), expiring("live", 4, 2_000)),
+        ];
+        assert_eq!(read_all(dir.path(), &stats.outputs), expected);
+        assert_eq!(meter.counter_total("lsm_compaction_expired_total"), 2);
+    }
+
+    #[test]
+    fn test_splits_output_and_rejects_partial_levels() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        let keys: Vec<String> = (0..2_000).map(|i| format!("key{:05}", i)).collect();
+        let l0 = add_table(
+            &manifest,
+            dir.path(),
+            0,
+            keys.iter().map(|k| (k.as_str(), put("value", 1))).collect(),
+        );
+        let l1 = add_table(&manifest, dir.path(), 1, vec![("key00100", put("x", 0))]);
+
+        let config = CompactionConfig {
+            target_file_size: 8 * 1024,
+            ..Default::default()
+        };
+        let compactor = Compactor::new(dir.path(), config);
+        let partial = Compaction {
+            inputs: vec![l0.clone()],
+            output_level: 1,
+        };
+        assert!(matches!(
+            compactor.run(&manifest, &partial),
+            Err(LsmError::InvalidEdit(_))
+        ));
+
+        let compaction = Compaction {
+            inputs: vec![l0, l1],
+            output_level: 1,
+        };
+        let stats = compactor.run(&manifest, &compaction).unwrap();
+        assert!(stats.outputs.len() > 1);
+        assert_eq!(stats.output_entries, 2_000);
+        for pair in stats.outputs.windows(2) {
+            assert!(pair[0].largest_key < pair[1].smallest_key);
+        }
+        assert_eq!(manifest.current().level(1).len(), stats.outputs.len());
+    }
+}
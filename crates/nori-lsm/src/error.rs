@@ -0,0 +1,18 @@
+//! LSM engine errors.
+
+use thiserror::Error;
+
+/// Errors from the engine and its manifest.
+#[derive(Debug, Error)]
+pub enum LsmError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Corrupt manifest: {0}")]
+    Corruption(String),
+    #[error("Invalid version edit: {0}")]
+    InvalidEdit(String),
+    #[error("SSTable error: {0}")]
+    Sst(#[from] nori_sstable::SstError),
+    #[error("WAL error: {0}")]
+    Wal(#[from] nori_wal::SegmentError),
+}
@@ -1,6 +1,37 @@
 //! Embeddable LSM engine (WAL+SST+compaction+snapshots).
-//! Skeleton library - to be implemented.
+//!
+//! The engine's durable state is spread over the WAL, the SSTables and the
+//! [`Manifest`], which says which tables are live, in which level, and up to
+//! where the WAL has been flushed into them. Every change to the table set
+//! (a flush, a compaction) is one [`VersionEdit`], made durable in the
+//! manifest before the resulting [`Version`] is swapped in, so a crash at
+//! any point recovers to a consistent set of tables.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_lsm::{Manifest, ManifestConfig, TableMeta, VersionEdit};
+//! # fn example(info: nori_sstable::SstInfo, flushed_through: nori_wal::Position) -> Result<(), nori_lsm::LsmError> {
+//! let manifest = Manifest::open("/data/db", ManifestConfig::default())?;
+//!
+//! // After flushing a memtable to table `id`
+//! let id = manifest.new_table_id();
+//! manifest.apply(VersionEdit {
+//!     added: vec![TableMeta::from_info(id, 0, &info)],
+//!     wal_checkpoint: Some(flushed_through),
+//!     ..Default::default()
+//! })?;
+//! println!("{} tables in L0", manifest.current().level(0).len());
+//! # Ok(())
+//! # }
+//! ```
 
-pub fn placeholder() -> &'static str {
-    "nori-lsm"
-}
+pub mod compaction;
+pub mod error;
+pub mod manifest;
+pub mod version;
+
+pub use compaction::{Compaction, CompactionConfig, CompactionStats, Compactor};
+pub use error::LsmError;
+pub use manifest::{Manifest, ManifestConfig, CURRENT_FILE};
+pub use version::{table_file_name, TableMeta, Version, VersionEdit, NUM_LEVELS};
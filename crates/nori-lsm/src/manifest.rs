@@ -0,0 +1,348 @@
+//! The MANIFEST: a durable log of version edits.
+//!
+//! The table set is the result of every [`VersionEdit`] since the engine
+//! was created. Each edit is appended to the active manifest file as one
+//! nori-wal [`Record`], so it gets the same CRC and framing as the log, and
+//! fsynced before the new [`Version`] becomes visible. A crash therefore
+//! leaves either the old version or the new one.
+//!
+//! The name of the active manifest (`MANIFEST-000001` and so on) is kept in
+//! a `CURRENT` file, replaced atomically. When the manifest outgrows
+//! `max_manifest_size`, a new one is started with a single edit holding
+//! the whole version, `CURRENT` is switched to it, and the old one is
+//! deleted.
+//!
+//! On open the edits are replayed in order. A damaged or incomplete record
+//! ends the replay and is cut off: it can only be an edit whose append
+//! never returned, so its version was never used.
+
+use crate::error::LsmError;
+use crate::version::{Version, VersionEdit};
+use nori_wal::Record;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Name of the file naming the active manifest.
+pub const CURRENT_FILE: &str = "CURRENT";
+
+/// Key of the records holding edits.
+const EDIT_KEY: &[u8] = b"edit";
+
+/// Settings for a [`Manifest`].
+#[derive(Debug, Clone)]
+pub struct ManifestConfig {
+    /// Size past which the manifest is rewritten as a single snapshot edit.
+    pub max_manifest_size: u64,
+}
+
+impl Default for ManifestConfig {
+    fn default() -> Self {
+        Self {
+            max_manifest_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+struct Active {
+    file: File,
+    number: u64,
+    size: u64,
+}
+
+/// The durable record of which tables are live.
+///
+/// [`Manifest::current`] is cheap and lock-free for readers in practice: it
+/// clones an `Arc` to an immutable [`Version`]. Edits are serialized.
+pub struct Manifest {
+    dir: PathBuf,
+    config: ManifestConfig,
+    active: Mutex<Active>,
+    current: RwLock<Arc<Version>>,
+    next_table_id: AtomicU64,
+}
+
+impl Manifest {
+    /// Opens the manifest in `dir`, replaying its edits, or creates an
+    /// empty one.
+    pub fn open(dir: impl Into<PathBuf>, config: ManifestConfig) -> Result<Self, LsmError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+
+        let (version, active) = match read_current(&dir)? {
+            Some(number) => recover(&dir, number)?,
+            None => {
+                let version = Version::default();
+                let active = create_manifest(&dir, 1, &version)?;
+                (version, active)
+            }
+        };
+        Ok(Self {
+            next_table_id: AtomicU64::new(version.next_table_id()),
+            dir,
+            config,
+            active: Mutex::new(active),
+            current: RwLock::new(Arc::new(version)),
+        })
+    }
+
+    /// Returns the directory holding the manifest.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the current version.
+    pub fn current(&self) -> Arc<Version> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Hands out a table ID that is never reused, even across restarts once
+    /// an edit has been applied after it.
+    pub fn new_table_id(&self) -> u64 {
+        self.next_table_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Durably applies `edit` and makes the result the current version.
+    ///
+    /// Fails without changing anything if the edit does not fit the current
+    /// version. If writing fails, the manifest should be reopened before
+    /// further use.
+    pub fn apply(&self, mut edit: VersionEdit) -> Result<Arc<Version>, LsmError> {
+        let mut active = self.active.lock().unwrap();
+        edit.next_table_id = Some(self.next_table_id.load(Ordering::Relaxed));
+        let version = Arc::new(self.current().apply(&edit)?);
+
+        let record = Record::put(EDIT_KEY, edit.encode()).encode();
+        active.file.write_all(&record)?;
+        active.file.sync_data()?;
+        active.size += record.len() as u64;
+        *self.current.write().unwrap() = version.clone();
+
+        if active.size > self.config.max_manifest_size {
+            let number = active.number + 1;
+            *active = create_manifest(&self.dir, number, &version)?;
+            let _ = std::fs::remove_file(self.dir.join(manifest_name(number - 1)));
+        }
+        Ok(version)
+    }
+
+    /// Number of the active manifest file.
+    pub fn manifest_number(&self) -> u64 {
+        self.active.lock().unwrap().number
+    }
+}
+
+impl std::fmt::Debug for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Manifest")
+            .field("dir", &self.dir)
+            .field("current", &self.current())
+            .finish()
+    }
+}
+
+fn manifest_name(number: u64) -> String {
+    format!("MANIFEST-{:06}", number)
+}
+
+fn read_current(dir: &Path) -> Result<Option<u64>, LsmError> {
+    let contents = match std::fs::read_to_string(dir.join(CURRENT_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    contents
+        .trim_end()
+        .strip_prefix("MANIFEST-")
+        .and_then(|number| number.parse().ok())
+        .map(Some)
+        .ok_or_else(|| LsmError::Corruption(format!("bad CURRENT file {:?}", contents)))
+}
+
+/// Writes a new manifest holding `version` and points `CURRENT` at it.
+fn create_manifest(dir: &Path, number: u64, version: &Version) -> Result<Active, LsmError> {
+    let path = dir.join(manifest_name(number));
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)?;
+    let record = Record::put(EDIT_KEY, version.snapshot().encode()).encode();
+    file.write_all(&record)?;
+    file.sync_all()?;
+
+    let temp_path = dir.join("CURRENT.tmp");
+    let mut current = File::create(&temp_path)?;
+    current.write_all(format!("{}\n", manifest_name(number)).as_bytes())?;
+    current.sync_all()?;
+    std::fs::rename(&temp_path, dir.join(CURRENT_FILE))?;
+    sync_dir(dir)?;
+
+    Ok(Active {
+        file,
+        number,
+        size: record.len() as u64,
+    })
+}
+
+/// Replays manifest `number`, cutting off a damaged tail.
+fn recover(dir: &Path, number: u64) -> Result<(Version, Active), LsmError> {
+    let path = dir.join(manifest_name(number));
+    let data = std::fs::read(&path)?;
+    let mut version = Version::default();
+    let mut offset = 0;
+    while offset < data.len() {
+        let Ok((record, len)) = Record::decode(&data[offset..]) else {
+            break;
+        };
+        if record.key.as_ref() != EDIT_KEY {
+            return Err(LsmError::Corruption(format!(
+                "unexpected record at offset {} of {}",
+                offset,
+                path.display()
+            )));
+        }
+        version = version.apply(&VersionEdit::decode(&record.value)?)?;
+        offset += len;
+    }
+    if offset == 0 {
+        return Err(LsmError::Corruption(format!(
+            "{} has no readable edits",
+            path.display()
+        )));
+    }
+
+    let file = OpenOptions::new().append(true).open(&path)?;
+    if offset < data.len() {
+        file.set_len(offset as u64)?;
+        file.sync_all()?;
+    }
+    Ok((
+        version,
+        Active {
+            file,
+            number,
+            size: offset as u64,
+        },
+    ))
+}
+
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::TableMeta;
+    use bytes::Bytes;
+    use nori_wal::Position;
+    use tempfile::TempDir;
+
+    fn add(manifest: &Manifest, level: u8) -> u64 {
+        let id = manifest.new_table_id();
+        let table = TableMeta {
+            id,
+            level,
+            file_size: 100,
+            entries: 1,
+            smallest_key: Bytes::from(format!("k{:04}", id)),
+            largest_key: Bytes::from(format!("k{:04}", id)),
+            max_lsn: Some(id),
+        };
+        manifest
+            .apply(VersionEdit {
+                added: vec![table],
+                last_sequence: Some(id),
+                wal_checkpoint: Some(Position {
+                    segment_id: 0,
+                    offset: id * 10,
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_recovers_edits() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        assert_eq!(manifest.current().tables().count(), 0);
+        let ids: Vec<_> = (0..5).map(|_| add(&manifest, 0)).collect();
+        manifest
+            .apply(VersionEdit {
+                removed: vec![(0, ids[0])],
+                ..Default::default()
+            })
+            .unwrap();
+        // Handed out but never used, and still not reused after a restart
+        let unused = manifest.new_table_id();
+        add(&manifest, 1);
+        let before = manifest.current();
+        drop(manifest);
+
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        assert_eq!(manifest.current(), before);
+        assert_eq!(manifest.current().level(0).len(), 4);
+        assert_eq!(manifest.current().last_sequence(), unused + 1);
+        assert!(manifest.new_table_id() > unused + 1);
+    }
+
+    #[test]
+    fn test_cuts_off_torn_edit() {
+        let dir = TempDir::new().unwrap();
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        add(&manifest, 0);
+        add(&manifest, 0);
+        let before = manifest.current();
+        drop(manifest);
+
+        // Half of a third edit made it to disk
+        let path = dir.path().join(manifest_name(1));
+        let edit = VersionEdit {
+            removed: vec![(0, 1)],
+            ..Default::default()
+        };
+        let record = Record::put(EDIT_KEY, edit.encode()).encode();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+        let torn_len = file.metadata().unwrap().len();
+
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        assert_eq!(manifest.current(), before);
+        assert!(std::fs::metadata(&path).unwrap().len() < torn_len);
+        add(&manifest, 0);
+        drop(manifest);
+        let manifest = Manifest::open(dir.path(), ManifestConfig::default()).unwrap();
+        assert_eq!(manifest.current().level(0).len(), 3);
+    }
+
+    #[test]
+    fn test_rewrites_large_manifest() {
+        let dir = TempDir::new().unwrap();
+        let config = ManifestConfig {
+            max_manifest_size: 1024,
+        };
+        let manifest = Manifest::open(dir.path(), config.clone()).unwrap();
+        for _ in 0..50 {
+            add(&manifest, 0);
+        }
+        let number = manifest.manifest_number();
+        assert!(number > 1);
+        assert!(!dir.path().join(manifest_name(number - 1)).exists());
+        let before = manifest.current();
+        drop(manifest);
+
+        let manifest = Manifest::open(dir.path(), config).unwrap();
+        assert_eq!(manifest.manifest_number(), number);
+        assert_eq!(manifest.current(), before);
+        assert_eq!(manifest.current().level(0).len(), 50);
+    }
+}
@@ -0,0 +1,385 @@
+//! Versions of the table set and the edits between them.
+
+use crate::error::LsmError;
+use bytes::{Buf, BufMut, Bytes};
+use nori_sstable::SstInfo;
+use nori_wal::Position;
+
+/// Number of levels, L0 through L6.
+pub const NUM_LEVELS: usize = 7;
+
+/// Returns the file name of table `id`.
+pub fn table_file_name(id: u64) -> String {
+    format!("{:06}.sst", id)
+}
+
+/// A live table as recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableMeta {
+    pub id: u64,
+    pub level: u8,
+    pub file_size: u64,
+    pub entries: u64,
+    pub smallest_key: Bytes,
+    pub largest_key: Bytes,
+    pub max_lsn: Option<u64>,
+}
+
+impl TableMeta {
+    /// Describes the table written as `info`, to be added as `id` in `level`.
+    pub fn from_info(id: u64, level: u8, info: &SstInfo) -> Self {
+        Self {
+            id,
+            level,
+            file_size: info.file_size,
+            entries: info.entries,
+            smallest_key: info.smallest_key.clone(),
+            largest_key: info.largest_key.clone(),
+            max_lsn: info.max_lsn,
+        }
+    }
+
+    /// Returns true if the table's key range overlaps `[smallest, largest]`.
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8]) -> bool {
+        self.smallest_key.as_ref() <= largest && smallest <= self.largest_key.as_ref()
+    }
+}
+
+/// A change to the table set, applied atomically.
+///
+/// A flush adds one L0 table and moves the WAL checkpoint; a compaction
+/// removes its inputs and adds its outputs in the same edit, so no reader
+/// ever sees both or neither.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub added: Vec<TableMeta>,
+    /// Level and ID of each table removed.
+    pub removed: Vec<(u8, u64)>,
+    /// New WAL checkpoint: records before it are all in tables.
+    pub wal_checkpoint: Option<Position>,
+    /// New highest sequence number (LSN) covered by the tables.
+    pub last_sequence: Option<u64>,
+    /// Lowest table ID not yet handed out. Set by the manifest.
+    pub next_table_id: Option<u64>,
+}
+
+const TAG_ADD: u8 = 1;
+const TAG_REMOVE: u8 = 2;
+const TAG_WAL_CHECKPOINT: u8 = 3;
+const TAG_LAST_SEQUENCE: u8 = 4;
+const TAG_NEXT_TABLE_ID: u8 = 5;
+
+impl VersionEdit {
+    /// Encodes the edit as a list of tagged fields (little-endian).
+    pub fn encode(&self) -> Bytes {
+        let mut buf = Vec::new();
+        for table in &self.added {
+            buf.put_u8(TAG_ADD);
+            buf.put_u8(table.level);
+            buf.put_u64_le(table.id);
+            buf.put_u64_le(table.file_size);
+            buf.put_u64_le(table.entries);
+            buf.put_u64_le(table.max_lsn.unwrap_or(0));
+            for key in [&table.smallest_key, &table.largest_key] {
+                buf.put_u32_le(key.len() as u32);
+                buf.put_slice(key);
+            }
+        }
+        for &(level, id) in &self.removed {
+            buf.put_u8(TAG_REMOVE);
+            buf.put_u8(level);
+            buf.put_u64_le(id);
+        }
+        if let Some(position) = self.wal_checkpoint {
+            buf.put_u8(TAG_WAL_CHECKPOINT);
+            buf.put_u64_le(position.segment_id);
+            buf.put_u64_le(position.offset);
+        }
+        if let Some(sequence) = self.last_sequence {
+            buf.put_u8(TAG_LAST_SEQUENCE);
+            buf.put_u64_le(sequence);
+        }
+        if let Some(id) = self.next_table_id {
+            buf.put_u8(TAG_NEXT_TABLE_ID);
+            buf.put_u64_le(id);
+        }
+        buf.into()
+    }
+
+    /// Decodes an edit written by [`VersionEdit::encode`].
+    pub fn decode(mut data: &[u8]) -> Result<Self, LsmError> {
+        let mut edit = VersionEdit::default();
+        while data.has_remaining() {
+            let tag = data.get_u8();
+            match tag {
+                TAG_ADD => {
+                    need(data, 1 + 8 * 4)?;
+                    let level = data.get_u8();
+                    let id = data.get_u64_le();
+                    let file_size = data.get_u64_le();
+                    let entries = data.get_u64_le();
+                    let max_lsn = Some(data.get_u64_le()).filter(|&lsn| lsn != 0);
+                    let smallest_key = get_key(&mut data)?;
+                    let largest_key = get_key(&mut data)?;
+                    edit.added.push(TableMeta {
+                        id,
+                        level,
+                        file_size,
+                        entries,
+                        smallest_key,
+                        largest_key,
+                        max_lsn,
+                    });
+                }
+                TAG_REMOVE => {
+                    need(data, 1 + 8)?;
+                    edit.removed.push((data.get_u8(), data.get_u64_le()));
+                }
+                TAG_WAL_CHECKPOINT => {
+                    need(data, 16)?;
+                    edit.wal_checkpoint = Some(Position {
+                        segment_id: data.get_u64_le(),
+                        offset: data.get_u64_le(),
+                    });
+                }
+                TAG_LAST_SEQUENCE => {
+                    need(data, 8)?;
+                    edit.last_sequence = Some(data.get_u64_le());
+                }
+                TAG_NEXT_TABLE_ID => {
+                    need(data, 8)?;
+                    edit.next_table_id = Some(data.get_u64_le());
+                }
+                tag => return Err(LsmError::Corruption(format!("unknown edit tag {}", tag))),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn need(data: &[u8], len: usize) -> Result<(), LsmError> {
+    if data.len() < len {
+        return Err(LsmError::Corruption("truncated version edit".into()));
+    }
+    Ok(())
+}
+
+fn get_key(data: &mut &[u8]) -> Result<Bytes, LsmError> {
+    need(data, 4)?;
+    let len = data.get_u32_le() as usize;
+    need(data, len)?;
+    let key = Bytes::copy_from_slice(&data[..len]);
+    data.advance(len);
+    Ok(key)
+}
+
+/// An immutable snapshot of the table set.
+///
+/// L0 tables may overlap and are kept in the order they were added, oldest
+/// first. Tables in each deeper level do not overlap and are sorted by key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    levels: Vec<Vec<TableMeta>>,
+    wal_checkpoint: Option<Position>,
+    last_sequence: u64,
+    next_table_id: u64,
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Self {
+            levels: vec![Vec::new(); NUM_LEVELS],
+            wal_checkpoint: None,
+            last_sequence: 0,
+            next_table_id: 1,
+        }
+    }
+}
+
+impl Version {
+    /// Tables in `level`, empty for levels past the last.
+    pub fn level(&self, level: usize) -> &[TableMeta] {
+        self.levels.get(level).map_or(&[], Vec::as_slice)
+    }
+
+    /// Every live table, level by level.
+    pub fn tables(&self) -> impl Iterator<Item = &TableMeta> {
+        self.levels.iter().flatten()
+    }
+
+    /// Position in the WAL before which every record is in a table.
+    pub fn wal_checkpoint(&self) -> Option<Position> {
+        self.wal_checkpoint
+    }
+
+    /// Highest sequence number (LSN) the tables cover.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Lowest table ID not yet handed out.
+    pub fn next_table_id(&self) -> u64 {
+        self.next_table_id
+    }
+
+    /// Returns the version that results from applying `edit`, or an error if
+    /// it removes a table that is not live or adds one that already is.
+    pub fn apply(&self, edit: &VersionEdit) -> Result<Version, LsmError> {
+        let mut next = self.clone();
+        for &(level, id) in &edit.removed {
+            let tables = next
+                .levels
+                .get_mut(level as usize)
+                .ok_or_else(|| LsmError::InvalidEdit(format!("no level {}", level)))?;
+            let at = tables.iter().position(|t| t.id == id).ok_or_else(|| {
+                LsmError::InvalidEdit(format!("table {} is not in L{}", id, level))
+            })?;
+            tables.remove(at);
+        }
+        for table in &edit.added {
+            if next.tables().any(|t| t.id == table.id) {
+                return Err(LsmError::InvalidEdit(format!(
+                    "table {} is already live",
+                    table.id
+                )));
+            }
+            let tables = next
+                .levels
+                .get_mut(table.level as usize)
+                .ok_or_else(|| LsmError::InvalidEdit(format!("no level {}", table.level)))?;
+            if table.level == 0 {
+                tables.push(table.clone());
+            } else {
+                let at = tables.partition_point(|t| t.smallest_key < table.smallest_key);
+                tables.insert(at, table.clone());
+            }
+            next.next_table_id = next.next_table_id.max(table.id + 1);
+        }
+        if let Some(position) = edit.wal_checkpoint {
+            next.wal_checkpoint = Some(position);
+        }
+        if let Some(sequence) = edit.last_sequence {
+            next.last_sequence = next.last_sequence.max(sequence);
+        }
+        if let Some(id) = edit.next_table_id {
+            next.next_table_id = next.next_table_id.max(id);
+        }
+        Ok(next)
+    }
+
+    /// Returns an edit that rebuilds this version from an empty one.
+    pub fn snapshot(&self) -> VersionEdit {
+        VersionEdit {
+            added: self.tables().cloned().collect(),
+            removed: Vec::new(),
+            wal_checkpoint: self.wal_checkpoint,
+            last_sequence: Some(self.last_sequence),
+            next_table_id: Some(self.next_table_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(id: u64, level: u8, smallest: &str, largest: &str) -> TableMeta {
+        TableMeta {
+            id,
+            level,
+            file_size: 4096,
+            entries: 10,
+            smallest_key: Bytes::copy_from_slice(smallest.as_bytes()),
+            largest_key: Bytes::copy_from_slice(largest.as_bytes()),
+            max_lsn: Some(id * 10),
+        }
+    }
+
+    #[test]
+    fn test_edit_round_trip() {
+        let edit = VersionEdit {
+            added: vec![table(3, 0, "a", "m"), table(4, 1, "", "zz")],
+            removed: vec![(0, 1), (1, 2)],
+            wal_checkpoint: Some(Position {
+                segment_id: 2,
+                offset: 77,
+            }),
+            last_sequence: Some(900),
+            next_table_id: Some(5),
+        };
+        assert_eq!(VersionEdit::decode(&edit.encode()).unwrap(), edit);
+        assert_eq!(
+            VersionEdit::decode(&VersionEdit::default().encode()).unwrap(),
+            VersionEdit::default()
+        );
+
+        let encoded = edit.encode();
+        assert!(matches!(
+            VersionEdit::decode(&encoded[..encoded.len() - 3]),
+            Err(LsmError::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn test_apply() {
+        let version = Version::default()
+            .apply(&VersionEdit {
+                added: vec![
+                    table(5, 1, "m", "p"),
+                    table(2, 1, "a", "c"),
+                    table(3, 0, "b", "z"),
+                ],
+                last_sequence: Some(50),
+                ..Default::default()
+            })
+            .unwrap();
+        let ids = |level| {
+            version
+                .level(level)
+                .iter()
+                .map(|t| t.id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0), vec![3]);
+        assert_eq!(ids(1), vec![2, 5]);
+        assert_eq!(version.next_table_id(), 6);
+        assert_eq!(version.last_sequence(), 50);
+
+        // A compaction swaps tables in one edit
+        let compacted = version
+            .apply(&VersionEdit {
+                added: vec![table(6, 1, "a", "z")],
+                removed: vec![(0, 3), (1, 2), (1, 5)],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            compacted.tables().map(|t| t.id).collect::<Vec<_>>(),
+            vec![6]
+        );
+        assert_eq!(
+            Version::default().apply(&compacted.snapshot()).unwrap(),
+            compacted
+        );
+
+        for bad in [
+            VersionEdit {
+                removed: vec![(0, 5)],
+                ..Default::default()
+            },
+            VersionEdit {
+                added: vec![table(6, 2, "a", "b")],
+                ..Default::default()
+            },
+            VersionEdit {
+                added: vec![table(7, NUM_LEVELS as u8, "a", "b")],
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                compacted.apply(&bad),
+                Err(LsmError::InvalidEdit(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! In-memory write buffer rebuilt from the nori-wal log.
+//!
+//! A [`Memtable`] holds the latest write to each key, sorted, until it is
+//! flushed to an SSTable. It sits between the WAL and the rest of a store:
+//! - Writes are appended to the WAL, then applied with [`Memtable::apply`]
+//! - Puts, deletes (kept as tombstones, which shadow older data) and TTLs
+//!   are understood; expired values read as deleted
+//! - Memory use is tracked approximately, and [`Memtable::is_full`] says
+//!   when to flush
+//! - After a restart, [`Memtable::open_wal`] rebuilds it from the WAL
+//!   during recovery, using the WAL's replay-on-open hook
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_memtable::{Lookup, Memtable, MemtableConfig};
+//! use nori_wal::{Record, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, memtable, _) =
+//!     Memtable::open_wal(WalConfig::default(), MemtableConfig::default()).await?;
+//!
+//! let record = Record::put(b"key".as_slice(), b"value".as_slice());
+//! wal.append(&record).await?;
+//! memtable.apply(&record);
+//! assert_eq!(memtable.get(b"key"), Lookup::Found("value".into()));
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod memtable;
+
+pub use memtable::{Lookup, MemEntry, Memtable, MemtableConfig, ENTRY_OVERHEAD};
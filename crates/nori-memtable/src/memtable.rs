@@ -0,0 +1,479 @@
+//! The memtable and its entries.
+
+use bytes::Bytes;
+use nori_wal::{Position, Record, RecoveryInfo, SegmentError, Wal, WalConfig};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Bytes charged per entry on top of its key and value, for the map node,
+/// the entry itself and the reference-counted buffers.
+pub const ENTRY_OVERHEAD: usize = 96;
+
+/// Settings for a [`Memtable`].
+#[derive(Debug, Clone)]
+pub struct MemtableConfig {
+    /// Approximate size at which [`Memtable::is_full`] reports that the
+    /// memtable should be flushed.
+    pub max_size_bytes: usize,
+}
+
+impl Default for MemtableConfig {
+    fn default() -> Self {
+        Self {
+            max_size_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// The latest write to a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemEntry {
+    /// The value written, or `None` for a delete.
+    pub value: Option<Bytes>,
+    /// LSN of the record that wrote it, if it came from the WAL.
+    pub lsn: Option<u64>,
+    /// When a value written with a TTL expires.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl MemEntry {
+    /// Returns the value if the entry is a put that has not expired at `now`.
+    pub fn value_at(&self, now: SystemTime) -> Option<&Bytes> {
+        match self.expires_at {
+            Some(expires_at) if expires_at <= now => None,
+            _ => self.value.as_ref(),
+        }
+    }
+
+    /// Returns true if the entry is a delete.
+    pub fn is_tombstone(&self) -> bool {
+        self.value.is_none()
+    }
+
+    fn charge(key: &[u8], value: Option<&Bytes>) -> usize {
+        ENTRY_OVERHEAD + key.len() + value.map_or(0, Bytes::len)
+    }
+}
+
+/// Result of a point lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// The key holds this value.
+    Found(Bytes),
+    /// The key was deleted, or its value expired. Older data for the key,
+    /// in the memtable's predecessors, must not be consulted.
+    Deleted,
+    /// The memtable knows nothing about the key.
+    Absent,
+}
+
+/// Sorted in-memory buffer of the latest write to each key.
+///
+/// Records are applied in log order; a record whose LSN is lower than that
+/// of the entry it would replace is ignored, so replaying a stretch of the
+/// log twice is harmless. A value with a TTL expires at its record's
+/// timestamp plus the TTL (or, for records without a timestamp, when it was
+/// applied plus the TTL) and then reads as deleted.
+///
+/// LSNs double as sequence numbers for snapshot reads. Once
+/// [`Memtable::set_snapshot_floor`] is given the oldest sequence a reader
+/// may still ask for, older writes to a key are kept as long as one of them
+/// is what a read as of that sequence sees, and
+/// [`Memtable::entry_as_of`] and [`Memtable::range_as_of`] read them.
+/// Records without an LSN replace every version of their key.
+///
+/// All methods take `&self`; a memtable can be shared between a writer and
+/// any number of readers.
+#[derive(Debug)]
+pub struct Memtable {
+    config: MemtableConfig,
+    /// Versions of each key, newest first.
+    entries: RwLock<BTreeMap<Bytes, Vec<MemEntry>>>,
+    size: AtomicUsize,
+    max_lsn: AtomicU64,
+    /// Oldest sequence a snapshot may read at; `u64::MAX` keeps only the
+    /// latest version.
+    snapshot_floor: AtomicU64,
+}
+
+impl Memtable {
+    /// Creates an empty memtable with the default configuration.
+    pub fn new() -> Self {
+        Self::with_config(MemtableConfig::default())
+    }
+
+    /// Creates an empty memtable with `config`.
+    pub fn with_config(config: MemtableConfig) -> Self {
+        Self {
+            config,
+            entries: RwLock::new(BTreeMap::new()),
+            size: AtomicUsize::new(0),
+            max_lsn: AtomicU64::new(0),
+            snapshot_floor: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Opens the WAL described by `wal_config` and rebuilds a memtable from
+    /// every record it recovers, in the same pass as recovery.
+    pub async fn open_wal(
+        wal_config: WalConfig,
+        config: MemtableConfig,
+    ) -> Result<(Wal, Memtable, RecoveryInfo), SegmentError> {
+        let memtable = Memtable::with_config(config);
+        let (wal, info) = Wal::open_with_replay(wal_config, memtable.replayer()).await?;
+        Ok((wal, memtable, info))
+    }
+
+    /// Returns a callback for [`Wal::open_with_replay`] that applies each
+    /// recovered record to this memtable.
+    pub fn replayer(&self) -> impl FnMut(Record, Position) + Send + '_ {
+        |record, _| self.apply(&record)
+    }
+
+    /// Applies a put or delete.
+    pub fn apply(&self, record: &Record) {
+        self.apply_at(record, SystemTime::now())
+    }
+
+    /// Like [`Memtable::apply`], with `now` as the apply time used for TTLs
+    /// of records without a timestamp.
+    pub fn apply_at(&self, record: &Record, now: SystemTime) {
+        let value = (!record.tombstone).then(|| record.value.clone());
+        let expires_at = match (&value, record.ttl) {
+            (Some(_), Some(ttl)) => Some(record.timestamp.unwrap_or(now) + ttl),
+            _ => None,
+        };
+        let entry = MemEntry {
+            value,
+            lsn: record.lsn,
+            expires_at,
+        };
+        let added = MemEntry::charge(&record.key, entry.value.as_ref());
+
+        let charge = |entry: &MemEntry| MemEntry::charge(&record.key, entry.value.as_ref());
+        let mut entries = self.entries.write().unwrap();
+        let versions = entries.entry(record.key.clone()).or_default();
+        let mut removed = 0;
+        match (record.lsn, versions.first()) {
+            (Some(lsn), Some(MemEntry { lsn: Some(_), .. })) => {
+                let at = versions
+                    .iter()
+                    .position(|v| v.lsn.is_some_and(|v| v <= lsn))
+                    .unwrap_or(versions.len());
+                if versions.get(at).is_some_and(|v| v.lsn == Some(lsn)) {
+                    removed += charge(&versions[at]);
+                    versions[at] = entry;
+                } else {
+                    versions.insert(at, entry);
+                }
+            }
+            _ => {
+                removed += versions.drain(..).map(|v| charge(&v)).sum::<usize>();
+                versions.push(entry);
+            }
+        }
+
+        // Keep what is newer than the floor, and the newest version at or
+        // below it, which is what a read at the floor sees
+        let floor = self.snapshot_floor.load(Ordering::Relaxed);
+        if let Some(keep) = versions
+            .iter()
+            .position(|v| v.lsn.map_or(true, |v| v <= floor))
+        {
+            removed += versions
+                .drain(keep + 1..)
+                .map(|v| charge(&v))
+                .sum::<usize>();
+        }
+        self.size.fetch_add(added, Ordering::Relaxed);
+        self.size.fetch_sub(removed, Ordering::Relaxed);
+        if let Some(lsn) = record.lsn {
+            self.max_lsn.fetch_max(lsn, Ordering::Relaxed);
+        }
+    }
+
+    /// Looks up `key` as of now.
+    pub fn get(&self, key: &[u8]) -> Lookup {
+        self.get_at(key, SystemTime::now())
+    }
+
+    /// Looks up `key`, treating values that expired by `now` as deleted.
+    pub fn get_at(&self, key: &[u8], now: SystemTime) -> Lookup {
+        match self.entry(key) {
+            None => Lookup::Absent,
+            Some(entry) => match entry.value_at(now) {
+                Some(value) => Lookup::Found(value.clone()),
+                None => Lookup::Deleted,
+            },
+        }
+    }
+
+    /// Returns the raw entry for `key`, including tombstones and expired
+    /// values.
+    pub fn entry(&self, key: &[u8]) -> Option<MemEntry> {
+        self.entry_as_of(key, u64::MAX)
+    }
+
+    /// Returns the raw entry for `key` as a read at sequence `seq` sees it:
+    /// the newest write with an LSN no higher than `seq`.
+    ///
+    /// Older writes are only kept from the snapshot floor on, so `seq`
+    /// should be at least the floor.
+    pub fn entry_as_of(&self, key: &[u8], seq: u64) -> Option<MemEntry> {
+        let entries = self.entries.read().unwrap();
+        visible(entries.get(key)?, seq).cloned()
+    }
+
+    /// Returns the entries with keys between `start` and `end`, sorted by
+    /// key, including tombstones and expired values.
+    pub fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<(Bytes, MemEntry)> {
+        self.range_as_of(start, end, u64::MAX)
+    }
+
+    /// Like [`Memtable::range`], as a read at sequence `seq` sees it.
+    pub fn range_as_of(
+        &self,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+        seq: u64,
+    ) -> Vec<(Bytes, MemEntry)> {
+        self.entries
+            .read()
+            .unwrap()
+            .range::<[u8], _>((start, end))
+            .filter_map(|(key, versions)| Some((key.clone(), visible(versions, seq)?.clone())))
+            .collect()
+    }
+
+    /// Returns every entry sorted by key, for flushing.
+    pub fn entries(&self) -> Vec<(Bytes, MemEntry)> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Returns the number of keys, counting deleted ones.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    /// Returns true if nothing has been applied.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the highest LSN applied, if any record came from the WAL.
+    ///
+    /// Once the memtable has been flushed, the WAL is no longer needed up to
+    /// and including this LSN.
+    pub fn max_lsn(&self) -> Option<u64> {
+        match self.max_lsn.load(Ordering::Relaxed) {
+            0 => None,
+            lsn => Some(lsn),
+        }
+    }
+
+    /// Sets the oldest sequence a snapshot may read at, or `None` once no
+    /// snapshot is open.
+    ///
+    /// Older writes to a key are dropped as the key is written again, unless
+    /// a read at the floor would see them. Versions already dropped are not
+    /// brought back, so the floor should only be lowered to a sequence
+    /// no older than the latest write applied.
+    pub fn set_snapshot_floor(&self, floor: Option<u64>) {
+        self.snapshot_floor
+            .store(floor.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// Returns the approximate memory held by the entries, in bytes.
+    pub fn approximate_size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Returns true once the memtable has reached its configured size and
+    /// should be flushed.
+    pub fn is_full(&self) -> bool {
+        self.approximate_size() >= self.config.max_size_bytes
+    }
+
+    /// Returns the memtable's configuration.
+    pub fn config(&self) -> &MemtableConfig {
+        &self.config
+    }
+}
+
+impl Default for Memtable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The newest of `versions` (newest first) visible at `seq`.
+fn visible(versions: &[MemEntry], seq: u64) -> Option<&MemEntry> {
+    versions
+        .iter()
+        .find(|v| v.lsn.map_or(true, |lsn| lsn <= seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    fn stamped(mut record: Record, lsn: u64) -> Record {
+        record.lsn = Some(lsn);
+        record.timestamp = Some(UNIX_EPOCH + Duration::from_secs(1000));
+        record
+    }
+
+    #[test]
+    fn test_puts_deletes_and_size() {
+        let memtable = Memtable::new();
+        assert!(memtable.is_empty());
+        assert_eq!(memtable.get(b"a"), Lookup::Absent);
+
+        memtable.apply(&Record::put(b"a".as_slice(), b"1".as_slice()));
+        memtable.apply(&Record::put(b"b".as_slice(), b"22".as_slice()));
+        assert_eq!(memtable.get(b"a"), Lookup::Found("1".into()));
+        assert_eq!(memtable.approximate_size(), 2 * ENTRY_OVERHEAD + 5);
+
+        memtable.apply(&Record::put(b"a".as_slice(), b"111".as_slice()));
+        memtable.apply(&Record::delete(b"b".as_slice()));
+        assert_eq!(memtable.get(b"a"), Lookup::Found("111".into()));
+        assert_eq!(memtable.get(b"b"), Lookup::Deleted);
+        assert_eq!(memtable.len(), 2);
+        assert_eq!(memtable.approximate_size(), 2 * ENTRY_OVERHEAD + 5);
+        assert!(memtable.entry(b"b").unwrap().is_tombstone());
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let memtable = Memtable::new();
+        let ttl = Duration::from_secs(10);
+        let record = stamped(
+            Record::put_with_ttl(b"k".as_slice(), b"v".as_slice(), ttl),
+            1,
+        );
+        memtable.apply(&record);
+
+        let written = record.timestamp.unwrap();
+        assert_eq!(memtable.get_at(b"k", written), Lookup::Found("v".into()));
+        assert_eq!(memtable.get_at(b"k", written + ttl), Lookup::Deleted);
+
+        // Without a timestamp the TTL runs from when the record was applied
+        let now = UNIX_EPOCH + Duration::from_secs(5000);
+        memtable.apply_at(
+            &Record::put_with_ttl(b"j".as_slice(), b"v".as_slice(), ttl),
+            now,
+        );
+        assert_eq!(memtable.entry(b"j").unwrap().expires_at, Some(now + ttl));
+    }
+
+    #[test]
+    fn test_older_lsns_are_ignored() {
+        let memtable = Memtable::new();
+        memtable.apply(&stamped(Record::put(b"k".as_slice(), b"new".as_slice()), 5));
+        memtable.apply(&stamped(Record::delete(b"k".as_slice()), 3));
+        assert_eq!(memtable.get(b"k"), Lookup::Found("new".into()));
+        assert_eq!(memtable.max_lsn(), Some(5));
+    }
+
+    #[test]
+    fn test_keeps_versions_above_snapshot_floor() {
+        let memtable = Memtable::new();
+        let put = |value: &'static str, lsn| stamped(Record::put(b"k".as_slice(), value), lsn);
+        memtable.apply(&put("1", 1));
+        memtable.apply(&put("2", 2));
+        // Without a snapshot only the latest version is kept
+        assert_eq!(memtable.entry_as_of(b"k", 1), None);
+
+        memtable.set_snapshot_floor(Some(2));
+        memtable.apply(&put("3", 3));
+        memtable.apply(&stamped(Record::delete(b"k".as_slice()), 4));
+        memtable.apply(&stamped(
+            Record::put(b"other".as_slice(), b"x".as_slice()),
+            5,
+        ));
+        assert_eq!(memtable.entry_as_of(b"k", 2).unwrap().value.unwrap(), "2");
+        assert_eq!(memtable.entry_as_of(b"k", 3).unwrap().value.unwrap(), "3");
+        assert!(memtable.entry(b"k").unwrap().is_tombstone());
+        let keys: Vec<_> = memtable
+            .range_as_of(Bound::Unbounded, Bound::Unbounded, 4)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, ["k"]);
+        assert_eq!(memtable.len(), 2);
+        assert_eq!(memtable.approximate_size(), 4 * ENTRY_OVERHEAD + 1 + 4 + 6);
+
+        // Once the snapshot is gone the next write drops what it kept
+        memtable.set_snapshot_floor(None);
+        memtable.apply(&put("5", 6));
+        assert_eq!(memtable.entry_as_of(b"k", 2), None);
+        assert_eq!(memtable.entry(b"k").unwrap().lsn, Some(6));
+        assert_eq!(memtable.approximate_size(), 2 * ENTRY_OVERHEAD + 1 + 1 + 6);
+    }
+
+    #[test]
+    fn test_range_and_full() {
+        let config = MemtableConfig {
+            max_size_bytes: 3 * ENTRY_OVERHEAD,
+        };
+        let memtable = Memtable::with_config(config);
+        for key in [b"d", b"a", b"c", b"b"] {
+            memtable.apply(&Record::put(key.as_slice(), b"".as_slice()));
+        }
+        assert!(memtable.is_full());
+
+        let keys = |entries: Vec<(Bytes, MemEntry)>| {
+            entries.into_iter().map(|(key, _)| key).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(memtable.range(Bound::Included(b"b"), Bound::Excluded(b"d"))),
+            ["b", "c"]
+        );
+        assert_eq!(keys(memtable.entries()), ["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_rebuilt_from_wal() {
+        let dir = TempDir::new().unwrap();
+        let wal_config = WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let (wal, memtable, _) = Memtable::open_wal(wal_config.clone(), MemtableConfig::default())
+            .await
+            .unwrap();
+        for record in [
+            Record::put(b"a".as_slice(), b"1".as_slice()),
+            Record::put(b"b".as_slice(), b"2".as_slice()),
+            Record::delete(b"a".as_slice()),
+        ] {
+            wal.append(&record).await.unwrap();
+            memtable.apply(&record);
+        }
+        wal.sync().await.unwrap();
+        let before = memtable.entries();
+        wal.close().await.unwrap();
+
+        let (_wal, memtable, info) = Memtable::open_wal(wal_config, MemtableConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 3);
+        assert_eq!(memtable.get(b"a"), Lookup::Deleted);
+        assert_eq!(memtable.get(b"b"), Lookup::Found("2".into()));
+        assert_eq!(memtable.max_lsn(), Some(3));
+
+        // Same contents, though only replayed entries carry LSNs
+        let values = |entries: Vec<(Bytes, MemEntry)>| {
+            entries
+                .into_iter()
+                .map(|(key, e)| (key, e.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(memtable.entries()), values(before));
+    }
+}
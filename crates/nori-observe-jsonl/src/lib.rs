@@ -0,0 +1,360 @@
+//! JSON-lines file backend for nori-observe.
+//!
+//! [`JsonlMeter`] appends one JSON object per line for every metric update
+//! and every [`VizEvent`], so telemetry can be kept on an air-gapped box or
+//! inspected with `jq` without any collector:
+//!
+//! ```text
+//! {"ts_ms":1718000000000,"type":"counter","metric":"wal_appends_total","labels":{"shard":"3"},"value":1}
+//! {"ts_ms":1718000000004,"type":"event","event":{"Wal":{"node":1,"seg":4,"kind":{"SegmentRoll":{"bytes":134217728}}}}}
+//! ```
+//!
+//! The file is rotated once it grows past [`JsonlConfig::max_bytes`]:
+//! `nori.jsonl` becomes `nori.jsonl.1`, `nori.jsonl.1` becomes
+//! `nori.jsonl.2`, and so on, keeping at most [`JsonlConfig::max_files`]
+//! old files.
+//!
+//! Lines are buffered; the buffer is flushed after each event, by
+//! [`JsonlMeter::flush`] and when the meter is dropped. Write errors can't be
+//! returned through [`Meter`], so they are counted instead, see
+//! [`JsonlMeter::write_errors`].
+
+use nori_observe::{Counter, Gauge, Histogram, Label, Meter, VizEvent};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where and how much to write.
+#[derive(Debug, Clone)]
+pub struct JsonlConfig {
+    /// File to append to. Rotated files get `.1`, `.2`, ... appended.
+    pub path: PathBuf,
+    /// Size after which the file is rotated.
+    pub max_bytes: u64,
+    /// Rotated files to keep. 0 truncates the file instead of keeping it.
+    pub max_files: usize,
+}
+
+impl Default for JsonlConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("nori-telemetry.jsonl"),
+            max_bytes: 64 * 1024 * 1024, // 64 MiB
+            max_files: 4,
+        }
+    }
+}
+
+/// A [`Meter`] writing JSON lines to a rotating file. See the
+/// [crate docs](crate).
+#[derive(Clone)]
+pub struct JsonlMeter {
+    sink: Arc<Sink>,
+}
+
+struct Sink {
+    config: JsonlConfig,
+    out: Mutex<Output>,
+    write_errors: AtomicU64,
+}
+
+struct Output {
+    file: BufWriter<File>,
+    /// Bytes in the current file, including the unflushed buffer.
+    written: u64,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    ts_ms: u64,
+    #[serde(flatten)]
+    line: Line<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Line<'a> {
+    Counter {
+        metric: &'a str,
+        labels: &'a BTreeMap<&'static str, String>,
+        value: u64,
+    },
+    Gauge {
+        metric: &'a str,
+        labels: &'a BTreeMap<&'static str, String>,
+        value: i64,
+    },
+    Histogram {
+        metric: &'a str,
+        labels: &'a BTreeMap<&'static str, String>,
+        value: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        trace_id: Option<&'a str>,
+    },
+    Event {
+        event: &'a VizEvent,
+    },
+}
+
+impl JsonlMeter {
+    /// Opens (or creates) the file at `config.path` and appends to it.
+    pub fn open(config: JsonlConfig) -> io::Result<Self> {
+        let file = open_append(&config.path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            sink: Arc::new(Sink {
+                config,
+                out: Mutex::new(Output {
+                    file: BufWriter::new(file),
+                    written,
+                }),
+                write_errors: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Writes buffered lines to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        self.sink.lock().file.flush()
+    }
+
+    /// Lines that could not be written (or rotations that failed) so far.
+    pub fn write_errors(&self) -> u64 {
+        self.sink.write_errors.load(Ordering::Relaxed)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Returns `path` with `.n` appended to its file name.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+impl Sink {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Output> {
+        self.out.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self, line: Line<'_>, flush: bool) {
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut buf = match serde_json::to_vec(&Entry { ts_ms, line }) {
+            Ok(buf) => buf,
+            Err(_) => {
+                self.write_errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        buf.push(b'\n');
+
+        let mut out = self.lock();
+        let full = out.written > 0 && out.written + buf.len() as u64 > self.config.max_bytes;
+        if full && self.rotate(&mut out).is_err() {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut result = out.file.write_all(&buf);
+        if result.is_ok() && flush {
+            result = out.file.flush();
+        }
+        match result {
+            Ok(()) => out.written += buf.len() as u64,
+            Err(_) => {
+                self.write_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Shifts the current and rotated files up by one and starts a new
+    /// file. On failure the current file keeps being appended to.
+    fn rotate(&self, out: &mut Output) -> io::Result<()> {
+        out.file.flush()?;
+        let path = &self.config.path;
+        if self.config.max_files == 0 {
+            out.file = BufWriter::new(File::create(path)?);
+            out.written = 0;
+            return Ok(());
+        }
+        for n in (1..self.config.max_files).rev() {
+            match fs::rename(rotated(path, n), rotated(path, n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(path, rotated(path, 1))?;
+        out.file = BufWriter::new(open_append(path)?);
+        out.written = 0;
+        Ok(())
+    }
+}
+
+struct Instrument {
+    sink: Arc<Sink>,
+    name: &'static str,
+    labels: BTreeMap<&'static str, String>,
+}
+
+impl Counter for Instrument {
+    fn inc(&self, v: u64) {
+        let line = Line::Counter {
+            metric: self.name,
+            labels: &self.labels,
+            value: v,
+        };
+        self.sink.write(line, false);
+    }
+}
+impl Gauge for Instrument {
+    fn set(&self, v: i64) {
+        let line = Line::Gauge {
+            metric: self.name,
+            labels: &self.labels,
+            value: v,
+        };
+        self.sink.write(line, false);
+    }
+}
+impl Histogram for Instrument {
+    fn observe(&self, v: f64) {
+        let line = Line::Histogram {
+            metric: self.name,
+            labels: &self.labels,
+            value: v,
+            trace_id: None,
+        };
+        self.sink.write(line, false);
+    }
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        let line = Line::Histogram {
+            metric: self.name,
+            labels: &self.labels,
+            value: v,
+            trace_id: Some(trace_id),
+        };
+        self.sink.write(line, false);
+    }
+}
+
+impl JsonlMeter {
+    fn instrument(&self, name: &'static str, labels: &[Label]) -> Instrument {
+        Instrument {
+            sink: self.sink.clone(),
+            name,
+            labels: labels.iter().map(|(k, v)| (*k, v.to_string())).collect(),
+        }
+    }
+}
+
+impl Meter for JsonlMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        Box::new(self.instrument(name, labels))
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        Box::new(self.instrument(name, labels))
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        _buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        Box::new(self.instrument(name, labels))
+    }
+    fn emit(&self, evt: VizEvent) {
+        self.sink.write(Line::Event { event: &evt }, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{WalEvt, WalKind, LATENCY_MS_BUCKETS};
+    use serde_json::Value;
+
+    fn read_lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_writes_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nori.jsonl");
+        let meter = JsonlMeter::open(JsonlConfig {
+            path: path.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        meter
+            .counter_with("wal_appends_total", &[("shard", 3.to_string().into())])
+            .inc(2);
+        meter
+            .histo("wal_fsync_ms", LATENCY_MS_BUCKETS, &[])
+            .observe_with_exemplar(1.5, "4bf92f35");
+        meter.emit(VizEvent::Wal(WalEvt {
+            node: 1,
+            seg: 4,
+            kind: WalKind::SegmentRoll { bytes: 64 },
+        }));
+
+        let lines = read_lines(&path);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "counter");
+        assert_eq!(lines[0]["metric"], "wal_appends_total");
+        assert_eq!(lines[0]["labels"]["shard"], "3");
+        assert_eq!(lines[0]["value"], 2);
+        assert_eq!(lines[1]["value"], 1.5);
+        assert_eq!(lines[1]["trace_id"], "4bf92f35");
+        assert_eq!(lines[2]["type"], "event");
+        assert_eq!(lines[2]["event"]["Wal"]["seg"], 4);
+        assert!(lines[2]["ts_ms"].as_u64().unwrap() > 0);
+        let event: VizEvent = serde_json::from_value(lines[2]["event"].clone()).unwrap();
+        assert!(matches!(event, VizEvent::Wal(WalEvt { seg: 4, .. })));
+        assert_eq!(meter.write_errors(), 0);
+    }
+
+    #[test]
+    fn test_rotates_and_keeps_max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nori.jsonl");
+        let meter = JsonlMeter::open(JsonlConfig {
+            path: path.clone(),
+            max_bytes: 200,
+            max_files: 2,
+        })
+        .unwrap();
+
+        let counter = meter.counter("appends", &[]);
+        for i in 0..40 {
+            counter.inc(i);
+        }
+        meter.flush().unwrap();
+
+        assert!(rotated(&path, 1).exists());
+        assert!(rotated(&path, 2).exists());
+        assert!(!rotated(&path, 3).exists());
+        for file in [path.clone(), rotated(&path, 1), rotated(&path, 2)] {
+            assert!(fs::metadata(&file).unwrap().len() <= 200);
+        }
+        // The newest values are in the current file
+        let last = read_lines(&path).pop().unwrap();
+        assert_eq!(last["value"], 39);
+        let older = read_lines(&rotated(&path, 1)).pop().unwrap();
+        assert!(older["value"].as_u64().unwrap() < 39);
+    }
+}
@@ -1,5 +1,376 @@
 //! Prometheus exporter for nori-observe.
+//!
+//! [`PrometheusMeter`] registers every counter, gauge and histogram it is
+//! asked for in a [`prometheus::Registry`], and [`serve`] exposes that
+//! registry in the text format on `/metrics`:
+//!
+//! ```no_run
+//! use nori_observe_prom::PrometheusMeter;
+//! use std::sync::Arc;
+//!
+//! let meter = Arc::new(PrometheusMeter::new());
+//! let server = nori_observe_prom::serve("0.0.0.0:9898", meter.registry().clone())?;
+//! println!("metrics on http://{}/metrics", server.local_addr());
+//! // Hand `meter` to the WAL and other components
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Prometheus requires every series of a metric to use the same label keys,
+//! so the keys an instrument is first created with stick; asking for the same
+//! name with other keys (or as another kind of instrument) returns an
+//! instrument that records nothing. Emitted [`VizEvent`]s are counted in
+//! `nori_events_total`, by `event` type and `severity`.
 
-pub fn internal_placeholder() -> &'static str {
-    "nori-observe-prom"
+use nori_observe::{Counter, Gauge, Histogram, Label, Meter, NoopMeter, Severity, VizEvent};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::JoinHandle;
+
+/// A [`Meter`] backed by a Prometheus registry. See the [crate docs](crate).
+pub struct PrometheusMeter {
+    registry: Registry,
+    families: Mutex<HashMap<&'static str, Family>>,
+    events: IntCounterVec,
+}
+
+/// A registered metric and the label keys it was created with.
+enum Family {
+    Counter(IntCounterVec, Vec<&'static str>),
+    Gauge(IntGaugeVec, Vec<&'static str>),
+    Histogram(HistogramVec, Vec<&'static str>),
+}
+
+impl Default for PrometheusMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusMeter {
+    /// Creates a meter with its own registry.
+    pub fn new() -> Self {
+        Self::with_registry(Registry::new())
+    }
+
+    /// Creates a meter that registers into `registry`, alongside whatever
+    /// else the application exports.
+    pub fn with_registry(registry: Registry) -> Self {
+        let events = IntCounterVec::new(
+            Opts::new("nori_events_total", "Visualization events emitted"),
+            &["event", "severity"],
+        )
+        .expect("valid metric");
+        // A fresh registry can't already hold it; a shared one might
+        let _ = registry.register(Box::new(events.clone()));
+        Self {
+            registry,
+            families: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    /// Returns the registry metrics are registered in.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Renders the registry in the Prometheus text format.
+    pub fn render(&self) -> String {
+        render(&self.registry)
+    }
+
+    /// Returns the family for `name`, creating it with `make` if needed.
+    /// `None` if the name is taken by an incompatible instrument or could not
+    /// be registered.
+    fn family<T>(
+        &self,
+        name: &'static str,
+        labels: &[Label],
+        make: impl FnOnce(Vec<&'static str>) -> prometheus::Result<Family>,
+        pick: impl FnOnce(&Family, &[&'static str]) -> Option<T>,
+    ) -> Option<T> {
+        let keys: Vec<&'static str> = labels.iter().map(|(k, _)| *k).collect();
+        let mut families = self.families.lock().unwrap_or_else(PoisonError::into_inner);
+        if !families.contains_key(name) {
+            let family = make(keys.clone()).ok()?;
+            let collector: Box<dyn prometheus::core::Collector> = match &family {
+                Family::Counter(c, _) => Box::new(c.clone()),
+                Family::Gauge(g, _) => Box::new(g.clone()),
+                Family::Histogram(h, _) => Box::new(h.clone()),
+            };
+            self.registry.register(collector).ok()?;
+            families.insert(name, family);
+        }
+        pick(&families[name], &keys)
+    }
+}
+
+fn values(labels: &[Label]) -> Vec<&str> {
+    labels.iter().map(|(_, v)| v.as_ref()).collect()
+}
+
+struct PromCounter(prometheus::IntCounter);
+struct PromGauge(prometheus::IntGauge);
+struct PromHisto(prometheus::Histogram);
+
+impl Counter for PromCounter {
+    fn inc(&self, v: u64) {
+        self.0.inc_by(v);
+    }
+}
+impl Gauge for PromGauge {
+    fn set(&self, v: i64) {
+        self.0.set(v);
+    }
+}
+impl Histogram for PromHisto {
+    fn observe(&self, v: f64) {
+        self.0.observe(v);
+    }
+}
+
+impl Meter for PrometheusMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        let counter = self.family(
+            name,
+            labels,
+            |keys| {
+                Ok(Family::Counter(
+                    IntCounterVec::new(Opts::new(name, name), &keys)?,
+                    keys,
+                ))
+            },
+            |family, keys| match family {
+                Family::Counter(c, k) if k == keys => {
+                    c.get_metric_with_label_values(&values(labels)).ok()
+                }
+                _ => None,
+            },
+        );
+        match counter {
+            Some(c) => Box::new(PromCounter(c)),
+            None => NoopMeter.counter_with(name, labels),
+        }
+    }
+
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        let gauge = self.family(
+            name,
+            labels,
+            |keys| {
+                Ok(Family::Gauge(
+                    IntGaugeVec::new(Opts::new(name, name), &keys)?,
+                    keys,
+                ))
+            },
+            |family, keys| match family {
+                Family::Gauge(g, k) if k == keys => {
+                    g.get_metric_with_label_values(&values(labels)).ok()
+                }
+                _ => None,
+            },
+        );
+        match gauge {
+            Some(g) => Box::new(PromGauge(g)),
+            None => NoopMeter.gauge_with(name, labels),
+        }
+    }
+
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        let histo = self.family(
+            name,
+            labels,
+            |keys| {
+                let mut opts = HistogramOpts::new(name, name);
+                if !buckets.is_empty() {
+                    opts = opts.buckets(buckets.to_vec());
+                }
+                Ok(Family::Histogram(HistogramVec::new(opts, &keys)?, keys))
+            },
+            |family, keys| match family {
+                Family::Histogram(h, k) if k == keys => {
+                    h.get_metric_with_label_values(&values(labels)).ok()
+                }
+                _ => None,
+            },
+        );
+        match histo {
+            Some(h) => Box::new(PromHisto(h)),
+            None => NoopMeter.histo_with(name, buckets, labels),
+        }
+    }
+
+    fn emit(&self, evt: VizEvent) {
+        let event = match evt {
+            VizEvent::Wal(_) => "wal",
+            VizEvent::Compaction(_) => "compaction",
+            VizEvent::Raft(_) => "raft",
+            VizEvent::Swim(_) => "swim",
+            VizEvent::Shard(_) => "shard",
+            VizEvent::Cache(_) => "cache",
+            _ => "other",
+        };
+        let severity = match evt.severity() {
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+        };
+        self.events.with_label_values(&[event, severity]).inc();
+    }
+}
+
+/// Renders `registry` in the Prometheus text format.
+pub fn render(registry: &Registry) -> String {
+    let mut out = Vec::new();
+    // Encoding into a Vec only fails on malformed metric families
+    let _ = TextEncoder::new().encode(&registry.gather(), &mut out);
+    String::from_utf8(out).unwrap_or_default()
+}
+
+/// A background thread answering `GET /metrics`, started by [`serve`].
+/// Dropping it stops the thread.
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Returns the address the server listens on, useful after binding to
+    /// port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        // Wake the blocking accept so the thread sees the flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serves `registry` on `http://addr/metrics` from a background thread.
+///
+/// Requests are answered one at a time, which is plenty for a scraper.
+pub fn serve(addr: impl ToSocketAddrs, registry: Registry) -> io::Result<MetricsServer> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        std::thread::Builder::new()
+            .name("nori-metrics".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // A broken connection only affects that scrape
+                        let _ = respond(stream, &registry);
+                    }
+                }
+            })?
+    };
+    Ok(MetricsServer {
+        addr,
+        stop,
+        thread: Some(thread),
+    })
+}
+
+fn respond(mut stream: TcpStream, registry: &Registry) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers; the request has no body we care about
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            TextEncoder::new().format_type().to_string(),
+            render(registry),
+        ),
+        _ => (
+            "404 Not Found",
+            "text/plain".to_string(),
+            "not found\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{WalEvt, WalKind};
+    use std::io::Read;
+
+    #[test]
+    fn test_records_and_serves() {
+        let meter = PrometheusMeter::new();
+        meter
+            .counter_with("wal_appends_total", &[("shard", 3.to_string().into())])
+            .inc(5);
+        meter.gauge("wal_segments", &[]).set(4);
+        meter.histo("wal_fsync_ms", &[1.0, 10.0], &[]).observe(2.5);
+        meter.emit(VizEvent::Wal(WalEvt {
+            node: 1,
+            seg: 0,
+            kind: WalKind::SegmentRoll { bytes: 1 },
+        }));
+        // Same name, other label keys: dropped rather than failing
+        meter.counter("wal_appends_total", &[("node", "1")]).inc(1);
+
+        let text = meter.render();
+        assert!(
+            text.contains("wal_appends_total{shard=\"3\"} 5"),
+            "{}",
+            text
+        );
+        assert!(text.contains("wal_segments 4"));
+        assert!(text.contains("wal_fsync_ms_bucket{le=\"10\"} 1"));
+        assert!(text.contains("nori_events_total{event=\"wal\",severity=\"info\"} 1"));
+        assert!(!text.contains("node=\"1\""));
+
+        let server = serve("127.0.0.1:0", meter.registry().clone()).unwrap();
+        let mut conn = TcpStream::connect(server.local_addr()).unwrap();
+        conn.write_all(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("wal_segments 4"));
+        drop(server);
+    }
 }
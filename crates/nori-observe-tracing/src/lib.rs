@@ -0,0 +1,251 @@
+//! tracing backend for nori-observe.
+//!
+//! [`TracingMeter`] turns everything reported through a
+//! [`Meter`](nori_observe::Meter) into `tracing` events and spans:
+//!
+//! - each [`VizEvent`] is an event under the `nori` target, with a level
+//!   from its [`Severity`] and its fields spelled out (`event`, `node`,
+//!   `seg`, `kind`, ...)
+//! - counter, gauge and histogram updates are `TRACE` events under
+//!   `nori::metrics` with `metric`, `value` and `labels` fields, plus
+//!   `trace_id` for histogram samples with an exemplar
+//! - blocks timed with `obs_timed!` run inside a `DEBUG` span named `timed`
+//!   under `nori`, and report their duration when it closes
+//! - finished spans of a distributed trace become a `DEBUG` span named
+//!   `span` under `nori`, carrying the trace, span and parent span IDs in
+//!   hex, with one event reporting their duration
+
+use nori_observe::{
+    Counter, Gauge, Histogram, Label, Meter, Severity, SpanRecord, Timer, VizEvent,
+};
+use std::fmt::Write;
+use std::time::Instant;
+use tracing::Level;
+
+/// Target of events derived from [`VizEvent`]s and of timing spans.
+pub const TARGET: &str = "nori";
+/// Target of counter, gauge and histogram updates.
+pub const METRICS_TARGET: &str = "nori::metrics";
+
+/// Reports through the current `tracing` subscriber. See the
+/// [crate docs](crate).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracingMeter;
+
+/// Emits an event under [`TARGET`] at a level picked at runtime.
+macro_rules! event_at {
+    ($severity:expr, $($fields:tt)+) => {
+        match $severity {
+            Severity::Debug => tracing::event!(target: TARGET, Level::DEBUG, $($fields)+),
+            Severity::Info => tracing::event!(target: TARGET, Level::INFO, $($fields)+),
+            Severity::Warn => tracing::event!(target: TARGET, Level::WARN, $($fields)+),
+            Severity::Error => tracing::event!(target: TARGET, Level::ERROR, $($fields)+),
+        }
+    };
+}
+
+/// Formats labels as `key=value` pairs separated by commas.
+fn format_labels(labels: &[Label]) -> String {
+    let mut out = String::new();
+    for (i, (key, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{}={}", key, value);
+    }
+    out
+}
+
+/// Formats trace and span IDs as lowercase hex, as in a `traceparent`.
+fn hex(id: &[u8]) -> String {
+    let mut out = String::with_capacity(id.len() * 2);
+    for byte in id {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+struct Instrument {
+    name: &'static str,
+    labels: String,
+}
+
+impl Counter for Instrument {
+    fn inc(&self, v: u64) {
+        tracing::trace!(target: METRICS_TARGET, metric = self.name, kind = "counter", value = v, labels = %self.labels);
+    }
+}
+impl Gauge for Instrument {
+    fn set(&self, v: i64) {
+        tracing::trace!(target: METRICS_TARGET, metric = self.name, kind = "gauge", value = v, labels = %self.labels);
+    }
+}
+impl Histogram for Instrument {
+    fn observe(&self, v: f64) {
+        tracing::trace!(target: METRICS_TARGET, metric = self.name, kind = "histogram", value = v, labels = %self.labels);
+    }
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        tracing::trace!(target: METRICS_TARGET, metric = self.name, kind = "histogram", value = v, labels = %self.labels, trace_id);
+    }
+}
+
+/// Keeps a `timed` span entered until dropped.
+struct SpanTimer {
+    start: Instant,
+    // Dropped last, so the duration is reported inside the span
+    span: tracing::span::EnteredSpan,
+}
+impl Timer for SpanTimer {}
+impl Drop for SpanTimer {
+    fn drop(&mut self) {
+        let ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        tracing::debug!(target: TARGET, parent: &*self.span, elapsed_ms = ms, "timed block done");
+    }
+}
+
+impl Meter for TracingMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        Box::new(Instrument {
+            name,
+            labels: format_labels(labels),
+        })
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        Box::new(Instrument {
+            name,
+            labels: format_labels(labels),
+        })
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        _buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        Box::new(Instrument {
+            name,
+            labels: format_labels(labels),
+        })
+    }
+    fn start_timer(
+        &self,
+        name: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Box<dyn Timer> {
+        let labels: Vec<Label> = labels.iter().map(|&(k, v)| (k, v.into())).collect();
+        let span = tracing::debug_span!(target: TARGET, "timed", metric = name, labels = %format_labels(&labels));
+        Box::new(SpanTimer {
+            start: Instant::now(),
+            span: span.entered(),
+        })
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        let ms = span.duration.as_secs_f64() * 1000.0;
+        let traced = tracing::debug_span!(
+            target: TARGET,
+            "span",
+            name = span.name,
+            trace_id = %hex(&span.trace_id),
+            span_id = %hex(&span.span_id),
+            parent_span_id = %hex(&span.parent_span_id),
+            attributes = %format_labels(&span.attributes),
+        );
+        tracing::debug!(target: TARGET, parent: &traced, elapsed_ms = ms, "span done");
+    }
+    fn emit(&self, evt: VizEvent) {
+        let severity = evt.severity();
+        match &evt {
+            VizEvent::Wal(e) => {
+                event_at!(severity, event = "wal", node = e.node, seg = e.seg, kind = ?e.kind)
+            }
+            VizEvent::Compaction(e) => {
+                event_at!(severity, event = "compaction", node = e.node, level = e.level, kind = ?e.kind)
+            }
+            VizEvent::Raft(e) => {
+                event_at!(severity, event = "raft", shard = e.shard, term = e.term, kind = ?e.kind)
+            }
+            VizEvent::Swim(e) => event_at!(severity, event = "swim", node = e.node, kind = ?e.kind),
+            VizEvent::Shard(e) => {
+                event_at!(severity, event = "shard", shard = e.shard, kind = ?e.kind)
+            }
+            VizEvent::Cache(e) => {
+                event_at!(severity, event = "cache", name = %e.name, hit_ratio = e.hit_ratio)
+            }
+            other => event_at!(severity, event = "other", detail = ?other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{obs_timed, WalEvt, WalKind};
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects formatted subscriber output.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_reports_through_tracing() {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let meter = TracingMeter;
+            meter.emit(VizEvent::Wal(WalEvt {
+                node: 1,
+                seg: 3,
+                kind: WalKind::CorruptionDetected { offset: 64 },
+            }));
+            meter
+                .counter_with("wal_appends_total", &[("shard", 7.to_string().into())])
+                .inc(2);
+            meter
+                .histo("wal_fsync_ms", &[], &[])
+                .observe_with_exemplar(42.0, "4bf92f35");
+            let out = obs_timed!(meter, "replay_ms", &[("phase", "scan")], { 5 });
+            assert_eq!(out, 5);
+            meter.record_span(&SpanRecord {
+                name: "wal.append",
+                trace_id: [0xab; 16],
+                span_id: [2; 8],
+                parent_span_id: [1; 8],
+                duration: std::time::Duration::from_millis(3),
+                attributes: vec![("lsn", "9".into())],
+            });
+        });
+
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", text);
+        assert!(lines[0].contains("ERROR nori: event=\"wal\" node=1 seg=3"));
+        assert!(lines[0].contains("CorruptionDetected { offset: 64 }"));
+        assert!(lines[1].contains("TRACE nori::metrics: metric=\"wal_appends_total\""));
+        assert!(lines[1].contains("value=2 labels=shard=7"));
+        assert!(lines[2].contains("value=42.0 labels= trace_id=\"4bf92f35\""));
+        assert!(lines[3].contains("timed{metric=\"replay_ms\" labels=phase=scan}"));
+        assert!(lines[3].contains("elapsed_ms="));
+        assert!(lines[4].contains("span{name=\"wal.append\" trace_id=abababab"));
+        assert!(lines[4].contains("span_id=0202020202020202 parent_span_id=0101010101010101"));
+        assert!(lines[4].contains("attributes=lsn=9}"));
+        assert!(lines[4].contains("elapsed_ms=3"));
+    }
+}
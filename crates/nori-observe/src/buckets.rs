@@ -0,0 +1,57 @@
+//! Histogram bucket presets, so the same kind of measurement is bucketed
+//! the same way in every crate and dashboards line up.
+//!
+//! Pass them wherever buckets are asked for:
+//!
+//! ```
+//! use nori_observe::{obs_hist, Meter, NoopMeter, LATENCY_MS_BUCKETS};
+//!
+//! let meter = NoopMeter;
+//! meter.histo("wal_fsync_ms", LATENCY_MS_BUCKETS, &[]).observe(0.8);
+//! obs_hist!(meter, "wal_fsync_ms", LATENCY_MS_BUCKETS, &[], 0.8);
+//! ```
+
+/// Latencies of individual operations in milliseconds, from 50µs to 10s:
+/// appends, fsyncs, reads, RPCs. The default for `obs_timed!`.
+pub const LATENCY_MS_BUCKETS: &[f64] = &[
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0,
+    5000.0, 10000.0,
+];
+
+/// Durations of long-running work in milliseconds, from 1ms to 10 minutes:
+/// recoveries, compactions, snapshots.
+pub const DURATION_MS_BUCKETS: &[f64] = &[
+    1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0, 10000.0, 30000.0, 60000.0, 300000.0,
+    600000.0,
+];
+
+/// Sizes in bytes, in powers of 4 from 64 B to 1 GiB: records, batches,
+/// blocks, segments.
+pub const SIZE_BYTES_BUCKETS: &[f64] = &[
+    64.0,
+    256.0,
+    1024.0,
+    4096.0,
+    16384.0,
+    65536.0,
+    262144.0,
+    1048576.0,
+    4194304.0,
+    16777216.0,
+    67108864.0,
+    268435456.0,
+    1073741824.0,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_are_increasing() {
+        // Backends such as Prometheus reject unsorted bounds
+        for buckets in [LATENCY_MS_BUCKETS, DURATION_MS_BUCKETS, SIZE_BYTES_BUCKETS] {
+            assert!(buckets.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! In-process broadcast of events to any number of subscribers.
+//!
+//! [`EventBus`] is a meter whose [`emit`](Meter::emit) appends to a bounded
+//! ring buffer and returns immediately, so a slow consumer can never stall
+//! the component emitting. Each [`Subscriber`] reads the buffer at its own
+//! pace; one that falls more than the capacity behind skips the oldest
+//! events and counts them as dropped.
+//!
+//! ```
+//! use nori_observe::{EventBus, Meter, VizEvent, WalEvt, WalKind};
+//!
+//! let bus = EventBus::new(1024);
+//! let mut ui = bus.subscribe();
+//! bus.emit(VizEvent::Wal(WalEvt { node: 1, seg: 4, kind: WalKind::SegmentRoll { bytes: 64 } }));
+//! assert!(ui.try_recv().is_some());
+//! ```
+//!
+//! The bus only carries events; its counters, gauges and histograms do
+//! nothing. Combine it with a metrics backend through a
+//! [`MultiMeter`](crate::MultiMeter).
+
+use crate::{Counter, Gauge, Histogram, Label, Meter, NoopMeter, VizEvent};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+/// Bounded broadcast channel of [`VizEvent`]s. Clones publish to the same
+/// bus; subscribers see the end of the stream once every clone is dropped.
+pub struct EventBus {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    capacity: usize,
+    state: Mutex<State>,
+    senders: AtomicUsize,
+}
+
+#[derive(Default)]
+struct State {
+    /// The most recent events, oldest first.
+    events: VecDeque<VizEvent>,
+    /// Sequence number of the front of `events`.
+    first_seq: u64,
+    subscribers: usize,
+    /// Subscribers waiting in `recv` for the next event.
+    waiting: Vec<Waker>,
+    closed: bool,
+}
+
+impl State {
+    fn next_seq(&self) -> u64 {
+        self.first_seq + self.events.len() as u64
+    }
+
+    /// Returns the event at a subscriber's `next_seq` and advances it,
+    /// first skipping ahead past events that were already evicted.
+    fn take(&self, next_seq: &mut u64, dropped: &mut u64) -> Option<VizEvent> {
+        if *next_seq < self.first_seq {
+            *dropped += self.first_seq - *next_seq;
+            *next_seq = self.first_seq;
+        }
+        let evt = self
+            .events
+            .get((*next_seq - self.first_seq) as usize)?
+            .clone();
+        *next_seq += 1;
+        Some(evt)
+    }
+}
+
+impl EventBus {
+    /// Creates a bus that holds up to `capacity` events for subscribers that
+    /// fall behind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "event bus capacity must be at least 1");
+        Self {
+            shared: Arc::new(Shared {
+                capacity,
+                state: Mutex::new(State::default()),
+                senders: AtomicUsize::new(1),
+            }),
+        }
+    }
+
+    /// Returns a subscriber that receives every event emitted from now on.
+    pub fn subscribe(&self) -> Subscriber {
+        let mut state = self.shared.lock();
+        state.subscribers += 1;
+        Subscriber {
+            shared: self.shared.clone(),
+            next_seq: state.next_seq(),
+            dropped: 0,
+        }
+    }
+
+    /// Number of live subscribers.
+    pub fn subscriber_count(&self) -> usize {
+        self.shared.lock().subscribers
+    }
+}
+
+impl Clone for EventBus {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for EventBus {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let mut state = self.shared.lock();
+            state.closed = true;
+            wake_all(&mut state);
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn wake_all(state: &mut State) {
+    for waker in state.waiting.drain(..) {
+        waker.wake();
+    }
+}
+
+impl Meter for EventBus {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        NoopMeter.counter_with(name, labels)
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        NoopMeter.gauge_with(name, labels)
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        NoopMeter.histo_with(name, buckets, labels)
+    }
+    fn emit(&self, evt: VizEvent) {
+        let mut state = self.shared.lock();
+        if state.subscribers == 0 {
+            // Nobody could ever read it
+            return;
+        }
+        if state.events.len() == self.shared.capacity {
+            state.events.pop_front();
+            state.first_seq += 1;
+        }
+        state.events.push_back(evt);
+        wake_all(&mut state);
+    }
+}
+
+/// Receiving end of an [`EventBus`], created with [`EventBus::subscribe`].
+pub struct Subscriber {
+    shared: Arc<Shared>,
+    next_seq: u64,
+    dropped: u64,
+}
+
+impl Subscriber {
+    /// Returns the next event if one is buffered, without waiting.
+    pub fn try_recv(&mut self) -> Option<VizEvent> {
+        let state = self.shared.lock();
+        state.take(&mut self.next_seq, &mut self.dropped)
+    }
+
+    /// Waits for the next event. Returns `None` once every [`EventBus`]
+    /// handle is dropped and the buffered events have been read.
+    pub async fn recv(&mut self) -> Option<VizEvent> {
+        poll_fn(|cx| {
+            let mut state = self.shared.lock();
+            if let Some(evt) = state.take(&mut self.next_seq, &mut self.dropped) {
+                return Poll::Ready(Some(evt));
+            }
+            if state.closed {
+                return Poll::Ready(None);
+            }
+            state.waiting.push(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Events emitted that this subscriber has not read yet.
+    pub fn lag(&self) -> u64 {
+        let state = self.shared.lock();
+        state.next_seq() - self.next_seq.max(state.first_seq)
+    }
+
+    /// Events this subscriber missed because it fell more than the bus
+    /// capacity behind.
+    pub fn dropped(&self) -> u64 {
+        let state = self.shared.lock();
+        self.dropped + state.first_seq.saturating_sub(self.next_seq)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.shared.lock().subscribers -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{WalEvt, WalKind};
+
+    fn roll(seg: u64) -> VizEvent {
+        VizEvent::Wal(WalEvt {
+            node: 1,
+            seg,
+            kind: WalKind::SegmentRoll { bytes: 0 },
+        })
+    }
+
+    fn seg(evt: VizEvent) -> u64 {
+        match evt {
+            VizEvent::Wal(e) => e.seg,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_slow_subscriber_drops_oldest() {
+        let bus = EventBus::new(4);
+        bus.emit(roll(0)); // before anyone subscribed
+        let mut fast = bus.subscribe();
+        let mut slow = bus.subscribe();
+        assert_eq!(bus.subscriber_count(), 2);
+
+        for i in 1..=3 {
+            bus.emit(roll(i));
+        }
+        assert_eq!(seg(fast.try_recv().unwrap()), 1);
+        assert_eq!(fast.lag(), 2);
+        for i in 4..=6 {
+            bus.emit(roll(i));
+        }
+
+        // The slow one lost 1 and 2 to the bound, the fast one only 2
+        assert_eq!(slow.lag(), 4);
+        assert_eq!(slow.dropped(), 2);
+        let seen: Vec<_> = std::iter::from_fn(|| slow.try_recv()).map(seg).collect();
+        assert_eq!(seen, [3, 4, 5, 6]);
+        assert_eq!(slow.dropped(), 2);
+        assert_eq!(seg(fast.try_recv().unwrap()), 3);
+        assert_eq!(fast.dropped(), 1);
+
+        drop(slow);
+        assert_eq!(bus.subscriber_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_recv_waits_and_ends_when_closed() {
+        let bus = EventBus::new(8);
+        let mut sub = bus.subscribe();
+        let reader = tokio::spawn(async move {
+            let mut segs = Vec::new();
+            while let Some(evt) = sub.recv().await {
+                segs.push(seg(evt));
+            }
+            segs
+        });
+
+        let sender = bus.clone();
+        drop(bus);
+        for i in 0..3 {
+            tokio::task::yield_now().await;
+            sender.emit(roll(i));
+        }
+        drop(sender);
+        assert_eq!(reader.await.unwrap(), [0, 1, 2]);
+    }
+}
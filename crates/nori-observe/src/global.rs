@@ -0,0 +1,53 @@
+//! Process-wide default meter.
+//!
+//! Components deep in a call tree can report through [`global`] instead of
+//! having a meter threaded through every constructor. Until
+//! [`set_global_meter`] is called it is a [`NoopMeter`].
+
+use crate::{Meter, NoopMeter};
+use std::sync::{Arc, PoisonError, RwLock};
+
+static GLOBAL: RwLock<Option<Arc<dyn Meter>>> = RwLock::new(None);
+
+/// Installs `meter` as the global meter, replacing any set before.
+///
+/// Instruments already created from the previous meter keep reporting to it.
+pub fn set_global_meter(meter: Arc<dyn Meter>) {
+    *GLOBAL.write().unwrap_or_else(PoisonError::into_inner) = Some(meter);
+}
+
+/// Returns the global meter.
+pub fn global() -> Arc<dyn Meter> {
+    GLOBAL
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .unwrap_or_else(|| Arc::new(NoopMeter))
+}
+
+// Exercises the macros, which record nothing with `off`
+#[cfg(all(test, not(feature = "off")))]
+mod tests {
+    use super::*;
+    use crate::TestMeter;
+
+    #[test]
+    fn test_global_macros() {
+        // Reporting before anything is installed goes nowhere
+        crate::obs_count_global!("early", &[], 1);
+
+        let meter = TestMeter::new();
+        set_global_meter(Arc::new(meter.clone()));
+        crate::obs_count_global!("appends", &[("node", "1")], 2);
+        crate::obs_gauge_global!("segments", &[], 5);
+        crate::obs_hist_global!("fsync_ms", &[], 0.5);
+        let out = crate::obs_timed_global!("work_ms", &[], { 7 });
+
+        assert_eq!(out, 7);
+        assert_eq!(meter.counter_total("early"), 0);
+        assert_eq!(meter.counter_total("appends"), 2);
+        assert_eq!(meter.gauge_value("segments"), Some(5));
+        assert_eq!(meter.histo_samples("fsync_ms"), vec![0.5]);
+        assert_eq!(meter.histo_samples("work_ms").len(), 1);
+    }
+}
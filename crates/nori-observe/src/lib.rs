@@ -2,6 +2,36 @@
 //!
 //! Core crates depend only on these traits and event types. Backends live elsewhere.
 
+mod buckets;
+mod bus;
+mod global;
+mod multi;
+#[cfg(feature = "record")]
+mod record;
+mod sampling;
+mod scoped;
+mod severity;
+mod testing;
+mod timed;
+
+pub use buckets::{DURATION_MS_BUCKETS, LATENCY_MS_BUCKETS, SIZE_BYTES_BUCKETS};
+pub use bus::{EventBus, Subscriber};
+pub use global::{global, set_global_meter};
+pub use multi::MultiMeter;
+#[cfg(feature = "record")]
+pub use record::{replay, EventLog, EventRecorder, ReplaySpeed};
+pub use sampling::{SamplingConfig, SamplingMeter};
+pub use scoped::ScopedMeter;
+pub use severity::{Severity, SeverityFilter};
+pub use testing::TestMeter;
+pub use timed::timed_future;
+
+use std::borrow::Cow;
+
+/// A label: static key, value either static or computed at runtime (a shard
+/// id, a tenant, a directory path).
+pub type Label = (&'static str, Cow<'static, str>);
+
 pub trait Counter: Send + Sync {
     fn inc(&self, v: u64);
 }
@@ -10,26 +40,106 @@ pub trait Gauge: Send + Sync {
 }
 pub trait Histogram: Send + Sync {
     fn observe(&self, v: f64);
+
+    /// Observes `v` and links it to the trace it came from, e.g. a slow
+    /// fsync to the request that waited on it. Backends with exemplar
+    /// support attach `trace_id` to the sample; by default it is dropped.
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        let _ = trace_id;
+        self.observe(v);
+    }
 }
 
+/// Backends implement the `_with` methods, which take labels whose values
+/// may be owned; the static-label methods forward to them.
 pub trait Meter: Send + Sync + 'static {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter>;
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge>;
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram>;
+    fn emit(&self, evt: VizEvent);
+
     fn counter(
         &self,
         name: &'static str,
         labels: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Counter>;
+    ) -> Box<dyn Counter> {
+        self.counter_with(name, &static_labels(labels))
+    }
     fn gauge(
         &self,
         name: &'static str,
         labels: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Gauge>;
+    ) -> Box<dyn Gauge> {
+        self.gauge_with(name, &static_labels(labels))
+    }
     fn histo(
         &self,
         name: &'static str,
-        _buckets: &'static [f64],
+        buckets: &'static [f64],
         labels: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Histogram>;
-    fn emit(&self, evt: VizEvent);
+    ) -> Box<dyn Histogram> {
+        self.histo_with(name, buckets, &static_labels(labels))
+    }
+
+    /// Starts timing a block for `obs_timed!`; the measurement ends when the
+    /// returned guard is dropped. By default the elapsed milliseconds go to
+    /// histogram `name` with [`LATENCY_MS_BUCKETS`], but backends can map it
+    /// to something richer, like a span.
+    fn start_timer(
+        &self,
+        name: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Box<dyn Timer> {
+        Box::new(HistoTimer {
+            histo: self.histo(name, LATENCY_MS_BUCKETS, labels),
+            start: std::time::Instant::now(),
+        })
+    }
+
+    /// Reports a finished span of a distributed trace, such as a WAL append
+    /// made on behalf of a traced request. Backends without tracing drop it,
+    /// which is the default.
+    fn record_span(&self, span: &SpanRecord) {
+        let _ = span;
+    }
+}
+
+/// A finished span of a distributed trace, reported with
+/// [`Meter::record_span`]. IDs are as in a W3C `traceparent`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanRecord {
+    pub name: &'static str,
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// The span this one is a child of, in the same trace.
+    pub parent_span_id: [u8; 8],
+    pub duration: std::time::Duration,
+    pub attributes: Vec<Label>,
+}
+
+/// Guard for a running measurement, returned by [`Meter::start_timer`]. It
+/// records when dropped.
+pub trait Timer {}
+
+struct HistoTimer {
+    histo: Box<dyn Histogram>,
+    start: std::time::Instant,
+}
+impl Timer for HistoTimer {}
+impl Drop for HistoTimer {
+    fn drop(&mut self) {
+        self.histo
+            .observe(self.start.elapsed().as_secs_f64() * 1000.0);
+    }
+}
+
+fn static_labels(labels: &'static [(&'static str, &'static str)]) -> Vec<Label> {
+    labels.iter().map(|&(k, v)| (k, Cow::Borrowed(v))).collect()
 }
 
 /// A do-nothing meter for tests and users who don't care about telemetry.
@@ -48,34 +158,25 @@ impl Histogram for NoopH {
     fn observe(&self, _v: f64) {}
 }
 impl Meter for NoopMeter {
-    fn counter(
-        &self,
-        _n: &'static str,
-        _l: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Counter> {
+    fn counter_with(&self, _n: &'static str, _l: &[Label]) -> Box<dyn Counter> {
         Box::new(NoopC)
     }
-    fn gauge(
-        &self,
-        _n: &'static str,
-        _l: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Gauge> {
+    fn gauge_with(&self, _n: &'static str, _l: &[Label]) -> Box<dyn Gauge> {
         Box::new(NoopG)
     }
-    fn histo(
-        &self,
-        _n: &'static str,
-        _b: &'static [f64],
-        _l: &'static [(&'static str, &'static str)],
-    ) -> Box<dyn Histogram> {
+    fn histo_with(&self, _n: &'static str, _b: &'static [f64], _l: &[Label]) -> Box<dyn Histogram> {
         Box::new(NoopH)
     }
     fn emit(&self, _e: VizEvent) {}
 }
 
 /// Typed events for live visualization (keys/values never included).
+///
+/// With the `serde` feature, events and their parts implement `Serialize` and
+/// `Deserialize` so they can be shipped to a remote visualizer or logged.
 #[non_exhaustive]
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VizEvent {
     Wal(WalEvt),
     Compaction(CompEvt),
@@ -86,26 +187,129 @@ pub enum VizEvent {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WalEvt {
     pub node: u32,
     pub seg: u64,
     pub kind: WalKind,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum WalKind {
-    SegmentRoll { bytes: u64 },
-    Fsync { ms: u32 },
-    CorruptionTruncated,
+    SegmentRoll {
+        bytes: u64,
+    },
+    Fsync {
+        ms: u32,
+    },
+    /// Recovery cut `bytes` of damaged data out of segment `seg`, starting
+    /// at `offset` in the segment as it was before recovery.
+    CorruptionTruncated {
+        offset: u64,
+        bytes: u64,
+        cause: CorruptionCause,
+    },
+    /// Corruption found in a sealed segment by a background check (nothing was modified).
+    CorruptionDetected {
+        offset: u64,
+    },
+    /// A sealed segment was re-read and every record passed CRC validation.
+    SegmentVerified,
+    /// A sealed segment could not be read back for verification.
+    SegmentVerifyFailed {
+        error: String,
+    },
+    /// A segment was deleted. Superseded by `SegmentPurged`, which nori-wal
+    /// emits instead.
     SegmentGc,
+    /// The WAL was dropped without `close()` or `sync()` while holding this
+    /// many appended bytes that were never fsynced.
+    UnsyncedDrop {
+        bytes: u64,
+    },
+    /// Appends waiting for the active segment grew past the backpressure
+    /// threshold, holding `queued_bytes` between them.
+    AppendBackpressure {
+        queued_bytes: u64,
+    },
+    /// An fsync took `ms`, at or above the configured `threshold_ms`. The
+    /// `Fsync` event for it is emitted as well.
+    SlowFsync {
+        ms: u32,
+        threshold_ms: u32,
+    },
+    /// Segment `seg` and its `bytes` were deleted, e.g. by retention or a
+    /// checkpoint purge.
+    SegmentPurged {
+        seg: u64,
+        bytes: u64,
+    },
+    /// A retention pass deleted `deleted_segments` segments; `WalEvt::seg` is
+    /// the cutoff, the segments deleted were all before it.
+    RetentionEnforced {
+        deleted_segments: u64,
+    },
+    /// Recovery on open finished in `ms`, leaving `records` valid records.
+    RecoveryCompleted {
+        ms: u64,
+        records: u64,
+    },
+    /// Operations of kind `op` slower than `threshold_ms` are using up the
+    /// error budget of their latency SLO `burn_rate` times faster than it
+    /// allows, at or above the configured alerting rate.
+    LatencySloBurn {
+        op: LatencyOp,
+        threshold_ms: u32,
+        burn_rate: f64,
+    },
+    /// The burn rate of the latency SLO for `op` fell back below the
+    /// alerting rate after a `LatencySloBurn`.
+    LatencySloRecovered {
+        op: LatencyOp,
+        burn_rate: f64,
+    },
+}
+
+/// Operation a latency SLO applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LatencyOp {
+    /// An `append` or `append_batch` call, including any fsync it waited for.
+    Append,
+    /// An fsync of the active segment.
+    Fsync,
+}
+
+impl LatencyOp {
+    /// Returns the operation's name, e.g. for a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyOp::Append => "append",
+            LatencyOp::Fsync => "fsync",
+        }
+    }
+}
+
+/// What gave damaged data away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CorruptionCause {
+    /// A record was complete but its checksum did not match.
+    Crc,
+    /// The bytes did not parse as a record: cut short, bad header or
+    /// undecodable payload.
+    Framing,
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CompEvt {
     pub node: u32,
     pub level: u8,
     pub kind: CompKind,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompKind {
     Scheduled,
     Start,
@@ -114,12 +318,14 @@ pub enum CompKind {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RaftEvt {
     pub shard: u32,
     pub term: u64,
     pub kind: RaftKind,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RaftKind {
     VoteReq { from: u32 },
     VoteGranted { from: u32 },
@@ -128,11 +334,13 @@ pub enum RaftKind {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SwimEvt {
     pub node: u32,
     pub kind: SwimKind,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SwimKind {
     Alive,
     Suspect,
@@ -141,11 +349,13 @@ pub enum SwimKind {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShardEvt {
     pub shard: u32,
     pub kind: ShardKind,
 }
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ShardKind {
     Plan,
     SnapshotStart,
@@ -154,37 +364,240 @@ pub enum ShardKind {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CacheEvt {
-    pub name: &'static str,
+    pub name: Cow<'static, str>,
     pub hit_ratio: f32,
 }
 
-/// Macros (simple versions). Can be feature-gated if desired.
+/// Macros (simple versions). All of them compile to nothing with the `off`
+/// feature: arguments are still type-checked, behind an `if false` the
+/// compiler removes along with their evaluation.
+#[cfg(not(feature = "off"))]
 #[macro_export]
 macro_rules! obs_count {
     ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
         $m.counter($name, $labels).inc($v as u64);
     }};
 }
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_count {
+    ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
+        if false {
+            $m.counter($name, $labels).inc($v as u64);
+        }
+    }};
+}
+#[cfg(not(feature = "off"))]
 #[macro_export]
 macro_rules! obs_gauge {
     ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
         $m.gauge($name, $labels).set($v as i64);
     }};
 }
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_gauge {
+    ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
+        if false {
+            $m.gauge($name, $labels).set($v as i64);
+        }
+    }};
+}
+/// Observes `$v`, optionally with bucket bounds such as
+/// [`LATENCY_MS_BUCKETS`]; without them the backend picks its own.
+#[cfg(not(feature = "off"))]
 #[macro_export]
 macro_rules! obs_hist {
     ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
         $m.histo($name, &[], $labels).observe($v as f64);
     }};
+    ($m:expr, $name:expr, $buckets:expr, $labels:expr, $v:expr) => {{
+        $m.histo($name, $buckets, $labels).observe($v as f64);
+    }};
 }
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_hist {
+    ($m:expr, $name:expr, $labels:expr, $v:expr) => {{
+        if false {
+            $m.histo($name, &[], $labels).observe($v as f64);
+        }
+    }};
+    ($m:expr, $name:expr, $buckets:expr, $labels:expr, $v:expr) => {{
+        if false {
+            $m.histo($name, $buckets, $labels).observe($v as f64);
+        }
+    }};
+}
+/// Times a synchronous block. For work that awaits, use `obs_timed_async!`.
+#[cfg(not(feature = "off"))]
 #[macro_export]
 macro_rules! obs_timed {
     ($m:expr, $name:expr, $labels:expr, $body:block) => {{
-        let __t = std::time::Instant::now();
+        let __timer = $m.start_timer($name, $labels);
         let __ret = { $body };
-        let __ms = __t.elapsed().as_secs_f64() * 1000.0;
-        $m.histo($name, &[], $labels).observe(__ms);
+        drop(__timer);
         __ret
     }};
 }
+/// With the `off` feature only the block runs.
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_timed {
+    ($m:expr, $name:expr, $labels:expr, $body:block) => {{
+        if false {
+            drop($m.start_timer($name, $labels));
+        }
+        $body
+    }};
+}
+/// Wraps a future so that the milliseconds from its first poll to its
+/// completion are observed into histogram `$name`, with
+/// [`LATENCY_MS_BUCKETS`]. The result is a future to `.await` or spawn.
+#[cfg(not(feature = "off"))]
+#[macro_export]
+macro_rules! obs_timed_async {
+    ($m:expr, $name:expr, $labels:expr, $fut:expr) => {
+        $crate::timed_future($m.histo($name, $crate::LATENCY_MS_BUCKETS, $labels), $fut)
+    };
+}
+/// With the `off` feature the future is returned unwrapped.
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_timed_async {
+    ($m:expr, $name:expr, $labels:expr, $fut:expr) => {{
+        if false {
+            let _ = $m.histo($name, &[], $labels);
+        }
+        $fut
+    }};
+}
+/// Emits `$evt` through `$m`. Use it rather than calling `emit` directly on
+/// hot paths, so that events compile out with the `off` feature too.
+#[cfg(not(feature = "off"))]
+#[macro_export]
+macro_rules! obs_emit {
+    ($m:expr, $evt:expr) => {{
+        $m.emit($evt);
+    }};
+}
+#[cfg(feature = "off")]
+#[macro_export]
+macro_rules! obs_emit {
+    ($m:expr, $evt:expr) => {{
+        if false {
+            $m.emit($evt);
+        }
+    }};
+}
+
+/// Variants of the macros above that report through [`global()`].
+#[macro_export]
+macro_rules! obs_count_global {
+    ($name:expr, $labels:expr, $v:expr) => {
+        $crate::obs_count!($crate::global(), $name, $labels, $v)
+    };
+}
+#[macro_export]
+macro_rules! obs_gauge_global {
+    ($name:expr, $labels:expr, $v:expr) => {
+        $crate::obs_gauge!($crate::global(), $name, $labels, $v)
+    };
+}
+#[macro_export]
+macro_rules! obs_hist_global {
+    ($name:expr, $labels:expr, $v:expr) => {
+        $crate::obs_hist!($crate::global(), $name, $labels, $v)
+    };
+    ($name:expr, $buckets:expr, $labels:expr, $v:expr) => {
+        $crate::obs_hist!($crate::global(), $name, $buckets, $labels, $v)
+    };
+}
+#[macro_export]
+macro_rules! obs_timed_global {
+    ($name:expr, $labels:expr, $body:block) => {
+        $crate::obs_timed!($crate::global(), $name, $labels, $body)
+    };
+}
+#[macro_export]
+macro_rules! obs_timed_async_global {
+    ($name:expr, $labels:expr, $fut:expr) => {
+        $crate::obs_timed_async!($crate::global(), $name, $labels, $fut)
+    };
+}
+#[macro_export]
+macro_rules! obs_emit_global {
+    ($evt:expr) => {
+        $crate::obs_emit!($crate::global(), $evt)
+    };
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_round_trip_through_json() {
+        let events = [
+            VizEvent::Wal(WalEvt {
+                node: 1,
+                seg: 12,
+                kind: WalKind::CorruptionDetected { offset: 4096 },
+            }),
+            VizEvent::Raft(RaftEvt {
+                shard: 3,
+                term: 9,
+                kind: RaftKind::LeaderElected { node: 2 },
+            }),
+            VizEvent::Cache(CacheEvt {
+                name: format!("block-{}", 0).into(),
+                hit_ratio: 0.5,
+            }),
+        ];
+        for event in events {
+            let json = serde_json::to_string(&event).unwrap();
+            let back: VizEvent = serde_json::from_str(&json).unwrap();
+            assert_eq!(format!("{:?}", back), format!("{:?}", event));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "off"))]
+mod off_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_macros_compile_out() {
+        let meter = TestMeter::new();
+        let mut evaluated = false;
+        obs_count!(meter, "appends", &[], {
+            evaluated = true;
+            1
+        });
+        obs_gauge!(meter, "segments", &[], 3);
+        obs_hist!(meter, "fsync_ms", LATENCY_MS_BUCKETS, &[], 0.5);
+        obs_emit!(
+            meter,
+            VizEvent::Wal(WalEvt {
+                node: 1,
+                seg: 0,
+                kind: WalKind::SegmentGc,
+            })
+        );
+        // Bodies still run, only the measurement is gone
+        assert_eq!(obs_timed!(meter, "work_ms", &[], { 7 }), 7);
+        assert_eq!(
+            obs_timed_async!(meter, "flush_ms", &[], async { 8 }).await,
+            8
+        );
+
+        assert!(!evaluated);
+        assert_eq!(meter.counter_total("appends"), 0);
+        assert_eq!(meter.gauge_value("segments"), None);
+        assert!(meter.histo_samples("fsync_ms").is_empty());
+        assert!(meter.histo_samples("work_ms").is_empty());
+        assert!(meter.events().is_empty());
+    }
+}
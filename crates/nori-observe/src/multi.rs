@@ -0,0 +1,103 @@
+//! Fan-out to several meters at once.
+
+use crate::{Counter, Gauge, Histogram, Label, Meter, SpanRecord, Timer, VizEvent};
+use std::sync::Arc;
+
+/// Forwards every instrument and event to all of its children, e.g. metrics
+/// to a Prometheus backend and events to a live UI.
+///
+/// ```
+/// use nori_observe::{Meter, MultiMeter, NoopMeter};
+/// use std::sync::Arc;
+///
+/// let meter = MultiMeter(vec![Arc::new(NoopMeter), Arc::new(NoopMeter)]);
+/// meter.counter("wal_appends_total", &[]).inc(1);
+/// ```
+#[derive(Clone, Default)]
+pub struct MultiMeter(pub Vec<Arc<dyn Meter>>);
+
+struct MultiC(Vec<Box<dyn Counter>>);
+impl Counter for MultiC {
+    fn inc(&self, v: u64) {
+        for c in &self.0 {
+            c.inc(v);
+        }
+    }
+}
+struct MultiG(Vec<Box<dyn Gauge>>);
+impl Gauge for MultiG {
+    fn set(&self, v: i64) {
+        for g in &self.0 {
+            g.set(v);
+        }
+    }
+}
+struct MultiH(Vec<Box<dyn Histogram>>);
+impl Histogram for MultiH {
+    fn observe(&self, v: f64) {
+        for h in &self.0 {
+            h.observe(v);
+        }
+    }
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        for h in &self.0 {
+            h.observe_with_exemplar(v, trace_id);
+        }
+    }
+}
+/// Holds the children's timers, which record when it is dropped.
+struct MultiT {
+    _timers: Vec<Box<dyn Timer>>,
+}
+impl Timer for MultiT {}
+
+impl Meter for MultiMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        Box::new(MultiC(
+            self.0
+                .iter()
+                .map(|m| m.counter_with(name, labels))
+                .collect(),
+        ))
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        Box::new(MultiG(
+            self.0.iter().map(|m| m.gauge_with(name, labels)).collect(),
+        ))
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        Box::new(MultiH(
+            self.0
+                .iter()
+                .map(|m| m.histo_with(name, buckets, labels))
+                .collect(),
+        ))
+    }
+    fn start_timer(
+        &self,
+        name: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Box<dyn Timer> {
+        Box::new(MultiT {
+            _timers: self.0.iter().map(|m| m.start_timer(name, labels)).collect(),
+        })
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        for m in &self.0 {
+            m.record_span(span);
+        }
+    }
+    fn emit(&self, evt: VizEvent) {
+        if let Some((last, rest)) = self.0.split_last() {
+            for m in rest {
+                m.emit(evt.clone());
+            }
+            last.emit(evt);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Recording the event stream to a file and replaying it later.
+//!
+//! [`EventRecorder`] is a meter that writes every emitted event to a file,
+//! with the time since recording started, and passes everything on to an
+//! inner meter. [`replay`] reads such a file back and emits its events into
+//! any meter, at the pace they were recorded, faster, or all at once:
+//!
+//! ```no_run
+//! use nori_observe::{replay, EventLog, EventRecorder, NoopMeter, ReplaySpeed, TestMeter};
+//! use std::sync::Arc;
+//!
+//! // During the incident
+//! let recorder = EventRecorder::create("incident.nev", Arc::new(NoopMeter))?;
+//! // ... hand `Arc::new(recorder)` to the WAL and other components ...
+//!
+//! // Later, ten times faster
+//! let viz = TestMeter::new();
+//! replay(EventLog::open("incident.nev")?, &viz, ReplaySpeed::Faster(10.0))?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! The file starts with an 8-byte magic, followed by one frame per event: a
+//! little-endian `u32` length and the bincode encoding of the microseconds
+//! since start and the event. A frame cut short by a crash ends the log.
+
+use crate::{Counter, Gauge, Histogram, Label, Meter, SpanRecord, Timer, VizEvent};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// First bytes of every recording; the last one is the format version.
+const MAGIC: &[u8; 8] = b"NORIEVT\x01";
+
+/// A [`Meter`] recording events to a file. See the [module docs](self).
+///
+/// Frames are buffered; [`flush`](Self::flush) writes them out, and so does
+/// dropping the recorder. Write errors can't be returned through [`Meter`],
+/// so they are counted, see [`write_errors`](Self::write_errors).
+pub struct EventRecorder {
+    inner: Arc<dyn Meter>,
+    out: Mutex<BufWriter<File>>,
+    started: Instant,
+    write_errors: AtomicU64,
+}
+
+impl EventRecorder {
+    /// Creates (or truncates) the file at `path` and starts recording.
+    pub fn create(path: impl AsRef<Path>, inner: Arc<dyn Meter>) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        Ok(Self {
+            inner,
+            out: Mutex::new(out),
+            started: Instant::now(),
+            write_errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Writes buffered events to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        self.lock().flush()
+    }
+
+    /// Events that could not be recorded so far.
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BufWriter<File>> {
+        self.out.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, evt: &VizEvent) -> io::Result<()> {
+        let at_us = self.started.elapsed().as_micros() as u64;
+        let frame = bincode::serialize(&(at_us, evt))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let len = u32::try_from(frame.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "event too large"))?;
+        let mut out = self.lock();
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(&frame)
+    }
+}
+
+impl Meter for EventRecorder {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        self.inner.counter_with(name, labels)
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        self.inner.gauge_with(name, labels)
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        self.inner.histo_with(name, buckets, labels)
+    }
+    fn start_timer(
+        &self,
+        name: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Box<dyn Timer> {
+        self.inner.start_timer(name, labels)
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        self.inner.record_span(span);
+    }
+    fn emit(&self, evt: VizEvent) {
+        if self.record(&evt).is_err() {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.emit(evt);
+    }
+}
+
+/// Reads a recording made by [`EventRecorder`], yielding each event with
+/// the time it was recorded at, relative to the start of the recording.
+pub struct EventLog {
+    reader: BufReader<File>,
+}
+
+impl EventLog {
+    /// Opens a recording, failing with `InvalidData` if the file isn't one.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a nori-observe event recording",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Reads the next frame. `None` at the end, including a torn last frame.
+    fn read_frame(&mut self) -> io::Result<Option<(Duration, VizEvent)>> {
+        let mut len = [0u8; 4];
+        if !read_all_or_eof(&mut self.reader, &mut len)? {
+            return Ok(None);
+        }
+        // Read what is there rather than trusting the length with an allocation
+        let len = u64::from(u32::from_le_bytes(len));
+        let mut frame = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut frame)?;
+        if (frame.len() as u64) < len {
+            return Ok(None);
+        }
+        let (at_us, evt): (u64, VizEvent) = bincode::deserialize(&frame)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((Duration::from_micros(at_us), evt)))
+    }
+}
+
+/// Fills `buf`, returning false if the reader ends first.
+fn read_all_or_eof(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl Iterator for EventLog {
+    type Item = io::Result<(Duration, VizEvent)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// How fast [`replay`] emits events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplaySpeed {
+    /// With the gaps they were recorded with.
+    Original,
+    /// With the gaps divided by this factor; 10.0 is ten times as fast.
+    Faster(f64),
+    /// Back to back, without waiting.
+    Unpaced,
+}
+
+/// Emits every event in `log` into `meter`, sleeping the calling thread
+/// between them as `speed` asks. Returns the number of events replayed.
+pub fn replay(log: EventLog, meter: &dyn Meter, speed: ReplaySpeed) -> io::Result<u64> {
+    let factor = match speed {
+        ReplaySpeed::Original => Some(1.0),
+        ReplaySpeed::Faster(factor) if factor > 0.0 && factor.is_finite() => Some(factor),
+        ReplaySpeed::Faster(_) | ReplaySpeed::Unpaced => None,
+    };
+    let started = Instant::now();
+    let mut replayed = 0;
+    for frame in log {
+        let (at, evt) = frame?;
+        if let Some(factor) = factor {
+            let due = at.div_f64(factor);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        meter.emit(evt);
+        replayed += 1;
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NoopMeter, TestMeter, WalEvt, WalKind};
+
+    fn roll(seg: u64) -> VizEvent {
+        VizEvent::Wal(WalEvt {
+            node: 1,
+            seg,
+            kind: WalKind::SegmentRoll { bytes: 64 },
+        })
+    }
+
+    #[test]
+    fn test_records_and_replays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.nev");
+        let live = TestMeter::new();
+        let recorder = EventRecorder::create(&path, Arc::new(live.clone())).unwrap();
+        recorder.emit(roll(0));
+        std::thread::sleep(Duration::from_millis(20));
+        recorder.emit(roll(1));
+        recorder.counter("appends", &[]).inc(3);
+        drop(recorder);
+        assert_eq!(live.events().len(), 2);
+        assert_eq!(live.counter_total("appends"), 3);
+
+        let frames: Vec<_> = EventLog::open(&path).unwrap().map(Result::unwrap).collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[1].0 - frames[0].0 >= Duration::from_millis(20));
+
+        // Paced replay keeps the gap, scaled down
+        let replayed = TestMeter::new();
+        let started = Instant::now();
+        let n = replay(
+            EventLog::open(&path).unwrap(),
+            &replayed,
+            ReplaySpeed::Faster(2.0),
+        )
+        .unwrap();
+        assert_eq!(n, 2);
+        assert!(started.elapsed() >= Duration::from_millis(10));
+        let segs: Vec<_> = replayed
+            .events()
+            .into_iter()
+            .map(|e| match e {
+                VizEvent::Wal(e) => e.seg,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(segs, [0, 1]);
+    }
+
+    #[test]
+    fn test_torn_frame_ends_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.nev");
+        let recorder = EventRecorder::create(&path, Arc::new(NoopMeter)).unwrap();
+        recorder.emit(roll(0));
+        recorder.emit(roll(1));
+        drop(recorder);
+
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+        let n = replay(
+            EventLog::open(&path).unwrap(),
+            &NoopMeter,
+            ReplaySpeed::Unpaced,
+        )
+        .unwrap();
+        assert_eq!(n, 1);
+
+        std::fs::write(&path, b"not a recording").unwrap();
+        let err = EventLog::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,285 @@
+//! Thinning out high-frequency telemetry before it reaches a backend.
+
+use crate::{
+    CompKind, Counter, Gauge, Histogram, Label, Meter, RaftKind, ShardKind, SpanRecord, SwimKind,
+    VizEvent, WalKind,
+};
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+/// How much of the telemetry a [`SamplingMeter`] lets through.
+#[derive(Debug, Clone)]
+pub struct SamplingConfig {
+    /// Keep one histogram observation in this many, per instrument. 1 keeps
+    /// all of them.
+    pub histo_one_in: u64,
+    /// Events allowed per second for each event kind (every fsync is one
+    /// kind, every segment roll another), with bursts of up to the same
+    /// number. `None` lets every event through.
+    pub events_per_sec: Option<u32>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            histo_one_in: 1,
+            events_per_sec: None,
+        }
+    }
+}
+
+/// Samples histograms 1-in-N and rate-limits events per kind before handing
+/// them to the inner meter. Counters and gauges are passed through as is.
+///
+/// Observations with an exemplar are always kept. What is left out is
+/// counted, both in [`dropped_samples`] and
+/// [`dropped_events`] and in the inner meter's
+/// `obs_dropped_samples_total` and `obs_dropped_events_total` counters. Kept
+/// histogram observations are not scaled up, so counts read from a sampled
+/// histogram are about `histo_one_in` times too low.
+///
+/// ```
+/// use nori_observe::{Meter, NoopMeter, SamplingConfig, SamplingMeter};
+/// use std::sync::Arc;
+///
+/// // Per-append latencies at 500k ops/sec: keep 1 in 100, and at most
+/// // 10 fsync events a second
+/// let meter = SamplingMeter::new(
+///     Arc::new(NoopMeter),
+///     SamplingConfig { histo_one_in: 100, events_per_sec: Some(10) },
+/// );
+/// ```
+///
+/// [`dropped_samples`]: SamplingMeter::dropped_samples
+/// [`dropped_events`]: SamplingMeter::dropped_events
+#[derive(Clone)]
+pub struct SamplingMeter {
+    inner: Arc<dyn Meter>,
+    config: SamplingConfig,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    dropped_samples: AtomicU64,
+    dropped_events: AtomicU64,
+    samples_counter: Box<dyn Counter>,
+    events_counter: Box<dyn Counter>,
+    buckets: Mutex<HashMap<KindKey, TokenBucket>>,
+}
+
+/// Identifies an event kind, ignoring the values it carries.
+#[derive(PartialEq, Eq, Hash)]
+enum KindKey {
+    Wal(Discriminant<WalKind>),
+    Compaction(Discriminant<CompKind>),
+    Raft(Discriminant<RaftKind>),
+    Swim(Discriminant<SwimKind>),
+    Shard(Discriminant<ShardKind>),
+    Cache,
+}
+
+impl KindKey {
+    fn of(evt: &VizEvent) -> Self {
+        match evt {
+            VizEvent::Wal(e) => KindKey::Wal(discriminant(&e.kind)),
+            VizEvent::Compaction(e) => KindKey::Compaction(discriminant(&e.kind)),
+            VizEvent::Raft(e) => KindKey::Raft(discriminant(&e.kind)),
+            VizEvent::Swim(e) => KindKey::Swim(discriminant(&e.kind)),
+            VizEvent::Shard(e) => KindKey::Shard(discriminant(&e.kind)),
+            VizEvent::Cache(_) => KindKey::Cache,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn full(rate: f64) -> Self {
+        Self {
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, rate: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SamplingMeter {
+    pub fn new(inner: Arc<dyn Meter>, config: SamplingConfig) -> Self {
+        let shared = Arc::new(Shared {
+            dropped_samples: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            samples_counter: inner.counter("obs_dropped_samples_total", &[]),
+            events_counter: inner.counter("obs_dropped_events_total", &[]),
+            buckets: Mutex::new(HashMap::new()),
+        });
+        Self {
+            inner,
+            config,
+            shared,
+        }
+    }
+
+    /// Histogram observations left out so far.
+    pub fn dropped_samples(&self) -> u64 {
+        self.shared.dropped_samples.load(Ordering::Relaxed)
+    }
+
+    /// Events left out by the rate limit so far.
+    pub fn dropped_events(&self) -> u64 {
+        self.shared.dropped_events.load(Ordering::Relaxed)
+    }
+}
+
+struct SampledH {
+    inner: Box<dyn Histogram>,
+    one_in: u64,
+    seen: AtomicU64,
+    shared: Arc<Shared>,
+}
+
+impl Histogram for SampledH {
+    fn observe(&self, v: f64) {
+        if self.seen.fetch_add(1, Ordering::Relaxed) % self.one_in == 0 {
+            self.inner.observe(v);
+        } else {
+            self.shared.dropped_samples.fetch_add(1, Ordering::Relaxed);
+            self.shared.samples_counter.inc(1);
+        }
+    }
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        // Worth keeping whatever the rate, and too rare to cost much
+        self.inner.observe_with_exemplar(v, trace_id);
+    }
+}
+
+impl Meter for SamplingMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        self.inner.counter_with(name, labels)
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        self.inner.gauge_with(name, labels)
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        let inner = self.inner.histo_with(name, buckets, labels);
+        if self.config.histo_one_in <= 1 {
+            return inner;
+        }
+        Box::new(SampledH {
+            inner,
+            one_in: self.config.histo_one_in,
+            seen: AtomicU64::new(0),
+            shared: self.shared.clone(),
+        })
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        // Only traced requests produce spans, so they are sampled already
+        self.inner.record_span(span);
+    }
+    fn emit(&self, evt: VizEvent) {
+        if let Some(rate) = self.config.events_per_sec {
+            let rate = f64::from(rate);
+            let allowed = {
+                let mut buckets = self
+                    .shared
+                    .buckets
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                buckets
+                    .entry(KindKey::of(&evt))
+                    .or_insert_with(|| TokenBucket::full(rate))
+                    .take(rate)
+            };
+            if !allowed {
+                self.shared.dropped_events.fetch_add(1, Ordering::Relaxed);
+                self.shared.events_counter.inc(1);
+                return;
+            }
+        }
+        self.inner.emit(evt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestMeter, WalEvt};
+
+    fn wal(kind: WalKind) -> VizEvent {
+        VizEvent::Wal(WalEvt {
+            node: 1,
+            seg: 0,
+            kind,
+        })
+    }
+
+    #[test]
+    fn test_samples_histograms_and_limits_events() {
+        let recorded = TestMeter::new();
+        let meter = SamplingMeter::new(
+            Arc::new(recorded.clone()),
+            SamplingConfig {
+                histo_one_in: 4,
+                events_per_sec: Some(3),
+            },
+        );
+
+        let histo = meter.histo("append_us", &[], &[]);
+        for i in 0..10 {
+            histo.observe(i as f64);
+        }
+        histo.observe_with_exemplar(99.0, "slow-append");
+        assert_eq!(recorded.histo_samples("append_us"), [0.0, 4.0, 8.0, 99.0]);
+        assert_eq!(meter.dropped_samples(), 7);
+
+        // Each kind gets its own burst of 3
+        for ms in 0..5 {
+            meter.emit(wal(WalKind::Fsync { ms }));
+        }
+        meter.emit(wal(WalKind::SegmentRoll { bytes: 1 }));
+        let fsyncs = recorded
+            .events()
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    VizEvent::Wal(WalEvt {
+                        kind: WalKind::Fsync { .. },
+                        ..
+                    })
+                )
+            })
+            .count();
+        assert_eq!(fsyncs, 3);
+        assert_eq!(recorded.events().len(), 4);
+        assert_eq!(meter.dropped_events(), 2);
+
+        // Counters pass through; drops are reported to the inner meter
+        meter.counter("appends", &[]).inc(10);
+        assert_eq!(recorded.counter_total("appends"), 10);
+        assert_eq!(recorded.counter_total("obs_dropped_samples_total"), 7);
+        assert_eq!(recorded.counter_total("obs_dropped_events_total"), 2);
+    }
+}
@@ -0,0 +1,135 @@
+//! Child meters that prefix names and add base labels.
+
+use crate::{Counter, Gauge, Histogram, Label, Meter, SpanRecord, VizEvent};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Prefixes every instrument name with `prefix_` and adds a base set of
+/// labels (node, shard, ...) before handing it to the parent meter. Labels
+/// given with an instrument win over base labels with the same key. Events
+/// pass through unchanged.
+///
+/// ```
+/// use nori_observe::{Meter, TestMeter};
+/// use std::sync::Arc;
+///
+/// let root = TestMeter::new();
+/// let parent: Arc<dyn Meter> = Arc::new(root.clone());
+/// let wal = parent.scoped("wal", vec![("shard", "7".into())]);
+/// wal.counter("appends_total", &[]).inc(1);
+///
+/// assert_eq!(root.counter_value("wal_appends_total", &[("shard", "7")]), 1);
+/// ```
+#[derive(Clone)]
+pub struct ScopedMeter {
+    parent: Arc<dyn Meter>,
+    prefix: String,
+    labels: Vec<Label>,
+}
+
+impl ScopedMeter {
+    pub fn new(parent: Arc<dyn Meter>, prefix: impl Into<String>, labels: Vec<Label>) -> Self {
+        Self {
+            parent,
+            prefix: prefix.into(),
+            labels,
+        }
+    }
+
+    fn name(&self, name: &'static str) -> &'static str {
+        if self.prefix.is_empty() {
+            return name;
+        }
+        intern(format!("{}_{}", self.prefix, name))
+    }
+
+    fn labels(&self, labels: &[Label]) -> Vec<Label> {
+        let mut merged: Vec<Label> = self
+            .labels
+            .iter()
+            .filter(|(k, _)| !labels.iter().any(|(own, _)| own == k))
+            .cloned()
+            .collect();
+        merged.extend_from_slice(labels);
+        merged
+    }
+}
+
+impl dyn Meter {
+    /// Returns a child meter that prefixes names with `prefix_` and adds
+    /// `labels` to every instrument. See [`ScopedMeter`].
+    pub fn scoped(self: Arc<Self>, prefix: impl Into<String>, labels: Vec<Label>) -> ScopedMeter {
+        ScopedMeter::new(self, prefix, labels)
+    }
+}
+
+impl Meter for ScopedMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        self.parent
+            .counter_with(self.name(name), &self.labels(labels))
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        self.parent
+            .gauge_with(self.name(name), &self.labels(labels))
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        self.parent
+            .histo_with(self.name(name), buckets, &self.labels(labels))
+    }
+    fn emit(&self, evt: VizEvent) {
+        self.parent.emit(evt);
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        self.parent.record_span(span);
+    }
+}
+
+/// Returns a `'static` copy of `name`. Instrument names come from a small
+/// fixed set, so each distinct one is leaked once and reused after.
+fn intern(name: String) -> &'static str {
+    static NAMES: Mutex<Option<HashSet<&'static str>>> = Mutex::new(None);
+    let mut names = NAMES.lock().unwrap_or_else(PoisonError::into_inner);
+    let names = names.get_or_insert_with(HashSet::new);
+    if let Some(&interned) = names.get(name.as_str()) {
+        return interned;
+    }
+    let interned: &'static str = Box::leak(name.into_boxed_str());
+    names.insert(interned);
+    interned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestMeter;
+
+    #[test]
+    fn test_nested_scopes() {
+        let root = TestMeter::new();
+        let parent: Arc<dyn Meter> = Arc::new(root.clone());
+        let node: Arc<dyn Meter> = Arc::new(parent.scoped("nori", vec![("node", "1".into())]));
+        let wal = node.scoped("wal", vec![("shard", "3".into())]);
+
+        wal.counter("appends_total", &[]).inc(2);
+        wal.counter_with("appends_total", &[("shard", "4".into())])
+            .inc(1);
+        wal.gauge("segments", &[]).set(9);
+
+        let both = [("node", "1"), ("shard", "3")];
+        assert_eq!(root.counter_value("nori_wal_appends_total", &both), 2);
+        assert_eq!(
+            root.counter_value("nori_wal_appends_total", &[("node", "1"), ("shard", "4")]),
+            1
+        );
+        assert_eq!(root.gauge_value("nori_wal_segments"), Some(9));
+        assert!(std::ptr::eq(
+            intern("nori_wal_segments".to_string()),
+            intern("nori_wal_segments".to_string())
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+//! Event severities and a meter that drops the minor ones.
+
+use crate::{
+    CompKind, Counter, Gauge, Histogram, Label, Meter, RaftKind, SpanRecord, SwimKind, Timer,
+    VizEvent, WalKind,
+};
+use std::sync::Arc;
+
+/// How much an event matters, from routine to needing attention.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    /// High-frequency detail, e.g. every fsync.
+    Debug,
+    /// Normal state changes, e.g. a segment roll or a new leader.
+    Info,
+    /// Degraded but handled, e.g. a suspected peer or unsynced data dropped.
+    Warn,
+    /// Data damaged or lost.
+    Error,
+}
+
+impl VizEvent {
+    /// Returns how much this event matters.
+    pub fn severity(&self) -> Severity {
+        match self {
+            VizEvent::Wal(e) => match e.kind {
+                WalKind::Fsync { .. } | WalKind::SegmentVerified => Severity::Debug,
+                WalKind::SegmentRoll { .. }
+                | WalKind::SegmentGc
+                | WalKind::SegmentPurged { .. }
+                | WalKind::RetentionEnforced { .. }
+                | WalKind::RecoveryCompleted { .. }
+                | WalKind::LatencySloRecovered { .. } => Severity::Info,
+                WalKind::UnsyncedDrop { .. }
+                | WalKind::AppendBackpressure { .. }
+                | WalKind::SlowFsync { .. }
+                | WalKind::LatencySloBurn { .. } => Severity::Warn,
+                WalKind::CorruptionTruncated { .. }
+                | WalKind::CorruptionDetected { .. }
+                | WalKind::SegmentVerifyFailed { .. } => Severity::Error,
+            },
+            VizEvent::Compaction(e) => match e.kind {
+                CompKind::Progress { .. } => Severity::Debug,
+                CompKind::Scheduled | CompKind::Start | CompKind::Finish { .. } => Severity::Info,
+            },
+            VizEvent::Raft(e) => match e.kind {
+                RaftKind::VoteReq { .. } | RaftKind::VoteGranted { .. } => Severity::Debug,
+                RaftKind::LeaderElected { .. } | RaftKind::StepDown => Severity::Info,
+            },
+            VizEvent::Swim(e) => match e.kind {
+                SwimKind::Alive => Severity::Debug,
+                SwimKind::Leave => Severity::Info,
+                SwimKind::Suspect | SwimKind::Confirm => Severity::Warn,
+            },
+            VizEvent::Shard(_) => Severity::Info,
+            VizEvent::Cache(_) => Severity::Debug,
+        }
+    }
+}
+
+/// Passes events of at least `min` severity on to the inner meter and drops
+/// the rest. Instruments are not filtered.
+///
+/// ```
+/// use nori_observe::{Meter, NoopMeter, Severity, SeverityFilter};
+/// use std::sync::Arc;
+///
+/// // Keep rotations and corruption, skip per-append fsyncs
+/// let meter = SeverityFilter::new(Arc::new(NoopMeter), Severity::Info);
+/// ```
+#[derive(Clone)]
+pub struct SeverityFilter {
+    inner: Arc<dyn Meter>,
+    min: Severity,
+}
+
+impl SeverityFilter {
+    pub fn new(inner: Arc<dyn Meter>, min: Severity) -> Self {
+        Self { inner, min }
+    }
+}
+
+impl Meter for SeverityFilter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        self.inner.counter_with(name, labels)
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        self.inner.gauge_with(name, labels)
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        self.inner.histo_with(name, buckets, labels)
+    }
+    fn start_timer(
+        &self,
+        name: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Box<dyn Timer> {
+        self.inner.start_timer(name, labels)
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        self.inner.record_span(span);
+    }
+    fn emit(&self, evt: VizEvent) {
+        if evt.severity() >= self.min {
+            self.inner.emit(evt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TestMeter, WalEvt};
+
+    #[test]
+    fn test_filter_drops_minor_events() {
+        let recorded = TestMeter::new();
+        let meter = SeverityFilter::new(Arc::new(recorded.clone()), Severity::Info);
+        for kind in [
+            WalKind::Fsync { ms: 1 },
+            WalKind::SegmentRoll { bytes: 10 },
+            WalKind::Fsync { ms: 2 },
+            WalKind::CorruptionDetected { offset: 4 },
+        ] {
+            meter.emit(VizEvent::Wal(WalEvt {
+                node: 1,
+                seg: 0,
+                kind,
+            }));
+        }
+        meter.counter("fsyncs", &[]).inc(2);
+
+        let kept: Vec<_> = recorded.events().iter().map(|e| e.severity()).collect();
+        assert_eq!(kept, [Severity::Info, Severity::Error]);
+        assert_eq!(recorded.counter_total("fsyncs"), 2);
+    }
+}
@@ -0,0 +1,280 @@
+//! A meter that remembers everything, for assertions in tests.
+
+use crate::{Counter, Gauge, Histogram, Label, Meter, SpanRecord, VizEvent};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Instrument name and its labels.
+type Key = (&'static str, Vec<(String, String)>);
+
+/// Records counter increments, gauge values, histogram samples and emitted
+/// events so tests can check what a component reported.
+///
+/// Clones share the same recordings, so keep one and hand another to the
+/// code under test:
+///
+/// ```
+/// use nori_observe::{Meter, TestMeter, VizEvent, WalEvt, WalKind};
+/// use std::sync::Arc;
+///
+/// let meter = TestMeter::new();
+/// let shared: Arc<dyn Meter> = Arc::new(meter.clone());
+/// shared.counter("wal_appends_total", &[("node", "1")]).inc(2);
+/// shared.emit(VizEvent::Wal(WalEvt { node: 1, seg: 0, kind: WalKind::Fsync { ms: 3 } }));
+///
+/// assert_eq!(meter.counter_total("wal_appends_total"), 2);
+/// assert!(meter.events().iter().any(|e| matches!(
+///     e,
+///     VizEvent::Wal(WalEvt { kind: WalKind::Fsync { .. }, .. })
+/// )));
+/// ```
+#[derive(Clone, Default)]
+pub struct TestMeter {
+    recorded: Arc<Mutex<Recorded>>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    counters: HashMap<Key, u64>,
+    gauges: HashMap<Key, i64>,
+    histos: HashMap<Key, Vec<f64>>,
+    exemplars: HashMap<Key, Vec<(f64, String)>>,
+    events: Vec<VizEvent>,
+    spans: Vec<SpanRecord>,
+}
+
+impl TestMeter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sum of all increments to counter `name`, across label sets.
+    pub fn counter_total(&self, name: &str) -> u64 {
+        let recorded = self.lock();
+        recorded
+            .counters
+            .iter()
+            .filter(|((n, _), _)| *n == name)
+            .map(|(_, v)| v)
+            .sum()
+    }
+
+    /// Sum of the increments to counter `name` with exactly these labels.
+    pub fn counter_value(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let recorded = self.lock();
+        recorded
+            .counters
+            .iter()
+            .find(|((n, l), _)| *n == name && labels_eq(l, labels))
+            .map_or(0, |(_, v)| *v)
+    }
+
+    /// Last value set on gauge `name`, with any labels.
+    pub fn gauge_value(&self, name: &str) -> Option<i64> {
+        let recorded = self.lock();
+        recorded
+            .gauges
+            .iter()
+            .find(|((n, _), _)| *n == name)
+            .map(|(_, v)| *v)
+    }
+
+    /// Samples observed by histogram `name`, across label sets.
+    pub fn histo_samples(&self, name: &str) -> Vec<f64> {
+        let recorded = self.lock();
+        recorded
+            .histos
+            .iter()
+            .filter(|((n, _), _)| *n == name)
+            .flat_map(|(_, v)| v.iter().copied())
+            .collect()
+    }
+
+    /// Samples observed with an exemplar by histogram `name`, with their
+    /// trace ids, across label sets. They are in [`histo_samples`] too.
+    ///
+    /// [`histo_samples`]: TestMeter::histo_samples
+    pub fn histo_exemplars(&self, name: &str) -> Vec<(f64, String)> {
+        let recorded = self.lock();
+        recorded
+            .exemplars
+            .iter()
+            .filter(|((n, _), _)| *n == name)
+            .flat_map(|(_, v)| v.iter().cloned())
+            .collect()
+    }
+
+    /// Events emitted so far, oldest first.
+    pub fn events(&self) -> Vec<VizEvent> {
+        self.lock().events.clone()
+    }
+
+    /// Spans recorded so far, oldest first.
+    pub fn spans(&self) -> Vec<SpanRecord> {
+        self.lock().spans.clone()
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn clear(&self) {
+        *self.lock() = Recorded::default();
+    }
+
+    fn instrument(&self, name: &'static str, labels: &[Label]) -> Box<TestInstrument> {
+        Box::new(TestInstrument {
+            recorded: self.recorded.clone(),
+            key: key(name, labels),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Recorded> {
+        self.recorded
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn key(name: &'static str, labels: &[Label]) -> Key {
+    let labels = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    (name, labels)
+}
+
+fn labels_eq(recorded: &[(String, String)], labels: &[(&str, &str)]) -> bool {
+    recorded.len() == labels.len()
+        && recorded
+            .iter()
+            .zip(labels)
+            .all(|((k1, v1), (k2, v2))| k1 == k2 && v1 == v2)
+}
+
+struct TestInstrument {
+    recorded: Arc<Mutex<Recorded>>,
+    key: Key,
+}
+
+impl TestInstrument {
+    fn with<R>(&self, f: impl FnOnce(&mut Recorded, &Key) -> R) -> R {
+        let mut recorded = self
+            .recorded
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        f(&mut recorded, &self.key)
+    }
+}
+
+impl Counter for TestInstrument {
+    fn inc(&self, v: u64) {
+        self.with(|r, key| *r.counters.entry(key.clone()).or_default() += v);
+    }
+}
+impl Gauge for TestInstrument {
+    fn set(&self, v: i64) {
+        self.with(|r, key| r.gauges.insert(key.clone(), v));
+    }
+}
+impl Histogram for TestInstrument {
+    fn observe(&self, v: f64) {
+        self.with(|r, key| r.histos.entry(key.clone()).or_default().push(v));
+    }
+    fn observe_with_exemplar(&self, v: f64, trace_id: &str) {
+        self.observe(v);
+        self.with(|r, key| {
+            r.exemplars
+                .entry(key.clone())
+                .or_default()
+                .push((v, trace_id.to_string()))
+        });
+    }
+}
+
+impl Meter for TestMeter {
+    fn counter_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Counter> {
+        self.instrument(name, labels)
+    }
+    fn gauge_with(&self, name: &'static str, labels: &[Label]) -> Box<dyn Gauge> {
+        self.instrument(name, labels)
+    }
+    fn histo_with(
+        &self,
+        name: &'static str,
+        _buckets: &'static [f64],
+        labels: &[Label],
+    ) -> Box<dyn Histogram> {
+        self.instrument(name, labels)
+    }
+    fn emit(&self, evt: VizEvent) {
+        self.lock().events.push(evt);
+    }
+    fn record_span(&self, span: &SpanRecord) {
+        self.lock().spans.push(span.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MultiMeter, WalEvt, WalKind};
+
+    #[test]
+    fn test_multi_meter_reaches_every_child() {
+        let (a, b) = (TestMeter::new(), TestMeter::new());
+        let meter = MultiMeter(vec![Arc::new(a.clone()), Arc::new(b.clone())]);
+
+        meter.counter("appends", &[("shard", "1")]).inc(3);
+        meter.counter("appends", &[("shard", "2")]).inc(1);
+        meter.gauge("segments", &[]).set(4);
+        meter.histo("fsync_ms", &[], &[]).observe(1.5);
+        meter
+            .histo("fsync_ms", &[], &[])
+            .observe_with_exemplar(40.0, "4bf92f35");
+        meter.emit(VizEvent::Wal(WalEvt {
+            node: 1,
+            seg: 7,
+            kind: WalKind::SegmentRoll { bytes: 10 },
+        }));
+        let span = SpanRecord {
+            name: "wal.fsync",
+            trace_id: [1; 16],
+            span_id: [3; 8],
+            parent_span_id: [2; 8],
+            duration: std::time::Duration::from_millis(4),
+            attributes: Vec::new(),
+        };
+        meter.record_span(&span);
+
+        for m in [&a, &b] {
+            assert_eq!(m.counter_total("appends"), 4);
+            assert_eq!(m.counter_value("appends", &[("shard", "2")]), 1);
+            assert_eq!(m.gauge_value("segments"), Some(4));
+            assert_eq!(m.histo_samples("fsync_ms"), vec![1.5, 40.0]);
+            assert_eq!(
+                m.histo_exemplars("fsync_ms"),
+                vec![(40.0, "4bf92f35".to_string())]
+            );
+            assert_eq!(m.events().len(), 1);
+            assert_eq!(m.spans(), vec![span.clone()]);
+        }
+
+        a.clear();
+        assert_eq!(a.counter_total("appends"), 0);
+        assert!(a.events().is_empty());
+        assert_eq!(b.events().len(), 1);
+    }
+
+    #[test]
+    fn test_dynamic_label_values() {
+        let meter = TestMeter::new();
+        for shard in [3u32, 3, 8] {
+            meter
+                .counter_with("appends", &[("shard", shard.to_string().into())])
+                .inc(1);
+        }
+        // Static labels end up in the same series as equal dynamic ones
+        meter.counter("appends", &[("shard", "8")]).inc(1);
+
+        assert_eq!(meter.counter_value("appends", &[("shard", "3")]), 2);
+        assert_eq!(meter.counter_value("appends", &[("shard", "8")]), 2);
+    }
+}
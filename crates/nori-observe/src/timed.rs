@@ -0,0 +1,45 @@
+//! Timing of async work.
+//!
+//! A timer guard held across an `.await` would count time spent before the
+//! future is first polled, and guards that enter spans must not cross await
+//! points at all. [`timed_future`] instead starts the clock on the first poll
+//! and stops it when the future completes.
+
+use crate::Histogram;
+use std::future::Future;
+use std::time::Instant;
+
+/// Awaits `fut`, then observes the milliseconds from its first poll to its
+/// completion into `histo`. Nothing is recorded if the future is dropped
+/// before it completes. Usually reached through `obs_timed_async!`.
+pub async fn timed_future<F: Future>(histo: Box<dyn Histogram>, fut: F) -> F::Output {
+    let start = Instant::now();
+    let out = fut.await;
+    histo.observe(start.elapsed().as_secs_f64() * 1000.0);
+    out
+}
+
+// Exercises the macros, which record nothing with `off`
+#[cfg(all(test, not(feature = "off")))]
+mod tests {
+    use crate::{obs_timed_async, Meter, TestMeter};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_measures_from_first_poll() {
+        let meter = TestMeter::new();
+        let fut = obs_timed_async!(meter, "flush_ms", &[("phase", "sync")], async {
+            tokio::task::yield_now().await;
+            7
+        });
+        // Time before the first poll is not counted
+        std::thread::sleep(Duration::from_millis(50));
+        // Spawnable, so it can cross threads
+        let out = tokio::spawn(fut).await.unwrap();
+
+        assert_eq!(out, 7);
+        let samples = meter.histo_samples("flush_ms");
+        assert_eq!(samples.len(), 1);
+        assert!(samples[0] < 50.0, "{:?}", samples);
+    }
+}
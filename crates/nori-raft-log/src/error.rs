@@ -0,0 +1,32 @@
+//! Errors returned by the Raft log.
+
+use nori_wal::SegmentError;
+use thiserror::Error;
+
+/// Errors from [`RaftLog`](crate::RaftLog) operations.
+#[derive(Debug, Error)]
+pub enum RaftLogError {
+    /// The underlying WAL failed.
+    #[error(transparent)]
+    Wal(#[from] SegmentError),
+
+    /// The entry has been folded into the snapshot and is no longer stored.
+    #[error("entry {index} has been compacted")]
+    Compacted { index: u64 },
+
+    /// The entry is past the end of the log.
+    #[error("entry {index} is past the last index {last_index}")]
+    Unavailable { index: u64, last_index: u64 },
+
+    /// Appended entries do not follow on from each other or from the log.
+    #[error("expected entry {expected}, got {actual}")]
+    NotContiguous { expected: u64, actual: u64 },
+
+    /// A snapshot at or before the current cut point was installed.
+    #[error("snapshot at {index} does not move past the current snapshot at {current}")]
+    SnapshotOutOfDate { index: u64, current: u64 },
+
+    /// The WAL holds records that do not form a valid Raft log.
+    #[error("corrupt raft log: {0}")]
+    Corrupt(String),
+}
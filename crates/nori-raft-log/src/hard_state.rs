@@ -0,0 +1,80 @@
+//! Consensus metadata kept beside the log.
+//!
+//! Raft must never forget its current term or the vote it cast in it, and
+//! must recover the cluster configuration it last acted on. These are stored
+//! as WAL metadata blobs (see [`nori_wal::MetaStore`]), each replaced
+//! atomically and checked by CRC on read:
+//!
+//! | Blob             | Value                                           |
+//! |------------------|-------------------------------------------------|
+//! | `hard-state`     | term (8 bytes) + voted for (0, or 1 + 8 bytes)  |
+//! | `cluster-config` | opaque bytes from the caller                    |
+//!
+//! Integers are big-endian, like the log records.
+
+use crate::error::RaftLogError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub(crate) const HARD_STATE_BLOB: &str = "hard-state";
+pub(crate) const CLUSTER_CONFIG_BLOB: &str = "cluster-config";
+
+/// The persistent state a Raft node must not lose: the latest term it has
+/// seen and the candidate it voted for in that term.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<u64>,
+}
+
+impl HardState {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(17);
+        buf.put_u64(self.term);
+        if let Some(node) = self.voted_for {
+            buf.put_u8(1);
+            buf.put_u64(node);
+        } else {
+            buf.put_u8(0);
+        }
+        buf
+    }
+
+    pub(crate) fn decode(mut data: &[u8]) -> Result<Self, RaftLogError> {
+        let len = data.len();
+        let (term, voted_for) = match len {
+            9 if data[8] == 0 => (data.get_u64(), None),
+            17 if data[8] == 1 => {
+                let term = data.get_u64();
+                data.advance(1);
+                (term, Some(data.get_u64()))
+            }
+            _ => {
+                return Err(RaftLogError::Corrupt(format!(
+                    "hard state of {} bytes",
+                    len
+                )))
+            }
+        };
+        Ok(Self { term, voted_for })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for state in [
+            HardState::default(),
+            HardState {
+                term: 9,
+                voted_for: Some(3),
+            },
+        ] {
+            assert_eq!(HardState::decode(&state.encode()).unwrap(), state);
+        }
+        assert!(HardState::decode(&[0; 10]).is_err());
+        assert!(HardState::decode(&[2; 17]).is_err());
+    }
+}
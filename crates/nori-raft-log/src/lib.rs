@@ -0,0 +1,44 @@
+//! Raft log storage on top of nori-wal.
+//!
+//! [`RaftLog`] stores a consensus log of [`Entry`]s, addressed by index and
+//! tagged with a term, in a [`Wal`](nori_wal::Wal). It provides what a Raft
+//! implementation needs from storage:
+//! - `append_entries` with the log-matching rules (matching entries are
+//!   kept, the first conflicting one truncates the suffix)
+//! - `truncate_suffix`, `first_index`, `last_index` and `term`
+//! - Range reads with a size limit, for building `AppendEntries` requests
+//! - A snapshot cut point, with segments before it purged
+//! - The current term, vote and cluster configuration, each replaced
+//!   atomically beside the log
+//!
+//! Each operation is one fsynced WAL record, and the index of entries to
+//! WAL positions is rebuilt by replay on open.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_raft_log::{Entry, RaftLog};
+//! use nori_wal::WalConfig;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut log = RaftLog::open(WalConfig::default()).await?;
+//! let next = log.last_index() + 1;
+//! log.append_entries(&[Entry::new(next, 1, b"set x=1".as_slice())]).await?;
+//!
+//! let entries = log.entries(log.first_index(), next + 1, Some(1 << 20)).await?;
+//! // Once a snapshot covering `next` has been written elsewhere
+//! log.compact_to(next).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod entry;
+mod error;
+mod hard_state;
+mod log;
+mod state;
+
+pub use entry::{Entry, SnapshotPoint};
+pub use error::RaftLogError;
+pub use hard_state::HardState;
+pub use log::RaftLog;
@@ -0,0 +1,467 @@
+//! The Raft log itself.
+
+use crate::entry::{Entry, LogOp, SnapshotPoint};
+use crate::error::RaftLogError;
+use crate::hard_state::{HardState, CLUSTER_CONFIG_BLOB, HARD_STATE_BLOB};
+use crate::state::LogState;
+use bytes::Bytes;
+use nori_wal::{Position, Record, RecoveryBudget, Wal, WalBuilder, WalConfig};
+
+/// A Raft log persisted in a [`Wal`].
+///
+/// Entries are addressed by index and carry the term they were created in.
+/// Every mutation is fsynced before it returns, whatever the WAL's fsync
+/// policy, since Raft may only acknowledge entries that are durable. If a
+/// mutation fails the in-memory index may no longer match the WAL, and the
+/// log should be reopened.
+pub struct RaftLog {
+    wal: Wal,
+    state: LogState,
+}
+
+impl RaftLog {
+    /// Opens the log stored in `config.dir`, replaying it to rebuild the
+    /// index.
+    pub async fn open(config: WalConfig) -> Result<Self, RaftLogError> {
+        Self::open_with(WalBuilder::from_config(config)).await
+    }
+
+    /// Opens the log with a WAL configured by `builder`.
+    ///
+    /// Any recovery budget is ignored, since the whole index is needed
+    /// before the log can be used.
+    pub async fn open_with(builder: WalBuilder) -> Result<Self, RaftLogError> {
+        let mut state = LogState::default();
+        let mut replay_error = None;
+        let (wal, _) = builder
+            .recovery_budget(RecoveryBudget::default())
+            .open_with_replay(|record, position| {
+                if replay_error.is_none() {
+                    if let Err(e) = replay(&mut state, record, position) {
+                        replay_error = Some(e);
+                    }
+                }
+            })
+            .await?;
+        if let Some(e) = replay_error {
+            return Err(e);
+        }
+        state.check()?;
+        Ok(Self { wal, state })
+    }
+
+    /// Index of the first stored entry. One past the snapshot point, or 1 if
+    /// no snapshot has been taken; past `last_index` if the log is empty.
+    pub fn first_index(&self) -> u64 {
+        self.state.first_index()
+    }
+
+    /// Index of the last entry, or of the snapshot point if no entries
+    /// follow it, or 0 for a new log.
+    pub fn last_index(&self) -> u64 {
+        self.state.last_index()
+    }
+
+    /// Term of the entry at `last_index`.
+    pub fn last_term(&self) -> u64 {
+        self.state
+            .term(self.last_index())
+            .expect("the last index always has a term")
+    }
+
+    /// Returns the term of the entry at `index`. The snapshot point still
+    /// has a term, so `first_index() - 1` can always be looked up.
+    pub fn term(&self, index: u64) -> Result<u64, RaftLogError> {
+        self.state.term(index)
+    }
+
+    /// Returns the last entry covered by a snapshot, if any.
+    pub fn snapshot_point(&self) -> Option<SnapshotPoint> {
+        self.state.snapshot()
+    }
+
+    /// Returns the entries in `lo..hi`.
+    ///
+    /// With `max_bytes`, stops once the payloads returned would exceed it,
+    /// but always returns at least one entry when the range is not empty.
+    pub async fn entries(
+        &self,
+        lo: u64,
+        hi: u64,
+        max_bytes: Option<u64>,
+    ) -> Result<Vec<Entry>, RaftLogError> {
+        if lo >= hi {
+            return Ok(Vec::new());
+        }
+        // Checks both ends of the range
+        self.state.position(hi - 1)?;
+        let mut reader = self.wal.reader(self.state.position(lo)?);
+
+        let mut entries = Vec::new();
+        let mut bytes = 0;
+        let mut index = lo;
+        while index < hi {
+            let expected = self.state.position(index)?;
+            let Some((record, position)) = reader.next_record().await? else {
+                return Err(RaftLogError::Corrupt(format!("entry {} is missing", index)));
+            };
+            // Truncated entries and other operations are skipped
+            if position != expected {
+                continue;
+            }
+            let LogOp::Entry(entry) = LogOp::from_record(record)? else {
+                return Err(RaftLogError::Corrupt(format!(
+                    "entry {} does not hold an entry",
+                    index
+                )));
+            };
+
+            bytes += entry.data.len() as u64;
+            if max_bytes.is_some_and(|max| bytes > max) && !entries.is_empty() {
+                break;
+            }
+            entries.push(entry);
+            index += 1;
+        }
+        Ok(entries)
+    }
+
+    /// Appends entries received from a leader, or proposed by this node.
+    ///
+    /// `entries` must have consecutive indexes starting no later than
+    /// `last_index() + 1`. Entries already in the log with the same term are
+    /// kept; at the first one whose term differs, the log is truncated and
+    /// the rest appended. Entries covered by the snapshot are ignored.
+    pub async fn append_entries(&mut self, entries: &[Entry]) -> Result<(), RaftLogError> {
+        let Some(first) = entries.first() else {
+            return Ok(());
+        };
+        for pair in entries.windows(2) {
+            if pair[1].index != pair[0].index + 1 {
+                return Err(RaftLogError::NotContiguous {
+                    expected: pair[0].index + 1,
+                    actual: pair[1].index,
+                });
+            }
+        }
+        let last_index = self.last_index();
+        if first.index > last_index + 1 {
+            return Err(RaftLogError::NotContiguous {
+                expected: last_index + 1,
+                actual: first.index,
+            });
+        }
+
+        let first_index = self.first_index();
+        let mut new = entries.iter().filter(|e| e.index >= first_index).peekable();
+        while let Some(entry) = new.peek() {
+            if entry.index > last_index || self.state.term(entry.index)? != entry.term {
+                break;
+            }
+            new.next();
+        }
+        let Some(conflict) = new.peek() else {
+            return Ok(());
+        };
+
+        let mut ops = Vec::new();
+        if conflict.index <= last_index {
+            ops.push(LogOp::Truncate(conflict.index));
+        }
+        ops.extend(new.cloned().map(LogOp::Entry));
+        self.write(&ops).await
+    }
+
+    /// Removes the entry at `index` and every entry after it.
+    ///
+    /// Fails with [`RaftLogError::Compacted`] if `index` is covered by the
+    /// snapshot; does nothing if it is past the end of the log.
+    pub async fn truncate_suffix(&mut self, index: u64) -> Result<(), RaftLogError> {
+        if index < self.first_index() {
+            return Err(RaftLogError::Compacted { index });
+        }
+        if index > self.last_index() {
+            return Ok(());
+        }
+        self.write(&[LogOp::Truncate(index)]).await
+    }
+
+    /// Moves the snapshot point to `index`, after a snapshot covering the
+    /// entries up to it has been taken.
+    ///
+    /// Entries up to `index` are dropped, and WAL segments holding nothing
+    /// but dropped entries are deleted. Does nothing if `index` is already
+    /// covered by the snapshot.
+    pub async fn compact_to(&mut self, index: u64) -> Result<(), RaftLogError> {
+        if index < self.first_index() {
+            return Ok(());
+        }
+        let term = self.state.term(index)?;
+        self.write(&[LogOp::Snapshot(SnapshotPoint { index, term })])
+            .await?;
+        self.purge().await
+    }
+
+    /// Installs a snapshot received from the leader.
+    ///
+    /// If the log holds the snapshot's last entry with the same term, the
+    /// entries after it are kept; otherwise the whole log is discarded and
+    /// the next entry appended must be `point.index + 1`.
+    pub async fn install_snapshot(&mut self, point: SnapshotPoint) -> Result<(), RaftLogError> {
+        if let Some(current) = self.snapshot_point() {
+            if point.index <= current.index {
+                return Err(RaftLogError::SnapshotOutOfDate {
+                    index: point.index,
+                    current: current.index,
+                });
+            }
+        }
+        self.write(&[LogOp::Snapshot(point)]).await?;
+        self.purge().await
+    }
+
+    /// Returns the saved term and vote, or the default (term 0, no vote) if
+    /// none has been saved.
+    pub async fn hard_state(&self) -> Result<HardState, RaftLogError> {
+        match self.wal.meta().get(HARD_STATE_BLOB).await? {
+            Some(data) => HardState::decode(&data),
+            None => Ok(HardState::default()),
+        }
+    }
+
+    /// Durably replaces the term and vote. Call this before answering any
+    /// message that depends on them.
+    pub async fn save_hard_state(&self, state: &HardState) -> Result<(), RaftLogError> {
+        let data = state.encode();
+        Ok(self.wal.meta().put(HARD_STATE_BLOB, &data).await?)
+    }
+
+    /// Returns the saved cluster configuration, if any.
+    pub async fn cluster_config(&self) -> Result<Option<Bytes>, RaftLogError> {
+        Ok(self.wal.meta().get(CLUSTER_CONFIG_BLOB).await?)
+    }
+
+    /// Durably replaces the cluster configuration, in whatever encoding the
+    /// caller uses.
+    pub async fn save_cluster_config(&self, config: &[u8]) -> Result<(), RaftLogError> {
+        Ok(self.wal.meta().put(CLUSTER_CONFIG_BLOB, config).await?)
+    }
+
+    /// Returns the underlying WAL, for metrics and inspection.
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    /// Closes the underlying WAL.
+    pub async fn close(self) -> Result<(), RaftLogError> {
+        Ok(self.wal.close().await?)
+    }
+
+    /// Durably appends `ops` and applies them to the index.
+    async fn write(&mut self, ops: &[LogOp]) -> Result<(), RaftLogError> {
+        let records: Vec<Record> = ops.iter().map(LogOp::to_record).collect();
+        let positions = self.wal.append_batch(&records).await?;
+        self.wal.sync().await?;
+        for (op, position) in ops.iter().zip(positions) {
+            self.state.apply(op, position)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes segments that hold nothing still needed.
+    async fn purge(&mut self) -> Result<(), RaftLogError> {
+        if let Some(cutoff) = self.state.purge_cutoff() {
+            self.wal.delete_segments_before(cutoff).await?;
+        }
+        Ok(())
+    }
+}
+
+fn replay(state: &mut LogState, record: Record, position: Position) -> Result<(), RaftLogError> {
+    let op = LogOp::from_record(record)?;
+    state.apply(&op, position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn config(dir: &TempDir) -> WalConfig {
+        WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    fn entries(range: std::ops::RangeInclusive<u64>, term: u64) -> Vec<Entry> {
+        range
+            .map(|i| Entry::new(i, term, format!("cmd{}", i).into_bytes()))
+            .collect()
+    }
+
+    fn segment_count(dir: &TempDir) -> usize {
+        std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension() == Some("wal".as_ref()))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_append_and_read_back_after_reopen() {
+        let dir = TempDir::new().unwrap();
+        let mut log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!((log.first_index(), log.last_index()), (1, 0));
+        assert_eq!(log.last_term(), 0);
+
+        log.append_entries(&entries(1..=5, 1)).await.unwrap();
+        log.append_entries(&entries(6..=8, 2)).await.unwrap();
+        log.close().await.unwrap();
+
+        let log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!((log.first_index(), log.last_index()), (1, 8));
+        assert_eq!(log.last_term(), 2);
+        assert_eq!(log.term(5).unwrap(), 1);
+        assert_eq!(
+            log.entries(3, 7, None).await.unwrap(),
+            [entries(3..=5, 1), entries(6..=6, 2)].concat()
+        );
+        // Each payload is 4 bytes
+        assert_eq!(log.entries(1, 9, Some(10)).await.unwrap().len(), 2);
+        assert_eq!(log.entries(1, 9, Some(0)).await.unwrap().len(), 1);
+        assert!(matches!(
+            log.entries(1, 10, None).await,
+            Err(RaftLogError::Unavailable { index: 9, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_entries_replace_the_suffix() {
+        let dir = TempDir::new().unwrap();
+        let mut log = RaftLog::open(config(&dir)).await.unwrap();
+        log.append_entries(&entries(1..=5, 1)).await.unwrap();
+
+        // A matching prefix is left alone, the conflict at 4 truncates
+        let mut from_leader = entries(2..=3, 1);
+        from_leader.extend(entries(4..=4, 2));
+        log.append_entries(&from_leader).await.unwrap();
+        assert_eq!(log.last_index(), 4);
+        assert_eq!(log.term(4).unwrap(), 2);
+
+        // Stale duplicates change nothing
+        log.append_entries(&entries(1..=2, 1)).await.unwrap();
+        assert_eq!(log.last_index(), 4);
+
+        log.truncate_suffix(3).await.unwrap();
+        log.append_entries(&entries(3..=3, 3)).await.unwrap();
+        let expected = [entries(1..=2, 1), entries(3..=3, 3)].concat();
+        assert_eq!(log.entries(1, 4, None).await.unwrap(), expected);
+        log.close().await.unwrap();
+
+        let log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!(log.entries(1, 4, None).await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_gaps_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let mut log = RaftLog::open(config(&dir)).await.unwrap();
+        log.append_entries(&entries(1..=2, 1)).await.unwrap();
+        assert!(matches!(
+            log.append_entries(&entries(4..=5, 1)).await,
+            Err(RaftLogError::NotContiguous {
+                expected: 3,
+                actual: 4
+            })
+        ));
+        let mut skipping = entries(3..=3, 1);
+        skipping.push(Entry::new(5, 1, b"x".as_slice()));
+        assert!(log.append_entries(&skipping).await.is_err());
+        assert_eq!(log.last_index(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compaction_purges_segments_and_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            max_segment_size: 1024 * 1024,
+            ..config(&dir)
+        };
+        let mut log = RaftLog::open(config.clone()).await.unwrap();
+        let payload = vec![0u8; 64 * 1024];
+        for i in 1..=40 {
+            let entry = Entry::new(i, 1, payload.clone());
+            log.append_entries(&[entry]).await.unwrap();
+        }
+        let segments_before = segment_count(&dir);
+
+        log.compact_to(30).await.unwrap();
+        assert_eq!(log.first_index(), 31);
+        assert_eq!(log.term(30).unwrap(), 1);
+        assert!(matches!(
+            log.term(29),
+            Err(RaftLogError::Compacted { index: 29 })
+        ));
+        assert!(segment_count(&dir) < segments_before);
+        log.append_entries(&entries(41..=42, 2)).await.unwrap();
+        log.close().await.unwrap();
+
+        let log = RaftLog::open(config).await.unwrap();
+        assert_eq!((log.first_index(), log.last_index()), (31, 42));
+        assert_eq!(
+            log.snapshot_point(),
+            Some(SnapshotPoint { index: 30, term: 1 })
+        );
+        assert_eq!(log.entries(31, 43, None).await.unwrap().len(), 12);
+    }
+
+    #[tokio::test]
+    async fn test_install_snapshot_past_the_log() {
+        let dir = TempDir::new().unwrap();
+        let mut log = RaftLog::open(config(&dir)).await.unwrap();
+        log.append_entries(&entries(1..=3, 1)).await.unwrap();
+
+        let point = SnapshotPoint { index: 10, term: 4 };
+        log.install_snapshot(point).await.unwrap();
+        assert_eq!((log.first_index(), log.last_index()), (11, 10));
+        assert_eq!(log.last_term(), 4);
+        assert!(matches!(
+            log.install_snapshot(SnapshotPoint { index: 8, term: 4 })
+                .await,
+            Err(RaftLogError::SnapshotOutOfDate {
+                index: 8,
+                current: 10
+            })
+        ));
+        log.append_entries(&entries(11..=12, 4)).await.unwrap();
+        log.close().await.unwrap();
+
+        let log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!((log.first_index(), log.last_index()), (11, 12));
+        assert_eq!(log.term(10).unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_hard_state_and_config_survive_reopen() {
+        let dir = TempDir::new().unwrap();
+        let log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!(log.hard_state().await.unwrap(), HardState::default());
+        assert_eq!(log.cluster_config().await.unwrap(), None);
+
+        let state = HardState {
+            term: 5,
+            voted_for: Some(2),
+        };
+        log.save_hard_state(&state).await.unwrap();
+        log.save_cluster_config(b"1,2,3").await.unwrap();
+        log.close().await.unwrap();
+
+        let log = RaftLog::open(config(&dir)).await.unwrap();
+        assert_eq!(log.hard_state().await.unwrap(), state);
+        assert_eq!(log.cluster_config().await.unwrap().unwrap(), &b"1,2,3"[..]);
+
+        // A damaged blob is an error, never a silently reset vote
+        std::fs::write(dir.path().join("hard-state.meta"), b"garbage").unwrap();
+        assert!(log.hard_state().await.is_err());
+    }
+}
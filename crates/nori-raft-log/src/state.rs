@@ -0,0 +1,233 @@
+//! The in-memory index of the log, rebuilt from the WAL on open.
+
+use crate::entry::{LogOp, SnapshotPoint};
+use crate::error::RaftLogError;
+use nori_wal::Position;
+use std::collections::VecDeque;
+
+/// Where a live entry is stored.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    term: u64,
+    position: Position,
+}
+
+/// Terms and WAL positions of the live entries, and the snapshot point.
+#[derive(Debug, Default)]
+pub(crate) struct LogState {
+    /// Index of `slots[0]`; meaningless while `slots` is empty.
+    first: u64,
+    slots: VecDeque<Slot>,
+    snapshot: Option<SnapshotPoint>,
+    /// Where the record holding `snapshot` was written.
+    snapshot_position: Option<Position>,
+}
+
+impl LogState {
+    pub(crate) fn first_index(&self) -> u64 {
+        if self.slots.is_empty() {
+            self.snapshot.map_or(1, |s| s.index + 1)
+        } else {
+            self.first
+        }
+    }
+
+    pub(crate) fn last_index(&self) -> u64 {
+        if self.slots.is_empty() {
+            self.snapshot.map_or(0, |s| s.index)
+        } else {
+            self.first + self.slots.len() as u64 - 1
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Option<SnapshotPoint> {
+        self.snapshot
+    }
+
+    /// Returns the term of the entry at `index`, which may be the snapshot
+    /// point. Index 0 precedes every entry and has term 0.
+    pub(crate) fn term(&self, index: u64) -> Result<u64, RaftLogError> {
+        match self.snapshot {
+            Some(snapshot) if snapshot.index == index => return Ok(snapshot.term),
+            None if index == 0 => return Ok(0),
+            _ => {}
+        }
+        self.slot(index).map(|slot| slot.term)
+    }
+
+    pub(crate) fn position(&self, index: u64) -> Result<Position, RaftLogError> {
+        self.slot(index).map(|slot| slot.position)
+    }
+
+    fn slot(&self, index: u64) -> Result<&Slot, RaftLogError> {
+        if index < self.first_index() {
+            return Err(RaftLogError::Compacted { index });
+        }
+        let last_index = self.last_index();
+        if index > last_index {
+            return Err(RaftLogError::Unavailable { index, last_index });
+        }
+        Ok(&self.slots[(index - self.first) as usize])
+    }
+
+    /// Applies an operation stored at `position`.
+    ///
+    /// Replay may begin partway through the log, since segments wholly
+    /// before the first live entry are purged, so an entry landing on an
+    /// empty index is accepted whatever its index; [`LogState::check`]
+    /// verifies the result once replay is done.
+    pub(crate) fn apply(&mut self, op: &LogOp, position: Position) -> Result<(), RaftLogError> {
+        match op {
+            LogOp::Entry(entry) => {
+                if self.slots.is_empty() {
+                    self.first = entry.index;
+                } else if entry.index != self.last_index() + 1 {
+                    return Err(RaftLogError::NotContiguous {
+                        expected: self.last_index() + 1,
+                        actual: entry.index,
+                    });
+                }
+                self.slots.push_back(Slot {
+                    term: entry.term,
+                    position,
+                });
+            }
+            LogOp::Truncate(index) => {
+                if !self.slots.is_empty() {
+                    let keep = index.saturating_sub(self.first);
+                    self.slots.truncate(keep as usize);
+                }
+            }
+            LogOp::Snapshot(point) => {
+                let covered = point.index.checked_sub(self.first);
+                match covered.and_then(|i| self.slots.get(i as usize)) {
+                    // The log continues past the snapshot
+                    Some(slot) if slot.term == point.term => {
+                        self.slots.drain(..=covered.unwrap() as usize);
+                        self.first = point.index + 1;
+                    }
+                    // Entries before the snapshot were purged already
+                    None if covered.is_none() && !self.slots.is_empty() => {}
+                    // The log disagrees with the snapshot or ends before it
+                    _ => self.slots.clear(),
+                }
+                self.snapshot = Some(*point);
+                self.snapshot_position = Some(position);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies that the live entries follow on from the snapshot point.
+    pub(crate) fn check(&self) -> Result<(), RaftLogError> {
+        let expected = self.snapshot.map_or(1, |s| s.index + 1);
+        if !self.slots.is_empty() && self.first != expected {
+            return Err(RaftLogError::Corrupt(format!(
+                "entries start at {} but the snapshot ends at {}",
+                self.first,
+                expected - 1
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the position before which no record is needed any more, once
+    /// a snapshot has been taken.
+    pub(crate) fn purge_cutoff(&self) -> Option<Position> {
+        let snapshot = self.snapshot_position?;
+        Some(match self.slots.front() {
+            Some(slot) => slot.position.min(snapshot),
+            None => snapshot,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::Entry;
+
+    fn at(offset: u64) -> Position {
+        Position {
+            segment_id: 0,
+            offset,
+        }
+    }
+
+    fn entry(index: u64, term: u64) -> LogOp {
+        LogOp::Entry(Entry::new(index, term, bytes::Bytes::new()))
+    }
+
+    fn snapshot(index: u64, term: u64) -> LogOp {
+        LogOp::Snapshot(SnapshotPoint { index, term })
+    }
+
+    fn replay(ops: &[LogOp]) -> LogState {
+        let mut state = LogState::default();
+        for (i, op) in ops.iter().enumerate() {
+            state.apply(op, at(i as u64 * 100)).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn test_truncate_then_reappend() {
+        let state = replay(&[
+            entry(1, 1),
+            entry(2, 1),
+            entry(3, 1),
+            LogOp::Truncate(2),
+            entry(2, 2),
+        ]);
+        assert_eq!((state.first_index(), state.last_index()), (1, 2));
+        assert_eq!(state.term(2).unwrap(), 2);
+        assert_eq!(state.position(2).unwrap(), at(400));
+        state.check().unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_keeps_matching_suffix() {
+        let state = replay(&[entry(1, 1), entry(2, 1), entry(3, 2), snapshot(2, 1)]);
+        assert_eq!((state.first_index(), state.last_index()), (3, 3));
+        assert_eq!(state.term(2).unwrap(), 1);
+        assert!(matches!(
+            state.term(1),
+            Err(RaftLogError::Compacted { index: 1 })
+        ));
+        assert_eq!(state.purge_cutoff(), Some(at(200)));
+    }
+
+    #[test]
+    fn test_snapshot_discards_conflicting_log() {
+        let state = replay(&[entry(1, 1), entry(2, 1), snapshot(2, 3)]);
+        assert_eq!((state.first_index(), state.last_index()), (3, 2));
+        assert_eq!(state.term(2).unwrap(), 3);
+        assert_eq!(state.purge_cutoff(), Some(at(200)));
+
+        let state = replay(&[entry(1, 1), snapshot(5, 2)]);
+        assert_eq!((state.first_index(), state.last_index()), (6, 5));
+    }
+
+    #[test]
+    fn test_replay_after_purge_starts_mid_log() {
+        // Entries 1-2 and the first snapshot record were purged
+        let state = replay(&[entry(3, 1), entry(4, 1), entry(5, 2), snapshot(4, 1)]);
+        assert_eq!((state.first_index(), state.last_index()), (5, 5));
+        state.check().unwrap();
+
+        let state = replay(&[entry(3, 1), entry(4, 1)]);
+        assert!(matches!(state.check(), Err(RaftLogError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_gaps_are_rejected() {
+        let mut state = replay(&[entry(1, 1)]);
+        assert!(matches!(
+            state.apply(&entry(3, 1), at(100)),
+            Err(RaftLogError::NotContiguous {
+                expected: 2,
+                actual: 3
+            })
+        ));
+    }
+}
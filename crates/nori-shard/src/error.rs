@@ -0,0 +1,10 @@
+//! Routing errors.
+
+use thiserror::Error;
+
+/// Errors from the shard router.
+#[derive(Debug, Error)]
+pub enum ShardError {
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+}
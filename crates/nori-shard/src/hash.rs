@@ -0,0 +1,95 @@
+//! The hash functions behind routing.
+//!
+//! Both are stable across versions and platforms: changing them would move
+//! every key to another shard.
+
+/// Hashes a key: 64-bit FNV-1a followed by a murmur3 finalizer, which
+/// spreads the short, similar keys FNV-1a is weak on.
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in key {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    mix(hash)
+}
+
+/// Jump consistent hash (Lamping and Veach, 2014): maps `hash` to one of
+/// `buckets` buckets such that growing the count from n to n + 1 moves
+/// only 1/(n + 1) of the hashes, all into the new bucket.
+///
+/// `buckets` must be at least 1.
+pub fn jump_hash(mut hash: u64, buckets: u32) -> u32 {
+    let mut bucket: i64 = -1;
+    let mut next: i64 = 0;
+    while next < i64::from(buckets) {
+        bucket = next;
+        hash = hash.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((hash >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+/// Weighted rendezvous (highest random weight) score of `node` for `item`.
+///
+/// The item goes to the nodes with the highest scores. With
+/// `-weight / ln(u)` for a uniform `u` in (0, 1), each node wins in
+/// proportion to its weight, and changing one node's weight or presence
+/// only moves items to or from that node. A weight of 0 never wins.
+pub fn rendezvous_score(item: u64, node: u32, weight: u32) -> f64 {
+    if weight == 0 {
+        return f64::NEG_INFINITY;
+    }
+    let hash = mix(item ^ mix(u64::from(node).wrapping_add(0x9e37_79b9_7f4a_7c15)));
+    // 53 random bits, shifted into (0, 1)
+    let unit = ((hash >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -f64::from(weight) / unit.ln()
+}
+
+fn mix(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_hash_moves_only_to_new_bucket() {
+        let hashes: Vec<u64> = (0..10_000u32).map(|i| key_hash(&i.to_be_bytes())).collect();
+        let before: Vec<u32> = hashes.iter().map(|&h| jump_hash(h, 10)).collect();
+        let after: Vec<u32> = hashes.iter().map(|&h| jump_hash(h, 11)).collect();
+
+        let mut moved = 0;
+        for (old, new) in before.iter().zip(&after) {
+            if old != new {
+                assert_eq!(*new, 10);
+                moved += 1;
+            }
+        }
+        // About 1/11 of them
+        assert!((700..1_100).contains(&moved), "{} moved", moved);
+        assert!(before.iter().all(|&b| b < 10));
+        assert_eq!(jump_hash(42, 1), 0);
+    }
+
+    #[test]
+    fn test_rendezvous_follows_weights() {
+        let mut wins = [0u32; 3];
+        let weights = [1, 2, 0];
+        for item in 0..30_000u64 {
+            let winner = (0..3u32)
+                .max_by(|&a, &b| {
+                    let score = |n: u32| rendezvous_score(item, n, weights[n as usize]);
+                    score(a).total_cmp(&score(b))
+                })
+                .unwrap();
+            wins[winner as usize] += 1;
+        }
+        assert_eq!(wins[2], 0);
+        assert!((9_000..11_000).contains(&wins[0]), "{:?}", wins);
+    }
+}
@@ -0,0 +1,37 @@
+//! Consistent-hash routing of keys to shards and shards to nodes.
+//!
+//! The key space is divided into a fixed number of shards, each a unit of
+//! replication with its own WAL. A [`Router`] answers two questions:
+//! - Which shard holds a key: jump consistent hashing over the shard count,
+//!   so a key's shard never changes
+//! - Which nodes hold a shard: weighted rendezvous hashing over the nodes
+//!   from a [`TopologySource`], so a topology change only moves the shards
+//!   of the nodes that joined, left or were reweighted
+//!
+//! Nodes come from a [`StaticTopology`] or, with [`SwimTopology`], from the
+//! members a nori-swim node sees as active. [`Router::refresh`] recomputes
+//! the placement and returns the shards that moved, for whatever opens and
+//! closes per-shard WALs to act on.
+//!
+//! # Example
+//!
+//! ```
+//! use nori_shard::{Router, RouterConfig, StaticTopology};
+//! use std::sync::Arc;
+//!
+//! let topology = Arc::new(StaticTopology::uniform([1, 2, 3, 4]));
+//! let router = Router::new(RouterConfig::default(), topology).unwrap();
+//! let route = router.route(b"user:42");
+//! assert_eq!(route.nodes.len(), 3);
+//! println!("shard {} on nodes {:?}", route.shard, route.nodes);
+//! ```
+
+pub mod error;
+pub mod hash;
+pub mod router;
+pub mod topology;
+
+pub use error::ShardError;
+pub use hash::{jump_hash, key_hash, rendezvous_score};
+pub use router::{place, Route, Router, RouterConfig};
+pub use topology::{NodeInfo, StaticTopology, SwimTopology, TopologySource};
@@ -0,0 +1,264 @@
+//! Mapping keys to shards and shards to nodes.
+
+use crate::error::ShardError;
+use crate::hash::{jump_hash, key_hash, rendezvous_score};
+use crate::topology::{NodeInfo, TopologySource};
+use std::sync::{Arc, RwLock};
+
+/// Settings for a [`Router`].
+#[derive(Debug, Clone)]
+pub struct RouterConfig {
+    /// Number of shards the key space is divided into (default: 1024).
+    /// Changing it moves keys between shards; leave room to grow.
+    pub num_shards: u32,
+    /// Nodes holding each shard (default: 3).
+    pub replicas: usize,
+}
+
+impl Default for RouterConfig {
+    fn default() -> Self {
+        Self {
+            num_shards: 1024,
+            replicas: 3,
+        }
+    }
+}
+
+impl RouterConfig {
+    pub fn validate(&self) -> Result<(), ShardError> {
+        if self.num_shards == 0 {
+            return Err(ShardError::InvalidConfig(
+                "num_shards must be at least 1".into(),
+            ));
+        }
+        if self.replicas == 0 {
+            return Err(ShardError::InvalidConfig(
+                "replicas must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where a key lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub shard: u32,
+    /// Nodes holding the shard, the preferred one first. Fewer than
+    /// `replicas` if there are not enough nodes.
+    pub nodes: Vec<u32>,
+}
+
+/// Routes keys to shards with jump consistent hashing, and shards to nodes
+/// with weighted rendezvous hashing.
+///
+/// Keys never change shard while `num_shards` stays the same. Shards move
+/// between nodes only when the topology changes, and then only to or from
+/// the nodes that changed: adding a node takes about its weighted share of
+/// shards from the others, removing one hands its shards out evenly.
+///
+/// Placement is recomputed from the [`TopologySource`] by
+/// [`Router::refresh`]; lookups between refreshes use the last placement
+/// and never block on the source.
+pub struct Router {
+    config: RouterConfig,
+    source: Arc<dyn TopologySource>,
+    /// Nodes of each shard, indexed by shard.
+    placement: RwLock<Arc<Vec<Vec<u32>>>>,
+}
+
+impl Router {
+    /// Creates a router and computes the placement for the source's
+    /// current nodes.
+    pub fn new(config: RouterConfig, source: Arc<dyn TopologySource>) -> Result<Self, ShardError> {
+        config.validate()?;
+        let placement = place_all(&config, &source.nodes());
+        Ok(Self {
+            config,
+            source,
+            placement: RwLock::new(Arc::new(placement)),
+        })
+    }
+
+    pub fn config(&self) -> &RouterConfig {
+        &self.config
+    }
+
+    /// Returns the shard holding `key`.
+    pub fn shard_for(&self, key: &[u8]) -> u32 {
+        jump_hash(key_hash(key), self.config.num_shards)
+    }
+
+    /// Returns the nodes holding `shard`, the preferred one first.
+    pub fn nodes_for(&self, shard: u32) -> Vec<u32> {
+        let placement = self.placement.read().unwrap();
+        placement.get(shard as usize).cloned().unwrap_or_default()
+    }
+
+    /// Returns the shard of `key` and the nodes holding it.
+    pub fn route(&self, key: &[u8]) -> Route {
+        let shard = self.shard_for(key);
+        Route {
+            shard,
+            nodes: self.nodes_for(shard),
+        }
+    }
+
+    /// Returns the shards `node` holds a replica of, in order.
+    pub fn shards_on(&self, node: u32) -> Vec<u32> {
+        let placement = self.placement.read().unwrap();
+        (0..self.config.num_shards)
+            .filter(|&shard| placement[shard as usize].contains(&node))
+            .collect()
+    }
+
+    /// Recomputes the placement from the topology source, returning the
+    /// shards whose nodes changed.
+    pub fn refresh(&self) -> Vec<u32> {
+        let placement = place_all(&self.config, &self.source.nodes());
+        let mut current = self.placement.write().unwrap();
+        let changed = (0..self.config.num_shards)
+            .filter(|&shard| current[shard as usize] != placement[shard as usize])
+            .collect();
+        *current = Arc::new(placement);
+        changed
+    }
+}
+
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Picks up to `replicas` nodes for `shard`, highest rendezvous score first.
+pub fn place(shard: u32, nodes: &[NodeInfo], replicas: usize) -> Vec<u32> {
+    let item = key_hash(&shard.to_be_bytes());
+    let mut scored: Vec<(f64, u32)> = nodes
+        .iter()
+        .filter(|node| node.weight > 0)
+        .map(|node| (rendezvous_score(item, node.id, node.weight), node.id))
+        .collect();
+    // Ties broken by ID, so the order never depends on the source's order
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.dedup_by_key(|(_, id)| *id);
+    scored
+        .into_iter()
+        .take(replicas)
+        .map(|(_, id)| id)
+        .collect()
+}
+
+fn place_all(config: &RouterConfig, nodes: &[NodeInfo]) -> Vec<Vec<u32>> {
+    (0..config.num_shards)
+        .map(|shard| place(shard, nodes, config.replicas))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::{StaticTopology, SwimTopology};
+    use std::sync::Mutex;
+
+    /// A topology that can be changed between refreshes.
+    struct Changing(Mutex<Vec<NodeInfo>>);
+
+    impl TopologySource for Changing {
+        fn nodes(&self) -> Vec<NodeInfo> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    fn node(id: u32, weight: u32) -> NodeInfo {
+        NodeInfo { id, weight }
+    }
+
+    #[test]
+    fn test_routes_keys_to_replicated_shards() {
+        let source = Arc::new(StaticTopology::uniform(1..=5));
+        let router = Router::new(RouterConfig::default(), source).unwrap();
+
+        let route = router.route(b"user:42");
+        assert_eq!(route.shard, router.shard_for(b"user:42"));
+        assert_eq!(route.nodes.len(), 3);
+        let mut distinct = route.nodes.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 3);
+
+        // Every node holds about 3/5 of the shards
+        for id in 1..=5 {
+            let held = router.shards_on(id).len();
+            assert!((500..730).contains(&held), "node {} holds {}", id, held);
+        }
+        assert!(matches!(
+            Router::new(
+                RouterConfig {
+                    num_shards: 0,
+                    ..Default::default()
+                },
+                Arc::new(StaticTopology::default())
+            ),
+            Err(ShardError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_refresh_moves_only_affected_shards() {
+        let source = Arc::new(Changing(Mutex::new(
+            (1..=4).map(|id| node(id, 1)).collect(),
+        )));
+        let config = RouterConfig {
+            num_shards: 1000,
+            replicas: 1,
+        };
+        let router = Router::new(config, source.clone()).unwrap();
+        let before: Vec<_> = (0..1000).map(|s| router.nodes_for(s)).collect();
+
+        // A fifth node takes about a fifth of the shards, from everyone
+        source.0.lock().unwrap().push(node(5, 1));
+        let changed = router.refresh();
+        assert!(
+            (150..250).contains(&changed.len()),
+            "{} moved",
+            changed.len()
+        );
+        for &shard in &changed {
+            assert_eq!(router.nodes_for(shard), [5]);
+        }
+        assert!(router.refresh().is_empty());
+
+        // Draining node 2 moves only its shards
+        let on_two = router.shards_on(2);
+        source.0.lock().unwrap()[1].weight = 0;
+        let mut changed = router.refresh();
+        changed.sort();
+        assert_eq!(changed, on_two);
+        assert!(router.shards_on(2).is_empty());
+        let unchanged = (0..1000)
+            .filter(|&s| before[s as usize] == router.nodes_for(s))
+            .count();
+        assert!(unchanged > 500);
+    }
+
+    #[tokio::test]
+    async fn test_swim_topology_lists_active_members() {
+        let config = nori_swim::SwimConfig {
+            node_id: 7,
+            bind_addr: ([127, 0, 0, 1], 0).into(),
+            ..Default::default()
+        };
+        let swim = nori_swim::Swim::start(config, Arc::new(nori_observe::NoopMeter))
+            .await
+            .unwrap();
+        let weights = [(7, 4)].into_iter().collect();
+        let topology = SwimTopology::new(Arc::new(swim)).with_weights(weights, 1);
+        assert_eq!(topology.nodes(), vec![node(7, 4)]);
+
+        let router = Router::new(RouterConfig::default(), Arc::new(topology)).unwrap();
+        assert_eq!(router.route(b"k").nodes, [7]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Where the router learns which nodes exist.
+
+use nori_swim::Swim;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A node that can hold shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeInfo {
+    pub id: u32,
+    /// Relative share of shards the node should hold; 0 drains it.
+    pub weight: u32,
+}
+
+/// Supplies the current set of nodes.
+///
+/// The router asks on every [`Router::refresh`](crate::Router::refresh), so
+/// a source should answer from what it already knows rather than block.
+pub trait TopologySource: Send + Sync {
+    /// Returns the nodes shards may be placed on, in any order.
+    fn nodes(&self) -> Vec<NodeInfo>;
+}
+
+/// A fixed list of nodes, for tests and statically configured clusters.
+#[derive(Debug, Clone, Default)]
+pub struct StaticTopology {
+    nodes: Vec<NodeInfo>,
+}
+
+impl StaticTopology {
+    pub fn new(nodes: Vec<NodeInfo>) -> Self {
+        Self { nodes }
+    }
+
+    /// Nodes `ids`, each with weight 1.
+    pub fn uniform(ids: impl IntoIterator<Item = u32>) -> Self {
+        Self::new(
+            ids.into_iter()
+                .map(|id| NodeInfo { id, weight: 1 })
+                .collect(),
+        )
+    }
+}
+
+impl TopologySource for StaticTopology {
+    fn nodes(&self) -> Vec<NodeInfo> {
+        self.nodes.clone()
+    }
+}
+
+/// The members a SWIM node sees as alive or suspect.
+///
+/// Suspects keep their shards, so a node that refutes a suspicion does not
+/// cause shards to move twice; shards move once it is declared dead or
+/// leaves.
+pub struct SwimTopology {
+    swim: Arc<Swim>,
+    weights: HashMap<u32, u32>,
+    default_weight: u32,
+}
+
+impl SwimTopology {
+    /// Every active member, with weight 1.
+    pub fn new(swim: Arc<Swim>) -> Self {
+        Self {
+            swim,
+            weights: HashMap::new(),
+            default_weight: 1,
+        }
+    }
+
+    /// Gives the members in `weights` their weight; the rest keep
+    /// `default_weight`.
+    pub fn with_weights(mut self, weights: HashMap<u32, u32>, default_weight: u32) -> Self {
+        self.weights = weights;
+        self.default_weight = default_weight;
+        self
+    }
+}
+
+impl TopologySource for SwimTopology {
+    fn nodes(&self) -> Vec<NodeInfo> {
+        self.swim
+            .members()
+            .into_iter()
+            .filter(|member| member.state.is_active())
+            .map(|member| NodeInfo {
+                id: member.id,
+                weight: *self.weights.get(&member.id).unwrap_or(&self.default_weight),
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for SwimTopology {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SwimTopology")
+            .field("weights", &self.weights)
+            .field("default_weight", &self.default_weight)
+            .finish()
+    }
+}
@@ -0,0 +1,264 @@
+//! Blocks of prefix-compressed, sorted entries.
+//!
+//! Each entry stores only the part of its key that differs from the previous
+//! key. Every `restart_interval` entries the full key is stored instead, and
+//! the offsets of these restart points, listed at the end of the block, let
+//! a lookup binary search to the right stretch before scanning.
+//!
+//! Entry layout:
+//! - shared: varint (bytes shared with the previous key)
+//! - unshared: varint
+//! - value_len: varint
+//! - key suffix: `unshared` bytes
+//! - value: `value_len` bytes
+//!
+//! The block ends with the restart offsets (u32 each, little-endian) and
+//! their count (u32).
+
+use crate::error::SstError;
+use crate::format::{get_varint, put_varint};
+use bytes::{Buf, BufMut, Bytes};
+use std::cmp::Ordering;
+
+pub(crate) struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    since_restart: usize,
+    restart_interval: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    pub fn new(restart_interval: usize) -> Self {
+        Self {
+            buf: Vec::new(),
+            restarts: Vec::new(),
+            since_restart: 0,
+            restart_interval,
+            last_key: Vec::new(),
+        }
+    }
+
+    /// Adds an entry; keys must be added in increasing order.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        let shared = if self.since_restart == 0 || self.buf.is_empty() {
+            self.restarts.push(self.buf.len() as u32);
+            self.since_restart = 0;
+            0
+        } else {
+            key.iter()
+                .zip(&self.last_key)
+                .take_while(|(a, b)| a == b)
+                .count()
+        };
+        put_varint(&mut self.buf, shared as u64);
+        put_varint(&mut self.buf, (key.len() - shared) as u64);
+        put_varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.since_restart = (self.since_restart + 1) % self.restart_interval;
+    }
+
+    /// Size of the block if finished now.
+    pub fn estimated_len(&self) -> usize {
+        self.buf.len() + 4 * (self.restarts.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Last key added, kept across `finish`.
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// Returns the encoded block and resets the builder.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buf);
+        for &restart in &self.restarts {
+            block.put_u32_le(restart);
+        }
+        block.put_u32_le(self.restarts.len() as u32);
+        self.restarts.clear();
+        self.since_restart = 0;
+        block
+    }
+}
+
+/// A parsed block.
+#[derive(Debug, Clone)]
+pub struct Block {
+    data: Bytes,
+    /// Where the restart array starts, which is also where entries end.
+    restarts_at: usize,
+    restarts: usize,
+}
+
+impl Block {
+    pub(crate) fn new(data: Bytes) -> Result<Self, SstError> {
+        let corrupt = || SstError::Corruption("bad block restart array".into());
+        if data.len() < 4 {
+            return Err(corrupt());
+        }
+        let restarts = (&data[data.len() - 4..]).get_u32_le() as usize;
+        let restarts_at = restarts
+            .checked_mul(4)
+            .and_then(|len| (data.len() - 4).checked_sub(len))
+            .ok_or_else(corrupt)?;
+        Ok(Self {
+            data,
+            restarts_at,
+            restarts,
+        })
+    }
+
+    /// Bytes the block holds, for cache accounting.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn restart(&self, i: usize) -> usize {
+        let at = self.restarts_at + 4 * i;
+        (&self.data[at..at + 4]).get_u32_le() as usize
+    }
+
+    /// Returns an iterator from the first entry.
+    pub(crate) fn iter(&self) -> BlockIter<'_> {
+        BlockIter {
+            block: self,
+            offset: 0,
+            key: Vec::new(),
+        }
+    }
+
+    /// Returns an iterator from the first entry with a key at least `key`.
+    pub(crate) fn seek(&self, key: &[u8]) -> Result<BlockIter<'_>, SstError> {
+        // Last restart point whose key is below the target
+        let (mut low, mut high) = (0, self.restarts);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            let mut iter = self.iter();
+            iter.offset = self.restart(mid);
+            match iter.next_entry()? {
+                Some((restart_key, _)) if restart_key.as_slice() < key => low = mid,
+                _ => high = mid,
+            }
+        }
+
+        let mut iter = self.iter();
+        if self.restarts > 0 {
+            iter.offset = self.restart(low);
+        }
+        loop {
+            let before = (iter.offset, iter.key.clone());
+            match iter.next_entry()? {
+                Some((entry_key, _)) if entry_key.as_slice() < key => {}
+                _ => {
+                    (iter.offset, iter.key) = before;
+                    return Ok(iter);
+                }
+            }
+        }
+    }
+
+    /// Returns the value stored under exactly `key`.
+    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Bytes>, SstError> {
+        match self.seek(key)?.next_entry()? {
+            Some((entry_key, value)) if entry_key.as_slice().cmp(key) == Ordering::Equal => {
+                Ok(Some(value))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+pub(crate) struct BlockIter<'a> {
+    block: &'a Block,
+    offset: usize,
+    key: Vec<u8>,
+}
+
+impl BlockIter<'_> {
+    /// Returns the next key and value, or `None` at the end of the block.
+    pub fn next_entry(&mut self) -> Result<Option<(Vec<u8>, Bytes)>, SstError> {
+        if self.offset >= self.block.restarts_at {
+            return Ok(None);
+        }
+        let corrupt = || SstError::Corruption("bad block entry".into());
+        let mut data = &self.block.data[self.offset..self.block.restarts_at];
+        let shared = get_varint(&mut data)? as usize;
+        let unshared = get_varint(&mut data)? as usize;
+        let value_len = get_varint(&mut data)? as usize;
+        if shared > self.key.len() || unshared.saturating_add(value_len) > data.len() {
+            return Err(corrupt());
+        }
+
+        let header_len = self.block.restarts_at - self.offset - data.len();
+        let key_at = self.offset + header_len;
+        let value_at = key_at + unshared;
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&data[..unshared]);
+        self.offset = value_at + value_len;
+        Ok(Some((
+            self.key.clone(),
+            self.block.data.slice(value_at..value_at + value_len),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(keys: &[String], restart_interval: usize) -> Block {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for key in keys {
+            builder.add(key.as_bytes(), format!("v-{}", key).as_bytes());
+        }
+        Block::new(builder.finish().into()).unwrap()
+    }
+
+    #[test]
+    fn test_iterate_and_seek() {
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i * 2)).collect();
+        for restart_interval in [1, 3, 16, 1000] {
+            let block = build(&keys, restart_interval);
+
+            let mut iter = block.iter();
+            let mut seen = Vec::new();
+            while let Some((key, value)) = iter.next_entry().unwrap() {
+                assert_eq!(value, format!("v-{}", String::from_utf8_lossy(&key)));
+                seen.push(String::from_utf8(key).unwrap());
+            }
+            assert_eq!(seen, keys);
+
+            assert_eq!(block.get(b"key010").unwrap().unwrap(), "v-key010");
+            assert_eq!(block.get(b"key011").unwrap(), None);
+            assert_eq!(block.get(b"a").unwrap(), None);
+            assert_eq!(block.get(b"z").unwrap(), None);
+            let (key, _) = block
+                .seek(b"key011")
+                .unwrap()
+                .next_entry()
+                .unwrap()
+                .unwrap();
+            assert_eq!(key, b"key012");
+            assert!(block.seek(b"z").unwrap().next_entry().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_restart_count() {
+        let mut data = build(&["a".to_string()], 16).data.to_vec();
+        let len = data.len();
+        data[len - 4..].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(matches!(
+            Block::new(data.into()),
+            Err(SstError::Corruption(_))
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+//! Bloom filters over a table's keys.
+//!
+//! A point lookup for a key that is not in a table still costs an index
+//! search and a block read. With many overlapping L0 tables that adds up, so
+//! each table carries a bloom filter: a lookup that the filter rules out
+//! skips the table without touching the disk. At 10 bits per key about 1%
+//! of lookups for absent keys get through.
+//!
+//! The filter uses `k` probes derived from one 64-bit hash by double
+//! hashing (Kirsch and Mitzenmacher). Block layout: the bit array, then `k`
+//! as a u8.
+
+/// A bloom filter, built by the writer and loaded by the reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    probes: u8,
+}
+
+impl BloomFilter {
+    /// Builds a filter over `hashes` (from [`key_hash`]) with about
+    /// `bits_per_key` bits for each.
+    pub fn build(hashes: &[u64], bits_per_key: usize) -> Self {
+        // ln(2) * bits per key probes minimize the false positive rate
+        let probes = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let nbits = (hashes.len() * bits_per_key).max(64);
+        let mut filter = Self {
+            bits: vec![0; nbits.div_ceil(8)],
+            probes,
+        };
+        for &hash in hashes {
+            for bit in filter.bit_positions(hash) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// Returns false if `key` is certainly not in the set.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.may_contain_hash(key_hash(key))
+    }
+
+    fn may_contain_hash(&self, hash: u64) -> bool {
+        self.bit_positions(hash)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let nbits = (self.bits.len() * 8) as u64;
+        let (h1, h2) = (hash, hash.rotate_right(32) | 1);
+        (0..u64::from(self.probes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut block = self.bits.clone();
+        block.push(self.probes);
+        block
+    }
+
+    pub(crate) fn decode(mut block: Vec<u8>) -> Option<Self> {
+        let probes = block.pop()?;
+        (!block.is_empty() && (1..=30).contains(&probes)).then_some(Self {
+            bits: block,
+            probes,
+        })
+    }
+
+    /// Size of the bit array in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+}
+
+/// Hashes a key for the filter (64-bit FNV-1a with a final avalanche).
+pub fn key_hash(key: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &byte in key {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives_and_few_false_positives() {
+        let keys: Vec<Vec<u8>> = (0..10_000u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        let hashes: Vec<u64> = keys.iter().map(|key| key_hash(key)).collect();
+        let filter = BloomFilter::build(&hashes, 10);
+        assert!(keys.iter().all(|key| filter.may_contain(key)));
+
+        let false_positives = (10_000..20_000u32)
+            .filter(|i| filter.may_contain(&i.to_be_bytes()))
+            .count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+
+        let decoded = BloomFilter::decode(filter.encode()).unwrap();
+        assert_eq!(decoded, filter);
+        assert_eq!(BloomFilter::decode(vec![0xff, 0]), None);
+    }
+}
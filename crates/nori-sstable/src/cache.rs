@@ -0,0 +1,378 @@
+//! Shared cache of decoded blocks.
+//!
+//! Without a cache every point lookup reads a block from disk and checks its
+//! CRC, and every table consulted adds another read. A [`BlockCache`] keeps
+//! recently used blocks in memory, shared by all the readers opened with it
+//! ([`SstReader::open_with_cache`](crate::SstReader::open_with_cache)).
+//!
+//! The cache is split into shards, each an LRU list behind its own lock, so
+//! concurrent readers rarely contend. Capacity is in bytes of block data
+//! plus a fixed overhead per block, divided evenly between the shards.
+//!
+//! The hit ratio over the last `report_interval` is emitted as a
+//! [`CacheEvt`] through the meter. Reports piggyback on lookups, so an idle
+//! cache stays quiet.
+
+use crate::block::Block;
+use nori_observe::{obs_emit, CacheEvt, Meter, NoopMeter, VizEvent};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bytes charged per cached block on top of its data.
+pub const BLOCK_OVERHEAD: usize = 128;
+
+/// Settings for a [`BlockCache`].
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Bytes the cache may hold across all shards.
+    pub capacity_bytes: usize,
+    /// Number of independently locked shards.
+    pub shards: usize,
+    /// Name reported in `CacheEvt`s, to tell several caches apart.
+    pub name: Cow<'static, str>,
+    /// How often to emit the hit ratio, or `None` to never emit it.
+    pub report_interval: Option<Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity_bytes: 64 * 1024 * 1024,
+            shards: 16,
+            name: Cow::Borrowed("block"),
+            report_interval: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// Counters of a [`BlockCache`] since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    /// Bytes currently charged, including per-block overhead.
+    pub usage_bytes: usize,
+    /// Blocks currently cached.
+    pub entries: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that hit, or 0 before the first lookup.
+    pub fn hit_ratio(&self) -> f32 {
+        ratio(self.hits, self.misses)
+    }
+}
+
+type Key = (u64, u64);
+
+#[derive(Default)]
+struct Shard {
+    blocks: HashMap<Key, (Arc<Block>, u64)>,
+    /// Blocks by last use, oldest first.
+    order: BTreeMap<u64, Key>,
+    tick: u64,
+    usage: usize,
+}
+
+impl Shard {
+    fn touch(&mut self, key: Key) -> Option<Arc<Block>> {
+        self.tick += 1;
+        let (block, used) = self.blocks.get_mut(&key)?;
+        self.order.remove(used);
+        *used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(block.clone())
+    }
+}
+
+/// Where the hit ratio was last reported.
+struct Window {
+    at: Instant,
+    hits: u64,
+    misses: u64,
+}
+
+/// A sharded LRU cache of blocks, shared between table readers.
+pub struct BlockCache {
+    config: CacheConfig,
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    next_table_id: AtomicU64,
+    window: Mutex<Window>,
+    meter: Arc<dyn Meter>,
+}
+
+impl BlockCache {
+    /// Creates a cache that reports nowhere.
+    pub fn new(config: CacheConfig) -> Self {
+        Self::with_meter(config, Arc::new(NoopMeter))
+    }
+
+    /// Creates a cache that reports its hit ratio through `meter`.
+    pub fn with_meter(config: CacheConfig, meter: Arc<dyn Meter>) -> Self {
+        let shards = config.shards.max(1);
+        Self {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            shard_capacity: config.capacity_bytes / shards,
+            config,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            next_table_id: AtomicU64::new(0),
+            window: Mutex::new(Window {
+                at: Instant::now(),
+                hits: 0,
+                misses: 0,
+            }),
+            meter,
+        }
+    }
+
+    /// Returns the cache's configuration.
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Returns a snapshot of the counters.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            stats.usage_bytes += shard.usage;
+            stats.entries += shard.blocks.len();
+        }
+        stats
+    }
+
+    /// Emits the hit ratio since the previous report and starts a new window.
+    /// Does nothing if no lookups happened in between.
+    pub fn report(&self) {
+        let mut window = self.window.lock().unwrap();
+        self.report_window(&mut window);
+    }
+
+    /// Returns an ID no other table reading through this cache has.
+    pub(crate) fn new_table_id(&self) -> u64 {
+        self.next_table_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn get(&self, table: u64, offset: u64) -> Option<Arc<Block>> {
+        let key = (table, offset);
+        let block = self.shard(key).lock().unwrap().touch(key);
+        let counter = if block.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.maybe_report();
+        block
+    }
+
+    pub(crate) fn insert(&self, table: u64, offset: u64, block: Arc<Block>) {
+        let key = (table, offset);
+        let charge = block.size() + BLOCK_OVERHEAD;
+        if charge > self.shard_capacity {
+            return;
+        }
+
+        let mut shard = self.shard(key).lock().unwrap();
+        if shard.touch(key).is_some() {
+            return;
+        }
+        while shard.usage + charge > self.shard_capacity {
+            let Some((_, oldest)) = shard.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = shard.blocks.remove(&oldest) {
+                shard.usage -= evicted.size() + BLOCK_OVERHEAD;
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let tick = shard.tick;
+        shard.blocks.insert(key, (block, tick));
+        shard.order.insert(tick, key);
+        shard.usage += charge;
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn shard(&self, (table, offset): Key) -> &Mutex<Shard> {
+        let hash = (table.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ offset)
+            .wrapping_mul(0xff51_afd7_ed55_8ccd);
+        &self.shards[(hash >> 32) as usize % self.shards.len()]
+    }
+
+    fn maybe_report(&self) {
+        let Some(interval) = self.config.report_interval else {
+            return;
+        };
+        // Whoever holds the window is already reporting
+        let Ok(mut window) = self.window.try_lock() else {
+            return;
+        };
+        if window.at.elapsed() >= interval {
+            self.report_window(&mut window);
+        }
+    }
+
+    fn report_window(&self, window: &mut Window) {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let (window_hits, window_misses) = (hits - window.hits, misses - window.misses);
+        *window = Window {
+            at: Instant::now(),
+            hits,
+            misses,
+        };
+        if window_hits + window_misses > 0 {
+            obs_emit!(
+                self.meter,
+                VizEvent::Cache(CacheEvt {
+                    name: self.config.name.clone(),
+                    hit_ratio: ratio(window_hits, window_misses),
+                })
+            );
+        }
+    }
+}
+
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("config", &self.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+fn ratio(hits: u64, misses: u64) -> f32 {
+    match hits + misses {
+        0 => 0.0,
+        total => hits as f32 / total as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::reader::SstReader;
+    use crate::writer::{write_table, SstConfig};
+    use bytes::Bytes;
+    use nori_memtable::MemEntry;
+    use nori_observe::TestMeter;
+    use tempfile::TempDir;
+
+    fn block(len: usize) -> Arc<Block> {
+        let mut builder = BlockBuilder::new(16);
+        builder.add(b"k", &vec![0; len]);
+        Arc::new(Block::new(builder.finish().into()).unwrap())
+    }
+
+    fn single_shard(capacity_bytes: usize) -> CacheConfig {
+        CacheConfig {
+            capacity_bytes,
+            shards: 1,
+            report_interval: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let charge = block(100).size() + BLOCK_OVERHEAD;
+        let cache = BlockCache::new(single_shard(3 * charge));
+        for offset in 0..3 {
+            cache.insert(1, offset, block(100));
+        }
+        assert!(cache.get(1, 0).is_some());
+        cache.insert(1, 3, block(100));
+
+        assert!(cache.get(1, 1).is_none());
+        for offset in [0, 2, 3] {
+            assert!(cache.get(1, offset).is_some());
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 3);
+        assert_eq!(stats.usage_bytes, 3 * charge);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!((stats.hits, stats.misses), (4, 1));
+
+        // Blocks larger than a shard are not cached at all
+        cache.insert(2, 0, block(4 * charge));
+        assert!(cache.get(2, 0).is_none());
+        assert_eq!(cache.stats().entries, 3);
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_reports_hit_ratio() {
+        let meter = Arc::new(TestMeter::new());
+        let config = CacheConfig {
+            name: "test".into(),
+            report_interval: Some(Duration::ZERO),
+            ..single_shard(1 << 20)
+        };
+        let cache = BlockCache::with_meter(config, meter.clone());
+        cache.insert(1, 0, block(10));
+        cache.get(1, 1);
+        cache.report();
+        assert!(cache.get(1, 0).is_some());
+
+        let ratios: Vec<_> = meter
+            .events()
+            .into_iter()
+            .map(|event| match event {
+                VizEvent::Cache(CacheEvt { name, hit_ratio }) if name == "test" => hit_ratio,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(ratios, vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_readers_share_cache() {
+        let dir = TempDir::new().unwrap();
+        let entries: Vec<_> = (0..500u32)
+            .map(|i| {
+                let entry = MemEntry {
+                    value: Some(Bytes::from(vec![1; 32])),
+                    lsn: None,
+                    expires_at: None,
+                };
+                (Bytes::from(i.to_be_bytes().to_vec()), entry)
+            })
+            .collect();
+        let path = dir.path().join("1.sst");
+        write_table(&path, SstConfig::default(), entries).unwrap();
+
+        let cache = Arc::new(BlockCache::new(CacheConfig::default()));
+        let first = SstReader::open_with_cache(&path, cache.clone()).unwrap();
+        let second = SstReader::open_with_cache(&path, cache.clone()).unwrap();
+        for _ in 0..3 {
+            assert!(first.get(&7u32.to_be_bytes()).unwrap().is_some());
+        }
+        assert!(second.get(&7u32.to_be_bytes()).unwrap().is_some());
+
+        // Each reader has its own ID, so the second one misses once
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+        assert_eq!(stats.entries, 2);
+    }
+}
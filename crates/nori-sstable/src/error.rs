@@ -0,0 +1,18 @@
+//! SSTable errors.
+
+use thiserror::Error;
+
+/// Errors from writing, reading and flushing tables.
+#[derive(Debug, Error)]
+pub enum SstError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Corrupt table: {0}")]
+    Corruption(String),
+    #[error("Keys out of order: {0}")]
+    KeyOrder(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("WAL error: {0}")]
+    Wal(#[from] nori_wal::SegmentError),
+}
@@ -0,0 +1,78 @@
+//! Flushing a memtable to a table.
+
+use crate::error::SstError;
+use crate::writer::{write_table, SstConfig, SstInfo};
+use nori_memtable::Memtable;
+use nori_wal::{Position, Wal};
+use std::path::PathBuf;
+
+/// Writes the contents of `memtable` to a table at `path`, then records a
+/// WAL checkpoint at `checkpoint`.
+///
+/// `checkpoint` is the WAL position up to which the memtable holds every
+/// record, usually `wal.current_position()` taken when the memtable
+/// stopped taking writes. Once the checkpoint is recorded the WAL no longer
+/// needs the records before it, and purges their segments if configured to
+/// (`purge_on_checkpoint`). The checkpoint is only recorded after the table
+/// is durable, so a crash in between replays the records again instead of
+/// losing them.
+///
+/// The table is written on a blocking thread.
+pub async fn flush_memtable(
+    memtable: &Memtable,
+    path: impl Into<PathBuf>,
+    config: SstConfig,
+    wal: &Wal,
+    checkpoint: Position,
+) -> Result<SstInfo, SstError> {
+    let path = path.into();
+    let entries = memtable.entries();
+    let info = tokio::task::spawn_blocking(move || write_table(path, config, entries))
+        .await
+        .map_err(std::io::Error::other)??;
+    wal.checkpoint(checkpoint).await?;
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::SstReader;
+    use nori_memtable::{Lookup, MemtableConfig};
+    use nori_wal::{Record, WalConfig};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_flush_records_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let wal_config = WalConfig {
+            dir: dir.path().join("wal"),
+            ..Default::default()
+        };
+        let (wal, memtable, _) = Memtable::open_wal(wal_config, MemtableConfig::default())
+            .await
+            .unwrap();
+        for i in 0..50u32 {
+            let record = Record::put(i.to_be_bytes().to_vec(), b"v".as_slice());
+            wal.append(&record).await.unwrap();
+            memtable.apply(&record);
+        }
+        wal.sync().await.unwrap();
+        let through = wal.current_position().await;
+
+        let path = dir.path().join("000001.sst");
+        let info = flush_memtable(&memtable, &path, SstConfig::default(), &wal, through)
+            .await
+            .unwrap();
+        assert_eq!(info.entries, 50);
+        assert_eq!(wal.last_checkpoint().await.unwrap().position, through);
+
+        let table = SstReader::open(&path).unwrap();
+        for (key, _) in memtable.entries() {
+            let Lookup::Found(value) = memtable.get(&key) else {
+                panic!("missing {:?}", key);
+            };
+            assert_eq!(table.get(&key).unwrap().unwrap().value, Some(value));
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! On-disk layout shared by the writer and the reader.
+//!
+//! A table is a sequence of blocks followed by a fixed-size footer:
+//!
+//! ```text
+//! [data block 0][data block 1]...[filter block][index block][footer]
+//! ```
+//!
+//! Every block is followed by a crc32c (u32, little-endian) of its bytes. The
+//! index block maps the last key of each data block to the block's handle
+//! (offset and length, as varints). The filter block is a bloom filter over
+//! every key (see [`crate::bloom`]). The footer (64 bytes, little-endian):
+//! - filter_offset: u64
+//! - filter_len: u64 (0 if the table has no filter)
+//! - index_offset: u64
+//! - index_len: u64
+//! - entries: u64
+//! - max_lsn: u64 (0 if no entry carries an LSN)
+//! - version: u32 (2)
+//! - crc32c: u32 (of the preceding fields)
+//! - magic: `NORISST\0`
+//!
+//! Version 1 tables have no filter and a 48-byte footer without the filter
+//! fields. The version sits at the same distance from the end of the file in
+//! both, so the reader can tell them apart.
+
+use crate::error::SstError;
+use bytes::{Buf, BufMut, Bytes};
+use nori_memtable::MemEntry;
+use std::fs::File;
+use std::time::{Duration, UNIX_EPOCH};
+
+pub(crate) const MAGIC: &[u8; 8] = b"NORISST\0";
+pub(crate) const VERSION: u32 = 2;
+/// Footer length of the current version.
+pub(crate) const FOOTER_LEN: usize = 8 * 6 + 4 + 4 + 8;
+/// Footer length of version 1 tables.
+pub(crate) const FOOTER_V1_LEN: usize = 8 * 4 + 4 + 4 + 8;
+/// Length of the crc32c after each block.
+pub(crate) const BLOCK_TRAILER_LEN: usize = 4;
+
+/// Location of a block in the file, not counting its trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl BlockHandle {
+    pub(crate) fn encode(&self, buf: &mut Vec<u8>) {
+        put_varint(buf, self.offset);
+        put_varint(buf, self.len);
+    }
+
+    pub(crate) fn decode(mut data: &[u8]) -> Result<Self, SstError> {
+        let offset = get_varint(&mut data)?;
+        let len = get_varint(&mut data)?;
+        Ok(Self { offset, len })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Footer {
+    /// Bloom filter, if the table has one.
+    pub filter: Option<BlockHandle>,
+    pub index: BlockHandle,
+    pub entries: u64,
+    pub max_lsn: Option<u64>,
+}
+
+impl Footer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FOOTER_LEN);
+        let filter = self.filter.unwrap_or(BlockHandle { offset: 0, len: 0 });
+        buf.put_u64_le(filter.offset);
+        buf.put_u64_le(filter.len);
+        buf.put_u64_le(self.index.offset);
+        buf.put_u64_le(self.index.len);
+        buf.put_u64_le(self.entries);
+        buf.put_u64_le(self.max_lsn.unwrap_or(0));
+        buf.put_u32_le(VERSION);
+        let crc = crc32c::crc32c(&buf);
+        buf.put_u32_le(crc);
+        buf.put_slice(MAGIC);
+        buf
+    }
+
+    /// Decodes the footer from the last bytes of a table (up to
+    /// [`FOOTER_LEN`] of them), returning it and its length.
+    pub fn decode(tail: &[u8]) -> Result<(Self, usize), SstError> {
+        let len = tail.len();
+        if len < FOOTER_V1_LEN || &tail[len - 8..] != MAGIC {
+            return Err(SstError::Corruption("bad footer magic".into()));
+        }
+        let version = (&tail[len - 16..]).get_u32_le();
+        let footer_len = match version {
+            1 => FOOTER_V1_LEN,
+            VERSION if len >= FOOTER_LEN => FOOTER_LEN,
+            _ => {
+                return Err(SstError::Corruption(format!(
+                    "unsupported version {}",
+                    version
+                )))
+            }
+        };
+        let (body, mut rest) = tail[len - footer_len..len - 8].split_at(footer_len - 12);
+        if rest.get_u32_le() != crc32c::crc32c(body) {
+            return Err(SstError::Corruption("footer checksum mismatch".into()));
+        }
+        let mut body = body;
+        let filter = if version == 1 {
+            None
+        } else {
+            let handle = BlockHandle {
+                offset: body.get_u64_le(),
+                len: body.get_u64_le(),
+            };
+            Some(handle).filter(|handle| handle.len > 0)
+        };
+        let index = BlockHandle {
+            offset: body.get_u64_le(),
+            len: body.get_u64_le(),
+        };
+        let entries = body.get_u64_le();
+        let max_lsn = Some(body.get_u64_le()).filter(|&lsn| lsn != 0);
+        let footer = Self {
+            filter,
+            index,
+            entries,
+            max_lsn,
+        };
+        Ok((footer, footer_len))
+    }
+}
+
+pub(crate) fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub(crate) fn get_varint(data: &mut &[u8]) -> Result<u64, SstError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, rest)) = data.split_first() else {
+            break;
+        };
+        *data = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(SstError::Corruption("truncated varint".into()))
+}
+
+/// Reads exactly `buf.len()` bytes at `offset`, without moving a shared
+/// cursor, so one file can serve concurrent readers.
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        let mut read = 0;
+        while read < buf.len() {
+            let n = std::os::windows::fs::FileExt::seek_read(
+                file,
+                &mut buf[read..],
+                offset + read as u64,
+            )?;
+            if n == 0 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            read += n;
+        }
+        Ok(())
+    }
+}
+
+const ENTRY_TOMBSTONE: u8 = 0b001;
+const ENTRY_LSN: u8 = 0b010;
+const ENTRY_EXPIRES: u8 = 0b100;
+
+/// Encodes a data block value: flags (u8), then the LSN and expiry time in
+/// milliseconds since the Unix epoch as varints if present, then the value.
+pub(crate) fn encode_entry(entry: &MemEntry, buf: &mut Vec<u8>) {
+    let mut flags = 0;
+    if entry.value.is_none() {
+        flags |= ENTRY_TOMBSTONE;
+    }
+    if entry.lsn.is_some() {
+        flags |= ENTRY_LSN;
+    }
+    if entry.expires_at.is_some() {
+        flags |= ENTRY_EXPIRES;
+    }
+    buf.push(flags);
+    if let Some(lsn) = entry.lsn {
+        put_varint(buf, lsn);
+    }
+    if let Some(expires_at) = entry.expires_at {
+        let millis = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        put_varint(buf, millis.as_millis() as u64);
+    }
+    if let Some(value) = &entry.value {
+        buf.extend_from_slice(value);
+    }
+}
+
+pub(crate) fn decode_entry(data: Bytes) -> Result<MemEntry, SstError> {
+    let mut rest = &data[..];
+    let Some(&flags) = rest.first() else {
+        return Err(SstError::Corruption("empty entry".into()));
+    };
+    rest = &rest[1..];
+    let lsn = if flags & ENTRY_LSN != 0 {
+        Some(get_varint(&mut rest)?)
+    } else {
+        None
+    };
+    let expires_at = if flags & ENTRY_EXPIRES != 0 {
+        Some(UNIX_EPOCH + Duration::from_millis(get_varint(&mut rest)?))
+    } else {
+        None
+    };
+    let value = (flags & ENTRY_TOMBSTONE == 0).then(|| data.slice(data.len() - rest.len()..));
+    Ok(MemEntry {
+        value,
+        lsn,
+        expires_at,
+    })
+}
@@ -1,6 +1,56 @@
 //! Immutable sorted tables with blocks, index, bloom, compression.
-//! Skeleton library - to be implemented.
+//!
+//! A table is written once, from entries in key order (typically a flushed
+//! [`Memtable`](nori_memtable::Memtable)), and then only read:
+//! - Entries are grouped into blocks of about 4 KiB, with keys
+//!   prefix-compressed against their predecessor and a restart array for
+//!   binary search within the block
+//! - Every block carries a crc32c, checked on each read
+//! - An index block maps the last key of each data block to its location,
+//!   so a point lookup reads one data block
+//! - A bloom filter over the keys lets lookups for absent keys skip the
+//!   table without reading it
+//! - The table is built in a temporary file and renamed into place once
+//!   fsynced, so a visible table is always complete
+//! - A sharded LRU [`BlockCache`] can be shared by readers to keep hot
+//!   blocks in memory, reporting its hit ratio through nori-observe
+//!
+//! [`flush_memtable`] writes a memtable and then records a WAL checkpoint,
+//! letting the WAL purge the prefix the table now covers.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_sstable::{flush_memtable, SstConfig, SstReader};
+//! # async fn example(
+//! #     wal: nori_wal::Wal,
+//! #     memtable: nori_memtable::Memtable,
+//! # ) -> Result<(), nori_sstable::SstError> {
+//! let flushed_through = wal.current_position().await;
+//! let info = flush_memtable(&memtable, "/data/000001.sst", SstConfig::default(), &wal, flushed_through).await?;
+//!
+//! let table = SstReader::open(&info.path)?;
+//! if let Some(entry) = table.get(b"key")? {
+//!     println!("{:?}", entry.value);
+//! }
+//! # Ok(())
+//! # }
+//! ```
 
-pub fn placeholder() -> &'static str {
-    "nori-sstable"
-}
+mod block;
+pub mod bloom;
+pub mod cache;
+pub mod error;
+pub mod flush;
+pub mod format;
+pub mod reader;
+pub mod writer;
+
+pub use block::Block;
+pub use bloom::BloomFilter;
+pub use cache::{BlockCache, CacheConfig, CacheStats};
+pub use error::SstError;
+pub use flush::flush_memtable;
+pub use format::BlockHandle;
+pub use reader::{SstIter, SstReader};
+pub use writer::{write_table, SstConfig, SstInfo, SstWriter};
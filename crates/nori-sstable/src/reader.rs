@@ -0,0 +1,400 @@
+//! Reading tables.
+
+use crate::block::Block;
+use crate::bloom::BloomFilter;
+use crate::cache::BlockCache;
+use crate::error::SstError;
+use crate::format::{
+    decode_entry, read_exact_at, BlockHandle, Footer, BLOCK_TRAILER_LEN, FOOTER_LEN, FOOTER_V1_LEN,
+};
+use bytes::Bytes;
+use nori_memtable::MemEntry;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// An open table.
+///
+/// The index is loaded on open; a lookup binary searches it for the one
+/// block that can hold the key and reads that block with a positioned read.
+/// Reads are blocking and the reader can be shared between threads. Opened
+/// with a [`BlockCache`], it looks for data blocks there first.
+#[derive(Debug)]
+pub struct SstReader {
+    path: PathBuf,
+    file: File,
+    file_size: u64,
+    footer: Footer,
+    footer_len: usize,
+    filter: Option<BloomFilter>,
+    /// Last key of each data block, and where the block is.
+    index: Vec<(Bytes, BlockHandle)>,
+    /// Cache for data blocks, and the table's ID in it.
+    cache: Option<(Arc<BlockCache>, u64)>,
+}
+
+impl SstReader {
+    /// Opens the table at `path`, checking its footer and index.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, SstError> {
+        Self::open_inner(path.into(), None)
+    }
+
+    /// Like [`SstReader::open`], keeping the data blocks it reads in `cache`.
+    pub fn open_with_cache(
+        path: impl Into<PathBuf>,
+        cache: Arc<BlockCache>,
+    ) -> Result<Self, SstError> {
+        Self::open_inner(path.into(), Some(cache))
+    }
+
+    fn open_inner(path: PathBuf, cache: Option<Arc<BlockCache>>) -> Result<Self, SstError> {
+        let file = File::open(&path)?;
+        let file_size = file.metadata()?.len();
+        if file_size < FOOTER_V1_LEN as u64 {
+            return Err(SstError::Corruption(format!(
+                "{} is too small to be a table",
+                path.display()
+            )));
+        }
+        let tail_len = file_size.min(FOOTER_LEN as u64);
+        let mut tail = vec![0; tail_len as usize];
+        read_exact_at(&file, &mut tail, file_size - tail_len)?;
+        let (footer, footer_len) = Footer::decode(&tail)?;
+
+        let mut reader = Self {
+            path,
+            file,
+            file_size,
+            footer,
+            footer_len,
+            filter: None,
+            index: Vec::new(),
+            cache: None,
+        };
+        if let Some(handle) = footer.filter {
+            let filter = BloomFilter::decode(reader.read_checked(handle)?)
+                .ok_or_else(|| SstError::Corruption("bad bloom filter".into()))?;
+            reader.filter = Some(filter);
+        }
+        let index_block = reader.load_block(footer.index)?;
+        let mut iter = index_block.iter();
+        while let Some((key, handle)) = iter.next_entry()? {
+            reader
+                .index
+                .push((key.into(), BlockHandle::decode(&handle)?));
+        }
+        reader.cache = cache.map(|cache| {
+            let id = cache.new_table_id();
+            (cache, id)
+        });
+        Ok(reader)
+    }
+
+    /// Path of the table file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Size of the table file in bytes.
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Number of entries, counting tombstones.
+    pub fn len(&self) -> u64 {
+        self.footer.entries
+    }
+
+    /// Returns true if the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.footer.entries == 0
+    }
+
+    /// Highest LSN among the entries, if any carry one.
+    pub fn max_lsn(&self) -> Option<u64> {
+        self.footer.max_lsn
+    }
+
+    /// Last key in the table, if it has any.
+    pub fn largest_key(&self) -> Option<&Bytes> {
+        self.index.last().map(|(key, _)| key)
+    }
+
+    /// Returns false if the table certainly does not hold `key`, according
+    /// to its bloom filter. Tables without a filter may hold any key.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(true, |filter| filter.may_contain(key))
+    }
+
+    /// Returns the entry stored under `key`, including tombstones and expired
+    /// values. Keys ruled out by the bloom filter cost no reads.
+    pub fn get(&self, key: &[u8]) -> Result<Option<MemEntry>, SstError> {
+        if !self.may_contain(key) {
+            return Ok(None);
+        }
+        let i = self.index.partition_point(|(last, _)| last.as_ref() < key);
+        let Some(&(_, handle)) = self.index.get(i) else {
+            return Ok(None);
+        };
+        match self.read_block(handle)?.get(key)? {
+            Some(value) => Ok(Some(decode_entry(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns an iterator over every entry, in key order.
+    pub fn iter(&self) -> SstIter<'_> {
+        SstIter {
+            reader: self,
+            next_block: 0,
+            entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns the block at `handle`, from the cache if possible.
+    pub(crate) fn read_block(&self, handle: BlockHandle) -> Result<Arc<Block>, SstError> {
+        let Some((cache, id)) = &self.cache else {
+            return self.load_block(handle);
+        };
+        if let Some(block) = cache.get(*id, handle.offset) {
+            return Ok(block);
+        }
+        let block = self.load_block(handle)?;
+        cache.insert(*id, handle.offset, block.clone());
+        Ok(block)
+    }
+
+    /// Reads and checks the block at `handle`.
+    fn load_block(&self, handle: BlockHandle) -> Result<Arc<Block>, SstError> {
+        Ok(Arc::new(Block::new(self.read_checked(handle)?.into())?))
+    }
+
+    /// Reads the bytes at `handle` and checks them against their trailer.
+    fn read_checked(&self, handle: BlockHandle) -> Result<Vec<u8>, SstError> {
+        let end = handle
+            .offset
+            .checked_add(handle.len + BLOCK_TRAILER_LEN as u64)
+            .filter(|&end| end <= self.file_size - self.footer_len as u64)
+            .ok_or_else(|| SstError::Corruption(format!("block {:?} out of bounds", handle)))?;
+        let mut buf = vec![0; (end - handle.offset) as usize];
+        read_exact_at(&self.file, &mut buf, handle.offset)?;
+
+        let (data, crc) = buf.split_at(handle.len as usize);
+        if crc32c::crc32c(data) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return Err(SstError::Corruption(format!(
+                "checksum mismatch in block at offset {}",
+                handle.offset
+            )));
+        }
+        buf.truncate(handle.len as usize);
+        Ok(buf)
+    }
+}
+
+/// Iterator over a table's entries, returned by [`SstReader::iter`].
+pub struct SstIter<'a> {
+    reader: &'a SstReader,
+    next_block: usize,
+    entries: std::vec::IntoIter<(Bytes, MemEntry)>,
+}
+
+impl SstIter<'_> {
+    fn load_block(&mut self, handle: BlockHandle) -> Result<(), SstError> {
+        let block = self.reader.read_block(handle)?;
+        let mut iter = block.iter();
+        let mut entries = Vec::new();
+        while let Some((key, value)) = iter.next_entry()? {
+            entries.push((Bytes::from(key), decode_entry(value)?));
+        }
+        self.entries = entries.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for SstIter<'_> {
+    type Item = Result<(Bytes, MemEntry), SstError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+            let &(_, handle) = self.reader.index.get(self.next_block)?;
+            self.next_block += 1;
+            if let Err(e) = self.load_block(handle) {
+                // Stop after reporting the error
+                self.next_block = self.reader.index.len();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::{write_table, SstConfig, SstWriter};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    fn entries(n: u64) -> Vec<(Bytes, MemEntry)> {
+        (0..n)
+            .map(|i| {
+                let key = Bytes::from(format!("key{:05}", i * 2));
+                let entry = MemEntry {
+                    value: (i % 7 != 0).then(|| Bytes::from(format!("value-{}", i))),
+                    lsn: (i % 3 != 0).then_some(i + 1),
+                    expires_at: (i % 5 == 0).then(|| UNIX_EPOCH + Duration::from_secs(i)),
+                };
+                (key, entry)
+            })
+            .collect()
+    }
+
+    fn small_blocks() -> SstConfig {
+        SstConfig {
+            block_size: 256,
+            restart_interval: 4,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let entries = entries(1000);
+        let info = write_table(dir.path().join("1.sst"), small_blocks(), entries.clone()).unwrap();
+        assert_eq!(info.entries, 1000);
+        assert_eq!(info.smallest_key, "key00000");
+        assert_eq!(info.largest_key, "key01998");
+        assert_eq!(info.max_lsn, Some(999));
+        assert!(!dir.path().join("1.sst.tmp").exists());
+
+        let table = SstReader::open(&info.path).unwrap();
+        assert_eq!(table.len(), 1000);
+        assert_eq!(table.file_size(), info.file_size);
+        assert_eq!(table.max_lsn(), Some(999));
+        assert!(table.index.len() > 10);
+
+        for (key, entry) in &entries {
+            assert_eq!(table.get(key).unwrap().as_ref(), Some(entry));
+        }
+        for missing in ["key00001", "a", "key01999", "z"] {
+            assert_eq!(table.get(missing.as_bytes()).unwrap(), None);
+        }
+        let scanned: Vec<_> = table.iter().map(Result::unwrap).collect();
+        assert_eq!(scanned, entries);
+    }
+
+    #[test]
+    fn test_bloom_filter_skips_absent_keys() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("1.sst");
+        write_table(&path, small_blocks(), entries(1000)).unwrap();
+
+        let cache = Arc::new(BlockCache::new(Default::default()));
+        let table = SstReader::open_with_cache(&path, cache.clone()).unwrap();
+        assert!(table.filter.is_some());
+        let absent = (0..1000)
+            .map(|i| format!("key{:05}", i * 2 + 1))
+            .filter(|key| table.get(key.as_bytes()).unwrap().is_none())
+            .count();
+        assert_eq!(absent, 1000);
+        // Only false positives reach the blocks
+        let stats = cache.stats();
+        assert!(stats.hits + stats.misses < 50, "{:?}", stats);
+
+        // Without a filter every lookup reads a block
+        let config = SstConfig {
+            bloom_bits_per_key: 0,
+            ..small_blocks()
+        };
+        write_table(&path, config, entries(1000)).unwrap();
+        let table = SstReader::open(&path).unwrap();
+        assert!(table.filter.is_none());
+        assert!(table.may_contain(b"key00001"));
+    }
+
+    #[test]
+    fn test_reads_version_1_tables() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("1.sst");
+        let config = SstConfig {
+            bloom_bits_per_key: 0,
+            ..small_blocks()
+        };
+        let entries = entries(100);
+        write_table(&path, config, entries.clone()).unwrap();
+
+        // Swap the footer for a version 1 footer, which lacks the filter handle
+        let mut data = std::fs::read(&path).unwrap();
+        let footer = data.split_off(data.len() - FOOTER_LEN);
+        let mut v1 = footer[16..16 + 32].to_vec();
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        let crc = crc32c::crc32c(&v1);
+        v1.extend_from_slice(&crc.to_le_bytes());
+        v1.extend_from_slice(crate::format::MAGIC);
+        data.extend_from_slice(&v1);
+        std::fs::write(&path, &data).unwrap();
+
+        let table = SstReader::open(&path).unwrap();
+        assert_eq!(table.footer_len, FOOTER_V1_LEN);
+        let scanned: Vec<_> = table.iter().map(Result::unwrap).collect();
+        assert_eq!(scanned, entries);
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let dir = TempDir::new().unwrap();
+        let info = write_table(dir.path().join("empty.sst"), SstConfig::default(), []).unwrap();
+        let table = SstReader::open(&info.path).unwrap();
+        assert!(table.is_empty());
+        assert_eq!(table.largest_key(), None);
+        assert_eq!(table.get(b"k").unwrap(), None);
+        assert_eq!(table.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_rejects_unsorted_keys() {
+        let dir = TempDir::new().unwrap();
+        let mut writer = SstWriter::create(dir.path().join("1.sst"), small_blocks()).unwrap();
+        let (_, entry) = entries(1).pop().unwrap();
+        writer.add(b"b", &entry).unwrap();
+        for key in [b"a", b"b"] {
+            assert!(matches!(
+                writer.add(key, &entry),
+                Err(SstError::KeyOrder(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let dir = TempDir::new().unwrap();
+        let info = write_table(dir.path().join("1.sst"), small_blocks(), entries(100)).unwrap();
+
+        // Flip a bit in the first data block: opening only reads the index
+        let mut data = std::fs::read(&info.path).unwrap();
+        data[10] ^= 0x01;
+        std::fs::write(&info.path, &data).unwrap();
+        let table = SstReader::open(&info.path).unwrap();
+        assert!(matches!(
+            table.get(b"key00000"),
+            Err(SstError::Corruption(_))
+        ));
+        let results: Vec<_> = table.iter().collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+
+        // A damaged footer fails the open
+        let last = data.len() - 20;
+        data[last] ^= 0x01;
+        std::fs::write(&info.path, &data).unwrap();
+        assert!(matches!(
+            SstReader::open(&info.path),
+            Err(SstError::Corruption(_))
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+//! Writing tables.
+
+use crate::block::BlockBuilder;
+use crate::bloom::{key_hash, BloomFilter};
+use crate::error::SstError;
+use crate::format::{encode_entry, BlockHandle, Footer};
+use bytes::Bytes;
+use nori_memtable::MemEntry;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Settings for writing tables.
+#[derive(Debug, Clone)]
+pub struct SstConfig {
+    /// Target size of a data block before its trailer. Blocks are cut once
+    /// they reach it, so one holding a large value can be bigger.
+    pub block_size: usize,
+    /// Entries between full keys in a block. Lower values make lookups
+    /// within a block faster and the block larger.
+    pub restart_interval: usize,
+    /// Bloom filter bits per key, or 0 for no filter. 10 bits give about 1%
+    /// false positives.
+    pub bloom_bits_per_key: usize,
+}
+
+impl Default for SstConfig {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            restart_interval: 16,
+            bloom_bits_per_key: 10,
+        }
+    }
+}
+
+impl SstConfig {
+    /// Checks the settings for values the writer cannot use.
+    pub fn validate(&self) -> Result<(), SstError> {
+        if self.block_size == 0 {
+            return Err(SstError::InvalidConfig(
+                "block_size must be positive".into(),
+            ));
+        }
+        if self.restart_interval == 0 {
+            return Err(SstError::InvalidConfig(
+                "restart_interval must be positive".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Summary of a finished table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SstInfo {
+    pub path: PathBuf,
+    /// Entries written, counting tombstones.
+    pub entries: u64,
+    /// Size of the file in bytes.
+    pub file_size: u64,
+    /// First key, or empty if the table has no entries.
+    pub smallest_key: Bytes,
+    /// Last key, or empty if the table has no entries.
+    pub largest_key: Bytes,
+    /// Highest LSN among the entries, if any carry one.
+    pub max_lsn: Option<u64>,
+}
+
+/// Writes a table from entries added in increasing key order.
+///
+/// The table is written to a temporary file and only renamed to its final
+/// path, fsynced, by [`SstWriter::finish`]; a table that is visible under
+/// its name is complete. Dropping the writer leaves the temporary file
+/// behind.
+pub struct SstWriter {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: BufWriter<File>,
+    config: SstConfig,
+    offset: u64,
+    data: BlockBuilder,
+    index: BlockBuilder,
+    entries: u64,
+    smallest_key: Option<Bytes>,
+    max_lsn: Option<u64>,
+    key_hashes: Vec<u64>,
+    scratch: Vec<u8>,
+}
+
+impl SstWriter {
+    /// Starts a table that will be stored at `path`.
+    pub fn create(path: impl Into<PathBuf>, config: SstConfig) -> Result<Self, SstError> {
+        config.validate()?;
+        let path = path.into();
+        let temp_path = path.with_extension("sst.tmp");
+        let file = BufWriter::new(File::create(&temp_path)?);
+        Ok(Self {
+            path,
+            temp_path,
+            file,
+            data: BlockBuilder::new(config.restart_interval),
+            index: BlockBuilder::new(1),
+            config,
+            offset: 0,
+            entries: 0,
+            smallest_key: None,
+            max_lsn: None,
+            key_hashes: Vec::new(),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Adds an entry. Keys must be strictly increasing.
+    pub fn add(&mut self, key: &[u8], entry: &MemEntry) -> Result<(), SstError> {
+        if self.entries > 0 && key <= self.data.last_key() {
+            return Err(SstError::KeyOrder(format!(
+                "{:?} after {:?}",
+                Bytes::copy_from_slice(key),
+                Bytes::copy_from_slice(self.data.last_key())
+            )));
+        }
+
+        self.scratch.clear();
+        encode_entry(entry, &mut self.scratch);
+        self.data.add(key, &self.scratch);
+        self.entries += 1;
+        if self.config.bloom_bits_per_key > 0 {
+            self.key_hashes.push(key_hash(key));
+        }
+        self.smallest_key
+            .get_or_insert_with(|| Bytes::copy_from_slice(key));
+        if let Some(lsn) = entry.lsn {
+            self.max_lsn = Some(self.max_lsn.map_or(lsn, |max| max.max(lsn)));
+        }
+
+        if self.data.estimated_len() >= self.config.block_size {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    /// Entries added so far.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Bytes written so far plus the block being built, for splitting output
+    /// into tables of a target size.
+    pub fn estimated_size(&self) -> u64 {
+        self.offset + self.data.estimated_len() as u64
+    }
+
+    /// Writes the index and footer, fsyncs the table and moves it to its
+    /// final path.
+    pub fn finish(mut self) -> Result<SstInfo, SstError> {
+        if !self.data.is_empty() {
+            self.flush_data_block()?;
+        }
+        let filter = if self.key_hashes.is_empty() {
+            None
+        } else {
+            let filter = BloomFilter::build(&self.key_hashes, self.config.bloom_bits_per_key);
+            Some(self.write_block(&filter.encode())?)
+        };
+        let largest_key = Bytes::copy_from_slice(self.index.last_key());
+        let index_block = self.index.finish();
+        let index = self.write_block(&index_block)?;
+        let footer = Footer {
+            filter,
+            index,
+            entries: self.entries,
+            max_lsn: self.max_lsn,
+        };
+        let footer = footer.encode();
+        self.file.write_all(&footer)?;
+        self.offset += footer.len() as u64;
+
+        let file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&self.temp_path, &self.path)?;
+        sync_parent(&self.path)?;
+
+        Ok(SstInfo {
+            path: self.path,
+            entries: self.entries,
+            file_size: self.offset,
+            smallest_key: self.smallest_key.unwrap_or_default(),
+            largest_key,
+            max_lsn: self.max_lsn,
+        })
+    }
+
+    fn flush_data_block(&mut self) -> Result<(), SstError> {
+        let block = self.data.finish();
+        let handle = self.write_block(&block)?;
+        let mut value = Vec::new();
+        handle.encode(&mut value);
+        let last_key = self.data.last_key().to_vec();
+        self.index.add(&last_key, &value);
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> Result<BlockHandle, SstError> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            len: block.len() as u64,
+        };
+        self.file.write_all(block)?;
+        self.file.write_all(&crc32c::crc32c(block).to_le_bytes())?;
+        self.offset += block.len() as u64 + 4;
+        Ok(handle)
+    }
+}
+
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Writes `entries`, which must be sorted by key, to a table at `path`.
+pub fn write_table<I>(
+    path: impl Into<PathBuf>,
+    config: SstConfig,
+    entries: I,
+) -> Result<SstInfo, SstError>
+where
+    I: IntoIterator<Item = (Bytes, MemEntry)>,
+{
+    let mut writer = SstWriter::create(path, config)?;
+    for (key, entry) in entries {
+        writer.add(&key, &entry)?;
+    }
+    writer.finish()
+}
@@ -0,0 +1,96 @@
+//! Failure detector configuration.
+
+use crate::error::SwimError;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Configuration for a [`Swim`](crate::Swim) node.
+///
+/// The defaults suit a LAN: a member that stops answering is suspected
+/// within about a second and declared dead five seconds later unless it
+/// refutes the suspicion.
+#[derive(Debug, Clone)]
+pub struct SwimConfig {
+    /// This node's member ID, unique in the cluster.
+    pub node_id: u32,
+    /// UDP and TCP address to listen on. Port 0 picks a free port.
+    pub bind_addr: SocketAddr,
+    /// Address other members should use to reach this node, when it differs
+    /// from the bound address (NAT, `0.0.0.0` binds).
+    pub advertise_addr: Option<SocketAddr>,
+    /// Members to join through on start. An empty list starts a new cluster.
+    pub seeds: Vec<SocketAddr>,
+    /// How often a member is probed.
+    pub protocol_period: Duration,
+    /// How long to wait for a direct ack before asking others to probe.
+    pub ack_timeout: Duration,
+    /// How many members are asked to probe indirectly.
+    pub indirect_probes: usize,
+    /// How long a member stays suspected before it is declared dead.
+    pub suspicion_timeout: Duration,
+    /// Most membership updates piggybacked on one message.
+    pub max_piggyback: usize,
+    /// Each update is sent `retransmit_mult * log2(members + 1)` times.
+    pub retransmit_mult: u32,
+    /// Timeout for connecting to and syncing with a seed over TCP.
+    pub join_timeout: Duration,
+}
+
+impl Default for SwimConfig {
+    fn default() -> Self {
+        Self {
+            node_id: 0,
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 7946)),
+            advertise_addr: None,
+            seeds: Vec::new(),
+            protocol_period: Duration::from_secs(1),
+            ack_timeout: Duration::from_millis(300),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_secs(5),
+            max_piggyback: 8,
+            retransmit_mult: 4,
+            join_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl SwimConfig {
+    /// Checks that the timeouts fit together.
+    pub fn validate(&self) -> Result<(), SwimError> {
+        if self.protocol_period.is_zero() || self.ack_timeout.is_zero() {
+            return Err(SwimError::InvalidConfig(
+                "protocol_period and ack_timeout must be non-zero".to_string(),
+            ));
+        }
+        if self.ack_timeout >= self.protocol_period {
+            return Err(SwimError::InvalidConfig(format!(
+                "ack_timeout {:?} must be shorter than protocol_period {:?}",
+                self.ack_timeout, self.protocol_period
+            )));
+        }
+        if self.retransmit_mult == 0 {
+            return Err(SwimError::InvalidConfig(
+                "retransmit_mult must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(SwimConfig::default().validate().is_ok());
+        let config = SwimConfig {
+            ack_timeout: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(SwimError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+//! Errors returned by the failure detector.
+
+use thiserror::Error;
+
+/// Errors from [`Swim`](crate::Swim) and its wire format.
+#[derive(Debug, Error)]
+pub enum SwimError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// A message could not be decoded.
+    #[error("Malformed message: {0}")]
+    Decode(String),
+
+    /// None of the seeds could be reached.
+    #[error("Could not join through any of {0} seeds")]
+    JoinFailed(usize),
+}
@@ -1,6 +1,56 @@
-//! SWIM-like membership/failure detector.
-//! Skeleton library - to be implemented.
+//! SWIM-style membership and failure detection.
+//!
+//! Each [`Swim`] node keeps a list of cluster [`Member`]s and checks them
+//! with the SWIM protocol (Das, Gupta and Motivala, 2002):
+//! - Every protocol period it pings one member, visiting all of them in a
+//!   random round-robin order
+//! - With no ack in time it asks a few others to ping the member for it,
+//!   so one lossy link does not condemn a healthy node
+//! - With still no ack the member is suspected, and declared dead if it
+//!   does not refute the suspicion (by bumping its incarnation) within the
+//!   suspicion timeout
+//! - Membership changes ride along on protocol messages, each sent a
+//!   logarithmic number of times, instead of being broadcast
+//!
+//! Probes and gossip use UDP; joining is a TCP exchange of full membership
+//! with a seed. Changes are reported as `SwimEvt`s through a nori-observe
+//! [`Meter`](nori_observe::Meter): `Alive` when a member joins or refutes a
+//! suspicion, `Suspect`, `Confirm` when it is declared dead, and `Leave`.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_observe::NoopMeter;
+//! use nori_swim::{MemberState, Swim, SwimConfig};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> Result<(), nori_swim::SwimError> {
+//! let config = SwimConfig {
+//!     node_id: 2,
+//!     bind_addr: "10.0.0.2:7946".parse().unwrap(),
+//!     seeds: vec!["10.0.0.1:7946".parse().unwrap()],
+//!     ..Default::default()
+//! };
+//! let node = Swim::start(config, Arc::new(NoopMeter)).await?;
+//! let alive = node
+//!     .members()
+//!     .into_iter()
+//!     .filter(|m| m.state == MemberState::Alive)
+//!     .count();
+//! println!("{} members alive", alive);
+//! node.leave().await?;
+//! # Ok(())
+//! # }
+//! ```
 
-pub fn placeholder() -> &'static str {
-    "nori-swim"
-}
+mod config;
+mod error;
+mod member;
+mod membership;
+mod message;
+mod node;
+
+pub use config::SwimConfig;
+pub use error::SwimError;
+pub use member::{Member, MemberState};
+pub use node::Swim;
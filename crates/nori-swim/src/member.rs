@@ -0,0 +1,110 @@
+//! Cluster members as seen by one node.
+
+use std::fmt;
+use std::net::SocketAddr;
+
+/// What a node believes about a member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemberState {
+    /// Answering probes.
+    Alive,
+    /// Missed a probe; declared dead unless it refutes in time.
+    Suspect,
+    /// Failed to refute a suspicion.
+    Dead,
+    /// Left the cluster on purpose.
+    Left,
+}
+
+impl MemberState {
+    /// True for members that are still probed and gossiped to.
+    pub fn is_active(self) -> bool {
+        matches!(self, MemberState::Alive | MemberState::Suspect)
+    }
+}
+
+impl fmt::Display for MemberState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemberState::Alive => "alive",
+            MemberState::Suspect => "suspect",
+            MemberState::Dead => "dead",
+            MemberState::Left => "left",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A member and the state last heard about it.
+///
+/// This is also the unit of gossip: each message piggybacks a few members
+/// whose state changed recently. The incarnation is bumped only by the
+/// member itself, to refute a suspicion, so a higher incarnation always
+/// carries newer news.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Member {
+    pub id: u32,
+    pub addr: SocketAddr,
+    pub incarnation: u64,
+    pub state: MemberState,
+}
+
+impl Member {
+    /// Returns true if this news about a member should replace `current`.
+    ///
+    /// The SWIM precedence rules: an alive member is only overridden by a
+    /// higher incarnation, or by a suspicion or death of the same one; a
+    /// suspicion only by a higher incarnation or a death; and a death or
+    /// departure only by the member coming back with a higher incarnation.
+    pub fn supersedes(&self, current: &Member) -> bool {
+        use MemberState::*;
+        match (self.state, current.state) {
+            (Alive, _) => self.incarnation > current.incarnation,
+            (Suspect, Alive) => self.incarnation >= current.incarnation,
+            (Suspect, Suspect) => self.incarnation > current.incarnation,
+            (Dead | Left, Alive | Suspect) => self.incarnation >= current.incarnation,
+            (Suspect | Dead | Left, Dead | Left) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(incarnation: u64, state: MemberState) -> Member {
+        Member {
+            id: 1,
+            addr: "127.0.0.1:1".parse().unwrap(),
+            incarnation,
+            state,
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        use MemberState::*;
+        let cases = [
+            (member(1, Alive), member(0, Alive), true),
+            (member(1, Alive), member(1, Alive), false),
+            (member(1, Alive), member(1, Suspect), false),
+            (member(2, Alive), member(1, Suspect), true),
+            (member(1, Suspect), member(1, Alive), true),
+            (member(0, Suspect), member(1, Alive), false),
+            (member(1, Suspect), member(1, Suspect), false),
+            (member(1, Dead), member(1, Suspect), true),
+            (member(1, Left), member(1, Alive), true),
+            (member(1, Suspect), member(1, Dead), false),
+            (member(2, Alive), member(1, Dead), true),
+        ];
+        for (news, current, expected) in cases {
+            assert_eq!(
+                news.supersedes(&current),
+                expected,
+                "{:?} over {:?}",
+                news,
+                current
+            );
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! The membership list and its gossip queue, without any I/O.
+//!
+//! [`Membership`] applies news about members under the SWIM precedence
+//! rules, refutes suspicions about the local node, times out suspicions,
+//! picks probe targets and chooses which updates to piggyback next. The
+//! network side in [`crate::node`] drives it; the current time is passed in
+//! so tests can step it.
+
+use crate::config::SwimConfig;
+use crate::member::{Member, MemberState};
+use nori_observe::{obs_emit, Meter, SwimEvt, SwimKind, VizEvent};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+struct Tracked {
+    member: Member,
+    /// When the member was first suspected at its current incarnation.
+    suspected_at: Option<Instant>,
+}
+
+/// An update waiting to be piggybacked, and how often it has been sent.
+struct Gossip {
+    member: Member,
+    sent: u32,
+}
+
+pub(crate) struct Membership {
+    local: Member,
+    members: HashMap<u32, Tracked>,
+    gossip: Vec<Gossip>,
+    /// Shuffled member IDs, probed in turn and reshuffled after each pass.
+    probe_order: Vec<u32>,
+    probe_next: usize,
+    rng: fastrand::Rng,
+    suspicion_timeout: Duration,
+    max_piggyback: usize,
+    retransmit_mult: u32,
+    meter: Arc<dyn Meter>,
+}
+
+impl Membership {
+    pub(crate) fn new(local: Member, config: &SwimConfig, meter: Arc<dyn Meter>) -> Self {
+        Self {
+            local,
+            members: HashMap::new(),
+            gossip: Vec::new(),
+            probe_order: Vec::new(),
+            probe_next: 0,
+            rng: fastrand::Rng::new(),
+            suspicion_timeout: config.suspicion_timeout,
+            max_piggyback: config.max_piggyback,
+            retransmit_mult: config.retransmit_mult,
+            meter,
+        }
+    }
+
+    pub(crate) fn local(&self) -> Member {
+        self.local
+    }
+
+    pub(crate) fn get(&self, id: u32) -> Option<Member> {
+        if id == self.local.id {
+            return Some(self.local);
+        }
+        self.members.get(&id).map(|tracked| tracked.member)
+    }
+
+    /// Every known member, the local node included, sorted by ID.
+    pub(crate) fn members(&self) -> Vec<Member> {
+        let mut members: Vec<Member> = self
+            .members
+            .values()
+            .map(|tracked| tracked.member)
+            .chain(std::iter::once(self.local))
+            .collect();
+        members.sort_by_key(|member| member.id);
+        members
+    }
+
+    /// Number of alive or suspected members besides the local node.
+    pub(crate) fn active_peers(&self) -> usize {
+        self.members
+            .values()
+            .filter(|tracked| tracked.member.state.is_active())
+            .count()
+    }
+
+    /// Applies news about a member, returning true if it changed anything.
+    /// Changes are queued for gossip.
+    pub(crate) fn apply(&mut self, news: Member, now: Instant) -> bool {
+        if news.id == self.local.id {
+            return self.apply_to_local(news);
+        }
+
+        let previous = match self.members.get(&news.id) {
+            Some(tracked) if !news.supersedes(&tracked.member) => return false,
+            Some(tracked) => Some(tracked.member.state),
+            None => None,
+        };
+        self.members.insert(
+            news.id,
+            Tracked {
+                member: news,
+                suspected_at: (news.state == MemberState::Suspect).then_some(now),
+            },
+        );
+        if previous != Some(news.state) && (previous.is_some() || news.state.is_active()) {
+            self.emit(news);
+        }
+        self.enqueue(news);
+        true
+    }
+
+    /// Refutes suspicion or death of the local node by moving to a higher
+    /// incarnation, which every other member will accept over the rumour.
+    fn apply_to_local(&mut self, news: Member) -> bool {
+        let refutes = news.state != MemberState::Alive
+            && news.incarnation >= self.local.incarnation
+            && self.local.state == MemberState::Alive;
+        if refutes {
+            self.local.incarnation = news.incarnation + 1;
+            self.enqueue(self.local);
+        }
+        refutes
+    }
+
+    /// Marks a member that missed its probe as suspected.
+    pub(crate) fn suspect(&mut self, id: u32, now: Instant) -> bool {
+        match self.members.get(&id) {
+            Some(tracked) if tracked.member.state == MemberState::Alive => {
+                let news = Member {
+                    state: MemberState::Suspect,
+                    ..tracked.member
+                };
+                self.apply(news, now)
+            }
+            _ => false,
+        }
+    }
+
+    /// Declares dead every member suspected for longer than the suspicion
+    /// timeout, returning how many were.
+    pub(crate) fn expire_suspicions(&mut self, now: Instant) -> usize {
+        let expired: Vec<Member> = self
+            .members
+            .values()
+            .filter(|tracked| {
+                tracked
+                    .suspected_at
+                    .is_some_and(|at| now.duration_since(at) >= self.suspicion_timeout)
+            })
+            .map(|tracked| Member {
+                state: MemberState::Dead,
+                ..tracked.member
+            })
+            .collect();
+        for news in &expired {
+            self.apply(*news, now);
+        }
+        expired.len()
+    }
+
+    /// Marks the local node as leaving and queues the news.
+    pub(crate) fn leave(&mut self) -> Member {
+        self.local.state = MemberState::Left;
+        self.enqueue(self.local);
+        self.local
+    }
+
+    /// Returns the next member to probe, visiting every active member once
+    /// per pass in random order.
+    pub(crate) fn next_probe_target(&mut self) -> Option<Member> {
+        for _ in 0..2 {
+            while self.probe_next < self.probe_order.len() {
+                let id = self.probe_order[self.probe_next];
+                self.probe_next += 1;
+                match self.members.get(&id) {
+                    Some(tracked) if tracked.member.state.is_active() => {
+                        return Some(tracked.member)
+                    }
+                    _ => {}
+                }
+            }
+            self.probe_order = self
+                .members
+                .values()
+                .filter(|tracked| tracked.member.state.is_active())
+                .map(|tracked| tracked.member.id)
+                .collect();
+            self.probe_order.sort_unstable();
+            self.rng.shuffle(&mut self.probe_order);
+            self.probe_next = 0;
+        }
+        None
+    }
+
+    /// Returns up to `count` random active members other than `exclude`.
+    pub(crate) fn random_peers(&mut self, count: usize, exclude: u32) -> Vec<Member> {
+        let mut peers: Vec<Member> = self
+            .members
+            .values()
+            .map(|tracked| tracked.member)
+            .filter(|member| member.state.is_active() && member.id != exclude)
+            .collect();
+        peers.sort_unstable_by_key(|member| member.id);
+        self.rng.shuffle(&mut peers);
+        peers.truncate(count);
+        peers
+    }
+
+    /// Takes the updates to piggyback on the next message: the least sent
+    /// first, each dropped once it has been sent
+    /// `retransmit_mult * ceil(log2(members + 1))` times.
+    pub(crate) fn piggyback(&mut self) -> Vec<Member> {
+        let cluster_size = self.active_peers() as u32 + 1;
+        let limit = self.retransmit_mult * (u32::BITS - cluster_size.leading_zeros());
+
+        self.gossip.sort_by_key(|gossip| gossip.sent);
+        let count = self.gossip.len().min(self.max_piggyback);
+        let updates = self.gossip[..count]
+            .iter_mut()
+            .map(|gossip| {
+                gossip.sent += 1;
+                gossip.member
+            })
+            .collect();
+        self.gossip.retain(|gossip| gossip.sent < limit);
+        updates
+    }
+
+    fn enqueue(&mut self, member: Member) {
+        self.gossip.retain(|gossip| gossip.member.id != member.id);
+        self.gossip.push(Gossip { member, sent: 0 });
+    }
+
+    fn emit(&self, member: Member) {
+        let kind = match member.state {
+            MemberState::Alive => SwimKind::Alive,
+            MemberState::Suspect => SwimKind::Suspect,
+            MemberState::Dead => SwimKind::Confirm,
+            MemberState::Left => SwimKind::Leave,
+        };
+        obs_emit!(
+            self.meter,
+            VizEvent::Swim(SwimEvt {
+                node: member.id,
+                kind,
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::TestMeter;
+
+    fn member(id: u32, incarnation: u64, state: MemberState) -> Member {
+        Member {
+            id,
+            addr: ([127, 0, 0, 1], 7000 + id as u16).into(),
+            incarnation,
+            state,
+        }
+    }
+
+    fn membership(meter: &TestMeter) -> Membership {
+        let config = SwimConfig {
+            node_id: 1,
+            max_piggyback: 2,
+            ..Default::default()
+        };
+        Membership::new(
+            member(1, 0, MemberState::Alive),
+            &config,
+            Arc::new(meter.clone()),
+        )
+    }
+
+    fn kinds(meter: &TestMeter) -> Vec<(u32, String)> {
+        meter
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                VizEvent::Swim(evt) => Some((evt.node, format!("{:?}", evt.kind))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_suspicion_expires_into_death() {
+        let meter = TestMeter::new();
+        let mut m = membership(&meter);
+        let start = Instant::now();
+        assert!(m.apply(member(2, 0, MemberState::Alive), start));
+        assert!(!m.apply(member(2, 0, MemberState::Alive), start));
+
+        assert!(m.suspect(2, start));
+        assert_eq!(m.expire_suspicions(start + Duration::from_secs(4)), 0);
+        assert_eq!(m.expire_suspicions(start + Duration::from_secs(5)), 1);
+        assert_eq!(m.get(2).unwrap().state, MemberState::Dead);
+        assert_eq!(m.active_peers(), 0);
+
+        // A stale alive rumour cannot resurrect it; a rejoin can
+        assert!(!m.apply(member(2, 0, MemberState::Alive), start));
+        assert!(m.apply(member(2, 1, MemberState::Alive), start));
+        assert_eq!(
+            kinds(&meter),
+            [
+                (2, "Alive".to_string()),
+                (2, "Suspect".to_string()),
+                (2, "Confirm".to_string()),
+                (2, "Alive".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refutes_suspicion_of_itself() {
+        let meter = TestMeter::new();
+        let mut m = membership(&meter);
+        let now = Instant::now();
+
+        assert!(m.apply(member(1, 0, MemberState::Suspect), now));
+        assert_eq!(m.local().incarnation, 1);
+        assert_eq!(m.piggyback(), [member(1, 1, MemberState::Alive)]);
+        // Old news about an incarnation already refuted is ignored
+        assert!(!m.apply(member(1, 0, MemberState::Dead), now));
+        assert!(kinds(&meter).is_empty());
+    }
+
+    #[test]
+    fn test_piggyback_prefers_fresh_updates_and_retires_old_ones() {
+        let meter = TestMeter::new();
+        let mut m = membership(&meter);
+        let now = Instant::now();
+        for id in 2..=4 {
+            m.apply(member(id, 0, MemberState::Alive), now);
+        }
+
+        // 4 members: each update goes out 4 * ceil(log2(5)) = 12 times
+        let mut sent: HashMap<u32, u32> = HashMap::new();
+        loop {
+            let updates = m.piggyback();
+            if updates.is_empty() {
+                break;
+            }
+            assert!(updates.len() <= 2);
+            for update in updates {
+                *sent.entry(update.id).or_default() += 1;
+            }
+        }
+        assert_eq!(sent, HashMap::from([(2, 12), (3, 12), (4, 12)]));
+
+        // Newer news about a member replaces what was queued for it
+        m.apply(member(3, 0, MemberState::Suspect), now);
+        assert_eq!(m.piggyback(), [member(3, 0, MemberState::Suspect)]);
+    }
+
+    #[test]
+    fn test_probe_order_visits_every_active_member() {
+        let meter = TestMeter::new();
+        let mut m = membership(&meter);
+        let now = Instant::now();
+        assert_eq!(m.next_probe_target(), None);
+        for id in 2..=5 {
+            m.apply(member(id, 0, MemberState::Alive), now);
+        }
+        m.apply(member(5, 0, MemberState::Left), now);
+
+        let mut round: Vec<u32> = (0..3).map(|_| m.next_probe_target().unwrap().id).collect();
+        round.sort();
+        assert_eq!(round, [2, 3, 4]);
+        assert!(m.random_peers(5, 3).iter().all(|p| p.id != 3 && p.id != 5));
+    }
+}
@@ -0,0 +1,268 @@
+//! Wire format of protocol messages.
+//!
+//! Every message is one UDP datagram (or one length-prefixed TCP frame for
+//! `Sync`), big-endian:
+//! - version: u8
+//! - tag: u8 (`p`ing, `a`ck, ping-`r`eq, `s`ync)
+//! - seq: u32 and from: u32 (all but `Sync`)
+//! - target address (ping-req only)
+//! - member count: u16, then each member as
+//!   id: u32, incarnation: u64, state: u8, address
+//!
+//! Addresses are a family byte (4 or 6), the IP and a u16 port.
+
+use crate::error::SwimError;
+use crate::member::{Member, MemberState};
+use bytes::{Buf, BufMut, BytesMut};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const VERSION: u8 = 1;
+const PING_TAG: u8 = b'p';
+const ACK_TAG: u8 = b'a';
+const PING_REQ_TAG: u8 = b'r';
+const SYNC_TAG: u8 = b's';
+
+/// A protocol message. `updates` carries piggybacked gossip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    /// Direct probe; answered with an `Ack` of the same `seq`.
+    Ping {
+        seq: u32,
+        from: u32,
+        updates: Vec<Member>,
+    },
+    Ack {
+        seq: u32,
+        from: u32,
+        updates: Vec<Member>,
+    },
+    /// Asks the receiver to probe `target` and forward its ack.
+    PingReq {
+        seq: u32,
+        from: u32,
+        target: SocketAddr,
+        updates: Vec<Member>,
+    },
+    /// Full membership, exchanged both ways when joining.
+    Sync { members: Vec<Member> },
+}
+
+impl Message {
+    pub(crate) fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(64);
+        buf.put_u8(VERSION);
+        let members = match self {
+            Message::Ping { seq, from, updates } => {
+                buf.put_u8(PING_TAG);
+                buf.put_u32(*seq);
+                buf.put_u32(*from);
+                updates
+            }
+            Message::Ack { seq, from, updates } => {
+                buf.put_u8(ACK_TAG);
+                buf.put_u32(*seq);
+                buf.put_u32(*from);
+                updates
+            }
+            Message::PingReq {
+                seq,
+                from,
+                target,
+                updates,
+            } => {
+                buf.put_u8(PING_REQ_TAG);
+                buf.put_u32(*seq);
+                buf.put_u32(*from);
+                put_addr(&mut buf, target);
+                updates
+            }
+            Message::Sync { members } => {
+                buf.put_u8(SYNC_TAG);
+                members
+            }
+        };
+        buf.put_u16(members.len() as u16);
+        for member in members {
+            buf.put_u32(member.id);
+            buf.put_u64(member.incarnation);
+            buf.put_u8(match member.state {
+                MemberState::Alive => 0,
+                MemberState::Suspect => 1,
+                MemberState::Dead => 2,
+                MemberState::Left => 3,
+            });
+            put_addr(&mut buf, &member.addr);
+        }
+        buf
+    }
+
+    pub(crate) fn decode(mut data: &[u8]) -> Result<Self, SwimError> {
+        let buf = &mut data;
+        let version = get_u8(buf)?;
+        if version != VERSION {
+            return Err(SwimError::Decode(format!("unknown version {}", version)));
+        }
+        let tag = get_u8(buf)?;
+        let header = match tag {
+            PING_TAG | ACK_TAG | PING_REQ_TAG => Some((get_u32(buf)?, get_u32(buf)?)),
+            SYNC_TAG => None,
+            _ => return Err(SwimError::Decode(format!("unknown tag {:#x}", tag))),
+        };
+        let target = if tag == PING_REQ_TAG {
+            Some(get_addr(buf)?)
+        } else {
+            None
+        };
+
+        let count = get_u16(buf)?;
+        let mut members = Vec::with_capacity(count.min(256) as usize);
+        for _ in 0..count {
+            let id = get_u32(buf)?;
+            let incarnation = get_u64(buf)?;
+            let state = match get_u8(buf)? {
+                0 => MemberState::Alive,
+                1 => MemberState::Suspect,
+                2 => MemberState::Dead,
+                3 => MemberState::Left,
+                other => return Err(SwimError::Decode(format!("unknown state {}", other))),
+            };
+            members.push(Member {
+                id,
+                addr: get_addr(buf)?,
+                incarnation,
+                state,
+            });
+        }
+        if buf.has_remaining() {
+            return Err(SwimError::Decode(format!(
+                "{} trailing bytes",
+                buf.remaining()
+            )));
+        }
+
+        let updates = members;
+        Ok(match (tag, header, target) {
+            (PING_TAG, Some((seq, from)), _) => Message::Ping { seq, from, updates },
+            (ACK_TAG, Some((seq, from)), _) => Message::Ack { seq, from, updates },
+            (PING_REQ_TAG, Some((seq, from)), Some(target)) => Message::PingReq {
+                seq,
+                from,
+                target,
+                updates,
+            },
+            _ => Message::Sync { members: updates },
+        })
+    }
+}
+
+fn put_addr(buf: &mut BytesMut, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.put_u8(4);
+            buf.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.put_u8(6);
+            buf.put_slice(&ip.octets());
+        }
+    }
+    buf.put_u16(addr.port());
+}
+
+fn get_addr(buf: &mut &[u8]) -> Result<SocketAddr, SwimError> {
+    let ip = match get_u8(buf)? {
+        4 => IpAddr::V4(Ipv4Addr::from(get_array::<4>(buf)?)),
+        6 => IpAddr::V6(Ipv6Addr::from(get_array::<16>(buf)?)),
+        other => {
+            return Err(SwimError::Decode(format!(
+                "unknown address family {}",
+                other
+            )))
+        }
+    };
+    Ok(SocketAddr::new(ip, get_u16(buf)?))
+}
+
+fn need(buf: &&[u8], len: usize) -> Result<(), SwimError> {
+    if buf.remaining() < len {
+        return Err(SwimError::Decode("message is truncated".to_string()));
+    }
+    Ok(())
+}
+
+fn get_array<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N], SwimError> {
+    need(buf, N)?;
+    let mut out = [0; N];
+    buf.copy_to_slice(&mut out);
+    Ok(out)
+}
+
+fn get_u8(buf: &mut &[u8]) -> Result<u8, SwimError> {
+    need(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn get_u16(buf: &mut &[u8]) -> Result<u16, SwimError> {
+    need(buf, 2)?;
+    Ok(buf.get_u16())
+}
+
+fn get_u32(buf: &mut &[u8]) -> Result<u32, SwimError> {
+    need(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+fn get_u64(buf: &mut &[u8]) -> Result<u64, SwimError> {
+    need(buf, 8)?;
+    Ok(buf.get_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let members = vec![
+            Member {
+                id: 1,
+                addr: "10.0.0.1:7946".parse().unwrap(),
+                incarnation: 3,
+                state: MemberState::Suspect,
+            },
+            Member {
+                id: 2,
+                addr: "[::1]:7000".parse().unwrap(),
+                incarnation: 0,
+                state: MemberState::Left,
+            },
+        ];
+        let messages = [
+            Message::Ping {
+                seq: 7,
+                from: 1,
+                updates: members.clone(),
+            },
+            Message::Ack {
+                seq: 7,
+                from: 2,
+                updates: Vec::new(),
+            },
+            Message::PingReq {
+                seq: 9,
+                from: 1,
+                target: "10.0.0.3:7946".parse().unwrap(),
+                updates: members.clone(),
+            },
+            Message::Sync { members },
+        ];
+        for message in messages {
+            let encoded = message.encode();
+            assert_eq!(Message::decode(&encoded).unwrap(), message);
+            // Every strict prefix is rejected rather than misread
+            for len in 0..encoded.len() {
+                assert!(Message::decode(&encoded[..len]).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! A SWIM node on the network.
+//!
+//! Probes and gossip travel over UDP: each protocol period the node pings
+//! one member, asks `indirect_probes` others to ping it on its behalf if no
+//! ack arrives within `ack_timeout`, and suspects it if the period ends
+//! without one. Joining is a TCP push-pull: the joiner and the seed send
+//! each other their full membership, so the joiner starts with a complete
+//! view instead of waiting for gossip to reach it.
+
+use crate::config::SwimConfig;
+use crate::error::SwimError;
+use crate::member::{Member, MemberState};
+use crate::membership::Membership;
+use crate::message::Message;
+use nori_observe::Meter;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant, MissedTickBehavior};
+
+/// Largest UDP datagram the node will receive.
+const MAX_DATAGRAM: usize = 64 * 1024;
+
+/// Largest `Sync` frame accepted over TCP.
+const MAX_SYNC_FRAME: u32 = 16 * 1024 * 1024;
+
+/// Someone waiting for an ack to a ping.
+enum Waiter {
+    /// This node's own probe.
+    Probe(oneshot::Sender<()>),
+    /// A probe made for another member's ping-req: the ack is forwarded to
+    /// `to` under the sequence number it asked with.
+    Relay {
+        to: SocketAddr,
+        seq: u32,
+        expires: Instant,
+    },
+}
+
+struct Shared {
+    config: SwimConfig,
+    socket: UdpSocket,
+    membership: Mutex<Membership>,
+    waiters: Mutex<HashMap<u32, Waiter>>,
+    next_seq: AtomicU32,
+    meter: Arc<dyn Meter>,
+}
+
+/// A member of a SWIM cluster.
+///
+/// Starting a node binds its UDP and TCP sockets, joins through the
+/// configured seeds and spawns the protocol tasks on the current tokio
+/// runtime. Membership changes are emitted as `SwimEvt`s through the
+/// meter. Dropping the node stops it without telling anyone, which the
+/// rest of the cluster sees as a failure; call [`Swim::leave`] to go
+/// gracefully.
+pub struct Swim {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Swim {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Swim {
+    /// Starts a node and joins the cluster through `config.seeds`.
+    ///
+    /// With no seeds the node starts a cluster of its own. With seeds, at
+    /// least one of them must answer.
+    pub async fn start(config: SwimConfig, meter: Arc<dyn Meter>) -> Result<Self, SwimError> {
+        config.validate()?;
+        let socket = UdpSocket::bind(config.bind_addr).await?;
+        let local_addr = socket.local_addr()?;
+        let listener = TcpListener::bind(local_addr).await?;
+
+        let advertise_addr = match config.advertise_addr {
+            Some(addr) => addr,
+            None if local_addr.ip().is_unspecified() => {
+                return Err(SwimError::InvalidConfig(format!(
+                    "bind_addr {} is unspecified; set advertise_addr",
+                    config.bind_addr
+                )))
+            }
+            None => local_addr,
+        };
+        let local = Member {
+            id: config.node_id,
+            addr: advertise_addr,
+            incarnation: 0,
+            state: MemberState::Alive,
+        };
+
+        let shared = Arc::new(Shared {
+            membership: Mutex::new(Membership::new(local, &config, meter.clone())),
+            config,
+            socket,
+            waiters: Mutex::new(HashMap::new()),
+            next_seq: AtomicU32::new(1),
+            meter,
+        });
+        let tasks = vec![
+            tokio::spawn(shared.clone().receive_loop()),
+            tokio::spawn(shared.clone().accept_loop(listener)),
+        ];
+        let mut node = Self {
+            shared,
+            local_addr,
+            tasks,
+        };
+
+        let seeds = node.shared.config.seeds.clone();
+        if !seeds.is_empty() {
+            node.join(&seeds).await?;
+        }
+        node.tasks
+            .push(tokio::spawn(node.shared.clone().probe_loop()));
+        Ok(node)
+    }
+
+    /// Syncs membership with each of `seeds` over TCP, returning how many
+    /// answered. Fails only if none did.
+    pub async fn join(&self, seeds: &[SocketAddr]) -> Result<usize, SwimError> {
+        let mut joined = 0;
+        for &seed in seeds {
+            let timeout = self.shared.config.join_timeout;
+            if let Ok(Ok(())) = time::timeout(timeout, self.shared.push_pull(seed)).await {
+                joined += 1;
+            }
+        }
+        if joined == 0 && !seeds.is_empty() {
+            return Err(SwimError::JoinFailed(seeds.len()));
+        }
+        Ok(joined)
+    }
+
+    /// The local node as the cluster sees it.
+    pub fn local(&self) -> Member {
+        self.shared.membership().local()
+    }
+
+    /// The address the UDP and TCP sockets are bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Every member this node knows of, itself included, sorted by ID.
+    /// Dead and departed members stay listed with that state.
+    pub fn members(&self) -> Vec<Member> {
+        self.shared.membership().members()
+    }
+
+    /// Returns what this node believes about member `id`.
+    pub fn member(&self, id: u32) -> Option<Member> {
+        self.shared.membership().get(id)
+    }
+
+    /// Leaves the cluster: tells every active member directly, then stops.
+    pub async fn leave(self) -> Result<(), SwimError> {
+        let (left, peers) = {
+            let mut membership = self.shared.membership();
+            let left = membership.leave();
+            (left, membership.random_peers(usize::MAX, left.id))
+        };
+        for peer in peers {
+            let message = Message::Ping {
+                seq: 0,
+                from: left.id,
+                updates: vec![left],
+            };
+            self.shared.send(peer.addr, &message).await;
+        }
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn membership(&self) -> MutexGuard<'_, Membership> {
+        self.membership.lock().expect("membership lock poisoned")
+    }
+
+    fn waiters(&self) -> MutexGuard<'_, HashMap<u32, Waiter>> {
+        self.waiters.lock().expect("waiter lock poisoned")
+    }
+
+    fn next_seq(&self) -> u32 {
+        self.next_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn local_id(&self) -> u32 {
+        self.config.node_id
+    }
+
+    fn apply(&self, updates: Vec<Member>) {
+        let now = Instant::now();
+        let mut membership = self.membership();
+        for update in updates {
+            membership.apply(update, now);
+        }
+    }
+
+    async fn send(&self, to: SocketAddr, message: &Message) {
+        if self.socket.send_to(&message.encode(), to).await.is_err() {
+            // Lost datagrams are part of the model; a missed ack follows
+            self.meter.counter("swim_send_errors_total", &[]).inc(1);
+        }
+    }
+
+    /// Runs one protocol period: probe the next member directly, then
+    /// through others, and suspect it if nobody got an ack.
+    async fn probe_round(&self) {
+        let now = Instant::now();
+        let target = {
+            let mut membership = self.membership();
+            membership.expire_suspicions(now);
+            membership.next_probe_target()
+        };
+        self.waiters().retain(|_, waiter| match waiter {
+            Waiter::Relay { expires, .. } => *expires > now,
+            Waiter::Probe(_) => true,
+        });
+        let Some(target) = target else {
+            return;
+        };
+
+        let seq = self.next_seq();
+        let (tx, mut rx) = oneshot::channel();
+        self.waiters().insert(seq, Waiter::Probe(tx));
+        self.meter.counter("swim_probes_total", &[]).inc(1);
+        let updates = self.membership().piggyback();
+        let ping = Message::Ping {
+            seq,
+            from: self.local_id(),
+            updates,
+        };
+        self.send(target.addr, &ping).await;
+
+        let mut acked = matches!(
+            time::timeout(self.config.ack_timeout, &mut rx).await,
+            Ok(Ok(()))
+        );
+        if !acked {
+            let helpers = self
+                .membership()
+                .random_peers(self.config.indirect_probes, target.id);
+            for helper in helpers {
+                self.meter.counter("swim_indirect_probes_total", &[]).inc(1);
+                let updates = self.membership().piggyback();
+                let ping_req = Message::PingReq {
+                    seq,
+                    from: self.local_id(),
+                    target: target.addr,
+                    updates,
+                };
+                self.send(helper.addr, &ping_req).await;
+            }
+            let remaining = self.config.protocol_period - self.config.ack_timeout;
+            acked = matches!(time::timeout(remaining, &mut rx).await, Ok(Ok(())));
+        }
+        self.waiters().remove(&seq);
+
+        if !acked {
+            self.meter.counter("swim_probe_failures_total", &[]).inc(1);
+            self.membership().suspect(target.id, Instant::now());
+        }
+    }
+
+    async fn probe_loop(self: Arc<Self>) {
+        let mut interval = time::interval(self.config.protocol_period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.probe_round().await;
+        }
+    }
+
+    async fn receive_loop(self: Arc<Self>) {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                // ICMP errors from earlier sends surface here on some platforms
+                Err(_) => continue,
+            };
+            match Message::decode(&buf[..len]) {
+                Ok(message) => self.handle(message, from).await,
+                Err(_) => self.meter.counter("swim_decode_errors_total", &[]).inc(1),
+            }
+        }
+    }
+
+    async fn handle(&self, message: Message, from: SocketAddr) {
+        match message {
+            Message::Ping { seq, updates, .. } => {
+                self.apply(updates);
+                let ack = Message::Ack {
+                    seq,
+                    from: self.local_id(),
+                    updates: self.membership().piggyback(),
+                };
+                self.send(from, &ack).await;
+            }
+            Message::Ack { seq, updates, .. } => {
+                self.apply(updates);
+                let waiter = self.waiters().remove(&seq);
+                match waiter {
+                    Some(Waiter::Probe(tx)) => {
+                        let _ = tx.send(());
+                    }
+                    Some(Waiter::Relay { to, seq, .. }) => {
+                        let ack = Message::Ack {
+                            seq,
+                            from: self.local_id(),
+                            updates: self.membership().piggyback(),
+                        };
+                        self.send(to, &ack).await;
+                    }
+                    None => {}
+                }
+            }
+            Message::PingReq {
+                seq,
+                target,
+                updates,
+                ..
+            } => {
+                self.apply(updates);
+                let relay_seq = self.next_seq();
+                let relay = Waiter::Relay {
+                    to: from,
+                    seq,
+                    expires: Instant::now() + self.config.protocol_period,
+                };
+                self.waiters().insert(relay_seq, relay);
+                let ping = Message::Ping {
+                    seq: relay_seq,
+                    from: self.local_id(),
+                    updates: self.membership().piggyback(),
+                };
+                self.send(target, &ping).await;
+            }
+            // Full syncs only travel over TCP
+            Message::Sync { .. } => {}
+        }
+    }
+
+    /// Sends our membership to `seed` and applies what it sends back.
+    async fn push_pull(&self, seed: SocketAddr) -> Result<(), SwimError> {
+        let mut stream = TcpStream::connect(seed).await?;
+        let members = self.membership().members();
+        write_frame(&mut stream, &Message::Sync { members }).await?;
+        match read_frame(&mut stream).await? {
+            Message::Sync { members } => {
+                self.apply(members);
+                Ok(())
+            }
+            other => Err(SwimError::Decode(format!(
+                "expected a sync, got {:?}",
+                other
+            ))),
+        }
+    }
+
+    async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let shared = self.clone();
+            tokio::spawn(async move {
+                let timeout = shared.config.join_timeout;
+                let _ = time::timeout(timeout, shared.serve_sync(stream)).await;
+            });
+        }
+    }
+
+    async fn serve_sync(&self, mut stream: TcpStream) -> Result<(), SwimError> {
+        let Message::Sync { members: theirs } = read_frame(&mut stream).await? else {
+            return Err(SwimError::Decode("expected a sync".to_string()));
+        };
+        self.apply(theirs);
+        let members = self.membership().members();
+        write_frame(&mut stream, &Message::Sync { members }).await
+    }
+}
+
+async fn write_frame(stream: &mut TcpStream, message: &Message) -> Result<(), SwimError> {
+    let body = message.encode();
+    stream.write_u32(body.len() as u32).await?;
+    stream.write_all(&body).await?;
+    Ok(stream.flush().await?)
+}
+
+async fn read_frame(stream: &mut TcpStream) -> Result<Message, SwimError> {
+    let len = stream.read_u32().await?;
+    if len > MAX_SYNC_FRAME {
+        return Err(SwimError::Decode(format!("sync frame of {} bytes", len)));
+    }
+    let mut body = vec![0; len as usize];
+    stream.read_exact(&mut body).await?;
+    Message::decode(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::{SwimEvt, SwimKind, TestMeter, VizEvent};
+    use std::time::Duration;
+
+    fn config(node_id: u32, seeds: Vec<SocketAddr>) -> SwimConfig {
+        SwimConfig {
+            node_id,
+            bind_addr: ([127, 0, 0, 1], 0).into(),
+            seeds,
+            protocol_period: Duration::from_millis(50),
+            ack_timeout: Duration::from_millis(20),
+            suspicion_timeout: Duration::from_millis(200),
+            ..Default::default()
+        }
+    }
+
+    async fn start(node_id: u32, seeds: Vec<SocketAddr>, meter: &TestMeter) -> Swim {
+        Swim::start(config(node_id, seeds), Arc::new(meter.clone()))
+            .await
+            .unwrap()
+    }
+
+    /// Polls until `node` sees member `id` in `state`.
+    async fn wait_for(node: &Swim, id: u32, state: MemberState) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while node.member(id).map(|m| m.state) != Some(state) {
+            assert!(
+                Instant::now() < deadline,
+                "node {} never saw {} as {}",
+                node.local().id,
+                id,
+                state
+            );
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn saw(meter: &TestMeter, id: u32, kind: fn(&SwimKind) -> bool) -> bool {
+        meter.events().iter().any(|event| {
+            matches!(event, VizEvent::Swim(SwimEvt { node, kind: k }) if *node == id && kind(k))
+        })
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_join_then_detect_failure() {
+        let meter = TestMeter::new();
+        let a = start(1, vec![], &meter).await;
+        let b = start(2, vec![a.local_addr()], &meter).await;
+        let c = start(3, vec![a.local_addr()], &meter).await;
+        // c learned b from a's full membership on join; b hears of c by gossip
+        assert_eq!(c.member(2).unwrap().state, MemberState::Alive);
+        wait_for(&b, 3, MemberState::Alive).await;
+
+        drop(c);
+        wait_for(&a, 3, MemberState::Dead).await;
+        wait_for(&b, 3, MemberState::Dead).await;
+        assert_eq!(a.member(2).unwrap().state, MemberState::Alive);
+        assert!(saw(&meter, 3, |k| matches!(k, SwimKind::Suspect)));
+        assert!(saw(&meter, 3, |k| matches!(k, SwimKind::Confirm)));
+        assert!(meter.counter_total("swim_probe_failures_total") > 0);
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_graceful_leave() {
+        let meter = TestMeter::new();
+        let a = start(1, vec![], &meter).await;
+        let b = start(2, vec![a.local_addr()], &meter).await;
+        wait_for(&a, 2, MemberState::Alive).await;
+
+        b.leave().await.unwrap();
+        wait_for(&a, 2, MemberState::Left).await;
+        assert!(saw(&meter, 2, |k| matches!(k, SwimKind::Leave)));
+        assert!(!saw(&meter, 2, |k| matches!(k, SwimKind::Confirm)));
+    }
+
+    #[tokio::test]
+    async fn test_join_fails_without_a_live_seed() {
+        let meter = TestMeter::new();
+        let gone = start(1, vec![], &meter).await;
+        let addr = gone.local_addr();
+        drop(gone);
+        let mut config = config(2, vec![addr]);
+        config.join_timeout = Duration::from_millis(200);
+        assert!(matches!(
+            Swim::start(config, Arc::new(meter)).await,
+            Err(SwimError::JoinFailed(1))
+        ));
+    }
+}
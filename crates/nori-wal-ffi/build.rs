@@ -0,0 +1,20 @@
+//! Generates the C header for the exported functions into `OUT_DIR`.
+//! The copy checked in at `include/nori_wal.h` is what C builds use; a test
+//! fails when it no longer matches the generated one, so the build itself
+//! never writes to the source tree.
+
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("reading cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("generating the C header")
+        .write_to_file(format!("{}/nori_wal.h", out_dir));
+}
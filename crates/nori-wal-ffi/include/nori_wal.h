@@ -0,0 +1,240 @@
+/* nori-wal C API. Generated by cbindgen from src/lib.rs; do not edit. */
+
+#ifndef NORI_WAL_H
+#define NORI_WAL_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Result of every fallible call. Zero or positive is success; on a negative
+// status, `nori_wal_last_error` describes what went wrong.
+enum NoriWalStatus
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : int32_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  NORI_WAL_STATUS_OK = 0,
+  // `nori_wal_iter_next` has reached the durable end of the log.
+  NORI_WAL_STATUS_END = 1,
+  // A null pointer, a directory that isn't UTF-8, or a bad option.
+  NORI_WAL_STATUS_INVALID_ARGUMENT = -1,
+  // An I/O error, such as a full disk.
+  NORI_WAL_STATUS_IO = -2,
+  // Data on disk failed validation.
+  NORI_WAL_STATUS_CORRUPTION = -3,
+  // Another process has the WAL directory open.
+  NORI_WAL_STATUS_LOCKED = -4,
+  // The WAL was closed underneath the call.
+  NORI_WAL_STATUS_CLOSED = -5,
+  // The record is larger than the configured maximum.
+  NORI_WAL_STATUS_TOO_LARGE = -6,
+  // The position is no longer in the log (purged or truncated).
+  NORI_WAL_STATUS_NOT_FOUND = -7,
+  // Any other error.
+  NORI_WAL_STATUS_ERROR = -8,
+  // A bug: the call panicked, and its effects are unknown.
+  NORI_WAL_STATUS_PANIC = -9,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum NoriWalStatus NoriWalStatus;
+#else
+typedef int32_t NoriWalStatus;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+// When appended records are fsynced.
+typedef enum NoriWalFsync {
+  // After every append.
+  NORI_WAL_FSYNC_ALWAYS,
+  // At most once per `fsync_window_ms`.
+  NORI_WAL_FSYNC_BATCH,
+  // When the OS decides, or on `nori_wal_sync`.
+  NORI_WAL_FSYNC_OS,
+} NoriWalFsync;
+
+// An open WAL.
+typedef struct NoriWal NoriWal;
+
+// A reader over the records of a WAL, from a starting point to its durable
+// end.
+typedef struct NoriWalIter NoriWalIter;
+
+// Settings for `nori_wal_open`; start from `nori_wal_options_default`.
+typedef struct NoriWalOptions {
+  // Segment size at which the WAL rotates to a new segment.
+  uint64_t max_segment_size;
+  enum NoriWalFsync fsync;
+  // Window for `NORI_WAL_FSYNC_BATCH`.
+  uint64_t fsync_window_ms;
+  // Allocate each segment's full size up front.
+  bool preallocate;
+  // Largest record accepted, or 0 for no limit.
+  uint64_t max_record_size;
+} NoriWalOptions;
+
+// A place in the log: a segment and a byte offset in it.
+typedef struct NoriWalPosition {
+  uint64_t segment_id;
+  uint64_t offset;
+} NoriWalPosition;
+
+// A record read by `nori_wal_iter_next`.
+//
+// `key` and `value` point into memory owned by the iterator, valid until
+// the next call on it or until it is freed. Optional fields are 0 when
+// unset.
+typedef struct NoriWalRecord {
+  const uint8_t *key;
+  size_t key_len;
+  // Empty for a delete.
+  const uint8_t *value;
+  size_t value_len;
+  // True for a delete.
+  bool tombstone;
+  uint64_t lsn;
+  // Milliseconds since the Unix epoch.
+  uint64_t timestamp_ms;
+  uint64_t ttl_ms;
+  // Meaningful only if `has_namespace` is set.
+  uint32_t namespace_id;
+  bool has_namespace;
+  // Where the record is in the log.
+  struct NoriWalPosition position;
+} NoriWalRecord;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Fills `options` with the defaults.
+//
+// # Safety
+//
+// `options` must be null or point to writable memory for a
+// `NoriWalOptions`.
+NoriWalStatus nori_wal_options_default(struct NoriWalOptions *options);
+
+// Opens the WAL in the directory `dir`, creating it if missing and
+// recovering it if it was not closed cleanly. `options` may be null for the
+// defaults. On success `*wal_out` is the WAL, to close with
+// `nori_wal_close`.
+//
+// # Safety
+//
+// `dir` must be a NUL-terminated string, `options` null or a valid
+// `NoriWalOptions`, and `wal_out` writable.
+NoriWalStatus nori_wal_open(const char *dir,
+                            const struct NoriWalOptions *options,
+                            struct NoriWal **wal_out);
+
+// Appends a put of `value` under `key`, expiring after `ttl_ms`
+// milliseconds unless it is 0. If `position_out` isn't null, the record's
+// position is written to it. The record is durable once the fsync policy
+// or `nori_wal_sync` has synced it.
+//
+// # Safety
+//
+// `wal` must come from `nori_wal_open`, `key` and `value` must point to
+// `key_len` and `value_len` readable bytes (or be null if the length is
+// 0), and `position_out` must be null or writable.
+NoriWalStatus nori_wal_append(const struct NoriWal *wal,
+                              const uint8_t *key,
+                              size_t key_len,
+                              const uint8_t *value,
+                              size_t value_len,
+                              uint64_t ttl_ms,
+                              struct NoriWalPosition *position_out);
+
+// Appends a delete of `key`, like `nori_wal_append`.
+//
+// # Safety
+//
+// As for `nori_wal_append`.
+NoriWalStatus nori_wal_delete(const struct NoriWal *wal,
+                              const uint8_t *key,
+                              size_t key_len,
+                              struct NoriWalPosition *position_out);
+
+// Makes every record appended so far durable.
+//
+// # Safety
+//
+// `wal` must come from `nori_wal_open`.
+NoriWalStatus nori_wal_sync(const struct NoriWal *wal);
+
+// Writes the position the next record will be appended at.
+//
+// # Safety
+//
+// `wal` must come from `nori_wal_open` and `position_out` be writable.
+NoriWalStatus nori_wal_current_position(const struct NoriWal *wal,
+                                        struct NoriWalPosition *position_out);
+
+// Writes the LSN the next appended record will get.
+//
+// # Safety
+//
+// `wal` must come from `nori_wal_open` and `lsn_out` be writable.
+NoriWalStatus nori_wal_next_lsn(const struct NoriWal *wal, uint64_t *lsn_out);
+
+// Starts reading at `position`, which must be a record boundary such as
+// one returned by an append. On success `*iter_out` is an iterator, to
+// free with `nori_wal_iter_free`.
+//
+// # Safety
+//
+// `wal` must come from `nori_wal_open` and `iter_out` be writable.
+NoriWalStatus nori_wal_read_from(const struct NoriWal *wal,
+                                 struct NoriWalPosition position,
+                                 struct NoriWalIter **iter_out);
+
+// Starts reading at the first record whose LSN is at least `lsn`, like
+// `nori_wal_read_from`.
+//
+// # Safety
+//
+// As for `nori_wal_read_from`.
+NoriWalStatus nori_wal_read_from_lsn(const struct NoriWal *wal,
+                                     uint64_t lsn,
+                                     struct NoriWalIter **iter_out);
+
+// Reads the next record into `*record_out`, returning
+// `NORI_WAL_STATUS_END` once the iterator has reached the durable end of
+// the log. Records made durable later are returned by later calls.
+//
+// # Safety
+//
+// `iter` must come from `nori_wal_read_from` or `nori_wal_read_from_lsn`
+// and `record_out` be writable.
+NoriWalStatus nori_wal_iter_next(struct NoriWalIter *iter, struct NoriWalRecord *record_out);
+
+// Frees an iterator. Null is ignored.
+//
+// # Safety
+//
+// `iter` must be null or come from `nori_wal_read_from` or
+// `nori_wal_read_from_lsn`, and not be used afterwards.
+void nori_wal_iter_free(struct NoriWalIter *iter);
+
+// Syncs and closes the WAL, releasing its directory. The handle is freed
+// whatever the status. Null is ignored.
+//
+// # Safety
+//
+// `wal` must be null or come from `nori_wal_open`, every iterator over it
+// must have been freed, and no other thread may be using it. It must not
+// be used afterwards.
+NoriWalStatus nori_wal_close(struct NoriWal *wal);
+
+// Returns the message of the last error on this thread, or null if there
+// has been none. The string stays valid until the next failing call on the
+// same thread.
+const char *nori_wal_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NORI_WAL_H */
@@ -0,0 +1,109 @@
+//! Status codes and the per-thread last error message.
+
+use nori_wal::{ErrorClass, SegmentError, WalError};
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Result of every fallible call. Zero or positive is success; on a negative
+/// status, `nori_wal_last_error` describes what went wrong.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoriWalStatus {
+    Ok = 0,
+    /// `nori_wal_iter_next` has reached the durable end of the log.
+    End = 1,
+    /// A null pointer, a directory that isn't UTF-8, or a bad option.
+    InvalidArgument = -1,
+    /// An I/O error, such as a full disk.
+    Io = -2,
+    /// Data on disk failed validation.
+    Corruption = -3,
+    /// Another process has the WAL directory open.
+    Locked = -4,
+    /// The WAL was closed underneath the call.
+    Closed = -5,
+    /// The record is larger than the configured maximum.
+    TooLarge = -6,
+    /// The position is no longer in the log (purged or truncated).
+    NotFound = -7,
+    /// Any other error.
+    Error = -8,
+    /// A bug: the call panicked, and its effects are unknown.
+    Panic = -9,
+}
+
+/// An error on its way out to C.
+pub(crate) struct FfiError {
+    pub(crate) status: NoriWalStatus,
+    pub(crate) message: String,
+}
+
+impl FfiError {
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        Self {
+            status: NoriWalStatus::InvalidArgument,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<SegmentError> for FfiError {
+    fn from(e: SegmentError) -> Self {
+        let status = match &e {
+            SegmentError::Locked(_) => NoriWalStatus::Locked,
+            SegmentError::Closed => NoriWalStatus::Closed,
+            SegmentError::RecordTooLarge { .. } => NoriWalStatus::TooLarge,
+            SegmentError::CursorGone(_) | SegmentError::Purged(_) | SegmentError::NotFound(_) => {
+                NoriWalStatus::NotFound
+            }
+            SegmentError::InvalidConfig(_) | SegmentError::Config(_) => {
+                NoriWalStatus::InvalidArgument
+            }
+            SegmentError::Io(_) | SegmentError::SegmentIo { .. } => NoriWalStatus::Io,
+            _ => NoriWalStatus::Error,
+        };
+        let message = e.to_string();
+        let status = match WalError::from(e).class() {
+            ErrorClass::Corruption => NoriWalStatus::Corruption,
+            _ => status,
+        };
+        Self { status, message }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Runs the body of an exported function: records the message of an error
+/// or panic for `nori_wal_last_error`, and returns the status.
+pub(crate) fn ffi_call(body: impl FnOnce() -> Result<NoriWalStatus, FfiError>) -> NoriWalStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(status)) => return status,
+        Ok(Err(e)) => (e.status, e.message),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            (NoriWalStatus::Panic, format!("panic: {}", message))
+        }
+    };
+    let message = CString::new(message.replace('\0', " ")).expect("NULs replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+    status
+}
+
+/// Returns the message of the last error on this thread, or null if there
+/// has been none. The string stays valid until the next failing call on the
+/// same thread.
+#[no_mangle]
+pub extern "C" fn nori_wal_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
@@ -0,0 +1,629 @@
+//! C ABI for nori-wal.
+//!
+//! Wraps [`nori_wal::blocking::Wal`], which runs the async WAL on a private
+//! runtime, behind plain C functions, so C and C++ programs can embed the
+//! log without knowing about tokio. `include/nori_wal.h` declares them; it
+//! is generated from this crate by cbindgen and checked in, and a test
+//! keeps the checked-in copy up to date.
+//!
+//! ```c
+//! NoriWal *wal;
+//! if (nori_wal_open("/var/lib/app/wal", NULL, &wal) != NORI_WAL_STATUS_OK) {
+//!     fprintf(stderr, "open: %s\n", nori_wal_last_error());
+//!     return 1;
+//! }
+//! NoriWalPosition start;
+//! nori_wal_current_position(wal, &start);
+//! nori_wal_append(wal, (const uint8_t *)"key", 3, (const uint8_t *)"value", 5, 0, NULL);
+//! nori_wal_sync(wal);
+//!
+//! NoriWalIter *iter;
+//! NoriWalRecord record;
+//! nori_wal_read_from(wal, start, &iter);
+//! while (nori_wal_iter_next(iter, &record) == NORI_WAL_STATUS_OK) {
+//!     fwrite(record.key, 1, record.key_len, stdout);
+//! }
+//! nori_wal_iter_free(iter);
+//! nori_wal_close(wal);
+//! ```
+//!
+//! Every fallible function returns a [`NoriWalStatus`] and writes its
+//! results through out-pointers; on failure, [`nori_wal_last_error`] gives
+//! the message. A panic is caught at the boundary and reported as
+//! `NORI_WAL_STATUS_PANIC`. A `NoriWal` may be shared between threads;
+//! an iterator belongs to one thread at a time and must be freed before
+//! its WAL is closed.
+
+mod error;
+
+pub use error::{nori_wal_last_error, NoriWalStatus};
+
+use error::{ffi_call, FfiError};
+use nori_wal::{blocking, FsyncPolicy, Position, Record, WalConfig};
+use std::ffi::{c_char, CStr};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An open WAL.
+pub struct NoriWal {
+    wal: blocking::Wal,
+}
+
+/// A reader over the records of a WAL, from a starting point to its durable
+/// end.
+pub struct NoriWalIter {
+    reader: blocking::WalReader,
+    /// The record last returned, which the caller's pointers point into.
+    current: Option<Record>,
+}
+
+/// A place in the log: a segment and a byte offset in it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoriWalPosition {
+    pub segment_id: u64,
+    pub offset: u64,
+}
+
+impl From<Position> for NoriWalPosition {
+    fn from(position: Position) -> Self {
+        Self {
+            segment_id: position.segment_id,
+            offset: position.offset,
+        }
+    }
+}
+
+impl From<NoriWalPosition> for Position {
+    fn from(position: NoriWalPosition) -> Self {
+        Self {
+            segment_id: position.segment_id,
+            offset: position.offset,
+        }
+    }
+}
+
+/// When appended records are fsynced.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoriWalFsync {
+    /// After every append.
+    Always,
+    /// At most once per `fsync_window_ms`.
+    Batch,
+    /// When the OS decides, or on `nori_wal_sync`.
+    Os,
+}
+
+/// Settings for `nori_wal_open`; start from `nori_wal_options_default`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NoriWalOptions {
+    /// Segment size at which the WAL rotates to a new segment.
+    pub max_segment_size: u64,
+    pub fsync: NoriWalFsync,
+    /// Window for `NORI_WAL_FSYNC_BATCH`.
+    pub fsync_window_ms: u64,
+    /// Allocate each segment's full size up front.
+    pub preallocate: bool,
+    /// Largest record accepted, or 0 for no limit.
+    pub max_record_size: u64,
+}
+
+impl Default for NoriWalOptions {
+    fn default() -> Self {
+        let config = WalConfig::default();
+        let (fsync, window) = match config.fsync_policy {
+            FsyncPolicy::Always => (NoriWalFsync::Always, Duration::ZERO),
+            FsyncPolicy::Batch(window) => (NoriWalFsync::Batch, window),
+            FsyncPolicy::Os => (NoriWalFsync::Os, Duration::ZERO),
+        };
+        Self {
+            max_segment_size: config.max_segment_size,
+            fsync,
+            fsync_window_ms: window.as_millis() as u64,
+            preallocate: config.preallocate,
+            max_record_size: config.max_record_size.unwrap_or(0),
+        }
+    }
+}
+
+impl NoriWalOptions {
+    fn to_config(self, dir: PathBuf) -> WalConfig {
+        let fsync_policy = match self.fsync {
+            NoriWalFsync::Always => FsyncPolicy::Always,
+            NoriWalFsync::Batch => FsyncPolicy::Batch(Duration::from_millis(self.fsync_window_ms)),
+            NoriWalFsync::Os => FsyncPolicy::Os,
+        };
+        WalConfig {
+            dir,
+            max_segment_size: self.max_segment_size,
+            fsync_policy,
+            preallocate: self.preallocate,
+            max_record_size: (self.max_record_size > 0).then_some(self.max_record_size),
+            ..WalConfig::default()
+        }
+    }
+}
+
+/// A record read by `nori_wal_iter_next`.
+///
+/// `key` and `value` point into memory owned by the iterator, valid until
+/// the next call on it or until it is freed. Optional fields are 0 when
+/// unset.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NoriWalRecord {
+    pub key: *const u8,
+    pub key_len: usize,
+    /// Empty for a delete.
+    pub value: *const u8,
+    pub value_len: usize,
+    /// True for a delete.
+    pub tombstone: bool,
+    pub lsn: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    pub ttl_ms: u64,
+    /// Meaningful only if `has_namespace` is set.
+    pub namespace_id: u32,
+    pub has_namespace: bool,
+    /// Where the record is in the log.
+    pub position: NoriWalPosition,
+}
+
+/// Returns a mutable reference through `ptr`, or an error naming `what` if
+/// it is null.
+unsafe fn out<'a, T>(ptr: *mut T, what: &str) -> Result<&'a mut T, FfiError> {
+    ptr.as_mut()
+        .ok_or_else(|| FfiError::invalid(format!("{} is null", what)))
+}
+
+unsafe fn wal_ref<'a>(wal: *const NoriWal) -> Result<&'a blocking::Wal, FfiError> {
+    wal.as_ref()
+        .map(|wal| &wal.wal)
+        .ok_or_else(|| FfiError::invalid("wal is null"))
+}
+
+/// Returns `len` bytes at `ptr`, which may be null only if `len` is 0.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize, what: &str) -> Result<&'a [u8], FfiError> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(FfiError::invalid(format!("{} is null", what))),
+        (false, len) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Fills `options` with the defaults.
+///
+/// # Safety
+///
+/// `options` must be null or point to writable memory for a
+/// `NoriWalOptions`.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_options_default(options: *mut NoriWalOptions) -> NoriWalStatus {
+    ffi_call(|| {
+        *out(options, "options")? = NoriWalOptions::default();
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Opens the WAL in the directory `dir`, creating it if missing and
+/// recovering it if it was not closed cleanly. `options` may be null for the
+/// defaults. On success `*wal_out` is the WAL, to close with
+/// `nori_wal_close`.
+///
+/// # Safety
+///
+/// `dir` must be a NUL-terminated string, `options` null or a valid
+/// `NoriWalOptions`, and `wal_out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_open(
+    dir: *const c_char,
+    options: *const NoriWalOptions,
+    wal_out: *mut *mut NoriWal,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal_out = out(wal_out, "wal_out")?;
+        if dir.is_null() {
+            return Err(FfiError::invalid("dir is null"));
+        }
+        let dir = CStr::from_ptr(dir)
+            .to_str()
+            .map_err(|_| FfiError::invalid("dir is not UTF-8"))?;
+        let options = options.as_ref().copied().unwrap_or_default();
+        let (wal, _) = blocking::Wal::open(options.to_config(PathBuf::from(dir)))?;
+        *wal_out = Box::into_raw(Box::new(NoriWal { wal }));
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Appends a put of `value` under `key`, expiring after `ttl_ms`
+/// milliseconds unless it is 0. If `position_out` isn't null, the record's
+/// position is written to it. The record is durable once the fsync policy
+/// or `nori_wal_sync` has synced it.
+///
+/// # Safety
+///
+/// `wal` must come from `nori_wal_open`, `key` and `value` must point to
+/// `key_len` and `value_len` readable bytes (or be null if the length is
+/// 0), and `position_out` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_append(
+    wal: *const NoriWal,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+    ttl_ms: u64,
+    position_out: *mut NoriWalPosition,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        let key = bytes(key, key_len, "key")?.to_vec();
+        let value = bytes(value, value_len, "value")?.to_vec();
+        let record = match ttl_ms {
+            0 => Record::put(key, value),
+            ttl => Record::put_with_ttl(key, value, Duration::from_millis(ttl)),
+        };
+        let position = wal.append(&record)?;
+        if let Some(position_out) = position_out.as_mut() {
+            *position_out = position.into();
+        }
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Appends a delete of `key`, like `nori_wal_append`.
+///
+/// # Safety
+///
+/// As for `nori_wal_append`.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_delete(
+    wal: *const NoriWal,
+    key: *const u8,
+    key_len: usize,
+    position_out: *mut NoriWalPosition,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        let key = bytes(key, key_len, "key")?.to_vec();
+        let position = wal.append(&Record::delete(key))?;
+        if let Some(position_out) = position_out.as_mut() {
+            *position_out = position.into();
+        }
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Makes every record appended so far durable.
+///
+/// # Safety
+///
+/// `wal` must come from `nori_wal_open`.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_sync(wal: *const NoriWal) -> NoriWalStatus {
+    ffi_call(|| {
+        wal_ref(wal)?.sync()?;
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Writes the position the next record will be appended at.
+///
+/// # Safety
+///
+/// `wal` must come from `nori_wal_open` and `position_out` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_current_position(
+    wal: *const NoriWal,
+    position_out: *mut NoriWalPosition,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        *out(position_out, "position_out")? = wal.current_position().into();
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Writes the LSN the next appended record will get.
+///
+/// # Safety
+///
+/// `wal` must come from `nori_wal_open` and `lsn_out` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_next_lsn(
+    wal: *const NoriWal,
+    lsn_out: *mut u64,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        *out(lsn_out, "lsn_out")? = wal.next_lsn();
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Starts reading at `position`, which must be a record boundary such as
+/// one returned by an append. On success `*iter_out` is an iterator, to
+/// free with `nori_wal_iter_free`.
+///
+/// # Safety
+///
+/// `wal` must come from `nori_wal_open` and `iter_out` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_read_from(
+    wal: *const NoriWal,
+    position: NoriWalPosition,
+    iter_out: *mut *mut NoriWalIter,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        let iter_out = out(iter_out, "iter_out")?;
+        *iter_out = new_iter(wal.reader(position.into()));
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Starts reading at the first record whose LSN is at least `lsn`, like
+/// `nori_wal_read_from`.
+///
+/// # Safety
+///
+/// As for `nori_wal_read_from`.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_read_from_lsn(
+    wal: *const NoriWal,
+    lsn: u64,
+    iter_out: *mut *mut NoriWalIter,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let wal = wal_ref(wal)?;
+        let iter_out = out(iter_out, "iter_out")?;
+        *iter_out = new_iter(wal.read_from_lsn(lsn)?);
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+fn new_iter(reader: blocking::WalReader) -> *mut NoriWalIter {
+    Box::into_raw(Box::new(NoriWalIter {
+        reader,
+        current: None,
+    }))
+}
+
+/// Reads the next record into `*record_out`, returning
+/// `NORI_WAL_STATUS_END` once the iterator has reached the durable end of
+/// the log. Records made durable later are returned by later calls.
+///
+/// # Safety
+///
+/// `iter` must come from `nori_wal_read_from` or `nori_wal_read_from_lsn`
+/// and `record_out` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_iter_next(
+    iter: *mut NoriWalIter,
+    record_out: *mut NoriWalRecord,
+) -> NoriWalStatus {
+    ffi_call(|| {
+        let iter = out(iter, "iter")?;
+        let record_out = out(record_out, "record_out")?;
+        iter.current = None;
+        let Some(next) = iter.reader.next() else {
+            return Ok(NoriWalStatus::End);
+        };
+        let (record, position) = next?;
+        let millis = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64)
+        };
+        *record_out = NoriWalRecord {
+            key: record.key.as_ptr(),
+            key_len: record.key.len(),
+            value: record.value.as_ptr(),
+            value_len: record.value.len(),
+            tombstone: record.tombstone,
+            lsn: record.lsn.unwrap_or(0),
+            timestamp_ms: record.timestamp.map_or(0, millis),
+            ttl_ms: record.ttl.map_or(0, |ttl| ttl.as_millis() as u64),
+            namespace_id: record.namespace.unwrap_or(0),
+            has_namespace: record.namespace.is_some(),
+            position: position.into(),
+        };
+        iter.current = Some(record);
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+/// Frees an iterator. Null is ignored.
+///
+/// # Safety
+///
+/// `iter` must be null or come from `nori_wal_read_from` or
+/// `nori_wal_read_from_lsn`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_iter_free(iter: *mut NoriWalIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Syncs and closes the WAL, releasing its directory. The handle is freed
+/// whatever the status. Null is ignored.
+///
+/// # Safety
+///
+/// `wal` must be null or come from `nori_wal_open`, every iterator over it
+/// must have been freed, and no other thread may be using it. It must not
+/// be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn nori_wal_close(wal: *mut NoriWal) -> NoriWalStatus {
+    ffi_call(|| {
+        if wal.is_null() {
+            return Ok(NoriWalStatus::Ok);
+        }
+        Box::from_raw(wal).wal.close()?;
+        Ok(NoriWalStatus::Ok)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr;
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/nori_wal.h");
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/nori_wal.h"))
+                == include_str!("../include/nori_wal.h"),
+            "include/nori_wal.h is stale; copy {} over it",
+            generated
+        );
+    }
+
+    unsafe fn open(dir: &std::path::Path) -> *mut NoriWal {
+        let dir = CString::new(dir.to_str().unwrap()).unwrap();
+        let mut wal = ptr::null_mut();
+        assert_eq!(
+            nori_wal_open(dir.as_ptr(), ptr::null(), &mut wal),
+            NoriWalStatus::Ok
+        );
+        wal
+    }
+
+    unsafe fn slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+        std::slice::from_raw_parts(ptr, len)
+    }
+
+    #[test]
+    fn test_append_sync_iterate_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        unsafe {
+            let wal = open(dir.path());
+            let mut start = NoriWalPosition {
+                segment_id: 0,
+                offset: 0,
+            };
+            assert_eq!(
+                nori_wal_current_position(wal, &mut start),
+                NoriWalStatus::Ok
+            );
+            let mut second = start;
+            assert_eq!(
+                nori_wal_append(wal, b"a".as_ptr(), 1, b"1".as_ptr(), 1, 0, ptr::null_mut()),
+                NoriWalStatus::Ok
+            );
+            assert_eq!(
+                nori_wal_append(wal, b"b".as_ptr(), 1, ptr::null(), 0, 5_000, &mut second),
+                NoriWalStatus::Ok
+            );
+            assert_eq!(
+                nori_wal_delete(wal, b"a".as_ptr(), 1, ptr::null_mut()),
+                NoriWalStatus::Ok
+            );
+            assert_eq!(nori_wal_sync(wal), NoriWalStatus::Ok);
+
+            let mut iter = ptr::null_mut();
+            assert_eq!(nori_wal_read_from(wal, start, &mut iter), NoriWalStatus::Ok);
+            let mut record = std::mem::MaybeUninit::<NoriWalRecord>::uninit();
+            let mut seen = Vec::new();
+            while nori_wal_iter_next(iter, record.as_mut_ptr()) == NoriWalStatus::Ok {
+                let r = record.assume_init();
+                seen.push((
+                    slice(r.key, r.key_len).to_vec(),
+                    slice(r.value, r.value_len).to_vec(),
+                    r.tombstone,
+                    r.lsn,
+                    r.ttl_ms,
+                ));
+                if r.lsn == 2 {
+                    assert_eq!(r.position, second);
+                }
+            }
+            assert_eq!(
+                seen,
+                [
+                    (b"a".to_vec(), b"1".to_vec(), false, 1, 0),
+                    (b"b".to_vec(), Vec::new(), false, 2, 5_000),
+                    (b"a".to_vec(), Vec::new(), true, 3, 0),
+                ]
+            );
+            nori_wal_iter_free(iter);
+            assert_eq!(nori_wal_close(wal), NoriWalStatus::Ok);
+
+            let wal = open(dir.path());
+            let mut lsn = 0;
+            assert_eq!(nori_wal_next_lsn(wal, &mut lsn), NoriWalStatus::Ok);
+            assert_eq!(lsn, 4);
+            let mut iter = ptr::null_mut();
+            assert_eq!(nori_wal_read_from_lsn(wal, 3, &mut iter), NoriWalStatus::Ok);
+            assert_eq!(
+                nori_wal_iter_next(iter, record.as_mut_ptr()),
+                NoriWalStatus::Ok
+            );
+            assert!(record.assume_init().tombstone);
+            assert_eq!(
+                nori_wal_iter_next(iter, record.as_mut_ptr()),
+                NoriWalStatus::End
+            );
+            nori_wal_iter_free(iter);
+            assert_eq!(nori_wal_close(wal), NoriWalStatus::Ok);
+        }
+    }
+
+    #[test]
+    fn test_errors_set_status_and_message() {
+        let dir = tempfile::tempdir().unwrap();
+        unsafe {
+            let wal = open(dir.path());
+            assert_eq!(
+                nori_wal_append(wal, ptr::null(), 3, ptr::null(), 0, 0, ptr::null_mut()),
+                NoriWalStatus::InvalidArgument
+            );
+            let message = CStr::from_ptr(nori_wal_last_error()).to_str().unwrap();
+            assert_eq!(message, "key is null");
+
+            // The directory is locked while open
+            let path = CString::new(dir.path().to_str().unwrap()).unwrap();
+            let mut second = ptr::null_mut();
+            assert_eq!(
+                nori_wal_open(path.as_ptr(), ptr::null(), &mut second),
+                NoriWalStatus::Locked
+            );
+            assert!(second.is_null());
+            assert_eq!(nori_wal_close(wal), NoriWalStatus::Ok);
+
+            let mut options = std::mem::MaybeUninit::uninit();
+            assert_eq!(
+                nori_wal_options_default(options.as_mut_ptr()),
+                NoriWalStatus::Ok
+            );
+            let options = NoriWalOptions {
+                max_record_size: 16,
+                ..options.assume_init()
+            };
+            let mut wal = ptr::null_mut();
+            assert_eq!(
+                nori_wal_open(path.as_ptr(), &options, &mut wal),
+                NoriWalStatus::Ok
+            );
+            let big = [0u8; 64];
+            assert_eq!(
+                nori_wal_append(
+                    wal,
+                    b"k".as_ptr(),
+                    1,
+                    big.as_ptr(),
+                    big.len(),
+                    0,
+                    ptr::null_mut()
+                ),
+                NoriWalStatus::TooLarge
+            );
+            assert_eq!(nori_wal_close(wal), NoriWalStatus::Ok);
+        }
+    }
+}
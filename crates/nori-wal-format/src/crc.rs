@@ -0,0 +1,47 @@
+//! CRC32C (Castagnoli), the checksum that ends every record.
+
+/// CRC32C of `data`.
+#[cfg(feature = "std")]
+pub fn crc32c(data: &[u8]) -> u32 {
+    ::crc32c::crc32c(data)
+}
+
+/// CRC32C of `data`.
+#[cfg(not(feature = "std"))]
+pub fn crc32c(data: &[u8]) -> u32 {
+    software(data)
+}
+
+/// Reflected Castagnoli polynomial.
+const POLY: u32 = 0x82F6_3B78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Table-driven CRC32C that runs anywhere; a few times slower than the
+/// SSE4.2 and ARMv8 instructions the `std` feature uses when available.
+#[cfg_attr(feature = "std", allow(dead_code))]
+pub(crate) fn software(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
@@ -0,0 +1,708 @@
+//! Wire format of nori-wal records, without the WAL.
+//!
+//! This crate is the codec `nori-wal` itself uses: the varints, flag bits,
+//! header extensions and CRC32C of a single record. It is `no_std` and has
+//! no required dependencies, so edge agents, WASM modules and browser
+//! tooling can produce and consume records byte-for-byte compatible with a
+//! WAL segment.
+//!
+//! Record format:
+//! - klen: varint
+//! - vlen: varint
+//! - flags: u8 (bits: 0=tombstone, 1=ttl_present, 2-3=compression, 4=extensions, 5-7=reserved)
+//! - ttl_ms?: varint (if ttl_present bit set)
+//! - extensions?: varint length, then entries of (tag: u8, len: varint, bytes[len])
+//!   (if extensions bit set; unknown tags are skipped): LSN, timestamp,
+//!   namespace and coalesced count as varints, and a hash-chain link and
+//!   trace context as bytes
+//! - key: bytes[klen]
+//! - value: bytes[vlen]
+//! - crc32c: u32 (little-endian)
+//!
+//! Compression is outside the codec: a compressed record's value is stored
+//! as the compressor produced it, and [`RecordRef::value`] holds those
+//! bytes. LZ4 values are prefixed with the uncompressed length as a varint.
+//!
+//! # Features
+//!
+//! - `alloc` (default): [`RecordRef::encode_to_vec`] and [`varint::put`].
+//!   Without it, encode into a caller buffer with [`RecordRef::encode_into`].
+//! - `std`: uses the hardware-accelerated `crc32c` crate instead of the
+//!   portable table, and implements `std::error::Error` for [`FormatError`].
+//!
+//! ```
+//! use nori_wal_format::RecordRef;
+//!
+//! let record = RecordRef::put(b"key", b"value").with_lsn(7);
+//! let mut buf = [0u8; 64];
+//! let len = record.encode_into(&mut buf).unwrap();
+//!
+//! let (decoded, size) = RecordRef::decode(&buf[..len]).unwrap();
+//! assert_eq!(decoded, record);
+//! assert_eq!(size, len);
+//! ```
+
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod crc;
+pub mod varint;
+
+pub use crc::crc32c;
+
+use core::fmt;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Flag bit marking a delete.
+pub const FLAG_TOMBSTONE: u8 = 0b0000_0001;
+/// Flag bit marking a TTL varint after the flags.
+pub const FLAG_TTL_PRESENT: u8 = 0b0000_0010;
+/// Flag bits holding the [`Compression`].
+pub const FLAG_COMPRESSION_MASK: u8 = 0b0000_1100;
+/// Flag bit marking an extension block after the TTL.
+pub const FLAG_EXTENSIONS: u8 = 0b0001_0000;
+
+/// Extension tag carrying the record's log sequence number.
+pub const EXT_LSN: u8 = 1;
+/// Extension tag carrying the append timestamp in milliseconds since the epoch.
+pub const EXT_TIMESTAMP: u8 = 2;
+/// Extension tag carrying the record's namespace ID.
+pub const EXT_NAMESPACE: u8 = 3;
+/// Extension tag carrying the hash of the previous record, in logs kept as
+/// a hash chain: [`CHAIN_LINK_LEN`] bytes.
+pub const EXT_CHAIN: u8 = 4;
+/// Extension tag carrying the W3C trace context of the append: trace ID,
+/// parent span ID and trace flags, [`TraceContext::LEN`] bytes.
+pub const EXT_TRACE: u8 = 5;
+/// Extension tag carrying the number of earlier puts to the record's key
+/// that were dropped in its favor before reaching the log.
+pub const EXT_COALESCED: u8 = 6;
+
+/// Size of the trailing checksum.
+pub const CRC_LEN: usize = 4;
+
+/// Size of a hash-chain link, a SHA-256 digest.
+pub const CHAIN_LINK_LEN: usize = 32;
+
+/// Errors from decoding (or encoding into too small a buffer).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    /// The data ends before the record does.
+    Incomplete,
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    InvalidCompression(u8),
+    /// A varint runs past 64 bits.
+    VarintOverflow,
+    BufferTooSmall {
+        needed: usize,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Incomplete => write!(f, "Incomplete record"),
+            FormatError::CrcMismatch { expected, actual } => write!(
+                f,
+                "CRC mismatch: expected {:#x}, got {:#x}",
+                expected, actual
+            ),
+            FormatError::InvalidCompression(v) => write!(f, "Invalid compression type: {}", v),
+            FormatError::VarintOverflow => write!(f, "varint overflow"),
+            FormatError::BufferTooSmall { needed } => {
+                write!(f, "buffer too small: record needs {} bytes", needed)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FormatError {}
+
+/// Compression type for record values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    /// Reads the compression from the two bits it is stored in.
+    pub fn from_bits(bits: u8) -> Result<Self, FormatError> {
+        match bits {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            2 => Ok(Compression::Zstd),
+            v => Err(FormatError::InvalidCompression(v)),
+        }
+    }
+
+    pub fn to_bits(self) -> u8 {
+        self as u8
+    }
+}
+
+/// The W3C trace context (`traceparent`) of the request that appended a
+/// record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    /// The caller's span, which spans for the append are children of.
+    pub span_id: [u8; 8],
+    /// Trace flags; bit 0 marks the trace as sampled.
+    pub flags: u8,
+}
+
+impl TraceContext {
+    /// Bytes of the [`EXT_TRACE`] extension.
+    pub const LEN: usize = 25;
+
+    /// Parses a version 00 `traceparent` header value, such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`. All-zero
+    /// trace or span IDs are invalid.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        if parts.next()? != "00" {
+            return None;
+        }
+        let mut context = TraceContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            flags: 0,
+        };
+        parse_hex(parts.next()?, &mut context.trace_id)?;
+        parse_hex(parts.next()?, &mut context.span_id)?;
+        let mut flags = [0];
+        parse_hex(parts.next()?, &mut flags)?;
+        context.flags = flags[0];
+        if parts.next().is_some() || context.trace_id == [0; 16] || context.span_id == [0; 8] {
+            return None;
+        }
+        Some(context)
+    }
+
+    /// The context as stored in the [`EXT_TRACE`] extension.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[..16].copy_from_slice(&self.trace_id);
+        bytes[16..24].copy_from_slice(&self.span_id);
+        bytes[24] = self.flags;
+        bytes
+    }
+
+    /// Reads an [`EXT_TRACE`] extension, or `None` if it is not one.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: bytes[..16].try_into().expect("16 bytes"),
+            span_id: bytes[16..24].try_into().expect("8 bytes"),
+            flags: bytes[24],
+        })
+    }
+}
+
+/// Formats the context as a `traceparent` header value.
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("00-")?;
+        for byte in self.trace_id {
+            write!(f, "{:02x}", byte)?;
+        }
+        f.write_str("-")?;
+        for byte in self.span_id {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "-{:02x}", self.flags)
+    }
+}
+
+/// Fills `out` from exactly `out.len() * 2` lowercase hex digits.
+fn parse_hex(text: &str, out: &mut [u8]) -> Option<()> {
+    let digits = text.as_bytes();
+    if digits.len() != out.len() * 2 {
+        return None;
+    }
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+    for (byte, pair) in out.iter_mut().zip(digits.chunks(2)) {
+        *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// A record as it is laid out on the wire, borrowing its key and value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordRef<'a> {
+    pub key: &'a [u8],
+    /// The stored value: compressed if `compression` is not `None`.
+    pub value: &'a [u8],
+    pub tombstone: bool,
+    pub ttl_ms: Option<u64>,
+    pub compression: Compression,
+    pub lsn: Option<u64>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: Option<u64>,
+    pub namespace: Option<u32>,
+    /// SHA-256 of the encoded record before this one, linking the two in a
+    /// hash chain. The codec stores it without computing or checking it.
+    pub chain: Option<[u8; CHAIN_LINK_LEN]>,
+    pub trace: Option<TraceContext>,
+    /// Earlier puts to the same key this record replaced.
+    pub coalesced: Option<u64>,
+}
+
+impl<'a> RecordRef<'a> {
+    /// A PUT of `value` under `key`.
+    pub fn put(key: &'a [u8], value: &'a [u8]) -> Self {
+        Self {
+            key,
+            value,
+            tombstone: false,
+            ttl_ms: None,
+            compression: Compression::None,
+            lsn: None,
+            timestamp_ms: None,
+            namespace: None,
+            chain: None,
+            trace: None,
+            coalesced: None,
+        }
+    }
+
+    /// A DELETE (tombstone) of `key`.
+    pub fn delete(key: &'a [u8]) -> Self {
+        Self {
+            tombstone: true,
+            ..Self::put(key, &[])
+        }
+    }
+
+    pub fn with_ttl_ms(mut self, ttl_ms: u64) -> Self {
+        self.ttl_ms = Some(ttl_ms);
+        self
+    }
+
+    pub fn with_lsn(mut self, lsn: u64) -> Self {
+        self.lsn = Some(lsn);
+        self
+    }
+
+    pub fn with_timestamp_ms(mut self, timestamp_ms: u64) -> Self {
+        self.timestamp_ms = Some(timestamp_ms);
+        self
+    }
+
+    pub fn with_namespace(mut self, namespace: u32) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    pub fn with_chain(mut self, previous_hash: [u8; CHAIN_LINK_LEN]) -> Self {
+        self.chain = Some(previous_hash);
+        self
+    }
+
+    pub fn with_trace(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    pub fn with_coalesced(mut self, replaced: u64) -> Self {
+        self.coalesced = Some(replaced);
+        self
+    }
+
+    /// Number of bytes the record encodes to, checksum included.
+    pub fn encoded_len(&self) -> usize {
+        let extensions = self.extensions_len();
+        let mut len = varint::encoded_len(self.key.len() as u64)
+            + varint::encoded_len(self.value.len() as u64)
+            + 1
+            + self.key.len()
+            + self.value.len()
+            + CRC_LEN;
+        if let Some(ttl_ms) = self.ttl_ms {
+            len += varint::encoded_len(ttl_ms);
+        }
+        if extensions > 0 {
+            len += varint::encoded_len(extensions as u64) + extensions;
+        }
+        len
+    }
+
+    /// Encodes the record into the start of `out`, returning its length.
+    pub fn encode_into(&self, out: &mut [u8]) -> Result<usize, FormatError> {
+        let needed = self.encoded_len();
+        if out.len() < needed {
+            return Err(FormatError::BufferTooSmall { needed });
+        }
+
+        let mut w = Writer { out, pos: 0 };
+        w.varint(self.key.len() as u64);
+        w.varint(self.value.len() as u64);
+
+        let extensions = self.extensions_len();
+        let mut flags = (self.compression.to_bits() << 2) & FLAG_COMPRESSION_MASK;
+        if self.tombstone {
+            flags |= FLAG_TOMBSTONE;
+        }
+        if self.ttl_ms.is_some() {
+            flags |= FLAG_TTL_PRESENT;
+        }
+        if extensions > 0 {
+            flags |= FLAG_EXTENSIONS;
+        }
+        w.bytes(&[flags]);
+
+        if let Some(ttl_ms) = self.ttl_ms {
+            w.varint(ttl_ms);
+        }
+        if extensions > 0 {
+            w.varint(extensions as u64);
+            for (tag, value) in self.extensions() {
+                w.bytes(&[tag]);
+                w.varint(varint::encoded_len(value) as u64);
+                w.varint(value);
+            }
+            if let Some(link) = &self.chain {
+                w.bytes(&[EXT_CHAIN]);
+                w.varint(CHAIN_LINK_LEN as u64);
+                w.bytes(link);
+            }
+            if let Some(trace) = &self.trace {
+                w.bytes(&[EXT_TRACE]);
+                w.varint(TraceContext::LEN as u64);
+                w.bytes(&trace.to_bytes());
+            }
+        }
+
+        w.bytes(self.key);
+        w.bytes(self.value);
+
+        let crc = crc32c(&w.out[..w.pos]);
+        w.bytes(&crc.to_le_bytes());
+        debug_assert_eq!(w.pos, needed);
+        Ok(w.pos)
+    }
+
+    /// Appends the encoded record to `buf`.
+    #[cfg(feature = "alloc")]
+    pub fn encode_to_vec(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.resize(start + self.encoded_len(), 0);
+        self.encode_into(&mut buf[start..])
+            .expect("buffer sized by encoded_len");
+    }
+
+    /// Decodes the record at the start of `data`, validating its checksum,
+    /// and returns it with the number of bytes it occupies.
+    pub fn decode(data: &'a [u8]) -> Result<(Self, usize), FormatError> {
+        if data.len() < 6 {
+            return Err(FormatError::Incomplete);
+        }
+
+        let mut cursor = data;
+        let klen = varint::read(&mut cursor)?;
+        let vlen = varint::read(&mut cursor)?;
+
+        let (&flags, rest) = cursor.split_first().ok_or(FormatError::Incomplete)?;
+        cursor = rest;
+        let compression = Compression::from_bits((flags & FLAG_COMPRESSION_MASK) >> 2)?;
+        let ttl_ms = if flags & FLAG_TTL_PRESENT != 0 {
+            Some(varint::read(&mut cursor)?)
+        } else {
+            None
+        };
+
+        let mut record = RecordRef {
+            key: &[],
+            value: &[],
+            tombstone: flags & FLAG_TOMBSTONE != 0,
+            ttl_ms,
+            compression,
+            lsn: None,
+            timestamp_ms: None,
+            namespace: None,
+            chain: None,
+            trace: None,
+            coalesced: None,
+        };
+        if flags & FLAG_EXTENSIONS != 0 {
+            let len = varint::read(&mut cursor)?;
+            record.decode_extensions(take(&mut cursor, len)?)?;
+        }
+
+        record.key = take(&mut cursor, klen)?;
+        record.value = take(&mut cursor, vlen)?;
+
+        let body_len = data.len() - cursor.len();
+        let stored = take(&mut cursor, CRC_LEN as u64)?;
+        let expected = u32::from_le_bytes(stored.try_into().expect("4 bytes"));
+        let actual = crc32c(&data[..body_len]);
+        if expected != actual {
+            return Err(FormatError::CrcMismatch { expected, actual });
+        }
+
+        Ok((record, body_len + CRC_LEN))
+    }
+
+    fn decode_extensions(&mut self, mut block: &[u8]) -> Result<(), FormatError> {
+        while let Some((&tag, rest)) = block.split_first() {
+            block = rest;
+            let len = varint::read(&mut block)?;
+            let mut value = take(&mut block, len)?;
+            match tag {
+                EXT_LSN => self.lsn = Some(varint::read(&mut value)?),
+                EXT_TIMESTAMP => self.timestamp_ms = Some(varint::read(&mut value)?),
+                EXT_NAMESPACE => {
+                    let namespace = varint::read(&mut value)?;
+                    // A value that does not fit is not ours to interpret
+                    self.namespace = u32::try_from(namespace).ok();
+                }
+                EXT_CHAIN => self.chain = value.try_into().ok(),
+                EXT_TRACE => self.trace = TraceContext::from_bytes(value),
+                EXT_COALESCED => self.coalesced = Some(varint::read(&mut value)?),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The header fields stored as varint extensions, as (tag, value).
+    fn extensions(&self) -> impl Iterator<Item = (u8, u64)> {
+        [
+            self.lsn.map(|lsn| (EXT_LSN, lsn)),
+            self.timestamp_ms.map(|ms| (EXT_TIMESTAMP, ms)),
+            self.namespace.map(|ns| (EXT_NAMESPACE, ns as u64)),
+            self.coalesced.map(|replaced| (EXT_COALESCED, replaced)),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    fn extensions_len(&self) -> usize {
+        let chain = match self.chain {
+            Some(_) => 1 + varint::encoded_len(CHAIN_LINK_LEN as u64) + CHAIN_LINK_LEN,
+            None => 0,
+        };
+        let trace = match self.trace {
+            Some(_) => 1 + varint::encoded_len(TraceContext::LEN as u64) + TraceContext::LEN,
+            None => 0,
+        };
+        self.extensions()
+            .map(|(_, value)| {
+                let len = varint::encoded_len(value);
+                1 + varint::encoded_len(len as u64) + len
+            })
+            .sum::<usize>()
+            + chain
+            + trace
+    }
+}
+
+/// Splits `len` bytes off the front of `cursor`.
+fn take<'a>(cursor: &mut &'a [u8], len: u64) -> Result<&'a [u8], FormatError> {
+    if (cursor.len() as u64) < len {
+        return Err(FormatError::Incomplete);
+    }
+    let (head, rest) = cursor.split_at(len as usize);
+    *cursor = rest;
+    Ok(head)
+}
+
+/// Infallible writes into a buffer already checked to be large enough.
+struct Writer<'a> {
+    out: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn varint(&mut self, value: u64) {
+        self.pos += varint::write(value, &mut self.out[self.pos..]).expect("buffer checked");
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.out[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn test_software_crc_matches_crc32c() {
+        for data in [&b""[..], b"a", b"123456789", &[0xFF; 1000]] {
+            assert_eq!(crc::software(data), ::crc32c::crc32c(data));
+        }
+        // The standard CRC-32C check value
+        assert_eq!(crc::software(b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0u64, 1, 127, 128, 16383, 16384, u64::MAX] {
+            let mut buf = [0; varint::MAX_LEN];
+            let len = varint::write(value, &mut buf).unwrap();
+            assert_eq!(len, varint::encoded_len(value));
+            let mut slice = &buf[..len];
+            assert_eq!(varint::read(&mut slice).unwrap(), value);
+            assert!(slice.is_empty());
+        }
+        assert_eq!(
+            varint::write(300, &mut [0; 1]),
+            Err(FormatError::BufferTooSmall { needed: 2 })
+        );
+        assert_eq!(
+            varint::read(&mut &[0x80; 11][..]),
+            Err(FormatError::VarintOverflow)
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let record = RecordRef::put(b"user/1", b"compressed-bytes")
+            .with_ttl_ms(5_000)
+            .with_lsn(42)
+            .with_timestamp_ms(1_700_000_000_123)
+            .with_namespace(9)
+            .with_chain([0xDE; CHAIN_LINK_LEN])
+            .with_trace(TraceContext {
+                trace_id: [0x4b; 16],
+                span_id: [0x0f; 8],
+                flags: 1,
+            });
+        let record = RecordRef {
+            compression: Compression::Zstd,
+            ..record
+        };
+        let mut buf = [0u8; 128];
+        let len = record.encode_into(&mut buf).unwrap();
+        assert_eq!(len, record.encoded_len());
+        assert_eq!(RecordRef::decode(&buf[..len]).unwrap(), (record, len));
+
+        // Trailing bytes belong to the next record
+        assert_eq!(RecordRef::decode(&buf).unwrap().1, len);
+
+        assert_eq!(
+            record.encode_into(&mut buf[..len - 1]),
+            Err(FormatError::BufferTooSmall { needed: len })
+        );
+        assert_eq!(
+            RecordRef::decode(&buf[..len - 1]),
+            Err(FormatError::Incomplete)
+        );
+        buf[len - 6] ^= 0xFF;
+        assert!(matches!(
+            RecordRef::decode(&buf[..len]),
+            Err(FormatError::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_traceparent_round_trip() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(header).unwrap();
+        assert_eq!(context.trace_id[..2], [0x4b, 0xf9]);
+        assert_eq!(context.span_id[7], 0xb7);
+        assert_eq!(context.flags, 1);
+        assert_eq!(TraceContext::from_bytes(&context.to_bytes()), Some(context));
+
+        assert_eq!(context.to_string(), header);
+
+        for bad in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+        ] {
+            assert_eq!(TraceContext::parse(bad), None, "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn test_unknown_extension_skipped() {
+        let mut buf = [0u8; 32];
+        let ext = [0xEE, 2, b'x', b'y', EXT_LSN, 1, 7];
+        let header = [1, 1, FLAG_EXTENSIONS | FLAG_TOMBSTONE, ext.len() as u8];
+        let body_len = header.len() + ext.len() + 2;
+        buf[..header.len()].copy_from_slice(&header);
+        buf[header.len()..header.len() + ext.len()].copy_from_slice(&ext);
+        buf[body_len - 2..body_len].copy_from_slice(b"kv");
+        let crc = crc32c(&buf[..body_len]);
+        buf[body_len..body_len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+        let (record, size) = RecordRef::decode(&buf).unwrap();
+        assert_eq!(size, body_len + CRC_LEN);
+        assert_eq!(record.key, b"k");
+        assert!(record.tombstone);
+        assert_eq!(record.lsn, Some(7));
+        assert_eq!(record.timestamp_ms, None);
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(
+            key in prop::collection::vec(any::<u8>(), 0..256),
+            value in prop::collection::vec(any::<u8>(), 0..256),
+            tombstone in any::<bool>(),
+            ttl_ms in prop::option::of(any::<u64>()),
+            lsn in prop::option::of(any::<u64>()),
+            timestamp_ms in prop::option::of(any::<u64>()),
+            namespace in prop::option::of(any::<u32>()),
+            chain in prop::option::of(any::<[u8; CHAIN_LINK_LEN]>()),
+            trace in prop::option::of((any::<[u8; 16]>(), any::<[u8; 8]>(), any::<u8>())),
+            coalesced in prop::option::of(any::<u64>()),
+        ) {
+            let record = RecordRef {
+                key: &key,
+                value: &value,
+                tombstone,
+                ttl_ms,
+                compression: Compression::None,
+                lsn,
+                timestamp_ms,
+                namespace,
+                chain,
+                trace: trace.map(|(trace_id, span_id, flags)| TraceContext {
+                    trace_id,
+                    span_id,
+                    flags,
+                }),
+                coalesced,
+            };
+            let mut buf = Vec::new();
+            record.encode_to_vec(&mut buf);
+            prop_assert_eq!(buf.len(), record.encoded_len());
+            prop_assert_eq!(RecordRef::decode(&buf).unwrap(), (record, buf.len()));
+        }
+    }
+}
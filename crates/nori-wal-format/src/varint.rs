@@ -0,0 +1,62 @@
+//! Unsigned LEB128 varints, as used for every length and integer field.
+
+use crate::FormatError;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+/// Longest encoding of a `u64`.
+pub const MAX_LEN: usize = 10;
+
+/// Number of bytes `value` encodes to.
+pub const fn encoded_len(value: u64) -> usize {
+    let bits = 64 - (value | 1).leading_zeros() as usize;
+    bits.div_ceil(7)
+}
+
+/// Writes `value` to the start of `out`, returning the number of bytes
+/// written.
+pub fn write(value: u64, out: &mut [u8]) -> Result<usize, FormatError> {
+    let len = encoded_len(value);
+    if out.len() < len {
+        return Err(FormatError::BufferTooSmall { needed: len });
+    }
+    let mut value = value;
+    for byte in &mut out[..len - 1] {
+        *byte = (value & 0x7F) as u8 | 0x80;
+        value >>= 7;
+    }
+    out[len - 1] = value as u8;
+    Ok(len)
+}
+
+/// Appends `value` to `buf`.
+#[cfg(feature = "alloc")]
+pub fn put(buf: &mut Vec<u8>, value: u64) {
+    let mut bytes = [0; MAX_LEN];
+    let len = write(value, &mut bytes).expect("MAX_LEN fits any u64");
+    buf.extend_from_slice(&bytes[..len]);
+}
+
+/// Reads a varint from the front of `data`, advancing past it.
+pub fn read(data: &mut &[u8]) -> Result<u64, FormatError> {
+    let mut result = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let (&byte, rest) = data.split_first().ok_or(FormatError::Incomplete)?;
+        *data = rest;
+
+        if shift >= 64 {
+            return Err(FormatError::VarintOverflow);
+        }
+
+        result |= ((byte & 0x7F) as u64) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+
+        shift += 7;
+    }
+}
@@ -0,0 +1,449 @@
+//! Python bindings for nori-wal.
+//!
+//! Exposes `Wal`, `Record` and record iteration as the `nori_wal` Python
+//! module, over [`nori_wal::blocking::Wal`]: every call runs to completion
+//! on the WAL's private runtime, with the GIL released while it waits.
+//!
+//! ```python
+//! import nori_wal
+//!
+//! with nori_wal.Wal("/var/lib/app/wal") as wal:
+//!     wal.append(nori_wal.Record(b"key", b"value", ttl_ms=60_000))
+//!     wal.sync()
+//!     rows = [r.to_dict() for r in wal.read()]  # e.g. for pandas.DataFrame(rows)
+//! ```
+//!
+//! Build the wheel with `maturin build -m crates/nori-wal-py/Cargo.toml`.
+
+use nori_wal::{blocking, FsyncPolicy, Position, Record, SegmentError, WalConfig};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+create_exception!(
+    nori_wal,
+    WalError,
+    PyException,
+    "An error from the WAL, such as an I/O error or a damaged record."
+);
+
+fn wal_error(e: SegmentError) -> PyErr {
+    WalError::new_err(e.to_string())
+}
+
+fn position_tuple(position: Position) -> (u64, u64) {
+    (position.segment_id, position.offset)
+}
+
+/// A WAL record: a put of a value under a key, or a delete of the key.
+#[pyclass(name = "Record", module = "nori_wal", frozen)]
+#[derive(Clone)]
+struct PyRecord {
+    record: Record,
+    /// Where the record was read from; `None` for one not read from a log.
+    position: Option<Position>,
+}
+
+#[pymethods]
+impl PyRecord {
+    /// A put of `value` under `key`, expiring after `ttl_ms` if given.
+    #[new]
+    #[pyo3(signature = (key, value, *, ttl_ms = None, namespace = None))]
+    fn new(key: &[u8], value: &[u8], ttl_ms: Option<u64>, namespace: Option<u32>) -> Self {
+        let key = key.to_vec();
+        let value = value.to_vec();
+        let mut record = match ttl_ms {
+            Some(ttl) => Record::put_with_ttl(key, value, Duration::from_millis(ttl)),
+            None => Record::put(key, value),
+        };
+        record.namespace = namespace;
+        Self {
+            record,
+            position: None,
+        }
+    }
+
+    /// A delete of `key`.
+    #[staticmethod]
+    #[pyo3(signature = (key, *, namespace = None))]
+    fn delete(key: &[u8], namespace: Option<u32>) -> Self {
+        let mut record = Record::delete(key.to_vec());
+        record.namespace = namespace;
+        Self {
+            record,
+            position: None,
+        }
+    }
+
+    #[getter]
+    fn key<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.record.key)
+    }
+
+    /// Empty for a delete.
+    #[getter]
+    fn value<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.record.value)
+    }
+
+    /// True for a delete.
+    #[getter]
+    fn tombstone(&self) -> bool {
+        self.record.tombstone
+    }
+
+    #[getter]
+    fn lsn(&self) -> Option<u64> {
+        self.record.lsn
+    }
+
+    /// Milliseconds since the Unix epoch.
+    #[getter]
+    fn timestamp_ms(&self) -> Option<u64> {
+        self.record.timestamp.map(|t| {
+            t.duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64)
+        })
+    }
+
+    #[getter]
+    fn ttl_ms(&self) -> Option<u64> {
+        self.record.ttl.map(|ttl| ttl.as_millis() as u64)
+    }
+
+    #[getter]
+    fn namespace(&self) -> Option<u32> {
+        self.record.namespace
+    }
+
+    /// `(segment_id, offset)` of a record read from a log.
+    #[getter]
+    fn position(&self) -> Option<(u64, u64)> {
+        self.position.map(position_tuple)
+    }
+
+    /// The fields as a dict, one row for a DataFrame.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("key", self.key(py))?;
+        dict.set_item("value", self.value(py))?;
+        dict.set_item("tombstone", self.tombstone())?;
+        dict.set_item("lsn", self.lsn())?;
+        dict.set_item("timestamp_ms", self.timestamp_ms())?;
+        dict.set_item("ttl_ms", self.ttl_ms())?;
+        dict.set_item("namespace", self.namespace())?;
+        dict.set_item("position", self.position())?;
+        Ok(dict)
+    }
+
+    fn __repr__(&self) -> String {
+        let key = String::from_utf8_lossy(&self.record.key);
+        match (self.record.tombstone, self.record.lsn) {
+            (true, Some(lsn)) => format!("Record.delete({:?}, lsn={})", key, lsn),
+            (true, None) => format!("Record.delete({:?})", key),
+            (false, lsn) => {
+                let value = String::from_utf8_lossy(&self.record.value);
+                match lsn {
+                    Some(lsn) => format!("Record({:?}, {:?}, lsn={})", key, value, lsn),
+                    None => format!("Record({:?}, {:?})", key, value),
+                }
+            }
+        }
+    }
+}
+
+/// An open WAL directory. Also a context manager that closes it on exit.
+#[pyclass(name = "Wal", module = "nori_wal")]
+struct PyWal {
+    /// `None` once closed. Readers hold their own reference, which keeps
+    /// the WAL's runtime alive for them.
+    wal: Mutex<Option<Arc<blocking::Wal>>>,
+}
+
+impl PyWal {
+    fn wal(&self) -> PyResult<Arc<blocking::Wal>> {
+        self.wal
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| wal_error(SegmentError::Closed))
+    }
+}
+
+#[pymethods]
+impl PyWal {
+    /// Opens the WAL in `dir`, creating it if missing and recovering it if
+    /// it was not closed cleanly. `fsync` is "always", "batch" (at most
+    /// once per `fsync_window_ms`) or "os".
+    #[new]
+    #[pyo3(signature = (dir, *, fsync = "batch", fsync_window_ms = 5, max_segment_size = None))]
+    fn new(
+        py: Python<'_>,
+        dir: PathBuf,
+        fsync: &str,
+        fsync_window_ms: u64,
+        max_segment_size: Option<u64>,
+    ) -> PyResult<Self> {
+        let fsync_policy = match fsync {
+            "always" => FsyncPolicy::Always,
+            "batch" => FsyncPolicy::Batch(Duration::from_millis(fsync_window_ms)),
+            "os" => FsyncPolicy::Os,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "fsync must be \"always\", \"batch\" or \"os\", not {:?}",
+                    other
+                )))
+            }
+        };
+        let defaults = WalConfig::default();
+        let config = WalConfig {
+            dir,
+            fsync_policy,
+            max_segment_size: max_segment_size.unwrap_or(defaults.max_segment_size),
+            ..defaults
+        };
+        let (wal, _) = py
+            .allow_threads(|| blocking::Wal::open(config))
+            .map_err(wal_error)?;
+        Ok(Self {
+            wal: Mutex::new(Some(Arc::new(wal))),
+        })
+    }
+
+    /// Appends a record, returning its `(segment_id, offset)`.
+    fn append(&self, py: Python<'_>, record: &PyRecord) -> PyResult<(u64, u64)> {
+        let wal = self.wal()?;
+        let position = py
+            .allow_threads(|| wal.append(&record.record))
+            .map_err(wal_error)?;
+        Ok(position_tuple(position))
+    }
+
+    /// Appends records as one write, returning their positions.
+    fn append_batch(&self, py: Python<'_>, records: Vec<PyRecord>) -> PyResult<Vec<(u64, u64)>> {
+        let wal = self.wal()?;
+        let records: Vec<_> = records.into_iter().map(|r| r.record).collect();
+        let positions = py
+            .allow_threads(|| wal.append_batch(&records))
+            .map_err(wal_error)?;
+        Ok(positions.into_iter().map(position_tuple).collect())
+    }
+
+    /// Makes every record appended so far durable.
+    fn sync(&self, py: Python<'_>) -> PyResult<()> {
+        let wal = self.wal()?;
+        py.allow_threads(|| wal.sync()).map_err(wal_error)
+    }
+
+    /// Reads records up to the durable end of the log: from `position`, a
+    /// `(segment_id, offset)` record boundary, from the first record with
+    /// an LSN of at least `lsn`, or from the start of the log.
+    #[pyo3(signature = (position = None, *, lsn = None))]
+    fn read(
+        &self,
+        py: Python<'_>,
+        position: Option<(u64, u64)>,
+        lsn: Option<u64>,
+    ) -> PyResult<PyReader> {
+        let wal = self.wal()?;
+        let reader = match (position, lsn) {
+            (Some(_), Some(_)) => {
+                return Err(PyValueError::new_err("pass position or lsn, not both"))
+            }
+            (Some((segment_id, offset)), None) => wal.reader(Position { segment_id, offset }),
+            (None, lsn) => py
+                .allow_threads(|| wal.read_from_lsn(lsn.unwrap_or(0)))
+                .map_err(wal_error)?,
+        };
+        Ok(PyReader {
+            reader: Mutex::new(reader),
+            _wal: wal,
+        })
+    }
+
+    /// `(segment_id, offset)` the next record will be appended at.
+    #[getter]
+    fn current_position(&self, py: Python<'_>) -> PyResult<(u64, u64)> {
+        let wal = self.wal()?;
+        Ok(position_tuple(py.allow_threads(|| wal.current_position())))
+    }
+
+    /// Position up to which appended records are durable.
+    #[getter]
+    fn durable_position(&self, py: Python<'_>) -> PyResult<(u64, u64)> {
+        let wal = self.wal()?;
+        Ok(position_tuple(py.allow_threads(|| wal.durable_position())))
+    }
+
+    /// LSN the next appended record will get.
+    #[getter]
+    fn next_lsn(&self) -> PyResult<u64> {
+        Ok(self.wal()?.next_lsn())
+    }
+
+    /// Syncs and closes the WAL, releasing its directory. Fails while
+    /// readers over it are still alive. Closing twice does nothing.
+    fn close(&self, py: Python<'_>) -> PyResult<()> {
+        let Some(wal) = self.wal.lock().unwrap().take() else {
+            return Ok(());
+        };
+        match Arc::try_unwrap(wal) {
+            Ok(wal) => py.allow_threads(|| wal.close()).map_err(wal_error),
+            Err(wal) => {
+                *self.wal.lock().unwrap() = Some(wal);
+                Err(WalError::new_err("the WAL has readers that are still open"))
+            }
+        }
+    }
+
+    fn __enter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: PyObject,
+        _exc: PyObject,
+        _traceback: PyObject,
+    ) -> PyResult<bool> {
+        self.close(py)?;
+        Ok(false)
+    }
+}
+
+/// Iterator over records, returned by `Wal.read`.
+#[pyclass(name = "Reader", module = "nori_wal")]
+struct PyReader {
+    reader: Mutex<blocking::WalReader>,
+    _wal: Arc<blocking::Wal>,
+}
+
+#[pymethods]
+impl PyReader {
+    /// `(segment_id, offset)` of the next record to be read.
+    #[getter]
+    fn position(&self) -> (u64, u64) {
+        position_tuple(self.reader.lock().unwrap().position())
+    }
+
+    fn __iter__(slf: Py<Self>) -> Py<Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<PyRecord>> {
+        let next = py.allow_threads(|| self.reader.lock().unwrap().next());
+        match next {
+            Some(Ok((record, position))) => Ok(Some(PyRecord {
+                record,
+                position: Some(position),
+            })),
+            Some(Err(e)) => Err(wal_error(e)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "nori_wal")]
+fn nori_wal_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyWal>()?;
+    m.add_class::<PyRecord>()?;
+    m.add_class::<PyReader>()?;
+    m.add("WalError", m.py().get_type::<WalError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    /// Runs `code` with the module imported as `nori_wal` and `dir` set to
+    /// a fresh directory.
+    fn run_python(code: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "nori_wal").unwrap();
+            nori_wal_py(&module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("nori_wal", module).unwrap();
+            globals.set_item("dir", dir.path()).unwrap();
+            let code = CString::new(code).unwrap();
+            if let Err(e) = py.run(&code, Some(&globals), None) {
+                e.display(py);
+                panic!("Python code failed: {}", e);
+            }
+        });
+    }
+
+    #[test]
+    fn test_write_then_read() {
+        run_python(
+            r#"
+with nori_wal.Wal(dir, fsync="always") as wal:
+    start = wal.current_position
+    assert wal.append(nori_wal.Record(b"a", b"1")) == start
+    wal.append_batch([
+        nori_wal.Record(b"b", b"2", ttl_ms=5000, namespace=3),
+        nori_wal.Record.delete(b"a"),
+    ])
+    wal.sync()
+    assert wal.next_lsn == 4
+
+    records = list(wal.read())
+    assert [r.key for r in records] == [b"a", b"b", b"a"]
+    assert [r.lsn for r in records] == [1, 2, 3]
+    assert records[1].ttl_ms == 5000 and records[1].namespace == 3
+    assert records[2].tombstone and records[2].value == b""
+    assert records[0].position == start
+    row = records[1].to_dict()
+    assert row["value"] == b"2" and row["timestamp_ms"] > 0
+    assert repr(records[2]) == 'Record.delete("a", lsn=3)'
+    assert [r.lsn for r in wal.read(lsn=2)] == [2, 3]
+    assert [r.lsn for r in wal.read(records[2].position)] == [3]
+    del records
+
+# Reopened, the log is still there
+wal = nori_wal.Wal(dir)
+reader = wal.read()
+try:
+    wal.close()
+    raise AssertionError("closed with a reader open")
+except nori_wal.WalError:
+    pass
+assert len(list(reader)) == 3
+del reader
+wal.close()
+try:
+    wal.sync()
+    raise AssertionError("synced a closed WAL")
+except nori_wal.WalError:
+    pass
+"#,
+        );
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        run_python(
+            r#"
+try:
+    nori_wal.Wal(dir, fsync="sometimes")
+    raise AssertionError("accepted a bad fsync policy")
+except ValueError:
+    pass
+with nori_wal.Wal(dir) as wal:
+    try:
+        wal.read((0, 0), lsn=1)
+        raise AssertionError("accepted position and lsn")
+    except ValueError:
+        pass
+"#,
+        );
+    }
+}
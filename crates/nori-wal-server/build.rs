@@ -0,0 +1,44 @@
+//! Generates the service and client code from the method list below, which
+//! mirrors `proto/nori_wal.proto`. Only the services are generated; the
+//! messages are hand-written in `src/proto.rs`, so no protoc is needed.
+
+use tonic_build::manual::{Builder, Method, MethodBuilder, Service};
+
+fn method(name: &str, route: &str, input: &str, output: &str) -> MethodBuilder {
+    Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::proto::{}", input))
+        .output_type(format!("crate::proto::{}", output))
+        .codec_path("tonic::codec::ProstCodec")
+}
+
+fn main() {
+    let service = Service::builder()
+        .name("Wal")
+        .package("nori.wal.v1")
+        .method(method("append", "Append", "AppendRequest", "AppendResponse").build())
+        .method(
+            method(
+                "append_stream",
+                "AppendStream",
+                "AppendRequest",
+                "AppendSummary",
+            )
+            .client_streaming()
+            .build(),
+        )
+        .method(method("read", "Read", "ReadRequest", "ReadResponse").build())
+        .method(
+            method("tail", "Tail", "TailRequest", "Entry")
+                .server_streaming()
+                .build(),
+        )
+        .method(method("stats", "Stats", "StatsRequest", "StatsResponse").build())
+        .build();
+
+    Builder::new()
+        .build_server(std::env::var_os("CARGO_FEATURE_SERVER").is_some())
+        .build_client(std::env::var_os("CARGO_FEATURE_CLIENT").is_some())
+        .compile(&[service]);
+}
@@ -0,0 +1,203 @@
+//! A typed client for [`WalService`](crate::WalService).
+
+use crate::error::Error;
+use crate::proto::wal_client::WalClient as RpcClient;
+use crate::proto::{self, AppendRequest, ReadRequest, StatsRequest, TailRequest};
+use nori_wal::{Position, Record};
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::{Channel, Endpoint};
+
+/// Result of [`WalClient::append_stream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendSummary {
+    /// Records appended.
+    pub records: u64,
+    /// Position of the last record appended, if any were.
+    pub last_position: Option<Position>,
+}
+
+/// A page of records returned by [`WalClient::read`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadBatch {
+    /// Records and their positions, in log order.
+    pub entries: Vec<(Record, Position)>,
+    /// Where to continue reading.
+    pub next: Position,
+    /// True if there were no more durable records after `next`.
+    pub end_of_log: bool,
+}
+
+/// Positions and counters returned by [`WalClient::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalStats {
+    /// LSN the next appended record will get.
+    pub next_lsn: u64,
+    /// Current write position.
+    pub current_position: Position,
+    /// Position up to which records are durable.
+    pub durable_position: Position,
+    /// Records appended since the WAL was opened.
+    pub appends: u64,
+    /// Encoded bytes appended since the WAL was opened.
+    pub bytes_appended: u64,
+    /// Fsyncs since the WAL was opened.
+    pub fsyncs: u64,
+    /// Segment rotations since the WAL was opened.
+    pub rotations: u64,
+    /// ID of the segment appends currently go to.
+    pub active_segment_id: u64,
+    /// Key and value bytes appended since the WAL was opened.
+    pub logical_bytes: u64,
+    /// Bytes written to disk since the WAL was opened, framing and
+    /// sidecars included.
+    pub physical_bytes: u64,
+}
+
+/// Records streamed by [`WalClient::tail`].
+pub type TailStream = Pin<Box<dyn Stream<Item = Result<(Record, Position), Error>> + Send>>;
+
+/// Client for a remote WAL.
+///
+/// Cloning is cheap and clones share the connection, so one client can be
+/// used from many tasks.
+#[derive(Debug, Clone)]
+pub struct WalClient {
+    inner: RpcClient<Channel>,
+}
+
+impl WalClient {
+    /// Connects to the service at `endpoint`, such as `http://10.0.0.1:7500`.
+    pub async fn connect(endpoint: impl Into<String>) -> Result<Self, Error> {
+        let channel = Endpoint::from_shared(endpoint.into())?.connect().await?;
+        Ok(Self::from_channel(channel))
+    }
+
+    /// Creates a client over an existing channel.
+    pub fn from_channel(channel: Channel) -> Self {
+        Self {
+            inner: RpcClient::new(channel),
+        }
+    }
+
+    /// Appends `records` as one batch, returning their positions. With
+    /// `sync` the server fsyncs before answering.
+    pub async fn append(&self, records: &[Record], sync: bool) -> Result<Vec<Position>, Error> {
+        let request = AppendRequest {
+            records: records.iter().map(Into::into).collect(),
+            sync,
+        };
+        let response = self.inner.clone().append(request).await?.into_inner();
+        Ok(response.positions.into_iter().map(Into::into).collect())
+    }
+
+    /// Appends each batch from `batches` in order over one call. With `sync`
+    /// the server fsyncs once, after the last batch.
+    pub async fn append_stream<S>(&self, batches: S, sync: bool) -> Result<AppendSummary, Error>
+    where
+        S: Stream<Item = Vec<Record>> + Send + 'static,
+    {
+        let requests = batches.map(move |batch| AppendRequest {
+            records: batch.iter().map(Into::into).collect(),
+            sync,
+        });
+        let summary = self
+            .inner
+            .clone()
+            .append_stream(requests)
+            .await?
+            .into_inner();
+        Ok(AppendSummary {
+            records: summary.records,
+            last_position: summary.last_position.map(Into::into),
+        })
+    }
+
+    /// Reads up to `max_records` durable records from `start`. The server
+    /// may return fewer; 0 asks for as many as it allows.
+    pub async fn read(&self, start: Position, max_records: u32) -> Result<ReadBatch, Error> {
+        self.read_with(ReadRequest {
+            start: Some(start.into()),
+            from_lsn: None,
+            max_records,
+        })
+        .await
+    }
+
+    /// Like [`WalClient::read`], starting at the first record whose LSN is at
+    /// least `lsn`.
+    pub async fn read_from_lsn(&self, lsn: u64, max_records: u32) -> Result<ReadBatch, Error> {
+        self.read_with(ReadRequest {
+            start: None,
+            from_lsn: Some(lsn),
+            max_records,
+        })
+        .await
+    }
+
+    async fn read_with(&self, request: ReadRequest) -> Result<ReadBatch, Error> {
+        let response = self.inner.clone().read(request).await?.into_inner();
+        Ok(ReadBatch {
+            entries: response
+                .entries
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            next: required(response.next)?,
+            end_of_log: response.end_of_log,
+        })
+    }
+
+    /// Streams the durable records from `start`, then new ones as they become
+    /// durable. Drop the stream to stop.
+    pub async fn tail(&self, start: Position) -> Result<TailStream, Error> {
+        self.tail_with(TailRequest {
+            start: Some(start.into()),
+            from_lsn: None,
+        })
+        .await
+    }
+
+    /// Like [`WalClient::tail`], starting at the first record whose LSN is at
+    /// least `lsn`.
+    pub async fn tail_from_lsn(&self, lsn: u64) -> Result<TailStream, Error> {
+        self.tail_with(TailRequest {
+            start: None,
+            from_lsn: Some(lsn),
+        })
+        .await
+    }
+
+    async fn tail_with(&self, request: TailRequest) -> Result<TailStream, Error> {
+        let stream = self.inner.clone().tail(request).await?.into_inner();
+        Ok(Box::pin(stream.map(|next| next?.try_into())))
+    }
+
+    /// Returns the log's positions and counters.
+    pub async fn stats(&self) -> Result<WalStats, Error> {
+        let stats = self
+            .inner
+            .clone()
+            .stats(StatsRequest {})
+            .await?
+            .into_inner();
+        Ok(WalStats {
+            next_lsn: stats.next_lsn,
+            current_position: required(stats.current_position)?,
+            durable_position: required(stats.durable_position)?,
+            appends: stats.appends,
+            bytes_appended: stats.bytes_appended,
+            fsyncs: stats.fsyncs,
+            rotations: stats.rotations,
+            active_segment_id: stats.active_segment_id,
+            logical_bytes: stats.logical_bytes,
+            physical_bytes: stats.physical_bytes,
+        })
+    }
+}
+
+fn required(position: Option<proto::Position>) -> Result<Position, Error> {
+    position
+        .map(Into::into)
+        .ok_or_else(|| Error::InvalidMessage("missing position".into()))
+}
@@ -0,0 +1,156 @@
+//! Conversions between nori-wal types and their protobuf messages.
+
+use crate::error::Error;
+use crate::proto;
+use nori_wal::{Compression, Durability, Position, Record};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+impl From<Position> for proto::Position {
+    fn from(position: Position) -> Self {
+        Self {
+            segment_id: position.segment_id,
+            offset: position.offset,
+        }
+    }
+}
+
+impl From<proto::Position> for Position {
+    fn from(position: proto::Position) -> Self {
+        Self {
+            segment_id: position.segment_id,
+            offset: position.offset,
+        }
+    }
+}
+
+impl From<Compression> for proto::Compression {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::None => proto::Compression::None,
+            Compression::Lz4 => proto::Compression::Lz4,
+            Compression::Zstd => proto::Compression::Zstd,
+        }
+    }
+}
+
+impl From<proto::Compression> for Compression {
+    fn from(compression: proto::Compression) -> Self {
+        match compression {
+            proto::Compression::None => Compression::None,
+            proto::Compression::Lz4 => Compression::Lz4,
+            proto::Compression::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+impl From<&Record> for proto::Record {
+    fn from(record: &Record) -> Self {
+        Self {
+            key: record.key.clone(),
+            value: record.value.clone(),
+            tombstone: record.tombstone,
+            ttl_ms: record.ttl.map(|ttl| ttl.as_millis() as u64),
+            compression: proto::Compression::from(record.compression) as i32,
+            lsn: record.lsn,
+            timestamp_ms: record.timestamp.map(unix_millis),
+            namespace: record.namespace,
+        }
+    }
+}
+
+impl TryFrom<proto::Record> for Record {
+    type Error = Error;
+
+    fn try_from(record: proto::Record) -> Result<Self, Error> {
+        let compression = proto::Compression::try_from(record.compression).map_err(|_| {
+            Error::InvalidMessage(format!("unknown compression {}", record.compression))
+        })?;
+        Ok(Record {
+            key: record.key,
+            value: record.value,
+            tombstone: record.tombstone,
+            ttl: record.ttl_ms.map(Duration::from_millis),
+            compression: compression.into(),
+            lsn: record.lsn,
+            timestamp: record
+                .timestamp_ms
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+            namespace: record.namespace,
+            chain: None,
+            trace: None,
+            coalesced: None,
+            durability: Durability::BestEffort,
+        })
+    }
+}
+
+impl From<(Record, Position)> for proto::Entry {
+    fn from((record, position): (Record, Position)) -> Self {
+        Self {
+            record: Some((&record).into()),
+            position: Some(position.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::Entry> for (Record, Position) {
+    type Error = Error;
+
+    fn try_from(entry: proto::Entry) -> Result<Self, Error> {
+        match (entry.record, entry.position) {
+            (Some(record), Some(position)) => Ok((record.try_into()?, position.into())),
+            _ => Err(Error::InvalidMessage(
+                "entry without record or position".into(),
+            )),
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut record = Record::put(b"k".as_slice(), b"v".as_slice());
+        record.ttl = Some(Duration::from_secs(30));
+        record.compression = Compression::Zstd;
+        record.lsn = Some(42);
+        record.timestamp = Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123));
+        record.namespace = Some(3);
+        let position = Position {
+            segment_id: 2,
+            offset: 128,
+        };
+
+        let entry = proto::Entry::from((record.clone(), position));
+        assert_eq!(
+            <(Record, Position)>::try_from(entry).unwrap(),
+            (record, position)
+        );
+
+        let tombstone = Record::delete(b"k".as_slice());
+        assert_eq!(
+            Record::try_from(proto::Record::from(&tombstone)).unwrap(),
+            tombstone
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_compression() {
+        let record = proto::Record {
+            compression: 9,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Record::try_from(record),
+            Err(Error::InvalidMessage(_))
+        ));
+    }
+}
@@ -0,0 +1,30 @@
+//! Errors from the client and from message conversion.
+
+use thiserror::Error;
+
+/// Errors from [`WalClient`](crate::WalClient) and from converting messages.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Transport error: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    #[error("Request failed: {0}")]
+    Status(Box<tonic::Status>),
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error::Status(Box::new(status))
+    }
+}
+
+impl Error {
+    /// Returns the gRPC status code if the server answered with an error.
+    pub fn code(&self) -> Option<tonic::Code> {
+        match self {
+            Error::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! gRPC service and client for using nori-wal as a standalone log.
+//!
+//! [`WalService`] wraps a [`Wal`](nori_wal::Wal) in a gRPC service with five
+//! methods:
+//! - `Append`: appends a batch of records atomically and returns their
+//!   positions, optionally fsyncing first
+//! - `AppendStream`: appends a client stream of batches in order, for bulk
+//!   loads over one call
+//! - `Read`: returns a page of durable records from a position or LSN, with
+//!   the position to continue from
+//! - `Tail`: streams durable records from a position or LSN, then new ones
+//!   as they become durable, until the client goes away
+//! - `Stats`: positions, the next LSN and the WAL's counters
+//!
+//! The schema is in `proto/nori_wal.proto`, for generating clients in other
+//! languages; [`WalClient`] is the Rust one. Both sides are behind features
+//! (`server` and `client`, on by default).
+//!
+//! # Example
+//!
+//! ```no_run
+//! use nori_wal::{Position, Record, Wal, WalConfig};
+//! use nori_wal_server::{WalClient, WalService};
+//! use std::sync::Arc;
+//! use tokio::net::TcpListener;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let listener = TcpListener::bind("0.0.0.0:7500").await?;
+//! tokio::spawn(WalService::new(Arc::new(wal)).serve(listener));
+//!
+//! let client = WalClient::connect("http://127.0.0.1:7500").await?;
+//! client.append(&[Record::put(b"k".as_slice(), b"v".as_slice())], true).await?;
+//! let start = Position { segment_id: 0, offset: 0 };
+//! for (record, position) in client.read(start, 100).await?.entries {
+//!     println!("{}: {:?}", position, record.key);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "client")]
+pub mod client;
+pub mod convert;
+pub mod error;
+pub mod proto;
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(feature = "client")]
+pub use client::{AppendSummary, ReadBatch, TailStream, WalClient, WalStats};
+pub use error::Error;
+#[cfg(feature = "server")]
+pub use server::{ServerConfig, WalService};
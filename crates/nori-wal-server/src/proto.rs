@@ -0,0 +1,129 @@
+//! Messages and generated service code for `proto/nori_wal.proto`.
+//!
+//! The messages are written out by hand, field for field, so that building
+//! the crate does not need protoc; keep them in sync with the `.proto` file.
+
+#![allow(missing_docs)]
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct Position {
+    #[prost(uint64, tag = "1")]
+    pub segment_id: u64,
+    #[prost(uint64, tag = "2")]
+    pub offset: u64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(bytes = "bytes", tag = "1")]
+    pub key: bytes::Bytes,
+    #[prost(bytes = "bytes", tag = "2")]
+    pub value: bytes::Bytes,
+    #[prost(bool, tag = "3")]
+    pub tombstone: bool,
+    #[prost(uint64, optional, tag = "4")]
+    pub ttl_ms: Option<u64>,
+    #[prost(enumeration = "Compression", tag = "5")]
+    pub compression: i32,
+    #[prost(uint64, optional, tag = "6")]
+    pub lsn: Option<u64>,
+    #[prost(uint64, optional, tag = "7")]
+    pub timestamp_ms: Option<u64>,
+    #[prost(uint32, optional, tag = "8")]
+    pub namespace: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Entry {
+    #[prost(message, optional, tag = "1")]
+    pub record: Option<Record>,
+    #[prost(message, optional, tag = "2")]
+    pub position: Option<Position>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AppendRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub records: Vec<Record>,
+    #[prost(bool, tag = "2")]
+    pub sync: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AppendResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub positions: Vec<Position>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AppendSummary {
+    #[prost(uint64, tag = "1")]
+    pub records: u64,
+    #[prost(message, optional, tag = "2")]
+    pub last_position: Option<Position>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadRequest {
+    #[prost(message, optional, tag = "1")]
+    pub start: Option<Position>,
+    #[prost(uint64, optional, tag = "2")]
+    pub from_lsn: Option<u64>,
+    #[prost(uint32, tag = "3")]
+    pub max_records: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<Entry>,
+    #[prost(message, optional, tag = "2")]
+    pub next: Option<Position>,
+    #[prost(bool, tag = "3")]
+    pub end_of_log: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TailRequest {
+    #[prost(message, optional, tag = "1")]
+    pub start: Option<Position>,
+    #[prost(uint64, optional, tag = "2")]
+    pub from_lsn: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct StatsRequest {}
+
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct StatsResponse {
+    #[prost(uint64, tag = "1")]
+    pub next_lsn: u64,
+    #[prost(message, optional, tag = "2")]
+    pub current_position: Option<Position>,
+    #[prost(message, optional, tag = "3")]
+    pub durable_position: Option<Position>,
+    #[prost(uint64, tag = "4")]
+    pub appends: u64,
+    #[prost(uint64, tag = "5")]
+    pub bytes_appended: u64,
+    #[prost(uint64, tag = "6")]
+    pub fsyncs: u64,
+    #[prost(uint64, tag = "7")]
+    pub rotations: u64,
+    #[prost(uint64, tag = "8")]
+    pub active_segment_id: u64,
+    #[prost(uint64, tag = "9")]
+    pub logical_bytes: u64,
+    #[prost(uint64, tag = "10")]
+    pub physical_bytes: u64,
+}
+
+include!(concat!(env!("OUT_DIR"), "/nori.wal.v1.Wal.rs"));
@@ -0,0 +1,335 @@
+//! The gRPC service.
+
+// tonic's handlers return `Status`, so the helpers feeding them do too
+#![allow(clippy::result_large_err)]
+
+use crate::proto::wal_server::{Wal as WalRpc, WalServer};
+use crate::proto::{
+    AppendRequest, AppendResponse, AppendSummary, Entry, ReadRequest, ReadResponse, StatsRequest,
+    StatsResponse, TailRequest,
+};
+use nori_wal::{ErrorClass, Position, Record, SegmentError, Wal, WalReader, WalTail};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+/// Settings for a [`WalService`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Most records a single `Read` returns, whatever the request asks for.
+    pub max_read_records: u32,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_read_records: 1024,
+        }
+    }
+}
+
+/// Serves a [`Wal`] over gRPC.
+///
+/// Appended records get their LSNs and timestamps from the WAL; any set in
+/// a request are ignored. Reads and tails only see durable records, as
+/// with the WAL's own readers.
+#[derive(Clone)]
+pub struct WalService {
+    wal: Arc<Wal>,
+    config: ServerConfig,
+}
+
+impl WalService {
+    /// Creates a service for `wal` with the default configuration.
+    pub fn new(wal: Arc<Wal>) -> Self {
+        Self::with_config(wal, ServerConfig::default())
+    }
+
+    /// Creates a service for `wal` with `config`.
+    pub fn with_config(wal: Arc<Wal>, config: ServerConfig) -> Self {
+        Self { wal, config }
+    }
+
+    /// Returns the service for adding to a `tonic` server alongside others.
+    pub fn into_server(self) -> WalServer<Self> {
+        WalServer::new(self)
+    }
+
+    /// Serves connections from `listener` until an error occurs.
+    pub async fn serve(self, listener: TcpListener) -> Result<(), tonic::transport::Error> {
+        tonic::transport::Server::builder()
+            .add_service(self.into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+    }
+
+    async fn open_reader(
+        &self,
+        start: Option<crate::proto::Position>,
+        from_lsn: Option<u64>,
+    ) -> Result<WalReader, Status> {
+        match start {
+            Some(start) if from_lsn.is_none() => Ok(self.wal.reader(start.into())),
+            _ => self
+                .wal
+                .read_from_lsn(from_lsn.unwrap_or(0))
+                .await
+                .map_err(status),
+        }
+    }
+
+    async fn open_tail(
+        &self,
+        start: Option<crate::proto::Position>,
+        from_lsn: Option<u64>,
+    ) -> Result<WalTail, Status> {
+        match start {
+            Some(start) if from_lsn.is_none() => Ok(self.wal.tail(start.into())),
+            _ => self
+                .wal
+                .tail_from_lsn(from_lsn.unwrap_or(0))
+                .await
+                .map_err(status),
+        }
+    }
+}
+
+/// Converts records from a request, dropping the fields the WAL assigns.
+fn decode_records(records: Vec<crate::proto::Record>) -> Result<Vec<Record>, Status> {
+    records
+        .into_iter()
+        .map(|record| {
+            let mut record =
+                Record::try_from(record).map_err(|e| Status::invalid_argument(e.to_string()))?;
+            record.lsn = None;
+            record.timestamp = None;
+            Ok(record)
+        })
+        .collect()
+}
+
+/// Maps a WAL error to the closest gRPC status.
+fn status(e: SegmentError) -> Status {
+    let message = e.to_string();
+    match e {
+        SegmentError::InvalidConfig(_) | SegmentError::RecordTooLarge { .. } => {
+            Status::invalid_argument(message)
+        }
+        SegmentError::CursorGone(_) | SegmentError::Purged(_) => Status::out_of_range(message),
+        SegmentError::NotFound(_) => Status::not_found(message),
+        SegmentError::Closed => Status::unavailable(message),
+        SegmentError::QuotaExceeded { .. } => Status::resource_exhausted(message),
+        e => match e.class() {
+            ErrorClass::Retriable => Status::unavailable(message),
+            ErrorClass::Corruption => Status::data_loss(message),
+            ErrorClass::Fatal => Status::internal(message),
+        },
+    }
+}
+
+type TailStream = Pin<Box<dyn Stream<Item = Result<Entry, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl WalRpc for WalService {
+    async fn append(
+        &self,
+        request: Request<AppendRequest>,
+    ) -> Result<Response<AppendResponse>, Status> {
+        let request = request.into_inner();
+        let records = decode_records(request.records)?;
+        let positions = self.wal.append_batch(&records).await.map_err(status)?;
+        if request.sync {
+            self.wal.sync().await.map_err(status)?;
+        }
+        Ok(Response::new(AppendResponse {
+            positions: positions.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn append_stream(
+        &self,
+        request: Request<Streaming<AppendRequest>>,
+    ) -> Result<Response<AppendSummary>, Status> {
+        let mut stream = request.into_inner();
+        let mut summary = AppendSummary::default();
+        let mut sync = false;
+        while let Some(request) = stream.message().await? {
+            let records = decode_records(request.records)?;
+            let positions = self.wal.append_batch(&records).await.map_err(status)?;
+            summary.records += positions.len() as u64;
+            if let Some(&last) = positions.last() {
+                summary.last_position = Some(last.into());
+            }
+            sync |= request.sync;
+        }
+        if sync {
+            self.wal.sync().await.map_err(status)?;
+        }
+        Ok(Response::new(summary))
+    }
+
+    async fn read(&self, request: Request<ReadRequest>) -> Result<Response<ReadResponse>, Status> {
+        let request = request.into_inner();
+        let limit = match request.max_records {
+            0 => self.config.max_read_records,
+            n => n.min(self.config.max_read_records),
+        } as usize;
+
+        let mut reader = self.open_reader(request.start, request.from_lsn).await?;
+        let mut entries = Vec::new();
+        let mut end_of_log = false;
+        while entries.len() < limit {
+            match reader.next_record().await.map_err(status)? {
+                Some(entry) => entries.push(entry.into()),
+                None => {
+                    end_of_log = true;
+                    break;
+                }
+            }
+        }
+        Ok(Response::new(ReadResponse {
+            entries,
+            next: Some(reader.position().into()),
+            end_of_log,
+        }))
+    }
+
+    type TailStream = TailStream;
+
+    async fn tail(&self, request: Request<TailRequest>) -> Result<Response<TailStream>, Status> {
+        let request = request.into_inner();
+        let tail = self.open_tail(request.start, request.from_lsn).await?;
+        let stream = tail.map(|next| next.map(Entry::from).map_err(status));
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn stats(&self, _: Request<StatsRequest>) -> Result<Response<StatsResponse>, Status> {
+        let metrics = self.wal.metrics().await;
+        let position = |p: Position| Some(p.into());
+        Ok(Response::new(StatsResponse {
+            next_lsn: self.wal.next_lsn(),
+            current_position: position(self.wal.current_position().await),
+            durable_position: position(self.wal.durable_position().await),
+            appends: metrics.appends,
+            bytes_appended: metrics.bytes_appended,
+            fsyncs: metrics.fsyncs,
+            rotations: metrics.rotations,
+            active_segment_id: metrics.active_segment_id,
+            logical_bytes: metrics.write_efficiency.logical_bytes,
+            physical_bytes: metrics.write_efficiency.physical_bytes,
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    use super::*;
+    use crate::WalClient;
+    use nori_wal::WalConfig;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn wal_config(dir: &TempDir) -> WalConfig {
+        WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    async fn start(wal_config: WalConfig, config: ServerConfig) -> (Arc<Wal>, WalClient) {
+        let wal = Arc::new(Wal::open(wal_config).await.unwrap().0);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(WalService::with_config(wal.clone(), config).serve(listener));
+        let client = WalClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        (wal, client)
+    }
+
+    fn batch(keys: std::ops::Range<u32>) -> Vec<Record> {
+        keys.map(|i| Record::put(i.to_be_bytes().to_vec(), b"v".as_slice()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_append_read_and_stats() {
+        let dir = TempDir::new().unwrap();
+        let config = ServerConfig {
+            max_read_records: 4,
+        };
+        let (wal, client) = start(wal_config(&dir), config).await;
+
+        let positions = client.append(&batch(0..3), true).await.unwrap();
+        assert_eq!(positions.len(), 3);
+        let batches = tokio_stream::iter(vec![batch(3..5), Vec::new(), batch(5..10)]);
+        let summary = client.append_stream(batches, true).await.unwrap();
+        assert_eq!(summary.records, 7);
+        assert_eq!(
+            summary.last_position,
+            Some(wal.last_record().await.unwrap().unwrap().1)
+        );
+
+        // Reads are capped by the server and page through the log
+        let mut start = positions[0];
+        let mut keys = Vec::new();
+        loop {
+            let page = client.read(start, 100).await.unwrap();
+            assert!(page.entries.len() <= 4);
+            keys.extend(page.entries.iter().map(|(record, _)| record.key.clone()));
+            start = page.next;
+            if page.end_of_log {
+                break;
+            }
+        }
+        let expected: Vec<_> = batch(0..10).into_iter().map(|r| r.key).collect();
+        assert_eq!(keys, expected);
+
+        let page = client.read_from_lsn(8, 0).await.unwrap();
+        let lsns: Vec<_> = page.entries.iter().map(|(r, _)| r.lsn.unwrap()).collect();
+        assert_eq!(lsns, vec![8, 9, 10]);
+        assert!(page.end_of_log);
+
+        let stats = client.stats().await.unwrap();
+        assert_eq!(stats.next_lsn, 11);
+        assert_eq!(stats.appends, 10);
+        assert!(stats.physical_bytes > stats.logical_bytes);
+        assert_eq!(stats.durable_position, wal.durable_position().await);
+    }
+
+    #[tokio::test]
+    async fn test_tail_follows_new_appends() {
+        let dir = TempDir::new().unwrap();
+        let (_wal, client) = start(wal_config(&dir), ServerConfig::default()).await;
+        client.append(&batch(0..2), true).await.unwrap();
+
+        let mut tail = client.tail_from_lsn(2).await.unwrap();
+        let (record, _) = tail.next().await.unwrap().unwrap();
+        assert_eq!(record.lsn, Some(2));
+
+        client.append(&batch(2..4), true).await.unwrap();
+        for lsn in 3..=4 {
+            let next = tokio::time::timeout(Duration::from_secs(5), tail.next()).await;
+            let (record, _) = next.unwrap().unwrap().unwrap();
+            assert_eq!(record.lsn, Some(lsn));
+            assert_eq!(record.key, (lsn as u32 - 1).to_be_bytes().to_vec());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_status_codes() {
+        let dir = TempDir::new().unwrap();
+        let wal_config = WalConfig {
+            max_record_size: Some(1024),
+            ..wal_config(&dir)
+        };
+        let (_wal, client) = start(wal_config, ServerConfig::default()).await;
+
+        let huge = Record::put(b"k".as_slice(), vec![0; 2048]);
+        let err = client.append(&[huge], false).await.unwrap_err();
+        assert_eq!(err.code(), Some(tonic::Code::InvalidArgument));
+    }
+}
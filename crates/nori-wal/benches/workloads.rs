@@ -0,0 +1,66 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nori_wal::workload::{self, Workload};
+use nori_wal::{FsyncPolicy, Wal, WalConfig};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Records appended per iteration; each iteration gets a fresh WAL.
+const RECORDS: u64 = 2_000;
+
+fn workloads_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("workloads");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(RECORDS));
+
+    let sizes = [
+        ("fixed_256B", "256"),
+        ("uniform_64B_4KB", "64..4096"),
+        ("mostly_small", "100:9,16384:1"),
+    ];
+    let policies = [
+        ("os", FsyncPolicy::Os),
+        ("batch_5ms", FsyncPolicy::Batch(Duration::from_millis(5))),
+    ];
+    let writers = [1, 4];
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    for (size_name, sizes) in &sizes {
+        for (policy_name, policy) in &policies {
+            for writers in writers {
+                let name = format!("{}_{}_{}w", size_name, policy_name, writers);
+                let workload = Workload {
+                    records: RECORDS,
+                    writers,
+                    sizes: sizes.parse().unwrap(),
+                    ..Workload::default()
+                };
+                group.bench_function(BenchmarkId::new("append", name), |b| {
+                    b.to_async(&runtime).iter_custom(|iters| {
+                        let workload = workload.clone();
+                        async move {
+                            let mut total = Duration::ZERO;
+                            for _ in 0..iters {
+                                let temp_dir = TempDir::new().unwrap();
+                                let config = WalConfig {
+                                    dir: temp_dir.path().to_path_buf(),
+                                    fsync_policy: *policy,
+                                    ..Default::default()
+                                };
+                                let (wal, _) = Wal::open(config).await.unwrap();
+                                let report = workload::run(Arc::new(wal), &workload).await.unwrap();
+                                total += report.elapsed;
+                            }
+                            total
+                        }
+                    });
+                });
+            }
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, workloads_benchmark);
+criterion_main!(benches);
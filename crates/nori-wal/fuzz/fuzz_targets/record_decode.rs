@@ -0,0 +1,36 @@
+//! Feeds arbitrary bytes to `Record::decode` and the header-only peeks.
+//!
+//! Decoding must never panic, and whatever it accepts must be consistent:
+//! the peeks agree with the full decode, the record occupies exactly the
+//! bytes it claims, and re-encoding it decodes to the same record.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nori_wal::Record;
+
+fuzz_target!(|data: &[u8]| {
+    let peeked = Record::peek_header(data);
+    let Ok((record, size)) = Record::decode(data) else {
+        return;
+    };
+
+    assert!(size <= data.len());
+    let (header, peeked_size) = peeked.expect("peek rejected a record decode accepted");
+    assert_eq!(peeked_size, size);
+    assert_eq!(header.key, &record.key[..]);
+    assert_eq!(header.tombstone, record.tombstone);
+    assert_eq!(header.lsn, record.lsn);
+    assert_eq!(header.timestamp, record.timestamp);
+    assert_eq!(header.namespace, record.namespace);
+
+    // The record is self-delimiting: trailing bytes do not change it
+    let (exact, exact_size) =
+        Record::decode(&data[..size]).expect("record depends on bytes past its end");
+    assert_eq!(exact, record);
+    assert_eq!(exact_size, size);
+
+    let (reencoded, _) =
+        Record::decode(&record.encode()).expect("re-encoded record does not decode");
+    assert_eq!(reencoded, record);
+});
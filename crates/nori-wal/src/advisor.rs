@@ -0,0 +1,410 @@
+//! Configuration advice drawn from an observed workload.
+//!
+//! The right batch window, segment size and choice of compression depend on
+//! how large records are, how fast they arrive and how long the disk takes to
+//! fsync. A [`WorkloadObserver`] collects those figures into a
+//! [`WorkloadProfile`], either on a running WAL with
+//! [`Wal::observe_workload`](crate::Wal::observe_workload) or from records
+//! read back from disk, as `nori-wal advise` does. [`WorkloadProfile::advise`]
+//! then turns the profile into settings, with the reasoning behind each.
+//!
+//! The advice applies rules of thumb to a single window. It is a starting
+//! point for tuning, so check it against a benchmark of the real workload
+//! (`nori-wal bench`) before relying on it.
+//!
+//! ```
+//! use nori_wal::advisor::WorkloadObserver;
+//! use nori_wal::Record;
+//! use std::time::Duration;
+//!
+//! let mut observer = WorkloadObserver::new();
+//! for i in 0..1000 {
+//!     let record = Record::put(format!("key{}", i), vec![b'x'; 512]);
+//!     observer.observe(&record, record.encode().len() as u64);
+//!     observer.observe_fsync(Duration::from_millis(2));
+//! }
+//! let advice = observer.finish(Duration::from_secs(1)).advise();
+//! assert_eq!(advice.compression, nori_wal::Compression::Lz4);
+//! ```
+
+use crate::metrics::LatencySummary;
+use crate::record::{Compression, Record};
+use crate::segment::FsyncPolicy;
+use std::time::Duration;
+
+/// Record sizes kept before the observer starts thinning them out.
+const MAX_SIZE_SAMPLES: usize = 1 << 16;
+/// Every this many records, one value is compressed to gauge how well the
+/// workload compresses.
+const COMPRESSION_SAMPLE_EVERY: u64 = 16;
+/// Values shorter than this are left out of the compression sample; LZ4
+/// finds little to work with in them.
+const MIN_SAMPLED_VALUE: usize = 64;
+/// Below this share of the disk's time spent in fsyncs, fsyncing every
+/// append costs little enough to keep.
+const FSYNC_BUSY_LIMIT: f64 = 0.1;
+/// How long a segment should take to fill.
+const SEGMENT_FILL_TIME: Duration = Duration::from_secs(600);
+const MIN_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+const MAX_SEGMENT_SIZE: u64 = 1024 * 1024 * 1024;
+/// Compression is worth its CPU once values shrink to this fraction of
+/// their size.
+const COMPRESSION_WORTHWHILE: f64 = 0.7;
+
+/// Percentiles of record sizes, in encoded bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeSummary {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    /// Largest record, exact.
+    pub max: u64,
+}
+
+/// What a workload looked like over one window.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkloadProfile {
+    /// How long the workload was observed for.
+    pub window: Duration,
+    /// Records appended in the window.
+    pub appends: u64,
+    /// Encoded bytes appended in the window.
+    pub bytes: u64,
+    /// Sizes of the appended records.
+    pub record_size: SizeSummary,
+    /// Size of sampled values after LZ4 compression, as a fraction of their
+    /// raw size. `None` when no value was large enough to sample.
+    pub compression_ratio: Option<f64>,
+    /// Fsync latency. All zero when no fsync was observed.
+    pub fsync_latency: LatencySummary,
+}
+
+/// Settings recommended for a workload, returned by
+/// [`WorkloadProfile::advise`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Advice {
+    /// `None` when no fsync was observed to base a policy on.
+    pub fsync_policy: Option<FsyncPolicy>,
+    pub max_segment_size: u64,
+    /// Compression to give appended records with
+    /// [`Record::with_compression`].
+    pub compression: Compression,
+    /// Why each setting was chosen, one line per setting in the order
+    /// above.
+    pub reasons: Vec<String>,
+}
+
+impl WorkloadProfile {
+    /// Records appended per second over the window.
+    pub fn appends_per_second(&self) -> f64 {
+        per_second(self.appends, self.window)
+    }
+
+    /// Encoded bytes appended per second over the window.
+    pub fn bytes_per_second(&self) -> f64 {
+        per_second(self.bytes, self.window)
+    }
+
+    /// Recommends an fsync policy, a segment size and a compression for
+    /// this workload.
+    pub fn advise(&self) -> Advice {
+        let mut reasons = Vec::new();
+
+        let rate = self.appends_per_second();
+        let fsync_policy = if self.fsync_latency.count == 0 {
+            reasons.push("fsync_policy: no fsyncs were observed, so it is left as is".to_string());
+            None
+        } else {
+            let busy = rate * self.fsync_latency.p50.as_secs_f64();
+            if busy < FSYNC_BUSY_LIMIT {
+                reasons.push(format!(
+                    "fsync_policy: fsyncing each of {:.0} appends/s at a median of {:?} keeps \
+                     the disk busy {:.0}% of the time, so batching gains little",
+                    rate,
+                    self.fsync_latency.p50,
+                    busy * 100.0
+                ));
+                Some(FsyncPolicy::Always)
+            } else {
+                let millis = self.fsync_latency.p90.as_micros().div_ceil(1000);
+                let window = Duration::from_millis(millis.clamp(1, 50) as u64);
+                reasons.push(format!(
+                    "fsync_policy: fsyncing each of {:.0} appends/s at a median of {:?} would \
+                     need {:.0}% of the disk's time; a {:?} window (about the p90 fsync) \
+                     shares each fsync between {:.0} appends",
+                    rate,
+                    self.fsync_latency.p50,
+                    busy * 100.0,
+                    window,
+                    (rate * window.as_secs_f64()).max(1.0)
+                ));
+                Some(FsyncPolicy::Batch(window))
+            }
+        };
+
+        let fill = self.bytes_per_second() * SEGMENT_FILL_TIME.as_secs_f64();
+        let max_segment_size = (fill as u64)
+            .max(self.record_size.max)
+            .next_power_of_two()
+            .clamp(MIN_SEGMENT_SIZE, MAX_SEGMENT_SIZE);
+        reasons.push(format!(
+            "max_segment_size: at {:.0} bytes/s a {} MiB segment fills in about {}, which \
+             keeps rotation rare and purging fine-grained",
+            self.bytes_per_second(),
+            max_segment_size / (1024 * 1024),
+            fill_time(max_segment_size, self.bytes_per_second())
+        ));
+
+        let compression = match self.compression_ratio {
+            Some(ratio) if ratio <= COMPRESSION_WORTHWHILE => {
+                reasons.push(format!(
+                    "compression: sampled values shrink to {:.0}% of their size with LZ4",
+                    ratio * 100.0
+                ));
+                Compression::Lz4
+            }
+            Some(ratio) => {
+                reasons.push(format!(
+                    "compression: sampled values only shrink to {:.0}% of their size with \
+                     LZ4, not worth the CPU",
+                    ratio * 100.0
+                ));
+                Compression::None
+            }
+            None => {
+                reasons.push(format!(
+                    "compression: no values of {} bytes or more to sample",
+                    MIN_SAMPLED_VALUE
+                ));
+                Compression::None
+            }
+        };
+
+        Advice {
+            fsync_policy,
+            max_segment_size,
+            compression,
+            reasons,
+        }
+    }
+}
+
+/// Collects the figures of a [`WorkloadProfile`] one record at a time.
+#[derive(Debug)]
+pub struct WorkloadObserver {
+    appends: u64,
+    bytes: u64,
+    /// Sizes of every `stride`th record.
+    sizes: Vec<u64>,
+    stride: u64,
+    sampled_raw: u64,
+    sampled_compressed: u64,
+    fsyncs: Vec<Duration>,
+}
+
+impl WorkloadObserver {
+    pub fn new() -> Self {
+        Self {
+            appends: 0,
+            bytes: 0,
+            sizes: Vec::new(),
+            stride: 1,
+            sampled_raw: 0,
+            sampled_compressed: 0,
+            fsyncs: Vec::new(),
+        }
+    }
+
+    /// Counts an appended record, `encoded_len` bytes long on disk.
+    ///
+    /// Sizes are kept exactly up to a limit, past which every other one is
+    /// dropped, so a long window costs bounded memory and percentiles stay
+    /// representative.
+    pub fn observe(&mut self, record: &Record, encoded_len: u64) {
+        if self.appends % self.stride == 0 {
+            if self.sizes.len() == MAX_SIZE_SAMPLES {
+                let mut i = 0;
+                self.sizes.retain(|_| {
+                    i += 1;
+                    i % 2 == 1
+                });
+                self.stride *= 2;
+            }
+            if self.appends % self.stride == 0 {
+                self.sizes.push(encoded_len);
+            }
+        }
+        if self.appends % COMPRESSION_SAMPLE_EVERY == 0 && record.value.len() >= MIN_SAMPLED_VALUE {
+            let compressed = lz4::block::compress(&record.value, None, false)
+                .map_or(record.value.len(), |c| c.len());
+            self.sampled_raw += record.value.len() as u64;
+            self.sampled_compressed += compressed as u64;
+        }
+        self.appends += 1;
+        self.bytes += encoded_len;
+    }
+
+    /// Counts an fsync that took `elapsed`.
+    pub fn observe_fsync(&mut self, elapsed: Duration) {
+        self.fsyncs.push(elapsed);
+    }
+
+    /// Summarizes what was observed over `window`.
+    pub fn finish(mut self, window: Duration) -> WorkloadProfile {
+        self.sizes.sort_unstable();
+        self.fsyncs.sort_unstable();
+        let record_size = match self.sizes.last() {
+            Some(&max) => SizeSummary {
+                p50: percentile(&self.sizes, 0.50),
+                p90: percentile(&self.sizes, 0.90),
+                p99: percentile(&self.sizes, 0.99),
+                max,
+            },
+            None => SizeSummary::default(),
+        };
+        let fsync_latency = match self.fsyncs.last() {
+            Some(&max) => LatencySummary {
+                count: self.fsyncs.len() as u64,
+                p50: percentile(&self.fsyncs, 0.50),
+                p90: percentile(&self.fsyncs, 0.90),
+                p99: percentile(&self.fsyncs, 0.99),
+                max,
+            },
+            None => LatencySummary::default(),
+        };
+        WorkloadProfile {
+            window,
+            appends: self.appends,
+            bytes: self.bytes,
+            record_size,
+            compression_ratio: (self.sampled_raw > 0)
+                .then(|| self.sampled_compressed as f64 / self.sampled_raw as f64),
+            fsync_latency,
+        }
+    }
+}
+
+impl Default for WorkloadObserver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Nearest-rank percentile of a sorted, non-empty slice.
+fn percentile<T: Copy>(sorted: &[T], q: f64) -> T {
+    let rank = ((q * sorted.len() as f64).ceil() as usize).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+fn per_second(n: u64, window: Duration) -> f64 {
+    if window.is_zero() {
+        return 0.0;
+    }
+    n as f64 / window.as_secs_f64()
+}
+
+/// How long filling `bytes` takes at `rate` bytes per second, roughly.
+fn fill_time(bytes: u64, rate: f64) -> String {
+    if rate <= 0.0 {
+        return "never".to_string();
+    }
+    let secs = bytes as f64 / rate;
+    if secs < 120.0 {
+        format!("{:.0}s", secs)
+    } else if secs < 7200.0 {
+        format!("{:.0}m", secs / 60.0)
+    } else {
+        format!("{:.0}h", secs / 3600.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observe(records: u64, value: impl Fn(u64) -> Vec<u8>, fsync: Duration) -> WorkloadObserver {
+        let mut observer = WorkloadObserver::new();
+        for i in 0..records {
+            let record = Record::put(format!("key{}", i), value(i));
+            observer.observe(&record, record.encode().len() as u64);
+            observer.observe_fsync(fsync);
+        }
+        observer
+    }
+
+    #[test]
+    fn test_busy_disks_get_a_batch_window() {
+        // 10,000 appends/s at 2ms per fsync would need the disk 20 times over
+        let profile = observe(10_000, |_| vec![b'x'; 100], Duration::from_millis(2))
+            .finish(Duration::from_secs(1));
+        assert_eq!(profile.appends_per_second(), 10_000.0);
+        let advice = profile.advise();
+        assert_eq!(
+            advice.fsync_policy,
+            Some(FsyncPolicy::Batch(Duration::from_millis(2)))
+        );
+        assert_eq!(advice.compression, Compression::Lz4);
+        assert_eq!(advice.reasons.len(), 3);
+
+        // 10 appends/s keep it busy 2% of the time
+        let advice = observe(10, |_| vec![b'x'; 100], Duration::from_millis(2))
+            .finish(Duration::from_secs(1))
+            .advise();
+        assert_eq!(advice.fsync_policy, Some(FsyncPolicy::Always));
+        assert_eq!(advice.max_segment_size, MIN_SEGMENT_SIZE);
+    }
+
+    #[test]
+    fn test_segment_size_follows_the_write_rate() {
+        // About 1 MiB/s fills 600 MiB in ten minutes, rounded up to 1 GiB
+        let value = vec![0u8; 1024 * 1024];
+        let mut observer = WorkloadObserver::new();
+        let record = Record::put("k", value);
+        let len = record.encode().len() as u64;
+        for _ in 0..60 {
+            observer.observe(&record, len);
+        }
+        let profile = observer.finish(Duration::from_secs(60));
+        assert_eq!(profile.record_size.max, len);
+        let advice = profile.advise();
+        assert_eq!(advice.max_segment_size, MAX_SEGMENT_SIZE);
+        assert_eq!(advice.fsync_policy, None);
+    }
+
+    #[test]
+    fn test_incompressible_values_stay_uncompressed() {
+        // A xorshift stream gives LZ4 nothing to work with
+        let random = |seed: u64| {
+            let mut x = seed + 1;
+            (0..512)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    x as u8
+                })
+                .collect::<Vec<u8>>()
+        };
+        let profile = observe(100, random, Duration::ZERO).finish(Duration::from_secs(1));
+        assert!(profile.compression_ratio.unwrap() > COMPRESSION_WORTHWHILE);
+        assert_eq!(profile.advise().compression, Compression::None);
+
+        let profile = observe(100, |_| vec![1], Duration::ZERO).finish(Duration::from_secs(1));
+        assert_eq!(profile.compression_ratio, None);
+        assert_eq!(profile.advise().compression, Compression::None);
+    }
+
+    #[test]
+    fn test_size_samples_are_bounded() {
+        let mut observer = WorkloadObserver::new();
+        let record = Record::put("k", "v");
+        for i in 0..(3 * MAX_SIZE_SAMPLES as u64) {
+            observer.observe(&record, i % 100);
+        }
+        assert!(observer.sizes.len() <= MAX_SIZE_SAMPLES);
+        let profile = observer.finish(Duration::from_secs(1));
+        assert_eq!(profile.appends, 3 * MAX_SIZE_SAMPLES as u64);
+        let p50 = profile.record_size.p50;
+        assert!((45..=55).contains(&p50), "p50 {}", p50);
+    }
+}
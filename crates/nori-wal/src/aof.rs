@@ -0,0 +1,654 @@
+//! Seeding a WAL from a Redis append-only file.
+//!
+//! An AOF is the list of write commands a Redis server has run, in the
+//! RESP protocol. [`load`] replays the string commands in memory and
+//! returns the dataset they leave as put records, with what is left of each
+//! key's TTL; [`import`] appends those records to a WAL:
+//!
+//! ```no_run
+//! use nori_wal::{AofImportConfig, Wal, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let summary = nori_wal::aof::import(&wal, "/var/lib/redis/appendonlydir", AofImportConfig::default()).await?;
+//! println!("imported {} keys from {} commands", summary.records, summary.commands);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The commands understood are `SET` (with `EX`, `PX`, `EXAT`, `PXAT` and
+//! `KEEPTTL`), `SETEX`, `PSETEX`, `SETNX`, `MSET`, `MSETNX`, `GETSET`,
+//! `GETDEL`, `APPEND`, `INCR`, `DECR`, `INCRBY`, `DECRBY`, `DEL`, `UNLINK`,
+//! `EXPIRE`, `PEXPIRE`, `EXPIREAT`, `PEXPIREAT`, `PERSIST`, `SELECT`,
+//! `FLUSHDB` and `FLUSHALL`; `MULTI` and `EXEC` are passed over. Any other
+//! command, such as one on a hash or a list, fails the load unless
+//! [`AofImportConfig::skip_unsupported`] is set.
+//!
+//! Each record is timestamped with the time of the load. A database other
+//! than 0 becomes the record's namespace. Redis writes expiries to the AOF
+//! as absolute times, which are kept; a relative one (`EXPIRE`, `SETEX`)
+//! counts from the time of the load. Keys that have expired by then are
+//! left out.
+//!
+//! `path` is either a single AOF file or a Redis 7 `appendonlydir`, whose
+//! manifest names a base file and the increments after it. A base in RDB
+//! format, or an AOF with an RDB preamble, is not supported: rewrite it
+//! with `aof-use-rdb-preamble no` first. A command cut short at the end of
+//! the last file, as a crash leaves it, ends the load like Redis's
+//! `aof-load-truncated`.
+
+use crate::import::IterStream;
+use crate::record::Record;
+use crate::segment::SegmentError;
+use crate::wal::Wal;
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Settings for [`load`] and [`import`].
+#[derive(Debug, Clone, Default)]
+pub struct AofImportConfig {
+    /// Pass over commands outside the supported subset instead of failing
+    /// (default: false).
+    pub skip_unsupported: bool,
+}
+
+/// Outcome of a [`load`] or [`import`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AofImportSummary {
+    /// AOF files read.
+    pub files: u64,
+    /// Commands read.
+    pub commands: u64,
+    /// Records produced, one per live key.
+    pub records: u64,
+    /// Keys left out because they had expired.
+    pub expired: u64,
+    /// Commands passed over, by name, when skipping unsupported ones.
+    pub skipped: BTreeMap<String, u64>,
+    /// True if the last file ended in the middle of a command.
+    pub truncated: bool,
+}
+
+/// Reads the commands of an AOF, each as its arguments.
+///
+/// An iterator of commands; stops at the end of the input or a command cut
+/// short by it, and yields an error, then nothing, at malformed input.
+pub struct AofReader<R> {
+    input: R,
+    offset: u64,
+    truncated: bool,
+    done: bool,
+}
+
+impl<R: BufRead> AofReader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            input,
+            offset: 0,
+            truncated: false,
+            done: false,
+        }
+    }
+
+    /// Returns true if reading stopped at a command cut short by the end
+    /// of the input.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Reads one CRLF-terminated line, without the CRLF. `None` at the end
+    /// of the input, or if it ends mid-line.
+    fn read_line(&mut self) -> Result<Option<Vec<u8>>, SegmentError> {
+        let mut line = Vec::new();
+        let read = self.input.read_until(b'\n', &mut line)?;
+        self.offset += read as u64;
+        if read == 0 {
+            return Ok(None);
+        }
+        if !line.ends_with(b"\r\n") {
+            self.truncated = true;
+            return Ok(None);
+        }
+        line.truncate(line.len() - 2);
+        Ok(Some(line))
+    }
+
+    /// Reads a `<prefix><number>` line.
+    fn read_length(&mut self, prefix: u8) -> Result<Option<usize>, SegmentError> {
+        let Some(line) = self.read_line()? else {
+            self.truncated = true;
+            return Ok(None);
+        };
+        let length = line
+            .strip_prefix(&[prefix])
+            .and_then(|n| std::str::from_utf8(n).ok())
+            .and_then(|n| n.parse().ok());
+        match length {
+            Some(length) => Ok(Some(length)),
+            None => Err(self.malformed(&line)),
+        }
+    }
+
+    fn read_command(&mut self) -> Result<Option<Vec<Bytes>>, SegmentError> {
+        if self.offset == 0 && self.input.fill_buf()?.starts_with(b"REDIS") {
+            return Err(foreign("the AOF starts with an RDB preamble"));
+        }
+        let header = loop {
+            let Some(line) = self.read_line()? else {
+                return Ok(None);
+            };
+            // `#TS:` timestamp annotations
+            if !line.starts_with(b"#") {
+                break line;
+            }
+        };
+        let count = header
+            .strip_prefix(b"*")
+            .and_then(|n| std::str::from_utf8(n).ok())
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| self.malformed(&header))?;
+
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            let Some(length) = self.read_length(b'$')? else {
+                return Ok(None);
+            };
+            let mut arg = vec![0; length + 2];
+            match self.input.read_exact(&mut arg) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    self.truncated = true;
+                    return Ok(None);
+                }
+                result => result?,
+            }
+            self.offset += arg.len() as u64;
+            if !arg.ends_with(b"\r\n") {
+                return Err(foreign(format!(
+                    "argument not ended by CRLF before offset {}",
+                    self.offset
+                )));
+            }
+            arg.truncate(length);
+            args.push(Bytes::from(arg));
+        }
+        Ok(Some(args))
+    }
+
+    fn malformed(&mut self, line: &[u8]) -> SegmentError {
+        self.done = true;
+        foreign(format!(
+            "unexpected {:?} before offset {}",
+            String::from_utf8_lossy(line),
+            self.offset
+        ))
+    }
+}
+
+impl<R: BufRead> Iterator for AofReader<R> {
+    type Item = Result<Vec<Bytes>, SegmentError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_command().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.done = true;
+        }
+        result
+    }
+}
+
+impl<R> std::fmt::Debug for AofReader<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AofReader")
+            .field("offset", &self.offset)
+            .field("truncated", &self.truncated)
+            .finish()
+    }
+}
+
+/// A string key's value and expiry.
+struct Entry {
+    value: Bytes,
+    expires_at: Option<SystemTime>,
+}
+
+/// The keyspace the commands build, by database and key.
+struct Dataset {
+    now: SystemTime,
+    db: u32,
+    keys: BTreeMap<(u32, Bytes), Entry>,
+}
+
+impl Dataset {
+    fn new(now: SystemTime) -> Self {
+        Self {
+            now,
+            db: 0,
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// Applies a command, returning false if it is not supported.
+    fn apply(&mut self, args: &[Bytes]) -> Result<bool, SegmentError> {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let args = &args[1..];
+        match (name.as_str(), args) {
+            ("SET", [key, value, options @ ..]) => {
+                let expires_at = self.set_options(options)?;
+                let expires_at = match expires_at {
+                    Expiry::Keep => self.entry(key).and_then(|e| e.expires_at),
+                    Expiry::At(at) => Some(at),
+                    Expiry::Never => None,
+                };
+                self.set(key, value.clone(), expires_at);
+            }
+            ("SETEX", [key, seconds, value]) => {
+                let at = self.now + Duration::from_secs(number(seconds)?);
+                self.set(key, value.clone(), Some(at));
+            }
+            ("PSETEX", [key, millis, value]) => {
+                let at = self.now + Duration::from_millis(number(millis)?);
+                self.set(key, value.clone(), Some(at));
+            }
+            ("SETNX" | "GETSET", [key, value]) => self.set(key, value.clone(), None),
+            ("MSET" | "MSETNX", pairs) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+                for pair in pairs.chunks(2) {
+                    self.set(&pair[0], pair[1].clone(), None);
+                }
+            }
+            ("APPEND", [key, suffix]) => {
+                let (value, expires_at) = match self.entry(key) {
+                    Some(entry) => ([&entry.value[..], suffix].concat(), entry.expires_at),
+                    None => (suffix.to_vec(), None),
+                };
+                self.set(key, value.into(), expires_at);
+            }
+            ("INCR" | "DECR" | "INCRBY" | "DECRBY", [key, by @ ..]) if by.len() <= 1 => {
+                let by = match by.first() {
+                    Some(by) => signed(by)?,
+                    None => 1,
+                };
+                let by = if name.starts_with("DECR") { -by } else { by };
+                let (current, expires_at) = match self.entry(key) {
+                    Some(entry) => (signed(&entry.value)?, entry.expires_at),
+                    None => (0, None),
+                };
+                let value = current.wrapping_add(by).to_string();
+                self.set(key, value.into(), expires_at);
+            }
+            ("DEL" | "UNLINK" | "GETDEL", keys) if !keys.is_empty() => {
+                for key in keys {
+                    self.keys.remove(&(self.db, key.clone()));
+                }
+            }
+            ("EXPIRE", [key, seconds, ..]) => {
+                self.expire(key, self.now + Duration::from_secs(number(seconds)?))
+            }
+            ("PEXPIRE", [key, millis, ..]) => {
+                self.expire(key, self.now + Duration::from_millis(number(millis)?))
+            }
+            ("EXPIREAT", [key, seconds, ..]) => {
+                self.expire(key, UNIX_EPOCH + Duration::from_secs(number(seconds)?))
+            }
+            ("PEXPIREAT", [key, millis, ..]) => {
+                self.expire(key, UNIX_EPOCH + Duration::from_millis(number(millis)?))
+            }
+            ("PERSIST", [key]) => {
+                if let Some(entry) = self.keys.get_mut(&(self.db, key.clone())) {
+                    entry.expires_at = None;
+                }
+            }
+            ("SELECT", [db]) => self.db = number(db)? as u32,
+            ("FLUSHDB", _) => {
+                let db = self.db;
+                self.keys.retain(|(key_db, _), _| *key_db != db);
+            }
+            ("FLUSHALL", _) => self.keys.clear(),
+            ("MULTI" | "EXEC", []) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Parses the options after `SET key value`.
+    fn set_options(&self, options: &[Bytes]) -> Result<Expiry, SegmentError> {
+        let mut expiry = Expiry::Never;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let option = String::from_utf8_lossy(option).to_ascii_uppercase();
+            let mut amount = || -> Result<u64, SegmentError> {
+                number(
+                    options
+                        .next()
+                        .ok_or_else(|| foreign(format!("SET {} without a value", option)))?,
+                )
+            };
+            expiry = match option.as_str() {
+                "EX" => Expiry::At(self.now + Duration::from_secs(amount()?)),
+                "PX" => Expiry::At(self.now + Duration::from_millis(amount()?)),
+                "EXAT" => Expiry::At(UNIX_EPOCH + Duration::from_secs(amount()?)),
+                "PXAT" => Expiry::At(UNIX_EPOCH + Duration::from_millis(amount()?)),
+                "KEEPTTL" => Expiry::Keep,
+                "NX" | "XX" | "GET" => continue,
+                _ => return Err(foreign(format!("unknown SET option {}", option))),
+            };
+        }
+        Ok(expiry)
+    }
+
+    fn entry(&self, key: &Bytes) -> Option<&Entry> {
+        self.keys.get(&(self.db, key.clone()))
+    }
+
+    fn set(&mut self, key: &Bytes, value: Bytes, expires_at: Option<SystemTime>) {
+        self.keys
+            .insert((self.db, key.clone()), Entry { value, expires_at });
+    }
+
+    fn expire(&mut self, key: &Bytes, at: SystemTime) {
+        if let Some(entry) = self.keys.get_mut(&(self.db, key.clone())) {
+            entry.expires_at = Some(at);
+        }
+    }
+
+    /// Returns a put for every live key, and how many had expired.
+    fn into_records(self) -> (Vec<Record>, u64) {
+        let mut records = Vec::with_capacity(self.keys.len());
+        let mut expired = 0;
+        for ((db, key), entry) in self.keys {
+            let mut record = match entry.expires_at {
+                Some(at) => match at.duration_since(self.now) {
+                    Ok(ttl) if !ttl.is_zero() => Record::put_with_ttl(key, entry.value, ttl),
+                    _ => {
+                        expired += 1;
+                        continue;
+                    }
+                },
+                None => Record::put(key, entry.value),
+            };
+            record.namespace = (db != 0).then_some(db);
+            records.push(record.with_timestamp(self.now));
+        }
+        (records, expired)
+    }
+}
+
+/// The expiry a `SET` leaves.
+enum Expiry {
+    Never,
+    Keep,
+    At(SystemTime),
+}
+
+/// Returns the AOF files at `path`, in the order to replay them: the file
+/// itself, or the base and increments a Redis 7 manifest lists.
+pub fn aof_files(path: &Path) -> Result<Vec<PathBuf>, SegmentError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let manifest = std::fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .find(|p| p.extension().is_some_and(|e| e == "manifest"))
+        .ok_or_else(|| foreign(format!("no AOF manifest in {}", path.display())))?;
+
+    // Lines of `key value` pairs: `file <name> seq <n> type <b|h|i>`
+    let mut base = None;
+    let mut increments = Vec::new();
+    for line in std::fs::read_to_string(&manifest)?.lines() {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        let field = |name| {
+            fields
+                .chunks(2)
+                .find(|pair| pair[0] == name)
+                .and_then(|pair| pair.get(1).copied())
+        };
+        let (Some(file), Some(kind)) = (field("file"), field("type")) else {
+            continue;
+        };
+        let seq: u64 = field("seq").and_then(|s| s.parse().ok()).unwrap_or(0);
+        match kind {
+            "b" if file.ends_with(".rdb") => {
+                return Err(foreign(format!(
+                    "base file {} is an RDB file, which is not supported",
+                    file
+                )))
+            }
+            "b" => base = Some(path.join(file)),
+            "i" => increments.push((seq, path.join(file))),
+            _ => {}
+        }
+    }
+    increments.sort();
+    Ok(base
+        .into_iter()
+        .chain(increments.into_iter().map(|(_, file)| file))
+        .collect())
+}
+
+/// Replays the AOF at `path` and returns a put record for every live key,
+/// sorted by namespace and key.
+pub fn load(
+    path: impl AsRef<Path>,
+    config: &AofImportConfig,
+) -> Result<(Vec<Record>, AofImportSummary), SegmentError> {
+    let mut dataset = Dataset::new(SystemTime::now());
+    let mut summary = AofImportSummary::default();
+    for file in aof_files(path.as_ref())? {
+        let mut reader = AofReader::new(BufReader::new(File::open(&file)?));
+        for command in reader.by_ref() {
+            let command = command?;
+            summary.commands += 1;
+            if command.is_empty() {
+                continue;
+            }
+            if !dataset.apply(&command)? {
+                let name = String::from_utf8_lossy(&command[0]).to_ascii_uppercase();
+                if !config.skip_unsupported {
+                    return Err(foreign(format!(
+                        "unsupported command {} in {}",
+                        name,
+                        file.display()
+                    )));
+                }
+                *summary.skipped.entry(name).or_default() += 1;
+            }
+        }
+        summary.files += 1;
+        summary.truncated = reader.is_truncated();
+    }
+    let (records, expired) = dataset.into_records();
+    summary.records = records.len() as u64;
+    summary.expired = expired;
+    Ok((records, summary))
+}
+
+/// Replays the AOF at `path` and appends a put for every live key to `wal`,
+/// fsyncing once at the end.
+pub async fn import(
+    wal: &Wal,
+    path: impl AsRef<Path>,
+    config: AofImportConfig,
+) -> Result<AofImportSummary, SegmentError> {
+    let (records, summary) = load(path, &config)?;
+    wal.import(IterStream(records.into_iter())).await?;
+    Ok(summary)
+}
+
+fn number(arg: &Bytes) -> Result<u64, SegmentError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| {
+            foreign(format!(
+                "expected a number, got {:?}",
+                String::from_utf8_lossy(arg)
+            ))
+        })
+}
+
+fn signed(arg: &Bytes) -> Result<i64, SegmentError> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| {
+            foreign(format!(
+                "expected an integer, got {:?}",
+                String::from_utf8_lossy(arg)
+            ))
+        })
+}
+
+fn foreign(message: impl Into<String>) -> SegmentError {
+    SegmentError::ForeignLog(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalConfig;
+    use futures::StreamExt;
+    use tempfile::TempDir;
+
+    /// Encodes commands as RESP arrays, the way Redis writes them.
+    fn aof(commands: &[&[&str]]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for command in commands {
+            out.extend_from_slice(format!("*{}\r\n", command.len()).as_bytes());
+            for arg in *command {
+                out.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+            }
+        }
+        out
+    }
+
+    fn get<'a>(records: &'a [Record], key: &str) -> Option<&'a Record> {
+        records.iter().find(|r| r.key == key.as_bytes())
+    }
+
+    #[test]
+    fn test_replays_strings_and_expiries() {
+        let dir = TempDir::new().unwrap();
+        let far = (SystemTime::now() + Duration::from_secs(3_600))
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            .to_string();
+        let mut data = b"#TS:1700000000\r\n".to_vec();
+        data.extend(aof(&[
+            &["SELECT", "0"],
+            &["set", "plain", "1"],
+            &["SET", "session", "abc", "PXAT", &far],
+            &["SET", "gone", "x", "PXAT", "1000"],
+            &["MULTI"],
+            &["INCRBY", "plain", "41"],
+            &["APPEND", "session", "def"],
+            &["EXEC"],
+            &["MSET", "a", "1", "b", "2"],
+            &["DEL", "a"],
+            &["SET", "ttl", "v", "EX", "100"],
+            &["SET", "ttl", "w", "KEEPTTL"],
+            &["SET", "cleared", "v", "EX", "100"],
+            &["PERSIST", "cleared"],
+            &["SELECT", "2"],
+            &["SET", "plain", "other db"],
+            &["HSET", "hash", "field", "value"],
+        ]));
+        // Cut short by a crash
+        data.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nz");
+        let path = dir.path().join("appendonly.aof");
+        std::fs::write(&path, &data).unwrap();
+
+        let strict = load(&path, &AofImportConfig::default());
+        assert!(matches!(strict, Err(SegmentError::ForeignLog(_))));
+
+        let config = AofImportConfig {
+            skip_unsupported: true,
+        };
+        let (records, summary) = load(&path, &config).unwrap();
+        assert_eq!(summary.commands, 17);
+        assert_eq!(summary.skipped, [("HSET".to_string(), 1)].into());
+        assert_eq!((summary.records, summary.expired), (6, 1));
+        assert!(summary.truncated);
+
+        let plain = get(&records, "plain").unwrap();
+        assert_eq!(plain.value.as_ref(), b"42");
+        assert_eq!(plain.namespace, None);
+        assert!(plain.ttl.is_none() && plain.timestamp.is_some());
+        let session = get(&records, "session").unwrap();
+        assert_eq!(session.value.as_ref(), b"abcdef");
+        let ttl = session.ttl.unwrap();
+        assert!(ttl > Duration::from_secs(3_500) && ttl <= Duration::from_secs(3_600));
+        assert!(get(&records, "gone").is_none());
+        assert!(get(&records, "a").is_none());
+        assert_eq!(get(&records, "b").unwrap().value.as_ref(), b"2");
+        assert!(get(&records, "ttl").unwrap().ttl.is_some());
+        assert!(get(&records, "cleared").unwrap().ttl.is_none());
+        let other = records.last().unwrap();
+        assert_eq!(
+            (other.namespace, other.value.as_ref()),
+            (Some(2), b"other db".as_slice())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_imports_multi_part_aof() {
+        let redis = TempDir::new().unwrap();
+        let aof_dir = redis.path().join("appendonlydir");
+        std::fs::create_dir(&aof_dir).unwrap();
+        std::fs::write(
+            aof_dir.join("appendonly.aof.manifest"),
+            "file appendonly.aof.2.base.aof seq 2 type b\n\
+             file appendonly.aof.2.incr.aof seq 2 type i\n\
+             file appendonly.aof.1.incr.aof seq 1 type h\n\
+             file appendonly.aof.3.incr.aof seq 3 type i\n",
+        )
+        .unwrap();
+        std::fs::write(
+            aof_dir.join("appendonly.aof.2.base.aof"),
+            aof(&[&["SET", "k", "base"]]),
+        )
+        .unwrap();
+        std::fs::write(
+            aof_dir.join("appendonly.aof.2.incr.aof"),
+            aof(&[&["SET", "k", "two"]]),
+        )
+        .unwrap();
+        std::fs::write(
+            aof_dir.join("appendonly.aof.3.incr.aof"),
+            aof(&[&["SET", "k", "three"]]),
+        )
+        .unwrap();
+        std::fs::write(aof_dir.join("appendonly.aof.1.incr.aof"), b"not read").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::open(WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let start = wal.current_position().await;
+        let summary = import(&wal, &aof_dir, AofImportConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            (summary.files, summary.commands, summary.records),
+            (3, 3, 1)
+        );
+
+        let records: Vec<_> = wal.reader(start).map(|r| r.unwrap().0).collect().await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value.as_ref(), b"three");
+
+        // RDB preambles are refused
+        let rdb = redis.path().join("dump.aof");
+        std::fs::write(&rdb, b"REDIS0011\xfa\x09redis-ver").unwrap();
+        assert!(load(&rdb, &AofImportConfig::default()).is_err());
+    }
+}
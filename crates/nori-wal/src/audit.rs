@@ -0,0 +1,249 @@
+//! Audit trail of administrative operations.
+//!
+//! Operations that remove records or change how the log is kept (purging
+//! segments, truncating the tail, compaction, offline repair, migration to
+//! another directory, changing settings at runtime) each add a line to
+//! `audit.log` in the WAL directory once they have taken effect. When data
+//! goes missing, the audit log says which API call or tool removed it, when,
+//! and from which process. `nori-wal audit` prints and filters it.
+//!
+//! The log is off unless [`WalConfig::audit_log`](crate::WalConfig::audit_log)
+//! is set; offline `nori-wal repair` records its changes regardless.
+//!
+//! Each line is a JSON object:
+//!
+//! ```json
+//! {"at_ms":1700000000000,"operation":"purge","source":"Wal::delete_segments_before","pid":4242,"node_id":1,"detail":"segments before 000003:0, 3 deleted"}
+//! ```
+//!
+//! Entries are fsynced as they are written. An operation whose entry cannot
+//! be written still took effect; the error is returned so the caller knows
+//! the audit log is incomplete. Operations that change nothing, such as a
+//! purge with nothing to delete, are not recorded. The log is never pruned
+//! and moves with the WAL on [`Wal::migrate_to`](crate::Wal::migrate_to).
+
+use crate::fs::{Fs, LocalFs, OpenMode};
+use crate::report::json_string;
+use crate::segment::SegmentError;
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the audit log in the WAL directory.
+pub const AUDIT_FILE: &str = "audit.log";
+
+/// The kind of administrative operation an entry records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuditOperation {
+    /// Whole segments were deleted.
+    Purge,
+    /// The tail of the log was discarded.
+    Truncate,
+    /// Sealed segments were rewritten without superseded records.
+    Compact,
+    /// The log was cut offline by `nori-wal repair`.
+    Repair,
+    /// The WAL moved to another directory.
+    Migrate,
+    /// A setting was changed on the open WAL.
+    Reconfigure,
+}
+
+impl AuditOperation {
+    /// Every operation, in declaration order.
+    pub const ALL: [AuditOperation; 6] = [
+        AuditOperation::Purge,
+        AuditOperation::Truncate,
+        AuditOperation::Compact,
+        AuditOperation::Repair,
+        AuditOperation::Migrate,
+        AuditOperation::Reconfigure,
+    ];
+
+    /// The name written to the audit log.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditOperation::Purge => "purge",
+            AuditOperation::Truncate => "truncate",
+            AuditOperation::Compact => "compact",
+            AuditOperation::Repair => "repair",
+            AuditOperation::Migrate => "migrate",
+            AuditOperation::Reconfigure => "reconfigure",
+        }
+    }
+
+    /// Parses a name written by [`name`](Self::name).
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| op.name() == name)
+    }
+}
+
+impl fmt::Display for AuditOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// One line of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// When the operation took effect.
+    pub at: SystemTime,
+    pub operation: AuditOperation,
+    /// The API call or tool that made the change, such as
+    /// `Wal::truncate_from` or `nori-wal repair`.
+    pub source: String,
+    /// ID of the process that made the change.
+    pub pid: u32,
+    /// `node_id` of the WAL, or 0 for offline tools.
+    pub node_id: u32,
+    /// What changed, for people to read.
+    pub detail: String,
+}
+
+impl AuditEntry {
+    /// An entry for an operation by this process taking effect now.
+    pub fn now(
+        operation: AuditOperation,
+        source: impl Into<String>,
+        node_id: u32,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            at: SystemTime::now(),
+            operation,
+            source: source.into(),
+            pid: std::process::id(),
+            node_id,
+            detail: detail.into(),
+        }
+    }
+
+    /// The entry as a line of the audit log, newline included.
+    pub fn to_json_line(&self) -> String {
+        let at_ms = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        format!(
+            "{{\"at_ms\":{},\"operation\":\"{}\",\"source\":{},\"pid\":{},\"node_id\":{},\"detail\":{}}}\n",
+            at_ms,
+            self.operation,
+            json_string(&self.source),
+            self.pid,
+            self.node_id,
+            json_string(&self.detail),
+        )
+    }
+}
+
+/// Appends `entry` to the audit log of the WAL in `dir` and fsyncs it.
+///
+/// For offline tools working on the files directly; an open WAL records its
+/// own operations.
+pub async fn append_entry(dir: &Path, entry: &AuditEntry) -> Result<(), SegmentError> {
+    append_entry_on(&LocalFs, dir, entry).await
+}
+
+/// Like [`append_entry`], for a WAL stored on `fs`.
+pub(crate) async fn append_entry_on(
+    fs: &dyn Fs,
+    dir: &Path,
+    entry: &AuditEntry,
+) -> Result<(), SegmentError> {
+    let path = dir.join(AUDIT_FILE);
+    let created = !fs.exists(&path).await?;
+    let file = fs.open(&path, OpenMode::Create).await?;
+    let len = file.len().await?;
+    file.write_all_at(len, entry.to_json_line().as_bytes())
+        .await?;
+    file.sync_data().await?;
+    if created {
+        fs.sync_dir(dir).await?;
+    }
+    Ok(())
+}
+
+/// Moves the audit log from `from` to `to`, appending to any log already
+/// in `to`.
+pub(crate) async fn move_log(fs: &dyn Fs, from: &Path, to: &Path) -> Result<(), SegmentError> {
+    let source = from.join(AUDIT_FILE);
+    if !fs.exists(&source).await? {
+        return Ok(());
+    }
+    let data = crate::fs::read(fs, &source).await?;
+    let target = fs.open(&to.join(AUDIT_FILE), OpenMode::Create).await?;
+    let len = target.len().await?;
+    target.write_all_at(len, &data).await?;
+    target.sync_data().await?;
+    fs.sync_dir(to).await?;
+    fs.remove_file(&source).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn entry(operation: AuditOperation, detail: &str) -> AuditEntry {
+        AuditEntry {
+            at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            operation,
+            source: "Wal::truncate_from".to_string(),
+            pid: 42,
+            node_id: 7,
+            detail: detail.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_entries_are_json_lines() {
+        let line = entry(AuditOperation::Truncate, "from \"000001:0\"").to_json_line();
+        assert!(line.ends_with('\n'));
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["at_ms"], 1_700_000_000_123u64);
+        assert_eq!(json["operation"], "truncate");
+        assert_eq!(json["source"], "Wal::truncate_from");
+        assert_eq!(json["pid"], 42);
+        assert_eq!(json["node_id"], 7);
+        assert_eq!(json["detail"], "from \"000001:0\"");
+
+        for op in AuditOperation::ALL {
+            assert_eq!(AuditOperation::from_name(op.name()), Some(op));
+        }
+        assert_eq!(AuditOperation::from_name("delete"), None);
+    }
+
+    #[tokio::test]
+    async fn test_entries_append_and_move() {
+        let temp_dir = TempDir::new().unwrap();
+        let (old, new) = (temp_dir.path().join("old"), temp_dir.path().join("new"));
+        std::fs::create_dir_all(&old).unwrap();
+        std::fs::create_dir_all(&new).unwrap();
+
+        append_entry(&old, &entry(AuditOperation::Repair, "one"))
+            .await
+            .unwrap();
+        append_entry_on(&LocalFs, &old, &entry(AuditOperation::Purge, "two"))
+            .await
+            .unwrap();
+        append_entry(&new, &entry(AuditOperation::Migrate, "three"))
+            .await
+            .unwrap();
+        move_log(&LocalFs, &old, &new).await.unwrap();
+        assert!(!old.join(AUDIT_FILE).exists());
+
+        let log = std::fs::read_to_string(new.join(AUDIT_FILE)).unwrap();
+        let details: Vec<String> = log
+            .lines()
+            .map(|line| {
+                let json: serde_json::Value = serde_json::from_str(line).unwrap();
+                json["detail"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(details, ["three", "one", "two"]);
+    }
+}
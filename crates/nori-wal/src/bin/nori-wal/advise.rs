@@ -0,0 +1,291 @@
+//! `nori-wal advise`: recommend settings for the workload a WAL recorded.
+//!
+//! Record sizes, the arrival rate and how well values compress come from the
+//! records written in the last `--window-secs` before the newest one, going
+//! by their timestamps. Fsync latency cannot be read off the files, so it is
+//! only known with `--probe-fsync`, which times fsyncs of a scratch file in
+//! the same directory.
+
+use crate::segments::{self, Entry};
+use nori_wal::advisor::{Advice, WorkloadObserver, WorkloadProfile};
+use nori_wal::{Compression, FsyncPolicy};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Scratch file timed by `--probe-fsync`, removed afterwards.
+const PROBE_FILE: &str = ".advise-probe";
+const PROBE_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, or a single segment file
+    path: PathBuf,
+    /// How far back from the newest record to look
+    #[arg(long, default_value_t = 60)]
+    window_secs: u64,
+    /// Time this many fsyncs of a scratch file next to the segments
+    #[arg(long, default_value_t = 0)]
+    probe_fsync: usize,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+/// Profiles the records in the window, newest segments first, stopping at
+/// the first segment that starts before it.
+pub fn profile(args: &Args) -> io::Result<WorkloadProfile> {
+    let window_ms = args.window_secs.saturating_mul(1000);
+    let mut observer = WorkloadObserver::new();
+    let mut cutoff = None;
+    let (mut first_ms, mut last_ms) = (u64::MAX, 0);
+    for segment in segments::find(&args.path)?.iter().rev() {
+        let data = segment.read()?;
+        let mut records = Vec::new();
+        for entry in segments::scan(segment.id, &data) {
+            if let Entry::Record { size, record, .. } = entry {
+                let ms = record
+                    .timestamp
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_millis() as u64);
+                if let Some(ms) = ms {
+                    records.push((ms, size, record));
+                }
+            }
+        }
+        let Some(newest) = records.iter().map(|(ms, ..)| *ms).max() else {
+            continue;
+        };
+        let cutoff = *cutoff.get_or_insert(newest.saturating_sub(window_ms));
+        let mut before_window = false;
+        for (ms, size, record) in records {
+            if ms < cutoff {
+                before_window = true;
+                continue;
+            }
+            first_ms = first_ms.min(ms);
+            last_ms = last_ms.max(ms);
+            observer.observe(&record, size as u64);
+        }
+        if before_window {
+            break;
+        }
+    }
+
+    for elapsed in probe_fsync(&args.path, args.probe_fsync)? {
+        observer.observe_fsync(elapsed);
+    }
+    // The records may span less than the window asked for
+    let span = Duration::from_millis(last_ms.saturating_sub(first_ms).max(1));
+    Ok(observer.finish(span.min(Duration::from_millis(window_ms.max(1)))))
+}
+
+/// Times `count` fsyncs of a scratch file in the WAL's directory.
+fn probe_fsync(path: &Path, count: usize) -> io::Result<Vec<Duration>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    let dir = if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or(Path::new("."))
+    };
+    let probe = dir.join(PROBE_FILE);
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(&probe)?;
+    let block = [0u8; PROBE_BYTES];
+    let mut timings = Vec::with_capacity(count);
+    let result = (0..count).try_for_each(|_| {
+        file.write_all(&block)?;
+        let started = Instant::now();
+        file.sync_data()?;
+        timings.push(started.elapsed());
+        Ok(())
+    });
+    drop(file);
+    std::fs::remove_file(&probe)?;
+    result.map(|()| timings)
+}
+
+/// The recommended fsync policy as `wal.toml` would spell it.
+fn fsync_setting(policy: FsyncPolicy) -> String {
+    match policy {
+        FsyncPolicy::Always => "fsync_policy = \"always\"".to_string(),
+        FsyncPolicy::Batch(window) => format!("fsync_window = \"{}ms\"", window.as_millis()),
+        FsyncPolicy::Os => "fsync_policy = \"os\"".to_string(),
+    }
+}
+
+fn compression_name(compression: Compression) -> &'static str {
+    match compression {
+        Compression::None => "none",
+        Compression::Lz4 => "lz4",
+        Compression::Zstd => "zstd",
+    }
+}
+
+fn write_table(out: &mut impl Write, profile: &WorkloadProfile, advice: &Advice) -> io::Result<()> {
+    let size = &profile.record_size;
+    writeln!(
+        out,
+        "window       {:?} ({} records)",
+        profile.window, profile.appends
+    )?;
+    writeln!(
+        out,
+        "rate         {:.0} appends/s, {:.0} bytes/s",
+        profile.appends_per_second(),
+        profile.bytes_per_second()
+    )?;
+    writeln!(
+        out,
+        "record size  p50 {}  p90 {}  p99 {}  max {}",
+        size.p50, size.p90, size.p99, size.max
+    )?;
+    match profile.compression_ratio {
+        Some(ratio) => writeln!(out, "lz4 ratio    {:.2}", ratio)?,
+        None => writeln!(out, "lz4 ratio    -")?,
+    }
+    let fsync = &profile.fsync_latency;
+    if fsync.count > 0 {
+        writeln!(
+            out,
+            "fsync        p50 {:?}  p90 {:?}  p99 {:?}  max {:?}",
+            fsync.p50, fsync.p90, fsync.p99, fsync.max
+        )?;
+    } else {
+        writeln!(out, "fsync        not measured (see --probe-fsync)")?;
+    }
+
+    writeln!(out)?;
+    let mut settings = Vec::new();
+    if let Some(policy) = advice.fsync_policy {
+        settings.push(fsync_setting(policy));
+    }
+    settings.push(format!(
+        "max_segment_size = \"{}MiB\"",
+        advice.max_segment_size / (1024 * 1024)
+    ));
+    settings.push(format!(
+        "# compress values with Compression::{:?}",
+        advice.compression
+    ));
+    for setting in settings {
+        writeln!(out, "{}", setting)?;
+    }
+    writeln!(out)?;
+    for reason in &advice.reasons {
+        writeln!(out, "# {}", reason)?;
+    }
+    Ok(())
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let profile = profile(args)?;
+    let advice = profile.advise();
+    match args.format {
+        Format::Table => write_table(out, &profile, &advice),
+        Format::Json => {
+            let fsync = &profile.fsync_latency;
+            let report = json!({
+                "window_ms": profile.window.as_millis() as u64,
+                "appends": profile.appends,
+                "bytes": profile.bytes,
+                "record_size": {
+                    "p50": profile.record_size.p50,
+                    "p90": profile.record_size.p90,
+                    "p99": profile.record_size.p99,
+                    "max": profile.record_size.max,
+                },
+                "compression_ratio": profile.compression_ratio,
+                "fsync_us": (fsync.count > 0).then(|| json!({
+                    "count": fsync.count,
+                    "p50": fsync.p50.as_micros() as u64,
+                    "p90": fsync.p90.as_micros() as u64,
+                    "p99": fsync.p99.as_micros() as u64,
+                    "max": fsync.max.as_micros() as u64,
+                })),
+                "advice": {
+                    "fsync_policy": advice.fsync_policy.map(|policy| match policy {
+                        FsyncPolicy::Always => "always".to_string(),
+                        FsyncPolicy::Batch(window) => format!("batch {}ms", window.as_millis()),
+                        FsyncPolicy::Os => "os".to_string(),
+                    }),
+                    "max_segment_size": advice.max_segment_size,
+                    "compression": compression_name(advice.compression),
+                    "reasons": advice.reasons,
+                },
+            });
+            serde_json::to_writer_pretty(&mut *out, &report)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::Record;
+
+    #[test]
+    fn test_advise_looks_at_the_window_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let segment = |records: std::ops::Range<u64>| {
+            let mut data = Vec::new();
+            for i in records {
+                let mut record = Record::put(format!("key{}", i), vec![b'z'; 500]);
+                record.lsn = Some(i + 1);
+                // One record a second
+                record.timestamp = Some(start + Duration::from_secs(i));
+                data.extend_from_slice(&record.encode());
+            }
+            data
+        };
+        std::fs::write(dir.path().join("000000.wal"), segment(0..100)).unwrap();
+        std::fs::write(dir.path().join("000001.wal"), segment(100..200)).unwrap();
+
+        let args = Args {
+            path: dir.path().to_path_buf(),
+            window_secs: 150,
+            probe_fsync: 3,
+            format: Format::Json,
+        };
+        let profile = profile(&args).unwrap();
+        assert_eq!(profile.appends, 151);
+        assert_eq!(profile.window, Duration::from_secs(150));
+        assert_eq!(profile.fsync_latency.count, 3);
+        assert!(!dir.path().join(PROBE_FILE).exists());
+
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["appends"], 151);
+        assert_eq!(json["advice"]["compression"], "lz4");
+        assert_eq!(json["advice"]["fsync_policy"], "always");
+
+        let args = Args {
+            window_secs: 10,
+            probe_fsync: 0,
+            format: Format::Table,
+            ..args
+        };
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.contains("(11 records)"), "{}", table);
+        assert!(table.contains("max_segment_size = \"16MiB\""), "{}", table);
+        assert!(table.contains("not measured"));
+        assert!(!table.contains("fsync_policy ="));
+    }
+}
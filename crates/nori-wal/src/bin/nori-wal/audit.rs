@@ -0,0 +1,148 @@
+//! `nori-wal audit`: print the administrative operations recorded in a WAL
+//! directory's audit log, oldest first.
+
+use nori_wal::audit::AUDIT_FILE;
+use nori_wal::AuditOperation;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory
+    dir: PathBuf,
+    /// Only show this operation (purge, truncate, compact, repair, migrate
+    /// or reconfigure)
+    #[arg(long, value_parser = parse_operation)]
+    operation: Option<AuditOperation>,
+    /// Only show entries at or after this time, in milliseconds since the
+    /// Unix epoch
+    #[arg(long)]
+    since_ms: Option<u64>,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+fn parse_operation(name: &str) -> Result<AuditOperation, String> {
+    AuditOperation::from_name(name).ok_or_else(|| format!("unknown operation {:?}", name))
+}
+
+/// One line of the audit log, as `AuditEntry::to_json_line` writes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Line {
+    pub at_ms: u64,
+    pub operation: String,
+    pub source: String,
+    pub pid: u32,
+    pub node_id: u32,
+    pub detail: String,
+}
+
+/// Reads the entries of the audit log in `args.dir` that pass the filters.
+/// A WAL that never recorded anything has no audit log, and no entries.
+pub fn entries(args: &Args) -> io::Result<Vec<Line>> {
+    let file = match std::fs::File::open(args.dir.join(AUDIT_FILE)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && args.dir.is_dir() => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Line = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} line {}: {}", AUDIT_FILE, number + 1, e),
+            )
+        })?;
+        if args
+            .operation
+            .is_some_and(|operation| entry.operation != operation.name())
+        {
+            continue;
+        }
+        if args.since_ms.is_some_and(|since| entry.at_ms < since) {
+            continue;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    for entry in entries(args)? {
+        match args.format {
+            Format::Table => writeln!(
+                out,
+                "{} {:<11} {} pid={} node={}: {}",
+                entry.at_ms, entry.operation, entry.source, entry.pid, entry.node_id, entry.detail
+            )?,
+            Format::Json => {
+                serde_json::to_writer(&mut *out, &entry)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::AuditEntry;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_audit_filters_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = |operation, since_ms, format| Args {
+            dir: dir.path().to_path_buf(),
+            operation,
+            since_ms,
+            format,
+        };
+        assert!(entries(&args(None, None, Format::Table))
+            .unwrap()
+            .is_empty());
+
+        let mut log = String::new();
+        for (ms, operation) in [
+            (1_000, AuditOperation::Purge),
+            (2_000, AuditOperation::Truncate),
+            (3_000, AuditOperation::Purge),
+        ] {
+            let entry = AuditEntry {
+                at: UNIX_EPOCH + Duration::from_millis(ms),
+                ..AuditEntry::now(operation, "test", 3, format!("at {}", ms))
+            };
+            log.push_str(&entry.to_json_line());
+        }
+        std::fs::write(dir.path().join(AUDIT_FILE), log).unwrap();
+
+        let purges = entries(&args(Some(AuditOperation::Purge), None, Format::Json)).unwrap();
+        assert_eq!(purges.len(), 2);
+        let recent = entries(&args(None, Some(2_000), Format::Json)).unwrap();
+        let details: Vec<&str> = recent.iter().map(|e| e.detail.as_str()).collect();
+        assert_eq!(details, ["at 2000", "at 3000"]);
+
+        let mut out = Vec::new();
+        run(
+            &args(Some(AuditOperation::Truncate), None, Format::Table),
+            &mut out,
+        )
+        .unwrap();
+        let table = String::from_utf8(out).unwrap();
+        assert!(table.starts_with("2000 truncate    test pid="), "{}", table);
+        assert!(table.ends_with("node=3: at 2000\n"), "{}", table);
+        assert!(parse_operation("delete").is_err());
+    }
+}
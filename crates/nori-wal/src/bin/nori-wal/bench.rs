@@ -0,0 +1,245 @@
+//! `nori-wal bench`: runs a synthetic workload against a fresh WAL and
+//! reports throughput and latency percentiles.
+
+use crate::stats::Format;
+use nori_wal::workload::{self, SizeDistribution, Workload, WorkloadReport};
+use nori_wal::{FsyncPolicy, LatencySummary, Wal, WalConfig};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Fsync {
+    Always,
+    Batch,
+    Os,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Empty or missing directory for the WAL; a temporary one by default
+    #[arg(long)]
+    dir: Option<PathBuf>,
+    /// Keep the WAL afterwards instead of deleting it
+    #[arg(long)]
+    keep: bool,
+    /// Records to append
+    #[arg(long, default_value_t = 100_000)]
+    records: u64,
+    /// Concurrent writers
+    #[arg(long, default_value_t = 1)]
+    writers: usize,
+    /// Records per append_batch call; 1 appends one at a time
+    #[arg(long, default_value_t = 1)]
+    batch: usize,
+    /// Value sizes: SIZE, MIN..MAX or SIZE:WEIGHT,...
+    #[arg(long, default_value = "256")]
+    sizes: SizeDistribution,
+    /// Distinct keys
+    #[arg(long, default_value_t = 1_000_000)]
+    key_space: u64,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    #[arg(long, value_enum, default_value_t = Fsync::Batch)]
+    fsync: Fsync,
+    /// Batch fsync window
+    #[arg(long, default_value_t = 5)]
+    fsync_window_ms: u64,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+/// Latency percentiles in microseconds, for JSON output.
+#[derive(Debug, Serialize)]
+struct Latency {
+    count: u64,
+    p50_us: u128,
+    p90_us: u128,
+    p99_us: u128,
+    max_us: u128,
+}
+
+impl From<LatencySummary> for Latency {
+    fn from(summary: LatencySummary) -> Self {
+        Latency {
+            count: summary.count,
+            p50_us: summary.p50.as_micros(),
+            p90_us: summary.p90.as_micros(),
+            p99_us: summary.p99.as_micros(),
+            max_us: summary.max.as_micros(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Output {
+    records: u64,
+    writers: usize,
+    batch: usize,
+    sizes: String,
+    fsync: String,
+    bytes: u64,
+    elapsed_ms: u128,
+    records_per_sec: f64,
+    bytes_per_sec: f64,
+    fsyncs: u64,
+    append_latency: Latency,
+    fsync_latency: Latency,
+}
+
+fn percentiles(summary: &LatencySummary) -> String {
+    format!(
+        "p50 {:?}  p90 {:?}  p99 {:?}  max {:?}",
+        summary.p50, summary.p90, summary.p99, summary.max
+    )
+}
+
+fn write_table(out: &mut impl Write, o: &Output, report: &WorkloadReport) -> io::Result<()> {
+    writeln!(
+        out,
+        "workload    {} records, {} writers, batch {}, sizes {}, fsync {}",
+        o.records, o.writers, o.batch, o.sizes, o.fsync
+    )?;
+    writeln!(
+        out,
+        "throughput  {:.0} records/s, {:.1} MiB/s ({} bytes in {:?})",
+        o.records_per_sec,
+        o.bytes_per_sec / (1024.0 * 1024.0),
+        o.bytes,
+        report.elapsed
+    )?;
+    writeln!(
+        out,
+        "append      {}  ({} calls)",
+        percentiles(&report.append_latency),
+        report.append_latency.count
+    )?;
+    writeln!(
+        out,
+        "fsync       {}  ({} fsyncs)",
+        percentiles(&report.fsync_latency),
+        report.fsyncs
+    )
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let dir = match &args.dir {
+        Some(dir) => {
+            let used = std::fs::read_dir(dir).is_ok_and(|mut d| d.next().is_some());
+            if used {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not empty", dir.display()),
+                ));
+            }
+            dir.clone()
+        }
+        None => std::env::temp_dir().join(format!("nori-wal-bench-{}", std::process::id())),
+    };
+    let fsync_policy = match args.fsync {
+        Fsync::Always => FsyncPolicy::Always,
+        Fsync::Batch => FsyncPolicy::Batch(Duration::from_millis(args.fsync_window_ms)),
+        Fsync::Os => FsyncPolicy::Os,
+    };
+    let workload = Workload {
+        records: args.records,
+        writers: args.writers,
+        batch: args.batch,
+        sizes: args.sizes.clone(),
+        key_space: args.key_space,
+        seed: args.seed,
+    };
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(async {
+        let (wal, _) = Wal::open(WalConfig {
+            dir: dir.clone(),
+            fsync_policy,
+            ..WalConfig::default()
+        })
+        .await?;
+        let wal = Arc::new(wal);
+        let report = workload::run(Arc::clone(&wal), &workload).await;
+        if let Ok(wal) = Arc::try_unwrap(wal) {
+            wal.close().await?;
+        }
+        report
+    });
+    if !args.keep {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    let report = result.map_err(io::Error::other)?;
+
+    let output = Output {
+        records: report.records,
+        writers: args.writers,
+        batch: args.batch,
+        sizes: args.sizes.to_string(),
+        fsync: match fsync_policy {
+            FsyncPolicy::Batch(window) => format!("batch {:?}", window),
+            other => format!("{:?}", other).to_lowercase(),
+        },
+        bytes: report.bytes,
+        elapsed_ms: report.elapsed.as_millis(),
+        records_per_sec: report.records_per_sec(),
+        bytes_per_sec: report.bytes_per_sec(),
+        fsyncs: report.fsyncs,
+        append_latency: report.append_latency.into(),
+        fsync_latency: report.fsync_latency.into(),
+    };
+    match args.format {
+        Format::Table => write_table(out, &output, &report),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &output)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Cli {
+        #[command(flatten)]
+        args: Args,
+    }
+
+    #[test]
+    fn test_bench_reports_and_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().join("wal");
+        let cli = Cli::parse_from([
+            "bench",
+            "--dir",
+            wal_dir.to_str().unwrap(),
+            "--records",
+            "500",
+            "--writers",
+            "2",
+            "--sizes",
+            "10..100",
+            "--fsync",
+            "os",
+            "--format",
+            "json",
+        ]);
+        let mut out = Vec::new();
+        run(&cli.args, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["records"], 500);
+        assert_eq!(json["fsync"], "os");
+        assert_eq!(json["sizes"], "10..100");
+        assert!(json["append_latency"]["count"].as_u64().unwrap() >= 500);
+        assert!(!wal_dir.exists());
+
+        std::fs::create_dir(&wal_dir).unwrap();
+        std::fs::write(wal_dir.join("000000.wal"), b"").unwrap();
+        let err = run(&cli.args, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,183 @@
+//! `nori-wal clone`: copies the records of a WAL directory into a fresh
+//! one, one segment in memory at a time, like `Wal::copy_to` does for an
+//! open WAL.
+
+use crate::export::Codec;
+use crate::import::IterStream;
+use crate::segments::{self, Entry};
+use bytes::Bytes;
+use nori_wal::{ImportConfig, Position, Wal, WalConfig};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory to copy
+    source: PathBuf,
+    /// Directory for the copy, created if missing; must not hold segments
+    dest: PathBuf,
+    /// Keep only the newest record for each key (per namespace)
+    #[arg(long)]
+    drop_superseded: bool,
+    /// Write every value with this compression instead of its own
+    #[arg(long, value_enum)]
+    compression: Option<Codec>,
+}
+
+/// Copies every record that decodes, keeping LSNs and timestamps, into a
+/// new WAL whose segments are numbered from zero. Damaged records are
+/// skipped up to the end of their segment, as `export` does.
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let sources = segments::find(&args.source)?;
+    if args.dest.is_dir() && !segments::find(&args.dest)?.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already contains WAL segments", args.dest.display()),
+        ));
+    }
+
+    // Where the newest record for each key is
+    let mut newest: HashMap<(Option<u32>, Bytes), Position> = HashMap::new();
+    if args.drop_superseded {
+        for segment in &sources {
+            for entry in segments::scan(segment.id, &segment.read()?) {
+                if let Entry::Record {
+                    position, record, ..
+                } = entry
+                {
+                    newest.insert((record.namespace, record.key), position);
+                }
+            }
+        }
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (copied, bytes, dropped, segments) = runtime
+        .block_on(async {
+            let (wal, _) = Wal::open(WalConfig {
+                dir: args.dest.clone(),
+                ..WalConfig::default()
+            })
+            .await?;
+            let (mut copied, mut bytes, mut dropped) = (0, 0, 0);
+            for segment in &sources {
+                let data = segment.read()?;
+                let mut kept = Vec::new();
+                for entry in segments::scan(segment.id, &data) {
+                    let (position, mut record) = match entry {
+                        Entry::Record {
+                            position, record, ..
+                        } => (position, record),
+                        Entry::Damaged {
+                            position, error, ..
+                        } => {
+                            eprintln!(
+                                "nori-wal: skipping damaged records from {} to the end of the segment: {}",
+                                position, error
+                            );
+                            continue;
+                        }
+                    };
+                    let key = (record.namespace, record.key.clone());
+                    if args.drop_superseded && newest.get(&key) != Some(&position) {
+                        dropped += 1;
+                        continue;
+                    }
+                    // The copy's hash chain, if any, is its own
+                    record.chain = None;
+                    if let Some(codec) = args.compression {
+                        record.compression = codec.into();
+                    }
+                    kept.push(record);
+                }
+                let summary = wal
+                    .import_with(IterStream(kept.into_iter()), ImportConfig::default())
+                    .await?;
+                copied += summary.records;
+                bytes += summary.bytes;
+            }
+            let segments = wal.current_position().await.segment_id + 1;
+            wal.close().await?;
+            Ok::<_, nori_wal::SegmentError>((copied, bytes, dropped, segments))
+        })
+        .map_err(io::Error::other)?;
+
+    writeln!(
+        out,
+        "copied {} records ({} bytes) into {} segments, dropped {} superseded",
+        copied, bytes, segments, dropped
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::{Compression, Record};
+
+    #[test]
+    fn test_clone_drops_superseded_and_renumbers() {
+        let source = tempfile::tempdir().unwrap();
+        let mut lsn = 0;
+        let mut segment = |keys: &[&str]| {
+            let mut data = Vec::new();
+            for key in keys {
+                lsn += 1;
+                let mut record = Record::put(key.to_string(), format!("v{}", lsn));
+                record.lsn = Some(lsn);
+                data.extend_from_slice(&record.encode());
+            }
+            data
+        };
+        let first = segment(&["a", "b", "a"]);
+        let second = segment(&["b", "c"]);
+        std::fs::write(source.path().join("000007.wal"), first).unwrap();
+        std::fs::write(source.path().join("000008.wal"), second).unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        let dest = dest.path();
+        let mut args = Args {
+            source: source.path().to_path_buf(),
+            dest: dest.to_path_buf(),
+            drop_superseded: true,
+            compression: Some(Codec::Lz4),
+        };
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("copied 3 records ("), "{}", out);
+        assert!(
+            out.ends_with("into 1 segments, dropped 2 superseded\n"),
+            "{}",
+            out
+        );
+
+        let data = std::fs::read(dest.join("000000.wal")).unwrap();
+        let records: Vec<_> = segments::scan(0, &data)
+            .map(|entry| match entry {
+                Entry::Record { record, .. } => record,
+                Entry::Damaged { error, .. } => panic!("{}", error),
+            })
+            .collect();
+        assert!(records.iter().all(|r| r.compression == Compression::Lz4));
+        let kept: Vec<_> = records
+            .iter()
+            .map(|r| (&r.key[..], &r.value[..], r.lsn))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                (&b"a"[..], &b"v3"[..], Some(3)),
+                (b"b", b"v4", Some(4)),
+                (b"c", b"v5", Some(5)),
+            ]
+        );
+
+        // Segments already in the destination are never written over
+        args.drop_superseded = false;
+        let err = run(&args, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,77 @@
+//! `nori-wal du`: disk usage of a WAL directory, file by file.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory
+    dir: PathBuf,
+    /// Print only the total
+    #[arg(long, short)]
+    summarize: bool,
+}
+
+/// Prints the size of every file in the directory, largest first, then the
+/// total. Segments are counted with their pre-allocated space, as on disk.
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(&args.dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((metadata.len(), entry.file_name()));
+        }
+    }
+    files.sort_unstable_by(|a, b| b.cmp(a));
+
+    let total: u64 = files.iter().map(|(len, _)| len).sum();
+    if !args.summarize {
+        for (len, name) in &files {
+            writeln!(out, "{:>12}  {}", len, name.to_string_lossy())?;
+        }
+    }
+    writeln!(out, "{:>12}  total ({} files)", total, files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_du_lists_files_largest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("000000.wal"), [0; 100]).unwrap();
+        std::fs::write(dir.path().join("000001.wal"), [0; 300]).unwrap();
+        std::fs::write(dir.path().join("LOCK"), b"").unwrap();
+
+        let mut args = Args {
+            dir: dir.path().to_path_buf(),
+            summarize: false,
+        };
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let lines: Vec<String> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "300 000001.wal",
+                "100 000000.wal",
+                "0 LOCK",
+                "400 total (3 files)"
+            ]
+        );
+
+        args.summarize = true;
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().trim(),
+            "400  total (3 files)"
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! `nori-wal export`: writes records out as JSON Lines or CSV, one segment
+//! in memory at a time, or as Kafka log segments in a directory.
+
+use crate::segments::{self, Entry};
+use nori_wal::{Compression, KafkaExportConfig, KafkaSegmentWriter, Position, Record};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// One JSON object per line
+    Json,
+    /// A header line, then one row per record
+    Csv,
+    /// Kafka log segments and indexes, written to --out
+    Kafka,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, or a single segment file
+    path: PathBuf,
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Start at this position (SEGMENT:OFFSET)
+    #[arg(long)]
+    from: Option<Position>,
+    /// Write every key and value as hex, even valid UTF-8
+    #[arg(long)]
+    hex: bool,
+    /// Partition directory for --format kafka
+    #[arg(long, required_if_eq("format", "kafka"))]
+    out: Option<PathBuf>,
+    /// Kafka offset of the first record exported
+    #[arg(long, default_value_t = 0)]
+    base_offset: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Put,
+    Del,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl From<Codec> for Compression {
+    fn from(codec: Codec) -> Self {
+        match codec {
+            Codec::None => Compression::None,
+            Codec::Lz4 => Compression::Lz4,
+            Codec::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+/// How `key` and `value` are written: as they are when both are UTF-8,
+/// otherwise as hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Utf8,
+    Hex,
+}
+
+/// One exported record; the same fields in both formats, so CSV columns
+/// and JSON keys match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Row {
+    pub lsn: Option<u64>,
+    pub timestamp_ms: Option<u64>,
+    pub namespace: Option<u32>,
+    pub ttl_ms: Option<u64>,
+    pub op: Op,
+    pub compression: Codec,
+    pub encoding: Encoding,
+    pub key: String,
+    pub value: String,
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>, String> {
+    if s.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in {:?}", s));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("invalid hex {:?}", s))
+        })
+        .collect()
+}
+
+impl Row {
+    pub fn from_record(record: &Record, hex: bool) -> Self {
+        let text = match (
+            std::str::from_utf8(&record.key),
+            std::str::from_utf8(&record.value),
+        ) {
+            (Ok(key), Ok(value)) if !hex => Some((key.to_string(), value.to_string())),
+            _ => None,
+        };
+        let (encoding, key, value) = match text {
+            Some((key, value)) => (Encoding::Utf8, key, value),
+            None => (Encoding::Hex, to_hex(&record.key), to_hex(&record.value)),
+        };
+        Row {
+            lsn: record.lsn,
+            timestamp_ms: record
+                .timestamp
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64),
+            namespace: record.namespace,
+            ttl_ms: record.ttl.map(|ttl| ttl.as_millis() as u64),
+            op: if record.tombstone { Op::Del } else { Op::Put },
+            compression: match record.compression {
+                Compression::None => Codec::None,
+                Compression::Lz4 => Codec::Lz4,
+                Compression::Zstd => Codec::Zstd,
+            },
+            encoding,
+            key,
+            value,
+        }
+    }
+
+    pub fn into_record(self) -> Result<Record, String> {
+        let (key, value) = match self.encoding {
+            Encoding::Utf8 => (self.key.into_bytes(), self.value.into_bytes()),
+            Encoding::Hex => (from_hex(&self.key)?, from_hex(&self.value)?),
+        };
+        let mut record = match self.op {
+            Op::Put => Record::put(key, value),
+            Op::Del => Record::delete(key),
+        };
+        record.lsn = self.lsn;
+        record.timestamp = self
+            .timestamp_ms
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms));
+        record.namespace = self.namespace;
+        record.ttl = self.ttl_ms.map(Duration::from_millis);
+        record.compression = self.compression.into();
+        Ok(record)
+    }
+}
+
+/// Where records go, in the format asked for.
+enum Sink<W: Write> {
+    Json(W, bool),
+    Csv(Box<csv::Writer<W>>, bool),
+    Kafka(Box<KafkaSegmentWriter>, W),
+}
+
+impl<W: Write> Sink<W> {
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            Sink::Json(out, hex) => {
+                serde_json::to_writer(&mut *out, &Row::from_record(record, *hex))?;
+                writeln!(out)
+            }
+            Sink::Csv(out, hex) => out
+                .serialize(Row::from_record(record, *hex))
+                .map_err(io::Error::other),
+            Sink::Kafka(writer, _) => writer.append(record).map(|_| ()),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Json(mut out, _) => out.flush(),
+            Sink::Csv(mut out, _) => out.flush(),
+            Sink::Kafka(writer, mut out) => {
+                let summary = writer.finish()?;
+                writeln!(
+                    out,
+                    "exported {} records in {} batches and {} segments, next offset {}",
+                    summary.records, summary.batches, summary.segments, summary.next_offset
+                )
+            }
+        }
+    }
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let from = args.from.unwrap_or(Position {
+        segment_id: 0,
+        offset: 0,
+    });
+    let mut sink = match args.format {
+        Format::Json => Sink::Json(out, args.hex),
+        Format::Csv => Sink::Csv(Box::new(csv::Writer::from_writer(out)), args.hex),
+        Format::Kafka => {
+            let dir = args.out.as_ref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--format kafka needs --out")
+            })?;
+            let config = KafkaExportConfig {
+                base_offset: args.base_offset,
+                ..Default::default()
+            };
+            let writer = KafkaSegmentWriter::create(dir, config).map_err(io::Error::other)?;
+            Sink::Kafka(Box::new(writer), out)
+        }
+    };
+
+    for segment in segments::find(&args.path)? {
+        if segment.id < from.segment_id {
+            continue;
+        }
+        let data = segment.read()?;
+        for entry in segments::scan(segment.id, &data) {
+            match entry {
+                Entry::Record {
+                    position, record, ..
+                } if position >= from => sink.write(&record)?,
+                Entry::Record { .. } => {}
+                Entry::Damaged {
+                    position, error, ..
+                } => eprintln!(
+                    "nori-wal: skipping damaged records from {} to the end of the segment: {}",
+                    position, error
+                ),
+            }
+        }
+    }
+    sink.finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::SystemTime;
+
+    pub(crate) fn args(path: &Path, format: Format) -> Args {
+        Args {
+            path: path.to_path_buf(),
+            format,
+            from: None,
+            hex: false,
+            out: None,
+            base_offset: 0,
+        }
+    }
+
+    /// The current time, cut to the millisecond precision rows carry.
+    fn now_ms() -> SystemTime {
+        let ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_rows_round_trip_records() {
+        let mut put = Record::put_with_ttl(
+            b"user/1".as_slice(),
+            b"a,\"quoted\"\nvalue".as_slice(),
+            Duration::from_secs(30),
+        )
+        .with_namespace(7)
+        .with_compression(Compression::Zstd);
+        put.lsn = Some(12);
+        put.timestamp = Some(now_ms());
+        let binary = Record::put(b"\x00\xff".as_slice(), b"v".as_slice());
+        let del = Record::delete(b"user/1".as_slice());
+
+        for record in [put, binary, del] {
+            let row = Row::from_record(&record, false);
+            assert_eq!(row.clone().into_record().unwrap(), record);
+            let hex = Row::from_record(&record, true);
+            assert_eq!(hex.encoding, Encoding::Hex);
+            assert_eq!(hex.into_record().unwrap(), record);
+        }
+        assert_eq!(
+            Row::from_record(&Record::put(b"\x00\xff".as_slice(), b"v".as_slice()), false).key,
+            "00ff"
+        );
+        assert!(from_hex("0").is_err() && from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = Vec::new();
+        for key in ["a", "b"] {
+            data.extend_from_slice(&Record::put(key.as_bytes(), b"x,y".as_slice()).encode());
+        }
+        std::fs::write(dir.path().join("000000.wal"), data).unwrap();
+
+        let export = |format| {
+            let mut out = Vec::new();
+            run(&args(dir.path(), format), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let json = export(Format::Json);
+        let lines: Vec<serde_json::Value> = json
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["key"], "b");
+        assert_eq!(lines[1]["op"], "put");
+
+        let csv = export(Format::Csv);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "lsn,timestamp_ms,namespace,ttl_ms,op,compression,encoding,key,value",
+                ",,,,put,none,utf8,a,\"x,y\"",
+                ",,,,put,none,utf8,b,\"x,y\"",
+            ]
+        );
+
+        let kafka = dir.path().join("events-0");
+        let mut out = Vec::new();
+        let args = Args {
+            out: Some(kafka.clone()),
+            base_offset: 42,
+            ..args(dir.path(), Format::Kafka)
+        };
+        run(&args, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "exported 2 records in 1 batches and 1 segments, next offset 44\n"
+        );
+        assert!(kafka.join("00000000000000000042.log").exists());
+    }
+}
@@ -0,0 +1,227 @@
+//! `nori-wal import`: appends the records of a `nori-wal export` file to a
+//! WAL, reading the file as it goes.
+
+use crate::export::{Format, Row};
+use futures_core::Stream;
+use nori_wal::{ImportConfig, Record, Wal, WalConfig};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, created if missing
+    dir: PathBuf,
+    /// File written by `nori-wal export`, or - for stdin
+    input: PathBuf,
+    /// Input format; by default taken from the extension, JSON otherwise
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Keep the exported LSNs instead of numbering records from the end of
+    /// the WAL. Only sensible for an empty WAL.
+    #[arg(long)]
+    keep_lsns: bool,
+    /// Fsync every this many bytes instead of only at the end
+    #[arg(long)]
+    sync_every: Option<u64>,
+}
+
+/// Rows from an export file, as records; stops at the first bad row and
+/// keeps the error for the caller.
+struct Rows {
+    rows: Box<dyn Iterator<Item = Result<Row, String>>>,
+    keep_lsns: bool,
+    read: u64,
+    error: Rc<RefCell<Option<String>>>,
+}
+
+impl Iterator for Rows {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        let row = self.rows.next()?;
+        self.read += 1;
+        match row.and_then(Row::into_record) {
+            Ok(mut record) => {
+                if !self.keep_lsns {
+                    record.lsn = None;
+                }
+                Some(record)
+            }
+            Err(e) => {
+                *self.error.borrow_mut() = Some(format!("row {}: {}", self.read, e));
+                None
+            }
+        }
+    }
+}
+
+/// A ready-at-once stream over an iterator, for `Wal::import`.
+pub struct IterStream<I>(pub I);
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.get_mut().0.next())
+    }
+}
+
+fn open(input: &Path) -> io::Result<Box<dyn BufRead>> {
+    if input == Path::new("-") {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(input)?)))
+    }
+}
+
+fn rows(input: Box<dyn BufRead>, format: Format) -> Box<dyn Iterator<Item = Result<Row, String>>> {
+    match format {
+        Format::Json => Box::new(input.lines().filter_map(|line| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(serde_json::from_str(&line).map_err(|e| e.to_string())),
+            Err(e) => Some(Err(e.to_string())),
+        })),
+        Format::Csv => Box::new(
+            csv::Reader::from_reader(input)
+                .into_deserialize()
+                .map(|row| row.map_err(|e| e.to_string())),
+        ),
+        Format::Kafka => Box::new(std::iter::once(Err(
+            "Kafka segments cannot be imported".to_string()
+        ))),
+    }
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let format =
+        args.format
+            .unwrap_or_else(|| match args.input.extension().and_then(|e| e.to_str()) {
+                Some("csv") => Format::Csv,
+                _ => Format::Json,
+            });
+    let error = Rc::new(RefCell::new(None));
+    let rows = Rows {
+        rows: rows(open(&args.input)?, format),
+        keep_lsns: args.keep_lsns,
+        read: 0,
+        error: Rc::clone(&error),
+    };
+    let config = ImportConfig {
+        sync_every: args.sync_every,
+        ..ImportConfig::default()
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let summary = runtime
+        .block_on(async {
+            let (wal, _) = Wal::open(WalConfig {
+                dir: args.dir.clone(),
+                ..WalConfig::default()
+            })
+            .await?;
+            let summary = wal.import_with(IterStream(rows), config).await?;
+            wal.close().await?;
+            Ok::<_, nori_wal::SegmentError>(summary)
+        })
+        .map_err(io::Error::other)?;
+
+    writeln!(
+        out,
+        "imported {} records ({} bytes), log ends at {}",
+        summary.records, summary.bytes, summary.end
+    )?;
+    // What came before the bad row is in the WAL and durable
+    match error.take() {
+        Some(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+
+    #[test]
+    fn test_export_then_import() {
+        let source = tempfile::tempdir().unwrap();
+        let mut data = Vec::new();
+        for (lsn, key) in [(1, "a"), (2, "b"), (3, "\u{1F600}")] {
+            let mut record = Record::put(key.as_bytes(), b"v\n,".as_slice());
+            record.lsn = Some(lsn);
+            data.extend_from_slice(&record.encode());
+        }
+        std::fs::write(source.path().join("000000.wal"), data).unwrap();
+
+        for (format, name) in [(Format::Json, "out.jsonl"), (Format::Csv, "out.csv")] {
+            let file = source.path().join(name);
+            let export_args = export::tests::args(source.path(), format);
+            export::run(&export_args, &mut File::create(&file).unwrap()).unwrap();
+
+            let target = tempfile::tempdir().unwrap();
+            let args = Args {
+                dir: target.path().to_path_buf(),
+                input: file,
+                format: None,
+                keep_lsns: false,
+                sync_every: None,
+            };
+            let mut out = Vec::new();
+            run(&args, &mut out).unwrap();
+            assert!(String::from_utf8(out)
+                .unwrap()
+                .starts_with("imported 3 records"));
+
+            let rows = |dir: &Path| {
+                let mut out = Vec::new();
+                export::run(&export::tests::args(dir, Format::Json), &mut out).unwrap();
+                String::from_utf8(out)
+                    .unwrap()
+                    .lines()
+                    .map(|l| {
+                        let row: Row = serde_json::from_str(l).unwrap();
+                        (row.lsn, row.key, row.value)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(rows(target.path()), rows(source.path()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_import_stops_at_a_bad_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("rows.jsonl");
+        std::fs::write(
+            &input,
+            concat!(
+                r#"{"lsn":null,"timestamp_ms":null,"namespace":null,"ttl_ms":null,"op":"put","compression":"none","encoding":"utf8","key":"a","value":"1"}"#,
+                "\n",
+                r#"{"op":"put"}"#,
+                "\n"
+            ),
+        )
+        .unwrap();
+        let args = Args {
+            dir: dir.path().join("wal"),
+            input,
+            format: None,
+            keep_lsns: false,
+            sync_every: None,
+        };
+        let mut out = Vec::new();
+        let err = run(&args, &mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("row 2: "), "{}", err);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("imported 1 records"));
+    }
+}
@@ -0,0 +1,87 @@
+//! `nori-wal import-aof`: seeds a WAL with the string keys a Redis
+//! append-only file leaves, with what is left of their TTLs.
+
+use nori_wal::{AofImportConfig, Wal, WalConfig};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, created if missing
+    dir: PathBuf,
+    /// Redis AOF file, or a Redis 7 appendonlydir
+    input: PathBuf,
+    /// Pass over commands other than the string ones instead of failing
+    #[arg(long)]
+    skip_unsupported: bool,
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let config = AofImportConfig {
+        skip_unsupported: args.skip_unsupported,
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let summary = runtime
+        .block_on(async {
+            let (wal, _) = Wal::open(WalConfig {
+                dir: args.dir.clone(),
+                ..WalConfig::default()
+            })
+            .await?;
+            let summary = nori_wal::aof::import(&wal, &args.input, config).await?;
+            wal.close().await?;
+            Ok::<_, nori_wal::SegmentError>(summary)
+        })
+        .map_err(io::Error::other)?;
+
+    writeln!(
+        out,
+        "imported {} keys from {} commands in {} files ({} expired)",
+        summary.records, summary.commands, summary.files, summary.expired
+    )?;
+    for (command, count) in &summary.skipped {
+        writeln!(out, "skipped {} {} commands", count, command)?;
+    }
+    if summary.truncated {
+        writeln!(out, "the last command was cut short and ignored")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_aof() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("appendonly.aof");
+        std::fs::write(
+            &input,
+            concat!(
+                "*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+                "*3\r\n$5\r\nLPUSH\r\n$1\r\nl\r\n$1\r\nx\r\n",
+                "*3\r\n$6\r\nEXPIRE\r\n$1\r\na\r\n$2\r\n60\r\n",
+            ),
+        )
+        .unwrap();
+        let mut args = Args {
+            dir: dir.path().join("wal"),
+            input,
+            skip_unsupported: false,
+        };
+        let err = run(&args, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("LPUSH"), "{}", err);
+
+        args.skip_unsupported = true;
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "imported 1 keys from 3 commands in 1 files (0 expired)\nskipped 1 LPUSH commands\n"
+        );
+    }
+}
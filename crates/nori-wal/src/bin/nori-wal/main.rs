@@ -0,0 +1,104 @@
+//! `nori-wal`: inspects WAL directories and segment files offline.
+//!
+//! Every subcommand reads the files directly rather than opening the WAL,
+//! so it is safe to point at the directory of a live or crashed process.
+
+mod advise;
+mod audit;
+mod bench;
+mod clone;
+mod du;
+mod dump;
+mod export;
+mod import;
+mod import_aof;
+mod repair;
+mod segments;
+mod stats;
+mod tail;
+mod verify;
+
+use clap::{Parser, Subcommand};
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+#[derive(Debug, Parser)]
+#[command(name = "nori-wal", version, about = "Inspect nori-wal directories")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the records in a WAL directory or segment
+    Dump(dump::Args),
+    /// Check every record and segment, printing a JSON report; exits 1 if
+    /// anything is wrong
+    Verify(verify::Args),
+    /// Discard the log from a position or the first damaged record onwards
+    Repair(repair::Args),
+    /// Per-segment sizes, record counts, LSN and time ranges, and totals
+    Stats(stats::Args),
+    /// Disk usage of every file in a WAL directory
+    Du(du::Args),
+    /// Print the last records, and with --follow the ones written after
+    Tail(tail::Args),
+    /// Write records out as JSON Lines or CSV
+    Export(export::Args),
+    /// Append the records of an export file to a WAL
+    Import(import::Args),
+    /// Copy the records of a WAL directory into a fresh one, optionally
+    /// dropping superseded records and recompressing
+    Clone(clone::Args),
+    /// Append the string keys a Redis append-only file leaves to a WAL
+    ImportAof(import_aof::Args),
+    /// Run a synthetic workload against a fresh WAL and report throughput
+    /// and latency percentiles
+    Bench(bench::Args),
+    /// Recommend a batch window, segment size and compression for the
+    /// workload recorded in a WAL directory
+    Advise(advise::Args),
+    /// Print the purges, truncations and other administrative operations
+    /// recorded in a WAL directory's audit log
+    Audit(audit::Args),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let result = match &cli.command {
+        Command::Dump(args) => dump::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Verify(args) => verify::run(args, &mut out).map(|ok| {
+            if ok {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }),
+        Command::Repair(args) => {
+            repair::run(args, &mut out, repair::ask).map(|()| ExitCode::SUCCESS)
+        }
+        Command::Stats(args) => stats::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Du(args) => du::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Tail(args) => tail::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Export(args) => export::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Import(args) => import::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Clone(args) => clone::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::ImportAof(args) => import_aof::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Bench(args) => bench::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Advise(args) => advise::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+        Command::Audit(args) => audit::run(args, &mut out).map(|()| ExitCode::SUCCESS),
+    };
+    match result.and_then(|code| out.flush().map(|()| code)) {
+        Ok(code) => code,
+        // `nori-wal dump dir | head` closing the pipe is not an error
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        // 2, like a usage error: the WAL could not be checked at all
+        Err(e) => {
+            eprintln!("nori-wal: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! `nori-wal repair`: discards the log from a position onwards, the way
+//! `SegmentManager::truncate_from` would, but offline. Repairs that discard
+//! anything are recorded in the WAL's audit log.
+
+use crate::segments::{self, Entry, SegmentFile};
+use nori_wal::audit::{self, AuditEntry};
+use nori_wal::{AuditOperation, DirLock, Position};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Args)]
+#[group(id = "cut", required = true, args = ["at", "auto"])]
+pub struct Args {
+    /// WAL directory
+    dir: PathBuf,
+    /// Discard everything from this record boundary on (SEGMENT:OFFSET)
+    #[arg(long)]
+    at: Option<Position>,
+    /// Discard everything from the first damaged record on
+    #[arg(long)]
+    auto: bool,
+    /// Show what would be discarded without changing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Don't ask for confirmation
+    #[arg(long, short)]
+    yes: bool,
+}
+
+/// One change to a segment file.
+#[derive(Debug)]
+pub enum Action {
+    /// Cut the segment at `offset`.
+    Truncate {
+        segment: SegmentFile,
+        offset: u64,
+        discarded: Discarded,
+    },
+    /// Delete the segment: the cut is at its start, or it comes after it.
+    Remove {
+        segment: SegmentFile,
+        discarded: Discarded,
+    },
+}
+
+/// What an action throws away.
+#[derive(Debug, Default)]
+pub struct Discarded {
+    pub records: u64,
+    pub bytes: u64,
+    /// LSNs of the first and last discarded records that have one.
+    pub lsns: Option<(u64, u64)>,
+}
+
+impl Discarded {
+    fn record(&mut self, lsn: Option<u64>) {
+        self.records += 1;
+        if let Some(lsn) = lsn {
+            let (first, _) = self.lsns.get_or_insert((lsn, lsn));
+            self.lsns = Some((*first, lsn));
+        }
+    }
+}
+
+/// Works out the actions that cut the WAL in `args.dir` as asked. Empty if
+/// there is nothing to discard.
+pub fn plan(args: &Args) -> io::Result<Vec<Action>> {
+    if !args.dir.is_dir() {
+        return Err(invalid(format!(
+            "{} is not a directory",
+            args.dir.display()
+        )));
+    }
+    let files = segments::find(&args.dir)?;
+    let cut = match args.at {
+        Some(at) => check_boundary(&files, at)?,
+        None => first_damage(&files)?,
+    };
+    let Some(cut) = cut else {
+        return Ok(Vec::new());
+    };
+
+    let mut actions = Vec::new();
+    for segment in files.into_iter().filter(|s| s.id >= cut.segment_id) {
+        let data = segment.read()?;
+        let from = if segment.id == cut.segment_id {
+            cut.offset
+        } else {
+            0
+        };
+        let mut discarded = Discarded {
+            bytes: data.len() as u64 - from,
+            ..Discarded::default()
+        };
+        for entry in segments::scan(segment.id, &data) {
+            if let Entry::Record {
+                position, record, ..
+            } = entry
+            {
+                if position.offset >= from {
+                    discarded.record(record.lsn);
+                }
+            }
+        }
+        actions.push(if from == 0 {
+            Action::Remove { segment, discarded }
+        } else {
+            Action::Truncate {
+                segment,
+                offset: from,
+                discarded,
+            }
+        });
+    }
+    Ok(actions)
+}
+
+/// Makes sure `at` starts a record, or the damage or end of its segment.
+/// `None` if it is at the end of the log.
+fn check_boundary(files: &[SegmentFile], at: Position) -> io::Result<Option<Position>> {
+    let Some(segment) = files.iter().find(|s| s.id == at.segment_id) else {
+        return Err(invalid(format!("no segment {:06}", at.segment_id)));
+    };
+    let data = segment.read()?;
+    let mut end = 0;
+    for entry in segments::scan(segment.id, &data) {
+        let (position, next) = match entry {
+            Entry::Record { position, size, .. } => (position, position.offset + size as u64),
+            Entry::Damaged { position, len, .. } => (position, position.offset + len),
+        };
+        if position == at {
+            return Ok(Some(at));
+        }
+        end = next;
+    }
+    let last = files.last().is_some_and(|s| s.id == at.segment_id);
+    match at.offset {
+        offset if offset == end && last => Ok(None),
+        offset if offset == end => Ok(Some(at)),
+        _ => Err(invalid(format!("{} is not a record boundary", at))),
+    }
+}
+
+/// The first damaged record in the log, if any.
+fn first_damage(files: &[SegmentFile]) -> io::Result<Option<Position>> {
+    for segment in files {
+        let data = segment.read()?;
+        let damage = segments::scan(segment.id, &data).find_map(|entry| match entry {
+            Entry::Damaged { position, .. } => Some(position),
+            Entry::Record { .. } => None,
+        });
+        if damage.is_some() {
+            return Ok(damage);
+        }
+    }
+    Ok(None)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Carries out `actions`, truncating the first segment before removing
+/// the later ones so a crash part way leaves a shorter, valid log.
+pub fn apply(actions: &[Action]) -> io::Result<()> {
+    for action in actions {
+        match action {
+            Action::Truncate {
+                segment, offset, ..
+            } => {
+                let file = OpenOptions::new().write(true).open(&segment.path)?;
+                file.set_len(*offset)?;
+                file.sync_all()?;
+            }
+            Action::Remove { segment, .. } => std::fs::remove_file(&segment.path)?,
+        }
+    }
+    Ok(())
+}
+
+fn describe(out: &mut impl Write, action: &Action) -> io::Result<()> {
+    let discarded = match action {
+        Action::Truncate {
+            segment,
+            offset,
+            discarded,
+        } => {
+            write!(out, "truncate {:06}.wal at {}: ", segment.id, offset)?;
+            discarded
+        }
+        Action::Remove { segment, discarded } => {
+            write!(out, "remove {:06}.wal: ", segment.id)?;
+            discarded
+        }
+    };
+    write!(out, "{} records", discarded.records)?;
+    if let Some((first, last)) = discarded.lsns {
+        write!(out, " (lsn {}..={})", first, last)?;
+    }
+    writeln!(out, ", {} bytes", discarded.bytes)
+}
+
+/// Plans, shows and (unless it's a dry run or `confirm` says no) applies
+/// the repair.
+pub fn run(
+    args: &Args,
+    out: &mut impl Write,
+    confirm: impl FnOnce() -> io::Result<bool>,
+) -> io::Result<()> {
+    // Held until the repair is done, so a WAL can't open the directory midway
+    let _lock = if args.dry_run {
+        None
+    } else {
+        Some(DirLock::acquire(&args.dir).map_err(io::Error::other)?)
+    };
+
+    let actions = plan(args)?;
+    if actions.is_empty() {
+        writeln!(out, "nothing to discard")?;
+        return Ok(());
+    }
+    for action in &actions {
+        describe(out, action)?;
+    }
+    if args.dry_run {
+        writeln!(out, "dry run, nothing changed")?;
+        return Ok(());
+    }
+    out.flush()?;
+    if !args.yes && !confirm()? {
+        writeln!(out, "aborted, nothing changed")?;
+        return Ok(());
+    }
+    apply(&actions)?;
+    record(&args.dir, &actions)?;
+    writeln!(out, "done")
+}
+
+/// Adds the applied `actions` to the audit log of the WAL in `dir`.
+fn record(dir: &Path, actions: &[Action]) -> io::Result<()> {
+    let Some(first) = actions.first() else {
+        return Ok(());
+    };
+    let cut = match first {
+        Action::Truncate {
+            segment, offset, ..
+        } => Position {
+            segment_id: segment.id,
+            offset: *offset,
+        },
+        Action::Remove { segment, .. } => Position {
+            segment_id: segment.id,
+            offset: 0,
+        },
+    };
+    let (records, bytes) = actions.iter().fold((0, 0), |(records, bytes), action| {
+        let discarded = match action {
+            Action::Truncate { discarded, .. } | Action::Remove { discarded, .. } => discarded,
+        };
+        (records + discarded.records, bytes + discarded.bytes)
+    });
+    let detail = format!(
+        "from {}, {} records and {} bytes discarded",
+        cut, records, bytes
+    );
+    let entry = AuditEntry::now(AuditOperation::Repair, "nori-wal repair", 0, detail);
+    tokio::runtime::Builder::new_current_thread()
+        .build()?
+        .block_on(audit::append_entry(dir, &entry))
+        .map_err(io::Error::other)
+}
+
+/// Asks on stderr and reads the answer from stdin.
+pub fn ask() -> io::Result<bool> {
+    eprint!("discard the above? [y/N] ");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::Record;
+
+    fn segment(lsns: &[u64]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &lsn in lsns {
+            let mut record = Record::put(b"k".as_slice(), b"v".as_slice());
+            record.lsn = Some(lsn);
+            data.extend_from_slice(&record.encode());
+        }
+        data
+    }
+
+    fn args(dir: &tempfile::TempDir, at: Option<Position>, dry_run: bool) -> Args {
+        Args {
+            dir: dir.path().to_path_buf(),
+            at,
+            auto: at.is_none(),
+            dry_run,
+            yes: true,
+        }
+    }
+
+    #[test]
+    fn test_auto_repair_cuts_at_first_damage() {
+        let dir = tempfile::tempdir().unwrap();
+        let record = segment(&[1]).len();
+        let mut first = segment(&[1, 2, 3]);
+        first[record + 3] ^= 0xff;
+        std::fs::write(dir.path().join("000000.wal"), &first).unwrap();
+        std::fs::write(dir.path().join("000001.wal"), segment(&[4, 5])).unwrap();
+
+        let mut out = Vec::new();
+        run(&args(&dir, None, true), &mut out, || unreachable!()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "truncate 000000.wal at {}: 0 records, {} bytes\n\
+                 remove 000001.wal: 2 records (lsn 4..=5), {} bytes\n\
+                 dry run, nothing changed\n",
+                record,
+                2 * record,
+                2 * record
+            )
+        );
+        assert!(dir.path().join("000001.wal").exists());
+
+        let mut out = Vec::new();
+        run(&args(&dir, None, false), &mut out, || unreachable!()).unwrap();
+        let len = std::fs::metadata(dir.path().join("000000.wal"))
+            .unwrap()
+            .len();
+        assert_eq!(len, record as u64);
+        assert!(!dir.path().join("000001.wal").exists());
+        let log = std::fs::read_to_string(dir.path().join(audit::AUDIT_FILE)).unwrap();
+        assert!(
+            log.contains("\"operation\":\"repair\",\"source\":\"nori-wal repair\""),
+            "{}",
+            log
+        );
+        assert!(log.contains(&format!("from 000000:{}, 2 records", record)));
+
+        let mut out = Vec::new();
+        run(&args(&dir, None, false), &mut out, || unreachable!()).unwrap();
+        assert_eq!(out, b"nothing to discard\n");
+    }
+
+    #[test]
+    fn test_repair_at_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let record = segment(&[1]).len() as u64;
+        std::fs::write(dir.path().join("000000.wal"), segment(&[1, 2])).unwrap();
+
+        let at = |offset| Position {
+            segment_id: 0,
+            offset,
+        };
+        let err = plan(&args(&dir, Some(at(1)), true)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(plan(&args(&dir, Some(at(2 * record)), true))
+            .unwrap()
+            .is_empty());
+
+        let mut declined = args(&dir, Some(at(0)), false);
+        declined.yes = false;
+        let mut out = Vec::new();
+        run(&declined, &mut out, || Ok(false)).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("aborted, nothing changed\n"));
+        assert!(dir.path().join("000000.wal").exists());
+
+        // Cutting at the start removes the segment; a running WAL blocks it
+        let lock = DirLock::acquire(dir.path()).unwrap();
+        let mut out = Vec::new();
+        run(&args(&dir, Some(at(0)), false), &mut out, || Ok(true)).unwrap_err();
+        drop(lock);
+        run(&args(&dir, Some(at(0)), false), &mut out, || Ok(true)).unwrap();
+        assert!(!dir.path().join("000000.wal").exists());
+    }
+}
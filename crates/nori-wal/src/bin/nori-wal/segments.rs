@@ -0,0 +1,112 @@
+//! Finding segment files and walking the records in them, without opening
+//! the WAL (which would recover and lock the directory).
+
+use nori_wal::{Position, Record, RecordError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A segment file on disk.
+#[derive(Debug, Clone)]
+pub struct SegmentFile {
+    pub id: u64,
+    pub path: PathBuf,
+}
+
+impl SegmentFile {
+    /// Reads the whole segment.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        std::fs::read(&self.path)
+    }
+}
+
+/// Parses a segment ID out of a `000042.wal` file name.
+fn segment_id(path: &Path) -> Option<u64> {
+    if path.extension()? != "wal" {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+/// Returns the segments at `path`, in order: every segment in a WAL
+/// directory, or the single segment `path` names.
+pub fn find(path: &Path) -> io::Result<Vec<SegmentFile>> {
+    if !path.is_dir() {
+        let id = segment_id(path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a WAL directory or segment", path.display()),
+            )
+        })?;
+        return Ok(vec![SegmentFile {
+            id,
+            path: path.to_path_buf(),
+        }]);
+    }
+
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if let Some(id) = segment_id(&path) {
+            segments.push(SegmentFile { id, path });
+        }
+    }
+    segments.sort_unstable_by_key(|s| s.id);
+    Ok(segments)
+}
+
+/// What a scan found at one offset of a segment.
+#[derive(Debug)]
+pub enum Entry {
+    /// A record that decoded and passed its CRC check.
+    Record {
+        position: Position,
+        /// Encoded size, checksum included.
+        size: usize,
+        record: Record,
+    },
+    /// Bytes that don't decode. The scan stops here: without a valid
+    /// length there is no telling where the next record starts.
+    Damaged {
+        position: Position,
+        /// Bytes from here to the end of the segment, trailing zeros
+        /// excluded.
+        len: u64,
+        error: RecordError,
+    },
+}
+
+/// Walks the records of segment `segment_id`, whose bytes are `data`.
+///
+/// Zeros at the end are pre-allocated space, not damage, and end the scan.
+pub fn scan(segment_id: u64, data: &[u8]) -> impl Iterator<Item = Entry> + '_ {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    let mut offset = 0;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done || offset >= end {
+            return None;
+        }
+        let position = Position {
+            segment_id,
+            offset: offset as u64,
+        };
+        match Record::decode(&data[offset..]) {
+            Ok((record, size)) => {
+                offset += size;
+                Some(Entry::Record {
+                    position,
+                    size,
+                    record,
+                })
+            }
+            Err(error) => {
+                done = true;
+                Some(Entry::Damaged {
+                    position,
+                    len: (end - offset) as u64,
+                    error,
+                })
+            }
+        }
+    })
+}
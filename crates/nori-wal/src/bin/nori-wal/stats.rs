@@ -0,0 +1,249 @@
+//! `nori-wal stats`: what is in each segment, and in the whole WAL.
+
+use crate::segments::{self, Entry};
+use nori_wal::Compression;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, or a single segment file
+    path: PathBuf,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+/// Figures for one segment, or for all of them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct SegmentStats {
+    /// `None` for the totals.
+    pub segment: Option<u64>,
+    /// Size of the file, pre-allocated space included.
+    pub file_bytes: u64,
+    /// Bytes taken by valid records.
+    pub record_bytes: u64,
+    pub records: u64,
+    pub deletes: u64,
+    pub compressed: u64,
+    /// Key and value bytes, before compression.
+    pub payload_bytes: u64,
+    pub first_lsn: Option<u64>,
+    pub last_lsn: Option<u64>,
+    /// Oldest and newest record timestamps, in ms since the Unix epoch.
+    pub first_ms: Option<u64>,
+    pub last_ms: Option<u64>,
+    /// Bytes after the first damaged record, which were not looked at.
+    pub damaged_bytes: u64,
+}
+
+impl SegmentStats {
+    /// Payload bytes per byte of record on disk; above 1 once compression
+    /// saves more than the record headers cost.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.record_bytes > 0).then(|| self.payload_bytes as f64 / self.record_bytes as f64)
+    }
+
+    fn add(&mut self, other: &SegmentStats) {
+        self.file_bytes += other.file_bytes;
+        self.record_bytes += other.record_bytes;
+        self.records += other.records;
+        self.deletes += other.deletes;
+        self.compressed += other.compressed;
+        self.payload_bytes += other.payload_bytes;
+        self.first_lsn = min(self.first_lsn, other.first_lsn);
+        self.last_lsn = self.last_lsn.max(other.last_lsn);
+        self.first_ms = min(self.first_ms, other.first_ms);
+        self.last_ms = self.last_ms.max(other.last_ms);
+        self.damaged_bytes += other.damaged_bytes;
+    }
+}
+
+/// The smaller of two optional values, ignoring `None`s.
+fn min(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        _ => a.or(b),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub segments: Vec<SegmentStats>,
+    pub total: SegmentStats,
+}
+
+pub fn collect(args: &Args) -> io::Result<Stats> {
+    let mut segments = Vec::new();
+    let mut total = SegmentStats::default();
+    for segment in segments::find(&args.path)? {
+        let data = segment.read()?;
+        let mut stats = SegmentStats {
+            segment: Some(segment.id),
+            file_bytes: data.len() as u64,
+            ..SegmentStats::default()
+        };
+        for entry in segments::scan(segment.id, &data) {
+            let record = match entry {
+                Entry::Record { size, record, .. } => {
+                    stats.record_bytes += size as u64;
+                    record
+                }
+                Entry::Damaged { len, .. } => {
+                    stats.damaged_bytes = len;
+                    continue;
+                }
+            };
+            stats.records += 1;
+            stats.deletes += u64::from(record.tombstone);
+            stats.compressed += u64::from(record.compression != Compression::None);
+            stats.payload_bytes += (record.key.len() + record.value.len()) as u64;
+            if let Some(lsn) = record.lsn {
+                stats.first_lsn.get_or_insert(lsn);
+                stats.last_lsn = Some(lsn);
+            }
+            let ms = record
+                .timestamp
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64);
+            stats.first_ms = min(stats.first_ms, ms);
+            stats.last_ms = stats.last_ms.max(ms);
+        }
+        total.add(&stats);
+        segments.push(stats);
+    }
+    Ok(Stats { segments, total })
+}
+
+fn range(first: Option<u64>, last: Option<u64>) -> String {
+    match (first, last) {
+        (Some(first), Some(last)) => format!("{}..={}", first, last),
+        _ => "-".to_string(),
+    }
+}
+
+fn write_table(out: &mut impl Write, stats: &Stats) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:<8} {:>12} {:>12} {:>9} {:>8} {:>10} {:>6}  {:<21}  TIME (ms)",
+        "SEGMENT", "FILE", "DATA", "RECORDS", "DELETES", "COMPRESSED", "RATIO", "LSN"
+    )?;
+    for row in stats.segments.iter().chain([&stats.total]) {
+        let name = match row.segment {
+            Some(id) => format!("{:06}", id),
+            None => "total".to_string(),
+        };
+        let ratio = row.ratio().map_or("-".to_string(), |r| format!("{:.2}", r));
+        write!(
+            out,
+            "{:<8} {:>12} {:>12} {:>9} {:>8} {:>10} {:>6}  {:<21}  {}",
+            name,
+            row.file_bytes,
+            row.record_bytes,
+            row.records,
+            row.deletes,
+            row.compressed,
+            ratio,
+            range(row.first_lsn, row.last_lsn),
+            range(row.first_ms, row.last_ms),
+        )?;
+        if row.damaged_bytes > 0 {
+            write!(out, "  ({} damaged bytes)", row.damaged_bytes)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let stats = collect(args)?;
+    match args.format {
+        Format::Table => write_table(out, &stats),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, &stats)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::Record;
+
+    #[test]
+    fn test_stats_per_segment_and_total() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut lsn = 0;
+        let mut segment = |records: Vec<Record>| {
+            let mut data = Vec::new();
+            for mut record in records {
+                lsn += 1;
+                record.lsn = Some(lsn);
+                data.extend_from_slice(&record.encode());
+            }
+            data.extend_from_slice(&[0; 16]);
+            data
+        };
+        let big = vec![b'x'; 4096];
+        let first = segment(vec![
+            Record::put(b"a".as_slice(), b"1".as_slice()),
+            Record::delete(b"a".as_slice()),
+        ]);
+        let second = segment(vec![
+            Record::put(b"b".as_slice(), big.clone()).with_compression(Compression::Lz4)
+        ]);
+        std::fs::write(dir.path().join("000000.wal"), &first).unwrap();
+        std::fs::write(dir.path().join("000001.wal"), &second).unwrap();
+
+        let args = Args {
+            path: dir.path().to_path_buf(),
+            format: Format::Json,
+        };
+        let stats = collect(&args).unwrap();
+        assert_eq!(stats.segments.len(), 2);
+        let s0 = &stats.segments[0];
+        assert_eq!((s0.records, s0.deletes, s0.payload_bytes), (2, 1, 3));
+        assert_eq!(s0.file_bytes, first.len() as u64);
+        assert_eq!(s0.record_bytes, first.len() as u64 - 16);
+        assert_eq!((s0.first_lsn, s0.last_lsn), (Some(1), Some(2)));
+        assert!(s0.ratio().unwrap() < 1.0);
+        let s1 = &stats.segments[1];
+        assert_eq!(s1.compressed, 1);
+        assert!(s1.ratio().unwrap() > 10.0);
+
+        let total = &stats.total;
+        assert_eq!(total.segment, None);
+        assert_eq!(
+            (total.records, total.first_lsn, total.last_lsn),
+            (3, Some(1), Some(3))
+        );
+        assert_eq!(total.file_bytes, (first.len() + second.len()) as u64);
+
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["total"]["records"], 3);
+
+        let args = Args {
+            format: Format::Table,
+            ..args
+        };
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let table = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("SEGMENT"));
+        assert!(lines[1].starts_with("000000"));
+        assert!(lines[3].starts_with("total"));
+        assert!(lines[3].contains("1..=3"));
+    }
+}
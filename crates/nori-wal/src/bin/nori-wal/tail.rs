@@ -0,0 +1,262 @@
+//! `nori-wal tail`: the last records of a WAL, and optionally the ones
+//! written after them.
+//!
+//! `WalTail` is woken by the writer in its own process, and opening the WAL
+//! here would need the directory lock a live node holds. So this follows
+//! the segment files instead, polling for new bytes the way `tail -f`
+//! does, and only ever reads.
+
+use crate::dump;
+use crate::segments;
+use nori_wal::{Position, Record, RecordError};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to look for new records once caught up.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Bytes read at a time; doubled while a record doesn't fit.
+const CHUNK: usize = 64 * 1024;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory
+    dir: PathBuf,
+    /// Keep printing records as they are written
+    #[arg(long, short)]
+    follow: bool,
+    /// Only print records whose key starts with this
+    #[arg(long)]
+    prefix: Option<String>,
+    /// Records to print before following
+    #[arg(long, short = 'n', default_value_t = 10)]
+    lines: usize,
+    /// Print keys and values as hex instead of lossy UTF-8
+    #[arg(long)]
+    hex: bool,
+}
+
+/// Reads records from segment files as they grow, moving on to the next
+/// segment once one appears.
+#[derive(Debug)]
+pub struct Follower {
+    dir: PathBuf,
+    position: Position,
+}
+
+impl Follower {
+    /// Starts at the beginning of the oldest segment in `dir`.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        let segment_id = segments::find(dir)?.first().map_or(0, |s| s.id);
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            position: Position {
+                segment_id,
+                offset: 0,
+            },
+        })
+    }
+
+    /// Hands every record written since the last call to `f`, in order.
+    pub fn drain(&mut self, mut f: impl FnMut(Position, usize, Record)) -> io::Result<()> {
+        loop {
+            self.drain_segment(&mut f)?;
+            let next = segments::find(&self.dir)?
+                .into_iter()
+                .find(|s| s.id > self.position.segment_id);
+            let Some(next) = next else {
+                return Ok(());
+            };
+            // The writer is done with a segment before it creates the next,
+            // so one more pass sees all of it; bytes that still don't decode
+            // never will
+            if !self.drain_segment(&mut f)? {
+                eprintln!(
+                    "nori-wal: skipping damaged records from {} to the end of the segment",
+                    self.position
+                );
+            }
+            self.position = Position {
+                segment_id: next.id,
+                offset: 0,
+            };
+        }
+    }
+
+    /// Reads the current segment as far as it decodes. Returns whether the
+    /// segment ended cleanly, as opposed to at bytes that don't decode:
+    /// damage, or a record still being written.
+    fn drain_segment(&mut self, f: &mut impl FnMut(Position, usize, Record)) -> io::Result<bool> {
+        let path = self
+            .dir
+            .join(format!("{:06}.wal", self.position.segment_id));
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            // Purged under us
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+
+        let mut chunk = CHUNK;
+        loop {
+            file.seek(SeekFrom::Start(self.position.offset))?;
+            let mut buf = Vec::new();
+            (&mut file).take(chunk as u64).read_to_end(&mut buf)?;
+
+            let mut consumed = 0;
+            let stopped = loop {
+                let rest = &buf[consumed..];
+                // Pre-allocated space the writer hasn't reached
+                if rest.iter().all(|&b| b == 0) {
+                    break None;
+                }
+                match Record::decode(rest) {
+                    Ok((record, size)) => {
+                        let position = Position {
+                            offset: self.position.offset + consumed as u64,
+                            ..self.position
+                        };
+                        consumed += size;
+                        f(position, size, record);
+                    }
+                    Err(e) => break Some(e),
+                }
+            };
+            self.position.offset += consumed as u64;
+
+            match stopped {
+                None => return Ok(true),
+                // The chunk cut a record short: read a bigger one
+                Some(RecordError::Incomplete) if buf.len() == chunk && consumed == 0 => chunk *= 2,
+                Some(_) if consumed > 0 => chunk = CHUNK,
+                Some(_) => return Ok(false),
+            }
+        }
+    }
+}
+
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<()> {
+    let prefix = args.prefix.as_deref().unwrap_or("").as_bytes();
+    let mut follower = Follower::new(&args.dir)?;
+
+    let mut last = VecDeque::with_capacity(args.lines);
+    follower.drain(|position, size, record| {
+        if args.lines > 0 && record.key.starts_with(prefix) {
+            if last.len() == args.lines {
+                last.pop_front();
+            }
+            last.push_back((position, size, record));
+        }
+    })?;
+    for (position, size, record) in &last {
+        dump::write_record(out, *position, *size, record, args.hex)?;
+    }
+    out.flush()?;
+
+    if !args.follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut result = Ok(());
+        follower.drain(|position, size, record| {
+            if result.is_ok() && record.key.starts_with(prefix) {
+                result = dump::write_record(out, position, size, &record, args.hex);
+            }
+        })?;
+        result?;
+        out.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(key: &str) -> Vec<u8> {
+        Record::put(key.as_bytes().to_vec(), b"v".as_slice())
+            .encode()
+            .to_vec()
+    }
+
+    fn drain(follower: &mut Follower) -> Vec<(Position, String)> {
+        let mut seen = Vec::new();
+        follower
+            .drain(|position, _, record| {
+                seen.push((position, String::from_utf8_lossy(&record.key).into_owned()))
+            })
+            .unwrap();
+        seen
+    }
+
+    #[test]
+    fn test_follows_growing_segments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("000003.wal");
+        let first = encode("a");
+        let mut data = first.clone();
+        data.extend_from_slice(&[0; 256]);
+        std::fs::write(&path, &data).unwrap();
+
+        let mut follower = Follower::new(dir.path()).unwrap();
+        let keys: Vec<_> = drain(&mut follower).into_iter().map(|(_, k)| k).collect();
+        assert_eq!(keys, ["a"]);
+
+        // Half a record is not read until the rest of it lands
+        let second = encode("b");
+        let (head, rest) = second.split_at(4);
+        data[first.len()..first.len() + 4].copy_from_slice(head);
+        std::fs::write(&path, &data).unwrap();
+        assert!(drain(&mut follower).is_empty());
+        data[first.len() + 4..first.len() + second.len()].copy_from_slice(rest);
+        std::fs::write(&path, &data).unwrap();
+        let seen = drain(&mut follower);
+        assert_eq!(
+            seen,
+            [(
+                Position {
+                    segment_id: 3,
+                    offset: first.len() as u64
+                },
+                "b".to_string()
+            )]
+        );
+
+        // A record bigger than a chunk, in the next segment
+        let big = "k".repeat(3 * CHUNK);
+        std::fs::write(dir.path().join("000004.wal"), encode(&big)).unwrap();
+        let seen = drain(&mut follower);
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].0.segment_id, 4);
+        assert_eq!(seen[0].1.len(), big.len());
+        assert!(drain(&mut follower).is_empty());
+    }
+
+    #[test]
+    fn test_tail_prints_last_matching_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = ["user/1", "order/1", "user/2", "user/3"]
+            .into_iter()
+            .flat_map(encode)
+            .collect();
+        std::fs::write(dir.path().join("000000.wal"), data).unwrap();
+
+        let args = Args {
+            dir: dir.path().to_path_buf(),
+            follow: false,
+            prefix: Some("user/".to_string()),
+            lines: 2,
+            hex: false,
+        };
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let keys: Vec<_> = out
+            .lines()
+            .map(|l| l.split("key=").nth(1).unwrap())
+            .collect();
+        assert_eq!(keys, [r#""user/2" value="v""#, r#""user/3" value="v""#]);
+    }
+}
@@ -0,0 +1,281 @@
+//! `nori-wal verify`: checks a WAL directory offline and prints a JSON
+//! report of anything wrong with it. In a hash-chained WAL it also checks
+//! the links between records, and that the log still holds a head attested
+//! earlier.
+
+use crate::segments::{self, Entry};
+use nori_wal::{ChainHead, ChainVerifier};
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// WAL directory, or a single segment file
+    path: PathBuf,
+    /// Chain head exported earlier (SEGMENT:OFFSET@CHECKSUM) that the log
+    /// must still hold
+    #[arg(long)]
+    chain_head: Option<ChainHead>,
+}
+
+/// What `verify` prints.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub segments: u64,
+    pub records: u64,
+    /// Bytes of valid records, pre-allocated space excluded.
+    pub bytes: u64,
+    pub first_segment: Option<u64>,
+    pub last_segment: Option<u64>,
+    pub last_lsn: Option<u64>,
+    /// Head of the hash chain, if any record is chained.
+    pub chain_head: Option<String>,
+    pub problems: Vec<Problem>,
+}
+
+/// Something wrong with the WAL.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A segment file could not be read.
+    Unreadable { segment: u64, error: String },
+    /// Bytes that are not a valid record: a CRC mismatch or bad framing.
+    /// Nothing after them in the segment could be checked.
+    Damaged {
+        position: String,
+        bytes: u64,
+        error: String,
+    },
+    /// Segment IDs skip from `after` to `next`.
+    MissingSegments { after: u64, next: u64 },
+    /// A record's LSN is not above the one before it.
+    LsnNotIncreasing {
+        position: String,
+        previous: u64,
+        lsn: u64,
+    },
+    /// A record's hash-chain link does not match the record before it.
+    ChainBroken { position: String },
+    /// The attested chain head is gone, or has another hash.
+    ChainHeadMismatch { head: String },
+}
+
+/// Checks every segment at `path`.
+pub fn check(args: &Args) -> io::Result<Report> {
+    let mut report = Report::default();
+    let mut previous_lsn: Option<u64> = None;
+    let mut chain = ChainVerifier::new(args.chain_head);
+
+    for segment in segments::find(&args.path)? {
+        if let Some(last) = report.last_segment {
+            if segment.id != last + 1 {
+                report.problems.push(Problem::MissingSegments {
+                    after: last,
+                    next: segment.id,
+                });
+                chain.restart();
+            }
+        }
+        report.first_segment.get_or_insert(segment.id);
+        report.last_segment = Some(segment.id);
+        report.segments += 1;
+
+        let data = match segment.read() {
+            Ok(data) => data,
+            Err(e) => {
+                report.problems.push(Problem::Unreadable {
+                    segment: segment.id,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        for entry in segments::scan(segment.id, &data) {
+            match entry {
+                Entry::Record {
+                    position,
+                    size,
+                    record,
+                } => {
+                    report.records += 1;
+                    report.bytes += size as u64;
+                    let start = position.offset as usize;
+                    chain
+                        .check(position, &data[start..start + size])
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    let Some(lsn) = record.lsn else { continue };
+                    if let Some(previous) = previous_lsn.filter(|&p| lsn <= p) {
+                        report.problems.push(Problem::LsnNotIncreasing {
+                            position: position.to_string(),
+                            previous,
+                            lsn,
+                        });
+                    }
+                    previous_lsn = Some(lsn);
+                    report.last_lsn = Some(lsn);
+                }
+                Entry::Damaged {
+                    position,
+                    len,
+                    error,
+                } => report.problems.push(Problem::Damaged {
+                    position: position.to_string(),
+                    bytes: len,
+                    error: error.to_string(),
+                }),
+            }
+        }
+    }
+
+    let chain = chain.finish();
+    if chain.unchained < chain.records {
+        report.chain_head = chain.head.map(|head| head.to_string());
+    }
+    report
+        .problems
+        .extend(chain.breaks.iter().map(|position| Problem::ChainBroken {
+            position: position.to_string(),
+        }));
+    if let (Some(false), Some(head)) = (chain.attested, args.chain_head) {
+        report.problems.push(Problem::ChainHeadMismatch {
+            head: head.to_string(),
+        });
+    }
+
+    report.ok = report.problems.is_empty();
+    Ok(report)
+}
+
+/// Prints the report, returning whether the WAL is intact.
+pub fn run(args: &Args, out: &mut impl Write) -> io::Result<bool> {
+    let report = check(args)?;
+    serde_json::to_writer_pretty(&mut *out, &report)?;
+    writeln!(out)?;
+    Ok(report.ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_wal::{Position, Record};
+
+    fn segment(lsns: &[u64]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for &lsn in lsns {
+            let mut record = Record::put(b"k".as_slice(), b"v".as_slice());
+            record.lsn = Some(lsn);
+            data.extend_from_slice(&record.encode());
+        }
+        data.extend_from_slice(&[0; 32]);
+        data
+    }
+
+    #[test]
+    fn test_verify_reports_each_kind_of_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = Args {
+            path: dir.path().to_path_buf(),
+            chain_head: None,
+        };
+        std::fs::write(dir.path().join("000001.wal"), segment(&[1, 2])).unwrap();
+        std::fs::write(dir.path().join("000002.wal"), segment(&[3, 4])).unwrap();
+        std::fs::write(dir.path().join("LOCK"), b"").unwrap();
+
+        let report = check(&args).unwrap();
+        assert!(report.ok, "{:?}", report.problems);
+        assert_eq!(report.segments, 2);
+        assert_eq!(report.records, 4);
+        assert_eq!(report.first_segment, Some(1));
+        assert_eq!(report.last_lsn, Some(4));
+
+        // Segment 3 goes missing, segment 4 repeats an LSN and is damaged
+        let mut data = segment(&[4, 5]);
+        let first = segment(&[4]).len() - 32;
+        data[first + 2] ^= 0xff;
+        std::fs::write(dir.path().join("000004.wal"), data).unwrap();
+
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        let report = check(&args).unwrap();
+        assert_eq!(report.records, 5);
+        assert_eq!(report.problems.len(), 3, "{:?}", report.problems);
+        assert_eq!(
+            report.problems[0],
+            Problem::MissingSegments { after: 2, next: 4 }
+        );
+        assert_eq!(
+            report.problems[1],
+            Problem::LsnNotIncreasing {
+                position: "000004:0".to_string(),
+                previous: 4,
+                lsn: 4,
+            }
+        );
+        assert!(matches!(
+            &report.problems[2],
+            Problem::Damaged { position, .. } if *position == format!("000004:{}", first)
+        ));
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["problems"][0]["kind"], "missing_segments");
+    }
+
+    #[test]
+    fn test_verify_checks_the_hash_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut data = Vec::new();
+        let mut link = [0; 32];
+        let mut heads = Vec::new();
+        for i in 0..4 {
+            let mut record = Record::put(format!("k{}", i), "v");
+            record.chain = Some(link);
+            let encoded = record.encode();
+            link = nori_wal::record_hash(&encoded);
+            let position = Position {
+                segment_id: 0,
+                offset: data.len() as u64,
+            };
+            heads.push(
+                ChainHead {
+                    position,
+                    hash: link,
+                }
+                .to_string(),
+            );
+            data.extend_from_slice(&encoded);
+        }
+        let path = dir.path().join("000000.wal");
+        std::fs::write(&path, &data).unwrap();
+        let args = |head: &str| Args {
+            path: dir.path().to_path_buf(),
+            chain_head: Some(head.parse().unwrap()),
+        };
+
+        let report = check(&args(&heads[1])).unwrap();
+        assert!(report.ok, "{:?}", report.problems);
+        assert_eq!(report.chain_head.as_deref(), Some(heads[3].as_str()));
+
+        // Swap the second record for another with a valid checksum
+        let (start, _) = heads[1][7..].split_once('@').unwrap();
+        let start: usize = start.parse().unwrap();
+        let mut forged = Record::put("k1", "w");
+        let (previous, _) = Record::decode(&data[start..]).unwrap();
+        forged.chain = previous.chain;
+        data.splice(start..start + forged.encode().len(), forged.encode());
+        std::fs::write(&path, &data).unwrap();
+
+        let report = check(&args(&heads[1])).unwrap();
+        assert!(!report.ok);
+        assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+        assert!(matches!(&report.problems[0], Problem::ChainBroken { .. }));
+        assert_eq!(
+            report.problems[1],
+            Problem::ChainHeadMismatch {
+                head: heads[1].clone()
+            }
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Synchronous WAL for callers without an async runtime.
+//!
+//! [`Wal`] wraps the async [`crate::Wal`] and runs each operation to
+//! completion on a private runtime with a single worker thread, which also
+//! hosts background work such as scrubbing. CLI tools and other synchronous
+//! programs can use it without setting up tokio themselves.
+//!
+//! ```no_run
+//! use nori_wal::blocking::Wal;
+//! use nori_wal::{Position, Record, WalConfig};
+//!
+//! # fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default())?;
+//! wal.append(&Record::put(b"key".as_slice(), b"value".as_slice()))?;
+//! wal.sync()?;
+//!
+//! let start = Position { segment_id: 0, offset: 0 };
+//! for entry in wal.reader(start) {
+//!     let (record, position) = entry?;
+//!     println!("{:?} at {:?}", record.key, position);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Methods block the calling thread, so they must not be called from inside
+//! an async runtime; use [`crate::Wal`] there instead.
+
+use crate::checkpoint::Checkpoint;
+use crate::metrics::WalMetrics;
+use crate::record::Record;
+use crate::recovery::RecoveryInfo;
+use crate::segment::{Position, SegmentError};
+use crate::wal::WalConfig;
+use nori_observe::{Meter, NoopMeter};
+use std::sync::Arc;
+use tokio::runtime::{Handle, Runtime};
+
+/// Synchronous handle to a WAL. See the [module docs](self).
+pub struct Wal {
+    // Declared before `runtime` so the WAL is dropped while its runtime is
+    // still alive
+    inner: crate::Wal,
+    runtime: Runtime,
+}
+
+impl Wal {
+    /// Opens a WAL, performing recovery if needed.
+    pub fn open(config: WalConfig) -> Result<(Self, RecoveryInfo), SegmentError> {
+        Self::open_with_meter(config, Arc::new(NoopMeter))
+    }
+
+    /// Opens a WAL with a custom observability meter.
+    pub fn open_with_meter(
+        config: WalConfig,
+        meter: Arc<dyn Meter>,
+    ) -> Result<(Self, RecoveryInfo), SegmentError> {
+        let runtime = runtime()?;
+        let (inner, info) = runtime.block_on(crate::Wal::open_with_meter(config, meter))?;
+        Ok((Self { inner, runtime }, info))
+    }
+
+    /// Opens a WAL, passing every recovered record to `replay` in log order.
+    pub fn open_with_replay<F>(
+        config: WalConfig,
+        replay: F,
+    ) -> Result<(Self, RecoveryInfo), SegmentError>
+    where
+        F: FnMut(Record, Position) + Send,
+    {
+        let runtime = runtime()?;
+        let (inner, info) = runtime.block_on(crate::Wal::open_with_replay(config, replay))?;
+        Ok((Self { inner, runtime }, info))
+    }
+
+    /// Appends a record, returning the position it was written at.
+    pub fn append(&self, record: &Record) -> Result<Position, SegmentError> {
+        self.runtime.block_on(self.inner.append(record))
+    }
+
+    /// Appends records as one write, returning their positions.
+    pub fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        self.runtime.block_on(self.inner.append_batch(records))
+    }
+
+    /// Flushes buffered data to the OS (but doesn't fsync).
+    pub fn flush(&self) -> Result<(), SegmentError> {
+        self.runtime.block_on(self.inner.flush())
+    }
+
+    /// Syncs all data to disk (fsync).
+    pub fn sync(&self) -> Result<(), SegmentError> {
+        self.runtime.block_on(self.inner.sync())
+    }
+
+    /// Returns the current write position in the WAL.
+    pub fn current_position(&self) -> Position {
+        self.runtime.block_on(self.inner.current_position())
+    }
+
+    /// Returns the position up to which appended records are known to be durable.
+    pub fn durable_position(&self) -> Position {
+        self.runtime.block_on(self.inner.durable_position())
+    }
+
+    /// Returns the LSN that the next appended record will be assigned.
+    pub fn next_lsn(&self) -> u64 {
+        self.inner.next_lsn()
+    }
+
+    /// Returns counts, sizes and latency percentiles. See
+    /// [`crate::Wal::metrics`].
+    pub fn metrics(&self) -> WalMetrics {
+        self.runtime.block_on(self.inner.metrics())
+    }
+
+    /// Returns an iterator over the records from `position` up to the durable
+    /// end of the log, across segments.
+    pub fn reader(&self, position: Position) -> WalReader {
+        WalReader {
+            inner: self.inner.reader(position),
+            handle: self.runtime.handle().clone(),
+        }
+    }
+
+    /// Returns an iterator starting at the first record whose LSN is at
+    /// least `lsn`. See [`crate::Wal::read_from_lsn`].
+    pub fn read_from_lsn(&self, lsn: u64) -> Result<WalReader, SegmentError> {
+        let inner = self.runtime.block_on(self.inner.read_from_lsn(lsn))?;
+        Ok(WalReader {
+            inner,
+            handle: self.runtime.handle().clone(),
+        })
+    }
+
+    /// Discards every record at and after `position`. See
+    /// [`crate::Wal::truncate_from`].
+    pub fn truncate_from(&self, position: Position) -> Result<u64, SegmentError> {
+        self.runtime.block_on(self.inner.truncate_from(position))
+    }
+
+    /// Deletes whole segments before `position`. See
+    /// [`crate::Wal::delete_segments_before`].
+    pub fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
+        self.runtime
+            .block_on(self.inner.delete_segments_before(position))
+    }
+
+    /// Records a durable checkpoint. See [`crate::Wal::checkpoint`].
+    pub fn checkpoint(&self, position: Position) -> Result<Checkpoint, SegmentError> {
+        self.runtime.block_on(self.inner.checkpoint(position))
+    }
+
+    /// Returns the most recent checkpoint, if any.
+    pub fn last_checkpoint(&self) -> Option<Checkpoint> {
+        self.runtime.block_on(self.inner.last_checkpoint())
+    }
+
+    /// Returns the WAL's configuration.
+    pub fn config(&self) -> &WalConfig {
+        self.inner.config()
+    }
+
+    /// Gracefully closes the WAL. See [`crate::Wal::close`].
+    pub fn close(self) -> Result<(), SegmentError> {
+        let Self { inner, runtime } = self;
+        runtime.block_on(inner.close())
+    }
+}
+
+/// Iterator over WAL records, created by [`Wal::reader`].
+///
+/// Stops at the durable end of the log as of each call, and after the first
+/// error.
+pub struct WalReader {
+    inner: crate::WalReader,
+    handle: Handle,
+}
+
+impl WalReader {
+    /// Returns the position of the next record to be read.
+    pub fn position(&self) -> Position {
+        self.inner.position()
+    }
+}
+
+impl Iterator for WalReader {
+    type Item = Result<(Record, Position), SegmentError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.handle.block_on(self.inner.next_record()).transpose()
+    }
+}
+
+/// Builds the runtime a blocking WAL runs on.
+fn runtime() -> Result<Runtime, SegmentError> {
+    Ok(tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("nori-wal")
+        .enable_time()
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_blocking_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config.clone()).unwrap();
+        let start = wal.current_position();
+        for i in 0..5 {
+            let key = format!("key{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), b"v".as_slice()))
+                .unwrap();
+        }
+        wal.sync().unwrap();
+        assert_eq!(wal.durable_position(), wal.current_position());
+
+        let keys: Vec<_> = wal
+            .reader(start)
+            .map(|entry| entry.unwrap().0.key)
+            .collect();
+        assert_eq!(keys.len(), 5);
+        assert_eq!(keys[4].as_ref(), b"key4");
+        wal.close().unwrap();
+
+        let mut replayed = 0;
+        let (wal, info) = Wal::open_with_replay(config, |_, _| replayed += 1).unwrap();
+        assert_eq!(info.valid_records, 5);
+        assert_eq!(replayed, 5);
+        assert_eq!(wal.next_lsn(), 6);
+    }
+}
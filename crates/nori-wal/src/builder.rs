@@ -0,0 +1,491 @@
+//! Fluent construction of a [`Wal`].
+//!
+//! ```no_run
+//! use nori_wal::{FsyncPolicy, Wal};
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let (wal, info) = Wal::builder()
+//!     .dir("/var/lib/app/wal")
+//!     .fsync(FsyncPolicy::Always)
+//!     .segment_size(64 << 20)
+//!     .open()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every setter maps onto a [`WalConfig`] field, and anything not set keeps
+//! its default, so new options can be added without breaking callers.
+
+use crate::clock::{Clock, SystemClock};
+use crate::fs::{Fs, LocalFs};
+use crate::memory::MemoryBudget;
+use crate::record::Record;
+use crate::recovery::{RecoveryBudget, RecoveryInfo, RecoveryMode, RecoveryTarget};
+use crate::runtime::{Runtime, TokioRuntime};
+use crate::segment::{FsyncPolicy, Position, SegmentError};
+use crate::slo::LatencySlo;
+use crate::store::SegmentStore;
+use crate::wal::{Wal, WalConfig};
+use nori_observe::{Meter, NoopMeter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Builder for a [`Wal`], created with [`Wal::builder`].
+#[derive(Clone)]
+pub struct WalBuilder {
+    config: WalConfig,
+    meter: Arc<dyn Meter>,
+    runtime: Arc<dyn Runtime>,
+    fs: Arc<dyn Fs>,
+    segment_store: Option<Arc<dyn SegmentStore>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for WalBuilder {
+    fn default() -> Self {
+        Self::from_config(WalConfig::default())
+    }
+}
+
+impl WalBuilder {
+    /// Starts from an existing configuration rather than the defaults.
+    pub fn from_config(config: WalConfig) -> Self {
+        Self {
+            config,
+            meter: Arc::new(NoopMeter),
+            runtime: Arc::new(TokioRuntime),
+            fs: Arc::new(LocalFs),
+            segment_store: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Directory to store WAL segments in.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.dir = dir.into();
+        self
+    }
+
+    /// Fsync policy for durability.
+    pub fn fsync(mut self, policy: FsyncPolicy) -> Self {
+        self.config.fsync_policy = policy;
+        self
+    }
+
+    /// Size in bytes at which segments rotate.
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.config.max_segment_size = bytes;
+        self
+    }
+
+    /// Whether new segment files are pre-allocated.
+    pub fn preallocate(mut self, enabled: bool) -> Self {
+        self.config.preallocate = enabled;
+        self
+    }
+
+    /// Node ID for observability events.
+    pub fn node_id(mut self, node_id: u32) -> Self {
+        self.config.node_id = node_id;
+        self
+    }
+
+    /// Meter that receives the WAL's events and metrics.
+    pub fn meter(mut self, meter: Arc<dyn Meter>) -> Self {
+        self.meter = meter;
+        self
+    }
+
+    /// Runtime for the WAL's background tasks, timers and blocking work.
+    pub fn runtime(mut self, runtime: Arc<dyn Runtime>) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Filesystem the segments and their sidecar files are stored on, such
+    /// as a [`SimFs`](crate::sim::SimFs) in crash tests.
+    pub fn fs(mut self, fs: Arc<dyn Fs>) -> Self {
+        self.fs = fs;
+        self
+    }
+
+    /// Store that keeps the segment bytes, instead of segment files on the
+    /// [`fs`](Self::fs). Recovery, compaction, scrubbing and verification
+    /// go through it; sidecar files stay on the `fs`. See [`SegmentStore`]
+    /// for the operations that still require segments on the `fs`.
+    pub fn segment_store(mut self, store: Arc<dyn SegmentStore>) -> Self {
+        self.segment_store = Some(store);
+        self
+    }
+
+    /// Clock for the batch-fsync window, age-based rotation, record
+    /// timestamps and TTL expiry, such as a
+    /// [`MockClock`](crate::clock::MockClock) in tests.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether bytes discarded by recovery are kept under `quarantine/`.
+    pub fn quarantine_corrupted(mut self, enabled: bool) -> Self {
+        self.config.quarantine_corrupted = enabled;
+        self
+    }
+
+    /// How recovery handles records that fail to decode.
+    pub fn recovery_mode(mut self, mode: RecoveryMode) -> Self {
+        self.config.recovery_mode = mode;
+        self
+    }
+
+    /// Scrubs sealed segments in the background at this interval.
+    pub fn scrub_interval(mut self, interval: Duration) -> Self {
+        self.config.scrub_interval = Some(interval);
+        self
+    }
+
+    /// Compacts old sealed segments in the background at this interval.
+    pub fn compaction_interval(mut self, interval: Duration) -> Self {
+        self.config.compaction_interval = Some(interval);
+        self
+    }
+
+    /// Whether each segment is verified in the background once sealed.
+    pub fn verify_on_seal(mut self, enabled: bool) -> Self {
+        self.config.verify_on_seal = enabled;
+        self
+    }
+
+    /// Whether verified sealed segments get seal sidecars.
+    pub fn seal_segments(mut self, enabled: bool) -> Self {
+        self.config.seal_segments = enabled;
+        self
+    }
+
+    /// Whether administrative operations are recorded in the audit log
+    /// (off by default).
+    pub fn audit_log(mut self, enabled: bool) -> Self {
+        self.config.audit_log = enabled;
+        self
+    }
+
+    /// Whether each appended record links to the one before it.
+    pub fn hash_chain(mut self, enabled: bool) -> Self {
+        self.config.hash_chain = enabled;
+        self
+    }
+
+    /// Memory kept for recently read records; zero disables the cache.
+    pub fn record_cache_bytes(mut self, bytes: u64) -> Self {
+        self.config.record_cache_bytes = bytes;
+        self
+    }
+
+    /// Appends compressed on the blocking pool at once; zero compresses
+    /// inline.
+    pub fn encode_workers(mut self, workers: usize) -> Self {
+        self.config.encode_workers = workers;
+        self
+    }
+
+    /// Bytes of values to compress before an append offloads them.
+    pub fn encode_offload_bytes(mut self, bytes: u64) -> Self {
+        self.config.encode_offload_bytes = bytes;
+        self
+    }
+
+    /// Whether batches leave out puts replaced later in the batch.
+    pub fn coalesce_keys(mut self, enabled: bool) -> Self {
+        self.config.coalesce_keys = enabled;
+        self
+    }
+
+    /// Whether keys whose TTL ran out can be listed and deleted.
+    pub fn track_expiry(mut self, enabled: bool) -> Self {
+        self.config.track_expiry = enabled;
+        self
+    }
+
+    /// Memory budget to share with other WALs.
+    pub fn memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.config.memory_budget = Some(budget);
+        self
+    }
+
+    /// Alerts when appends miss this latency objective too often.
+    pub fn append_slo(mut self, slo: LatencySlo) -> Self {
+        self.config.append_slo = Some(slo);
+        self
+    }
+
+    /// Alerts when fsyncs miss this latency objective too often.
+    pub fn fsync_slo(mut self, slo: LatencySlo) -> Self {
+        self.config.fsync_slo = Some(slo);
+        self
+    }
+
+    /// Stops recovery at this LSN or timestamp.
+    pub fn recovery_target(mut self, target: RecoveryTarget) -> Self {
+        self.config.recovery_target = Some(target);
+        self
+    }
+
+    /// Whether records past the recovery target are removed from disk.
+    pub fn truncate_after_target(mut self, enabled: bool) -> Self {
+        self.config.truncate_after_target = enabled;
+        self
+    }
+
+    /// Whether records with an expired TTL are left out of replay.
+    pub fn skip_expired_on_replay(mut self, enabled: bool) -> Self {
+        self.config.skip_expired_on_replay = enabled;
+        self
+    }
+
+    /// Bound on replay work done before `open` returns.
+    pub fn recovery_budget(mut self, budget: RecoveryBudget) -> Self {
+        self.config.recovery_budget = budget;
+        self
+    }
+
+    /// Whether a bounded recovery is finished by a background task.
+    pub fn resume_recovery_in_background(mut self, enabled: bool) -> Self {
+        self.config.resume_recovery_in_background = enabled;
+        self
+    }
+
+    /// Number of JSON recovery reports to keep.
+    pub fn recovery_reports_kept(mut self, count: usize) -> Self {
+        self.config.recovery_reports_kept = count;
+        self
+    }
+
+    /// Whether segments wholly before each new checkpoint are deleted.
+    pub fn purge_on_checkpoint(mut self, enabled: bool) -> Self {
+        self.config.purge_on_checkpoint = enabled;
+        self
+    }
+
+    /// Largest encoded record that appends accept.
+    pub fn max_record_size(mut self, bytes: u64) -> Self {
+        self.config.max_record_size = Some(bytes);
+        self
+    }
+
+    /// Rotates the active segment once it has held records for this long.
+    pub fn max_segment_age(mut self, age: Duration) -> Self {
+        self.config.max_segment_age = Some(age);
+        self
+    }
+
+    /// Whether unsynced appends are fsynced, best-effort, if the WAL is
+    /// dropped without being closed.
+    pub fn sync_on_drop(mut self, enabled: bool) -> Self {
+        self.config.sync_on_drop = enabled;
+        self
+    }
+
+    /// Reports fsyncs taking at least this long with a `SlowFsync` event.
+    pub fn slow_fsync_threshold(mut self, threshold: Duration) -> Self {
+        self.config.slow_fsync_threshold = Some(threshold);
+        self
+    }
+
+    /// Returns the configuration built so far.
+    pub fn config(&self) -> &WalConfig {
+        &self.config
+    }
+
+    /// Opens the WAL, performing recovery if needed.
+    pub async fn open(self) -> Result<(Wal, RecoveryInfo), SegmentError> {
+        Wal::open_inner(
+            self.config,
+            self.meter,
+            self.runtime,
+            self.fs,
+            self.segment_store,
+            self.clock,
+            None,
+        )
+        .await
+    }
+
+    /// Opens the WAL, passing every recovered record to `replay` in log
+    /// order. See [`Wal::open_with_replay`].
+    pub async fn open_with_replay<F>(
+        self,
+        mut replay: F,
+    ) -> Result<(Wal, RecoveryInfo), SegmentError>
+    where
+        F: FnMut(Record, Position) + Send,
+    {
+        Wal::open_inner(
+            self.config,
+            self.meter,
+            self.runtime,
+            self.fs,
+            self.segment_store,
+            self.clock,
+            Some(&mut replay),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::copy::CopyOptions;
+    use crate::sim::SimFs;
+    use crate::store::FsSegmentStore;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_builder_opens_with_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let builder = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Always)
+            .segment_size(64 << 20)
+            .seal_segments(true)
+            .node_id(7);
+        assert_eq!(builder.config().max_segment_size, 64 << 20);
+        assert_eq!(builder.config().fsync_policy, FsyncPolicy::Always);
+        assert!(builder.config().seal_segments);
+        assert_eq!(builder.config().node_id, 7);
+        // Untouched fields keep their defaults
+        assert!(builder.config().preallocate);
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        wal.append(&Record::put(b"k".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.close().await.unwrap();
+
+        let mut replayed = Vec::new();
+        let (_wal, info) = builder
+            .open_with_replay(|record, _| replayed.push(record.key))
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 1);
+        assert_eq!(replayed, vec![bytes::Bytes::from_static(b"k")]);
+
+        let invalid = Wal::builder().dir(temp_dir.path()).segment_size(0);
+        assert!(matches!(invalid.open().await, Err(SegmentError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_builder_recovers_through_segment_store() {
+        // Segments on one filesystem, sidecar files on another
+        let sidecar_fs = Arc::new(SimFs::new());
+        let segment_fs = Arc::new(SimFs::new());
+        let dir = PathBuf::from("/sim/wal");
+        segment_fs.create_dir_all(&dir).await.unwrap();
+        let store = Arc::new(FsSegmentStore::new(segment_fs.clone()));
+        let builder = Wal::builder()
+            .dir(&dir)
+            .fsync(FsyncPolicy::Always)
+            .preallocate(false)
+            .fs(sidecar_fs.clone())
+            .segment_store(store.clone());
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        wal.append(&Record::put(b"k".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.close().await.unwrap();
+        assert_eq!(store.list(&dir).await.unwrap(), vec![0]);
+        assert!(sidecar_fs
+            .read_dir(&dir)
+            .await
+            .unwrap()
+            .iter()
+            .all(|path| path.extension().map_or(true, |ext| ext != "wal")));
+
+        // A torn tail in the store is found and cut off by recovery
+        let len = store.len(&dir, 0).await.unwrap();
+        let file = store.open_append(&dir, 0, false).await.unwrap();
+        file.write_all_at(len, &[0xAB; 16]).await.unwrap();
+        drop(file);
+
+        let mut replayed = Vec::new();
+        let (_wal, info) = builder
+            .open_with_replay(|record, _| replayed.push(record.key))
+            .await
+            .unwrap();
+        assert!(info.corruption_detected);
+        assert_eq!(replayed, vec![bytes::Bytes::from_static(b"k")]);
+        assert_eq!(store.len(&dir, 0).await.unwrap(), len);
+    }
+
+    #[tokio::test]
+    async fn test_builder_maintains_segments_in_store() {
+        // Segments in memory, apart from the WAL's own filesystem
+        let sidecar_fs = Arc::new(SimFs::new());
+        let segment_fs = Arc::new(SimFs::new());
+        let dir = PathBuf::from("/sim/wal");
+        segment_fs.create_dir_all(&dir).await.unwrap();
+        let store = Arc::new(FsSegmentStore::new(segment_fs.clone()));
+        let builder = WalBuilder::from_config(WalConfig {
+            dir: dir.clone(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            compaction_retain_segments: 1,
+            ..Default::default()
+        })
+        .fs(sidecar_fs.clone())
+        .segment_store(store.clone());
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let value = vec![7u8; 100 * 1024];
+        for _ in 0..10 {
+            for key in ["a", "b", "c"] {
+                wal.append(&Record::put(key, value.clone())).await.unwrap();
+            }
+        }
+        wal.sync().await.unwrap();
+        let sealed_len = store.len(&dir, 0).await.unwrap();
+
+        let scrub = wal.scrub().await.unwrap();
+        assert!(scrub.segments_checked > 0);
+        assert!(scrub.corrupt.is_empty());
+        let compaction = wal.compact().await.unwrap();
+        assert!(compaction.segments_compacted > 0);
+        assert!(store.len(&dir, 0).await.unwrap() < sealed_len);
+
+        // Whole-directory operations need segment files on the WAL's Fs
+        let dest = PathBuf::from("/sim/dest");
+        assert!(matches!(
+            wal.backup_to(&dest).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            wal.copy_to(&dest, CopyOptions::default()).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        wal.close().await.unwrap();
+
+        // Hash-chain verification reads through the store too
+        let chained = Wal::builder()
+            .dir(&dir)
+            .preallocate(false)
+            .hash_chain(true)
+            .fs(sidecar_fs.clone())
+            .segment_store(store.clone());
+        let (mut wal, _) = chained.open().await.unwrap();
+        wal.append(&Record::put(b"k".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        let head = wal.chain_head();
+        let report = wal.verify_chain(head).await.unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.attested, Some(true));
+        assert!(matches!(
+            wal.migrate_to(&dest).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+    }
+}
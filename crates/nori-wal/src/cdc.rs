@@ -0,0 +1,923 @@
+//! Change data capture: delivering the log to other systems.
+//!
+//! A [`CdcRunner`] follows a WAL with a [`WalTail`] and hands its records,
+//! in batches, to a [`CdcSink`]. Once the sink has accepted a batch, the
+//! runner saves a cursor past it in the WAL's [`MetaStore`](crate::MetaStore),
+//! so a restarted runner carries on from there:
+//!
+//! ```no_run
+//! use nori_wal::{CdcConfig, CdcRunner, JsonLinesSink, Wal, WalConfig};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> Result<(), nori_wal::CdcError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let config = CdcConfig { name: "audit".to_string(), ..Default::default() };
+//! let mut runner = CdcRunner::open(Arc::new(wal), JsonLinesSink::stdout(), config).await?;
+//! runner.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Delivery is at least once. A batch is sent again if the sink fails it,
+//! and after a crash between the sink accepting a batch and the cursor
+//! being saved; sinks should tolerate repeats, which the LSN and position
+//! on every event make easy to spot. Records are never skipped: if reading
+//! or sending fails, the runner goes back to the last saved cursor.
+//!
+//! Three sinks come with the crate: [`JsonLinesSink`] writes JSON Lines to
+//! any writer, [`KafkaSink`] hands messages to a Kafka client through
+//! [`KafkaProducer`], and `WebhookSink` (`webhook` feature) POSTs each
+//! batch to an HTTP endpoint as a JSON array.
+//!
+//! The WAL does not know about the saved cursor: purging segments the
+//! runner has yet to deliver makes it fail with [`SegmentError::CursorGone`].
+
+use crate::kafka;
+use crate::reader::{Cursor, WalTail};
+use crate::record::{timestamp_millis, Record};
+use crate::segment::{Position, SegmentError};
+use crate::wal::Wal;
+use bytes::Bytes;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Prefix of the metadata blob holding a runner's cursor.
+const CURSOR_PREFIX: &str = "cdc-";
+
+/// Errors from a [`CdcSink`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SinkError {
+    /// Sending again may succeed (a timeout, a refused connection, a busy
+    /// server).
+    #[error("transient sink error: {0}")]
+    Transient(String),
+    /// Sending again will not help (a rejected request, bad credentials).
+    #[error("sink error: {0}")]
+    Permanent(String),
+}
+
+/// Errors from a [`CdcRunner`].
+#[derive(Debug, Error)]
+pub enum CdcError {
+    #[error("WAL error: {0}")]
+    Wal(#[from] SegmentError),
+    #[error("{0}")]
+    Sink(SinkError),
+    #[error("sink still failing after {attempts} attempts: {last}")]
+    RetriesExhausted { attempts: u32, last: SinkError },
+    #[error("saved cursor {0:?} is damaged")]
+    BadCursor(String),
+    #[error("invalid CDC config: {0}")]
+    InvalidConfig(String),
+}
+
+/// Where a runner without a saved cursor starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CdcStart {
+    /// The oldest record still in the log.
+    #[default]
+    Beginning,
+    /// The end of the log: only records appended from now on.
+    End,
+}
+
+/// Settings for a [`CdcRunner`].
+#[derive(Debug, Clone)]
+pub struct CdcConfig {
+    /// Names the runner's saved cursor, so runners feeding different sinks
+    /// keep separate progress. 1 to 60 ASCII letters, digits, `-` or `_`
+    /// (default: "default").
+    pub name: String,
+    /// Where to start without a saved cursor (default: the beginning).
+    pub start: CdcStart,
+    /// Most records in a batch (default: 500).
+    pub batch_records: usize,
+    /// A batch is sent once its keys and values reach this many bytes
+    /// (default: 1 MiB).
+    pub batch_bytes: usize,
+    /// How long to wait for more records before sending a batch that is not
+    /// full (default: 100ms).
+    pub linger: Duration,
+    /// Sends of one batch before giving up on transient errors (default: 10).
+    pub max_attempts: u32,
+    /// Wait before the first resend, doubled for each one after (default:
+    /// 100ms).
+    pub retry_backoff: Duration,
+    /// Longest wait between resends (default: 10s).
+    pub max_backoff: Duration,
+}
+
+impl Default for CdcConfig {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            start: CdcStart::Beginning,
+            batch_records: 500,
+            batch_bytes: 1024 * 1024,
+            linger: Duration::from_millis(100),
+            max_attempts: 10,
+            retry_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl CdcConfig {
+    fn validate(&self) -> Result<(), CdcError> {
+        let name_ok = (1..=60).contains(&self.name.len())
+            && self
+                .name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !name_ok {
+            return Err(CdcError::InvalidConfig(format!(
+                "name {:?} must be 1 to 60 letters, digits, '-' or '_'",
+                self.name
+            )));
+        }
+        if self.batch_records == 0 || self.batch_bytes == 0 {
+            return Err(CdcError::InvalidConfig(
+                "batch_records and batch_bytes must be positive".to_string(),
+            ));
+        }
+        if self.max_attempts == 0 {
+            return Err(CdcError::InvalidConfig(
+                "max_attempts must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn cursor_name(&self) -> String {
+        format!("{}{}", CURSOR_PREFIX, self.name)
+    }
+}
+
+/// A record read from the log, as handed to a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CdcEvent {
+    pub record: Record,
+    /// Where the record is in the log.
+    pub position: Position,
+}
+
+impl CdcEvent {
+    /// Encodes the event as a JSON object with the fields of a
+    /// `nori-wal export` row, plus `position`.
+    ///
+    /// Key and value are text when both are UTF-8 and hex otherwise, as
+    /// `encoding` says; a delete has `op` `"del"` and an empty value.
+    pub fn to_json(&self) -> String {
+        let record = &self.record;
+        let mut out = String::with_capacity(128 + record.key.len() + record.value.len());
+        let number = |n: Option<u64>| n.map_or("null".to_string(), |n| n.to_string());
+        let _ = write!(
+            out,
+            r#"{{"position":"{}","lsn":{},"timestamp_ms":{},"namespace":{},"ttl_ms":{},"op":"{}","#,
+            self.position,
+            number(record.lsn),
+            number(record.timestamp.map(timestamp_millis)),
+            number(record.namespace.map(u64::from)),
+            number(record.ttl.map(|ttl| ttl.as_millis() as u64)),
+            if record.tombstone { "del" } else { "put" },
+        );
+        match (
+            std::str::from_utf8(&record.key),
+            std::str::from_utf8(&record.value),
+        ) {
+            (Ok(key), Ok(value)) => {
+                out.push_str(r#""encoding":"utf8","key":"#);
+                push_json_string(&mut out, key);
+                out.push_str(r#","value":"#);
+                push_json_string(&mut out, value);
+            }
+            _ => {
+                out.push_str(r#""encoding":"hex","key":""#);
+                push_hex(&mut out, &record.key);
+                out.push_str(r#"","value":""#);
+                push_hex(&mut out, &record.value);
+                out.push('"');
+            }
+        }
+        out.push('}');
+        out
+    }
+}
+
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn push_hex(out: &mut String, data: &[u8]) {
+    for byte in data {
+        let _ = write!(out, "{:02x}", byte);
+    }
+}
+
+/// Where a [`CdcRunner`] delivers records.
+pub trait CdcSink: Send {
+    /// Delivers a batch of events, in log order.
+    ///
+    /// Returning `Ok` means the events are safely stored downstream; the
+    /// runner then saves its cursor past them. A batch may be sent more
+    /// than once, in whole, after an error or a crash.
+    fn send(&mut self, events: &[CdcEvent]) -> impl Future<Output = Result<(), SinkError>> + Send;
+}
+
+/// Progress of a [`CdcRunner`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CdcStats {
+    /// Batches the sink has accepted.
+    pub batches: u64,
+    /// Events in those batches.
+    pub events: u64,
+    /// Sends repeated after a transient error.
+    pub retries: u64,
+}
+
+/// Follows a WAL and delivers its records to a sink, saving its progress.
+pub struct CdcRunner<S> {
+    wal: Arc<Wal>,
+    sink: S,
+    config: CdcConfig,
+    /// `None` after an error, until reopened at `delivered`.
+    tail: Option<WalTail>,
+    /// Position past the last batch the sink accepted.
+    delivered: Cursor,
+    stats: CdcStats,
+}
+
+impl<S: CdcSink> CdcRunner<S> {
+    /// Opens a runner at its saved cursor, or at `config.start` if it has
+    /// none.
+    pub async fn open(wal: Arc<Wal>, sink: S, config: CdcConfig) -> Result<Self, CdcError> {
+        config.validate()?;
+        let name = config.cursor_name();
+        let tail = match wal.meta().get(&name).await? {
+            Some(saved) => {
+                let cursor = Cursor::from_bytes(&saved).ok_or(CdcError::BadCursor(name))?;
+                wal.resume_tail(&cursor).await?
+            }
+            None => match config.start {
+                CdcStart::Beginning => wal.tail_from_lsn(0).await?,
+                CdcStart::End => wal.tail(wal.current_position().await),
+            },
+        };
+        Ok(Self {
+            wal,
+            sink,
+            config,
+            delivered: tail.cursor(),
+            tail: Some(tail),
+            stats: CdcStats::default(),
+        })
+    }
+
+    /// Returns the cursor past the last batch the sink accepted.
+    pub fn cursor(&self) -> Cursor {
+        self.delivered
+    }
+
+    pub fn stats(&self) -> CdcStats {
+        self.stats
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Delivers batches until an error.
+    ///
+    /// The runner can carry on after the error; it starts again from the
+    /// last batch the sink accepted.
+    pub async fn run(&mut self) -> Result<(), CdcError> {
+        loop {
+            self.run_once().await?;
+        }
+    }
+
+    /// Waits for records, delivers one batch of them, and saves the cursor
+    /// past it. Returns how many events were delivered.
+    pub async fn run_once(&mut self) -> Result<usize, CdcError> {
+        let result = self.deliver_batch().await;
+        if result.is_err() {
+            self.tail = None;
+        }
+        result
+    }
+
+    async fn deliver_batch(&mut self) -> Result<usize, CdcError> {
+        if self.tail.is_none() {
+            self.tail = Some(self.wal.resume_tail(&self.delivered).await?);
+        }
+        let tail = self.tail.as_mut().expect("tail reopened above");
+        let events = next_batch(tail, &self.config).await?;
+        let cursor = tail.cursor();
+
+        self.send(&events).await?;
+        self.wal
+            .meta()
+            .put(&self.config.cursor_name(), &cursor.to_bytes())
+            .await?;
+        self.delivered = cursor;
+        self.stats.batches += 1;
+        self.stats.events += events.len() as u64;
+        Ok(events.len())
+    }
+
+    /// Sends a batch, retrying transient errors with backoff.
+    async fn send(&mut self, events: &[CdcEvent]) -> Result<(), CdcError> {
+        let mut backoff = self.config.retry_backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match self.sink.send(events).await {
+                Ok(()) => return Ok(()),
+                Err(e @ SinkError::Permanent(_)) => return Err(CdcError::Sink(e)),
+                Err(last) if attempts >= self.config.max_attempts => {
+                    return Err(CdcError::RetriesExhausted { attempts, last })
+                }
+                Err(_) => {
+                    self.stats.retries += 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                }
+            }
+        }
+    }
+}
+
+impl<S> std::fmt::Debug for CdcRunner<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CdcRunner")
+            .field("name", &self.config.name)
+            .field("delivered", &self.delivered.position())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// Waits for a record, then collects more until the batch is full or
+/// `linger` has passed.
+async fn next_batch(tail: &mut WalTail, config: &CdcConfig) -> Result<Vec<CdcEvent>, CdcError> {
+    let (record, position) = tail.next_record().await?;
+    let mut bytes = record.key.len() + record.value.len();
+    let mut events = vec![CdcEvent { record, position }];
+    // `next_record` neither skips nor repeats a record when the timeout
+    // cancels it
+    let deadline = tokio::time::Instant::now() + config.linger;
+    while events.len() < config.batch_records && bytes < config.batch_bytes {
+        let Ok(next) = tokio::time::timeout_at(deadline, tail.next_record()).await else {
+            break;
+        };
+        let (record, position) = next?;
+        bytes += record.key.len() + record.value.len();
+        events.push(CdcEvent { record, position });
+    }
+    Ok(events)
+}
+
+/// Writes events as JSON Lines, one [`CdcEvent::to_json`] object per line,
+/// flushing after each batch.
+pub struct JsonLinesSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl JsonLinesSink<std::io::Stdout> {
+    /// Writes to standard output.
+    pub fn stdout() -> Self {
+        Self::new(std::io::stdout())
+    }
+}
+
+impl<W: Write + Send> CdcSink for JsonLinesSink<W> {
+    async fn send(&mut self, events: &[CdcEvent]) -> Result<(), SinkError> {
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&event.to_json());
+            lines.push('\n');
+        }
+        self.writer
+            .write_all(lines.as_bytes())
+            .and_then(|()| self.writer.flush())
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::BrokenPipe => SinkError::Permanent(e.to_string()),
+                _ => SinkError::Transient(e.to_string()),
+            })
+    }
+}
+
+impl<W> std::fmt::Debug for JsonLinesSink<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonLinesSink").finish_non_exhaustive()
+    }
+}
+
+/// A Kafka message made from a record, as [`kafka::export`](crate::kafka::export)
+/// writes it: the record's key, its value (none for a delete), its
+/// timestamp, and the `nori.lsn`, `nori.namespace` and `nori.ttl_ms`
+/// headers for the fields that are set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaMessage {
+    pub key: Bytes,
+    pub value: Option<Bytes>,
+    pub timestamp_ms: Option<u64>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl KafkaMessage {
+    pub fn from_record(record: &Record) -> Self {
+        Self {
+            key: record.key.clone(),
+            value: (!record.tombstone).then(|| record.value.clone()),
+            timestamp_ms: record.timestamp.map(timestamp_millis),
+            headers: kafka::headers(record),
+        }
+    }
+}
+
+/// Publishes messages through a Kafka client.
+///
+/// nori-wal has no Kafka client of its own; implement this over the one
+/// the application uses, producing to the topic of its choice and waiting
+/// for the acknowledgements. Keying partitions by the message key keeps
+/// each key's changes in order.
+pub trait KafkaProducer: Send {
+    /// Publishes messages, in order, returning once all are acknowledged.
+    fn produce(
+        &mut self,
+        messages: Vec<KafkaMessage>,
+    ) -> impl Future<Output = Result<(), SinkError>> + Send;
+}
+
+/// Publishes events as [`KafkaMessage`]s through a [`KafkaProducer`].
+#[derive(Debug)]
+pub struct KafkaSink<P> {
+    producer: P,
+}
+
+impl<P: KafkaProducer> KafkaSink<P> {
+    pub fn new(producer: P) -> Self {
+        Self { producer }
+    }
+
+    pub fn producer(&self) -> &P {
+        &self.producer
+    }
+}
+
+impl<P: KafkaProducer> CdcSink for KafkaSink<P> {
+    async fn send(&mut self, events: &[CdcEvent]) -> Result<(), SinkError> {
+        let messages = events
+            .iter()
+            .map(|event| KafkaMessage::from_record(&event.record))
+            .collect();
+        self.producer.produce(messages).await
+    }
+}
+
+#[cfg(feature = "webhook")]
+pub use webhook::WebhookSink;
+
+#[cfg(feature = "webhook")]
+mod webhook {
+    use super::{CdcError, CdcEvent, CdcSink, SinkError};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    /// Longest response read, status line and all.
+    const MAX_RESPONSE: u64 = 64 * 1024;
+
+    /// POSTs each batch to an HTTP endpoint as a JSON array of
+    /// [`CdcEvent::to_json`] objects.
+    ///
+    /// A 2xx response accepts the batch. 408, 429 and 5xx responses and
+    /// connection errors are transient; other responses fail the batch.
+    /// Plain `http://` only: put a TLS-terminating proxy in front of an
+    /// `https://` endpoint.
+    #[derive(Debug, Clone)]
+    pub struct WebhookSink {
+        host: String,
+        port: u16,
+        path: String,
+        headers: Vec<(String, String)>,
+        timeout: Duration,
+    }
+
+    impl WebhookSink {
+        /// Creates a sink for `url`, of the form `http://host[:port][/path]`.
+        pub fn new(url: &str) -> Result<Self, CdcError> {
+            let invalid = || CdcError::InvalidConfig(format!("unsupported webhook URL {:?}", url));
+            let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+            let (authority, path) = match rest.find('/') {
+                Some(slash) => rest.split_at(slash),
+                None => (rest, "/"),
+            };
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+                None => (authority, 80),
+            };
+            if host.is_empty() || path.contains(['\r', '\n', ' ']) {
+                return Err(invalid());
+            }
+            Ok(Self {
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+                headers: Vec::new(),
+                timeout: Duration::from_secs(10),
+            })
+        }
+
+        /// Adds a request header, such as `Authorization`.
+        pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+            self.headers.push((name.into(), value.into()));
+            self
+        }
+
+        /// Sets how long a request may take, connecting included (default:
+        /// 10s).
+        pub fn with_timeout(mut self, timeout: Duration) -> Self {
+            self.timeout = timeout;
+            self
+        }
+
+        async fn post(&self, body: &str) -> Result<u16, SinkError> {
+            let transient = |e: std::io::Error| SinkError::Transient(e.to_string());
+            let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+                .await
+                .map_err(transient)?;
+            let mut request = format!(
+                "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+                self.path,
+                self.host,
+                self.port,
+                body.len()
+            );
+            for (name, value) in &self.headers {
+                request.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            request.push_str("\r\n");
+            request.push_str(body);
+            stream
+                .write_all(request.as_bytes())
+                .await
+                .map_err(transient)?;
+
+            let mut response = Vec::new();
+            stream
+                .take(MAX_RESPONSE)
+                .read_to_end(&mut response)
+                .await
+                .map_err(transient)?;
+            // `HTTP/1.1 200 OK`
+            let status = std::str::from_utf8(&response)
+                .ok()
+                .and_then(|r| r.split_whitespace().nth(1))
+                .and_then(|code| code.parse().ok());
+            status.ok_or_else(|| SinkError::Transient("malformed HTTP response".to_string()))
+        }
+    }
+
+    impl CdcSink for WebhookSink {
+        async fn send(&mut self, events: &[CdcEvent]) -> Result<(), SinkError> {
+            let mut body = String::from("[");
+            for (i, event) in events.iter().enumerate() {
+                if i > 0 {
+                    body.push(',');
+                }
+                body.push_str(&event.to_json());
+            }
+            body.push(']');
+
+            let status = tokio::time::timeout(self.timeout, self.post(&body))
+                .await
+                .map_err(|_| SinkError::Transient("webhook timed out".to_string()))??;
+            match status {
+                200..=299 => Ok(()),
+                408 | 429 | 500..=599 => {
+                    Err(SinkError::Transient(format!("webhook returned {}", status)))
+                }
+                _ => Err(SinkError::Permanent(format!("webhook returned {}", status))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalConfig;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// Keeps what it is sent, failing the sends listed in `failures` first.
+    #[derive(Default)]
+    struct TestSink {
+        received: Arc<Mutex<Vec<CdcEvent>>>,
+        failures: Vec<SinkError>,
+    }
+
+    impl CdcSink for TestSink {
+        async fn send(&mut self, events: &[CdcEvent]) -> Result<(), SinkError> {
+            if !self.failures.is_empty() {
+                return Err(self.failures.remove(0));
+            }
+            self.received.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    async fn open_wal(dir: &TempDir) -> Arc<Wal> {
+        let (wal, _) = Wal::open(WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        Arc::new(wal)
+    }
+
+    async fn append(wal: &Wal, keys: std::ops::Range<u32>) {
+        for i in keys {
+            let key = format!("key{}", i);
+            wal.append(&Record::put(key, "value")).await.unwrap();
+        }
+        wal.sync().await.unwrap();
+    }
+
+    fn keys(events: &[CdcEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|e| String::from_utf8(e.record.key.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn config() -> CdcConfig {
+        CdcConfig {
+            name: "test".to_string(),
+            batch_records: 4,
+            linger: Duration::from_millis(10),
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delivers_batches_and_resumes_from_saved_cursor() {
+        let dir = TempDir::new().unwrap();
+        let wal = open_wal(&dir).await;
+        append(&wal, 0..10).await;
+
+        let sink = TestSink::default();
+        let received = sink.received.clone();
+        let mut runner = CdcRunner::open(wal.clone(), sink, config()).await.unwrap();
+        let mut sizes = Vec::new();
+        while runner.stats().events < 10 {
+            sizes.push(runner.run_once().await.unwrap());
+        }
+        assert_eq!(sizes, [4, 4, 2]);
+        assert_eq!(runner.stats().batches, 3);
+        let expected: Vec<_> = (0..10).map(|i| format!("key{}", i)).collect();
+        assert_eq!(keys(&received.lock().unwrap()), expected);
+        drop(runner);
+
+        // A new runner under the same name carries on; another name starts
+        // over, or at the end if asked to
+        append(&wal, 10..12).await;
+        let sink = TestSink::default();
+        let received = sink.received.clone();
+        let mut runner = CdcRunner::open(wal.clone(), sink, config()).await.unwrap();
+        assert_eq!(runner.run_once().await.unwrap(), 2);
+        assert_eq!(keys(&received.lock().unwrap()), ["key10", "key11"]);
+
+        let other = CdcConfig {
+            name: "other".to_string(),
+            ..config()
+        };
+        let mut runner = CdcRunner::open(wal.clone(), TestSink::default(), other)
+            .await
+            .unwrap();
+        assert_eq!(runner.run_once().await.unwrap(), 4);
+        let latest = CdcConfig {
+            name: "latest".to_string(),
+            start: CdcStart::End,
+            ..config()
+        };
+        let sink = TestSink::default();
+        let received = sink.received.clone();
+        let mut runner = CdcRunner::open(wal.clone(), sink, latest).await.unwrap();
+        append(&wal, 12..13).await;
+        assert_eq!(runner.run_once().await.unwrap(), 1);
+        assert_eq!(keys(&received.lock().unwrap()), ["key12"]);
+
+        let bad = CdcConfig {
+            name: "no spaces".to_string(),
+            ..config()
+        };
+        let result = CdcRunner::open(wal, TestSink::default(), bad).await;
+        assert!(matches!(result, Err(CdcError::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_retries_and_redelivers_failed_batches() {
+        let dir = TempDir::new().unwrap();
+        let wal = open_wal(&dir).await;
+        append(&wal, 0..3).await;
+
+        let transient = SinkError::Transient("busy".to_string());
+        let sink = TestSink {
+            failures: vec![transient.clone(), transient.clone()],
+            ..Default::default()
+        };
+        let received = sink.received.clone();
+        let mut runner = CdcRunner::open(wal.clone(), sink, config()).await.unwrap();
+        assert_eq!(runner.run_once().await.unwrap(), 3);
+        assert_eq!(runner.stats().retries, 2);
+
+        // A failed batch is not lost: the next attempt sends it again
+        append(&wal, 3..5).await;
+        let permanent = SinkError::Permanent("rejected".to_string());
+        runner.sink_mut().failures = vec![permanent.clone()];
+        assert!(matches!(
+            runner.run_once().await,
+            Err(CdcError::Sink(e)) if e == permanent
+        ));
+        runner.sink_mut().failures = vec![transient.clone(); 3];
+        let limited = CdcConfig {
+            max_attempts: 3,
+            ..config()
+        };
+        runner.config = limited;
+        assert!(matches!(
+            runner.run_once().await,
+            Err(CdcError::RetriesExhausted { attempts: 3, .. })
+        ));
+        assert_eq!(runner.run_once().await.unwrap(), 2);
+        assert_eq!(
+            keys(&received.lock().unwrap()),
+            ["key0", "key1", "key2", "key3", "key4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_and_kafka_sinks() {
+        let mut record = Record::delete(b"\xff".as_slice());
+        record.lsn = Some(7);
+        record.namespace = Some(3);
+        let binary = CdcEvent {
+            record,
+            position: Position {
+                segment_id: 2,
+                offset: 64,
+            },
+        };
+        let mut record = Record::put_with_ttl("k\"1\n", "v", Duration::from_secs(1));
+        record.lsn = Some(8);
+        let text = CdcEvent {
+            record,
+            position: Position {
+                segment_id: 2,
+                offset: 96,
+            },
+        };
+
+        let mut sink = JsonLinesSink::new(Vec::new());
+        sink.send(&[binary.clone(), text.clone()]).await.unwrap();
+        let out = String::from_utf8(sink.into_inner()).unwrap();
+        assert_eq!(
+            out,
+            concat!(
+                r#"{"position":"000002:64","lsn":7,"timestamp_ms":null,"namespace":3,"ttl_ms":null,"op":"del","encoding":"hex","key":"ff","value":""}"#,
+                "\n",
+                r#"{"position":"000002:96","lsn":8,"timestamp_ms":null,"namespace":null,"ttl_ms":1000,"op":"put","encoding":"utf8","key":"k\"1\n","value":"v"}"#,
+                "\n"
+            )
+        );
+
+        struct Producer(Vec<KafkaMessage>);
+        impl KafkaProducer for Producer {
+            async fn produce(&mut self, messages: Vec<KafkaMessage>) -> Result<(), SinkError> {
+                self.0.extend(messages);
+                Ok(())
+            }
+        }
+        let mut sink = KafkaSink::new(Producer(Vec::new()));
+        sink.send(&[binary, text]).await.unwrap();
+        let messages = &sink.producer().0;
+        assert_eq!(messages[0].value, None);
+        assert_eq!(
+            messages[0].headers,
+            [
+                (kafka::LSN_HEADER, "7".to_string()),
+                (kafka::NAMESPACE_HEADER, "3".to_string())
+            ]
+        );
+        assert_eq!(messages[1].value.as_deref(), Some(b"v".as_slice()));
+    }
+
+    #[cfg(feature = "webhook")]
+    #[tokio::test]
+    async fn test_webhook_sink() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["503 Service Unavailable", "200 OK", "400 Bad Request"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // Headers, then as much body as Content-Length says
+                loop {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length: usize = head
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let mut sink = WebhookSink::new(&format!("http://127.0.0.1:{}/hooks/wal", port))
+            .unwrap()
+            .with_header("Authorization", "Bearer secret");
+        let event = CdcEvent {
+            record: Record::put("k", "v"),
+            position: Position {
+                segment_id: 0,
+                offset: 0,
+            },
+        };
+        let events = [event.clone(), event];
+        assert!(matches!(
+            sink.send(&events).await,
+            Err(SinkError::Transient(_))
+        ));
+        sink.send(&events).await.unwrap();
+        assert!(matches!(
+            sink.send(&events).await,
+            Err(SinkError::Permanent(_))
+        ));
+
+        let requests = server.await.unwrap();
+        assert!(requests[1].starts_with("POST /hooks/wal HTTP/1.1\r\n"));
+        assert!(requests[1].contains("Authorization: Bearer secret\r\n"));
+        let body = requests[1].split_once("\r\n\r\n").unwrap().1;
+        assert!(body.starts_with(r#"[{"position""#) && body.ends_with("}]"));
+        assert_eq!(body.matches(r#""key":"k""#).count(), 2);
+
+        assert!(WebhookSink::new("https://example.com").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Hash-chained records for tamper evidence.
+//!
+//! With [`WalConfig::hash_chain`], every appended record carries the SHA-256
+//! of the record before it, link included. Each record's hash therefore
+//! depends on every record before it, and the hash of the last record, the
+//! [`ChainHead`], stands for the whole log up to there.
+//! Export it with [`Wal::chain_head`] and keep it somewhere the WAL's host
+//! cannot change, such as a signed attestation or another service.
+//! [`Wal::verify_chain`] later checks that each link still matches the
+//! record before it and that the log still holds the attested head: editing
+//! a record changes its hash and breaks the link in the next one, and
+//! rewriting every record after it to hide that changes the head. Keeping
+//! the links while editing a record would take a SHA-256 second preimage.
+//!
+//! Verification hashes the encoded records without decompressing values, so
+//! it is cheap enough to run on a schedule. The first record of a log
+//! starting at segment 0 links to all zeroes. Once the oldest
+//! segments are purged, the first remaining record's link can no longer be
+//! checked, and records appended while the chain was off are counted as
+//! unchained rather than checked.
+//!
+//! Compaction drops records from the middle of the log, which would break
+//! the chain, so it cannot be enabled together with it.
+//!
+//! [`WalConfig::hash_chain`]: crate::WalConfig::hash_chain
+//! [`Wal::chain_head`]: crate::Wal::chain_head
+//! [`Wal::verify_chain`]: crate::Wal::verify_chain
+
+use crate::segment::{Position, SegmentError, SegmentManager};
+use nori_wal_format::{FormatError, RecordRef, CHAIN_LINK_LEN};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Chunk size used when streaming a segment from disk.
+const VERIFY_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The SHA-256 of an encoded record, which the next record of a hash chain
+/// links to. `encoded` must be exactly one record.
+pub fn record_hash(encoded: &[u8]) -> [u8; CHAIN_LINK_LEN] {
+    Sha256::digest(encoded).into()
+}
+
+/// The last record of a hash-chained log: where it is and its hash.
+///
+/// Written as `SEGMENT:OFFSET@HASH`, with the hash in hex, e.g.
+/// `000003:4096@8f3a21c0...` (64 hex digits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainHead {
+    pub position: Position,
+    pub hash: [u8; CHAIN_LINK_LEN],
+}
+
+impl fmt::Display for ChainHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@", self.position)?;
+        self.hash
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Error returned when parsing a [`ChainHead`] from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid chain head {0:?}: expected SEGMENT:OFFSET@HASH")]
+pub struct ParseChainHeadError(String);
+
+impl FromStr for ChainHead {
+    type Err = ParseChainHeadError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once('@').and_then(|(position, hex)| {
+            if hex.len() != 2 * CHAIN_LINK_LEN || !hex.is_ascii() {
+                return None;
+            }
+            let mut hash = [0; CHAIN_LINK_LEN];
+            for (i, byte) in hash.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+            }
+            Some(ChainHead {
+                position: position.parse().ok()?,
+                hash,
+            })
+        });
+        parsed.ok_or_else(|| ParseChainHeadError(s.to_string()))
+    }
+}
+
+/// What verifying a hash chain found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainReport {
+    /// Records read.
+    pub records: u64,
+    /// Records without a link, appended while the chain was off.
+    pub unchained: u64,
+    /// Records whose link does not match the hash of the record before
+    /// them.
+    pub breaks: Vec<Position>,
+    /// First record that failed to decode. Nothing after it is checked.
+    pub corrupt: Option<Position>,
+    /// The last record read.
+    pub head: Option<ChainHead>,
+    /// Whether the log still holds the attested head, if one was given:
+    /// `Some(false)` if the record there is gone or has another hash,
+    /// `None` if no head was given or the log no longer reaches back to it.
+    pub attested: Option<bool>,
+}
+
+impl ChainReport {
+    /// Returns true if nothing in the report points at a modified log.
+    pub fn is_intact(&self) -> bool {
+        self.breaks.is_empty() && self.corrupt.is_none() && self.attested != Some(false)
+    }
+}
+
+/// Checks records against the ones before them, fed in log order.
+///
+/// [`Wal::verify_chain`](crate::Wal::verify_chain) drives one over the
+/// segments of an open WAL; tools reading segment files themselves can feed
+/// it the encoded records they find.
+#[derive(Debug, Default)]
+pub struct ChainVerifier {
+    attested: Option<ChainHead>,
+    /// Hash of the record checked last.
+    previous: Option<[u8; CHAIN_LINK_LEN]>,
+    first: Option<Position>,
+    report: ChainReport,
+}
+
+impl ChainVerifier {
+    /// A verifier that also looks for `attested` among the records.
+    pub fn new(attested: Option<ChainHead>) -> Self {
+        Self {
+            attested,
+            ..Self::default()
+        }
+    }
+
+    /// Checks the record encoded at the start of `encoded`, found at
+    /// `position`, and returns its encoded size.
+    ///
+    /// The record's own CRC32C is validated as it is decoded; its link is
+    /// compared with the SHA-256 of the record checked before it.
+    pub fn check(&mut self, position: Position, encoded: &[u8]) -> Result<usize, FormatError> {
+        let (record, size) = RecordRef::decode(encoded)?;
+        let hash = record_hash(&encoded[..size]);
+        self.report.records += 1;
+        match record.chain {
+            None => self.report.unchained += 1,
+            Some(link) => {
+                let origin = position.segment_id == 0 && position.offset == 0;
+                let expected = self.previous.or(origin.then_some([0; CHAIN_LINK_LEN]));
+                if expected.is_some_and(|expected| expected != link) {
+                    self.report.breaks.push(position);
+                }
+            }
+        }
+        if let Some(attested) = self.attested.filter(|a| a.position == position) {
+            self.report.attested = Some(attested.hash == hash);
+        }
+        self.first.get_or_insert(position);
+        self.previous = Some(hash);
+        self.report.head = Some(ChainHead { position, hash });
+        Ok(size)
+    }
+
+    /// Notes that the log is damaged at `position`, which ends the check.
+    pub fn corrupt(&mut self, position: Position) {
+        self.report.corrupt.get_or_insert(position);
+    }
+
+    /// Forgets the record before the next one, when the records between
+    /// them are gone, such as purged while the log was being read.
+    pub fn restart(&mut self) {
+        self.previous = None;
+    }
+
+    /// Returns what the check found.
+    pub fn finish(mut self) -> ChainReport {
+        if let Some(attested) = self.attested {
+            if self.report.attested.is_none() {
+                let purged = self.first.map_or(true, |first| attested.position < first);
+                if !purged {
+                    self.report.attested = Some(false);
+                }
+            }
+        }
+        self.report
+    }
+}
+
+/// Verifies the hash chain of the durable records `manager` holds.
+pub(crate) async fn verify_chain(
+    manager: &SegmentManager,
+    attested: Option<ChainHead>,
+) -> Result<ChainReport, SegmentError> {
+    let mut verifier = ChainVerifier::new(attested);
+    let durable = manager.durable_position().await;
+    let mut ids = manager.sealed_segment_ids().await?;
+    ids.retain(|&id| id < durable.segment_id);
+    ids.push(durable.segment_id);
+    let dir = manager.dir().await;
+
+    for id in ids {
+        let end = (id == durable.segment_id).then_some(durable.offset);
+        let file = match manager.store().open_read(&dir, id).await {
+            Ok(file) => file,
+            // Purged since it was listed
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                verifier.restart();
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let end = match end {
+            Some(end) => end,
+            None => file.len().await?,
+        };
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut offset = 0u64; // File offset of buffer[0]
+        let mut read = 0u64;
+        loop {
+            if read < end {
+                let len = VERIFY_CHUNK_SIZE.min((end - read) as usize);
+                let chunk = file.read_at(read, len).await?;
+                if chunk.is_empty() {
+                    break;
+                }
+                read += chunk.len() as u64;
+                buffer.extend_from_slice(&chunk);
+            }
+            let eof = read >= end;
+
+            let mut consumed = 0;
+            while consumed < buffer.len() {
+                let position = Position {
+                    segment_id: id,
+                    offset: offset + consumed as u64,
+                };
+                match verifier.check(position, &buffer[consumed..]) {
+                    Ok(size) => consumed += size,
+                    Err(FormatError::Incomplete) if !eof => break,
+                    Err(_) => {
+                        verifier.corrupt(position);
+                        return Ok(verifier.finish());
+                    }
+                }
+            }
+            buffer.drain(..consumed);
+            offset += consumed as u64;
+            if eof {
+                break;
+            }
+        }
+    }
+    Ok(verifier.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Record;
+
+    fn chained(records: usize) -> Vec<(Position, Vec<u8>)> {
+        let mut link = [0; CHAIN_LINK_LEN];
+        let mut offset = 0;
+        (0..records)
+            .map(|i| {
+                let mut record = Record::put(format!("k{}", i), "v");
+                record.chain = Some(link);
+                let encoded = record.encode().to_vec();
+                link = record_hash(&encoded);
+                let position = Position {
+                    segment_id: 0,
+                    offset,
+                };
+                offset += encoded.len() as u64;
+                (position, encoded)
+            })
+            .collect()
+    }
+
+    fn verify(records: &[(Position, Vec<u8>)], attested: Option<ChainHead>) -> ChainReport {
+        let mut verifier = ChainVerifier::new(attested);
+        for (position, encoded) in records {
+            verifier.check(*position, encoded).unwrap();
+        }
+        verifier.finish()
+    }
+
+    #[test]
+    fn test_chain_detects_replaced_records() {
+        let records = chained(5);
+        let report = verify(&records, None);
+        assert!(report.is_intact());
+        assert_eq!(report.records, 5);
+        let head = report.head.unwrap();
+        assert_eq!(head.position, records[4].0);
+        assert!(verify(&records, Some(head)).attested.unwrap());
+
+        // A record replaced with a valid one breaks the link after it
+        let mut tampered = records.clone();
+        let mut forged = Record::put("k2", "forged");
+        forged.chain = Some(record_hash(&records[1].1));
+        tampered[2].1 = forged.encode().to_vec();
+        let report = verify(&tampered, None);
+        assert_eq!(report.breaks, [records[3].0]);
+
+        // Relinking the rest of the log moves the head off the attested one
+        let mut link = record_hash(&tampered[2].1);
+        for entry in &mut tampered[3..] {
+            let (mut record, _) = Record::decode(&entry.1).unwrap();
+            record.chain = Some(link);
+            entry.1 = record.encode().to_vec();
+            link = record_hash(&entry.1);
+        }
+        let report = verify(&tampered, Some(head));
+        assert!(report.breaks.is_empty());
+        assert_eq!(report.attested, Some(false));
+        assert!(!report.is_intact());
+
+        // Without the records before it, neither the first link nor a head
+        // among them can be checked
+        assert!(verify(&records[1..], None).is_intact());
+        let old = ChainHead {
+            position: records[0].0,
+            hash: [1; CHAIN_LINK_LEN],
+        };
+        assert_eq!(verify(&records[1..], Some(old)).attested, None);
+    }
+
+    #[test]
+    fn test_chain_head_round_trips_as_text() {
+        let mut hash = [0; CHAIN_LINK_LEN];
+        hash[..4].copy_from_slice(&[0x8f, 0x3a, 0x21, 0xc0]);
+        let head = ChainHead {
+            position: Position {
+                segment_id: 3,
+                offset: 4096,
+            },
+            hash,
+        };
+        let text = format!("000003:4096@8f3a21c0{}", "0".repeat(56));
+        assert_eq!(head.to_string(), text);
+        assert_eq!(text.parse(), Ok(head));
+        assert!("000003:4096".parse::<ChainHead>().is_err());
+        assert!("000003:4096@8f3a21c0".parse::<ChainHead>().is_err());
+    }
+
+    #[test]
+    fn test_record_hash_is_sha256() {
+        // The SHA-256 test vector for "abc"
+        let hex: String = record_hash(b"abc")
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
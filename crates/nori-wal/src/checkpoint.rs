@@ -0,0 +1,161 @@
+//! Durable checkpoint markers.
+//!
+//! A checkpoint records the position up to which an embedder has made the
+//! log's effects durable elsewhere (a flushed memtable, an applied snapshot),
+//! so it knows where to resume replay and which segments are safe to purge.
+//! The latest checkpoint lives in a single `CHECKPOINT` file in the WAL
+//! directory, replaced atomically on every update.
+//!
+//! File layout (little-endian, 41 bytes):
+//! - magic: `NORICKPT`
+//! - version: u8
+//! - segment_id: u64
+//! - offset: u64
+//! - created_at: u64 (milliseconds since the Unix epoch)
+//! - reserved: u32
+//! - crc32c: u32 (of all preceding bytes)
+
+use crate::fs::{self, Fs};
+use crate::segment::{Position, SegmentError};
+use bytes::{Buf, BufMut, BytesMut};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the checkpoint file inside the WAL directory.
+pub const CHECKPOINT_FILE: &str = "CHECKPOINT";
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"NORICKPT";
+const CHECKPOINT_VERSION: u8 = 1;
+const CHECKPOINT_LEN: usize = 8 + 1 + 8 * 3 + 4 + 4;
+
+/// A durably recorded checkpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Every record before this position is covered by the checkpoint.
+    pub position: Position,
+    /// When the checkpoint was taken, to millisecond precision.
+    pub created_at: SystemTime,
+}
+
+impl Checkpoint {
+    /// Creates a checkpoint at `position`, stamped with the current time.
+    pub fn new(position: Position) -> Self {
+        Self::at(position, SystemTime::now())
+    }
+
+    /// Creates a checkpoint at `position`, stamped with `created_at`.
+    pub fn at(position: Position, created_at: SystemTime) -> Self {
+        let millis = crate::record::timestamp_millis(created_at);
+        Self {
+            position,
+            created_at: UNIX_EPOCH + Duration::from_millis(millis),
+        }
+    }
+
+    fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(CHECKPOINT_LEN);
+        buf.put_slice(CHECKPOINT_MAGIC);
+        buf.put_u8(CHECKPOINT_VERSION);
+        buf.put_u64_le(self.position.segment_id);
+        buf.put_u64_le(self.position.offset);
+        buf.put_u64_le(crate::record::timestamp_millis(self.created_at));
+        buf.put_u32_le(0);
+        let crc = crc32c::crc32c(&buf);
+        buf.put_u32_le(crc);
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != CHECKPOINT_LEN
+            || &data[..8] != CHECKPOINT_MAGIC
+            || data[8] != CHECKPOINT_VERSION
+        {
+            return None;
+        }
+        let (body, mut crc) = data.split_at(CHECKPOINT_LEN - 4);
+        if crc.get_u32_le() != crc32c::crc32c(body) {
+            return None;
+        }
+
+        let mut cursor = &body[9..];
+        Some(Self {
+            position: Position {
+                segment_id: cursor.get_u64_le(),
+                offset: cursor.get_u64_le(),
+            },
+            created_at: UNIX_EPOCH + Duration::from_millis(cursor.get_u64_le()),
+        })
+    }
+}
+
+/// Returns the path of the checkpoint file in `dir`.
+pub(crate) fn checkpoint_path(dir: &Path) -> PathBuf {
+    dir.join(CHECKPOINT_FILE)
+}
+
+/// Durably replaces the checkpoint in `dir`.
+pub(crate) async fn write_checkpoint(
+    fs: &dyn Fs,
+    dir: &Path,
+    checkpoint: &Checkpoint,
+) -> Result<(), SegmentError> {
+    let path = checkpoint_path(dir);
+    let temp_path = path.with_extension("tmp");
+
+    fs::write_synced(fs, &temp_path, &checkpoint.encode()).await?;
+
+    fs.rename(&temp_path, &path).await?;
+    Ok(fs.sync_dir(dir).await?)
+}
+
+/// Reads the checkpoint in `dir`.
+///
+/// A missing or damaged file yields `None`: forgetting a checkpoint only
+/// means replaying more of the log, never less.
+pub(crate) async fn read_checkpoint(fs: &dyn Fs, dir: &Path) -> Option<Checkpoint> {
+    let data = fs::read(fs, &checkpoint_path(dir)).await.ok()?;
+    Checkpoint::decode(&data)
+}
+
+/// Removes the checkpoint in `dir` if there is one.
+pub(crate) async fn remove_checkpoint(fs: &dyn Fs, dir: &Path) -> Result<(), SegmentError> {
+    match fs.remove_file(&checkpoint_path(dir)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::LocalFs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_checkpoint_roundtrip_and_damage() {
+        let temp_dir = TempDir::new().unwrap();
+        assert_eq!(read_checkpoint(&LocalFs, temp_dir.path()).await, None);
+
+        let checkpoint = Checkpoint::new(Position {
+            segment_id: 4,
+            offset: 8192,
+        });
+        write_checkpoint(&LocalFs, temp_dir.path(), &checkpoint)
+            .await
+            .unwrap();
+        assert_eq!(
+            read_checkpoint(&LocalFs, temp_dir.path()).await,
+            Some(checkpoint)
+        );
+
+        let path = checkpoint_path(temp_dir.path());
+        let mut data = std::fs::read(&path).unwrap();
+        data[10] ^= 0x01;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read_checkpoint(&LocalFs, temp_dir.path()).await, None);
+
+        remove_checkpoint(&LocalFs, temp_dir.path()).await.unwrap();
+        remove_checkpoint(&LocalFs, temp_dir.path()).await.unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,136 @@
+//! Pluggable clock for the WAL's time-dependent behavior.
+//!
+//! The batch-fsync window, age-based segment rotation, record timestamps
+//! and TTL expiry on replay all read the time through a [`Clock`], so tests
+//! and simulations can drive them deterministically. [`SystemClock`] is the
+//! default; [`MockClock`] only moves when told to.
+//!
+//! ```
+//! use nori_wal::clock::{Clock, MockClock};
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::new();
+//! let start = clock.now();
+//! clock.advance(Duration::from_secs(5));
+//! assert_eq!(clock.now() - start, Duration::from_secs(5));
+//! ```
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of monotonic and wall-clock time.
+pub trait Clock: Send + Sync + 'static {
+    /// Monotonic time, for measuring windows and ages.
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for stamping records and evaluating TTLs.
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The default clock, reading the system's time.
+///
+/// Monotonic time follows tokio's clock, so pausing time on a tokio runtime
+/// (`#[tokio::test(start_paused = true)]`) also pauses windows and ages
+/// measured with it. Wall-clock time is never paused.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until advanced.
+///
+/// Both readings move together: [`advance`](Self::advance) adds the same
+/// duration to monotonic and wall-clock time.
+#[derive(Debug)]
+pub struct MockClock {
+    base: Instant,
+    state: Mutex<MockState>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    elapsed: Duration,
+    system_time: SystemTime,
+}
+
+impl MockClock {
+    /// Creates a clock whose wall-clock time starts at a fixed point in
+    /// 2023, so runs that use it are repeatable.
+    pub fn new() -> Self {
+        Self::at(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+
+    /// Creates a clock whose wall-clock time starts at `system_time`.
+    pub fn at(system_time: SystemTime) -> Self {
+        Self {
+            base: Instant::now(),
+            state: Mutex::new(MockState {
+                elapsed: Duration::ZERO,
+                system_time,
+            }),
+        }
+    }
+
+    /// Moves both monotonic and wall-clock time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state();
+        state.elapsed += duration;
+        state.system_time += duration;
+    }
+
+    /// Sets wall-clock time to `system_time`, leaving monotonic time alone.
+    /// It may move backwards, as a system clock can after an adjustment.
+    pub fn set_system_time(&self, system_time: SystemTime) {
+        self.state().system_time = system_time;
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.base + self.state().elapsed
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.state().system_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_only_moves_when_advanced() {
+        let clock = MockClock::at(UNIX_EPOCH);
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        assert_eq!(clock.system_time(), UNIX_EPOCH);
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(clock.now() - start, Duration::from_millis(250));
+        assert_eq!(clock.system_time(), UNIX_EPOCH + Duration::from_millis(250));
+
+        // Wall-clock adjustments leave monotonic time alone
+        clock.set_system_time(UNIX_EPOCH);
+        assert_eq!(clock.system_time(), UNIX_EPOCH);
+        assert_eq!(clock.now() - start, Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,151 @@
+//! Coalescing of puts to the same key within a batch.
+//!
+//! A high-churn key, such as a counter bumped on every request, is often put
+//! many times in one [`append_batch`](crate::Wal::append_batch) when the
+//! caller batches its writes per fsync window. Only the last of those puts
+//! matters after replay, yet each is written out in full. With
+//! [`WalConfig::coalesce_keys`] the WAL leaves out every put that a later
+//! put to the same key and namespace in the same batch replaces, and writes
+//! the last one with [`Record::coalesced`] set to the number it replaced, so
+//! replay can tell that intermediate values were never logged.
+//!
+//! Deletes are always written, and a delete between two puts keeps the
+//! earlier put as well. Each record left out gets the position of the put
+//! that replaced it, which holds the key's final value. Should a left-out
+//! put be [critical](crate::Durability::Critical), the record written for it
+//! is too. Records appended in separate calls are never coalesced.
+//!
+//! [`WalConfig::coalesce_keys`]: crate::WalConfig::coalesce_keys
+
+use crate::record::{Durability, Record};
+use crate::segment::Position;
+use std::collections::HashMap;
+
+/// A batch with its replaced puts left out.
+pub(crate) struct Coalesced {
+    /// The records to write, in batch order.
+    pub(crate) records: Vec<Record>,
+    /// For each record of the batch, the index in `records` of the one
+    /// written for it.
+    written_as: Vec<usize>,
+}
+
+impl Coalesced {
+    /// Returns how many puts were left out.
+    pub(crate) fn dropped(&self) -> u64 {
+        (self.written_as.len() - self.records.len()) as u64
+    }
+
+    /// Maps the positions `records` were written at to a position for each
+    /// record of the batch.
+    pub(crate) fn positions(&self, written: &[Position]) -> Vec<Position> {
+        self.written_as.iter().map(|&i| written[i]).collect()
+    }
+}
+
+/// Leaves the puts in `records` that a later put replaces out of it, or
+/// returns `None` if there are none.
+pub(crate) fn coalesce(records: &[Record]) -> Option<Coalesced> {
+    // Each record's replacement: itself, or a later put to its key
+    let mut replaced_by: Vec<usize> = (0..records.len()).collect();
+    let mut last_put = HashMap::new();
+    for (i, record) in records.iter().enumerate() {
+        let key = (record.namespace, &record.key[..]);
+        if record.tombstone {
+            last_put.remove(&key);
+        } else if let Some(previous) = last_put.insert(key, i) {
+            replaced_by[previous] = i;
+        }
+    }
+    if replaced_by.iter().enumerate().all(|(i, &by)| i == by) {
+        return None;
+    }
+    // Follow each chain of replacements to the put that is written
+    for i in (0..records.len()).rev() {
+        replaced_by[i] = replaced_by[replaced_by[i]];
+    }
+
+    let mut replaced = vec![0u64; records.len()];
+    let mut critical = vec![false; records.len()];
+    for (i, &by) in replaced_by.iter().enumerate() {
+        if by != i {
+            replaced[by] += 1;
+            critical[by] |= records[i].durability == Durability::Critical;
+        }
+    }
+
+    let mut kept = vec![0; records.len()];
+    let mut written = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+        if replaced_by[i] != i {
+            continue;
+        }
+        kept[i] = written.len();
+        let mut record = record.clone();
+        if replaced[i] > 0 {
+            record.coalesced = Some(replaced[i]);
+        }
+        if critical[i] {
+            record.durability = Durability::Critical;
+        }
+        written.push(record);
+    }
+    Some(Coalesced {
+        records: written,
+        written_as: replaced_by.iter().map(|&by| kept[by]).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keeps_last_put_of_each_key() {
+        let records = vec![
+            Record::put("counter", "1"),
+            Record::put("other", "a"),
+            Record::put("counter", "2").with_durability(Durability::Critical),
+            Record::put("counter", "3"),
+            Record::put("counter", "x").with_namespace(7),
+            // The delete keeps the put before it
+            Record::put("gone", "1"),
+            Record::delete("gone"),
+            Record::put("gone", "2"),
+        ];
+        let coalesced = coalesce(&records).unwrap();
+        let keys: Vec<_> = coalesced
+            .records
+            .iter()
+            .map(|r| (&r.key[..], &r.value[..], r.coalesced))
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                (&b"other"[..], &b"a"[..], None),
+                (b"counter", b"3", Some(2)),
+                (b"counter", b"x", None),
+                (b"gone", b"1", None),
+                (b"gone", b"", None),
+                (b"gone", b"2", None),
+            ]
+        );
+        assert_eq!(coalesced.records[1].durability, Durability::Critical);
+        assert_eq!(coalesced.dropped(), 2);
+
+        let written: Vec<Position> = (0..6)
+            .map(|offset| Position {
+                segment_id: 0,
+                offset,
+            })
+            .collect();
+        let offsets: Vec<u64> = coalesced
+            .positions(&written)
+            .iter()
+            .map(|p| p.offset)
+            .collect();
+        assert_eq!(offsets, [1, 0, 1, 1, 2, 3, 4, 5]);
+
+        assert!(coalesce(&records[4..]).is_none());
+    }
+}
@@ -0,0 +1,293 @@
+//! Online compaction of sealed segments.
+//!
+//! A log kept for a long time fills up with records that later appends
+//! overwrote or deleted. Compaction rewrites old sealed segments, keeping for
+//! each key (within its namespace) only the newest record before a horizon,
+//! and drops the rest. Tombstones that are the newest record for their key
+//! are kept, so consumers replaying the log still see the delete.
+//!
+//! Only segments before the horizon are read and rewritten; records after it
+//! are never consulted, so a key overwritten after the horizon keeps its old
+//! record until the horizon moves past the overwrite. The horizon trails the
+//! active segment by [`WalConfig::compaction_retain_segments`] segments.
+//!
+//! Each segment is replaced by writing the kept records beside it, fsyncing
+//! them and renaming the result over the original, so a crash leaves one or
+//! the other. Kept records keep their LSNs and timestamps, which leaves gaps
+//! in the LSNs of compacted segments, and move to new offsets: positions
+//! saved inside a compacted segment, other than its start, no longer point at
+//! a record. Segments that an open reader is at or before are skipped until a
+//! later pass.
+//!
+//! [`WalConfig::compaction_retain_segments`]: crate::WalConfig::compaction_retain_segments
+
+use crate::audit::AuditOperation;
+use crate::record::Record;
+use crate::segment::{Position, SegmentError, SegmentManager};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Summary of one compaction pass.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Sealed segments read to find superseded records.
+    pub segments_scanned: u64,
+    /// Segments rewritten without their superseded records.
+    pub segments_compacted: u64,
+    /// Superseded records dropped.
+    pub records_dropped: u64,
+    /// Bytes the rewritten segments shrank by.
+    pub bytes_reclaimed: u64,
+}
+
+/// Compacts the sealed segments before segment `horizon`, rewriting those in
+/// which at least `min_dead_percent` of the bytes belong to superseded
+/// records. Passes that rewrite anything are audited as made by `source`.
+pub(crate) async fn compact_sealed_segments(
+    manager: &SegmentManager,
+    horizon: u64,
+    min_dead_percent: u8,
+    source: &str,
+) -> Result<CompactionReport, SegmentError> {
+    // Offsets found by the first pass must still be valid in the second
+    let _compacting = manager.compaction_lock().lock().await;
+    let mut report = CompactionReport::default();
+    let mut ids = manager.sealed_segment_ids().await?;
+    ids.retain(|&id| id < horizon);
+
+    // Where the newest record for each key before the horizon is
+    let mut newest: HashMap<(Option<u32>, Bytes), Position> = HashMap::new();
+    for &id in &ids {
+        for (record, position) in read_segment(manager, id).await? {
+            newest.insert((record.namespace, record.key), position);
+        }
+    }
+    report.segments_scanned = ids.len() as u64;
+
+    let dir = manager.dir().await;
+    for id in ids {
+        let mut kept = Vec::new();
+        let mut kept_usage = Vec::new();
+        let mut dropped = 0;
+        for (record, position) in read_segment(manager, id).await? {
+            let key = (record.namespace, record.key.clone());
+            if newest.get(&key) == Some(&position) {
+                let encoded = record.encode();
+                kept_usage.push((record.namespace, encoded.len() as u64));
+                kept.extend_from_slice(&encoded);
+            } else {
+                dropped += 1;
+            }
+        }
+        if dropped == 0 {
+            continue;
+        }
+
+        let len = manager.store().len(&dir, id).await?;
+        let dead = len.saturating_sub(kept.len() as u64);
+        if dead.saturating_mul(100) < u64::from(min_dead_percent).saturating_mul(len) {
+            continue;
+        }
+        if let Some(old_len) = manager
+            .replace_sealed_segment(id, &kept, &kept_usage)
+            .await?
+        {
+            report.segments_compacted += 1;
+            report.records_dropped += dropped;
+            report.bytes_reclaimed += old_len.saturating_sub(kept.len() as u64);
+        }
+    }
+
+    if report.segments_compacted > 0 {
+        let detail = format!(
+            "segments before {:06}: {} rewritten, {} records and {} bytes dropped",
+            horizon, report.segments_compacted, report.records_dropped, report.bytes_reclaimed
+        );
+        manager
+            .audit(AuditOperation::Compact, source, detail)
+            .await?;
+    }
+    Ok(report)
+}
+
+/// Reads every record of segment `id`. The reader is gone by the time this
+/// returns, as an open one would keep the segment from being replaced.
+async fn read_segment(
+    manager: &SegmentManager,
+    id: u64,
+) -> Result<Vec<(Record, Position)>, SegmentError> {
+    let mut reader = manager
+        .read_from(Position {
+            segment_id: id,
+            offset: 0,
+        })
+        .await?;
+    let mut records = Vec::new();
+    while let Some((record, position)) = reader.next_record().await? {
+        if position.segment_id != id {
+            break;
+        }
+        records.push((record, position));
+    }
+    Ok(records)
+}
+
+/// Runs a compaction pass every `interval`, keeping the newest
+/// `retain_segments` sealed segments out of it.
+pub(crate) async fn run_compactor(
+    manager: Arc<SegmentManager>,
+    interval: Duration,
+    retain_segments: u64,
+    min_dead_percent: u8,
+) {
+    loop {
+        manager.runtime().sleep(interval).await;
+        let horizon = compaction_horizon(&manager, retain_segments).await;
+        let _ = compact_sealed_segments(&manager, horizon, min_dead_percent, "compactor").await;
+    }
+}
+
+/// The first segment compaction leaves alone, `retain_segments` before the
+/// active one.
+pub(crate) async fn compaction_horizon(manager: &SegmentManager, retain_segments: u64) -> u64 {
+    manager
+        .current_position()
+        .await
+        .segment_id
+        .saturating_sub(retain_segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::SegmentConfig;
+    use nori_observe::NoopMeter;
+    use tempfile::TempDir;
+
+    async fn manager(dir: &std::path::Path) -> SegmentManager {
+        let config = SegmentConfig {
+            dir: dir.to_path_buf(),
+            max_segment_size: 256,
+            preallocate: false,
+            ..Default::default()
+        };
+        SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap()
+    }
+
+    async fn read_all(manager: &SegmentManager) -> Vec<(Record, Position)> {
+        let mut records = Vec::new();
+        for id in 0..=manager.current_position().await.segment_id {
+            records.extend(read_segment(manager, id).await.unwrap());
+        }
+        records
+    }
+
+    /// What replaying `records` leaves for each key.
+    fn final_state(records: &[(Record, Position)]) -> HashMap<(Option<u32>, Bytes), Option<Bytes>> {
+        records
+            .iter()
+            .map(|(record, _)| {
+                let value = (!record.tombstone).then(|| record.value.clone());
+                ((record.namespace, record.key.clone()), value)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_the_newest_record_per_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = manager(temp_dir.path()).await;
+        for round in 0..10 {
+            for key in ["a", "b", "c"] {
+                let value = format!("{}{}", key, round);
+                manager.append(&Record::put(key, value)).await.unwrap();
+            }
+        }
+        manager.append(&Record::delete("b")).await.unwrap();
+        let namespaced = Record::put("a", "other").with_namespace(7);
+        manager.append(&namespaced).await.unwrap();
+        manager.sync().await.unwrap();
+        let horizon = manager.current_position().await.segment_id;
+        assert!(horizon >= 2, "expected several sealed segments");
+        let before = read_all(&manager).await;
+
+        let report = compact_sealed_segments(&manager, horizon, 0, "test")
+            .await
+            .unwrap();
+        assert_eq!(report.segments_scanned, horizon);
+        assert!(report.segments_compacted > 0);
+        assert!(report.records_dropped > 0);
+        assert!(report.bytes_reclaimed > 0);
+
+        // Replaying the compacted log ends in the same state
+        let after = read_all(&manager).await;
+        assert_eq!(
+            before.len() as u64,
+            after.len() as u64 + report.records_dropped
+        );
+        assert_eq!(final_state(&after), final_state(&before));
+        let lsns: Vec<u64> = after.iter().map(|(r, _)| r.lsn.unwrap()).collect();
+        assert!(lsns.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(lsns.last(), Some(&32));
+
+        // Each key appears at most once before the horizon
+        let mut sealed_keys: Vec<_> = after
+            .iter()
+            .filter(|(_, position)| position.segment_id < horizon)
+            .map(|(record, _)| (record.namespace, record.key.clone()))
+            .collect();
+        let count = sealed_keys.len();
+        sealed_keys.sort();
+        sealed_keys.dedup();
+        assert_eq!(sealed_keys.len(), count);
+
+        let again = compact_sealed_segments(&manager, horizon, 0, "test")
+            .await
+            .unwrap();
+        assert_eq!(again.records_dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_compaction_skips_segments_below_the_threshold_or_read() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = manager(temp_dir.path()).await;
+        for i in 0..40 {
+            let key = if i % 10 == 0 {
+                "hot".to_string()
+            } else {
+                format!("key{}", i)
+            };
+            manager.append(&Record::put(key, "v")).await.unwrap();
+        }
+        manager.sync().await.unwrap();
+        let horizon = manager.current_position().await.segment_id;
+
+        // Few enough records are superseded that no segment qualifies
+        let report = compact_sealed_segments(&manager, horizon, 50, "test")
+            .await
+            .unwrap();
+        assert_eq!(report.segments_compacted, 0);
+
+        // A reader at the start holds every segment in place
+        let reader = manager
+            .read_from(Position {
+                segment_id: 0,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        let report = compact_sealed_segments(&manager, horizon, 0, "test")
+            .await
+            .unwrap();
+        assert_eq!(report.segments_compacted, 0);
+        drop(reader);
+        let report = compact_sealed_segments(&manager, horizon, 0, "test")
+            .await
+            .unwrap();
+        assert!(report.segments_compacted > 0);
+    }
+}
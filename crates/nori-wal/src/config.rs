@@ -0,0 +1,902 @@
+//! Loading [`WalConfig`] from configuration files and the environment.
+//!
+//! Settings use the names of the [`WalConfig`] fields, with a few
+//! additions for fields that are not plain values:
+//!
+//! | Setting                    | Example                      |
+//! |----------------------------|------------------------------|
+//! | `dir`                      | `/var/lib/app/wal`           |
+//! | `max_segment_size`         | `128MiB`, `64MB`, `1048576`  |
+//! | `fsync_policy`             | `always`, `batch`, `os`      |
+//! | `fsync_window`             | `5ms` (implies `batch`)      |
+//! | `preallocate`              | `true`                       |
+//! | `node_id`                  | `3`                          |
+//! | `quarantine_corrupted`     | `false`                      |
+//! | `recovery_mode`            | `truncate_tail`              |
+//! | `scrub_interval`           | `1h`, `off`                  |
+//! | `verify_on_seal`           | `true`                       |
+//! | `seal_segments`            | `true`                       |
+//! | `truncate_after_target`    | `false`                      |
+//! | `skip_expired_on_replay`   | `false`                      |
+//! | `recovery_budget_bytes`    | `4GiB`                       |
+//! | `recovery_budget_duration` | `30s`                        |
+//! | `resume_recovery_in_background` | `true`                  |
+//! | `recovery_reports_kept`    | `10`                         |
+//! | `purge_on_checkpoint`      | `true`                       |
+//! | `max_record_size`          | `16MiB`                      |
+//! | `max_segment_age`          | `15m`, `off`                 |
+//! | `sync_on_drop`             | `false`                      |
+//! | `slow_fsync_threshold`     | `100ms`, `off`               |
+//! | `compaction_interval`      | `10m`, `off`                 |
+//! | `compaction_retain_segments` | `4`                        |
+//! | `compaction_min_dead_percent` | `25`                      |
+//! | `audit_log`                | `false`                      |
+//! | `hash_chain`               | `false`                      |
+//! | `record_cache_bytes`       | `1MiB`, `0`                  |
+//! | `encode_workers`           | `2`, `0`                     |
+//! | `encode_offload_bytes`     | `64KiB`                      |
+//! | `append_slo`               | `20ms`, `off`                |
+//! | `fsync_slo`                | `10ms`, `off`                |
+//! | `coalesce_keys`            | `false`                      |
+//! | `track_expiry`             | `false`                      |
+//!
+//! Sizes take decimal (`KB`, `MB`, `GB`, `TB`) or binary (`KiB`, `MiB`,
+//! `GiB`, `TiB`) units, or none for bytes. Durations need a unit: `ns`, `us`,
+//! `ms`, `s`, `m` or `h`. Anything not set keeps its default. `append_slo`
+//! and `fsync_slo` set a [`LatencySlo`]'s threshold, leaving the rest of it
+//! at the defaults. The recovery
+//! target is left out on purpose, as it is chosen per restore rather than
+//! configured, and so is the memory budget, which is shared between WALs.
+//!
+//! [`WalConfig::from_env`] reads the same settings from variables such as
+//! `NORI_WAL_MAX_SEGMENT_SIZE`. [`WalConfig::from_file`] reads TOML or YAML
+//! and needs the `config` feature.
+//!
+//! However it was built, a configuration is checked by
+//! [`WalConfig::validate`] when the WAL opens.
+
+use crate::quota::{NamespaceQuota, QuotaEnforcement};
+use crate::recovery::RecoveryMode;
+use crate::segment::FsyncPolicy;
+use crate::slo::LatencySlo;
+use crate::wal::WalConfig;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Batch window used when `fsync_policy` is `batch` without `fsync_window`.
+const DEFAULT_FSYNC_WINDOW: Duration = Duration::from_millis(5);
+
+/// Smallest segment size accepted, below which rotation dominates.
+const MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
+
+/// Longest fsync batch window accepted, bounding the writes lost on a crash.
+const MAX_FSYNC_WINDOW: Duration = Duration::from_secs(1);
+
+/// Errors from loading or validating a [`WalConfig`].
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("I/O error reading config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse config: {0}")]
+    Parse(String),
+    #[error("Invalid value for `{field}`: {message}")]
+    InvalidValue { field: String, message: String },
+}
+
+impl ConfigError {
+    /// Returns the setting at fault, if the error is about one.
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            ConfigError::InvalidValue { field, .. } => Some(field),
+            _ => None,
+        }
+    }
+
+    fn invalid(field: &str, message: impl Into<String>) -> Self {
+        ConfigError::InvalidValue {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl WalConfig {
+    /// Checks that the settings make sense together and that the WAL
+    /// directory can be created and written, naming the offending setting
+    /// if not. Called by [`Wal::open`](crate::Wal::open).
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_record_size == Some(0) {
+            return Err(ConfigError::invalid(
+                "max_record_size",
+                "must be greater than 0",
+            ));
+        }
+        check_segment_size(self.max_segment_size, self.max_record_size)?;
+        check_segment_age(self.max_segment_age)?;
+        check_fsync_policy(self.fsync_policy)?;
+
+        if self.recovery_target.is_some() && !self.recovery_budget.is_unbounded() {
+            return Err(ConfigError::invalid(
+                "recovery_budget",
+                "cannot be combined with recovery_target",
+            ));
+        }
+
+        if self
+            .scrub_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(ConfigError::invalid("scrub_interval", "cannot be zero"));
+        }
+        if self
+            .compaction_interval
+            .is_some_and(|interval| interval.is_zero())
+        {
+            return Err(ConfigError::invalid(
+                "compaction_interval",
+                "cannot be zero",
+            ));
+        }
+        for quota in self.namespace_quotas.values() {
+            check_namespace_quota(quota)?;
+        }
+        if self.hash_chain && self.compaction_interval.is_some() {
+            return Err(ConfigError::invalid(
+                "compaction_interval",
+                "cannot be combined with hash_chain",
+            ));
+        }
+        if let Some(slo) = &self.append_slo {
+            check_latency_slo("append_slo", slo)?;
+        }
+        if let Some(slo) = &self.fsync_slo {
+            check_latency_slo("fsync_slo", slo)?;
+        }
+        if self
+            .memory_budget
+            .as_ref()
+            .is_some_and(|budget| budget.limit() == 0)
+        {
+            return Err(ConfigError::invalid(
+                "memory_budget",
+                "limit cannot be zero",
+            ));
+        }
+        if self.compaction_min_dead_percent > 100 {
+            return Err(ConfigError::invalid(
+                "compaction_min_dead_percent",
+                "must be at most 100",
+            ));
+        }
+
+        check_dir(&self.dir)
+    }
+
+    /// Reads a configuration from a TOML (`.toml`) or YAML (`.yaml`, `.yml`)
+    /// file holding a flat table of settings.
+    #[cfg(feature = "config")]
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let settings: std::collections::BTreeMap<String, file::Scalar> =
+            match extension.to_ascii_lowercase().as_str() {
+                "toml" => toml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?,
+                "yaml" | "yml" => {
+                    serde_yaml::from_str(&text).map_err(|e| ConfigError::Parse(e.to_string()))?
+                }
+                _ => {
+                    return Err(ConfigError::Parse(format!(
+                        "unsupported config file extension {:?} (expected toml, yaml or yml)",
+                        extension
+                    )))
+                }
+            };
+        let mut config = Self::default();
+        config.apply_settings(
+            settings
+                .into_iter()
+                .map(|(field, value)| (field, value.to_string())),
+            false,
+        )?;
+        Ok(config)
+    }
+
+    /// Reads a configuration from environment variables named `{prefix}_`
+    /// followed by the setting in upper case, such as `NORI_WAL_FSYNC_WINDOW`
+    /// for the prefix `NORI_WAL`.
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::default().with_env(prefix)
+    }
+
+    /// Overrides settings in this configuration with any set in environment
+    /// variables, as read by [`WalConfig::from_env`].
+    ///
+    /// Variables under the prefix that name no setting are ignored, since an
+    /// application may keep its own settings under the same prefix, such as
+    /// `MYAPP_WAL_LOG_LEVEL` next to `MYAPP_WAL_FSYNC_POLICY`. A misspelt
+    /// setting is therefore ignored too; [`WalConfig::from_file`] rejects both.
+    pub fn with_env(mut self, prefix: &str) -> Result<Self, ConfigError> {
+        let prefix = format!("{}_", prefix.trim_end_matches('_'));
+        let settings = std::env::vars().filter_map(|(name, value)| {
+            let field = name.strip_prefix(&prefix)?.to_ascii_lowercase();
+            Some((field, value))
+        });
+        self.apply_settings(settings, true)?;
+        Ok(self)
+    }
+
+    /// Applies `(setting, value)` pairs on top of this configuration,
+    /// skipping settings it does not know if `skip_unknown` is set and
+    /// failing on them otherwise.
+    fn apply_settings(
+        &mut self,
+        settings: impl IntoIterator<Item = (String, String)>,
+        skip_unknown: bool,
+    ) -> Result<(), ConfigError> {
+        // The policy and its window may come in either order
+        let mut fsync_policy = None;
+        let mut fsync_window = None;
+
+        for (field, value) in settings {
+            let field = field.as_str();
+            let value = value.trim();
+            match field {
+                "dir" => self.dir = PathBuf::from(value),
+                "max_segment_size" => self.max_segment_size = size(field, value)?,
+                "fsync_policy" => {
+                    fsync_policy = Some(match value.to_ascii_lowercase().as_str() {
+                        "always" => FsyncPolicy::Always,
+                        "batch" => FsyncPolicy::Batch(DEFAULT_FSYNC_WINDOW),
+                        "os" => FsyncPolicy::Os,
+                        _ => {
+                            return Err(ConfigError::invalid(
+                                field,
+                                format!("expected always, batch or os, got {:?}", value),
+                            ))
+                        }
+                    })
+                }
+                "fsync_window" => fsync_window = Some(duration(field, value)?),
+                "preallocate" => self.preallocate = boolean(field, value)?,
+                "node_id" => {
+                    self.node_id = value
+                        .parse()
+                        .map_err(|_| ConfigError::invalid(field, "expected a 32-bit integer"))?
+                }
+                "quarantine_corrupted" => self.quarantine_corrupted = boolean(field, value)?,
+                "recovery_mode" => {
+                    self.recovery_mode = match value.to_ascii_lowercase().replace('-', "_").as_str()
+                    {
+                        "fail_on_corruption" => RecoveryMode::FailOnCorruption,
+                        "truncate_tail" => RecoveryMode::TruncateTail,
+                        "skip_bad_records" => RecoveryMode::SkipBadRecords,
+                        _ => {
+                            return Err(ConfigError::invalid(
+                                field,
+                                format!(
+                                    "expected fail_on_corruption, truncate_tail or skip_bad_records, got {:?}",
+                                    value
+                                ),
+                            ))
+                        }
+                    }
+                }
+                "scrub_interval" => {
+                    self.scrub_interval = match value.to_ascii_lowercase().as_str() {
+                        "off" | "none" => None,
+                        _ => Some(duration(field, value)?),
+                    }
+                }
+                "verify_on_seal" => self.verify_on_seal = boolean(field, value)?,
+                "seal_segments" => self.seal_segments = boolean(field, value)?,
+                "truncate_after_target" => self.truncate_after_target = boolean(field, value)?,
+                "skip_expired_on_replay" => self.skip_expired_on_replay = boolean(field, value)?,
+                "recovery_budget_bytes" => {
+                    self.recovery_budget.max_bytes = Some(size(field, value)?)
+                }
+                "recovery_budget_duration" => {
+                    self.recovery_budget.max_duration = Some(duration(field, value)?)
+                }
+                "resume_recovery_in_background" => {
+                    self.resume_recovery_in_background = boolean(field, value)?
+                }
+                "recovery_reports_kept" => {
+                    self.recovery_reports_kept = value
+                        .parse()
+                        .map_err(|_| ConfigError::invalid(field, "expected a count"))?
+                }
+                "purge_on_checkpoint" => self.purge_on_checkpoint = boolean(field, value)?,
+                "max_record_size" => self.max_record_size = Some(size(field, value)?),
+                "max_segment_age" => {
+                    self.max_segment_age = match value.to_ascii_lowercase().as_str() {
+                        "off" | "none" => None,
+                        _ => Some(duration(field, value)?),
+                    }
+                }
+                "sync_on_drop" => self.sync_on_drop = boolean(field, value)?,
+                "slow_fsync_threshold" => {
+                    self.slow_fsync_threshold = match value.to_ascii_lowercase().as_str() {
+                        "off" | "none" => None,
+                        _ => Some(duration(field, value)?),
+                    }
+                }
+                "compaction_interval" => {
+                    self.compaction_interval = match value.to_ascii_lowercase().as_str() {
+                        "off" | "none" => None,
+                        _ => Some(duration(field, value)?),
+                    }
+                }
+                "compaction_retain_segments" => {
+                    self.compaction_retain_segments = value
+                        .parse()
+                        .map_err(|_| ConfigError::invalid(field, "expected a count"))?
+                }
+                "compaction_min_dead_percent" => {
+                    self.compaction_min_dead_percent = value
+                        .parse()
+                        .map_err(|_| ConfigError::invalid(field, "expected a percentage"))?
+                }
+                "audit_log" => self.audit_log = boolean(field, value)?,
+                "hash_chain" => self.hash_chain = boolean(field, value)?,
+                "record_cache_bytes" => self.record_cache_bytes = size(field, value)?,
+                "encode_workers" => {
+                    self.encode_workers = value
+                        .parse()
+                        .map_err(|_| ConfigError::invalid(field, "expected a count"))?
+                }
+                "encode_offload_bytes" => self.encode_offload_bytes = size(field, value)?,
+                "append_slo" => self.append_slo = latency_slo(field, value)?,
+                "fsync_slo" => self.fsync_slo = latency_slo(field, value)?,
+                "coalesce_keys" => self.coalesce_keys = boolean(field, value)?,
+                "track_expiry" => self.track_expiry = boolean(field, value)?,
+                _ if skip_unknown => {}
+                _ => return Err(ConfigError::invalid(field, "unknown setting")),
+            }
+        }
+
+        match (fsync_policy, fsync_window) {
+            (Some(FsyncPolicy::Batch(_)) | None, Some(window)) => {
+                self.fsync_policy = FsyncPolicy::Batch(window)
+            }
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::invalid(
+                    "fsync_window",
+                    "only applies to the batch fsync policy",
+                ))
+            }
+            (Some(policy), None) => self.fsync_policy = policy,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+/// Checks that segments are large enough to be worth rotating and to hold
+/// the largest record allowed.
+pub(crate) fn check_segment_size(
+    max_segment_size: u64,
+    max_record_size: Option<u64>,
+) -> Result<(), ConfigError> {
+    if max_segment_size < MIN_SEGMENT_SIZE {
+        return Err(ConfigError::invalid(
+            "max_segment_size",
+            format!(
+                "{} bytes is below the minimum of {} bytes",
+                max_segment_size, MIN_SEGMENT_SIZE
+            ),
+        ));
+    }
+    if let Some(max_record_size) = max_record_size {
+        if max_segment_size < max_record_size {
+            return Err(ConfigError::invalid(
+                "max_segment_size",
+                format!(
+                    "{} bytes cannot hold a record of max_record_size ({} bytes)",
+                    max_segment_size, max_record_size
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that age-based rotation, if enabled, has a non-zero age.
+pub(crate) fn check_segment_age(max_segment_age: Option<Duration>) -> Result<(), ConfigError> {
+    if max_segment_age.is_some_and(|age| age.is_zero()) {
+        return Err(ConfigError::invalid("max_segment_age", "cannot be zero"));
+    }
+    Ok(())
+}
+
+/// Checks that a latency SLO leaves an error budget and can alert.
+pub(crate) fn check_latency_slo(field: &str, slo: &LatencySlo) -> Result<(), ConfigError> {
+    if slo.threshold.is_zero() || slo.window.is_zero() {
+        return Err(ConfigError::invalid(
+            field,
+            "threshold and window cannot be zero",
+        ));
+    }
+    if !(slo.objective > 0.0 && slo.objective < 1.0) {
+        return Err(ConfigError::invalid(
+            field,
+            format!("objective must be between 0 and 1, got {}", slo.objective),
+        ));
+    }
+    // Every operation being slow burns at 1 / (1 - objective)
+    let highest = 1.0 / (1.0 - slo.objective);
+    if !(slo.max_burn_rate > 0.0 && slo.max_burn_rate <= highest) {
+        return Err(ConfigError::invalid(
+            field,
+            format!(
+                "max_burn_rate must be above 0 and at most {:.1} for this objective, got {}",
+                highest, slo.max_burn_rate
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a batch fsync window is neither zero nor long enough to lose
+/// a large amount of writes in a crash.
+pub(crate) fn check_namespace_quota(quota: &NamespaceQuota) -> Result<(), ConfigError> {
+    if quota.enforcement == QuotaEnforcement::Throttle(Duration::ZERO) {
+        return Err(ConfigError::invalid(
+            "namespace_quotas",
+            "throttle delay cannot be zero",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_fsync_policy(policy: FsyncPolicy) -> Result<(), ConfigError> {
+    if let FsyncPolicy::Batch(window) = policy {
+        if window.is_zero() {
+            return Err(ConfigError::invalid(
+                "fsync_policy",
+                "batch window cannot be zero - use FsyncPolicy::Always instead",
+            ));
+        }
+        if window > MAX_FSYNC_WINDOW {
+            return Err(ConfigError::invalid(
+                "fsync_policy",
+                format!(
+                    "batch window of {:?} risks losing too many writes (at most {:?})",
+                    window, MAX_FSYNC_WINDOW
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that `dir` is, or can be created as, a writable directory.
+fn check_dir(dir: &Path) -> Result<(), ConfigError> {
+    if dir.as_os_str().is_empty() {
+        return Err(ConfigError::invalid("dir", "must not be empty"));
+    }
+    // Missing directories are created on open, inside the nearest that exists
+    let existing = dir
+        .ancestors()
+        .find(|path| path.exists())
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if !existing.is_dir() {
+        return Err(ConfigError::invalid(
+            "dir",
+            format!("{} is not a directory", existing.display()),
+        ));
+    }
+    if !is_writable(existing) {
+        return Err(ConfigError::invalid(
+            "dir",
+            format!("{} is not writable", existing.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string for the whole call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
+
+fn boolean(field: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ConfigError::invalid(
+            field,
+            format!("expected true or false, got {:?}", value),
+        )),
+    }
+}
+
+fn size(field: &str, value: &str) -> Result<u64, ConfigError> {
+    parse_size(value).map_err(|message| ConfigError::invalid(field, message))
+}
+
+fn duration(field: &str, value: &str) -> Result<Duration, ConfigError> {
+    parse_duration(value).map_err(|message| ConfigError::invalid(field, message))
+}
+
+/// Reads a latency SLO from its threshold, or `off`.
+fn latency_slo(field: &str, value: &str) -> Result<Option<LatencySlo>, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "off" | "none" => Ok(None),
+        _ => Ok(Some(LatencySlo::new(duration(field, value)?))),
+    }
+}
+
+/// Splits `"128MiB"` into `("128", "MiB")`.
+fn split_unit(value: &str) -> (&str, &str) {
+    let value = value.trim();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(end);
+    (number, unit.trim())
+}
+
+/// Scales `number` by `unit`, exactly for whole numbers.
+fn scale(number: &str, unit: u64) -> Option<u64> {
+    if let Ok(whole) = number.parse::<u64>() {
+        return whole.checked_mul(unit);
+    }
+    let scaled = number.parse::<f64>().ok()? * unit as f64;
+    (scaled.is_finite() && scaled < u64::MAX as f64).then(|| scaled.round() as u64)
+}
+
+/// Parses a byte size such as `"128MiB"`, `"1.5GB"` or `"4096"`.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_unit(value);
+    let unit = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("unknown size unit {:?} in {:?}", unit, value)),
+    };
+    scale(number, unit).ok_or_else(|| format!("invalid size {:?}", value))
+}
+
+/// Parses a duration such as `"5ms"`, `"1.5s"` or `"2h"`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = split_unit(value);
+    let nanos_per_unit: u64 = match unit {
+        "ns" => 1,
+        "us" | "µs" => 1_000,
+        "ms" => 1_000_000,
+        "s" => 1_000_000_000,
+        "m" | "min" => 60 * 1_000_000_000,
+        "h" => 60 * 60 * 1_000_000_000,
+        "" => return Err(format!("duration {:?} needs a unit, such as 5ms", value)),
+        _ => return Err(format!("unknown duration unit {:?} in {:?}", unit, value)),
+    };
+    scale(number, nanos_per_unit)
+        .map(Duration::from_nanos)
+        .ok_or_else(|| format!("invalid duration {:?}", value))
+}
+
+#[cfg(feature = "config")]
+mod file {
+    use serde::Deserialize;
+    use std::fmt;
+
+    /// A setting's value as written in a file, before it is interpreted.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub(super) enum Scalar {
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Str(String),
+    }
+
+    impl fmt::Display for Scalar {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Scalar::Bool(b) => write!(f, "{}", b),
+                Scalar::Int(i) => write!(f, "{}", i),
+                Scalar::Float(x) => write!(f, "{}", x),
+                Scalar::Str(s) => f.write_str(s),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_size_and_duration() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("128MiB"), Ok(128 << 20));
+        assert_eq!(parse_size("64 MB"), Ok(64_000_000));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert!(parse_size("12 furlongs").is_err());
+        assert!(parse_size("MiB").is_err());
+
+        assert_eq!(parse_duration("5ms"), Ok(Duration::from_millis(5)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("250us"), Ok(Duration::from_micros(250)));
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+    }
+
+    #[test]
+    fn test_apply_settings() {
+        let mut config = WalConfig::default();
+        config
+            .apply_settings(
+                settings(&[
+                    ("fsync_window", "10ms"),
+                    ("max_segment_size", "64MiB"),
+                    ("dir", "/tmp/wal"),
+                    ("recovery_mode", "skip-bad-records"),
+                    ("scrub_interval", "1h"),
+                    ("seal_segments", "true"),
+                    ("recovery_budget_bytes", "1GiB"),
+                    ("sync_on_drop", "true"),
+                    ("slow_fsync_threshold", "off"),
+                    ("compaction_interval", "10m"),
+                    ("audit_log", "true"),
+                    ("record_cache_bytes", "4MiB"),
+                    ("encode_workers", "4"),
+                    ("encode_offload_bytes", "16KiB"),
+                    ("append_slo", "20ms"),
+                    ("fsync_slo", "off"),
+                    ("coalesce_keys", "true"),
+                    ("track_expiry", "true"),
+                ]),
+                false,
+            )
+            .unwrap();
+        assert_eq!(
+            config.fsync_policy,
+            FsyncPolicy::Batch(Duration::from_millis(10))
+        );
+        assert_eq!(config.max_segment_size, 64 << 20);
+        assert_eq!(config.dir, PathBuf::from("/tmp/wal"));
+        assert_eq!(config.recovery_mode, RecoveryMode::SkipBadRecords);
+        assert_eq!(config.scrub_interval, Some(Duration::from_secs(3600)));
+        assert!(config.seal_segments);
+        assert_eq!(config.recovery_budget.max_bytes, Some(1 << 30));
+        assert!(config.sync_on_drop);
+        assert_eq!(config.slow_fsync_threshold, None);
+        assert_eq!(config.compaction_interval, Some(Duration::from_secs(600)));
+        assert!(config.audit_log);
+        assert_eq!(config.record_cache_bytes, 4 << 20);
+        assert_eq!(config.encode_workers, 4);
+        assert_eq!(config.encode_offload_bytes, 16 << 10);
+        assert_eq!(
+            config.append_slo,
+            Some(LatencySlo::new(Duration::from_millis(20)))
+        );
+        assert_eq!(config.fsync_slo, None);
+        assert!(config.coalesce_keys);
+        assert!(config.track_expiry);
+
+        config
+            .apply_settings(settings(&[("fsync_policy", "always")]), false)
+            .unwrap();
+        assert_eq!(config.fsync_policy, FsyncPolicy::Always);
+
+        let err = config
+            .apply_settings(
+                settings(&[("fsync_policy", "os"), ("fsync_window", "5ms")]),
+                false,
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { ref field, .. } if field == "fsync_window")
+        );
+        let err = config
+            .apply_settings(settings(&[("max_segmnet_size", "1MiB")]), false)
+            .unwrap_err();
+        assert!(
+            matches!(err, ConfigError::InvalidValue { ref field, .. } if field == "max_segmnet_size")
+        );
+        config
+            .apply_settings(settings(&[("log_level", "debug")]), true)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_names_the_field() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let valid = WalConfig {
+            dir: temp_dir.path().join("nested/wal"),
+            ..Default::default()
+        };
+        valid.validate().unwrap();
+
+        let field = |config: WalConfig| config.validate().unwrap_err().field().map(String::from);
+        assert_eq!(
+            field(WalConfig {
+                max_segment_size: 0,
+                ..valid.clone()
+            }),
+            Some("max_segment_size".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                max_record_size: Some(256 << 20),
+                ..valid.clone()
+            }),
+            Some("max_segment_size".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                fsync_policy: FsyncPolicy::Batch(Duration::ZERO),
+                ..valid.clone()
+            }),
+            Some("fsync_policy".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                scrub_interval: Some(Duration::ZERO),
+                ..valid.clone()
+            }),
+            Some("scrub_interval".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                compaction_min_dead_percent: 101,
+                ..valid.clone()
+            }),
+            Some("compaction_min_dead_percent".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                hash_chain: true,
+                compaction_interval: Some(Duration::from_secs(60)),
+                ..valid.clone()
+            }),
+            Some("compaction_interval".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                fsync_slo: Some(LatencySlo {
+                    objective: 1.0,
+                    ..Default::default()
+                }),
+                ..valid.clone()
+            }),
+            Some("fsync_slo".into())
+        );
+        let throttle = crate::quota::NamespaceQuota {
+            enforcement: QuotaEnforcement::Throttle(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(
+            field(WalConfig {
+                namespace_quotas: [(1, throttle)].into(),
+                ..valid.clone()
+            }),
+            Some("namespace_quotas".into())
+        );
+        assert_eq!(
+            field(WalConfig {
+                memory_budget: Some(crate::memory::MemoryBudget::new(0)),
+                ..valid.clone()
+            }),
+            Some("memory_budget".into())
+        );
+
+        // A file where the directory should be
+        let file = temp_dir.path().join("not-a-dir");
+        std::fs::write(&file, b"").unwrap();
+        let err = WalConfig {
+            dir: file.join("wal"),
+            ..valid.clone()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.field(), Some("dir"));
+        assert!(err.to_string().contains("not a directory"), "{}", err);
+    }
+
+    /// Environment variables set for as long as the guard lives.
+    struct EnvVars(Vec<&'static str>);
+
+    impl EnvVars {
+        fn set(vars: &[(&'static str, &str)]) -> Self {
+            for (name, value) in vars {
+                std::env::set_var(name, value);
+            }
+            Self(vars.iter().map(|(name, _)| *name).collect())
+        }
+    }
+
+    impl Drop for EnvVars {
+        fn drop(&mut self) {
+            for name in &self.0 {
+                std::env::remove_var(name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_env() {
+        let vars = EnvVars::set(&[
+            ("NORI_WAL_TEST_ENV_MAX_SEGMENT_SIZE", "32MiB"),
+            ("NORI_WAL_TEST_ENV_FSYNC_POLICY", "os"),
+            ("NORI_WAL_TEST_ENV_NODE_ID", "9"),
+            // The application's own setting under the same prefix
+            ("NORI_WAL_TEST_ENV_LOG_LEVEL", "debug"),
+        ]);
+        let config = WalConfig::from_env("NORI_WAL_TEST_ENV").unwrap();
+        assert_eq!(config.max_segment_size, 32 << 20);
+        assert_eq!(config.fsync_policy, FsyncPolicy::Os);
+        assert_eq!(config.node_id, 9);
+        assert!(config.preallocate);
+
+        drop(vars);
+        assert!(std::env::var("NORI_WAL_TEST_ENV_NODE_ID").is_err());
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_from_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let toml_path = temp_dir.path().join("wal.toml");
+        std::fs::write(
+            &toml_path,
+            "dir = \"/data/wal\"\nmax_segment_size = \"256MiB\"\nfsync_window = \"2ms\"\nnode_id = 4\npreallocate = false\n",
+        )
+        .unwrap();
+        let config = WalConfig::from_file(&toml_path).unwrap();
+        assert_eq!(config.dir, PathBuf::from("/data/wal"));
+        assert_eq!(config.max_segment_size, 256 << 20);
+        assert_eq!(
+            config.fsync_policy,
+            FsyncPolicy::Batch(Duration::from_millis(2))
+        );
+        assert_eq!(config.node_id, 4);
+        assert!(!config.preallocate);
+
+        let yaml_path = temp_dir.path().join("wal.yml");
+        std::fs::write(
+            &yaml_path,
+            "max_segment_size: 1048576\nfsync_policy: always\nseal_segments: true\n",
+        )
+        .unwrap();
+        let config = WalConfig::from_file(&yaml_path).unwrap();
+        assert_eq!(config.max_segment_size, 1 << 20);
+        assert_eq!(config.fsync_policy, FsyncPolicy::Always);
+        assert!(config.seal_segments);
+
+        let bad_path = temp_dir.path().join("wal.ini");
+        std::fs::write(&bad_path, "").unwrap();
+        assert!(matches!(
+            WalConfig::from_file(&bad_path),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+//! Copying a WAL's records into a fresh directory.
+//!
+//! [`Wal::copy_to`] writes every durable record of an open WAL into a new
+//! WAL directory, numbering its segments from zero. Records keep their LSNs,
+//! timestamps and TTLs, so replaying the copy ends in the same state as
+//! replaying the original. Unlike [`Wal::backup_to`], which copies segment
+//! files byte for byte, the copy can leave out records that later writes
+//! replaced ([`CopyOptions::drop_superseded`]) and recompress values
+//! ([`CopyOptions::compression`]), which makes it the way to shrink a log
+//! that grew bloated or to hand someone a small copy to reproduce a bug.
+//!
+//! Positions are not kept, so the last checkpoint and the metadata blobs are
+//! not copied. Appends continue while the copy runs; compaction and purges
+//! wait for it.
+//!
+//! [`Wal::copy_to`]: crate::Wal::copy_to
+//! [`Wal::backup_to`]: crate::Wal::backup_to
+
+use crate::import::ImportConfig;
+use crate::record::Compression;
+use crate::segment::{Position, SegmentError, SegmentManager, SegmentReader};
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// What to change while copying, for
+/// [`Wal::copy_to`](crate::Wal::copy_to).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Only copy the newest record for each key (per namespace), tombstones
+    /// included (default: false).
+    pub drop_superseded: bool,
+    /// Compression to write every value with, instead of the one it was
+    /// appended with (default: None, keep it).
+    pub compression: Option<Compression>,
+}
+
+/// Outcome of a copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyReport {
+    /// Records written to the copy.
+    pub records_copied: u64,
+    /// Superseded records left out.
+    pub records_dropped: u64,
+    /// Segments the copy is made of.
+    pub segments: u64,
+    /// Encoded bytes written.
+    pub bytes: u64,
+    /// Durable position of the original when the copy started; the copy
+    /// holds the records before it.
+    pub position: Position,
+}
+
+/// Writes the durable records of `source` to `dest`, a WAL opened on an
+/// empty directory, and syncs it.
+pub(crate) async fn copy_records(
+    source: &SegmentManager,
+    dest: &SegmentManager,
+    options: &CopyOptions,
+) -> Result<CopyReport, SegmentError> {
+    // Positions found by the first pass must still be valid in the second
+    let _compacting = source.compaction_lock().lock().await;
+    let _purges = source.hold_purges().await;
+    let end = source.durable_position().await;
+    let mut ids = source.sealed_segment_ids().await?;
+    ids.retain(|&id| id < end.segment_id);
+    ids.push(end.segment_id);
+
+    let newest = if options.drop_superseded {
+        let mut newest: HashMap<(Option<u32>, Bytes), Position> = HashMap::new();
+        for &id in &ids {
+            let mut reader = read_segment(source, id).await?;
+            while let Some((record, position)) = reader.next_record().await? {
+                if position >= end {
+                    break;
+                }
+                newest.insert((record.namespace, record.key), position);
+            }
+        }
+        Some(newest)
+    } else {
+        None
+    };
+
+    let mut report = CopyReport {
+        records_copied: 0,
+        records_dropped: 0,
+        segments: 0,
+        bytes: 0,
+        position: end,
+    };
+    let chunk_bytes = ImportConfig::default().chunk_bytes;
+    let mut chunk = Vec::new();
+    let mut chunk_size = 0;
+    for &id in &ids {
+        let mut reader = read_segment(source, id).await?;
+        while let Some((mut record, position)) = reader.next_record().await? {
+            if position >= end {
+                break;
+            }
+            let key = (record.namespace, record.key.clone());
+            if newest
+                .as_ref()
+                .is_some_and(|newest| newest.get(&key) != Some(&position))
+            {
+                report.records_dropped += 1;
+                continue;
+            }
+            // The copy's hash chain, if any, is its own
+            record.chain = None;
+            if let Some(compression) = options.compression {
+                record.compression = compression;
+            }
+            chunk_size += record.key.len() + record.value.len();
+            chunk.push(record);
+            if chunk_size >= chunk_bytes {
+                report.bytes += dest.import_chunk(&chunk).await?;
+                report.records_copied += chunk.len() as u64;
+                chunk.clear();
+                chunk_size = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        report.bytes += dest.import_chunk(&chunk).await?;
+        report.records_copied += chunk.len() as u64;
+    }
+
+    dest.sync().await?;
+    report.segments = dest.current_position().await.segment_id + 1;
+    Ok(report)
+}
+
+/// Reads segment `id` from its start.
+async fn read_segment(manager: &SegmentManager, id: u64) -> Result<SegmentReader, SegmentError> {
+    manager
+        .read_from(Position {
+            segment_id: id,
+            offset: 0,
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FsyncPolicy, Record, Wal};
+    use futures::StreamExt;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_copy_drops_superseded_and_recompresses() {
+        let source_dir = TempDir::new().unwrap();
+        let dest_dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::builder()
+            .dir(source_dir.path())
+            .fsync(FsyncPolicy::Os)
+            .segment_size(1024 * 1024)
+            .preallocate(false)
+            .open()
+            .await
+            .unwrap();
+        for round in 0..20 {
+            for key in ["a", "b", "c"] {
+                let value = format!("{}-{}", key, round).repeat(5_000);
+                wal.append(&Record::put(key, value)).await.unwrap();
+            }
+        }
+        wal.append(&Record::delete("b")).await.unwrap();
+        wal.sync().await.unwrap();
+        assert!(wal.current_position().await.segment_id > 0);
+
+        let options = CopyOptions {
+            drop_superseded: true,
+            compression: Some(Compression::Zstd),
+        };
+        let report = wal.copy_to(dest_dir.path(), options).await.unwrap();
+        assert_eq!(report.records_copied, 3);
+        assert_eq!(report.records_dropped, 58);
+        assert_eq!(report.segments, 1);
+        assert_eq!(report.position, wal.durable_position().await);
+        // A copy never lands on segments that are already there
+        assert!(matches!(
+            wal.copy_to(dest_dir.path(), CopyOptions::default()).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        wal.close().await.unwrap();
+
+        let (copy, info) = Wal::builder()
+            .dir(dest_dir.path())
+            .preallocate(false)
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 3);
+        let records: Vec<_> = copy
+            .reader(Position {
+                segment_id: 0,
+                offset: 0,
+            })
+            .map(|entry| entry.unwrap().0)
+            .collect()
+            .await;
+        let kept: Vec<_> = records
+            .iter()
+            .map(|r| (&r.key[..], r.tombstone, r.lsn, r.compression))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                (&b"a"[..], false, Some(58), Compression::Zstd),
+                (b"c", false, Some(60), Compression::Zstd),
+                (b"b", true, Some(61), Compression::Zstd),
+            ]
+        );
+        assert_eq!(records[0].value, "a-19".repeat(5_000));
+        assert_eq!(copy.next_lsn(), 62);
+        copy.close().await.unwrap();
+    }
+}
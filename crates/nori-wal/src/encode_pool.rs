@@ -0,0 +1,126 @@
+//! Compression of record values off the async append path.
+//!
+//! Compressing a large value with zstd takes long enough to stall the
+//! executor thread an append runs on, and every other task waiting on that
+//! thread with it. Before taking the segment lock, an append hands the values
+//! of its compressed records to the runtime's blocking pool when they add up
+//! to at least [`WalConfig::encode_offload_bytes`], and waits for them. At
+//! most [`WalConfig::encode_workers`] appends are compressed at once; others
+//! wait for a turn rather than piling more threads onto the pool.
+//!
+//! Compressed values come back in record order, and LSNs, timestamps and
+//! chain links are still stamped under the segment lock, so offloading
+//! changes when the work is done but never what is written.
+//!
+//! [`WalConfig::encode_offload_bytes`]: crate::WalConfig::encode_offload_bytes
+//! [`WalConfig::encode_workers`]: crate::WalConfig::encode_workers
+
+use crate::record::{Compression, Record};
+use crate::runtime::{self, Runtime};
+use crate::segment::SegmentError;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::Semaphore;
+
+/// Bounded offloading of compression to a runtime's blocking pool.
+pub(crate) struct EncodePool {
+    /// One permit per worker; `None` compresses everything inline.
+    permits: Option<Semaphore>,
+    min_bytes: u64,
+    offloaded: AtomicU64,
+}
+
+impl EncodePool {
+    /// A pool compressing up to `workers` appends at once, for appends with
+    /// at least `min_bytes` of values to compress. Zero workers disables it.
+    pub(crate) fn new(workers: usize, min_bytes: u64) -> Self {
+        Self {
+            permits: (workers > 0).then(|| Semaphore::new(workers)),
+            min_bytes,
+            offloaded: AtomicU64::new(0),
+        }
+    }
+
+    /// Compresses the values of `records` on `runtime`'s blocking pool,
+    /// returning them in order, or `None` if they are better compressed
+    /// inline.
+    pub(crate) async fn compress(
+        &self,
+        runtime: &dyn Runtime,
+        records: &[Record],
+    ) -> Result<Option<Vec<Bytes>>, SegmentError> {
+        let Some(permits) = &self.permits else {
+            return Ok(None);
+        };
+        let bytes: u64 = records
+            .iter()
+            .filter(|record| record.compression != Compression::None)
+            .map(|record| record.value.len() as u64)
+            .sum();
+        if bytes == 0 || bytes < self.min_bytes {
+            return Ok(None);
+        }
+
+        let _permit = permits
+            .acquire()
+            .await
+            .expect("encode pool semaphore is never closed");
+        // Cloning shares the keys and values rather than copying them
+        let records = records.to_vec();
+        let values = runtime::run_blocking(runtime, move || {
+            records.iter().map(Record::compressed_value).collect()
+        })
+        .await?;
+        self.offloaded.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(values))
+    }
+
+    /// Returns how many appends had their values compressed on the pool.
+    pub(crate) fn offloaded(&self) -> u64 {
+        self.offloaded.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::TokioRuntime;
+
+    #[tokio::test]
+    async fn test_compresses_large_batches_in_order() {
+        let pool = EncodePool::new(2, 1024);
+        let records: Vec<Record> = (0..4u8)
+            .map(|i| Record::put("k", vec![i; 4096]).with_compression(Compression::Zstd))
+            .collect();
+        let values = pool
+            .compress(&TokioRuntime, &records)
+            .await
+            .unwrap()
+            .unwrap();
+        for (record, value) in records.iter().zip(&values) {
+            assert_eq!(record.encode_compressed(value), record.encode());
+        }
+        assert_eq!(pool.offloaded(), 1);
+
+        // Small or uncompressed values are left to the writer
+        let small = [Record::put("k", vec![0u8; 100]).with_compression(Compression::Lz4)];
+        assert!(pool
+            .compress(&TokioRuntime, &small)
+            .await
+            .unwrap()
+            .is_none());
+        let plain = [Record::put("k", vec![0u8; 4096])];
+        assert!(pool
+            .compress(&TokioRuntime, &plain)
+            .await
+            .unwrap()
+            .is_none());
+        let disabled = EncodePool::new(0, 0);
+        assert!(disabled
+            .compress(&TokioRuntime, &records)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(pool.offloaded(), 1);
+    }
+}
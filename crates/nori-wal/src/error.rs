@@ -0,0 +1,388 @@
+//! Classified WAL errors for retry and alerting policies.
+//!
+//! Operations return [`SegmentError`], whose variants say what went wrong but
+//! not what to do about it. [`WalError`] wraps one with an [`ErrorClass`] and,
+//! where known, the segment and offset involved:
+//!
+//! ```
+//! use nori_wal::{ErrorClass, SegmentError, WalError};
+//!
+//! let err = WalError::from(SegmentError::Corruption { segment_id: 3, offset: 4096 });
+//! assert_eq!(err.class(), ErrorClass::Corruption);
+//! assert_eq!(err.segment_id(), Some(3));
+//! assert!(!err.is_retriable());
+//! ```
+//!
+//! I/O errors from reading and writing segments come as
+//! [`SegmentError::SegmentIo`], which names the [`IoOp`] that failed, the
+//! segment and its file, and the byte offset, so a field report says where
+//! the disk let the WAL down without having to trace the process.
+
+use crate::record::RecordError;
+use crate::segment::{Position, SegmentError};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The operation on a segment file an I/O error happened in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IoOp {
+    /// Opening or creating a segment, including pre-allocating it.
+    Open,
+    /// Writing appended records.
+    Append,
+    /// Fsyncing appended records.
+    Sync,
+    /// Sealing a full segment at its written size.
+    Rotate,
+    /// Reading records, including during recovery and scrubbing.
+    Read,
+    /// Cutting the log short.
+    Truncate,
+}
+
+impl IoOp {
+    /// Returns the operation's name, as shown in error messages.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IoOp::Open => "open",
+            IoOp::Append => "append",
+            IoOp::Sync => "sync",
+            IoOp::Rotate => "rotate",
+            IoOp::Read => "read",
+            IoOp::Truncate => "truncate",
+        }
+    }
+}
+
+impl fmt::Display for IoOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a caller should react to an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Transient: the same operation may succeed if retried, possibly after
+    /// a backoff (interrupted I/O, a full disk, a directory still locked by
+    /// a closing instance, a namespace at its quota).
+    Retriable,
+    /// Retrying will not help: the request, configuration or environment
+    /// has to change first.
+    Fatal,
+    /// Data on disk failed validation or is missing. Worth an alert; the
+    /// log may need repair or restoring from a backup.
+    Corruption,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorClass::Retriable => "retriable",
+            ErrorClass::Fatal => "fatal",
+            ErrorClass::Corruption => "corruption",
+        })
+    }
+}
+
+/// A [`SegmentError`] with its [`ErrorClass`] and location in the log.
+#[derive(Debug)]
+pub struct WalError {
+    class: ErrorClass,
+    segment_id: Option<u64>,
+    offset: Option<u64>,
+    source: SegmentError,
+}
+
+impl WalError {
+    /// Returns how the error should be handled.
+    pub fn class(&self) -> ErrorClass {
+        self.class
+    }
+
+    /// Returns true if the operation may succeed when retried.
+    pub fn is_retriable(&self) -> bool {
+        self.class == ErrorClass::Retriable
+    }
+
+    /// Returns true if the error points at damaged or missing log data.
+    pub fn is_corruption(&self) -> bool {
+        self.class == ErrorClass::Corruption
+    }
+
+    /// Segment the error concerns, if known.
+    pub fn segment_id(&self) -> Option<u64> {
+        self.segment_id
+    }
+
+    /// Byte offset within [`WalError::segment_id`] the error concerns, if
+    /// known.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// Records where in the log the error happened, keeping any location
+    /// the error already carried.
+    pub fn at(mut self, position: Position) -> Self {
+        if self.segment_id.is_none() {
+            self.segment_id = Some(position.segment_id);
+            self.offset = Some(position.offset);
+        }
+        self
+    }
+
+    /// Returns the underlying error.
+    pub fn inner(&self) -> &SegmentError {
+        &self.source
+    }
+
+    /// Unwraps the underlying error.
+    pub fn into_inner(self) -> SegmentError {
+        self.source
+    }
+}
+
+impl From<SegmentError> for WalError {
+    fn from(source: SegmentError) -> Self {
+        let (segment_id, offset) = match &source {
+            SegmentError::Corruption { segment_id, offset } => (Some(*segment_id), Some(*offset)),
+            SegmentError::CursorGone(position) => {
+                (Some(position.segment_id), Some(position.offset))
+            }
+            SegmentError::SegmentIo {
+                segment_id, offset, ..
+            } => (Some(*segment_id), Some(*offset)),
+            SegmentError::NotFound(id) | SegmentError::Purged(id) => (Some(*id), None),
+            SegmentError::Gap { missing, .. } => (Some(*missing), None),
+            _ => (None, None),
+        };
+        Self {
+            class: source.class(),
+            segment_id,
+            offset,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for WalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} WAL error", self.class)?;
+        match (self.segment_id, self.offset) {
+            (Some(segment_id), Some(offset)) => {
+                write!(f, " (segment {}, offset {})", segment_id, offset)?
+            }
+            (Some(segment_id), None) => write!(f, " (segment {})", segment_id)?,
+            _ => {}
+        }
+        write!(f, ": {}", self.source)
+    }
+}
+
+impl std::error::Error for WalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl SegmentError {
+    /// Classifies the error for retry and alerting decisions.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            SegmentError::Io(e) | SegmentError::SegmentIo { source: e, .. } => io_class(e),
+            SegmentError::Record(e) => record_class(e),
+            SegmentError::Corruption { .. }
+            | SegmentError::Gap { .. }
+            | SegmentError::CorruptMeta(_) => ErrorClass::Corruption,
+            SegmentError::Locked(_)
+            | SegmentError::LeaseHeld(_)
+            | SegmentError::QuotaExceeded { .. } => ErrorClass::Retriable,
+            SegmentError::NotFound(_)
+            | SegmentError::InvalidConfig(_)
+            | SegmentError::CursorGone(_)
+            | SegmentError::Purged(_)
+            | SegmentError::RecordTooLarge { .. }
+            | SegmentError::Config(_)
+            | SegmentError::Closed
+            | SegmentError::Replication(_)
+            | SegmentError::ForeignLog(_)
+            | SegmentError::TailMoved { .. }
+            | SegmentError::LeaseExpired(_)
+            | SegmentError::Fenced { .. }
+            | SegmentError::InDoubt { .. } => ErrorClass::Fatal,
+        }
+    }
+
+    /// Returns true if the operation may succeed when retried.
+    pub fn is_retriable(&self) -> bool {
+        self.class() == ErrorClass::Retriable
+    }
+
+    /// Returns the underlying I/O error, whether or not it carries the
+    /// segment it happened in.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            SegmentError::Io(e) | SegmentError::SegmentIo { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Returns a function wrapping an I/O error from `op` on segment
+    /// `segment_id`, stored at `path`, at byte `offset`.
+    pub(crate) fn io_at(
+        op: IoOp,
+        segment_id: u64,
+        path: &Path,
+        offset: u64,
+    ) -> impl FnOnce(io::Error) -> SegmentError + '_ {
+        move |source| SegmentError::SegmentIo {
+            op,
+            segment_id,
+            path: path.to_path_buf(),
+            offset,
+            source,
+        }
+    }
+}
+
+fn io_class(e: &io::Error) -> ErrorClass {
+    if is_out_of_space(e) {
+        return ErrorClass::Retriable;
+    }
+    match e.kind() {
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            ErrorClass::Retriable
+        }
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => ErrorClass::Corruption,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+/// Whether `e` is a full disk or an exhausted quota. Matched on the OS
+/// error code, as the `ErrorKind`s for them are newer than the MSRV.
+fn is_out_of_space(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        matches!(e.raw_os_error(), Some(libc::ENOSPC | libc::EDQUOT))
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{ERROR_DISK_FULL, ERROR_HANDLE_DISK_FULL};
+        matches!(
+            e.raw_os_error().map(|code| code as u32),
+            Some(ERROR_DISK_FULL | ERROR_HANDLE_DISK_FULL)
+        )
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = e;
+        false
+    }
+}
+
+fn record_class(e: &RecordError) -> ErrorClass {
+    match e {
+        RecordError::Io(e) => io_class(e),
+        RecordError::CompressionFailed(_) => ErrorClass::Fatal,
+        RecordError::CrcMismatch { .. }
+        | RecordError::InvalidCompression(_)
+        | RecordError::DecompressionFailed(_)
+        | RecordError::Incomplete => ErrorClass::Corruption,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk_full() -> io::Error {
+        #[cfg(unix)]
+        let code = libc::ENOSPC;
+        #[cfg(windows)]
+        let code = windows_sys::Win32::Foundation::ERROR_DISK_FULL as i32;
+        io::Error::from_raw_os_error(code)
+    }
+
+    #[test]
+    fn test_classification() {
+        let cases = [
+            (
+                SegmentError::Io(io::Error::from(io::ErrorKind::Interrupted)),
+                ErrorClass::Retriable,
+            ),
+            (SegmentError::Io(disk_full()), ErrorClass::Retriable),
+            (
+                SegmentError::Io(io::Error::from(io::ErrorKind::PermissionDenied)),
+                ErrorClass::Fatal,
+            ),
+            (
+                SegmentError::Record(RecordError::CrcMismatch {
+                    expected: 1,
+                    actual: 2,
+                }),
+                ErrorClass::Corruption,
+            ),
+            (
+                SegmentError::RecordTooLarge { size: 10, max: 5 },
+                ErrorClass::Fatal,
+            ),
+            (SegmentError::Locked("wal".into()), ErrorClass::Retriable),
+            (
+                SegmentError::CorruptMeta("term".into()),
+                ErrorClass::Corruption,
+            ),
+        ];
+        for (err, class) in cases {
+            assert_eq!(err.class(), class, "{}", err);
+            assert_eq!(WalError::from(err).class(), class);
+        }
+    }
+
+    #[test]
+    fn test_location() {
+        let err = WalError::from(SegmentError::Purged(7));
+        assert_eq!((err.segment_id(), err.offset()), (Some(7), None));
+        // An error's own location wins over context added later
+        let err = err.at(Position {
+            segment_id: 9,
+            offset: 10,
+        });
+        assert_eq!(err.segment_id(), Some(7));
+
+        let err = WalError::from(SegmentError::Io(io::Error::other("disk gone"))).at(Position {
+            segment_id: 2,
+            offset: 128,
+        });
+        assert_eq!((err.segment_id(), err.offset()), (Some(2), Some(128)));
+        assert_eq!(
+            err.to_string(),
+            "fatal WAL error (segment 2, offset 128): I/O error: disk gone"
+        );
+    }
+
+    #[test]
+    fn test_io_context() {
+        let path = Path::new("/wal/000004.wal");
+        let err = SegmentError::io_at(IoOp::Sync, 4, path, 8192)(disk_full());
+        assert_eq!(
+            err.io_error().unwrap().raw_os_error(),
+            disk_full().raw_os_error()
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "I/O error during sync of segment 4 (/wal/000004.wal) at offset 8192: {}",
+                disk_full()
+            )
+        );
+
+        let err = WalError::from(err);
+        assert_eq!(err.class(), ErrorClass::Retriable);
+        assert_eq!((err.segment_id(), err.offset()), (Some(4), Some(8192)));
+        assert!(WalError::from(SegmentError::Closed)
+            .inner()
+            .io_error()
+            .is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! Tracking of keys whose TTL has run out.
+//!
+//! Records appended with a TTL keep it in the log, but nothing deletes the
+//! key once the TTL is up: that is left to the embedder, which also has to
+//! drop the key from its own state. With [`WalConfig::track_expiry`] the WAL
+//! keeps an index of the keys whose latest write carries a TTL, bucketed by
+//! the second the TTL runs out, so finding what has expired does not mean
+//! scanning the log:
+//!
+//! - [`Wal::expired_keys`] lists the keys whose TTL has run out, oldest
+//!   expiry first. A key leaves the list once it is put again without a TTL,
+//!   or deleted.
+//! - [`Wal::tombstone_expired`] appends a delete for each expired key the
+//!   embedder's hook accepts, giving it the chance to drop the key from its
+//!   own state first. A put racing with it is never shadowed: the delete is
+//!   only appended if the key is still expired with nothing appended since.
+//!
+//! Expiry is measured from each record's timestamp on the WAL's
+//! [`Clock`](crate::Clock). When the WAL opens, the index is rebuilt by
+//! reading the whole log, which takes as long as a recovery that decodes
+//! every record. A key whose latest write is cut by a truncation leaves the
+//! index, even if an earlier write to it had a TTL.
+//!
+//! [`WalConfig::track_expiry`]: crate::WalConfig::track_expiry
+//! [`Wal::expired_keys`]: crate::Wal::expired_keys
+//! [`Wal::tombstone_expired`]: crate::Wal::tombstone_expired
+
+use crate::record::Record;
+use crate::segment::Position;
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A key whose TTL has run out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiredKey {
+    pub namespace: Option<u32>,
+    pub key: Bytes,
+    /// When the TTL of the key's latest write ran out.
+    pub expired_at: SystemTime,
+    /// LSN of the key's latest write.
+    pub lsn: u64,
+}
+
+type Key = (Option<u32>, Bytes);
+
+/// The latest write of a key, which carries a TTL.
+struct Entry {
+    expires_at: SystemTime,
+    lsn: u64,
+    position: Position,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Keys whose latest write carries a TTL.
+    live: HashMap<Key, Entry>,
+    /// Keys by the second their TTL runs out, with the LSN of the write
+    /// that set it. Writes since replaced are left for `expired` to drop.
+    buckets: BTreeMap<u64, Vec<(Key, u64)>>,
+}
+
+/// Keys whose latest write carries a TTL, by when it runs out.
+#[derive(Default)]
+pub(crate) struct ExpiryIndex {
+    entries: Mutex<Entries>,
+}
+
+impl ExpiryIndex {
+    /// Notes `record`, written at `position` with `lsn` and `timestamp`.
+    pub(crate) fn note(
+        &self,
+        record: &Record,
+        lsn: u64,
+        timestamp: SystemTime,
+        position: Position,
+    ) {
+        let key = (record.namespace, record.key.clone());
+        let expires_at = record
+            .ttl
+            .filter(|_| !record.tombstone)
+            .and_then(|ttl| timestamp.checked_add(ttl));
+        let mut entries = self.entries.lock().unwrap();
+        let Some(expires_at) = expires_at else {
+            entries.live.remove(&key);
+            return;
+        };
+        entries
+            .buckets
+            .entry(bucket(expires_at))
+            .or_default()
+            .push((key.clone(), lsn));
+        entries.live.insert(
+            key,
+            Entry {
+                expires_at,
+                lsn,
+                position,
+            },
+        );
+    }
+
+    /// Returns the keys whose TTL ran out at or before `now`, oldest expiry
+    /// first.
+    pub(crate) fn expired(&self, now: SystemTime) -> Vec<ExpiredKey> {
+        let mut entries = self.entries.lock().unwrap();
+        let Entries { live, buckets } = &mut *entries;
+        let mut expired = Vec::new();
+        for (_, keys) in buckets.range_mut(..=bucket(now)) {
+            keys.retain(|(key, lsn)| live.get(key).is_some_and(|entry| entry.lsn == *lsn));
+            for (key, _) in keys.iter() {
+                let entry = &live[key];
+                if entry.expires_at <= now {
+                    expired.push(ExpiredKey {
+                        namespace: key.0,
+                        key: key.1.clone(),
+                        expired_at: entry.expires_at,
+                        lsn: entry.lsn,
+                    });
+                }
+            }
+        }
+        buckets.retain(|_, keys| !keys.is_empty());
+        expired.sort_by_key(|expired| expired.expired_at);
+        expired
+    }
+
+    /// Whether `expired` is still the latest write of its key.
+    pub(crate) fn is_current(&self, expired: &ExpiredKey) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .live
+            .get(&(expired.namespace, expired.key.clone()))
+            .is_some_and(|entry| entry.lsn == expired.lsn)
+    }
+
+    /// Forgets keys whose latest write was at or after `position`.
+    pub(crate) fn truncate(&self, position: Position) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.live.retain(|_, entry| entry.position < position);
+    }
+
+    /// Returns how many keys have a TTL pending or run out.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.lock().unwrap().live.len()
+    }
+}
+
+/// The second since the epoch `at` falls in.
+fn bucket(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(offset: u64) -> Position {
+        Position {
+            segment_id: 0,
+            offset,
+        }
+    }
+
+    #[test]
+    fn test_lists_keys_whose_latest_write_expired() {
+        let index = ExpiryIndex::default();
+        let t0 = UNIX_EPOCH + Duration::from_secs(1_000);
+        let ttl = |secs| Duration::from_secs(secs);
+
+        index.note(&Record::put_with_ttl("a", "1", ttl(10)), 1, t0, at(0));
+        index.note(&Record::put_with_ttl("b", "1", ttl(5)), 2, t0, at(10));
+        index.note(&Record::put_with_ttl("c", "1", ttl(5)), 3, t0, at(20));
+        index.note(&Record::put_with_ttl("d", "1", ttl(60)), 4, t0, at(30));
+        // Put again without a TTL, deleted, and given a later TTL
+        index.note(&Record::put("b", "2"), 5, t0, at(40));
+        index.note(&Record::delete("c"), 6, t0, at(50));
+        index.note(&Record::put_with_ttl("a", "2", ttl(30)), 7, t0, at(60));
+        index.note(&Record::put_with_ttl("e", "1", ttl(1)), 8, t0, at(70));
+        assert_eq!(index.len(), 3);
+
+        let keys = |now| -> Vec<(Bytes, u64)> {
+            index
+                .expired(t0 + now)
+                .into_iter()
+                .map(|expired| (expired.key, expired.lsn))
+                .collect()
+        };
+        assert!(keys(Duration::ZERO).is_empty());
+        assert_eq!(keys(ttl(20)), [(Bytes::from("e"), 8)]);
+        assert_eq!(
+            keys(ttl(30)),
+            [(Bytes::from("e"), 8), (Bytes::from("a"), 7)]
+        );
+        // Expired keys stay listed until they are written again
+        assert_eq!(keys(ttl(30)).len(), 2);
+
+        let expired = index.expired(t0 + ttl(30)).remove(0);
+        assert!(index.is_current(&expired));
+        index.note(&Record::delete("e"), 9, t0, at(80));
+        assert!(!index.is_current(&expired));
+
+        index.truncate(at(60));
+        assert_eq!(keys(ttl(120)), [(Bytes::from("d"), 4)]);
+    }
+}
@@ -0,0 +1,71 @@
+//! Fault-injection points for crash testing.
+//!
+//! With the `failpoints` feature enabled, the points named below can be armed
+//! through the [`fail`](https://docs.rs/fail) crate, e.g.
+//! `fail::cfg(failpoint::FSYNC, "3*off->return")`. A `return` action makes the
+//! operation fail with an injected I/O error at that point, which together
+//! with abandoning the WAL without closing it simulates a crash there. Without
+//! the feature every point compiles to nothing.
+
+use crate::segment::SegmentError;
+
+/// Before a record's bytes are written to the active segment.
+pub const APPEND_BEFORE_WRITE: &str = "wal::append::before_write";
+/// Writes the first half of a record, then fails (a torn write).
+pub const APPEND_TORN_WRITE: &str = "wal::append::torn_write";
+/// Before the active segment is fsynced.
+pub const FSYNC: &str = "wal::fsync";
+/// After the old segment is finalized, before the next one is created.
+pub const ROTATE_AFTER_FINALIZE: &str = "wal::rotate::after_finalize";
+/// After a repaired or compacted segment is written, before it is renamed
+/// into place.
+pub const RECOVERY_BEFORE_RENAME: &str = "wal::recovery::before_rename";
+/// After a seal sidecar is written, before renaming it into place.
+pub const SEAL_BEFORE_RENAME: &str = "wal::seal::before_rename";
+/// After a migration stages its copies, before it writes its forwarding marker.
+pub const MIGRATE_BEFORE_MARKER: &str = "wal::migrate::before_marker";
+/// After a migration writes its forwarding marker, before the old segments
+/// are removed.
+pub const MIGRATE_AFTER_MARKER: &str = "wal::migrate::after_marker";
+/// After `truncate_from` deletes later segments, before cutting the target.
+pub const TRUNCATE_AFTER_DELETE: &str = "wal::truncate::after_delete";
+
+/// Abandons `wal` the way a process crash would: nothing is flushed,
+/// finalized or cancelled, but the directory lock is released as the OS
+/// releases it when a process dies, so the WAL can be reopened.
+#[cfg(feature = "failpoints")]
+pub fn crash(mut wal: crate::Wal) {
+    drop(wal.lock.take());
+    std::mem::forget(wal);
+}
+
+/// Returns true if the point `name` is armed with a `return` action.
+#[inline]
+pub(crate) fn fired(name: &str) -> bool {
+    #[cfg(feature = "failpoints")]
+    {
+        fail::eval(name, |_| ()).is_some()
+    }
+    #[cfg(not(feature = "failpoints"))]
+    {
+        let _ = name;
+        false
+    }
+}
+
+/// Fails with an injected I/O error if the point `name` fires.
+#[inline]
+pub(crate) fn check(name: &str) -> Result<(), SegmentError> {
+    if fired(name) {
+        return Err(injected(name));
+    }
+    Ok(())
+}
+
+/// The error reported by a point that fired.
+pub(crate) fn injected(name: &str) -> SegmentError {
+    SegmentError::Io(std::io::Error::other(format!(
+        "injected failure at {}",
+        name
+    )))
+}
@@ -0,0 +1,379 @@
+//! Following a leader's log.
+//!
+//! A [`WalFollower`] is the receiving end of [`replication`](crate::replication):
+//! it connects to a leader's [`ReplicationServer`](crate::ReplicationServer),
+//! asks for the records from its own `next_lsn()` on, appends them to the
+//! local WAL with their LSNs and timestamps unchanged, fsyncs, and acks the
+//! last LSN made durable. After a restart it resumes from whatever the local
+//! WAL recovered, so a record is never skipped, and one that is resent is
+//! dropped rather than appended twice.
+//!
+//! The local WAL should not take appends of its own while following, since
+//! they would take LSNs the leader has assigned to other records.
+//!
+//! Reads on a follower that must see a client's own writes wait for the
+//! client's [`SessionToken`] with [`WalFollower::wait_for`]; see
+//! [`session`](crate::session).
+
+use crate::handle::WalWriter;
+use crate::replication::{protocol_error, read_frame, write_frame, Frame, PROTOCOL_VERSION};
+use crate::segment::SegmentError;
+use crate::session::SessionToken;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+/// Settings for a [`WalFollower`].
+#[derive(Debug, Clone)]
+pub struct FollowerConfig {
+    /// Address of the leader's replication server.
+    pub leader: SocketAddr,
+    /// Name reported to the leader, shown in its follower status.
+    pub name: String,
+    /// How long to wait before reconnecting after the connection drops.
+    pub reconnect_delay: Duration,
+    /// Timeout for connecting to the leader.
+    pub connect_timeout: Duration,
+    /// The connection is considered dead if nothing, not even a heartbeat,
+    /// arrives for this long.
+    pub idle_timeout: Duration,
+}
+
+impl Default for FollowerConfig {
+    fn default() -> Self {
+        Self {
+            leader: SocketAddr::from(([127, 0, 0, 1], 7400)),
+            name: "follower".to_string(),
+            reconnect_delay: Duration::from_secs(1),
+            connect_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Applies a leader's log stream to a local WAL.
+///
+/// `run` follows the leader, reconnecting as needed, until a fatal error;
+/// the progress getters can be read from other tasks meanwhile.
+pub struct WalFollower {
+    writer: WalWriter,
+    config: FollowerConfig,
+    leader_next_lsn: AtomicU64,
+    /// LSN of the last record from the leader made durable here.
+    applied: watch::Sender<u64>,
+}
+
+impl WalFollower {
+    /// Creates a follower that appends through `writer`. Records the local
+    /// WAL already holds count as applied.
+    pub fn new(writer: WalWriter, config: FollowerConfig) -> Self {
+        let applied = writer.next_lsn().saturating_sub(1);
+        Self {
+            writer,
+            config,
+            leader_next_lsn: AtomicU64::new(0),
+            applied: watch::Sender::new(applied),
+        }
+    }
+
+    /// LSN the next record from the leader must carry.
+    pub fn next_lsn(&self) -> u64 {
+        self.writer.next_lsn()
+    }
+
+    /// The leader's next LSN as of the last frame received, or 0 before the
+    /// first.
+    pub fn leader_next_lsn(&self) -> u64 {
+        self.leader_next_lsn.load(Ordering::Relaxed)
+    }
+
+    /// Records assigned on the leader that are not yet durable here, as of
+    /// the last frame received.
+    pub fn lag_records(&self) -> u64 {
+        self.leader_next_lsn().saturating_sub(self.next_lsn())
+    }
+
+    /// LSN of the last record made durable here, which readers of the local
+    /// WAL can see.
+    pub fn applied_lsn(&self) -> u64 {
+        *self.applied.borrow()
+    }
+
+    /// Returns true if readers of the local WAL already see every record
+    /// `token` stands for.
+    pub fn covers(&self, token: &SessionToken) -> bool {
+        self.applied_lsn() >= token.lsn()
+    }
+
+    /// Waits until readers of the local WAL see every record `token` stands
+    /// for. Waits for as long as it takes, so wrap it in a timeout to fall
+    /// back to the leader when this follower lags.
+    pub async fn wait_for(&self, token: &SessionToken) {
+        let mut applied = self.applied.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = applied.wait_for(|&lsn| lsn >= token.lsn()).await;
+    }
+
+    /// Follows the leader, reconnecting after dropped connections and I/O
+    /// errors. Returns only on a fatal error, such as the leader refusing
+    /// the stream because the records it needs were purged.
+    pub async fn run(&self) -> Result<(), SegmentError> {
+        loop {
+            match self.run_once().await {
+                Err(e) if e.io_error().is_none() => return Err(e),
+                _ => tokio::time::sleep(self.config.reconnect_delay).await,
+            }
+        }
+    }
+
+    /// Connects to the leader once and applies its stream until the
+    /// connection closes (`Ok`) or fails.
+    pub async fn run_once(&self) -> Result<(), SegmentError> {
+        let connect = TcpStream::connect(self.config.leader);
+        let stream = tokio::time::timeout(self.config.connect_timeout, connect)
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let hello = Frame::Hello {
+            version: PROTOCOL_VERSION,
+            start_lsn: self.writer.next_lsn(),
+            name: self.config.name.clone(),
+        };
+        write_frame(&mut writer, &hello).await?;
+
+        loop {
+            let frame = tokio::time::timeout(self.config.idle_timeout, read_frame(&mut reader))
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+            let (leader_next_lsn, records) = match frame {
+                None => return Ok(()),
+                Some(Frame::Records {
+                    leader_next_lsn,
+                    records,
+                }) => (leader_next_lsn, records),
+                Some(Frame::Error { message }) => {
+                    return Err(protocol_error(format!("leader refused: {}", message)))
+                }
+                Some(other) => return Err(protocol_error(format!("unexpected frame {:?}", other))),
+            };
+            self.leader_next_lsn
+                .store(leader_next_lsn, Ordering::Relaxed);
+
+            // Resent records are already here; anything else out of order
+            // means the logs have diverged
+            let next = self.writer.next_lsn();
+            let mut expected = next;
+            let mut fresh = Vec::with_capacity(records.len());
+            for record in records {
+                match record.lsn {
+                    Some(lsn) if lsn < next => {}
+                    Some(lsn) if lsn == expected => {
+                        expected += 1;
+                        fresh.push(record);
+                    }
+                    lsn => {
+                        return Err(protocol_error(format!(
+                            "expected LSN {}, leader sent {:?}",
+                            expected, lsn
+                        )))
+                    }
+                }
+            }
+            if fresh.is_empty() {
+                continue;
+            }
+
+            self.writer.append_batch(&fresh).await?;
+            self.writer.sync().await?;
+            self.applied.send_replace(expected - 1);
+            let ack = Frame::Ack {
+                durable_lsn: expected - 1,
+            };
+            write_frame(&mut writer, &ack).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::replication::{ReplicationConfig, ReplicationServer};
+    use crate::segment::Position;
+    use crate::wal::{Wal, WalConfig};
+    use futures::StreamExt;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    async fn open(dir: &Path) -> Wal {
+        let config = WalConfig {
+            dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        Wal::open(config).await.unwrap().0
+    }
+
+    async fn append(wal: &Wal, keys: std::ops::Range<u32>) {
+        for i in keys {
+            wal.append(&Record::put(i.to_be_bytes().to_vec(), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+        wal.sync().await.unwrap();
+    }
+
+    async fn read_all(wal: &Wal) -> Vec<Record> {
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        wal.reader(start)
+            .map(|next| next.unwrap().0)
+            .collect()
+            .await
+    }
+
+    async fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(tokio::time::Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    fn follower(wal: &Wal, leader: SocketAddr) -> Arc<WalFollower> {
+        let config = FollowerConfig {
+            leader,
+            name: "replica".to_string(),
+            reconnect_delay: Duration::from_millis(10),
+            ..Default::default()
+        };
+        Arc::new(WalFollower::new(wal.writer(), config))
+    }
+
+    #[tokio::test]
+    async fn test_follows_and_resumes_after_restart() {
+        let leader_dir = TempDir::new().unwrap();
+        let follower_dir = TempDir::new().unwrap();
+        let leader = open(leader_dir.path()).await;
+        append(&leader, 0..10).await;
+
+        let server = leader.replication_server(ReplicationConfig {
+            heartbeat_interval: Duration::from_millis(20),
+            ..Default::default()
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let background = server.clone();
+        tokio::spawn(async move { background.serve(listener).await });
+
+        let local = open(follower_dir.path()).await;
+        let follower = follower(&local, addr);
+        let task = tokio::spawn({
+            let follower = follower.clone();
+            async move { follower.run().await }
+        });
+        append(&leader, 10..20).await;
+        wait_until(|| follower.next_lsn() == 21).await;
+        wait_until(|| server.followers().first().and_then(|f| f.acked_lsn) == Some(20)).await;
+        assert_eq!(follower.lag_records(), 0);
+
+        // Restart the follower while the leader keeps writing
+        task.abort();
+        let _ = task.await;
+        drop(follower);
+        local.close().await.unwrap();
+        append(&leader, 20..30).await;
+
+        let local = open(follower_dir.path()).await;
+        assert_eq!(local.next_lsn(), 21);
+        let follower = self::follower(&local, addr);
+        let task = tokio::spawn({
+            let follower = follower.clone();
+            async move { follower.run().await }
+        });
+        wait_until(|| follower.next_lsn() == 31).await;
+        task.abort();
+        // The last batch may be appended but not yet synced
+        let _ = task.await;
+        local.sync().await.unwrap();
+
+        let records = read_all(&local).await;
+        assert_eq!(records.len(), 30);
+        assert_eq!(records, read_all(&leader).await);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_session_token() {
+        let leader_dir = TempDir::new().unwrap();
+        let follower_dir = TempDir::new().unwrap();
+        let leader = open(leader_dir.path()).await;
+        let local = open(follower_dir.path()).await;
+        append(&local, 0..5).await;
+        append(&leader, 0..5).await;
+
+        // Records already in the local WAL count as applied
+        let follower = follower(&local, "127.0.0.1:9".parse().unwrap());
+        assert_eq!(follower.applied_lsn(), 5);
+        let token = leader.session_token().await.unwrap();
+        assert_eq!(token.lsn(), 5);
+        assert!(follower.covers(&token));
+        follower.wait_for(&token).await;
+
+        append(&leader, 5..10).await;
+        let token = leader.session_token().await.unwrap();
+        assert!(!follower.covers(&token));
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), follower.wait_for(&token))
+                .await
+                .is_err()
+        );
+
+        let server = leader.replication_server(ReplicationConfig::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { server.serve(listener).await });
+        let follower = self::follower(&local, addr);
+        tokio::spawn({
+            let follower = follower.clone();
+            async move { follower.run().await }
+        });
+        tokio::time::timeout(Duration::from_secs(5), follower.wait_for(&token))
+            .await
+            .unwrap();
+        assert!(follower.covers(&token));
+        // Everything the token stands for is readable on the follower
+        assert_eq!(read_all(&local).await.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_diverged_follower_is_fatal() {
+        let leader_dir = TempDir::new().unwrap();
+        let follower_dir = TempDir::new().unwrap();
+        let leader = open(leader_dir.path()).await;
+        let local = open(follower_dir.path()).await;
+        append(&local, 0..5).await;
+
+        // The follower is ahead of a fresh leader, so its logs have diverged
+        let server = ReplicationServer::new(
+            leader.read_handle(),
+            ReplicationConfig::default(),
+            Arc::new(nori_observe::NoopMeter),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { server.serve(listener).await });
+
+        let follower = follower(&local, addr);
+        assert!(matches!(
+            follower.run().await,
+            Err(SegmentError::Replication(_))
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+//! Pluggable filesystem for segment files and sidecars.
+//!
+//! Every file the WAL reads or writes (segments, seals, checkpoints,
+//! quarantined bytes and recovery reports) goes through an [`Fs`], so the
+//! whole log can run against something other than the local disk.
+//! [`LocalFs`] is the default; [`SimFs`](crate::sim::SimFs) keeps files in
+//! memory and decides what survives a simulated crash.
+//!
+//! Files are read and written at explicit offsets rather than through a
+//! cursor, so one handle can be shared by concurrent readers.
+//!
+//! On Windows, [`LocalFs`] syncs files with `FlushFileBuffers` (which is
+//! what `std` calls for both `sync_data` and `sync_all`), renames with
+//! `MoveFileExW(MOVEFILE_WRITE_THROUGH)`, which returns only once the rename
+//! is on disk, and flushes directories through a handle opened with backup
+//! semantics.
+
+use crate::lock::DirLock;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A boxed filesystem operation, borrowing its arguments for `'a`.
+pub type FsFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// How [`Fs::open`] opens a file. Every mode but `Read` allows writing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// An existing file, read only.
+    Read,
+    /// An existing file.
+    Write,
+    /// Creates the file if it is missing, keeping existing contents.
+    Create,
+    /// Creates the file if it is missing and empties it if not.
+    Truncate,
+    /// Creates the file, failing if it already exists.
+    CreateNew,
+}
+
+/// Directory and file operations, as the WAL needs them.
+pub trait Fs: Send + Sync + 'static {
+    /// Opens the file at `path`.
+    fn open<'a>(&'a self, path: &'a Path, mode: OpenMode) -> FsFuture<'a, Arc<dyn FsFile>>;
+
+    /// Creates `dir` and any missing parents.
+    fn create_dir_all<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()>;
+
+    /// Returns the paths of the entries in `dir`, in no particular order.
+    fn read_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<PathBuf>>;
+
+    /// Returns the length of the file at `path`.
+    fn len<'a>(&'a self, path: &'a Path) -> FsFuture<'a, u64>;
+
+    /// Returns true if something exists at `path`.
+    fn exists<'a>(&'a self, path: &'a Path) -> FsFuture<'a, bool>;
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> FsFuture<'a, ()>;
+
+    /// Moves `from` to `to`, replacing any file already there.
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> FsFuture<'a, ()>;
+
+    /// Makes `dst` a second name for the file at `src`.
+    fn hard_link<'a>(&'a self, src: &'a Path, dst: &'a Path) -> FsFuture<'a, ()>;
+
+    /// Makes file creations, removals and renames in `dir` durable.
+    fn sync_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()>;
+
+    /// Takes the exclusive lock on a WAL directory, returning `None` if the
+    /// filesystem has no notion of one. Blocking; the WAL calls it as
+    /// blocking work on its runtime.
+    fn lock_dir(&self, dir: &Path) -> Result<Option<DirLock>, crate::segment::SegmentError>;
+}
+
+/// An open file.
+#[allow(clippy::len_without_is_empty)]
+pub trait FsFile: Send + Sync {
+    /// Reads up to `len` bytes at `offset`, returning fewer only at the end
+    /// of the file.
+    fn read_at(&self, offset: u64, len: usize) -> FsFuture<'_, Vec<u8>>;
+
+    /// Writes all of `data` at `offset`, extending the file if needed.
+    fn write_all_at<'a>(&'a self, offset: u64, data: &'a [u8]) -> FsFuture<'a, ()>;
+
+    fn len(&self) -> FsFuture<'_, u64>;
+
+    fn set_len(&self, len: u64) -> FsFuture<'_, ()>;
+
+    /// Reserves `len` bytes of zeros, growing the file to that length.
+    fn allocate(&self, len: u64) -> FsFuture<'_, ()>;
+
+    /// Makes written data durable.
+    fn sync_data(&self) -> FsFuture<'_, ()>;
+
+    /// Makes written data and the file's metadata durable.
+    fn sync_all(&self) -> FsFuture<'_, ()>;
+
+    /// Like [`sync_data`](Self::sync_data), but blocking, for `Drop`.
+    fn sync_data_blocking(&self) -> io::Result<()>;
+
+    /// Cuts the file to `len` bytes and syncs it if it is longer, blocking,
+    /// for `Drop`.
+    fn truncate_blocking(&self, len: u64) -> io::Result<()>;
+}
+
+/// Reads the whole file at `path`.
+pub(crate) async fn read(fs: &dyn Fs, path: &Path) -> io::Result<Vec<u8>> {
+    let file = fs.open(path, OpenMode::Read).await?;
+    let len = file.len().await?;
+    file.read_at(0, len as usize).await
+}
+
+/// Writes `data` to a new or emptied file at `path` and fsyncs it.
+pub(crate) async fn write_synced(fs: &dyn Fs, path: &Path, data: &[u8]) -> io::Result<()> {
+    let file = fs.open(path, OpenMode::Truncate).await?;
+    file.write_all_at(0, data).await?;
+    file.sync_all().await
+}
+
+/// Copies the first `len` bytes of `src` into a new file at `dst` and
+/// fsyncs it.
+pub(crate) async fn copy_prefix(fs: &dyn Fs, src: &Path, dst: &Path, len: u64) -> io::Result<()> {
+    const CHUNK: u64 = 1024 * 1024;
+
+    let reader = fs.open(src, OpenMode::Read).await?;
+    let writer = fs.open(dst, OpenMode::CreateNew).await?;
+    let mut offset = 0;
+    while offset < len {
+        let chunk = reader
+            .read_at(offset, (len - offset).min(CHUNK) as usize)
+            .await?;
+        if chunk.is_empty() {
+            break;
+        }
+        writer.write_all_at(offset, &chunk).await?;
+        offset += chunk.len() as u64;
+    }
+    writer.sync_all().await
+}
+
+/// The local disk, through `std::fs` on tokio's blocking pool.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalFs;
+
+/// Runs blocking file work on tokio's blocking pool.
+async fn blocking<T, F>(work: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|_| io::Error::other("background file task failed"))?
+}
+
+impl Fs for LocalFs {
+    fn open<'a>(&'a self, path: &'a Path, mode: OpenMode) -> FsFuture<'a, Arc<dyn FsFile>> {
+        let path = path.to_path_buf();
+        Box::pin(async move {
+            let file = blocking(move || {
+                let mut options = std::fs::OpenOptions::new();
+                options.read(true).write(mode != OpenMode::Read);
+                match mode {
+                    OpenMode::Read | OpenMode::Write => {}
+                    OpenMode::Create => {
+                        options.create(true).truncate(false);
+                    }
+                    OpenMode::Truncate => {
+                        options.create(true).truncate(true);
+                    }
+                    OpenMode::CreateNew => {
+                        options.create_new(true);
+                    }
+                }
+                options.open(path)
+            })
+            .await?;
+            Ok(Arc::new(LocalFile(Arc::new(file))) as Arc<dyn FsFile>)
+        })
+    }
+
+    fn create_dir_all<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()> {
+        Box::pin(tokio::fs::create_dir_all(dir))
+    }
+
+    fn read_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<PathBuf>> {
+        let dir = dir.to_path_buf();
+        Box::pin(blocking(move || {
+            std::fs::read_dir(dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect()
+        }))
+    }
+
+    fn len<'a>(&'a self, path: &'a Path) -> FsFuture<'a, u64> {
+        Box::pin(async move { Ok(tokio::fs::metadata(path).await?.len()) })
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> FsFuture<'a, bool> {
+        Box::pin(tokio::fs::try_exists(path))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> FsFuture<'a, ()> {
+        Box::pin(tokio::fs::remove_file(path))
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> FsFuture<'a, ()> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        Box::pin(blocking(move || rename_file(&from, &to)))
+    }
+
+    fn hard_link<'a>(&'a self, src: &'a Path, dst: &'a Path) -> FsFuture<'a, ()> {
+        Box::pin(tokio::fs::hard_link(src, dst))
+    }
+
+    fn sync_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            #[cfg(unix)]
+            tokio::fs::File::open(dir).await?.sync_all().await?;
+
+            #[cfg(windows)]
+            {
+                let dir = dir.to_path_buf();
+                blocking(move || sync_dir_windows(&dir)).await?;
+            }
+
+            #[cfg(not(any(unix, windows)))]
+            let _ = dir;
+
+            Ok(())
+        })
+    }
+
+    fn lock_dir(&self, dir: &Path) -> Result<Option<DirLock>, crate::segment::SegmentError> {
+        DirLock::acquire(dir).map(Some)
+    }
+}
+
+/// A file on the local disk.
+struct LocalFile(Arc<std::fs::File>);
+
+impl LocalFile {
+    /// Runs `work` on the file as blocking work.
+    fn with<T, F>(&self, work: F) -> FsFuture<'static, T>
+    where
+        F: FnOnce(&std::fs::File) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let file = Arc::clone(&self.0);
+        Box::pin(blocking(move || work(&file)))
+    }
+}
+
+impl FsFile for LocalFile {
+    fn read_at(&self, offset: u64, len: usize) -> FsFuture<'_, Vec<u8>> {
+        self.with(move |file| {
+            let mut buf = vec![0; len];
+            let mut n = 0;
+            while n < len {
+                match read_at(file, &mut buf[n..], offset + n as u64) {
+                    Ok(0) => break,
+                    Ok(read) => n += read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            buf.truncate(n);
+            Ok(buf)
+        })
+    }
+
+    fn write_all_at<'a>(&'a self, offset: u64, data: &'a [u8]) -> FsFuture<'a, ()> {
+        let data = data.to_vec();
+        self.with(move |file| write_all_at(file, &data, offset))
+    }
+
+    fn len(&self) -> FsFuture<'_, u64> {
+        self.with(|file| Ok(file.metadata()?.len()))
+    }
+
+    fn set_len(&self, len: u64) -> FsFuture<'_, ()> {
+        self.with(move |file| file.set_len(len))
+    }
+
+    fn allocate(&self, len: u64) -> FsFuture<'_, ()> {
+        self.with(move |file| crate::prealloc::preallocate(file, len))
+    }
+
+    fn sync_data(&self) -> FsFuture<'_, ()> {
+        self.with(|file| file.sync_data())
+    }
+
+    fn sync_all(&self) -> FsFuture<'_, ()> {
+        self.with(|file| file.sync_all())
+    }
+
+    fn sync_data_blocking(&self) -> io::Result<()> {
+        self.0.sync_data()
+    }
+
+    fn truncate_blocking(&self, len: u64) -> io::Result<()> {
+        if self.0.metadata()?.len() > len {
+            self.0.set_len(len)?;
+            self.0.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &std::fs::File, data: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &std::fs::File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    while !data.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, data, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                data = &data[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn rename_file(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::rename(from, to)
+}
+
+/// `path` as a NUL-terminated wide string for Win32 calls, made absolute and
+/// verbatim (`\\?\`) so it is not limited to `MAX_PATH` characters, which
+/// `std` does for its own calls.
+#[cfg(windows)]
+fn wide_path(path: &Path) -> io::Result<Vec<u16>> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Component, Prefix};
+
+    let joined = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    // Windows does not resolve `.` and `..` in verbatim paths
+    let mut path = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                path.pop();
+            }
+            component => path.push(component),
+        }
+    }
+    let mut components = path.components();
+    let mut verbatim = OsString::new();
+    let mut rest = path.as_path();
+    if let Some(Component::Prefix(prefix)) = components.next() {
+        match prefix.kind() {
+            Prefix::Disk(_) => verbatim.push(r"\\?\"),
+            Prefix::UNC(server, share) => {
+                verbatim.push(r"\\?\UNC\");
+                verbatim.push(server);
+                verbatim.push(r"\");
+                verbatim.push(share);
+                // The rest of the path starts at the root after the share
+                rest = components.as_path();
+            }
+            _ => {}
+        }
+    }
+    verbatim.push(rest);
+    Ok(verbatim.encode_wide().chain(Some(0)).collect())
+}
+
+/// Renames `from` to `to`, replacing it, and returns once the rename is
+/// durable. Windows cannot fsync a directory after a plain rename the way
+/// Unix can, so the write-through flag stands in for it.
+#[cfg(windows)]
+fn rename_file(from: &Path, to: &Path) -> io::Result<()> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        MoveFileExW, MOVEFILE_REPLACE_EXISTING, MOVEFILE_WRITE_THROUGH,
+    };
+
+    let (from, to) = (wide_path(from)?, wide_path(to)?);
+    // SAFETY: both paths are NUL-terminated and outlive the call
+    let ok = unsafe {
+        MoveFileExW(
+            from.as_ptr(),
+            to.as_ptr(),
+            MOVEFILE_REPLACE_EXISTING | MOVEFILE_WRITE_THROUGH,
+        )
+    };
+    if ok == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Flushes the entries of `dir`. A directory handle needs backup semantics
+/// to open and write access for `FlushFileBuffers`.
+#[cfg(windows)]
+fn sync_dir_windows(dir: &Path) -> io::Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Foundation::{ERROR_ACCESS_DENIED, ERROR_INVALID_FUNCTION};
+    use windows_sys::Win32::Storage::FileSystem::FILE_FLAG_BACKUP_SEMANTICS;
+
+    let result = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(dir)
+        .and_then(|dir| dir.sync_all());
+    match result {
+        // FAT volumes and some network shares refuse to flush directories.
+        // NTFS journals the entries, and renames are already write-through.
+        Err(e)
+            if e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32)
+                || e.raw_os_error() == Some(ERROR_INVALID_FUNCTION as i32) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_local_files_read_and_write_at_offsets() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        let fs = LocalFs;
+
+        assert_eq!(
+            fs.open(&path, OpenMode::Write).await.err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+        let file = fs.open(&path, OpenMode::Create).await.unwrap();
+        file.write_all_at(0, b"hello").await.unwrap();
+        file.write_all_at(5, b" world").await.unwrap();
+        file.write_all_at(0, b"J").await.unwrap();
+        file.sync_data().await.unwrap();
+        assert_eq!(file.len().await.unwrap(), 11);
+        assert_eq!(file.read_at(6, 100).await.unwrap(), b"world");
+        assert_eq!(read(&fs, &path).await.unwrap(), b"Jello world");
+
+        file.allocate(64).await.unwrap();
+        assert_eq!(fs.len(&path).await.unwrap(), 64);
+        file.truncate_blocking(5).unwrap();
+        assert_eq!(read(&fs, &path).await.unwrap(), b"Jello");
+
+        let copy = temp_dir.path().join("copy");
+        copy_prefix(&fs, &path, &copy, 3).await.unwrap();
+        assert_eq!(read(&fs, &copy).await.unwrap(), b"Jel");
+        assert!(copy_prefix(&fs, &path, &copy, 3).await.is_err());
+
+        let mut entries = fs.read_dir(temp_dir.path()).await.unwrap();
+        entries.sort();
+        assert_eq!(entries, [copy.clone(), path.clone()]);
+        fs.remove_file(&copy).await.unwrap();
+        assert!(!fs.exists(&copy).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_local_rename_replaces_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = LocalFs;
+        let (temp, path) = (
+            temp_dir.path().join("new.tmp"),
+            temp_dir.path().join("file"),
+        );
+
+        write_synced(&fs, &path, b"old").await.unwrap();
+        write_synced(&fs, &temp, b"new").await.unwrap();
+        fs.rename(&temp, &path).await.unwrap();
+        fs.sync_dir(temp_dir.path()).await.unwrap();
+
+        assert_eq!(read(&fs, &path).await.unwrap(), b"new");
+        assert!(!fs.exists(&temp).await.unwrap());
+        assert_eq!(
+            fs.rename(&temp, &path).await.err().unwrap().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // `..` resolves as it does for std's own calls
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        write_synced(&fs, &temp, b"newer").await.unwrap();
+        let through_sub = temp_dir.path().join("sub").join("..").join("new.tmp");
+        fs.rename(&through_sub, &path).await.unwrap();
+        assert_eq!(read(&fs, &path).await.unwrap(), b"newer");
+    }
+}
@@ -0,0 +1,467 @@
+//! Cloneable writer and reader handles onto an open [`Wal`].
+//!
+//! Subsystems that only write (ingest), only read (replication, indexing) or
+//! only checkpoint can each hold their own handle instead of sharing the
+//! `Wal` behind an `Arc`:
+//!
+//! ```no_run
+//! use nori_wal::{Record, Wal, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let writer = wal.writer();
+//! let reads = wal.read_handle();
+//!
+//! let start = reads.current_position().await;
+//! tokio::spawn(async move {
+//!     writer
+//!         .append(&Record::put(b"key".as_slice(), b"value".as_slice()))
+//!         .await
+//! });
+//! let mut tail = reads.tail(start);
+//! let (record, _) = tail.next_record().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Handles are cheap to clone and share the WAL's state. The [`Wal`] itself
+//! still owns the directory lock and background tasks: once it is closed or
+//! dropped, appends through a writer fail with [`SegmentError::Closed`],
+//! while readers go on serving the records already on disk.
+//!
+//! [`Wal`]: crate::Wal
+
+use crate::checkpoint::{self, Checkpoint};
+use crate::memory::MemoryBudget;
+use crate::reader::{Cursor, WalReader, WalTail};
+use crate::record::Record;
+use crate::runtime::BoxFuture;
+use crate::segment::{FsyncPolicy, Position, ReaderConfig, SegmentError, SegmentManager};
+use bytes::Bytes;
+use futures_sink::Sink;
+use std::pin::Pin;
+use std::sync::{Arc, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+/// Handle for appending, syncing and checkpointing, created with
+/// [`Wal::writer`](crate::Wal::writer).
+///
+/// It is also a [`Sink`] of records, so a stream can be forwarded straight
+/// into the log. Records are appended one at a time, in order. Flushing the
+/// sink makes everything sent so far as durable as the fsync policy allows:
+/// with [`FsyncPolicy::Batch`] it syncs without waiting for the window, with
+/// [`FsyncPolicy::Os`] it only hands buffered data to the OS, and with
+/// [`FsyncPolicy::Always`] appends are already durable. The inherent
+/// [`WalWriter::flush`] takes precedence over `SinkExt::flush`, so call the
+/// latter as `SinkExt::flush(&mut writer)`.
+pub struct WalWriter {
+    manager: Arc<SegmentManager>,
+    checkpoint: Arc<Mutex<Option<Checkpoint>>>,
+    purge_on_checkpoint: bool,
+    /// State of this handle's sink; a `Mutex` only to keep the handle
+    /// `Sync`, since it is reached through `&mut self`.
+    sink: std::sync::Mutex<SinkState>,
+}
+
+/// Progress of a [`WalWriter`] used as a [`Sink`].
+#[derive(Default)]
+struct SinkState {
+    /// Append or flush started by the sink and not yet finished.
+    in_flight: Option<BoxFuture<Result<(), SegmentError>>>,
+    /// Records were appended since the last flush.
+    dirty: bool,
+}
+
+impl Clone for WalWriter {
+    /// Clones share the WAL, but each has its own sink state.
+    fn clone(&self) -> Self {
+        Self::new(
+            self.manager.clone(),
+            self.checkpoint.clone(),
+            self.purge_on_checkpoint,
+        )
+    }
+}
+
+impl WalWriter {
+    pub(crate) fn new(
+        manager: Arc<SegmentManager>,
+        checkpoint: Arc<Mutex<Option<Checkpoint>>>,
+        purge_on_checkpoint: bool,
+    ) -> Self {
+        Self {
+            manager,
+            checkpoint,
+            purge_on_checkpoint,
+            sink: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Appends a record. See [`Wal::append`](crate::Wal::append).
+    pub async fn append(&self, record: &Record) -> Result<Position, SegmentError> {
+        self.manager.append(record).await
+    }
+
+    /// Appends a record only if the log still ends at `expected_tail`. See
+    /// [`Wal::append_if`](crate::Wal::append_if).
+    pub async fn append_if(
+        &self,
+        expected_tail: Position,
+        record: &Record,
+    ) -> Result<Position, SegmentError> {
+        self.manager.append_if(expected_tail, record).await
+    }
+
+    /// Appends records as one write. See
+    /// [`Wal::append_batch`](crate::Wal::append_batch).
+    pub async fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        self.manager.append_batch(records).await
+    }
+
+    /// Flushes buffered data to the OS (but doesn't fsync).
+    pub async fn flush(&self) -> Result<(), SegmentError> {
+        self.manager.flush().await
+    }
+
+    /// Syncs all data to disk (fsync).
+    pub async fn sync(&self) -> Result<(), SegmentError> {
+        self.manager.sync().await
+    }
+
+    /// Returns the current write position in the WAL.
+    pub async fn current_position(&self) -> Position {
+        self.manager.current_position().await
+    }
+
+    /// Returns the position up to which appended records are known to be durable.
+    pub async fn durable_position(&self) -> Position {
+        self.manager.durable_position().await
+    }
+
+    /// Returns the LSN that the next appended record will be assigned.
+    pub fn next_lsn(&self) -> u64 {
+        self.manager.next_lsn()
+    }
+
+    /// Durably records a checkpoint at `position`. See
+    /// [`Wal::checkpoint`](crate::Wal::checkpoint).
+    pub async fn checkpoint(&self, position: Position) -> Result<Checkpoint, SegmentError> {
+        let mut last = self.checkpoint.lock().await;
+        if last.is_some_and(|c| position < c.position) {
+            return Err(SegmentError::InvalidConfig(
+                "checkpoint cannot move backwards".to_string(),
+            ));
+        }
+        if position > self.manager.current_position().await {
+            return Err(SegmentError::InvalidConfig(
+                "checkpoint is past the end of the log".to_string(),
+            ));
+        }
+
+        let checkpoint = Checkpoint::at(position, self.manager.clock().system_time());
+        checkpoint::write_checkpoint(
+            self.manager.fs().as_ref(),
+            &self.manager.dir().await,
+            &checkpoint,
+        )
+        .await?;
+        *last = Some(checkpoint);
+
+        if self.purge_on_checkpoint {
+            self.manager.purge_before(position, "checkpoint").await?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Returns the most recent checkpoint, if one has been recorded.
+    pub async fn last_checkpoint(&self) -> Option<Checkpoint> {
+        *self.checkpoint.lock().await
+    }
+
+    fn sink_state(&mut self) -> &mut SinkState {
+        self.sink.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Drives the sink's append or flush in flight, if any, to completion.
+    fn poll_in_flight(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SegmentError>> {
+        let state = self.sink_state();
+        let Some(in_flight) = state.in_flight.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let result = ready!(in_flight.as_mut().poll(cx));
+        state.in_flight = None;
+        if result.is_err() {
+            // Whatever went wrong, the next flush should not be skipped
+            state.dirty = true;
+        }
+        Poll::Ready(result)
+    }
+}
+
+impl Sink<Record> for WalWriter {
+    type Error = SegmentError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_in_flight(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, record: Record) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        let manager = this.manager.clone();
+        let state = this.sink_state();
+        state.dirty = true;
+        state.in_flight = Some(Box::pin(async move {
+            manager.append(&record).await.map(|_| ())
+        }));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_in_flight(cx))?;
+            let manager = this.manager.clone();
+            let state = this.sink_state();
+            if !state.dirty {
+                return Poll::Ready(Ok(()));
+            }
+            state.dirty = false;
+            state.in_flight = Some(Box::pin(async move {
+                match manager.fsync_policy().await {
+                    FsyncPolicy::Always => Ok(()),
+                    FsyncPolicy::Batch(_) => manager.sync().await,
+                    FsyncPolicy::Os => manager.flush().await,
+                }
+            }));
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Handle for reading and following the log, created with
+/// [`Wal::read_handle`](crate::Wal::read_handle).
+#[derive(Clone)]
+pub struct WalReadHandle {
+    manager: Arc<SegmentManager>,
+}
+
+impl WalReadHandle {
+    pub(crate) fn new(manager: Arc<SegmentManager>) -> Self {
+        Self { manager }
+    }
+
+    /// Returns the current write position in the WAL.
+    pub async fn current_position(&self) -> Position {
+        self.manager.current_position().await
+    }
+
+    /// Returns the position up to which appended records are known to be durable.
+    pub async fn durable_position(&self) -> Position {
+        self.manager.durable_position().await
+    }
+
+    /// Returns the LSN that the next appended record will be assigned.
+    pub fn next_lsn(&self) -> u64 {
+        self.manager.next_lsn()
+    }
+
+    /// Returns the last durable record and its position.
+    pub async fn last_record(&self) -> Result<Option<(Record, Position)>, SegmentError> {
+        self.manager.last_record().await
+    }
+
+    /// Returns the WAL's memory budget, if one is set.
+    pub fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.manager.memory_budget()
+    }
+
+    /// Returns a reader that follows the log across segments from `position`
+    /// up to its durable end.
+    pub fn reader(&self, position: Position) -> WalReader {
+        self.reader_with(position, ReaderConfig::default())
+    }
+
+    /// Like [`WalReadHandle::reader`], with the given reader configuration.
+    pub fn reader_with(&self, position: Position, config: ReaderConfig) -> WalReader {
+        WalReader::new(self.manager.clone(), position, config)
+    }
+
+    /// Returns a reader starting at the first record whose LSN is at least
+    /// `lsn`. See [`Wal::read_from_lsn`](crate::Wal::read_from_lsn).
+    pub async fn read_from_lsn(&self, lsn: u64) -> Result<WalReader, SegmentError> {
+        let position = self.manager.seek_lsn(lsn).await?;
+        Ok(self.reader(position))
+    }
+
+    /// Returns a reader over the records appended at or after `since` and
+    /// before `until`. See [`Wal::read_range`](crate::Wal::read_range).
+    pub async fn read_range(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> Result<WalReader, SegmentError> {
+        let (start, end) = self.manager.time_bounds(since, until).await?;
+        let reader = self.reader(start).with_time_range(since, until);
+        Ok(match end {
+            Some(end) if end >= start => reader.until(end),
+            _ => reader,
+        })
+    }
+
+    /// Returns a reader over the records in `[from, to)` whose key starts
+    /// with `prefix`.
+    pub fn scan_prefix(&self, prefix: impl Into<Bytes>, from: Position, to: Position) -> WalReader {
+        self.reader(from).with_key_prefix(prefix).until(to)
+    }
+
+    /// Returns a tail that reads the log from `position` and then follows
+    /// new records as they become durable.
+    pub fn tail(&self, position: Position) -> WalTail {
+        WalTail::new(self.manager.clone(), position)
+    }
+
+    /// Returns a tail starting at the first record whose LSN is at least
+    /// `lsn`. See [`Wal::tail_from_lsn`](crate::Wal::tail_from_lsn).
+    pub async fn tail_from_lsn(&self, lsn: u64) -> Result<WalTail, SegmentError> {
+        let position = self.manager.seek_lsn(lsn).await?;
+        Ok(self.tail(position))
+    }
+
+    /// Returns a reader that continues from a saved cursor, failing with
+    /// [`SegmentError::CursorGone`] if its position is no longer in the log.
+    pub async fn resume_reader(&self, cursor: &Cursor) -> Result<WalReader, SegmentError> {
+        self.manager.check_position(cursor.position()).await?;
+        Ok(self.reader(cursor.position()))
+    }
+
+    /// Returns a tail that continues from a saved cursor, failing with
+    /// [`SegmentError::CursorGone`] if its position is no longer in the log.
+    pub async fn resume_tail(&self, cursor: &Cursor) -> Result<WalTail, SegmentError> {
+        self.manager.check_position(cursor.position()).await?;
+        Ok(self.tail(cursor.position()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::record::Record;
+    use crate::segment::{FsyncPolicy, Position, SegmentError};
+    use crate::wal::{Wal, WalConfig};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_handles_share_the_wal() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let start = wal.current_position().await;
+        let reads = wal.read_handle();
+        let mut tail = reads.clone().tail(start);
+
+        let writer = wal.writer();
+        let ingest: Vec<_> = (0..4)
+            .map(|task| {
+                let writer = writer.clone();
+                tokio::spawn(async move {
+                    for i in 0..5 {
+                        let key = format!("{}/{}", task, i);
+                        writer
+                            .append(&Record::put(key, b"v".as_slice()))
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in ingest {
+            task.await.unwrap();
+        }
+        for _ in 0..20 {
+            tail.next_record().await.unwrap();
+        }
+        assert_eq!(writer.next_lsn(), 21);
+
+        // A checkpoint through one handle is what the WAL sees
+        let middle = reads.read_from_lsn(10).await.unwrap().position();
+        writer.checkpoint(middle).await.unwrap();
+        assert_eq!(wal.last_checkpoint().await.unwrap().position, middle);
+
+        wal.close().await.unwrap();
+        assert!(matches!(
+            writer
+                .append(&Record::put(b"late".as_slice(), b"v".as_slice()))
+                .await,
+            Err(SegmentError::Closed)
+        ));
+        let mut reader = reads.reader(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        let mut count = 0;
+        while reader.next_record().await.unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 20);
+
+        let (wal, _) = Wal::open(config).await.unwrap();
+        assert_eq!(wal.last_checkpoint().await.unwrap().position, middle);
+    }
+
+    #[tokio::test]
+    async fn test_writer_as_sink() {
+        use futures::{SinkExt, StreamExt};
+        use std::time::Duration;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Batch(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let start = wal.current_position().await;
+
+        let records = (0..50).map(|i| Ok(Record::put(format!("key{}", i), b"v".as_slice())));
+        futures::stream::iter(records)
+            .forward(wal.writer())
+            .await
+            .unwrap();
+        // Forwarding ends with a flush, which syncs ahead of the batch window
+        assert_eq!(wal.durable_position().await, wal.current_position().await);
+
+        // Feeding a record finishes appending the one before it
+        let mut sink = wal.writer();
+        for key in ["fed0", "fed1"] {
+            sink.feed(Record::put(key, b"v".as_slice())).await.unwrap();
+        }
+        assert!(wal.durable_position().await < wal.current_position().await);
+        // The inherent `flush` only hands data to the OS
+        SinkExt::flush(&mut sink).await.unwrap();
+        assert_eq!(wal.durable_position().await, wal.current_position().await);
+
+        let keys: Vec<_> = wal
+            .reader(start)
+            .map(|entry| entry.unwrap().0.key)
+            .collect()
+            .await;
+        assert_eq!(keys.len(), 52);
+        assert_eq!(keys[49].as_ref(), b"key49");
+        assert_eq!(keys[51].as_ref(), b"fed1");
+
+        drop(wal);
+        assert!(matches!(
+            sink.send(Record::put(b"late".as_slice(), b"v".as_slice()))
+                .await,
+            Err(SegmentError::Closed)
+        ));
+    }
+}
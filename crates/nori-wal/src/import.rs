@@ -0,0 +1,259 @@
+//! Bulk loading for initial backfills.
+//!
+//! Appending a large dataset record by record pays for a lock round trip, a
+//! write call and possibly an fsync per record. [`Wal::import`] instead
+//! gathers records into chunks of about [`ImportConfig::chunk_bytes`], writes
+//! each run of a chunk that fits in the active segment with a single call,
+//! and fsyncs only every [`ImportConfig::sync_every`] bytes and at the end:
+//!
+//! ```no_run
+//! use nori_wal::{Record, Wal, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let records = (0..1_000_000).map(|i| Record::put(format!("key{}", i), b"value".as_slice()));
+//! let summary = wal.import(futures::stream::iter(records)).await?;
+//! println!("imported {} records up to {}", summary.records, summary.end);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The fsync policy is not applied while importing. Other writers may append
+//! concurrently; their records interleave with the imported chunks.
+//!
+//! [`Wal::import`]: crate::Wal::import
+
+use crate::record::Record;
+use crate::segment::{Position, SegmentError, SegmentManager};
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+
+/// Configuration for [`Wal::import_with`](crate::Wal::import_with).
+#[derive(Debug, Clone)]
+pub struct ImportConfig {
+    /// Approximate size of the chunks records are gathered into before being
+    /// written (default: 4 MiB).
+    pub chunk_bytes: usize,
+    /// Fsync after roughly this many bytes have been imported since the last
+    /// one, bounding how much a crash mid-import can lose (default: None,
+    /// only fsync at the end).
+    pub sync_every: Option<u64>,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        Self {
+            chunk_bytes: 4 * 1024 * 1024,
+            sync_every: None,
+        }
+    }
+}
+
+impl ImportConfig {
+    fn validate(&self) -> Result<(), SegmentError> {
+        if self.chunk_bytes == 0 {
+            return Err(SegmentError::InvalidConfig(
+                "import chunk size must be at least 1 byte".to_string(),
+            ));
+        }
+        if self.sync_every == Some(0) {
+            return Err(SegmentError::InvalidConfig(
+                "import sync interval must be at least 1 byte".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of an import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Records imported.
+    pub records: u64,
+    /// Encoded bytes written.
+    pub bytes: u64,
+    /// End of the log once the import finished, durable by then.
+    pub end: Position,
+}
+
+/// A ready-at-once stream over an iterator, for importing records already
+/// in memory.
+pub(crate) struct IterStream<I>(pub(crate) I);
+
+impl<I: Iterator + Unpin> Stream for IterStream<I> {
+    type Item = I::Item;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<I::Item>> {
+        Poll::Ready(self.get_mut().0.next())
+    }
+}
+
+/// Writes every record of `records` to the log. On error, the records
+/// written before it stay in the log but may not be durable.
+pub(crate) async fn import<S>(
+    manager: &SegmentManager,
+    records: S,
+    config: ImportConfig,
+) -> Result<ImportSummary, SegmentError>
+where
+    S: Stream<Item = Record>,
+{
+    config.validate()?;
+
+    let mut records = pin!(records);
+    let mut chunk = Vec::new();
+    let mut chunk_size = 0;
+    let mut unsynced = 0;
+    let mut summary = ImportSummary {
+        records: 0,
+        bytes: 0,
+        end: manager.current_position().await,
+    };
+
+    loop {
+        let next = poll_fn(|cx| records.as_mut().poll_next(cx)).await;
+        let done = next.is_none();
+        if let Some(record) = next {
+            chunk_size += record.key.len() + record.value.len();
+            chunk.push(record);
+            if chunk_size < config.chunk_bytes {
+                continue;
+            }
+        }
+
+        if !chunk.is_empty() {
+            let bytes = manager.import_chunk(&chunk).await?;
+            summary.records += chunk.len() as u64;
+            summary.bytes += bytes;
+            unsynced += bytes;
+            chunk.clear();
+            chunk_size = 0;
+
+            if config.sync_every.is_some_and(|every| unsynced >= every) {
+                manager.sync().await?;
+                unsynced = 0;
+            }
+        }
+        if done {
+            break;
+        }
+    }
+
+    manager.sync().await?;
+    summary.end = manager.current_position().await;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NamespaceQuota, QuotaEnforcement, Wal, WalConfig};
+    use futures::StreamExt;
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_import_rotates_and_syncs() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let start = wal.current_position().await;
+
+        let value = vec![7u8; 1024];
+        let records = (0..3000)
+            .map(|i| Record::put(format!("key{:04}", i), bytes::Bytes::from(value.clone())));
+        let import = ImportConfig {
+            chunk_bytes: 64 * 1024,
+            sync_every: Some(512 * 1024),
+        };
+        let summary = wal
+            .import_with(futures::stream::iter(records), import)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.records, 3000);
+        assert_eq!(summary.end, wal.durable_position().await);
+        let metrics = wal.metrics().await;
+        assert_eq!(summary.bytes, metrics.bytes_appended);
+        assert!(metrics.rotations >= 2);
+        assert!(metrics.fsyncs >= 5);
+
+        let keys: Vec<_> = wal
+            .reader(start)
+            .map(|entry| entry.unwrap().0.key)
+            .collect()
+            .await;
+        assert_eq!(keys.len(), 3000);
+        assert_eq!(keys[2999].as_ref(), b"key2999");
+        drop(wal);
+
+        let (wal, info) = Wal::open(config).await.unwrap();
+        assert_eq!(info.valid_records, 3000);
+        assert_eq!(wal.next_lsn(), 3001);
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_zero_sizes() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::open(WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let config = ImportConfig {
+            sync_every: Some(0),
+            ..Default::default()
+        };
+        let result = wal
+            .import_with(futures::stream::iter(Vec::<Record>::new()), config)
+            .await;
+        assert!(matches!(result, Err(SegmentError::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_import_enforces_quotas() {
+        let temp_dir = TempDir::new().unwrap();
+        let quota = NamespaceQuota {
+            max_records: Some(10),
+            max_bytes: None,
+            enforcement: QuotaEnforcement::Reject,
+        };
+        let (wal, _) = Wal::open(WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            namespace_quotas: HashMap::from([(1, quota)]),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let tenant = |i| Record::put(format!("key{}", i), b"value".as_slice()).with_namespace(1);
+
+        let summary = wal
+            .import(futures::stream::iter((0..10).map(tenant)))
+            .await
+            .unwrap();
+        assert_eq!(summary.records, 10);
+
+        let err = wal
+            .import(futures::stream::iter((10..20).map(tenant)))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            SegmentError::QuotaExceeded {
+                namespace: 1,
+                records: 10,
+                ..
+            }
+        ));
+        assert_eq!(wal.namespace_metrics(1).records_in_log, 10);
+    }
+}
@@ -0,0 +1,658 @@
+//! Exporting the log as Kafka log segments.
+//!
+//! [`KafkaSegmentWriter`] writes records into the files a Kafka broker keeps
+//! for one partition: `<base offset>.log` holding v2 record batches, with a
+//! sparse `.index` (offset to file position) and `.timeindex` (timestamp to
+//! offset) beside it, rolled every [`KafkaExportConfig::segment_bytes`].
+//! The files can be placed in the partition directory of a stopped broker,
+//! which rebuilds anything else it needs on startup, or read by any tool
+//! that reads segments.
+//!
+//! Records map onto Kafka records as follows:
+//! - Offsets are assigned in log order from
+//!   [`KafkaExportConfig::base_offset`]
+//! - The key and value are copied as they are; a tombstone gets a null
+//!   value, which is how a compacted topic marks a delete
+//! - The timestamp becomes the record's `CreateTime`, in milliseconds. A
+//!   record without one takes the timestamp of the record before it, or the
+//!   time the export started
+//! - The LSN, namespace and TTL, when set, go in the `nori.lsn`,
+//!   `nori.namespace` and `nori.ttl_ms` headers, as decimal strings
+//!
+//! Batches are uncompressed and carry no producer ID, as if written by a
+//! non-idempotent producer.
+//!
+//! ```no_run
+//! use nori_wal::{KafkaExportConfig, Position, Wal, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let start = Position { segment_id: 0, offset: 0 };
+//! let summary = nori_wal::kafka::export(
+//!     wal.reader(start),
+//!     "/var/lib/kafka/events-0",
+//!     KafkaExportConfig::default(),
+//! )
+//! .await?;
+//! println!("exported {} records up to offset {}", summary.records, summary.next_offset);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::record::Record;
+use crate::segment::{Position, SegmentError};
+use bytes::BufMut;
+use futures_core::Stream;
+use std::fs::File;
+use std::future::poll_fn;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header holding the record's LSN.
+pub const LSN_HEADER: &str = "nori.lsn";
+/// Header holding the record's namespace.
+pub const NAMESPACE_HEADER: &str = "nori.namespace";
+/// Header holding the record's TTL in milliseconds.
+pub const TTL_HEADER: &str = "nori.ttl_ms";
+
+/// Record batch format written (v2, Kafka 0.11 and later).
+const MAGIC: u8 = 2;
+
+/// Settings for a [`KafkaSegmentWriter`].
+#[derive(Debug, Clone)]
+pub struct KafkaExportConfig {
+    /// Offset of the first record (default: 0).
+    pub base_offset: u64,
+    /// A new segment is started once the current one would grow past this
+    /// (default: 1 GiB, Kafka's `segment.bytes`).
+    pub segment_bytes: u64,
+    /// Bytes of batches between index entries (default: 4096, Kafka's
+    /// `index.interval.bytes`).
+    pub index_interval_bytes: u64,
+    /// Most records in one batch (default: 500).
+    pub batch_records: usize,
+    /// A batch is closed once its records reach this size (default: 1 MiB,
+    /// below the broker's default `message.max.bytes`).
+    pub batch_bytes: usize,
+}
+
+impl Default for KafkaExportConfig {
+    fn default() -> Self {
+        Self {
+            base_offset: 0,
+            segment_bytes: 1024 * 1024 * 1024,
+            index_interval_bytes: 4096,
+            batch_records: 500,
+            batch_bytes: 1024 * 1024,
+        }
+    }
+}
+
+impl KafkaExportConfig {
+    fn validate(&self) -> Result<(), SegmentError> {
+        if self.batch_records == 0 || self.batch_bytes == 0 {
+            return Err(SegmentError::InvalidConfig(
+                "Kafka batches must allow at least 1 record and 1 byte".to_string(),
+            ));
+        }
+        if self.segment_bytes > i32::MAX as u64 {
+            return Err(SegmentError::InvalidConfig(
+                "Kafka segments can hold at most 2 GiB".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KafkaExportSummary {
+    /// Records written.
+    pub records: u64,
+    /// Record batches written.
+    pub batches: u64,
+    /// Segments written.
+    pub segments: u64,
+    /// Offset the next record would get.
+    pub next_offset: u64,
+    /// Position of the last record exported; a later export can resume
+    /// after it.
+    pub last: Option<Position>,
+}
+
+/// One open segment: the log and its two indexes.
+struct Segment {
+    base_offset: u64,
+    log: BufWriter<File>,
+    index: BufWriter<File>,
+    time_index: BufWriter<File>,
+    size: u64,
+    bytes_since_index: u64,
+    /// Largest timestamp so far, with the last offset of its batch.
+    max_timestamp: Option<(i64, u64)>,
+    last_indexed_timestamp: Option<i64>,
+}
+
+/// Writes records as Kafka log segments in a partition directory.
+///
+/// Records are gathered into batches; call [`KafkaSegmentWriter::finish`] to
+/// write the last one and flush the files.
+pub struct KafkaSegmentWriter {
+    dir: PathBuf,
+    config: KafkaExportConfig,
+    segment: Option<Segment>,
+    /// Records of the open batch, encoded, with their timestamps.
+    batch: Vec<(Vec<u8>, i64)>,
+    batch_len: usize,
+    next_offset: u64,
+    last_timestamp: i64,
+    summary: KafkaExportSummary,
+}
+
+impl KafkaSegmentWriter {
+    /// Creates `dir` if needed and prepares to write segments into it.
+    /// Existing segments with the same base offsets are overwritten.
+    pub fn create(
+        dir: impl Into<PathBuf>,
+        config: KafkaExportConfig,
+    ) -> Result<Self, SegmentError> {
+        config.validate()?;
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            next_offset: config.base_offset,
+            summary: KafkaExportSummary {
+                next_offset: config.base_offset,
+                ..Default::default()
+            },
+            config,
+            segment: None,
+            batch: Vec::new(),
+            batch_len: 0,
+            last_timestamp: timestamp_ms(SystemTime::now()),
+        })
+    }
+
+    /// Adds `record`, returning the offset it gets.
+    pub fn append(&mut self, record: &Record) -> io::Result<u64> {
+        if self.batch.len() >= self.config.batch_records
+            || self.batch_len >= self.config.batch_bytes
+        {
+            self.write_batch()?;
+        }
+
+        if let Some(timestamp) = record.timestamp {
+            self.last_timestamp = timestamp_ms(timestamp);
+        }
+        let offset = self.next_offset;
+        let base = self.next_offset - self.batch.len() as u64;
+        let base_timestamp = self.batch.first().map_or(self.last_timestamp, |b| b.1);
+        let encoded = encode_record(
+            record,
+            (offset - base) as i64,
+            self.last_timestamp - base_timestamp,
+        );
+        self.batch_len += encoded.len();
+        self.batch.push((encoded, self.last_timestamp));
+        self.next_offset += 1;
+        self.summary.records += 1;
+        self.summary.next_offset = self.next_offset;
+        Ok(offset)
+    }
+
+    /// Writes the last batch and flushes every file.
+    pub fn finish(mut self) -> io::Result<KafkaExportSummary> {
+        self.write_batch()?;
+        if let Some(segment) = self.segment.take() {
+            close(segment)?;
+        }
+        Ok(self.summary)
+    }
+
+    fn write_batch(&mut self) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let count = self.batch.len();
+        let base_offset = self.next_offset - count as u64;
+        let last_offset = self.next_offset - 1;
+        let base_timestamp = self.batch[0].1;
+        let max_timestamp = self
+            .batch
+            .iter()
+            .map(|b| b.1)
+            .max()
+            .unwrap_or(base_timestamp);
+
+        // Everything the CRC covers: attributes through the records
+        let mut body = Vec::with_capacity(40 + self.batch_len);
+        body.put_i16(0); // No compression, CreateTime, not transactional
+        body.put_i32((count - 1) as i32);
+        body.put_i64(base_timestamp);
+        body.put_i64(max_timestamp);
+        body.put_i64(-1); // Producer ID
+        body.put_i16(-1); // Producer epoch
+        body.put_i32(-1); // Base sequence
+        body.put_i32(count as i32);
+        for (record, _) in self.batch.drain(..) {
+            body.put_slice(&record);
+        }
+        self.batch_len = 0;
+
+        let mut batch = Vec::with_capacity(21 + body.len());
+        batch.put_i64(base_offset as i64);
+        batch.put_i32((4 + 1 + 4 + body.len()) as i32);
+        batch.put_i32(0); // Partition leader epoch
+        batch.put_u8(MAGIC);
+        batch.put_u32(crc32c::crc32c(&body));
+        batch.put_slice(&body);
+
+        let full = self
+            .segment
+            .as_ref()
+            .is_some_and(|s| s.size > 0 && s.size + batch.len() as u64 > self.config.segment_bytes);
+        if full {
+            close(self.segment.take().expect("segment is open"))?;
+        }
+        if self.segment.is_none() {
+            self.segment = Some(open(&self.dir, base_offset)?);
+            self.summary.segments += 1;
+        }
+        let segment = self.segment.as_mut().expect("segment is open");
+
+        // Indexed like a broker does: the batch's last offset and where the
+        // batch starts, once enough bytes have gone by
+        if segment
+            .max_timestamp
+            .map_or(true, |(timestamp, _)| max_timestamp > timestamp)
+        {
+            segment.max_timestamp = Some((max_timestamp, last_offset));
+        }
+        if segment.bytes_since_index > self.config.index_interval_bytes {
+            let relative = (last_offset - segment.base_offset) as u32;
+            segment.index.write_all(&relative.to_be_bytes())?;
+            segment
+                .index
+                .write_all(&(segment.size as u32).to_be_bytes())?;
+            let (timestamp, offset) = segment.max_timestamp.expect("set above");
+            if segment
+                .last_indexed_timestamp
+                .map_or(true, |t| timestamp > t)
+            {
+                write_time_entry(segment, timestamp, offset)?;
+            }
+            segment.bytes_since_index = 0;
+        }
+        segment.log.write_all(&batch)?;
+        segment.size += batch.len() as u64;
+        segment.bytes_since_index += batch.len() as u64;
+        self.summary.batches += 1;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for KafkaSegmentWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSegmentWriter")
+            .field("dir", &self.dir)
+            .field("next_offset", &self.next_offset)
+            .finish()
+    }
+}
+
+/// Exports every record of `records`, such as a [`WalReader`], into `dir`.
+///
+/// Stops at the first read error, after writing out what was read before
+/// it.
+///
+/// [`WalReader`]: crate::WalReader
+pub async fn export<S>(
+    records: S,
+    dir: impl AsRef<Path>,
+    config: KafkaExportConfig,
+) -> Result<KafkaExportSummary, SegmentError>
+where
+    S: Stream<Item = Result<(Record, Position), SegmentError>>,
+{
+    let mut writer = KafkaSegmentWriter::create(dir.as_ref(), config)?;
+    let mut records = pin!(records);
+    let mut last = None;
+    let mut failed = None;
+    while let Some(next) = poll_fn(|cx| records.as_mut().poll_next(cx)).await {
+        match next {
+            Ok((record, position)) => {
+                writer.append(&record)?;
+                last = Some(position);
+            }
+            Err(e) => {
+                failed = Some(e);
+                break;
+            }
+        }
+    }
+    let summary = writer.finish()?;
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(KafkaExportSummary { last, ..summary }),
+    }
+}
+
+/// Returns the file name of the segment starting at `base_offset`, with
+/// `extension` (`log`, `index` or `timeindex`).
+pub fn segment_file_name(base_offset: u64, extension: &str) -> String {
+    format!("{:020}.{}", base_offset, extension)
+}
+
+fn open(dir: &Path, base_offset: u64) -> io::Result<Segment> {
+    let create = |extension| -> io::Result<_> {
+        let path = dir.join(segment_file_name(base_offset, extension));
+        Ok(BufWriter::new(File::create(path)?))
+    };
+    Ok(Segment {
+        base_offset,
+        log: create("log")?,
+        index: create("index")?,
+        time_index: create("timeindex")?,
+        size: 0,
+        bytes_since_index: 0,
+        max_timestamp: None,
+        last_indexed_timestamp: None,
+    })
+}
+
+/// Flushes a segment, with a last time index entry for its largest
+/// timestamp as a broker writes when it rolls one.
+fn close(mut segment: Segment) -> io::Result<()> {
+    if let Some((timestamp, offset)) = segment.max_timestamp {
+        if segment
+            .last_indexed_timestamp
+            .map_or(true, |t| timestamp > t)
+        {
+            write_time_entry(&mut segment, timestamp, offset)?;
+        }
+    }
+    for file in [
+        &mut segment.log,
+        &mut segment.index,
+        &mut segment.time_index,
+    ] {
+        file.flush()?;
+        file.get_ref().sync_all()?;
+    }
+    Ok(())
+}
+
+fn write_time_entry(segment: &mut Segment, timestamp: i64, offset: u64) -> io::Result<()> {
+    let relative = (offset - segment.base_offset) as u32;
+    segment.time_index.write_all(&timestamp.to_be_bytes())?;
+    segment.time_index.write_all(&relative.to_be_bytes())?;
+    segment.last_indexed_timestamp = Some(timestamp);
+    Ok(())
+}
+
+/// Encodes one record of a v2 batch, length prefix included.
+fn encode_record(record: &Record, offset_delta: i64, timestamp_delta: i64) -> Vec<u8> {
+    let mut body = Vec::with_capacity(record.key.len() + record.value.len() + 32);
+    body.put_u8(0); // Attributes, unused
+    put_varint(&mut body, timestamp_delta);
+    put_varint(&mut body, offset_delta);
+    put_bytes(&mut body, Some(&record.key));
+    put_bytes(&mut body, (!record.tombstone).then_some(&record.value[..]));
+
+    let headers = headers(record);
+    put_varint(&mut body, headers.len() as i64);
+    for (name, value) in headers {
+        put_bytes(&mut body, Some(name.as_bytes()));
+        put_bytes(&mut body, Some(value.as_bytes()));
+    }
+
+    let mut encoded = Vec::with_capacity(body.len() + 5);
+    put_varint(&mut encoded, body.len() as i64);
+    encoded.extend_from_slice(&body);
+    encoded
+}
+
+/// Returns the `nori.*` headers for the record's fields that are set.
+pub(crate) fn headers(record: &Record) -> Vec<(&'static str, String)> {
+    let headers = [
+        (LSN_HEADER, record.lsn),
+        (NAMESPACE_HEADER, record.namespace.map(u64::from)),
+        (TTL_HEADER, record.ttl.map(|ttl| ttl.as_millis() as u64)),
+    ];
+    headers
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?.to_string())))
+        .collect()
+}
+
+/// Writes a length-prefixed byte string; `None` is null (length -1).
+fn put_bytes(buf: &mut Vec<u8>, data: Option<&[u8]>) {
+    match data {
+        Some(data) => {
+            put_varint(buf, data.len() as i64);
+            buf.put_slice(data);
+        }
+        None => put_varint(buf, -1),
+    }
+}
+
+/// Writes a zigzag-encoded varint, as Kafka encodes record fields.
+fn put_varint(buf: &mut Vec<u8>, value: i64) {
+    let mut v = ((value << 1) ^ (value >> 63)) as u64;
+    while v >= 0x80 {
+        buf.put_u8(v as u8 | 0x80);
+        v >>= 7;
+    }
+    buf.put_u8(v as u8);
+}
+
+fn timestamp_ms(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Buf;
+    use std::time::Duration;
+
+    /// A record read back from a segment.
+    #[derive(Debug, PartialEq)]
+    struct Decoded {
+        offset: u64,
+        timestamp: i64,
+        key: Vec<u8>,
+        value: Option<Vec<u8>>,
+        headers: Vec<(String, String)>,
+    }
+
+    fn get_varint(data: &mut &[u8]) -> i64 {
+        let (mut v, mut shift) = (0u64, 0);
+        loop {
+            let byte = data.get_u8();
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return (v >> 1) as i64 ^ -((v & 1) as i64);
+            }
+            shift += 7;
+        }
+    }
+
+    fn get_bytes(data: &mut &[u8]) -> Option<Vec<u8>> {
+        let len = get_varint(data);
+        (len >= 0).then(|| {
+            let bytes = data[..len as usize].to_vec();
+            data.advance(len as usize);
+            bytes
+        })
+    }
+
+    /// Decodes a `.log` file, checking each batch's framing and CRC, and
+    /// returns its records with the file position of each batch.
+    fn decode_log(mut data: &[u8]) -> (Vec<Decoded>, Vec<(u64, u64)>) {
+        let (mut records, mut batches) = (Vec::new(), Vec::new());
+        let total = data.len();
+        while data.has_remaining() {
+            let position = (total - data.len()) as u64;
+            let base_offset = data.get_i64() as u64;
+            let len = data.get_i32() as usize;
+            let mut batch = &data[..len];
+            data.advance(len);
+            assert_eq!(batch.get_i32(), 0);
+            assert_eq!(batch.get_u8(), MAGIC);
+            let crc = batch.get_u32();
+            assert_eq!(crc32c::crc32c(batch), crc);
+            assert_eq!(batch.get_i16(), 0);
+            let last_delta = batch.get_i32() as u64;
+            let base_timestamp = batch.get_i64();
+            let _max_timestamp = batch.get_i64();
+            assert_eq!((batch.get_i64(), batch.get_i16()), (-1, -1));
+            batch.get_i32();
+            let count = batch.get_i32();
+            assert_eq!(count as u64, last_delta + 1);
+            batches.push((base_offset + last_delta, position));
+            for _ in 0..count {
+                let len = get_varint(&mut batch) as usize;
+                let mut record = &batch[..len];
+                batch.advance(len);
+                assert_eq!(record.get_u8(), 0);
+                let timestamp = base_timestamp + get_varint(&mut record);
+                let offset = base_offset + get_varint(&mut record) as u64;
+                let key = get_bytes(&mut record).unwrap();
+                let value = get_bytes(&mut record);
+                let headers = (0..get_varint(&mut record))
+                    .map(|_| {
+                        let name = get_bytes(&mut record).unwrap();
+                        let value = get_bytes(&mut record).unwrap();
+                        (
+                            String::from_utf8(name).unwrap(),
+                            String::from_utf8(value).unwrap(),
+                        )
+                    })
+                    .collect();
+                assert!(record.is_empty());
+                records.push(Decoded {
+                    offset,
+                    timestamp,
+                    key,
+                    value,
+                    headers,
+                });
+            }
+            assert!(batch.is_empty());
+        }
+        (records, batches)
+    }
+
+    #[test]
+    fn test_varints_match_kafka() {
+        let encode = |value| {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+            buf
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(-1), [0x01]);
+        assert_eq!(encode(1), [0x02]);
+        assert_eq!(encode(63), [0x7e]);
+        assert_eq!(encode(64), [0x80, 0x01]);
+        assert_eq!(encode(-65), [0x81, 0x01]);
+    }
+
+    #[test]
+    fn test_writes_segments_and_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = KafkaExportConfig {
+            base_offset: 100,
+            segment_bytes: 8 * 1024,
+            index_interval_bytes: 1024,
+            batch_records: 10,
+            ..Default::default()
+        };
+        let mut writer = KafkaSegmentWriter::create(dir.path(), config).unwrap();
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for i in 0..300u64 {
+            let mut record = if i % 50 == 49 {
+                Record::delete(format!("key{}", i % 50))
+            } else {
+                Record::put_with_ttl(
+                    format!("key{}", i % 50),
+                    vec![b'v'; 40],
+                    Duration::from_secs(60),
+                )
+            };
+            record.lsn = Some(i + 1);
+            record.timestamp = (i != 10).then(|| start + Duration::from_millis(i));
+            record.namespace = (i % 2 == 0).then_some(3);
+            assert_eq!(writer.append(&record).unwrap(), 100 + i);
+        }
+        let summary = writer.finish().unwrap();
+        assert_eq!(summary.records, 300);
+        assert_eq!(summary.batches, 30);
+        assert_eq!(summary.next_offset, 400);
+        assert!(summary.segments > 1);
+
+        let mut names: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names.len() as u64, summary.segments * 3);
+        assert_eq!(names[0], "00000000000000000100.index");
+
+        let mut decoded = Vec::new();
+        for name in names.iter().filter(|n| n.ends_with(".log")) {
+            let base: u64 = name.trim_end_matches(".log").parse().unwrap();
+            let log = std::fs::read(dir.path().join(name)).unwrap();
+            assert!(log.len() as u64 <= 8 * 1024);
+            let (records, batches) = decode_log(&log);
+            assert_eq!(records[0].offset, base);
+
+            // Every index entry is the last offset of a batch and where the
+            // batch starts
+            let index = std::fs::read(dir.path().join(segment_file_name(base, "index"))).unwrap();
+            assert!(!index.is_empty());
+            for entry in index.chunks(8) {
+                let mut entry = entry;
+                let offset = base + entry.get_u32() as u64;
+                let position = entry.get_u32() as u64;
+                assert!(batches.contains(&(offset, position)));
+            }
+            let time_index =
+                std::fs::read(dir.path().join(segment_file_name(base, "timeindex"))).unwrap();
+            let last = &time_index[time_index.len() - 12..];
+            let max = records.iter().map(|r| r.timestamp).max().unwrap();
+            assert_eq!(i64::from_be_bytes(last[..8].try_into().unwrap()), max);
+            decoded.extend(records);
+        }
+
+        assert_eq!(decoded.len(), 300);
+        let ms = timestamp_ms(start);
+        assert_eq!(
+            decoded[0],
+            Decoded {
+                offset: 100,
+                timestamp: ms,
+                key: b"key0".to_vec(),
+                value: Some(vec![b'v'; 40]),
+                headers: vec![
+                    ("nori.lsn".into(), "1".into()),
+                    ("nori.namespace".into(), "3".into()),
+                    ("nori.ttl_ms".into(), "60000".into()),
+                ],
+            }
+        );
+        // No timestamp: the one before it
+        assert_eq!(decoded[10].timestamp, ms + 9);
+        assert_eq!(decoded[49].value, None);
+        assert_eq!(decoded[49].headers, [("nori.lsn".into(), "50".into())]);
+        assert!(decoded
+            .iter()
+            .zip(100..)
+            .all(|(r, offset)| r.offset == offset));
+    }
+}
@@ -0,0 +1,676 @@
+//! Time-bound single-writer ownership of a WAL directory.
+//!
+//! The directory lock keeps two processes on one host from writing the same
+//! WAL, but `flock` means little on shared storage, where an active and a
+//! passive node on different hosts mount the same directory. A [`Lease`]
+//! grants ownership for a bounded time instead: the holder renews it
+//! periodically, and once it goes unrenewed for its TTL another node may take
+//! over.
+//!
+//! Every change of holder (or lapse and re-grant) increments the lease's
+//! epoch. [`Wal::attach_lease`](crate::Wal::attach_lease) records the highest
+//! epoch attached to the WAL in its metadata and refuses older ones, so a
+//! deposed writer cannot come back, and from then on appends fail with
+//! [`SegmentError::LeaseExpired`] once the lease runs out. The epoch can also
+//! be handed to downstream systems as a fencing token.
+//!
+//! ```no_run
+//! use nori_wal::{FileLeaseStore, Lease, LeaseConfig, Wal, WalConfig};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let store = Arc::new(FileLeaseStore::new("/mnt/shared/wal"));
+//! let config = LeaseConfig {
+//!     holder: "node-a".to_string(),
+//!     ..Default::default()
+//! };
+//! // Take the lease before opening: recovery must not run under a live writer
+//! let lease = Lease::acquire(store, config).await?;
+//! let (wal, _) = Wal::open(WalConfig {
+//!     dir: "/mnt/shared/wal".into(),
+//!     ..Default::default()
+//! })
+//! .await?;
+//! wal.attach_lease(&lease).await?;
+//!
+//! let renewer = lease.clone();
+//! tokio::spawn(async move {
+//!     let lost = renewer.keep_alive().await;
+//!     eprintln!("lease lost: {}", lost);
+//! });
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Leases live in a [`LeaseStore`]. [`FileLeaseStore`] keeps them as files on
+//! the shared filesystem; a closure taking a holder and TTL is a store too,
+//! for leases kept in etcd, ZooKeeper or a database row.
+//!
+//! The store judges expiry by its wall clock, the holder by its own monotonic
+//! clock: a holder stops writing at the time it asked for the lease plus the
+//! TTL, never later than the store's expiry. Clocks of contending nodes must
+//! agree to well within the TTL.
+
+use crate::fs::{self, Fs, LocalFs};
+use crate::segment::SegmentError;
+use bytes::{Buf, BufMut, BytesMut};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A boxed lease store operation, borrowing its arguments for `'a`.
+pub type LeaseFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, SegmentError>> + Send + 'a>>;
+
+/// Who holds a lease, in which epoch, and until when.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseGrant {
+    pub holder: String,
+    /// Incremented whenever the lease is granted anew rather than renewed.
+    pub epoch: u64,
+    pub expires_at: SystemTime,
+}
+
+/// Where leases are kept.
+///
+/// Any `Fn(&str, Duration) -> LeaseFuture<'static, LeaseGrant>` is a store
+/// whose leases can only expire, not be released early.
+pub trait LeaseStore: Send + Sync + 'static {
+    /// Grants the lease to `holder` until `ttl` from now if it is free or
+    /// expired (in a new epoch), or extends it if `holder` already has it (in
+    /// the same epoch). Returns the lease as it now stands, which belongs to
+    /// someone else if their grant has not expired.
+    fn acquire<'a>(&'a self, holder: &'a str, ttl: Duration) -> LeaseFuture<'a, LeaseGrant>;
+
+    /// Ends the lease early if `holder` still has it in `epoch`, so another
+    /// node can take over without waiting for it to expire.
+    fn release<'a>(&'a self, holder: &'a str, epoch: u64) -> LeaseFuture<'a, ()> {
+        let _ = (holder, epoch);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl<F> LeaseStore for F
+where
+    F: Fn(&str, Duration) -> LeaseFuture<'static, LeaseGrant> + Send + Sync + 'static,
+{
+    fn acquire<'a>(&'a self, holder: &'a str, ttl: Duration) -> LeaseFuture<'a, LeaseGrant> {
+        self(holder, ttl)
+    }
+}
+
+/// Settings for [`Lease::acquire`].
+#[derive(Debug, Clone)]
+pub struct LeaseConfig {
+    /// Name of this node, unique among the contenders: 1 to 64 ASCII
+    /// letters, digits, `-`, `_` or `.`.
+    pub holder: String,
+    /// How long a grant lasts without renewal.
+    pub ttl: Duration,
+    /// How often [`Lease::keep_alive`] renews, well under `ttl`.
+    pub renew_interval: Duration,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        Self {
+            holder: String::new(),
+            ttl: Duration::from_secs(10),
+            renew_interval: Duration::from_secs(3),
+        }
+    }
+}
+
+impl LeaseConfig {
+    /// Checks the holder name and that renewals come before expiry.
+    pub fn validate(&self) -> Result<(), SegmentError> {
+        let valid_holder = !self.holder.is_empty()
+            && self.holder.len() <= 64
+            && self
+                .holder
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+        if !valid_holder {
+            return Err(SegmentError::InvalidConfig(format!(
+                "invalid lease holder {:?}",
+                self.holder
+            )));
+        }
+        if self.renew_interval.is_zero() || self.renew_interval >= self.ttl {
+            return Err(SegmentError::InvalidConfig(format!(
+                "lease renew_interval {:?} must be non-zero and under the ttl {:?}",
+                self.renew_interval, self.ttl
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The part of a lease a WAL checks on every append.
+#[derive(Debug)]
+pub(crate) struct LeaseState {
+    epoch: AtomicU64,
+    /// When the holder must stop writing; `None` once the lease is lost.
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl LeaseState {
+    fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner) = deadline;
+    }
+
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Fails with [`SegmentError::LeaseExpired`] unless the lease is still
+    /// held.
+    pub(crate) fn check(&self) -> Result<(), SegmentError> {
+        match self.deadline() {
+            Some(deadline) if Instant::now() < deadline => Ok(()),
+            _ => Err(SegmentError::LeaseExpired(self.epoch())),
+        }
+    }
+}
+
+/// Time-bound ownership granted by a [`LeaseStore`].
+///
+/// Clones share the grant, so one can renew in a background task while
+/// another is attached to a WAL.
+#[derive(Clone)]
+pub struct Lease {
+    store: Arc<dyn LeaseStore>,
+    config: LeaseConfig,
+    state: Arc<LeaseState>,
+}
+
+impl Lease {
+    /// Takes the lease, failing with [`SegmentError::LeaseHeld`] if another
+    /// holder has an unexpired grant.
+    pub async fn acquire(
+        store: Arc<dyn LeaseStore>,
+        config: LeaseConfig,
+    ) -> Result<Self, SegmentError> {
+        config.validate()?;
+        let lease = Self {
+            store,
+            config,
+            state: Arc::new(LeaseState {
+                epoch: AtomicU64::new(0),
+                deadline: Mutex::new(None),
+            }),
+        };
+        lease.request(true).await?;
+        Ok(lease)
+    }
+
+    /// Extends the lease by its TTL.
+    ///
+    /// Fails with [`SegmentError::LeaseHeld`] if another node has taken it
+    /// over, or [`SegmentError::LeaseExpired`] if it lapsed and was granted
+    /// again; either way the lease is lost for good. Other errors leave it
+    /// valid until its current deadline.
+    pub async fn renew(&self) -> Result<(), SegmentError> {
+        self.check()?;
+        self.request(false).await
+    }
+
+    async fn request(&self, first: bool) -> Result<(), SegmentError> {
+        let requested = Instant::now();
+        let grant = self
+            .store
+            .acquire(&self.config.holder, self.config.ttl)
+            .await?;
+        if grant.holder != self.config.holder {
+            self.state.set_deadline(None);
+            return Err(SegmentError::LeaseHeld(grant.holder));
+        }
+        if !first && grant.epoch != self.epoch() {
+            // Someone else may have written in between
+            self.state.set_deadline(None);
+            return Err(SegmentError::LeaseExpired(self.epoch()));
+        }
+
+        let remaining = grant
+            .expires_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        let deadline = (requested + self.config.ttl).min(Instant::now() + remaining);
+        self.state.epoch.store(grant.epoch, Ordering::Release);
+        self.state.set_deadline(Some(deadline));
+        Ok(())
+    }
+
+    /// Renews every `renew_interval` until the lease is lost, and returns
+    /// why. Failed renewals are retried until the lease runs out.
+    pub async fn keep_alive(&self) -> SegmentError {
+        loop {
+            tokio::time::sleep(self.config.renew_interval).await;
+            match self.renew().await {
+                Ok(()) => {}
+                Err(e @ (SegmentError::LeaseHeld(_) | SegmentError::LeaseExpired(_))) => return e,
+                // Retried at the next interval while the lease lasts
+                Err(_) => {
+                    if let Err(expired) = self.check() {
+                        return expired;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives the lease up so another node can take over at once. Appends to
+    /// a WAL it is attached to fail from now on.
+    pub async fn release(self) -> Result<(), SegmentError> {
+        self.state.set_deadline(None);
+        self.store.release(&self.config.holder, self.epoch()).await
+    }
+
+    /// Fails with [`SegmentError::LeaseExpired`] unless the lease is held.
+    pub fn check(&self) -> Result<(), SegmentError> {
+        self.state.check()
+    }
+
+    /// Returns true while the lease is held.
+    pub fn is_valid(&self) -> bool {
+        self.check().is_ok()
+    }
+
+    /// Returns how long the lease stays valid without renewal.
+    pub fn remaining(&self) -> Duration {
+        self.state.deadline().map_or(Duration::ZERO, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Returns the epoch the lease was granted in.
+    pub fn epoch(&self) -> u64 {
+        self.state.epoch()
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.config.holder
+    }
+
+    pub(crate) fn state(&self) -> &Arc<LeaseState> {
+        &self.state
+    }
+}
+
+impl std::fmt::Debug for Lease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lease")
+            .field("holder", &self.config.holder)
+            .field("epoch", &self.epoch())
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+/// File extension of lease files.
+pub const LEASE_EXTENSION: &str = "lease";
+
+const LEASE_MAGIC: &[u8; 8] = b"NORILEAS";
+const LEASE_VERSION: u8 = 1;
+
+/// Leases kept as files in a directory on shared storage.
+///
+/// Each epoch has its own file, `<epoch>.lease`, holding the holder and
+/// expiry. A renewal replaces the holder's file atomically; a takeover
+/// writes the next epoch's file aside and hard-links it into place, which
+/// fails if a competing node got there first, so each epoch has exactly one
+/// holder. The filesystem must support hard links and atomic renames (local
+/// disks, NFSv3+ and SMB do).
+pub struct FileLeaseStore {
+    fs: Arc<dyn Fs>,
+    dir: PathBuf,
+}
+
+impl FileLeaseStore {
+    /// Creates a store over `dir` on the local disk.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_fs(Arc::new(LocalFs), dir)
+    }
+
+    /// Creates a store over `dir` on `fs`.
+    pub fn with_fs(fs: Arc<dyn Fs>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            fs,
+            dir: dir.into(),
+        }
+    }
+
+    /// Returns the lease as it stands, or `None` if none was ever granted.
+    pub async fn current(&self) -> Result<Option<LeaseGrant>, SegmentError> {
+        match self.latest_epoch().await? {
+            Some(epoch) => self.read(epoch).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn path(&self, epoch: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", epoch, LEASE_EXTENSION))
+    }
+
+    fn temp_path(&self, holder: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}.tmp", holder, LEASE_EXTENSION))
+    }
+
+    async fn epochs(&self) -> Result<Vec<u64>, SegmentError> {
+        let entries = match self.fs.read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut epochs: Vec<u64> = entries
+            .iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == LEASE_EXTENSION))
+            .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+            .collect();
+        epochs.sort_unstable();
+        Ok(epochs)
+    }
+
+    async fn latest_epoch(&self) -> Result<Option<u64>, SegmentError> {
+        Ok(self.epochs().await?.last().copied())
+    }
+
+    async fn read(&self, epoch: u64) -> Result<LeaseGrant, SegmentError> {
+        let data = fs::read(self.fs.as_ref(), &self.path(epoch)).await?;
+        decode(&data)
+            .filter(|grant| grant.epoch == epoch)
+            .ok_or_else(|| SegmentError::CorruptMeta(format!("{:020}.lease", epoch)))
+    }
+
+    /// Writes `grant` to its holder's temporary file.
+    async fn write_temp(&self, grant: &LeaseGrant) -> Result<PathBuf, SegmentError> {
+        let temp_path = self.temp_path(&grant.holder);
+        fs::write_synced(self.fs.as_ref(), &temp_path, &encode(grant)).await?;
+        Ok(temp_path)
+    }
+
+    /// Replaces the file of `grant`'s epoch, which its holder already has.
+    async fn replace(&self, grant: &LeaseGrant) -> Result<(), SegmentError> {
+        let temp_path = self.write_temp(grant).await?;
+        self.fs.rename(&temp_path, &self.path(grant.epoch)).await?;
+        Ok(self.fs.sync_dir(&self.dir).await?)
+    }
+
+    /// Creates the file of `grant`'s epoch, returning false if another node
+    /// created it first.
+    async fn claim(&self, grant: &LeaseGrant) -> Result<bool, SegmentError> {
+        let temp_path = self.write_temp(grant).await?;
+        let linked = self.fs.hard_link(&temp_path, &self.path(grant.epoch)).await;
+        self.fs.remove_file(&temp_path).await?;
+        match linked {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        self.fs.sync_dir(&self.dir).await?;
+
+        // Older epochs only matter to their deposed holders, who find out
+        // when they next renew
+        for epoch in self.epochs().await? {
+            if epoch < grant.epoch {
+                let _ = self.fs.remove_file(&self.path(epoch)).await;
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl LeaseStore for FileLeaseStore {
+    fn acquire<'a>(&'a self, holder: &'a str, ttl: Duration) -> LeaseFuture<'a, LeaseGrant> {
+        Box::pin(async move {
+            self.fs.create_dir_all(&self.dir).await?;
+            let now = SystemTime::now();
+            let current = self.current().await?;
+            let mut grant = LeaseGrant {
+                holder: holder.to_string(),
+                epoch: current.as_ref().map_or(1, |current| current.epoch + 1),
+                expires_at: now + ttl,
+            };
+
+            match current {
+                Some(current) if current.expires_at > now && current.holder == holder => {
+                    grant.epoch = current.epoch;
+                    self.replace(&grant).await?;
+                    // A takeover may have raced with the renewal
+                    match self.current().await? {
+                        Some(latest) if latest.epoch != grant.epoch => Ok(latest),
+                        _ => Ok(grant),
+                    }
+                }
+                Some(current) if current.expires_at > now => Ok(current),
+                _ => {
+                    if self.claim(&grant).await? {
+                        Ok(grant)
+                    } else {
+                        self.read(grant.epoch).await
+                    }
+                }
+            }
+        })
+    }
+
+    fn release<'a>(&'a self, holder: &'a str, epoch: u64) -> LeaseFuture<'a, ()> {
+        Box::pin(async move {
+            match self.current().await? {
+                Some(current) if current.holder == holder && current.epoch == epoch => {
+                    self.replace(&LeaseGrant {
+                        expires_at: UNIX_EPOCH,
+                        ..current
+                    })
+                    .await
+                }
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+impl std::fmt::Debug for FileLeaseStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileLeaseStore")
+            .field("dir", &self.dir)
+            .finish()
+    }
+}
+
+/// Encodes a lease file:
+/// - magic: `NORILEAS`
+/// - version: u8
+/// - epoch: u64
+/// - expires_at: u64 (milliseconds since the Unix epoch)
+/// - holder_len: u16, then the holder
+/// - crc32c: u32 (of all preceding bytes)
+fn encode(grant: &LeaseGrant) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(8 + 1 + 8 + 8 + 2 + grant.holder.len() + 4);
+    buf.put_slice(LEASE_MAGIC);
+    buf.put_u8(LEASE_VERSION);
+    buf.put_u64_le(grant.epoch);
+    buf.put_u64_le(crate::record::timestamp_millis(grant.expires_at));
+    buf.put_u16_le(grant.holder.len() as u16);
+    buf.put_slice(grant.holder.as_bytes());
+    let crc = crc32c::crc32c(&buf);
+    buf.put_u32_le(crc);
+    buf
+}
+
+fn decode(data: &[u8]) -> Option<LeaseGrant> {
+    if data.len() < 8 + 1 + 8 + 8 + 2 + 4 || &data[..8] != LEASE_MAGIC || data[8] != LEASE_VERSION {
+        return None;
+    }
+    let (body, mut crc) = data.split_at(data.len() - 4);
+    if crc.get_u32_le() != crc32c::crc32c(body) {
+        return None;
+    }
+
+    let mut cursor = &body[9..];
+    let epoch = cursor.get_u64_le();
+    let expires_at = UNIX_EPOCH + Duration::from_millis(cursor.get_u64_le());
+    let len = cursor.get_u16_le() as usize;
+    if cursor.len() != len {
+        return None;
+    }
+    let holder = String::from_utf8(cursor.to_vec()).ok()?;
+    Some(LeaseGrant {
+        holder,
+        epoch,
+        expires_at,
+    })
+}
+
+/// Name of the metadata blob holding the highest lease epoch attached to a
+/// WAL.
+pub(crate) const FENCING_EPOCH_META: &str = "fencing-epoch";
+
+pub(crate) fn decode_epoch(value: &[u8]) -> Result<u64, SegmentError> {
+    let bytes = value
+        .try_into()
+        .map_err(|_| SegmentError::CorruptMeta(FENCING_EPOCH_META.to_string()))?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Record, Wal, WalConfig};
+    use tempfile::TempDir;
+
+    fn config(holder: &str, ttl_ms: u64) -> LeaseConfig {
+        LeaseConfig {
+            holder: holder.to_string(),
+            ttl: Duration::from_millis(ttl_ms),
+            renew_interval: Duration::from_millis(ttl_ms / 4),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_lease_handover() {
+        let temp_dir = TempDir::new().unwrap();
+        let store: Arc<dyn LeaseStore> = Arc::new(FileLeaseStore::new(temp_dir.path()));
+
+        let a = Lease::acquire(store.clone(), config("node-a", 60_000))
+            .await
+            .unwrap();
+        assert_eq!(a.epoch(), 1);
+        assert!(matches!(
+            Lease::acquire(store.clone(), config("node-b", 60_000)).await,
+            Err(SegmentError::LeaseHeld(holder)) if holder == "node-a"
+        ));
+        a.renew().await.unwrap();
+        assert_eq!(a.epoch(), 1);
+        assert!(a.remaining() > Duration::from_secs(59));
+
+        let stale = a.clone();
+        a.release().await.unwrap();
+        assert!(!stale.is_valid());
+        let b = Lease::acquire(store.clone(), config("node-b", 60_000))
+            .await
+            .unwrap();
+        assert_eq!(b.epoch(), 2);
+
+        // The deposed holder finds out on its next renewal
+        assert!(matches!(
+            stale.renew().await,
+            Err(SegmentError::LeaseExpired(1))
+        ));
+        let current = FileLeaseStore::new(temp_dir.path())
+            .current()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((current.holder.as_str(), current.epoch), ("node-b", 2));
+        assert!(!temp_dir.path().join(format!("{:020}.lease", 1)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_taken_over() {
+        let temp_dir = TempDir::new().unwrap();
+        let store: Arc<dyn LeaseStore> = Arc::new(FileLeaseStore::new(temp_dir.path()));
+
+        let a = Lease::acquire(store.clone(), config("node-a", 200))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(matches!(a.check(), Err(SegmentError::LeaseExpired(1))));
+
+        let b = Lease::acquire(store.clone(), config("node-b", 60_000))
+            .await
+            .unwrap();
+        assert_eq!(b.epoch(), 2);
+        assert!(matches!(
+            a.keep_alive().await,
+            SegmentError::LeaseExpired(1)
+        ));
+        assert!(matches!(
+            Lease::acquire(store, config("node-a", 200)).await,
+            Err(SegmentError::LeaseHeld(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wal_appends_require_attached_lease() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::open(WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        // A store backed by a callback, granting whatever epoch it is told
+        let epoch = Arc::new(AtomicU64::new(2));
+        let granted = epoch.clone();
+        let store: Arc<dyn LeaseStore> = Arc::new(move |holder: &str, ttl: Duration| {
+            let grant = LeaseGrant {
+                holder: holder.to_string(),
+                epoch: granted.load(Ordering::SeqCst),
+                expires_at: SystemTime::now() + ttl,
+            };
+            Box::pin(async move { Ok(grant) }) as LeaseFuture<'static, LeaseGrant>
+        });
+
+        let lease = Lease::acquire(store.clone(), config("node-a", 300))
+            .await
+            .unwrap();
+        wal.attach_lease(&lease).await.unwrap();
+        assert_eq!(wal.fencing_epoch().await.unwrap(), Some(2));
+        let record = Record::put(b"k".as_slice(), b"v".as_slice());
+        wal.append(&record).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert!(matches!(
+            wal.append(&record).await,
+            Err(SegmentError::LeaseExpired(2))
+        ));
+        assert!(matches!(
+            wal.writer()
+                .append_batch(std::slice::from_ref(&record))
+                .await,
+            Err(SegmentError::LeaseExpired(2))
+        ));
+
+        epoch.store(1, Ordering::SeqCst);
+        let stale = Lease::acquire(store, config("node-b", 300)).await.unwrap();
+        assert!(matches!(
+            wal.attach_lease(&stale).await,
+            Err(SegmentError::Fenced {
+                epoch: 1,
+                current: 2
+            })
+        ));
+
+        wal.detach_lease();
+        wal.append(&record).await.unwrap();
+    }
+}
@@ -2,10 +2,49 @@
 //!
 //! Implements a write-ahead log (WAL) with:
 //! - Varint-encoded records with CRC32C checksumming
-//! - Configurable fsync policies (always, batch, os)
+//! - Configurable fsync policies (always, batch, os), with per-record
+//!   durability classes that can force an fsync
 //! - Automatic segment rotation at 128MB
 //! - Crash recovery with partial-tail truncation
-//! - Observability via nori-observe
+//! - Tracking of keys whose TTL ran out, with a hook for deleting them
+//! - Optional background scrubbing of sealed segments
+//! - Online compaction of records superseded by later writes
+//! - Copying a WAL into a fresh directory, optionally compacted and
+//!   recompressed
+//! - Optional coalescing of puts to the same key within a batch
+//! - A memory budget shared by many WALs, with per-component accounting
+//! - Per-namespace quotas that reject, throttle or report a tenant filling
+//!   the log
+//! - Seal sidecars that let recovery skip verified segments
+//! - Optional hash chaining of records, with an exportable head for
+//!   tamper evidence
+//! - Durable checkpoints with optional segment purging
+//! - An audit log of purges, truncations and other administrative operations
+//! - Atomically replaced metadata blobs (a consensus layer's term and vote)
+//! - Time-bound write leases with fencing epochs, for active/passive
+//!   failover on shared storage
+//! - Readers that follow the log across segment boundaries
+//! - Tail subscriptions that wait for new durable appends
+//! - Parallel replay of sealed segments
+//! - Sets of per-shard logs, with two-phase commit for writes spanning shards
+//! - Bulk import for backfills
+//! - Export to Kafka log segments, and import from RocksDB and LevelDB
+//!   write-ahead logs and Redis append-only files
+//! - Change data capture to JSON Lines, Kafka and webhooks, with saved
+//!   cursors and at-least-once delivery
+//! - Streaming replication to followers over TCP, and a follower that
+//!   applies it (`replication` feature)
+//! - Session tokens for reading your own writes on followers
+//! - A `WalLog` trait with an in-memory implementation for tests
+//! - A synchronous API for callers without a runtime (`blocking` feature)
+//! - Fault-injection points for crash testing (`failpoints` feature)
+//! - A pluggable filesystem, with an in-memory one that simulates crashes
+//! - A pluggable segment store for keeping segment bytes elsewhere
+//! - An injectable clock for deterministic tests of time-dependent behavior
+//! - A configuration advisor that recommends settings for an observed
+//!   workload
+//! - Observability via nori-observe, with spans for appends carrying a
+//!   W3C trace context and alerts when append or fsync latency SLOs burn
 //!
 //! # Example
 //!
@@ -30,15 +69,120 @@
 //! }
 //! ```
 
+pub mod advisor;
+pub mod aof;
+pub mod audit;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod builder;
+pub mod cdc;
+pub mod chain;
+pub mod checkpoint;
+pub mod clock;
+pub mod coalesce;
+pub mod compaction;
+pub mod config;
+pub mod copy;
+pub mod encode_pool;
+pub mod error;
+pub mod expiry;
+pub mod failpoint;
+#[cfg(feature = "replication")]
+pub mod follower;
+pub mod fs;
+pub mod handle;
+pub mod import;
+pub mod kafka;
+pub mod lease;
+mod lock;
+mod lsn_index;
+pub mod mem;
+pub mod memory;
+pub mod meta;
+pub mod metrics;
+mod moved;
 mod prealloc;
+pub mod quota;
+pub mod reader;
 pub mod record;
+pub mod record_cache;
 pub mod recovery;
+pub mod replay;
+#[cfg(feature = "replication")]
+pub mod replication;
+pub mod report;
+pub mod rocksdb;
+pub mod runtime;
+pub mod scrub;
+pub mod seal;
 pub mod segment;
+#[cfg(feature = "replication")]
+pub mod session;
+pub mod sim;
+pub mod slo;
+pub mod store;
+pub mod trace;
+pub mod transaction;
 pub mod wal;
+pub mod wal_log;
+pub mod wal_set;
+pub mod workload;
 
-pub use record::{Compression, Record, RecordError};
-pub use recovery::RecoveryInfo;
+pub use advisor::{Advice, SizeSummary, WorkloadObserver, WorkloadProfile};
+pub use aof::{AofImportConfig, AofImportSummary, AofReader};
+pub use audit::{AuditEntry, AuditOperation};
+pub use builder::WalBuilder;
+#[cfg(feature = "webhook")]
+pub use cdc::WebhookSink;
+pub use cdc::{
+    CdcConfig, CdcError, CdcEvent, CdcRunner, CdcSink, CdcStart, CdcStats, JsonLinesSink,
+    KafkaMessage, KafkaProducer, KafkaSink, SinkError,
+};
+pub use chain::{record_hash, ChainHead, ChainReport, ChainVerifier};
+pub use checkpoint::Checkpoint;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use compaction::CompactionReport;
+pub use config::ConfigError;
+pub use copy::{CopyOptions, CopyReport};
+pub use error::{ErrorClass, IoOp, WalError};
+pub use expiry::ExpiredKey;
+#[cfg(feature = "replication")]
+pub use follower::{FollowerConfig, WalFollower};
+pub use fs::{Fs, FsFile, LocalFs, OpenMode};
+pub use handle::{WalReadHandle, WalWriter};
+pub use import::{ImportConfig, ImportSummary};
+pub use kafka::{KafkaExportConfig, KafkaExportSummary, KafkaSegmentWriter};
+pub use lease::{FileLeaseStore, Lease, LeaseConfig, LeaseGrant, LeaseStore};
+pub use lock::DirLock;
+pub use mem::{MemFault, MemWal, MemWalConfig};
+pub use memory::{MemoryBudget, MemoryComponent, MemoryStats};
+pub use meta::MetaStore;
+pub use metrics::{LatencySummary, NamespaceMetrics, WalMetrics, WriteEfficiency};
+pub use quota::{NamespaceQuota, QuotaEnforcement};
+pub use reader::{Cursor, WalReader, WalTail};
+pub use record::{Compression, Durability, Record, RecordError, RecordHeader, TraceContext};
+pub use recovery::{
+    PendingRecovery, RecoveryBudget, RecoveryGap, RecoveryInfo, RecoveryMode, RecoveryOptions,
+    RecoveryTarget,
+};
+pub use replay::{ReplayBatch, ReplayConfig, ReplayOrder, ReplaySummary};
+#[cfg(feature = "replication")]
+pub use replication::{FollowerStatus, ReplicationConfig, ReplicationServer};
+pub use rocksdb::{
+    MergeHandling, RocksBatch, RocksImportConfig, RocksImportSummary, RocksLogReader, RocksOp,
+};
+pub use runtime::{Runtime, TokioRuntime};
+pub use scrub::{ScrubReport, SegmentVerification};
 pub use segment::{
-    FsyncPolicy, Position, SegmentConfig, SegmentError, SegmentManager, SegmentReader,
+    BackupInfo, FsyncPolicy, ParsePositionError, Position, ReaderConfig, SegmentConfig,
+    SegmentError, SegmentManager, SegmentReader,
 };
+#[cfg(feature = "replication")]
+pub use session::SessionToken;
+pub use sim::{CrashMode, SimFault, SimFs};
+pub use slo::LatencySlo;
+pub use store::{FsSegmentStore, SegmentStore};
+pub use transaction::{CommittedTransaction, MarkerKind, TransactionRecovery};
 pub use wal::{Wal, WalConfig};
+pub use wal_log::{LogReader, WalLog};
+pub use wal_set::{Retention, WalSet, WalSetConfig};
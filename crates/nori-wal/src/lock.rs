@@ -0,0 +1,160 @@
+//! Exclusive lock on a WAL directory.
+//!
+//! An open [`Wal`](crate::Wal) holds an advisory lock on a `LOCK` file in its
+//! directory, so a second process (or a second `Wal` in the same process)
+//! cannot append to the same segments. The lock is released when the holder is
+//! closed or dropped, and the OS releases it if the process dies. The file
+//! itself is left in place; removing it while another opener may be waiting on
+//! it would let two holders lock different inodes.
+//!
+//! Offline tools that modify a WAL directory take the same lock, so they
+//! fail instead of changing segments under a running process.
+//!
+//! On Unix the lock is taken with `flock(2)`. On Windows the file is opened
+//! without write sharing, so a second opener fails with a sharing violation,
+//! and then locked with `LockFileEx`. Other platforms only create the file.
+
+use crate::segment::SegmentError;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file inside a WAL directory.
+pub(crate) const LOCK_FILE: &str = "LOCK";
+
+/// Held lock on a WAL directory; dropping it releases the lock.
+#[derive(Debug)]
+pub struct DirLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl DirLock {
+    /// Locks `dir`, failing with [`SegmentError::Locked`] if another holder
+    /// already has it.
+    pub fn acquire(dir: &Path) -> Result<Self, SegmentError> {
+        let path = dir.join(LOCK_FILE);
+        let file = open(&path)
+            .and_then(|file| try_lock(&file).map(|()| file))
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    SegmentError::Locked(dir.to_path_buf())
+                } else {
+                    SegmentError::Io(e)
+                }
+            })?;
+        Ok(Self { path, _file: file })
+    }
+
+    /// Releases the lock and removes the lock file, for directories the WAL
+    /// is leaving for good.
+    pub(crate) fn remove(self) -> std::io::Result<()> {
+        let path = self.path.clone();
+        drop(self);
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn open(path: &Path) -> std::io::Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+#[cfg(windows)]
+fn open(path: &Path) -> std::io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use windows_sys::Win32::Foundation::ERROR_SHARING_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::FILE_SHARE_READ;
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .share_mode(FILE_SHARE_READ)
+        .open(path)
+        .map_err(|e| {
+            if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION as i32) {
+                std::io::ErrorKind::WouldBlock.into()
+            } else {
+                e
+            }
+        })
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor is owned by `file` and stays open for the call
+    let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn try_lock(file: &File) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+    use windows_sys::Win32::Storage::FileSystem::{
+        LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    };
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    // SAFETY: the handle is owned by `file` and stays open for the call, and
+    // OVERLAPPED is plain data for which all zeroes (offset 0) is valid
+    let ret = unsafe {
+        let mut overlapped: OVERLAPPED = std::mem::zeroed();
+        LockFileEx(
+            file.as_raw_handle() as _,
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if ret != 0 {
+        return Ok(());
+    }
+    let e = std::io::Error::last_os_error();
+    if e.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+        Err(std::io::ErrorKind::WouldBlock.into())
+    } else {
+        Err(e)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn try_lock(_file: &File) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, any(unix, windows)))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let temp_dir = TempDir::new().unwrap();
+        let lock = DirLock::acquire(temp_dir.path()).unwrap();
+        assert!(matches!(
+            DirLock::acquire(temp_dir.path()),
+            Err(SegmentError::Locked(_))
+        ));
+
+        drop(lock);
+        let lock = DirLock::acquire(temp_dir.path()).unwrap();
+        lock.remove().unwrap();
+        assert!(!temp_dir.path().join(LOCK_FILE).exists());
+    }
+}
@@ -0,0 +1,233 @@
+//! Sparse in-memory index from LSNs to log positions, which also keeps the
+//! append time of each sample.
+//!
+//! For each segment the index keeps the LSN, timestamp and offset of its first
+//! record, plus a sample roughly every [`SAMPLE_INTERVAL`] bytes for segments
+//! written since the WAL was opened. Seeking to an LSN or a point in time then
+//! costs a lookup and a scan of at most one sample interval, instead of a scan
+//! from the start of the log. Segments from before the WAL was opened are
+//! indexed by their first record only, whose LSN is taken from the seal
+//! sidecar where there is one.
+//!
+//! Both LSNs and timestamps are assumed to grow with the log. The WAL assigns
+//! both on append, so this holds unless the clock steps backwards or callers
+//! supply their own. A first record without a timestamp predates the WAL
+//! stamping them, so it counts as older than any point in time.
+
+use crate::segment::Position;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Bytes of a segment between consecutive samples.
+pub(crate) const SAMPLE_INTERVAL: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    offset: u64,
+    lsn: u64,
+    timestamp: Option<SystemTime>,
+}
+
+impl Sample {
+    /// Whether the record was appended before `time`.
+    fn older_than(&self, time: SystemTime) -> bool {
+        self.timestamp.map_or(true, |timestamp| timestamp < time)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct LsnIndex {
+    /// Per segment, samples in increasing order.
+    segments: Mutex<BTreeMap<u64, Vec<Sample>>>,
+}
+
+impl LsnIndex {
+    /// Records that the record at `position` carries `lsn` and `timestamp`,
+    /// keeping it as a sample if it starts a segment or is far enough from the
+    /// last sample.
+    pub(crate) fn note(&self, position: Position, lsn: u64, timestamp: SystemTime) {
+        let mut segments = self.segments.lock().unwrap();
+        let samples = segments.entry(position.segment_id).or_default();
+        let due = samples.last().map_or(true, |last| {
+            position.offset >= last.offset + SAMPLE_INTERVAL
+        });
+        if due {
+            samples.push(Sample {
+                offset: position.offset,
+                lsn,
+                timestamp: Some(timestamp),
+            });
+        }
+    }
+
+    /// Records the first record of `segment_id`, for segments that were
+    /// written before the WAL was opened.
+    pub(crate) fn note_first(&self, segment_id: u64, lsn: u64, timestamp: Option<SystemTime>) {
+        let mut segments = self.segments.lock().unwrap();
+        let samples = segments.entry(segment_id).or_default();
+        if samples.first().map_or(true, |first| first.offset != 0) {
+            samples.insert(
+                0,
+                Sample {
+                    offset: 0,
+                    lsn,
+                    timestamp,
+                },
+            );
+        }
+    }
+
+    /// Returns true if the index knows the first record of `segment_id`.
+    pub(crate) fn contains(&self, segment_id: u64) -> bool {
+        self.segments
+            .lock()
+            .unwrap()
+            .get(&segment_id)
+            .and_then(|samples| samples.first())
+            .is_some_and(|first| first.offset == 0)
+    }
+
+    /// Returns the latest indexed position whose record's LSN is at most
+    /// `lsn`, from which a forward scan finds the record with that LSN.
+    pub(crate) fn seek(&self, lsn: u64) -> Option<Position> {
+        self.last_sample(|sample| sample.lsn <= lsn)
+    }
+
+    /// Returns the latest indexed position whose record is older than
+    /// `since`, so no record from `since` onwards comes before it.
+    pub(crate) fn seek_time(&self, since: SystemTime) -> Option<Position> {
+        self.last_sample(|sample| sample.older_than(since))
+    }
+
+    /// Returns the earliest indexed position whose record is no older than
+    /// `until`, so every record from there on is too.
+    pub(crate) fn end_time(&self, until: SystemTime) -> Option<Position> {
+        let segments = self.segments.lock().unwrap();
+        segments.iter().find_map(|(&segment_id, samples)| {
+            let i = samples.partition_point(|sample| sample.older_than(until));
+            samples.get(i).map(|sample| Position {
+                segment_id,
+                offset: sample.offset,
+            })
+        })
+    }
+
+    /// Finds the last sample satisfying `before`, which must hold for a
+    /// prefix of the samples.
+    fn last_sample(&self, before: impl Fn(&Sample) -> bool) -> Option<Position> {
+        let segments = self.segments.lock().unwrap();
+        segments.iter().rev().find_map(|(&segment_id, samples)| {
+            let i = samples.partition_point(&before);
+            i.checked_sub(1).map(|i| Position {
+                segment_id,
+                offset: samples[i].offset,
+            })
+        })
+    }
+
+    /// Forgets a deleted segment.
+    pub(crate) fn remove(&self, segment_id: u64) {
+        self.segments.lock().unwrap().remove(&segment_id);
+    }
+
+    /// Forgets every segment not in `live`, which must be sorted.
+    pub(crate) fn retain(&self, live: &[u64]) {
+        self.segments
+            .lock()
+            .unwrap()
+            .retain(|id, _| live.binary_search(id).is_ok());
+    }
+
+    /// Forgets everything at and after `position`.
+    pub(crate) fn truncate(&self, position: Position) {
+        let mut segments = self.segments.lock().unwrap();
+        segments.retain(|&id, _| id <= position.segment_id);
+        if let Some(samples) = segments.get_mut(&position.segment_id) {
+            samples.retain(|sample| sample.offset < position.offset);
+            if samples.is_empty() {
+                segments.remove(&position.segment_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(segment_id: u64, offset: u64) -> Position {
+        Position { segment_id, offset }
+    }
+
+    fn secs(n: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(n)
+    }
+
+    #[test]
+    fn test_seek_and_truncate() {
+        let index = LsnIndex::default();
+        for i in 0..100 {
+            index.note(at(0, i * 4096), i + 1, secs(i));
+        }
+        index.note(at(1, 0), 101, secs(100));
+        assert!(index.contains(0) && index.contains(1));
+
+        assert_eq!(index.seek(0), None);
+        assert_eq!(index.seek(1), Some(at(0, 0)));
+        // Sampled every 16 records of 4KB
+        assert_eq!(index.seek(20), Some(at(0, 16 * 4096)));
+        assert_eq!(index.seek(500), Some(at(1, 0)));
+
+        index.truncate(at(0, 40 * 4096));
+        assert!(!index.contains(1));
+        assert_eq!(index.seek(500), Some(at(0, 32 * 4096)));
+
+        index.retain(&[1, 2]);
+        assert_eq!(index.seek(500), None);
+
+        // A segment first seen mid-way gets its start filled in later
+        index.note(at(2, 8192), 300, secs(300));
+        assert!(!index.contains(2));
+        index.note_first(2, 290, Some(secs(290)));
+        assert!(index.contains(2));
+        assert_eq!(index.seek(295), Some(at(2, 0)));
+        assert_eq!(index.seek(300), Some(at(2, 8192)));
+    }
+
+    #[test]
+    fn test_time_bounds() {
+        let index = LsnIndex::default();
+        for i in 0..64 {
+            index.note(at(0, i * 4096), i + 1, secs(i));
+        }
+        index.note(at(1, 0), 65, secs(64));
+
+        assert_eq!(index.seek_time(secs(0)), None);
+        assert_eq!(index.seek_time(secs(1)), Some(at(0, 0)));
+        // Strictly older: the sample at 16 might share its second with `since`
+        assert_eq!(index.seek_time(secs(16)), Some(at(0, 0)));
+        assert_eq!(index.seek_time(secs(17)), Some(at(0, 16 * 4096)));
+        assert_eq!(index.seek_time(secs(1000)), Some(at(1, 0)));
+
+        assert_eq!(index.end_time(secs(0)), Some(at(0, 0)));
+        assert_eq!(index.end_time(secs(17)), Some(at(0, 32 * 4096)));
+        assert_eq!(index.end_time(secs(60)), Some(at(1, 0)));
+        assert_eq!(index.end_time(secs(65)), None);
+    }
+
+    #[test]
+    fn test_untimed_first_records() {
+        // Segments written before records carried timestamps
+        let index = LsnIndex::default();
+        index.note_first(0, 1, None);
+        index.note_first(1, 50, None);
+        index.note(at(2, 0), 100, secs(100));
+        assert_eq!(index.seek(60), Some(at(1, 0)));
+
+        assert_eq!(index.seek_time(secs(0)), Some(at(1, 0)));
+        assert_eq!(index.seek_time(secs(101)), Some(at(2, 0)));
+        assert_eq!(index.end_time(secs(0)), Some(at(2, 0)));
+    }
+}
@@ -0,0 +1,492 @@
+//! In-memory [`WalLog`] for tests.
+//!
+//! [`MemWal`] hands out the same positions and LSNs as [`Wal`](crate::Wal)
+//! for the same appends, rotates at the configured segment size, and applies
+//! the fsync policy to decide which records are durable. Nothing touches the
+//! disk: an fsync only moves the durable position, [`MemWal::crash`] drops
+//! whatever is not durable yet, and [`MemWal::fail_next`] makes upcoming
+//! operations fail with an injected I/O error.
+//!
+//! Recovery, checkpoints, seals and the other file-level features have no
+//! in-memory counterpart.
+
+use crate::clock::{Clock, SystemClock};
+use crate::record::{Durability, Record};
+use crate::segment::{FsyncPolicy, Position, SegmentError};
+use crate::wal_log::{LogReader, WalLog};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Operations that [`MemWal::fail_next`] can make fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemFault {
+    /// An append fails before anything is written.
+    Append,
+    /// An fsync fails, leaving the durable position where it was.
+    Sync,
+    /// A reader's `next_record` fails.
+    Read,
+}
+
+/// Configuration for a [`MemWal`].
+#[derive(Debug, Clone)]
+pub struct MemWalConfig {
+    /// Size in bytes at which segments rotate (default: 128 MiB).
+    pub max_segment_size: u64,
+    /// Decides when appends become durable, as for a [`Wal`](crate::Wal)
+    /// (default: batch with a 5ms window).
+    pub fsync_policy: FsyncPolicy,
+}
+
+impl Default for MemWalConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_size: 128 * 1024 * 1024,
+            fsync_policy: FsyncPolicy::Batch(std::time::Duration::from_millis(5)),
+        }
+    }
+}
+
+struct Entry {
+    position: Position,
+    size: u64,
+    record: Record,
+}
+
+impl Entry {
+    fn end(&self) -> Position {
+        Position {
+            segment_id: self.position.segment_id,
+            offset: self.position.offset + self.size,
+        }
+    }
+}
+
+struct State {
+    config: MemWalConfig,
+    /// Every record in the log, in position order.
+    entries: Vec<Entry>,
+    /// Position the next record is written at.
+    end: Position,
+    /// Position up to which records are durable.
+    durable: Position,
+    next_lsn: u64,
+    /// Oldest segment not yet deleted.
+    first_segment: u64,
+    last_sync: Option<Instant>,
+    clock: Arc<dyn Clock>,
+    faults: HashMap<MemFault, usize>,
+}
+
+impl State {
+    /// Consumes one pending failure of `fault`, if any.
+    fn check(&mut self, fault: MemFault) -> Result<(), SegmentError> {
+        match self.faults.get_mut(&fault) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                Err(SegmentError::Io(std::io::Error::other(format!(
+                    "injected {:?} failure",
+                    fault
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), SegmentError> {
+        self.check(MemFault::Sync)?;
+        self.durable = self.end;
+        self.last_sync = Some(self.clock.now());
+        Ok(())
+    }
+
+    fn apply_fsync_policy(&mut self, critical: bool) -> Result<(), SegmentError> {
+        if critical {
+            return self.sync();
+        }
+        match self.config.fsync_policy {
+            FsyncPolicy::Always => self.sync(),
+            FsyncPolicy::Batch(window) => {
+                let now = self.clock.now();
+                if self
+                    .last_sync
+                    .map_or(true, |last| now.saturating_duration_since(last) >= window)
+                {
+                    self.sync()
+                } else {
+                    Ok(())
+                }
+            }
+            FsyncPolicy::Os => Ok(()),
+        }
+    }
+
+    /// Writes `records` after rotating if they would overflow the segment.
+    fn append(&mut self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        self.check(MemFault::Append)?;
+
+        let now = self.clock.system_time();
+        let mut next = self.next_lsn;
+        let stamped: Vec<(Record, u64)> = records
+            .iter()
+            .map(|record| {
+                let lsn = record.lsn.unwrap_or(next);
+                next = next.max(lsn.saturating_add(1));
+                let mut stamped = record.clone();
+                stamped.lsn = Some(lsn);
+                stamped.timestamp = Some(record.timestamp.unwrap_or(now));
+                // Not stored in the log, so a record reads back without it
+                stamped.durability = Durability::BestEffort;
+                let size = stamped.encode().len() as u64;
+                (stamped, size)
+            })
+            .collect();
+        let total: u64 = stamped.iter().map(|(_, size)| size).sum();
+
+        if self.end.offset > 0 && self.end.offset + total > self.config.max_segment_size {
+            // Rotation seals the old segment, which makes it durable
+            self.end = Position {
+                segment_id: self.end.segment_id + 1,
+                offset: 0,
+            };
+            self.durable = self.end;
+        }
+
+        let mut positions = Vec::with_capacity(stamped.len());
+        for (record, size) in stamped {
+            positions.push(self.end);
+            self.entries.push(Entry {
+                position: self.end,
+                size,
+                record,
+            });
+            self.end.offset += size;
+        }
+        self.next_lsn = next;
+
+        let critical = records
+            .iter()
+            .any(|record| record.durability == Durability::Critical);
+        self.apply_fsync_policy(critical)?;
+        Ok(positions)
+    }
+}
+
+/// In-memory write-ahead log implementing [`WalLog`].
+///
+/// Clones share the same log, so a test can keep a handle for injecting
+/// failures after passing the log to the code under test.
+#[derive(Clone)]
+pub struct MemWal {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for MemWal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemWal {
+    /// Creates an empty log with the default configuration.
+    pub fn new() -> Self {
+        Self::with_config(MemWalConfig::default())
+    }
+
+    /// Creates an empty log with the given configuration.
+    pub fn with_config(config: MemWalConfig) -> Self {
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(State {
+                config,
+                entries: Vec::new(),
+                end: start,
+                durable: start,
+                next_lsn: 1,
+                first_segment: 0,
+                last_sync: None,
+                clock: Arc::new(SystemClock),
+                faults: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Reads the time from `clock`, for the batch-fsync window and record
+    /// timestamps, instead of the system clock.
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.state.lock().unwrap().clock = clock;
+        self
+    }
+
+    /// Makes the next `count` operations of kind `fault` fail with an
+    /// injected I/O error.
+    pub fn fail_next(&self, fault: MemFault, count: usize) {
+        *self.state.lock().unwrap().faults.entry(fault).or_default() += count;
+    }
+
+    /// Simulates a crash: records that are not durable yet are lost, and the
+    /// log continues from the durable position. Returns the number of
+    /// records lost.
+    pub fn crash(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let durable = state.durable;
+        let kept = state.entries.partition_point(|e| e.position < durable);
+        let lost = state.entries.len() - kept;
+        state.entries.truncate(kept);
+        state.end = durable;
+        state.next_lsn = state
+            .entries
+            .last()
+            .and_then(|e| e.record.lsn)
+            .map_or(1, |lsn| lsn + 1);
+        lost
+    }
+}
+
+impl WalLog for MemWal {
+    type Reader = MemReader;
+
+    async fn append(&self, record: &Record) -> Result<Position, SegmentError> {
+        let positions = self
+            .state
+            .lock()
+            .unwrap()
+            .append(std::slice::from_ref(record))?;
+        Ok(positions[0])
+    }
+
+    async fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        if records.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.state.lock().unwrap().append(records)
+    }
+
+    async fn sync(&self) -> Result<(), SegmentError> {
+        self.state.lock().unwrap().sync()
+    }
+
+    async fn current_position(&self) -> Position {
+        self.state.lock().unwrap().end
+    }
+
+    async fn durable_position(&self) -> Position {
+        self.state.lock().unwrap().durable
+    }
+
+    fn next_lsn(&self) -> u64 {
+        self.state.lock().unwrap().next_lsn
+    }
+
+    async fn read_from(&self, position: Position) -> Result<MemReader, SegmentError> {
+        Ok(MemReader {
+            state: self.state.clone(),
+            position,
+        })
+    }
+
+    async fn truncate_from(&self, position: Position) -> Result<u64, SegmentError> {
+        let mut state = self.state.lock().unwrap();
+        if position >= state.end {
+            return Ok(0);
+        }
+
+        let cut = state.entries.partition_point(|e| e.position < position);
+        let first = match state.entries.get(cut) {
+            Some(first) if first.position == position => first,
+            _ => {
+                return Err(SegmentError::Corruption {
+                    segment_id: position.segment_id,
+                    offset: position.offset,
+                })
+            }
+        };
+        if let Some(lsn) = first.record.lsn {
+            state.next_lsn = lsn;
+        }
+
+        let discarded = state.entries[cut..].iter().map(|e| e.size).sum();
+        state.entries.truncate(cut);
+        state.end = position;
+        state.durable = position;
+        Ok(discarded)
+    }
+
+    async fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
+        let mut state = self.state.lock().unwrap();
+        // Only whole segments before `position` go; the current one never does
+        if position.segment_id > state.end.segment_id {
+            return Ok(0);
+        }
+
+        let cutoff = position.segment_id;
+        let deleted = cutoff.saturating_sub(state.first_segment);
+        state.entries.retain(|e| e.position.segment_id >= cutoff);
+        state.first_segment = state.first_segment.max(cutoff);
+        Ok(deleted)
+    }
+}
+
+/// Reader over a [`MemWal`], returned by [`WalLog::read_from`].
+pub struct MemReader {
+    state: Arc<Mutex<State>>,
+    position: Position,
+}
+
+impl LogReader for MemReader {
+    async fn next_record(&mut self) -> Result<Option<(Record, Position)>, SegmentError> {
+        let mut state = self.state.lock().unwrap();
+        state.check(MemFault::Read)?;
+
+        let i = state
+            .entries
+            .partition_point(|e| e.position < self.position);
+        match state.entries.get(i) {
+            Some(entry) if entry.position < state.durable => {
+                self.position = entry.end();
+                Ok(Some((entry.record.clone(), entry.position)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str) -> Record {
+        Record::put(bytes::Bytes::from(key.to_string()), b"value".as_slice())
+    }
+
+    async fn read_all(wal: &MemWal, from: Position) -> Vec<Record> {
+        let mut reader = wal.read_from(from).await.unwrap();
+        let mut records = Vec::new();
+        while let Some((record, _)) = reader.next_record().await.unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    const START: Position = Position {
+        segment_id: 0,
+        offset: 0,
+    };
+
+    #[tokio::test]
+    async fn test_mock_clock_drives_batch_window() {
+        let window = std::time::Duration::from_millis(10);
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let wal = MemWal::with_config(MemWalConfig {
+            fsync_policy: FsyncPolicy::Batch(window),
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        let written_at = clock.system_time();
+
+        // The first append in a window is synced, later ones wait for it to end
+        wal.append(&put("a")).await.unwrap();
+        wal.append(&put("b")).await.unwrap();
+        clock.advance(window);
+        wal.append(&put("c")).await.unwrap();
+        wal.append(&put("d")).await.unwrap();
+        assert_eq!(wal.crash(), 1);
+        let records = read_all(&wal, START).await;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp, Some(written_at));
+    }
+
+    #[tokio::test]
+    async fn test_rotation_and_truncation() {
+        let wal = MemWal::with_config(MemWalConfig {
+            max_segment_size: 100,
+            fsync_policy: FsyncPolicy::Always,
+        });
+
+        let mut positions = Vec::new();
+        for i in 0..10 {
+            positions.push(wal.append(&put(&format!("key{}", i))).await.unwrap());
+        }
+        assert!(wal.current_position().await.segment_id > 0);
+        assert_eq!(read_all(&wal, START).await.len(), 10);
+
+        // Truncation must land on a record boundary
+        let mid = Position {
+            offset: positions[5].offset + 1,
+            ..positions[5]
+        };
+        assert!(matches!(
+            wal.truncate_from(mid).await,
+            Err(SegmentError::Corruption { .. })
+        ));
+        assert!(wal.truncate_from(positions[5]).await.unwrap() > 0);
+        assert_eq!(wal.next_lsn(), 6);
+        assert_eq!(wal.current_position().await, positions[5]);
+
+        let deleted = wal.delete_segments_before(positions[5]).await.unwrap();
+        assert_eq!(deleted, positions[5].segment_id);
+        let remaining = read_all(&wal, START).await;
+        assert!(remaining.len() < 5);
+        assert_eq!(remaining.last().unwrap().lsn, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_crash_loses_unsynced_records() {
+        let wal = MemWal::with_config(MemWalConfig {
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        });
+
+        wal.append(&put("a")).await.unwrap();
+        wal.sync().await.unwrap();
+        wal.append_batch(&[put("b"), put("c")]).await.unwrap();
+        // Readers stop at the durable end
+        assert_eq!(read_all(&wal, START).await.len(), 1);
+
+        assert_eq!(wal.crash(), 2);
+        assert_eq!(wal.next_lsn(), 2);
+
+        // A critical record syncs whatever the policy
+        wal.append(&put("b")).await.unwrap();
+        let critical = put("c").with_durability(Durability::Critical);
+        wal.append(&critical).await.unwrap();
+        assert_eq!(wal.crash(), 0);
+        let records = read_all(&wal, START).await;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].durability, Durability::BestEffort);
+        let position = wal.append(&put("d")).await.unwrap();
+        assert_eq!(position, wal.durable_position().await);
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures() {
+        let wal = MemWal::with_config(MemWalConfig {
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        });
+        let handle = wal.clone();
+
+        handle.fail_next(MemFault::Append, 1);
+        assert!(matches!(
+            wal.append(&put("a")).await,
+            Err(SegmentError::Io(_))
+        ));
+        assert_eq!(wal.next_lsn(), 1);
+
+        // A failed fsync leaves the record written but not durable
+        handle.fail_next(MemFault::Sync, 1);
+        assert!(wal.append(&put("b")).await.is_err());
+        assert_eq!(wal.durable_position().await, START);
+        wal.sync().await.unwrap();
+
+        handle.fail_next(MemFault::Read, 1);
+        let mut reader = wal.read_from(START).await.unwrap();
+        assert!(reader.next_record().await.is_err());
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"b");
+    }
+}
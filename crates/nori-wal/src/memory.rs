@@ -0,0 +1,416 @@
+//! A memory budget shared by any number of WALs.
+//!
+//! Each WAL sizes its buffers on its own, which leaves an embedder running
+//! many WALs in one process guessing at per-instance settings to stay under
+//! the memory it actually has. A [`MemoryBudget`] is one cap for all of
+//! them: create it once, set it in [`WalConfig::memory_budget`] of every WAL
+//! that should share it, and the memory they hold is accounted to it by
+//! [`MemoryComponent`]:
+//!
+//! - Append buffers: the keys and values of appends and imports in progress.
+//!   An append waits for room before it starts, so writers slow down rather
+//!   than pile up buffers when the budget is spent.
+//! - Read-ahead: segment bytes readers have fetched but not yet decoded. A
+//!   reader over budget reads ahead only [`SHRUNK_READ_AHEAD`] bytes at a
+//!   time, still growing its buffer for a record larger than that.
+//! - Record cache: the records kept by the [record cache](crate::record_cache).
+//!   The cache evicts its least recently used records to stay under budget,
+//!   and gives memory back when an append or replication batch is waiting
+//!   for room.
+//! - Replication: the record batches a
+//!   [`ReplicationServer`](crate::ReplicationServer) is sending. A batch
+//!   waits for room for its first record and stops growing once the budget
+//!   is spent.
+//!
+//! The budget is enforced at those points rather than on every allocation,
+//! so it can be exceeded briefly: by a reader fetching a record larger than
+//! the room left, and by appends racing for the last of it. A component
+//! holding nothing always gets its first reservation, so a budget filled by
+//! idle readers can slow appends down but never stop them. What each
+//! component holds is reported by [`MemoryBudget::stats`].
+//!
+//! ```no_run
+//! # use nori_wal::{MemoryBudget, Wal, WalConfig};
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let budget = MemoryBudget::new(256 * 1024 * 1024);
+//! for tenant in ["a", "b", "c"] {
+//!     let config = WalConfig {
+//!         dir: format!("/var/lib/app/wal-{}", tenant).into(),
+//!         memory_budget: Some(budget.clone()),
+//!         ..Default::default()
+//!     };
+//!     let (wal, _) = Wal::open(config).await?;
+//!     // ...
+//! }
+//! println!("{:?}", budget.stats());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`WalConfig::memory_budget`]: crate::WalConfig::memory_budget
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use tokio::sync::Notify;
+
+/// Bytes a reader over budget fetches per read.
+pub const SHRUNK_READ_AHEAD: usize = 4096;
+
+/// What memory under a [`MemoryBudget`] is held for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryComponent {
+    /// Keys and values of appends and imports in progress.
+    AppendBuffers,
+    /// Segment bytes read ahead by readers and not yet decoded.
+    ReadAhead,
+    /// Records kept by the record cache.
+    RecordCache,
+    /// Record batches being sent to followers.
+    Replication,
+}
+
+impl MemoryComponent {
+    const ALL: [MemoryComponent; 4] = [
+        MemoryComponent::AppendBuffers,
+        MemoryComponent::ReadAhead,
+        MemoryComponent::RecordCache,
+        MemoryComponent::Replication,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the name of the component, in snake case.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryComponent::AppendBuffers => "append_buffers",
+            MemoryComponent::ReadAhead => "read_ahead",
+            MemoryComponent::RecordCache => "record_cache",
+            MemoryComponent::Replication => "replication",
+        }
+    }
+}
+
+impl fmt::Display for MemoryComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Memory held under a budget, by component, and how often it was enforced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes the budget allows.
+    pub limit: u64,
+    /// Bytes held by all components.
+    pub used: u64,
+    pub append_buffers: u64,
+    pub read_ahead: u64,
+    pub record_cache: u64,
+    pub replication: u64,
+    /// Appends and replication batches that had to wait for room.
+    pub waits: u64,
+    /// Times a reader read ahead less, or the record cache evicted or left
+    /// out records, to stay under the limit.
+    pub shrinks: u64,
+}
+
+/// Memory that can be given back when the budget runs short.
+pub(crate) trait Reclaim: Send + Sync {
+    /// Frees up to `bytes`, returning how many were freed.
+    fn reclaim(&self, bytes: u64) -> u64;
+}
+
+struct Inner {
+    limit: u64,
+    used: AtomicU64,
+    components: [AtomicU64; 4],
+    waits: AtomicU64,
+    shrinks: AtomicU64,
+    /// Woken whenever memory is given back.
+    released: Notify,
+    reclaimers: Mutex<Vec<Weak<dyn Reclaim>>>,
+}
+
+/// A cap on the memory of every WAL it is set on.
+///
+/// Cloning is cheap and clones share the budget.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes.
+    pub fn new(limit: u64) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit,
+                used: AtomicU64::new(0),
+                components: Default::default(),
+                waits: AtomicU64::new(0),
+                shrinks: AtomicU64::new(0),
+                released: Notify::new(),
+                reclaimers: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the bytes the budget allows.
+    pub fn limit(&self) -> u64 {
+        self.inner.limit
+    }
+
+    /// Returns the bytes held by all components.
+    pub fn used(&self) -> u64 {
+        self.inner.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes held for `component`.
+    pub fn component_used(&self, component: MemoryComponent) -> u64 {
+        self.inner.components[component.index()].load(Ordering::Relaxed)
+    }
+
+    /// Returns what each component holds, and how often the budget was
+    /// enforced.
+    pub fn stats(&self) -> MemoryStats {
+        let [append_buffers, read_ahead, record_cache, replication] =
+            MemoryComponent::ALL.map(|component| self.component_used(component));
+        MemoryStats {
+            limit: self.limit(),
+            used: self.used(),
+            append_buffers,
+            read_ahead,
+            record_cache,
+            replication,
+            waits: self.inner.waits.load(Ordering::Relaxed),
+            shrinks: self.inner.shrinks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Whether `bytes` more fit under the limit.
+    pub(crate) fn fits(&self, bytes: u64) -> bool {
+        self.used().saturating_add(bytes) <= self.limit()
+    }
+
+    /// Counts a component holding less than it would have to stay under the
+    /// limit.
+    pub(crate) fn note_shrink(&self) {
+        self.inner.shrinks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Asks `reclaimer` for memory whenever a reservation has to wait. It is
+    /// forgotten once dropped.
+    pub(crate) fn register(&self, reclaimer: Weak<dyn Reclaim>) {
+        self.inner
+            .reclaimers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(reclaimer);
+    }
+
+    /// Returns an empty charge to `component`, to be resized as memory is
+    /// taken and given back.
+    pub(crate) fn charge(&self, component: MemoryComponent) -> MemoryCharge {
+        MemoryCharge {
+            budget: self.clone(),
+            component,
+            bytes: 0,
+        }
+    }
+
+    /// Charges `bytes` to `component`, waiting until they fit.
+    ///
+    /// Reclaimable memory is given back first. A component that holds
+    /// nothing gets its reservation without waiting.
+    pub(crate) async fn reserve(&self, component: MemoryComponent, bytes: u64) -> MemoryCharge {
+        let mut charge = self.charge(component);
+        let mut waited = false;
+        loop {
+            // Registered before checking, so a release in between is not missed
+            let released = self.inner.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            let fits = self.fits(bytes);
+            if !fits {
+                let short = (self.used() + bytes).saturating_sub(self.limit());
+                if self.reclaim(short) > 0 {
+                    continue;
+                }
+            }
+            if fits || self.component_used(component) == 0 {
+                charge.set(bytes);
+                if waited {
+                    self.inner.waits.fetch_add(1, Ordering::Relaxed);
+                }
+                return charge;
+            }
+            waited = true;
+            released.await;
+        }
+    }
+
+    /// Asks the registered reclaimers for `bytes`, returning how many they
+    /// freed.
+    fn reclaim(&self, bytes: u64) -> u64 {
+        let mut reclaimers = self
+            .inner
+            .reclaimers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        reclaimers.retain(|reclaimer| reclaimer.strong_count() > 0);
+        let mut freed = 0;
+        for reclaimer in reclaimers.iter().filter_map(Weak::upgrade) {
+            if freed >= bytes {
+                break;
+            }
+            freed += reclaimer.reclaim(bytes - freed);
+        }
+        if freed > 0 {
+            self.note_shrink();
+        }
+        freed
+    }
+
+    fn add(&self, component: MemoryComponent, bytes: u64) {
+        self.inner.used.fetch_add(bytes, Ordering::Relaxed);
+        self.inner.components[component.index()].fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn release(&self, component: MemoryComponent, bytes: u64) {
+        self.inner.used.fetch_sub(bytes, Ordering::Relaxed);
+        self.inner.components[component.index()].fetch_sub(bytes, Ordering::Relaxed);
+        self.inner.released.notify_waiters();
+    }
+}
+
+/// Memory held for one component, given back when dropped.
+pub(crate) struct MemoryCharge {
+    budget: MemoryBudget,
+    component: MemoryComponent,
+    bytes: u64,
+}
+
+impl MemoryCharge {
+    /// Returns the budget charged.
+    pub(crate) fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+
+    /// Sets the charge to `bytes`, going over the limit if need be.
+    pub(crate) fn set(&mut self, bytes: u64) {
+        if bytes > self.bytes {
+            self.budget.add(self.component, bytes - self.bytes);
+        } else if bytes < self.bytes {
+            self.budget.release(self.component, self.bytes - bytes);
+        }
+        self.bytes = bytes;
+    }
+
+    /// Adds `bytes` to the charge, returning whether the budget still has
+    /// room.
+    #[cfg(any(test, feature = "replication"))]
+    pub(crate) fn grow(&mut self, bytes: u64) -> bool {
+        self.set(self.bytes + bytes);
+        self.budget.used() <= self.budget.limit()
+    }
+}
+
+impl Drop for MemoryCharge {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Gives back what it holds when asked, like the record cache.
+    struct Pool(Mutex<MemoryCharge>);
+
+    impl Reclaim for Pool {
+        fn reclaim(&self, bytes: u64) -> u64 {
+            let mut charge = self.0.lock().unwrap();
+            let freed = bytes.min(charge.bytes);
+            let left = charge.bytes - freed;
+            charge.set(left);
+            freed
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reservations_wait_for_room() {
+        let budget = MemoryBudget::new(100);
+        let mut reading = budget.charge(MemoryComponent::ReadAhead);
+        reading.set(60);
+        let append = budget.reserve(MemoryComponent::AppendBuffers, 30).await;
+        assert_eq!(budget.used(), 90);
+
+        // Appends already hold memory, so the next one waits
+        let waiting = {
+            let budget = budget.clone();
+            tokio::spawn(async move {
+                let _charge = budget.reserve(MemoryComponent::AppendBuffers, 30).await;
+                budget.used()
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        reading.set(10);
+        assert_eq!(waiting.await.unwrap(), 70);
+        assert_eq!(budget.stats().waits, 1);
+
+        // A component holding nothing is never kept waiting
+        drop(append);
+        reading.set(100);
+        let append = budget.reserve(MemoryComponent::AppendBuffers, 30).await;
+        assert!(!budget.fits(0));
+        let mut batch = budget.charge(MemoryComponent::Replication);
+        assert!(!batch.grow(1));
+        assert_eq!(budget.stats().append_buffers, 30);
+        drop((append, batch, reading));
+        assert_eq!(
+            budget.stats(),
+            MemoryStats {
+                limit: 100,
+                waits: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reclaims_before_waiting() {
+        let budget = MemoryBudget::new(100);
+        let mut cached = budget.charge(MemoryComponent::RecordCache);
+        cached.set(80);
+        let pool: Arc<dyn Reclaim> = Arc::new(Pool(Mutex::new(cached)));
+        budget.register(Arc::downgrade(&pool));
+
+        let mut batch = budget.charge(MemoryComponent::Replication);
+        batch.set(10);
+        let _more = budget.reserve(MemoryComponent::Replication, 40).await;
+        assert_eq!(budget.component_used(MemoryComponent::RecordCache), 50);
+        assert_eq!(budget.used(), 100);
+        assert_eq!(budget.stats().shrinks, 1);
+        assert_eq!(budget.stats().waits, 0);
+
+        // Dropped reclaimers are forgotten
+        drop(pool);
+        assert_eq!(budget.reclaim(10), 0);
+        assert!(budget.inner.reclaimers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,310 @@
+//! Durable metadata blobs kept alongside the log.
+//!
+//! Consensus layers keep a few small values that must survive a crash
+//! exactly as last written: the current term, the vote cast in it, the
+//! cluster membership. [`MetaStore`] keeps each one under a name, in a
+//! `<name>.meta` file in the WAL directory, and replaces it atomically: the
+//! new value is written to a temporary file and fsynced, renamed over the old
+//! file, and the directory is fsynced. A reader sees the old value or the new
+//! one, never a mix.
+//!
+//! Unlike a damaged checkpoint, a damaged blob is an error
+//! ([`SegmentError::CorruptMeta`]) rather than a missing value: forgetting a
+//! vote is as unsafe as never having cast it.
+//!
+//! File layout (little-endian):
+//! - magic: `NORIMETA`
+//! - version: u8
+//! - length: u32
+//! - value: `length` bytes
+//! - crc32c: u32 (of all preceding bytes)
+
+use crate::fs::{self, Fs, LocalFs};
+use crate::segment::SegmentError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// File extension of metadata blobs in the WAL directory.
+pub const META_EXTENSION: &str = "meta";
+
+/// Largest value a blob may hold.
+pub const MAX_META_LEN: usize = 64 * 1024;
+
+/// Longest name a blob may have.
+pub const MAX_META_NAME_LEN: usize = 64;
+
+const META_MAGIC: &[u8; 8] = b"NORIMETA";
+const META_VERSION: u8 = 1;
+const META_HEADER_LEN: usize = 8 + 1 + 4;
+
+/// Named, atomically replaced metadata blobs in a directory.
+///
+/// Names are 1 to 64 ASCII letters, digits, `-` or `_`. Values are at most
+/// [`MAX_META_LEN`] bytes. Writes are serialized, and each one is durable
+/// when it returns.
+pub struct MetaStore {
+    fs: Arc<dyn Fs>,
+    dir: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl MetaStore {
+    /// Creates a store over `dir` on the local disk.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_fs(Arc::new(LocalFs), dir)
+    }
+
+    /// Creates a store over `dir` on `fs`.
+    pub fn with_fs(fs: Arc<dyn Fs>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            fs,
+            dir: dir.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// Returns the directory holding the blobs.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Durably replaces the blob `name` with `value`.
+    ///
+    /// If this fails the blob holds either its previous value or `value`.
+    pub async fn put(&self, name: &str, value: &[u8]) -> Result<(), SegmentError> {
+        check_name(name)?;
+        if value.len() > MAX_META_LEN {
+            return Err(SegmentError::RecordTooLarge {
+                size: value.len() as u64,
+                max: MAX_META_LEN as u64,
+            });
+        }
+
+        let _guard = self.write_lock.lock().await;
+        write_meta(self.fs.as_ref(), &self.dir, name, value).await
+    }
+
+    /// Returns the blob `name`, or `None` if it has never been written.
+    pub async fn get(&self, name: &str) -> Result<Option<Bytes>, SegmentError> {
+        check_name(name)?;
+        read_meta(self.fs.as_ref(), &self.dir, name).await
+    }
+
+    /// Durably removes the blob `name`, if there is one.
+    pub async fn remove(&self, name: &str) -> Result<(), SegmentError> {
+        check_name(name)?;
+        let _guard = self.write_lock.lock().await;
+        match self.fs.remove_file(&meta_path(&self.dir, name)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+            Ok(()) => Ok(self.fs.sync_dir(&self.dir).await?),
+        }
+    }
+
+    /// Returns the names of all stored blobs, sorted.
+    pub async fn names(&self) -> Result<Vec<String>, SegmentError> {
+        list_meta(self.fs.as_ref(), &self.dir).await
+    }
+
+    /// Durably copies every blob into `dest_dir`, returning how many were
+    /// copied.
+    pub(crate) async fn copy_to(&self, dest_dir: &Path) -> Result<usize, SegmentError> {
+        let _guard = self.write_lock.lock().await;
+        let names = list_meta(self.fs.as_ref(), &self.dir).await?;
+        for name in &names {
+            if let Some(value) = read_meta(self.fs.as_ref(), &self.dir, name).await? {
+                write_meta(self.fs.as_ref(), dest_dir, name, &value).await?;
+            }
+        }
+        Ok(names.len())
+    }
+
+    /// Moves every blob into `new_dir` and makes the store use it from then on.
+    pub(crate) async fn move_to(&mut self, new_dir: &Path) -> Result<(), SegmentError> {
+        self.copy_to(new_dir).await?;
+        for name in list_meta(self.fs.as_ref(), &self.dir).await? {
+            self.fs.remove_file(&meta_path(&self.dir, &name)).await?;
+        }
+        self.fs.sync_dir(&self.dir).await?;
+        self.dir = new_dir.to_path_buf();
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for MetaStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaStore").field("dir", &self.dir).finish()
+    }
+}
+
+fn check_name(name: &str) -> Result<(), SegmentError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_META_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err(SegmentError::InvalidConfig(format!(
+            "invalid metadata name {:?}",
+            name
+        )))
+    }
+}
+
+fn meta_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, META_EXTENSION))
+}
+
+fn encode(value: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(META_HEADER_LEN + value.len() + 4);
+    buf.put_slice(META_MAGIC);
+    buf.put_u8(META_VERSION);
+    buf.put_u32_le(value.len() as u32);
+    buf.put_slice(value);
+    let crc = crc32c::crc32c(&buf);
+    buf.put_u32_le(crc);
+    buf
+}
+
+fn decode(data: &[u8]) -> Option<Bytes> {
+    if data.len() < META_HEADER_LEN + 4 || &data[..8] != META_MAGIC || data[8] != META_VERSION {
+        return None;
+    }
+    let (body, mut crc) = data.split_at(data.len() - 4);
+    if crc.get_u32_le() != crc32c::crc32c(body) {
+        return None;
+    }
+
+    let mut cursor = &body[9..];
+    let len = cursor.get_u32_le() as usize;
+    (cursor.len() == len).then(|| Bytes::copy_from_slice(cursor))
+}
+
+async fn write_meta(fs: &dyn Fs, dir: &Path, name: &str, value: &[u8]) -> Result<(), SegmentError> {
+    let path = meta_path(dir, name);
+    let temp_path = path.with_extension("meta.tmp");
+
+    fs::write_synced(fs, &temp_path, &encode(value)).await?;
+
+    fs.rename(&temp_path, &path).await?;
+    Ok(fs.sync_dir(dir).await?)
+}
+
+async fn read_meta(fs: &dyn Fs, dir: &Path, name: &str) -> Result<Option<Bytes>, SegmentError> {
+    let data = match fs::read(fs, &meta_path(dir, name)).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    decode(&data)
+        .map(Some)
+        .ok_or_else(|| SegmentError::CorruptMeta(name.to_string()))
+}
+
+async fn list_meta(fs: &dyn Fs, dir: &Path) -> Result<Vec<String>, SegmentError> {
+    let entries = match fs.read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names: Vec<String> = entries
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == META_EXTENSION))
+        .filter_map(|path| path.file_stem()?.to_str().map(str::to_string))
+        .filter(|name| check_name(name).is_ok())
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{CrashMode, SimFault, SimFs};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_put_get_remove() {
+        let dir = TempDir::new().unwrap();
+        let store = MetaStore::new(dir.path());
+
+        assert_eq!(store.get("term").await.unwrap(), None);
+        store.put("term", b"7").await.unwrap();
+        store.put("vote", b"").await.unwrap();
+        store.put("term", b"8").await.unwrap();
+
+        let store = MetaStore::new(dir.path());
+        assert_eq!(store.get("term").await.unwrap().unwrap(), &b"8"[..]);
+        assert_eq!(store.get("vote").await.unwrap().unwrap(), &b""[..]);
+        assert_eq!(store.names().await.unwrap(), vec!["term", "vote"]);
+
+        store.remove("vote").await.unwrap();
+        store.remove("vote").await.unwrap();
+        assert_eq!(store.get("vote").await.unwrap(), None);
+        assert_eq!(store.names().await.unwrap(), vec!["term"]);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_names_and_large_values() {
+        let dir = TempDir::new().unwrap();
+        let store = MetaStore::new(dir.path());
+
+        for name in ["", "../term", "a.b", &"x".repeat(MAX_META_NAME_LEN + 1)] {
+            assert!(matches!(
+                store.put(name, b"1").await,
+                Err(SegmentError::InvalidConfig(_))
+            ));
+        }
+        assert!(matches!(
+            store.put("big", &vec![0; MAX_META_LEN + 1]).await,
+            Err(SegmentError::RecordTooLarge { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_detects_corruption() {
+        let dir = TempDir::new().unwrap();
+        let store = MetaStore::new(dir.path());
+        store.put("term", b"12345").await.unwrap();
+
+        let path = meta_path(dir.path(), "term");
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 5;
+        data[last] ^= 0x01;
+        std::fs::write(&path, &data).unwrap();
+        assert!(matches!(
+            store.get("term").await,
+            Err(SegmentError::CorruptMeta(name)) if name == "term"
+        ));
+
+        // Truncated files are corrupt too, not missing
+        std::fs::write(&path, &data[..4]).unwrap();
+        assert!(matches!(
+            store.get("term").await,
+            Err(SegmentError::CorruptMeta(_))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_put_keeps_previous_value() {
+        let fs = Arc::new(SimFs::new());
+        let store = MetaStore::with_fs(fs.clone(), "/wal");
+        fs.create_dir_all(Path::new("/wal")).await.unwrap();
+        store.put("term", b"old").await.unwrap();
+
+        for fault in [SimFault::Write, SimFault::TornWrite, SimFault::Sync] {
+            fs.fail_next(fault, 1);
+            assert!(store.put("term", b"new").await.is_err());
+            fs.crash(CrashMode::Torn { seed: 7 });
+            assert_eq!(store.get("term").await.unwrap().unwrap(), &b"old"[..]);
+        }
+
+        store.put("term", b"new").await.unwrap();
+        fs.crash(CrashMode::LoseUnsynced);
+        assert_eq!(store.get("term").await.unwrap().unwrap(), &b"new"[..]);
+    }
+}
@@ -0,0 +1,498 @@
+//! Built-in statistics, returned by [`Wal::metrics`](crate::Wal::metrics).
+//!
+//! The WAL keeps a few counters and latency sketches in atomics whether or
+//! not a [`Meter`](nori_observe::Meter) is attached, so basic numbers are
+//! always available. Latencies go into log-linear buckets (eight per power of
+//! two of microseconds), which bounds the error of a reported percentile to
+//! about 12% of its value.
+//!
+//! A few gauges are also kept on the meter, updated on append, rotation,
+//! fsync and segment deletion:
+//!
+//! - `wal_size_bytes`: bytes in all segments, counting only what was written
+//!   to the active one
+//! - `wal_segments`: segment files, including the active one
+//! - `wal_seconds_since_fsync`: as of the last update, so it is only fresh
+//!   while appends come in
+//! - `wal_active_segment_fill_percent`: how close the active segment is to
+//!   rotating by size, 0 to 100 (gauges hold integers)
+//! - `wal_logical_bytes` and `wal_physical_bytes`: the totals of
+//!   [`WriteEfficiency`], as of the last append
+//! - `wal_write_amplification_percent`: physical bytes as a percentage of
+//!   logical ones, so 100 means no overhead
+
+use nori_observe::{Gauge, Meter};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Linear sub-buckets per power of two.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Enough buckets for any `u64` number of microseconds.
+const BUCKETS: usize = SUB_BUCKETS * (64 - SUB_BUCKET_BITS as usize + 1);
+
+/// Snapshot of a WAL's activity since it was opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalMetrics {
+    /// Records appended.
+    pub appends: u64,
+    /// Encoded bytes appended.
+    pub bytes_appended: u64,
+    /// Fsyncs of the active segment, including those made by the fsync
+    /// policy.
+    pub fsyncs: u64,
+    /// Segment rotations.
+    pub rotations: u64,
+    /// ID of the segment appends currently go to.
+    pub active_segment_id: u64,
+    /// Bytes written to the active segment.
+    pub active_segment_bytes: u64,
+    /// Bytes of the active segment that have not been fsynced yet.
+    pub unsynced_bytes: u64,
+    /// Latency of `append` and `append_batch` calls, including waiting for
+    /// other writers and any fsync the policy made.
+    pub append_latency: LatencySummary,
+    /// Latency of fsyncs.
+    pub fsync_latency: LatencySummary,
+    /// Payload bytes appended against bytes written to disk.
+    pub write_efficiency: WriteEfficiency,
+    /// Reads served from the [record cache](crate::record_cache).
+    pub record_cache_hits: u64,
+    /// Record cache lookups that had to read the segment instead.
+    pub record_cache_misses: u64,
+    /// Appends whose values were compressed on the blocking pool (see
+    /// [`crate::encode_pool`]).
+    pub offloaded_encodes: u64,
+    /// Puts left out of appended batches because a later put in the same
+    /// batch replaced them (see [`crate::coalesce`]).
+    pub coalesced_puts: u64,
+    /// Keys whose latest write carries a TTL, pending or run out, when
+    /// expiry is tracked (see [`crate::expiry`]).
+    pub expiring_keys: u64,
+}
+
+/// Payload bytes appended against the bytes the WAL wrote to disk for them,
+/// for working out the overhead of the log format and policies.
+///
+/// Totals run from when the WAL was opened. For the overhead over an
+/// interval, take the difference of two snapshots with
+/// [`since`](Self::since).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteEfficiency {
+    /// Key and value bytes of appended records, before compression.
+    pub logical_bytes: u64,
+    /// Bytes written to disk: encoded records with their framing, seal
+    /// sidecars, and segments copied by a migration.
+    pub physical_bytes: u64,
+}
+
+impl WriteEfficiency {
+    /// Physical bytes written per logical byte, or `None` before anything
+    /// was appended. Below 1.0 when compression saves more than the
+    /// framing costs.
+    pub fn amplification(&self) -> Option<f64> {
+        (self.logical_bytes > 0).then(|| self.physical_bytes as f64 / self.logical_bytes as f64)
+    }
+
+    /// What was written between `earlier` and this snapshot.
+    pub fn since(&self, earlier: &WriteEfficiency) -> WriteEfficiency {
+        WriteEfficiency {
+            logical_bytes: self.logical_bytes.saturating_sub(earlier.logical_bytes),
+            physical_bytes: self.physical_bytes.saturating_sub(earlier.physical_bytes),
+        }
+    }
+}
+
+/// Percentiles from a latency sketch. All zero until something is recorded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    /// Samples recorded.
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Largest sample, exact.
+    pub max: Duration,
+}
+
+/// Activity in one namespace since the WAL was opened, returned by
+/// [`Wal::namespace_metrics`](crate::Wal::namespace_metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceMetrics {
+    /// Records appended.
+    pub appends: u64,
+    /// Encoded bytes appended.
+    pub bytes_appended: u64,
+    /// Records appended that are still in the log, what the namespace's
+    /// [quota](crate::quota) is checked against.
+    pub records_in_log: u64,
+    /// Encoded bytes of those records.
+    pub bytes_in_log: u64,
+    /// Appends made while the namespace was at its quota.
+    pub quota_exceeded: u64,
+}
+
+/// Counters and sketches updated on the append path.
+#[derive(Default)]
+pub(crate) struct WalStats {
+    appends: AtomicU64,
+    bytes_appended: AtomicU64,
+    fsyncs: AtomicU64,
+    /// Duration of the latest fsync, in nanoseconds.
+    last_fsync_nanos: AtomicU64,
+    rotations: AtomicU64,
+    coalesced_puts: AtomicU64,
+    logical_bytes: AtomicU64,
+    physical_bytes: AtomicU64,
+    append_latency: LatencySketch,
+    fsync_latency: LatencySketch,
+    namespaces: Mutex<HashMap<u32, NamespaceMetrics>>,
+}
+
+impl WalStats {
+    /// Records a successful append call that wrote `records` records,
+    /// holding `logical` payload bytes in `bytes` encoded ones.
+    pub(crate) fn record_append(
+        &self,
+        records: usize,
+        logical: u64,
+        bytes: u64,
+        elapsed: Duration,
+    ) {
+        self.appends.fetch_add(records as u64, Ordering::Relaxed);
+        self.bytes_appended.fetch_add(bytes, Ordering::Relaxed);
+        self.logical_bytes.fetch_add(logical, Ordering::Relaxed);
+        self.physical_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.append_latency.record(elapsed);
+    }
+
+    /// Counts `bytes` written to disk outside of appends, such as a seal
+    /// sidecar or a copied segment.
+    pub(crate) fn record_physical(&self, bytes: u64) {
+        self.physical_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_efficiency(&self) -> WriteEfficiency {
+        WriteEfficiency {
+            logical_bytes: self.logical_bytes.load(Ordering::Relaxed),
+            physical_bytes: self.physical_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Attributes appended records, given as `(namespace, encoded bytes)`,
+    /// to their namespaces. Records without one are counted only in the
+    /// totals.
+    pub(crate) fn record_namespaces(&self, appended: impl IntoIterator<Item = (Option<u32>, u64)>) {
+        let mut namespaces = None;
+        for (namespace, bytes) in appended {
+            let Some(namespace) = namespace else {
+                continue;
+            };
+            let namespaces = namespaces.get_or_insert_with(|| self.namespaces.lock().unwrap());
+            let entry = namespaces.entry(namespace).or_default();
+            entry.appends += 1;
+            entry.bytes_appended += bytes;
+        }
+    }
+
+    pub(crate) fn namespace(&self, namespace: u32) -> NamespaceMetrics {
+        let namespaces = self.namespaces.lock().unwrap();
+        namespaces.get(&namespace).copied().unwrap_or_default()
+    }
+
+    pub(crate) fn record_fsync(&self, elapsed: Duration) {
+        self.last_fsync_nanos
+            .store(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.fsyncs.fetch_add(1, Ordering::Relaxed);
+        self.fsync_latency.record(elapsed);
+    }
+
+    /// Returns how many fsyncs have been made, which numbers the latest.
+    pub(crate) fn fsync_count(&self) -> u64 {
+        self.fsyncs.load(Ordering::Relaxed)
+    }
+
+    /// Returns how long the latest fsync took.
+    pub(crate) fn last_fsync(&self) -> Duration {
+        Duration::from_nanos(self.last_fsync_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Counts puts left out of a batch by key coalescing.
+    pub(crate) fn record_coalesced(&self, puts: u64) {
+        self.coalesced_puts.fetch_add(puts, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rotation(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Builds a snapshot, given the state of the active segment.
+    pub(crate) fn snapshot(&self, active_segment_id: u64, size: u64, synced: u64) -> WalMetrics {
+        WalMetrics {
+            appends: self.appends.load(Ordering::Relaxed),
+            bytes_appended: self.bytes_appended.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            active_segment_id,
+            active_segment_bytes: size,
+            unsynced_bytes: size.saturating_sub(synced),
+            append_latency: self.append_latency.summary(),
+            fsync_latency: self.fsync_latency.summary(),
+            write_efficiency: self.write_efficiency(),
+            record_cache_hits: 0,
+            record_cache_misses: 0,
+            offloaded_encodes: 0,
+            coalesced_puts: self.coalesced_puts.load(Ordering::Relaxed),
+            expiring_keys: 0,
+        }
+    }
+}
+
+/// The gauges listed in the [module docs](self), with what they are
+/// computed from.
+pub(crate) struct WalGauges {
+    size_bytes: Box<dyn Gauge>,
+    segments: Box<dyn Gauge>,
+    seconds_since_fsync: Box<dyn Gauge>,
+    fill_percent: Box<dyn Gauge>,
+    logical_bytes: Box<dyn Gauge>,
+    physical_bytes: Box<dyn Gauge>,
+    amplification_percent: Box<dyn Gauge>,
+    sealed_segments: AtomicU64,
+    sealed_bytes: AtomicU64,
+    active_bytes: AtomicU64,
+    started: Instant,
+    /// Milliseconds after `started` of the last fsync.
+    synced_at_ms: AtomicU64,
+}
+
+impl WalGauges {
+    pub(crate) fn new(meter: &dyn Meter) -> Self {
+        Self {
+            size_bytes: meter.gauge("wal_size_bytes", &[]),
+            segments: meter.gauge("wal_segments", &[]),
+            seconds_since_fsync: meter.gauge("wal_seconds_since_fsync", &[]),
+            fill_percent: meter.gauge("wal_active_segment_fill_percent", &[]),
+            logical_bytes: meter.gauge("wal_logical_bytes", &[]),
+            physical_bytes: meter.gauge("wal_physical_bytes", &[]),
+            amplification_percent: meter.gauge("wal_write_amplification_percent", &[]),
+            sealed_segments: AtomicU64::new(0),
+            sealed_bytes: AtomicU64::new(0),
+            active_bytes: AtomicU64::new(0),
+            started: Instant::now(),
+            synced_at_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Replaces the sealed segment totals, after scanning the directory.
+    pub(crate) fn set_sealed(&self, segments: u64, bytes: u64) {
+        self.sealed_segments.store(segments, Ordering::Relaxed);
+        self.sealed_bytes.store(bytes, Ordering::Relaxed);
+        self.publish_size();
+    }
+
+    /// Counts a segment of `bytes` sealed by rotation.
+    pub(crate) fn sealed(&self, bytes: u64) {
+        self.sealed_segments.fetch_add(1, Ordering::Relaxed);
+        self.sealed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.publish_size();
+    }
+
+    /// Uncounts a deleted sealed segment of `bytes`.
+    pub(crate) fn purged(&self, bytes: u64) {
+        saturating_sub(&self.sealed_segments, 1);
+        saturating_sub(&self.sealed_bytes, bytes);
+        self.publish_size();
+    }
+
+    /// Updates the gauges after the active segment grew or changed.
+    pub(crate) fn active(&self, size: u64, max_segment_size: u64) {
+        self.active_bytes.store(size, Ordering::Relaxed);
+        self.publish_size();
+        let percent = size.saturating_mul(100) / max_segment_size.max(1);
+        self.fill_percent.set(percent.min(100) as i64);
+        let synced_at = self.synced_at_ms.load(Ordering::Relaxed);
+        let now = self.started.elapsed().as_millis() as u64;
+        self.seconds_since_fsync
+            .set((now.saturating_sub(synced_at) / 1000) as i64);
+    }
+
+    /// Publishes the write efficiency totals.
+    pub(crate) fn efficiency(&self, efficiency: WriteEfficiency) {
+        self.logical_bytes.set(efficiency.logical_bytes as i64);
+        self.physical_bytes.set(efficiency.physical_bytes as i64);
+        if let Some(amplification) = efficiency.amplification() {
+            self.amplification_percent
+                .set((amplification * 100.0).round() as i64);
+        }
+    }
+
+    pub(crate) fn fsynced(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.synced_at_ms.store(now, Ordering::Relaxed);
+        self.seconds_since_fsync.set(0);
+    }
+
+    fn publish_size(&self) {
+        let sealed = self.sealed_bytes.load(Ordering::Relaxed);
+        let active = self.active_bytes.load(Ordering::Relaxed);
+        self.size_bytes.set((sealed + active) as i64);
+        let segments = self.sealed_segments.load(Ordering::Relaxed) + 1;
+        self.segments.set(segments as i64);
+    }
+}
+
+fn saturating_sub(counter: &AtomicU64, n: u64) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(n))
+    });
+}
+
+/// Lock-free log-linear histogram of durations in microseconds.
+struct LatencySketch {
+    buckets: Box<[AtomicU64]>,
+    count: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for LatencySketch {
+    fn default() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencySketch {
+    fn record(&self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_index(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> LatencySummary {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let count: u64 = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+        let quantile = |q: f64| {
+            let rank = ((q * count as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (i, &n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return Duration::from_micros(bucket_upper(i).min(max));
+                }
+            }
+            Duration::from_micros(max)
+        };
+        if count == 0 {
+            return LatencySummary::default();
+        }
+        LatencySummary {
+            count,
+            p50: quantile(0.50),
+            p90: quantile(0.90),
+            p99: quantile(0.99),
+            max: Duration::from_micros(max),
+        }
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros < SUB_BUCKETS as u64 {
+        return micros as usize;
+    }
+    let exp = 63 - micros.leading_zeros();
+    let sub = (micros >> (exp - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    SUB_BUCKETS * (exp - SUB_BUCKET_BITS + 1) as usize + sub
+}
+
+/// Largest value that falls in bucket `index`.
+fn bucket_upper(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exp = (index / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+    let sub = (index % SUB_BUCKETS) as u64;
+    let width = 1u64 << (exp - SUB_BUCKET_BITS);
+    ((SUB_BUCKETS as u64 + sub) << (exp - SUB_BUCKET_BITS)).saturating_add(width - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_cover_their_values() {
+        for micros in [
+            0,
+            7,
+            8,
+            9,
+            15,
+            16,
+            100,
+            1_000,
+            123_456,
+            u64::MAX / 3,
+            u64::MAX,
+        ] {
+            let i = bucket_index(micros);
+            assert!(bucket_upper(i) >= micros, "{} in bucket {}", micros, i);
+            if i > 0 {
+                assert!(bucket_upper(i - 1) < micros, "{} in bucket {}", micros, i);
+            }
+        }
+        assert_eq!(bucket_index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_write_efficiency_over_an_interval() {
+        let stats = WalStats::default();
+        assert_eq!(stats.write_efficiency().amplification(), None);
+
+        stats.record_append(1, 100, 120, Duration::ZERO);
+        let earlier = stats.write_efficiency();
+        stats.record_append(2, 200, 260, Duration::ZERO);
+        stats.record_physical(40);
+        let interval = stats.write_efficiency().since(&earlier);
+        assert_eq!(
+            interval,
+            WriteEfficiency {
+                logical_bytes: 200,
+                physical_bytes: 300,
+            }
+        );
+        assert_eq!(interval.amplification(), Some(1.5));
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let sketch = LatencySketch::default();
+        assert_eq!(sketch.summary(), LatencySummary::default());
+
+        for micros in 1..=1000 {
+            sketch.record(Duration::from_micros(micros));
+        }
+        let summary = sketch.summary();
+        assert_eq!(summary.count, 1000);
+        assert_eq!(summary.max, Duration::from_micros(1000));
+        for (p, exact) in [
+            (summary.p50, 500.0),
+            (summary.p90, 900.0),
+            (summary.p99, 990.0),
+        ] {
+            let got = p.as_micros() as f64;
+            assert!(got >= exact && got <= exact * 1.125, "{} vs {}", got, exact);
+        }
+    }
+}
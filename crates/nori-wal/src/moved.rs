@@ -0,0 +1,287 @@
+//! Forwarding markers left behind by a directory migration.
+//!
+//! A migration first stages its copies in the new directory under a
+//! `.migrating` suffix, which nothing listing segments picks up. Once
+//! [`SegmentManager::migrate_to`](crate::SegmentManager::migrate_to) has
+//! made them durable, and before it moves them into place or removes anything
+//! from the old directory, it writes a `MOVED` file naming the new directory
+//! into the old one. A crash before that leaves the old directory as it was,
+//! and a retry discards the staged files. Opening a [`Wal`](crate::Wal) at a directory holding a marker follows
+//! it, first finishing whatever the migration left undone, so the log is
+//! found both after a crash partway through the move and by a configuration
+//! that still names the old directory.
+//!
+//! The marker is a transitional shim, not a permanent redirect: once every
+//! configuration names the new directory,
+//! [`Wal::clear_migration_marker`](crate::Wal::clear_migration_marker)
+//! finishes the migration and removes it, after which the old directory can
+//! be deleted.
+//!
+//! Like a metadata blob, a damaged marker is an error
+//! ([`SegmentError::CorruptMeta`]) rather than a missing one: ignoring it
+//! would open the emptied old directory as a new, empty log.
+//!
+//! File layout (little-endian):
+//! - magic: `NORIMOVD`
+//! - version: u8
+//! - length: u32
+//! - path: `length` bytes, the UTF-8 path of the new directory
+//! - crc32c: u32 (of all preceding bytes)
+
+use crate::audit;
+use crate::checkpoint;
+use crate::fs::{self, Fs};
+use crate::meta::MetaStore;
+use crate::seal;
+use crate::segment::{list_segment_ids, segment_path, SegmentError};
+use bytes::{Buf, BufMut, BytesMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the forwarding marker inside a migrated-from directory.
+pub const MOVED_FILE: &str = "MOVED";
+
+const MOVED_MAGIC: &[u8; 8] = b"NORIMOVD";
+const MOVED_VERSION: u8 = 1;
+const MOVED_HEADER_LEN: usize = 8 + 1 + 4;
+
+/// Markers followed before giving up, so a cycle cannot hang an open.
+const MAX_HOPS: usize = 16;
+
+/// Extension added to files a migration has copied but not yet moved into place.
+const STAGED_EXTENSION: &str = "migrating";
+
+/// Encodes a marker pointing at `target`, made absolute so it does not
+/// depend on the working directory of whoever opens the WAL next.
+pub(crate) fn encode(target: &Path) -> Result<Vec<u8>, SegmentError> {
+    let target = if target.is_absolute() {
+        target.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(target)
+    };
+    let path = target.to_str().ok_or_else(|| {
+        SegmentError::InvalidConfig(format!(
+            "migration target {} is not valid UTF-8",
+            target.display()
+        ))
+    })?;
+
+    let mut buf = BytesMut::with_capacity(MOVED_HEADER_LEN + path.len() + 4);
+    buf.put_slice(MOVED_MAGIC);
+    buf.put_u8(MOVED_VERSION);
+    buf.put_u32_le(path.len() as u32);
+    buf.put_slice(path.as_bytes());
+    let crc = crc32c::crc32c(&buf);
+    buf.put_u32_le(crc);
+    Ok(buf.to_vec())
+}
+
+fn decode(data: &[u8]) -> Option<PathBuf> {
+    if data.len() < MOVED_HEADER_LEN + 4 || &data[..8] != MOVED_MAGIC || data[8] != MOVED_VERSION {
+        return None;
+    }
+    let (body, mut crc) = data.split_at(data.len() - 4);
+    if crc.get_u32_le() != crc32c::crc32c(body) {
+        return None;
+    }
+    let mut cursor = &body[9..];
+    let len = cursor.get_u32_le() as usize;
+    if cursor.len() != len {
+        return None;
+    }
+    std::str::from_utf8(cursor).ok().map(PathBuf::from)
+}
+
+fn moved_path(dir: &Path) -> PathBuf {
+    dir.join(MOVED_FILE)
+}
+
+/// Durably writes the `encoded` marker into `dir`.
+pub(crate) async fn write_moved(
+    fs: &dyn Fs,
+    dir: &Path,
+    encoded: &[u8],
+) -> Result<(), SegmentError> {
+    let path = moved_path(dir);
+    let temp_path = path.with_extension("tmp");
+
+    fs::write_synced(fs, &temp_path, encoded).await?;
+
+    fs.rename(&temp_path, &path).await?;
+    Ok(fs.sync_dir(dir).await?)
+}
+
+/// Reads the marker in `dir`, if there is one.
+pub(crate) async fn read_moved(fs: &dyn Fs, dir: &Path) -> Result<Option<PathBuf>, SegmentError> {
+    let path = moved_path(dir);
+    if !fs.exists(&path).await? {
+        return Ok(None);
+    }
+    let data = fs::read(fs, &path).await?;
+    decode(&data)
+        .map(Some)
+        .ok_or_else(|| SegmentError::CorruptMeta(MOVED_FILE.to_string()))
+}
+
+/// Removes the marker in `dir` if there is one, as when a WAL migrates back
+/// into a directory it once left.
+pub(crate) async fn remove_moved(fs: &dyn Fs, dir: &Path) -> Result<(), SegmentError> {
+    match fs.remove_file(&moved_path(dir)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        Ok(()) => Ok(fs.sync_dir(dir).await?),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Where a migration stages the file that belongs at `path`.
+pub(crate) fn staged_path(path: &Path) -> PathBuf {
+    let mut staged = path.as_os_str().to_owned();
+    staged.push(".");
+    staged.push(STAGED_EXTENSION);
+    PathBuf::from(staged)
+}
+
+async fn staged_files(fs: &dyn Fs, dir: &Path) -> Result<Vec<PathBuf>, SegmentError> {
+    Ok(fs
+        .read_dir(dir)
+        .await?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == STAGED_EXTENSION))
+        .collect())
+}
+
+/// Removes the files an interrupted migration into `dir` staged there
+/// before writing its marker.
+pub(crate) async fn discard_staged(fs: &dyn Fs, dir: &Path) -> Result<(), SegmentError> {
+    let staged = staged_files(fs, dir).await?;
+    for path in &staged {
+        fs.remove_file(path).await?;
+    }
+    if !staged.is_empty() {
+        fs.sync_dir(dir).await?;
+    }
+    Ok(())
+}
+
+/// Moves the files a migration staged in `dir` into place.
+pub(crate) async fn promote_staged(fs: &dyn Fs, dir: &Path) -> Result<(), SegmentError> {
+    let staged = staged_files(fs, dir).await?;
+    for path in &staged {
+        fs.rename(path, &path.with_extension("")).await?;
+    }
+    if !staged.is_empty() {
+        fs.sync_dir(dir).await?;
+    }
+    Ok(())
+}
+
+/// Finishes the migration recorded by the marker in `dir` and removes the
+/// marker. Returns whether there was one.
+pub(crate) async fn clear(fs: &Arc<dyn Fs>, dir: &Path) -> Result<bool, SegmentError> {
+    let Some(target) = read_moved(fs.as_ref(), dir).await? else {
+        return Ok(false);
+    };
+    if !fs.exists(&target).await? {
+        return Err(SegmentError::InvalidConfig(format!(
+            "WAL directory {} was migrated to {}, which does not exist",
+            dir.display(),
+            target.display()
+        )));
+    }
+    finish_migration(fs, dir, &target).await?;
+    remove_moved(fs.as_ref(), dir).await?;
+    Ok(true)
+}
+
+/// Returns the directory the WAL in `dir` lives in, following markers and
+/// finishing the migration each one records.
+pub(crate) async fn follow(fs: &Arc<dyn Fs>, dir: &Path) -> Result<PathBuf, SegmentError> {
+    let mut dir = dir.to_path_buf();
+    for _ in 0..MAX_HOPS {
+        let Some(target) = read_moved(fs.as_ref(), &dir).await? else {
+            return Ok(dir);
+        };
+        if !fs.exists(&target).await? {
+            return Err(SegmentError::InvalidConfig(format!(
+                "WAL directory {} was migrated to {}, which does not exist",
+                dir.display(),
+                target.display()
+            )));
+        }
+        finish_migration(fs, &dir, &target).await?;
+        dir = target;
+    }
+    Err(SegmentError::InvalidConfig(format!(
+        "more than {} migration markers to follow from {}",
+        MAX_HOPS,
+        dir.display()
+    )))
+}
+
+/// Removes what a migration from `from` to `to` left behind in `from`,
+/// moving the checkpoint, metadata blobs and audit log if they are still
+/// there. Every step is idempotent, so an interrupted run is simply redone.
+async fn finish_migration(fs: &Arc<dyn Fs>, from: &Path, to: &Path) -> Result<(), SegmentError> {
+    let fs_ref = fs.as_ref();
+    // The marker is only written once `to` holds every segment, if staged
+    promote_staged(fs_ref, to).await?;
+    for id in list_segment_ids(fs_ref, from).await? {
+        fs_ref.remove_file(&segment_path(from, id)).await?;
+        seal::remove_seal(fs_ref, from, id).await?;
+    }
+    if let Some(last) = checkpoint::read_checkpoint(fs_ref, from).await {
+        if checkpoint::read_checkpoint(fs_ref, to).await.is_none() {
+            checkpoint::write_checkpoint(fs_ref, to, &last).await?;
+        }
+        checkpoint::remove_checkpoint(fs_ref, from).await?;
+    }
+    MetaStore::with_fs(fs.clone(), from).move_to(to).await?;
+    audit::move_log(fs_ref, from, to).await?;
+    Ok(fs_ref.sync_dir(from).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::LocalFs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_moved_roundtrip_and_damage() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs: Arc<dyn Fs> = Arc::new(LocalFs);
+        let old_dir = temp_dir.path().join("old");
+        let new_dir = temp_dir.path().join("new");
+        std::fs::create_dir_all(&old_dir).unwrap();
+        std::fs::create_dir_all(&new_dir).unwrap();
+        assert_eq!(read_moved(fs.as_ref(), &old_dir).await.unwrap(), None);
+        assert_eq!(follow(&fs, &old_dir).await.unwrap(), old_dir);
+
+        let encoded = encode(&new_dir).unwrap();
+        write_moved(fs.as_ref(), &old_dir, &encoded).await.unwrap();
+        assert_eq!(
+            read_moved(fs.as_ref(), &old_dir).await.unwrap(),
+            Some(new_dir.clone())
+        );
+        assert_eq!(follow(&fs, &old_dir).await.unwrap(), new_dir);
+
+        // A cycle is reported rather than followed forever
+        write_moved(fs.as_ref(), &new_dir, &encode(&old_dir).unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(
+            follow(&fs, &old_dir).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        remove_moved(fs.as_ref(), &new_dir).await.unwrap();
+        remove_moved(fs.as_ref(), &new_dir).await.unwrap();
+
+        let mut data = encoded;
+        data[10] ^= 0x01;
+        std::fs::write(moved_path(&old_dir), &data).unwrap();
+        assert!(matches!(
+            read_moved(fs.as_ref(), &old_dir).await,
+            Err(SegmentError::CorruptMeta(_))
+        ));
+    }
+}
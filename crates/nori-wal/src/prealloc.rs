@@ -11,38 +11,38 @@
 //! - Windows: `SetFileValidData`
 //! - Fallback: `set_len()` for other platforms
 
+use std::fs::File;
 use std::io;
-use tokio::fs::File;
 
 /// Pre-allocates space for a file.
 ///
 /// This attempts to use platform-specific efficient pre-allocation APIs.
 /// Falls back to `set_len()` if platform-specific allocation fails or is unavailable.
-pub async fn preallocate(file: &File, size: u64) -> io::Result<()> {
+pub fn preallocate(file: &File, size: u64) -> io::Result<()> {
     #[cfg(target_os = "linux")]
     {
-        preallocate_linux(file, size).await
+        preallocate_linux(file, size)
     }
 
     #[cfg(target_os = "macos")]
     {
-        preallocate_macos(file, size).await
+        preallocate_macos(file, size)
     }
 
     #[cfg(target_os = "windows")]
     {
-        preallocate_windows(file, size).await
+        preallocate_windows(file, size)
     }
 
     #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
     {
-        preallocate_fallback(file, size).await
+        preallocate_fallback(file, size)
     }
 }
 
 /// Linux-specific pre-allocation using fallocate(2).
 #[cfg(target_os = "linux")]
-async fn preallocate_linux(file: &File, size: u64) -> io::Result<()> {
+fn preallocate_linux(file: &File, size: u64) -> io::Result<()> {
     use std::os::unix::io::AsRawFd;
 
     let fd = file.as_raw_fd();
@@ -62,13 +62,13 @@ async fn preallocate_linux(file: &File, size: u64) -> io::Result<()> {
         Ok(())
     } else {
         // If fallocate failed, fall back to set_len
-        preallocate_fallback(file, size).await
+        preallocate_fallback(file, size)
     }
 }
 
 /// macOS-specific pre-allocation using fcntl(2) with F_PREALLOCATE.
 #[cfg(target_os = "macos")]
-async fn preallocate_macos(file: &File, size: u64) -> io::Result<()> {
+fn preallocate_macos(file: &File, size: u64) -> io::Result<()> {
     use std::os::unix::io::AsRawFd;
 
     let fd = file.as_raw_fd();
@@ -114,42 +114,42 @@ async fn preallocate_macos(file: &File, size: u64) -> io::Result<()> {
 
         if result == -1 {
             // Fall back to set_len if fcntl failed
-            return preallocate_fallback(file, size).await;
+            return preallocate_fallback(file, size);
         }
     }
 
     // Set the file size to match the allocated space
-    file.set_len(size).await?;
+    file.set_len(size)?;
     Ok(())
 }
 
 /// Windows-specific pre-allocation.
 #[cfg(target_os = "windows")]
-async fn preallocate_windows(file: &File, size: u64) -> io::Result<()> {
+fn preallocate_windows(file: &File, size: u64) -> io::Result<()> {
     // On Windows, set_len() is reasonably efficient as NTFS supports sparse files
     // and the system will allocate on first write. For true pre-allocation,
     // we'd need SetFileValidData which requires SE_MANAGE_VOLUME_NAME privilege.
     // For simplicity and safety, we use set_len which works for most cases.
-    preallocate_fallback(file, size).await
+    preallocate_fallback(file, size)
 }
 
 /// Fallback pre-allocation using standard set_len().
 ///
 /// This works on all platforms but may be less efficient than platform-specific APIs.
 /// Some filesystems may create sparse files instead of actually allocating disk space.
-async fn preallocate_fallback(file: &File, size: u64) -> io::Result<()> {
-    file.set_len(size).await?;
+fn preallocate_fallback(file: &File, size: u64) -> io::Result<()> {
+    file.set_len(size)?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::OpenOptions;
     use tempfile::TempDir;
-    use tokio::fs::OpenOptions;
 
-    #[tokio::test]
-    async fn test_preallocate_basic() {
+    #[test]
+    fn test_preallocate_basic() {
         let temp_dir = TempDir::new().unwrap();
         let path = temp_dir.path().join("test.dat");
 
@@ -158,19 +158,18 @@ mod tests {
             .write(true)
             .read(true)
             .open(&path)
-            .await
             .unwrap();
 
         // Pre-allocate 1MB
-        preallocate(&file, 1024 * 1024).await.unwrap();
+        preallocate(&file, 1024 * 1024).unwrap();
 
         // Verify file size
-        let metadata = file.metadata().await.unwrap();
+        let metadata = file.metadata().unwrap();
         assert_eq!(metadata.len(), 1024 * 1024);
     }
 
-    #[tokio::test]
-    async fn test_preallocate_zero_size() {
+    #[test]
+    fn test_preallocate_zero_size() {
         let temp_dir = TempDir::new().unwrap();
         let path = temp_dir.path().join("zero.dat");
 
@@ -179,18 +178,17 @@ mod tests {
             .write(true)
             .read(true)
             .open(&path)
-            .await
             .unwrap();
 
         // Pre-allocate 0 bytes (should be no-op)
-        preallocate(&file, 0).await.unwrap();
+        preallocate(&file, 0).unwrap();
 
-        let metadata = file.metadata().await.unwrap();
+        let metadata = file.metadata().unwrap();
         assert_eq!(metadata.len(), 0);
     }
 
-    #[tokio::test]
-    async fn test_preallocate_large_file() {
+    #[test]
+    fn test_preallocate_large_file() {
         let temp_dir = TempDir::new().unwrap();
         let path = temp_dir.path().join("large.dat");
 
@@ -199,13 +197,12 @@ mod tests {
             .write(true)
             .read(true)
             .open(&path)
-            .await
             .unwrap();
 
         // Pre-allocate 128MB (default segment size)
-        preallocate(&file, 128 * 1024 * 1024).await.unwrap();
+        preallocate(&file, 128 * 1024 * 1024).unwrap();
 
-        let metadata = file.metadata().await.unwrap();
+        let metadata = file.metadata().unwrap();
         assert_eq!(metadata.len(), 128 * 1024 * 1024);
     }
 }
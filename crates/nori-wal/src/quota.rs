@@ -0,0 +1,342 @@
+//! Per-namespace quotas on what tenants keep in a shared log.
+//!
+//! Embedders that give each tenant its own [namespace](crate::Record::with_namespace)
+//! can cap the records and bytes a namespace has in the log with a
+//! [`NamespaceQuota`], set in [`WalConfig::namespace_quotas`] or at runtime
+//! with [`Wal::set_namespace_quota`]. What happens to an append once its
+//! namespace is at the quota is up to the [`QuotaEnforcement`]: it can fail,
+//! be slowed down, or only be counted.
+//!
+//! Usage counts the encoded records a namespace appended since the WAL was
+//! opened, for as long as the segment holding them is in the log: purging a
+//! segment or compacting it gives the space back. Records already in the log
+//! when it was opened are not counted. Usage is checked before an append and
+//! updated after it, so a namespace can go over its quota by the append that
+//! reaches it, and by appends racing with that one.
+//!
+//! Usage and quota breaches are reported through
+//! [`Wal::namespace_metrics`] and, for namespaces with a quota, on the meter:
+//!
+//! - `wal_namespace_records` and `wal_namespace_bytes`: usage, labelled with
+//!   the `namespace`
+//! - `wal_namespace_quota_exceeded_total`: appends made while the namespace
+//!   was at its quota, labelled with the `namespace` and the `enforcement`
+//!
+//! [`WalConfig::namespace_quotas`]: crate::WalConfig::namespace_quotas
+//! [`Wal::set_namespace_quota`]: crate::Wal::set_namespace_quota
+//! [`Wal::namespace_metrics`]: crate::Wal::namespace_metrics
+
+use crate::record::Record;
+use crate::segment::SegmentError;
+use nori_observe::{Counter, Gauge, Meter};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// What an append to a namespace at its quota does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuotaEnforcement {
+    /// The append goes ahead and is only counted as a breach.
+    #[default]
+    Report,
+    /// The append fails with [`SegmentError::QuotaExceeded`].
+    Reject,
+    /// The append waits this long before going ahead, slowing the namespace
+    /// down without failing its writes.
+    Throttle(Duration),
+}
+
+impl QuotaEnforcement {
+    fn label(&self) -> &'static str {
+        match self {
+            QuotaEnforcement::Report => "report",
+            QuotaEnforcement::Reject => "reject",
+            QuotaEnforcement::Throttle(_) => "throttle",
+        }
+    }
+}
+
+/// Limits on what one namespace keeps in the log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NamespaceQuota {
+    /// Records the namespace may have in the log, or `None` for no limit.
+    pub max_records: Option<u64>,
+    /// Encoded bytes the namespace may have in the log, or `None` for no
+    /// limit.
+    pub max_bytes: Option<u64>,
+    pub enforcement: QuotaEnforcement,
+}
+
+impl NamespaceQuota {
+    /// Whether a namespace with this usage is at the quota.
+    fn reached(&self, usage: Usage) -> bool {
+        self.max_records.is_some_and(|max| usage.records >= max)
+            || self.max_bytes.is_some_and(|max| usage.bytes >= max)
+    }
+}
+
+/// Records and encoded bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Usage {
+    pub(crate) records: u64,
+    pub(crate) bytes: u64,
+}
+
+/// A namespace's usage by segment, with its gauges once it has a quota.
+#[derive(Default)]
+struct NamespaceState {
+    segments: BTreeMap<u64, Usage>,
+    total: Usage,
+    breaches: u64,
+    quota: Option<NamespaceQuota>,
+    instruments: Option<Instruments>,
+}
+
+struct Instruments {
+    records: Box<dyn Gauge>,
+    bytes: Box<dyn Gauge>,
+    /// Breach counters by enforcement label, created on first use.
+    exceeded: HashMap<&'static str, Box<dyn Counter>>,
+}
+
+impl NamespaceState {
+    fn publish(&self) {
+        if let Some(instruments) = &self.instruments {
+            instruments.records.set(self.total.records as i64);
+            instruments.bytes.set(self.total.bytes as i64);
+        }
+    }
+}
+
+/// Usage of every namespace, and the quotas it is checked against.
+pub(crate) struct QuotaTracker {
+    meter: Arc<dyn Meter>,
+    namespaces: Mutex<HashMap<u32, NamespaceState>>,
+}
+
+impl QuotaTracker {
+    pub(crate) fn new(meter: Arc<dyn Meter>) -> Self {
+        Self {
+            meter,
+            namespaces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn namespaces(&self) -> std::sync::MutexGuard<'_, HashMap<u32, NamespaceState>> {
+        self.namespaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sets or, with `None`, removes the quota of `namespace`.
+    pub(crate) fn set_quota(&self, namespace: u32, quota: Option<NamespaceQuota>) {
+        let mut namespaces = self.namespaces();
+        let state = namespaces.entry(namespace).or_default();
+        state.quota = quota;
+        if quota.is_some() && state.instruments.is_none() {
+            let label = [("namespace", namespace.to_string().into())];
+            state.instruments = Some(Instruments {
+                records: self.meter.gauge_with("wal_namespace_records", &label),
+                bytes: self.meter.gauge_with("wal_namespace_bytes", &label),
+                exceeded: HashMap::new(),
+            });
+            state.publish();
+        }
+    }
+
+    /// Returns the quota of `namespace`, if it has one.
+    pub(crate) fn quota(&self, namespace: u32) -> Option<NamespaceQuota> {
+        self.namespaces()
+            .get(&namespace)
+            .and_then(|state| state.quota)
+    }
+
+    /// Checks `records` against their namespaces' quotas before they are
+    /// appended. Returns how long to throttle the append for, or the error
+    /// it is rejected with.
+    pub(crate) fn check(&self, records: &[Record]) -> Result<Option<Duration>, SegmentError> {
+        let mut namespaces = self.namespaces();
+        if namespaces.is_empty() {
+            return Ok(None);
+        }
+        let mut throttle: Option<Duration> = None;
+        let mut checked: Vec<u32> = Vec::new();
+        for namespace in records.iter().filter_map(|r| r.namespace) {
+            if checked.contains(&namespace) {
+                continue;
+            }
+            checked.push(namespace);
+            let Some(state) = namespaces.get_mut(&namespace) else {
+                continue;
+            };
+            let Some(quota) = state.quota.filter(|quota| quota.reached(state.total)) else {
+                continue;
+            };
+            state.breaches += 1;
+            self.exceeded(namespace, state, quota.enforcement);
+            match quota.enforcement {
+                QuotaEnforcement::Report => {}
+                QuotaEnforcement::Reject => {
+                    return Err(SegmentError::QuotaExceeded {
+                        namespace,
+                        records: state.total.records,
+                        bytes: state.total.bytes,
+                    })
+                }
+                QuotaEnforcement::Throttle(delay) => {
+                    throttle = Some(throttle.map_or(delay, |t| t.max(delay)));
+                }
+            }
+        }
+        Ok(throttle)
+    }
+
+    fn exceeded(&self, namespace: u32, state: &mut NamespaceState, enforcement: QuotaEnforcement) {
+        let Some(instruments) = &mut state.instruments else {
+            return;
+        };
+        instruments
+            .exceeded
+            .entry(enforcement.label())
+            .or_insert_with(|| {
+                let labels = [
+                    ("namespace", namespace.to_string().into()),
+                    ("enforcement", enforcement.label().into()),
+                ];
+                self.meter
+                    .counter_with("wal_namespace_quota_exceeded_total", &labels)
+            })
+            .inc(1);
+    }
+
+    /// Counts appended records, given as `(namespace, encoded bytes)`, in
+    /// segment `segment_id`.
+    pub(crate) fn record(
+        &self,
+        segment_id: u64,
+        appended: impl IntoIterator<Item = (Option<u32>, u64)>,
+    ) {
+        let mut namespaces = None;
+        for (namespace, bytes) in appended {
+            let Some(namespace) = namespace else {
+                continue;
+            };
+            let namespaces = namespaces.get_or_insert_with(|| self.namespaces());
+            let state = namespaces.entry(namespace).or_default();
+            let segment = state.segments.entry(segment_id).or_default();
+            segment.records += 1;
+            segment.bytes += bytes;
+            state.total.records += 1;
+            state.total.bytes += bytes;
+            state.publish();
+        }
+    }
+
+    /// Replaces what segment `segment_id` holds, after compaction rewrote
+    /// it with `kept`, given as `(namespace, encoded bytes)`.
+    pub(crate) fn replace_segment(
+        &self,
+        segment_id: u64,
+        kept: impl IntoIterator<Item = (Option<u32>, u64)>,
+    ) {
+        self.release_segment(segment_id);
+        self.record(segment_id, kept);
+    }
+
+    /// Stops counting what segment `segment_id` held, once it is deleted.
+    pub(crate) fn release_segment(&self, segment_id: u64) {
+        for state in self.namespaces().values_mut() {
+            if let Some(usage) = state.segments.remove(&segment_id) {
+                state.total.records -= usage.records;
+                state.total.bytes -= usage.bytes;
+                state.publish();
+            }
+        }
+    }
+
+    /// Returns the usage of `namespace`, and how many appends found it at
+    /// its quota.
+    pub(crate) fn usage(&self, namespace: u32) -> (Usage, u64) {
+        self.namespaces()
+            .get(&namespace)
+            .map(|state| (state.total, state.breaches))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::NoopMeter;
+
+    fn quota(max_records: u64, enforcement: QuotaEnforcement) -> NamespaceQuota {
+        NamespaceQuota {
+            max_records: Some(max_records),
+            max_bytes: None,
+            enforcement,
+        }
+    }
+
+    #[test]
+    fn test_usage_follows_segments() {
+        let tracker = QuotaTracker::new(Arc::new(NoopMeter));
+        tracker.record(0, [(Some(1), 100), (Some(2), 10), (None, 5)]);
+        tracker.record(1, [(Some(1), 50)]);
+        let (usage, _) = tracker.usage(1);
+        assert_eq!(
+            usage,
+            Usage {
+                records: 2,
+                bytes: 150
+            }
+        );
+
+        tracker.replace_segment(1, []);
+        assert_eq!(
+            tracker.usage(1).0,
+            Usage {
+                records: 1,
+                bytes: 100
+            }
+        );
+        tracker.release_segment(0);
+        assert_eq!(tracker.usage(1).0, Usage::default());
+        assert_eq!(tracker.usage(2).0, Usage::default());
+    }
+
+    #[test]
+    fn test_enforcement() {
+        let tracker = QuotaTracker::new(Arc::new(NoopMeter));
+        let record = |namespace| Record::put("k", "v").with_namespace(namespace);
+        tracker.set_quota(1, Some(quota(1, QuotaEnforcement::Reject)));
+        let delay = Duration::from_millis(10);
+        tracker.set_quota(2, Some(quota(1, QuotaEnforcement::Throttle(delay))));
+        tracker.set_quota(3, Some(quota(1, QuotaEnforcement::Report)));
+
+        // Under quota, nothing happens
+        let all = [record(1), record(2), record(3)];
+        assert_eq!(tracker.check(&all).unwrap(), None);
+        tracker.record(0, [(Some(1), 10), (Some(2), 10), (Some(3), 10)]);
+
+        assert!(matches!(
+            tracker.check(&[record(1)]),
+            Err(SegmentError::QuotaExceeded {
+                namespace: 1,
+                records: 1,
+                ..
+            })
+        ));
+        assert_eq!(tracker.check(&[record(2), record(2)]).unwrap(), Some(delay));
+        assert_eq!(tracker.check(&[record(3)]).unwrap(), None);
+        assert_eq!(tracker.check(&[record(4)]).unwrap(), None);
+        assert_eq!(tracker.usage(1).1, 1);
+        assert_eq!(tracker.usage(2).1, 1);
+        assert_eq!(tracker.usage(3).1, 1);
+
+        // Purging gives the space back
+        tracker.release_segment(0);
+        assert_eq!(tracker.check(&[record(1)]).unwrap(), None);
+        tracker.set_quota(1, None);
+        assert_eq!(tracker.quota(1), None);
+    }
+}
@@ -0,0 +1,515 @@
+//! Log-wide reader that follows records across segment boundaries.
+
+use crate::record::{Record, RecordHeader};
+use crate::segment::{
+    Position, ReaderConfig, RecordFilter, SegmentError, SegmentManager, SegmentReader,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::SystemTime;
+use tokio::sync::futures::OwnedNotified;
+use tokio::sync::Notify;
+
+/// Version byte leading an encoded [`Cursor`].
+const CURSOR_VERSION: u8 = 1;
+const CURSOR_LEN: usize = 1 + 8 + 8;
+
+/// A reader's progress through the log, for persisting across restarts.
+///
+/// Export one with [`WalReader::cursor`] or [`WalTail::cursor`], store it as
+/// bytes (or through serde with the `serde` feature), and resume with
+/// [`Wal::resume_reader`](crate::Wal::resume_reader) or
+/// [`Wal::resume_tail`](crate::Wal::resume_tail). Resuming fails with
+/// [`SegmentError::CursorGone`] if the log no longer reaches the cursor,
+/// because its segment was purged or the log was truncated below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cursor {
+    position: Position,
+}
+
+impl Cursor {
+    /// Encodes the cursor into a compact, versioned byte string.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(CURSOR_LEN);
+        buf.put_u8(CURSOR_VERSION);
+        buf.put_u64_le(self.position.segment_id);
+        buf.put_u64_le(self.position.offset);
+        buf.freeze()
+    }
+
+    /// Decodes a cursor produced by [`Cursor::to_bytes`], or returns `None`
+    /// if `data` is not one.
+    pub fn from_bytes(mut data: &[u8]) -> Option<Self> {
+        if data.len() != CURSOR_LEN || data.get_u8() != CURSOR_VERSION {
+            return None;
+        }
+        Some(Self {
+            position: Position {
+                segment_id: data.get_u64_le(),
+                offset: data.get_u64_le(),
+            },
+        })
+    }
+
+    /// Returns the position of the next record the cursor will read.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+}
+
+/// Reads records in log order from a starting position to the durable end
+/// of the log, moving into each following segment as the previous one ends.
+///
+/// Only records below `durable_position()` are returned, so a reader never
+/// observes data that a crash could still take back. Once it has caught up,
+/// `next_record` returns `None`; calling it again later picks up records that
+/// have become durable since.
+///
+/// If the next segment is missing, for example because it was purged,
+/// `next_record` fails with [`SegmentError::Gap`] and the reader moves on to
+/// the next segment that does exist, so the caller may choose to continue.
+///
+/// `WalReader` is also a [`Stream`] of the same items. The stream ends when
+/// the reader catches up; polling it again later resumes from there.
+pub struct WalReader {
+    manager: Arc<SegmentManager>,
+    position: Position,
+    config: ReaderConfig,
+    segment: Option<SegmentReader>,
+    /// Durable offset the open segment reader was bounded at, if any.
+    limit: Option<u64>,
+    /// Lookup of the durable end (and the segment to read) in flight.
+    step: Option<StepFuture>,
+    /// Durable end of the log for the read in progress.
+    durable: Option<Position>,
+    key_filter: Option<KeyFilter>,
+    /// Append-time window records must fall in, as `[since, until)`.
+    window: Option<(SystemTime, SystemTime)>,
+    /// Namespace records must belong to.
+    namespace: Option<u32>,
+    /// Key filter, window and namespace combined, as handed to segment
+    /// readers.
+    filter: Option<RecordFilter>,
+    /// Position the reader stops at even if the log goes on.
+    end: Option<Position>,
+}
+
+/// Predicate on record keys, set with [`WalReader::with_key_filter`].
+type KeyFilter = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Outcome of looking up where the next record comes from.
+enum Step {
+    /// The reader has caught up with the durable end of the log.
+    CaughtUp,
+    /// The open segment reader is still good up to this durable end.
+    Continue(Position),
+    /// A segment reader was opened at the reader's position.
+    Opened {
+        durable: Position,
+        segment: SegmentReader,
+        limit: Option<u64>,
+    },
+    /// The segment at the reader's position does not exist.
+    Gap {
+        missing: u64,
+        resume_at: Option<u64>,
+    },
+}
+
+type StepFuture = Pin<Box<dyn Future<Output = Result<Step, SegmentError>> + Send>>;
+
+impl WalReader {
+    pub(crate) fn new(
+        manager: Arc<SegmentManager>,
+        position: Position,
+        config: ReaderConfig,
+    ) -> Self {
+        Self {
+            manager,
+            position,
+            config,
+            segment: None,
+            limit: None,
+            step: None,
+            durable: None,
+            key_filter: None,
+            window: None,
+            namespace: None,
+            filter: None,
+            end: None,
+        }
+    }
+
+    /// Rebuilds the combined filter after the key filter, window or namespace
+    /// changed, applying it to the segment already being read too.
+    fn refresh_filter(&mut self) {
+        let key_filter = self.key_filter.clone();
+        let window = self.window;
+        let namespace = self.namespace;
+        self.filter =
+            (key_filter.is_some() || window.is_some() || namespace.is_some()).then(|| {
+                Arc::new(move |header: &RecordHeader<'_>| {
+                    namespace.map_or(true, |namespace| header.namespace == Some(namespace))
+                        && key_filter
+                            .as_ref()
+                            .map_or(true, |filter| filter(header.key))
+                        && window.map_or(true, |(since, until)| {
+                            header
+                                .timestamp
+                                .is_some_and(|timestamp| since <= timestamp && timestamp < until)
+                        })
+                }) as RecordFilter
+            });
+        if let Some(segment) = self.segment.as_mut() {
+            segment.set_filter(self.filter.clone());
+        }
+    }
+
+    /// Only returns records whose key satisfies `predicate`, replacing any
+    /// earlier filter. Records that fail it are checksummed and stepped over
+    /// without decoding their values.
+    pub fn with_key_filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.key_filter = Some(Arc::new(predicate));
+        self.refresh_filter();
+        self
+    }
+
+    /// Only returns records whose key starts with `prefix`, replacing any
+    /// earlier filter.
+    pub fn with_key_prefix(self, prefix: impl Into<Bytes>) -> Self {
+        let prefix = prefix.into();
+        self.with_key_filter(move |key| key.starts_with(&prefix))
+    }
+
+    /// Only returns records appended at or after `since` and before `until`,
+    /// on top of any key filter. Records without a timestamp are skipped.
+    pub fn with_time_range(mut self, since: SystemTime, until: SystemTime) -> Self {
+        self.window = Some((since, until));
+        self.refresh_filter();
+        self
+    }
+
+    /// Only returns records in `namespace`, on top of any key filter. Other
+    /// records are stepped over without decoding their values.
+    pub fn with_namespace(mut self, namespace: u32) -> Self {
+        self.namespace = Some(namespace);
+        self.refresh_filter();
+        self
+    }
+
+    /// Stops the reader at `end`, which must be a record boundary: it behaves
+    /// as if the durable end of the log were no later than `end`.
+    pub fn until(mut self, end: Position) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    /// Returns the position of the next record to be read.
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Returns a cursor to resume reading from where this reader is.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            position: self.position,
+        }
+    }
+
+    /// Reads the next durable record, or `None` at the durable end of the log.
+    ///
+    /// Cancelling the returned future neither skips nor repeats a record.
+    pub async fn next_record(&mut self) -> Result<Option<(Record, Position)>, SegmentError> {
+        std::future::poll_fn(|cx| self.poll_next_record(cx)).await
+    }
+
+    fn poll_next_record(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(Record, Position)>, SegmentError>> {
+        loop {
+            if self.segment.is_none() && self.step.is_none() {
+                if let Some(next) = self.next_cached() {
+                    return Poll::Ready(Ok(Some(next)));
+                }
+            }
+            let durable = match self.durable {
+                Some(durable) => durable,
+                None => {
+                    if self.step.is_none() {
+                        self.step = Some(self.start_step());
+                    }
+                    let step = ready!(self
+                        .step
+                        .as_mut()
+                        .expect("step was just started")
+                        .as_mut()
+                        .poll(cx));
+                    self.step = None;
+                    match step? {
+                        Step::CaughtUp => return Poll::Ready(Ok(None)),
+                        Step::Continue(durable) => durable,
+                        Step::Opened {
+                            durable,
+                            mut segment,
+                            limit,
+                        } => {
+                            segment.set_filter(self.filter.clone());
+                            self.segment = Some(segment);
+                            self.limit = limit;
+                            durable
+                        }
+                        Step::Gap { missing, resume_at } => {
+                            if let Some(segment_id) = resume_at {
+                                self.position = Position {
+                                    segment_id,
+                                    offset: 0,
+                                };
+                            }
+                            return Poll::Ready(Err(SegmentError::Gap { missing, resume_at }));
+                        }
+                    }
+                }
+            };
+            self.durable = Some(durable);
+
+            let segment = self
+                .segment
+                .as_mut()
+                .expect("segment reader was just opened");
+            let next = ready!(segment.poll_next_record(cx));
+            self.durable = None;
+
+            // Also moves past records the key filter skipped
+            let next = next?;
+            self.position = segment.position();
+            if let Some((record, position)) = next {
+                self.manager
+                    .record_cache()
+                    .insert(position, &record, self.position);
+                return Poll::Ready(Ok(Some((record, position))));
+            }
+            self.segment = None;
+
+            if self.position.segment_id == durable.segment_id {
+                // Caught up with the durable end of the active segment
+                return Poll::Ready(Ok(None));
+            }
+            self.position = Position {
+                segment_id: self.position.segment_id + 1,
+                offset: 0,
+            };
+        }
+    }
+
+    /// Returns the records at the reader's position from the record cache,
+    /// stepping over those the filters reject, until one is missing.
+    fn next_cached(&mut self) -> Option<(Record, Position)> {
+        loop {
+            if self.end.is_some_and(|end| self.position >= end) {
+                return None;
+            }
+            let position = self.position;
+            let (record, next) = self.manager.record_cache().get(position)?;
+            self.position = next;
+            let header = RecordHeader {
+                key: &record.key,
+                tombstone: record.tombstone,
+                lsn: record.lsn,
+                timestamp: record.timestamp,
+                namespace: record.namespace,
+            };
+            if self.filter.as_ref().map_or(true, |filter| filter(&header)) {
+                return Some((record, position));
+            }
+        }
+    }
+
+    /// Starts looking up the durable end of the log and, unless the open
+    /// segment reader can carry on, opening the segment at the reader's
+    /// position.
+    fn start_step(&self) -> StepFuture {
+        let manager = Arc::clone(&self.manager);
+        let position = self.position;
+        let config = self.config;
+        let open_limit = self.segment.as_ref().map(|_| self.limit);
+        let end = self.end;
+
+        Box::pin(async move {
+            let mut durable = manager.durable_position().await;
+            if let Some(end) = end {
+                durable = durable.min(end);
+            }
+            if position >= durable {
+                return Ok(Step::CaughtUp);
+            }
+
+            // A reader bounded at an older durable offset would stop short
+            let limit = (position.segment_id == durable.segment_id).then_some(durable.offset);
+            if open_limit == Some(limit) {
+                return Ok(Step::Continue(durable));
+            }
+            match manager.read_from_with(position, config).await {
+                Ok(mut segment) => {
+                    if let Some(end) = limit {
+                        segment.limit_to(end);
+                    }
+                    Ok(Step::Opened {
+                        durable,
+                        segment,
+                        limit,
+                    })
+                }
+                Err(SegmentError::NotFound(missing) | SegmentError::Purged(missing)) => {
+                    Ok(Step::Gap {
+                        missing,
+                        resume_at: manager.next_segment_id_after(missing).await?,
+                    })
+                }
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+impl Stream for WalReader {
+    type Item = Result<(Record, Position), SegmentError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_record(cx).map(Result::transpose)
+    }
+}
+
+/// Follows the log like `tail -f`: returns the durable records from a
+/// starting position, then waits for new ones as they become durable.
+///
+/// A tail never runs out of records; drop it to stop following. Like
+/// [`WalReader`], it reports a missing segment with [`SegmentError::Gap`]
+/// and can carry on past it. It is also a [`Stream`] that never ends.
+pub struct WalTail {
+    reader: WalReader,
+    notify: Arc<Notify>,
+    /// Registered before each read attempt, so data made durable while the
+    /// reader is looking still wakes the tail once it has caught up.
+    wakeup: Option<Pin<Box<OwnedNotified>>>,
+    caught_up: bool,
+}
+
+impl WalTail {
+    pub(crate) fn new(manager: Arc<SegmentManager>, position: Position) -> Self {
+        let notify = manager.durable_notify();
+        Self {
+            reader: WalReader::new(manager, position, ReaderConfig::default()),
+            notify,
+            wakeup: None,
+            caught_up: false,
+        }
+    }
+
+    /// Only returns records whose key satisfies `predicate`, replacing any
+    /// earlier filter.
+    ///
+    /// Other records are skipped as they are read: their checksums are
+    /// verified, but their values are never copied or decompressed.
+    pub fn with_key_filter<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.reader = self.reader.with_key_filter(predicate);
+        self
+    }
+
+    /// Only returns records whose key starts with `prefix`, replacing any
+    /// earlier filter.
+    pub fn with_key_prefix(self, prefix: impl Into<Bytes>) -> Self {
+        let prefix = prefix.into();
+        self.with_key_filter(move |key| key.starts_with(&prefix))
+    }
+
+    /// Only returns records in `namespace`, on top of any key filter.
+    pub fn with_namespace(mut self, namespace: u32) -> Self {
+        self.reader = self.reader.with_namespace(namespace);
+        self
+    }
+
+    /// Returns the position of the next record to be read.
+    pub fn position(&self) -> Position {
+        self.reader.position()
+    }
+
+    /// Returns a cursor to resume following from where this tail is.
+    pub fn cursor(&self) -> Cursor {
+        self.reader.cursor()
+    }
+
+    /// Returns the next durable record, waiting for one to be appended if the
+    /// tail has caught up.
+    ///
+    /// Cancelling the returned future neither skips nor repeats a record.
+    pub async fn next_record(&mut self) -> Result<(Record, Position), SegmentError> {
+        std::future::poll_fn(|cx| self.poll_next_record(cx)).await
+    }
+
+    fn poll_next_record(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(Record, Position), SegmentError>> {
+        loop {
+            if self.caught_up {
+                let wakeup = self.wakeup.as_mut().expect("caught up without a wakeup");
+                ready!(wakeup.as_mut().poll(cx));
+                self.caught_up = false;
+                self.wakeup = None;
+            }
+            if self.wakeup.is_none() {
+                self.wakeup = Some(Box::pin(Arc::clone(&self.notify).notified_owned()));
+            }
+
+            match ready!(self.reader.poll_next_record(cx)) {
+                Ok(Some(next)) => return Poll::Ready(Ok(next)),
+                Ok(None) => self.caught_up = true,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl Stream for WalTail {
+    type Item = Result<(Record, Position), SegmentError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_record(cx).map(Some)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_serde_roundtrip() {
+        let position = Position {
+            segment_id: 3,
+            offset: 4096,
+        };
+        let json = serde_json::to_string(&position).unwrap();
+        assert_eq!(json, r#"{"segment_id":3,"offset":4096}"#);
+        assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), position);
+        let from_text: Position = serde_json::from_str(r#""000003:4096""#).unwrap();
+        assert_eq!(from_text, position);
+        assert!(serde_json::from_str::<Position>(r#""3-4096""#).is_err());
+
+        let cursor = Cursor { position };
+        let json = serde_json::to_string(&cursor).unwrap();
+        assert_eq!(serde_json::from_str::<Cursor>(&json).unwrap(), cursor);
+        assert_eq!(Cursor::from_bytes(&cursor.to_bytes()), Some(cursor));
+    }
+}
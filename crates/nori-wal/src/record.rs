@@ -3,17 +3,25 @@
 //! Record format:
 //! - klen: varint
 //! - vlen: varint
-//! - flags: u8 (bits: 0=tombstone, 1=ttl_present, 2-3=compression, 4-7=reserved)
+//! - flags: u8 (bits: 0=tombstone, 1=ttl_present, 2-3=compression, 4=extensions, 5-7=reserved)
 //! - ttl_ms?: varint (if ttl_present bit set)
+//! - extensions?: varint length, then entries of (tag: u8, len: varint, bytes[len])
+//!   (if extensions bit set; unknown tags are skipped)
 //! - key: bytes[klen]
 //! - value: bytes[vlen]
 //! - crc32c: u32 (little-endian)
+//!
+//! The codec itself lives in the `no_std` `nori-wal-format` crate; this
+//! module adds owned buffers, compression and `std::time` types on top.
 
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
+use nori_wal_format::{varint, FormatError, RecordRef};
 use std::io::{self, ErrorKind};
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+pub use nori_wal_format::{Compression, TraceContext};
+
 #[derive(Debug, Error)]
 pub enum RecordError {
     #[error("I/O error: {0}")]
@@ -30,45 +38,112 @@ pub enum RecordError {
     Incomplete,
 }
 
-/// Compression type for record values.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Compression {
-    None = 0,
-    Lz4 = 1,
-    Zstd = 2,
-}
-
-impl Compression {
-    fn from_bits(bits: u8) -> Result<Self, RecordError> {
-        match bits {
-            0 => Ok(Compression::None),
-            1 => Ok(Compression::Lz4),
-            2 => Ok(Compression::Zstd),
-            v => Err(RecordError::InvalidCompression(v)),
+impl From<FormatError> for RecordError {
+    fn from(e: FormatError) -> Self {
+        match e {
+            FormatError::Incomplete => RecordError::Incomplete,
+            FormatError::CrcMismatch { expected, actual } => {
+                RecordError::CrcMismatch { expected, actual }
+            }
+            FormatError::InvalidCompression(bits) => RecordError::InvalidCompression(bits),
+            e @ (FormatError::VarintOverflow | FormatError::BufferTooSmall { .. }) => {
+                io::Error::new(ErrorKind::InvalidData, e.to_string()).into()
+            }
         }
     }
-
-    fn to_bits(self) -> u8 {
-        self as u8
-    }
 }
 
-bitflags::bitflags! {
-    struct Flags: u8 {
-        const TOMBSTONE = 0b0000_0001;
-        const TTL_PRESENT = 0b0000_0010;
-        const COMPRESSION_MASK = 0b0000_1100;
-    }
+/// How urgently an appended record must reach disk.
+///
+/// Lets control-plane records that must not be lost share a log with
+/// data-plane records that can wait for the next batch fsync. The class is
+/// an instruction to the append and is not stored in the log, so decoded
+/// records are always [`BestEffort`](Durability::BestEffort).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Durability {
+    /// Follows the WAL's fsync policy.
+    #[default]
+    BestEffort,
+    /// Fsyncs before the append returns, together with anything still
+    /// waiting for a batch fsync, whatever the fsync policy.
+    Critical,
 }
 
 /// A WAL record representing a key-value operation.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Equality ignores [`durability`](Record::durability), which only affects
+/// the append, so a record read back compares equal to the one appended.
+#[derive(Debug, Clone)]
 pub struct Record {
     pub key: Bytes,
     pub value: Bytes,
     pub tombstone: bool,
     pub ttl: Option<Duration>,
     pub compression: Compression,
+    /// Log sequence number, assigned by the WAL on append.
+    pub lsn: Option<u64>,
+    /// Wall-clock time of the append, stored with millisecond precision.
+    pub timestamp: Option<SystemTime>,
+    /// Logical stream the record belongs to, for WALs shared by several
+    /// (None is the default stream).
+    pub namespace: Option<u32>,
+    /// SHA-256 of the record before this one, in a log kept as a hash chain
+    /// (see [`crate::chain`]). Assigned by the WAL on append.
+    pub chain: Option<[u8; nori_wal_format::CHAIN_LINK_LEN]>,
+    /// Trace context of the request that appended the record, which spans
+    /// for the append are children of (see [`crate::trace`]).
+    pub trace: Option<TraceContext>,
+    /// Earlier puts to the same key in the batch that this record replaced,
+    /// when the WAL coalesces keys (see [`crate::coalesce`]). Assigned by
+    /// the WAL on append.
+    pub coalesced: Option<u64>,
+    /// How urgently the append must reach disk. Not stored in the log, so
+    /// decoded records are always best-effort, and ignored by equality.
+    pub durability: Durability,
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        // Destructured so that a new field has to be considered here
+        let Record {
+            key,
+            value,
+            tombstone,
+            ttl,
+            compression,
+            lsn,
+            timestamp,
+            namespace,
+            chain,
+            trace,
+            coalesced,
+            durability: _,
+        } = self;
+        *key == other.key
+            && *value == other.value
+            && *tombstone == other.tombstone
+            && *ttl == other.ttl
+            && *compression == other.compression
+            && *lsn == other.lsn
+            && *timestamp == other.timestamp
+            && *namespace == other.namespace
+            && *chain == other.chain
+            && *trace == other.trace
+            && *coalesced == other.coalesced
+    }
+}
+
+impl Eq for Record {}
+
+/// The parts of an encoded record that can be read without decoding its
+/// value, returned by [`Record::peek_header`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordHeader<'a> {
+    pub key: &'a [u8],
+    pub tombstone: bool,
+    pub lsn: Option<u64>,
+    pub timestamp: Option<SystemTime>,
+    pub namespace: Option<u32>,
 }
 
 impl Record {
@@ -80,6 +155,13 @@ impl Record {
             tombstone: false,
             ttl: None,
             compression: Compression::None,
+            lsn: None,
+            timestamp: None,
+            namespace: None,
+            chain: None,
+            trace: None,
+            coalesced: None,
+            durability: Durability::BestEffort,
         }
     }
 
@@ -91,6 +173,13 @@ impl Record {
             tombstone: false,
             ttl: Some(ttl),
             compression: Compression::None,
+            lsn: None,
+            timestamp: None,
+            namespace: None,
+            chain: None,
+            trace: None,
+            coalesced: None,
+            durability: Durability::BestEffort,
         }
     }
 
@@ -102,6 +191,13 @@ impl Record {
             tombstone: true,
             ttl: None,
             compression: Compression::None,
+            lsn: None,
+            timestamp: None,
+            namespace: None,
+            chain: None,
+            trace: None,
+            coalesced: None,
+            durability: Durability::BestEffort,
         }
     }
 
@@ -111,12 +207,47 @@ impl Record {
         self
     }
 
+    /// Sets the log sequence number for this record.
+    pub fn with_lsn(mut self, lsn: u64) -> Self {
+        self.lsn = Some(lsn);
+        self
+    }
+
+    /// Sets the timestamp for this record.
+    pub fn with_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Puts the record in namespace `namespace`.
+    pub fn with_namespace(mut self, namespace: u32) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// Attaches the trace context of the request making the append.
+    pub fn with_trace(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Sets how urgently the append must reach disk.
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
     /// Encodes the record into bytes with CRC32C checksum.
+    ///
+    /// The layout is defined by `nori-wal-format`, which can also encode and
+    /// decode records without this crate.
     pub fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::new();
+        self.encode_compressed(&self.compressed_value())
+    }
 
-        // Compress value if needed
-        let value_to_write = match self.compression {
+    /// The value as written: compressed with the record's compression, if any.
+    pub(crate) fn compressed_value(&self) -> Bytes {
+        match self.compression {
             Compression::None => self.value.clone(),
             Compression::Lz4 => {
                 // Prepend original size for decompression
@@ -129,142 +260,91 @@ impl Record {
             Compression::Zstd => {
                 Bytes::from(zstd::encode_all(&self.value[..], 3).unwrap_or_else(|_| self.value.to_vec()))
             }
-        };
-
-        // Encode klen and vlen as varints (vlen is compressed size)
-        encode_varint(&mut buf, self.key.len() as u64);
-        encode_varint(&mut buf, value_to_write.len() as u64);
-
-        // Encode flags
-        let mut flags = Flags::empty();
-        if self.tombstone {
-            flags |= Flags::TOMBSTONE;
-        }
-        if self.ttl.is_some() {
-            flags |= Flags::TTL_PRESENT;
         }
-        let compression_bits = (self.compression.to_bits() & 0b11) << 2;
-        buf.put_u8(flags.bits() | compression_bits);
-
-        // Encode TTL if present
-        if let Some(ttl) = self.ttl {
-            encode_varint(&mut buf, ttl.as_millis() as u64);
-        }
-
-        // Encode key and value (value is already compressed if needed)
-        buf.put_slice(&self.key);
-        buf.put_slice(&value_to_write);
-
-        // Calculate and append CRC32C
-        let crc = crc32c::crc32c(&buf);
-        buf.put_u32_le(crc);
+    }
 
-        buf.freeze()
+    /// Encodes the record with `value`, its value as returned by
+    /// [`compressed_value`](Self::compressed_value).
+    pub(crate) fn encode_compressed(&self, value: &Bytes) -> Bytes {
+        let record = RecordRef {
+            key: &self.key,
+            value,
+            tombstone: self.tombstone,
+            ttl_ms: self.ttl.map(|ttl| ttl.as_millis() as u64),
+            compression: self.compression,
+            lsn: self.lsn,
+            timestamp_ms: self.timestamp.map(timestamp_millis),
+            namespace: self.namespace,
+            chain: self.chain,
+            trace: self.trace,
+            coalesced: self.coalesced,
+        };
+        let mut buf = Vec::with_capacity(record.encoded_len());
+        record.encode_to_vec(&mut buf);
+        Bytes::from(buf)
     }
 
     /// Decodes a record from bytes, validating the CRC32C checksum.
     pub fn decode(data: &[u8]) -> Result<(Self, usize), RecordError> {
-        if data.len() < 6 {
-            return Err(RecordError::Incomplete);
-        }
-
-        let mut cursor = data;
-
-        // Parse header: lengths, flags, and optional TTL
-        let klen = decode_varint(&mut cursor)?;
-        let vlen = decode_varint(&mut cursor)?;
-        let (tombstone, ttl, compression) = Self::decode_flags_and_ttl(&mut cursor)?;
-
-        // Extract key and compressed value
-        let key = Self::extract_bytes(&mut cursor, klen)?;
-        let compressed_value = Self::extract_bytes(&mut cursor, vlen)?;
-
-        // Verify CRC
-        let bytes_consumed = data.len() - cursor.len() + 4;
-        Self::verify_crc(data, bytes_consumed, &mut cursor)?;
-
-        // Decompress value
-        let value = Self::decompress_value(compressed_value, compression)?;
+        let (record, bytes_consumed) = RecordRef::decode(data)?;
+        let value = Self::decompress_value(record.value, record.compression)?;
 
         Ok((
             Record {
-                key,
+                key: Bytes::copy_from_slice(record.key),
                 value,
-                tombstone,
-                ttl,
-                compression,
+                tombstone: record.tombstone,
+                ttl: record.ttl_ms.map(Duration::from_millis),
+                compression: record.compression,
+                lsn: record.lsn,
+                timestamp: record.timestamp_ms.map(from_millis),
+                namespace: record.namespace,
+                chain: record.chain,
+                trace: record.trace,
+                coalesced: record.coalesced,
+                durability: Durability::BestEffort,
             },
             bytes_consumed,
         ))
     }
 
-    fn decode_flags_and_ttl(
-        cursor: &mut &[u8],
-    ) -> Result<(bool, Option<Duration>, Compression), RecordError> {
-        if cursor.is_empty() {
-            return Err(RecordError::Incomplete);
-        }
-
-        let flags_byte = cursor[0];
-        cursor.advance(1);
-
-        let flags = Flags::from_bits_truncate(flags_byte);
-        let tombstone = flags.contains(Flags::TOMBSTONE);
-        let compression_bits = (flags_byte & 0b0000_1100) >> 2;
-        let compression = Compression::from_bits(compression_bits)?;
-
-        let ttl = if flags.contains(Flags::TTL_PRESENT) {
-            let ttl_ms = decode_varint(cursor)?;
-            Some(Duration::from_millis(ttl_ms))
-        } else {
-            None
-        };
-
-        Ok((tombstone, ttl, compression))
+    /// Reads only the key of the record encoded at the start of `data`,
+    /// returning it with the record's encoded size.
+    ///
+    /// The checksum is still verified, but the value is neither copied nor
+    /// decompressed, so callers that only route or filter on keys can skip
+    /// records far more cheaply than with [`Record::decode`].
+    pub fn peek_key(data: &[u8]) -> Result<(&[u8], usize), RecordError> {
+        let (header, size) = Self::peek_header(data)?;
+        Ok((header.key, size))
     }
 
-    fn extract_bytes(cursor: &mut &[u8], len: u64) -> Result<Bytes, RecordError> {
-        let len = len as usize;
-        if cursor.len() < len {
-            return Err(RecordError::Incomplete);
-        }
-
-        let bytes = Bytes::copy_from_slice(&cursor[..len]);
-        cursor.advance(len);
-        Ok(bytes)
-    }
-
-    fn verify_crc(data: &[u8], bytes_consumed: usize, cursor: &mut &[u8]) -> Result<(), RecordError> {
-        if cursor.len() < 4 {
-            return Err(RecordError::Incomplete);
-        }
-
-        let stored_crc = cursor.get_u32_le();
-        let data_for_crc = &data[..bytes_consumed - 4];
-        let calculated_crc = crc32c::crc32c(data_for_crc);
-
-        if stored_crc != calculated_crc {
-            return Err(RecordError::CrcMismatch {
-                expected: stored_crc,
-                actual: calculated_crc,
-            });
-        }
-
-        Ok(())
+    /// Like [`Record::peek_key`], but also returns the record's tombstone
+    /// flag, LSN, timestamp and namespace.
+    pub fn peek_header(data: &[u8]) -> Result<(RecordHeader<'_>, usize), RecordError> {
+        let (record, bytes_consumed) = RecordRef::decode(data)?;
+        let header = RecordHeader {
+            key: record.key,
+            tombstone: record.tombstone,
+            lsn: record.lsn,
+            timestamp: record.timestamp_ms.map(from_millis),
+            namespace: record.namespace,
+        };
+        Ok((header, bytes_consumed))
     }
 
-    fn decompress_value(compressed: Bytes, compression: Compression) -> Result<Bytes, RecordError> {
+    fn decompress_value(compressed: &[u8], compression: Compression) -> Result<Bytes, RecordError> {
         match compression {
-            Compression::None => Ok(compressed),
+            Compression::None => Ok(Bytes::copy_from_slice(compressed)),
             Compression::Lz4 => {
-                let mut cursor = &compressed[..];
+                let mut cursor = compressed;
                 let original_size = decode_varint(&mut cursor)? as usize;
                 let decompressed = lz4::block::decompress(cursor, Some(original_size as i32))
                     .map_err(|e| RecordError::DecompressionFailed(e.to_string()))?;
                 Ok(Bytes::from(decompressed))
             }
             Compression::Zstd => {
-                let decompressed = zstd::decode_all(&compressed[..])
+                let decompressed = zstd::decode_all(compressed)
                     .map_err(|e| RecordError::DecompressionFailed(e.to_string()))?;
                 Ok(Bytes::from(decompressed))
             }
@@ -272,48 +352,28 @@ impl Record {
     }
 }
 
+/// Milliseconds since the Unix epoch, clamped to zero for earlier times.
+pub(crate) fn timestamp_millis(timestamp: SystemTime) -> u64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn from_millis(ms: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(ms)
+}
+
 /// Encodes a u64 as a varint (LEB128).
-fn encode_varint(buf: &mut BytesMut, mut value: u64) {
-    loop {
-        let mut byte = (value & 0x7F) as u8;
-        value >>= 7;
-        if value != 0 {
-            byte |= 0x80;
-        }
-        buf.put_u8(byte);
-        if value == 0 {
-            break;
-        }
-    }
+fn encode_varint(buf: &mut BytesMut, value: u64) {
+    let mut bytes = [0; varint::MAX_LEN];
+    let len = varint::write(value, &mut bytes).expect("MAX_LEN fits any u64");
+    buf.put_slice(&bytes[..len]);
 }
 
 /// Decodes a varint (LEB128) from bytes.
 fn decode_varint(data: &mut &[u8]) -> Result<u64, RecordError> {
-    let mut result = 0u64;
-    let mut shift = 0;
-
-    loop {
-        if data.is_empty() {
-            return Err(RecordError::Incomplete);
-        }
-
-        let byte = data[0];
-        data.advance(1);
-
-        if shift >= 64 {
-            return Err(io::Error::new(ErrorKind::InvalidData, "varint overflow").into());
-        }
-
-        result |= ((byte & 0x7F) as u64) << shift;
-
-        if byte & 0x80 == 0 {
-            break;
-        }
-
-        shift += 7;
-    }
-
-    Ok(result)
+    Ok(varint::read(data)?)
 }
 
 #[cfg(test)]
@@ -343,6 +403,16 @@ mod tests {
         assert_eq!(size, encoded.len());
     }
 
+    #[test]
+    fn test_durability_is_not_stored() {
+        let record = Record::put(b"hello".as_slice(), b"world".as_slice())
+            .with_durability(Durability::Critical);
+        let (decoded, _) = Record::decode(&record.encode()).unwrap();
+
+        assert_eq!(decoded.durability, Durability::BestEffort);
+        assert_eq!(record, decoded);
+    }
+
     #[test]
     fn test_record_delete_roundtrip() {
         let record = Record::delete(b"key_to_delete".as_slice());
@@ -444,6 +514,53 @@ mod tests {
         assert_eq!(decoded.compression, Compression::Lz4);
     }
 
+    #[test]
+    fn test_record_with_lsn_and_timestamp() {
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let record = Record::put_with_ttl(b"k".as_slice(), b"v".as_slice(), Duration::from_secs(1))
+            .with_lsn(42)
+            .with_timestamp(timestamp)
+            .with_namespace(9);
+        let encoded = record.encode();
+        let (decoded, size) = Record::decode(&encoded).unwrap();
+
+        assert_eq!(record, decoded);
+        assert_eq!(decoded.lsn, Some(42));
+        assert_eq!(decoded.timestamp, Some(timestamp));
+        assert_eq!(decoded.namespace, Some(9));
+        assert_eq!(size, encoded.len());
+    }
+
+    #[test]
+    fn test_unknown_extension_skipped() {
+        // Hand-build a record whose extension block holds an unknown tag
+        // followed by an LSN entry.
+        let put_extension = |buf: &mut BytesMut, tag: u8, value: &[u8]| {
+            buf.put_u8(tag);
+            encode_varint(buf, value.len() as u64);
+            buf.put_slice(value);
+        };
+        let mut buf = BytesMut::new();
+        encode_varint(&mut buf, 1);
+        encode_varint(&mut buf, 1);
+        buf.put_u8(nori_wal_format::FLAG_EXTENSIONS);
+        let mut ext = BytesMut::new();
+        put_extension(&mut ext, 0xEE, b"future");
+        put_extension(&mut ext, nori_wal_format::EXT_LSN, &[7]);
+        encode_varint(&mut buf, ext.len() as u64);
+        buf.put_slice(&ext);
+        buf.put_slice(b"kv");
+        let crc = crc32c::crc32c(&buf);
+        buf.put_u32_le(crc);
+
+        let (decoded, size) = Record::decode(&buf).unwrap();
+        assert_eq!(decoded.key.as_ref(), b"k");
+        assert_eq!(decoded.value.as_ref(), b"v");
+        assert_eq!(decoded.lsn, Some(7));
+        assert_eq!(decoded.timestamp, None);
+        assert_eq!(size, buf.len());
+    }
+
     #[test]
     fn test_crc_mismatch() {
         let record = Record::put(b"test".as_slice(), b"data".as_slice());
@@ -467,6 +584,45 @@ mod tests {
         assert!(matches!(result, Err(RecordError::Incomplete)));
     }
 
+    #[test]
+    fn test_peek_key() {
+        let record = Record::put(b"user/42".as_slice(), vec![b'x'; 4096])
+            .with_compression(Compression::Zstd);
+        let encoded = record.encode();
+
+        let (key, size) = Record::peek_key(&encoded).unwrap();
+        assert_eq!(key, b"user/42");
+        assert_eq!(size, encoded.len());
+
+        let stamped = Record::delete(b"user/42".as_slice())
+            .with_lsn(7)
+            .with_timestamp(UNIX_EPOCH + Duration::from_millis(1_234))
+            .with_namespace(3);
+        let encoded_stamped = stamped.encode();
+        let (header, _) = Record::peek_header(&encoded_stamped).unwrap();
+        assert_eq!(
+            header,
+            RecordHeader {
+                key: b"user/42",
+                tombstone: true,
+                lsn: stamped.lsn,
+                timestamp: stamped.timestamp,
+                namespace: Some(3),
+            }
+        );
+
+        assert!(matches!(
+            Record::peek_key(&encoded[..encoded.len() - 1]),
+            Err(RecordError::Incomplete)
+        ));
+        let mut corrupted = encoded.to_vec();
+        corrupted[size - 5] ^= 0xFF;
+        assert!(matches!(
+            Record::peek_key(&corrupted),
+            Err(RecordError::CrcMismatch { .. })
+        ));
+    }
+
     #[test]
     fn test_empty_key_value() {
         let record = Record::put(b"".as_slice(), b"".as_slice());
@@ -491,6 +647,12 @@ mod proptests {
             value in prop::collection::vec(any::<u8>(), 0..1024),
             tombstone in any::<bool>(),
             ttl_ms in prop::option::of(0u64..86400000),
+            lsn in prop::option::of(any::<u64>()),
+            timestamp_ms in prop::option::of(0u64..4_000_000_000_000),
+            namespace in prop::option::of(any::<u32>()),
+            chain in prop::option::of(any::<[u8; 32]>()),
+            trace_id in prop::option::of(any::<[u8; 16]>()),
+            coalesced in prop::option::of(any::<u64>()),
         ) {
             let record = Record {
                 key: Bytes::from(key),
@@ -498,10 +660,24 @@ mod proptests {
                 tombstone,
                 ttl: ttl_ms.map(Duration::from_millis),
                 compression: Compression::None,
+                lsn,
+                timestamp: timestamp_ms.map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+                namespace,
+                chain,
+                trace: trace_id.map(|trace_id| TraceContext {
+                    trace_id,
+                    span_id: [7; 8],
+                    flags: 1,
+                }),
+                coalesced,
+                durability: Durability::BestEffort,
             };
 
             let encoded = record.encode();
             let (decoded, size) = Record::decode(&encoded).unwrap();
+            let (key, peeked_size) = Record::peek_key(&encoded).unwrap();
+            prop_assert_eq!(key, &record.key[..]);
+            prop_assert_eq!(peeked_size, size);
 
             prop_assert_eq!(record, decoded);
             prop_assert_eq!(size, encoded.len());
@@ -0,0 +1,339 @@
+//! Cache of recently decoded records, keyed by position.
+//!
+//! Tail followers often read the same records more than once: several
+//! subscribers follow the same log, and a consumer that fails retries from
+//! its last saved position. A [`WalReader`](crate::WalReader) or
+//! [`WalTail`](crate::WalTail) that has no segment open looks for the record
+//! at its position here first, and only reads and checksums the segment file
+//! on a miss. Records read from files are added as readers return them, up to
+//! [`WalConfig::record_cache_bytes`], evicting the least recently used.
+//!
+//! Only durable records are cached, and entries are dropped when their
+//! records leave the log: segments purged or rewritten by compaction, and the
+//! tail cut by a truncation.
+//!
+//! Under a [memory budget](crate::memory), the cache also evicts to keep the
+//! budget from going over, and gives back what is asked of it when appends
+//! or replication wait for room.
+//!
+//! [`WalConfig::record_cache_bytes`]: crate::WalConfig::record_cache_bytes
+
+use crate::memory::{MemoryBudget, MemoryCharge, MemoryComponent, Reclaim};
+use crate::record::Record;
+use crate::segment::Position;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Bytes charged per entry on top of its key and value.
+const ENTRY_OVERHEAD: u64 = 128;
+
+struct Entry {
+    record: Record,
+    /// Position just past the record.
+    next: Position,
+    size: u64,
+    /// Tick of the last lookup or insert, the entry's key in `by_use`.
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_position: BTreeMap<Position, Entry>,
+    /// Positions by last use, least recent first.
+    by_use: BTreeMap<u64, Position>,
+    bytes: u64,
+    tick: u64,
+    /// What the entries hold, charged to the memory budget if there is one.
+    charge: Option<MemoryCharge>,
+}
+
+impl Entries {
+    fn touch(&mut self, position: Position) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.by_position.get_mut(&position) {
+            self.by_use.remove(&entry.used);
+            entry.used = tick;
+            self.by_use.insert(tick, position);
+        }
+    }
+
+    fn remove(&mut self, position: Position) {
+        if let Some(entry) = self.by_position.remove(&position) {
+            self.by_use.remove(&entry.used);
+            self.bytes -= entry.size;
+            if let Some(charge) = &mut self.charge {
+                charge.set(self.bytes);
+            }
+        }
+    }
+
+    /// Removes the least recently used entry, returning its size.
+    fn evict_oldest(&mut self) -> Option<u64> {
+        let (_, &oldest) = self.by_use.iter().next()?;
+        let size = self.by_position[&oldest].size;
+        self.remove(oldest);
+        Some(size)
+    }
+
+    /// Whether `size` more bytes would take the memory budget over.
+    fn over_budget(&self, size: u64) -> bool {
+        self.charge
+            .as_ref()
+            .is_some_and(|charge| !charge.budget().fits(size))
+    }
+
+    /// Removes every entry from `from` onwards, and before `to` if given.
+    fn remove_range(&mut self, from: Position, to: Option<Position>) {
+        let positions: Vec<Position> = match to {
+            Some(to) => self.by_position.range(from..to).map(|(p, _)| *p).collect(),
+            None => self.by_position.range(from..).map(|(p, _)| *p).collect(),
+        };
+        for position in positions {
+            self.remove(position);
+        }
+    }
+}
+
+/// Least recently used records, up to a budget of bytes.
+pub(crate) struct RecordCache {
+    capacity: u64,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RecordCache {
+    /// A cache holding up to `capacity` bytes of records, and no more than
+    /// `budget` has room for if given; zero disables it.
+    pub(crate) fn new(capacity: u64, budget: Option<&MemoryBudget>) -> Arc<Self> {
+        let entries = Entries {
+            charge: budget.map(|budget| budget.charge(MemoryComponent::RecordCache)),
+            ..Default::default()
+        };
+        let cache = Arc::new(Self {
+            capacity,
+            entries: Mutex::new(entries),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        });
+        if let Some(budget) = budget.filter(|_| capacity > 0) {
+            let reclaim: Arc<dyn Reclaim> = cache.clone();
+            budget.register(Arc::downgrade(&reclaim));
+        }
+        cache
+    }
+
+    /// Returns the most bytes of records the cache holds.
+    pub(crate) fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the record at `position` and the position after it, if cached.
+    pub(crate) fn get(&self, position: Position) -> Option<(Record, Position)> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let found = entries
+            .by_position
+            .get(&position)
+            .map(|entry| (entry.record.clone(), entry.next));
+        match found {
+            Some(_) => {
+                entries.touch(position);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        found
+    }
+
+    /// Caches the durable `record` read at `position`, which ends at `next`.
+    pub(crate) fn insert(&self, position: Position, record: &Record, next: Position) {
+        let size = (record.key.len() + record.value.len()) as u64 + ENTRY_OVERHEAD;
+        if size > self.capacity {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.by_position.contains_key(&position) {
+            entries.touch(position);
+            return;
+        }
+        while entries.bytes + size > self.capacity {
+            if entries.evict_oldest().is_none() {
+                break;
+            }
+        }
+        if entries.over_budget(size) {
+            if let Some(charge) = &entries.charge {
+                charge.budget().note_shrink();
+            }
+            while entries.over_budget(size) {
+                if entries.evict_oldest().is_none() {
+                    // Leave it out rather than go over the budget
+                    return;
+                }
+            }
+        }
+        entries.tick += 1;
+        let used = entries.tick;
+        entries.by_position.insert(
+            position,
+            Entry {
+                record: record.clone(),
+                next,
+                size,
+                used,
+            },
+        );
+        entries.by_use.insert(used, position);
+        entries.bytes += size;
+        let bytes = entries.bytes;
+        if let Some(charge) = &mut entries.charge {
+            charge.set(bytes);
+        }
+    }
+
+    /// Forgets the records of a deleted or rewritten segment.
+    pub(crate) fn remove_segment(&self, segment_id: u64) {
+        let from = Position {
+            segment_id,
+            offset: 0,
+        };
+        let to = Position {
+            segment_id: segment_id + 1,
+            offset: 0,
+        };
+        self.entries.lock().unwrap().remove_range(from, Some(to));
+    }
+
+    /// Forgets everything at and after `position`.
+    pub(crate) fn truncate(&self, position: Position) {
+        self.entries.lock().unwrap().remove_range(position, None);
+    }
+
+    /// Returns how many lookups found their record, and how many did not.
+    pub(crate) fn counts(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl Reclaim for RecordCache {
+    fn reclaim(&self, bytes: u64) -> u64 {
+        let mut entries = self.entries.lock().unwrap();
+        let mut freed = 0;
+        while freed < bytes {
+            let Some(size) = entries.evict_oldest() else {
+                break;
+            };
+            freed += size;
+        }
+        freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(segment_id: u64, offset: u64) -> Position {
+        Position { segment_id, offset }
+    }
+
+    fn put(value_len: usize) -> Record {
+        Record::put("k", vec![0u8; value_len])
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        // Room for three records of about 200 bytes
+        let cache = RecordCache::new(3 * (200 + ENTRY_OVERHEAD), None);
+        for i in 0..3 {
+            cache.insert(at(0, i * 100), &put(199), at(0, (i + 1) * 100));
+        }
+        let (record, next) = cache.get(at(0, 0)).unwrap();
+        assert_eq!(record.value.len(), 199);
+        assert_eq!(next, at(0, 100));
+
+        // The record at 100 was used least recently
+        cache.insert(at(0, 300), &put(199), at(0, 400));
+        assert!(cache.get(at(0, 100)).is_none());
+        assert!(cache.get(at(0, 0)).is_some());
+        assert!(cache.get(at(0, 300)).is_some());
+        assert_eq!(cache.counts(), (3, 1));
+
+        // Records bigger than the whole cache are not kept
+        cache.insert(at(0, 400), &put(1000), at(0, 1500));
+        assert!(cache.get(at(0, 400)).is_none());
+        assert!(cache.get(at(0, 300)).is_some());
+    }
+
+    #[test]
+    fn test_forgets_removed_records() {
+        let cache = RecordCache::new(1024 * 1024, None);
+        for segment_id in 0..3 {
+            for i in 0..4 {
+                cache.insert(
+                    at(segment_id, i * 10),
+                    &put(1),
+                    at(segment_id, (i + 1) * 10),
+                );
+            }
+        }
+        cache.remove_segment(0);
+        assert!(cache.get(at(0, 0)).is_none());
+        assert!(cache.get(at(1, 0)).is_some());
+
+        cache.truncate(at(1, 20));
+        assert!(cache.get(at(1, 10)).is_some());
+        assert!(cache.get(at(1, 20)).is_none());
+        assert!(cache.get(at(2, 0)).is_none());
+
+        let disabled = RecordCache::new(0, None);
+        disabled.insert(at(0, 0), &put(1), at(0, 10));
+        assert!(disabled.get(at(0, 0)).is_none());
+        assert_eq!(disabled.counts(), (0, 0));
+    }
+
+    #[test]
+    fn test_stays_under_memory_budget() {
+        let entry = 200 + ENTRY_OVERHEAD;
+        let budget = MemoryBudget::new(3 * entry);
+        let cache = RecordCache::new(1024 * 1024, Some(&budget));
+        for i in 0..4 {
+            cache.insert(at(0, i * 100), &put(199), at(0, (i + 1) * 100));
+        }
+        // The cache has room for four, the budget only for three
+        assert!(cache.get(at(0, 0)).is_none());
+        assert!(cache.get(at(0, 300)).is_some());
+        assert_eq!(
+            budget.component_used(MemoryComponent::RecordCache),
+            3 * entry
+        );
+        assert_eq!(budget.stats().shrinks, 1);
+
+        // Memory held elsewhere leaves less for the cache
+        let mut reading = budget.charge(MemoryComponent::ReadAhead);
+        reading.set(3 * entry);
+        cache.insert(at(0, 400), &put(199), at(0, 500));
+        assert!(cache.get(at(0, 400)).is_none());
+        assert_eq!(budget.component_used(MemoryComponent::RecordCache), 0);
+
+        // And the cache gives memory back when asked
+        drop(reading);
+        cache.insert(at(0, 400), &put(199), at(0, 500));
+        cache.insert(at(0, 500), &put(199), at(0, 600));
+        assert_eq!(cache.reclaim(1), entry);
+        assert!(cache.get(at(0, 400)).is_none());
+        assert_eq!(budget.used(), entry);
+        drop(cache);
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -1,32 +1,215 @@
 //! WAL recovery with corruption detection and partial-tail truncation.
 //!
-//! Implements prefix-valid recovery strategy:
+//! Implements prefix-valid recovery strategy by default:
 //! - Scans all segment files in order
 //! - Validates CRC32C for each record
 //! - Truncates partial/corrupt records at tail
-//! - Emits CorruptionTruncated events when data is lost
-
+//! - Emits a CorruptionTruncated event for each damaged range cut out
+//! - Optionally quarantines discarded bytes for later forensics
+//!
+//! [`RecoveryMode`] selects stricter (fail without touching data) or more
+//! lenient (skip damaged ranges and keep later records) handling, and a
+//! [`RecoveryTarget`] rolls the log back to a point in time. A
+//! [`RecoveryBudget`] bounds the work done before the WAL opens; the rest is
+//! picked up later with [`resume_recovery`].
+
+use crate::error::IoOp;
+use crate::fs::{self, Fs, LocalFs};
 use crate::record::{Record, RecordError};
+use crate::seal;
 use crate::segment::{Position, SegmentError};
-use nori_observe::{Meter, VizEvent, WalEvt, WalKind};
+use crate::store::{FsSegmentStore, SegmentStore};
+use nori_observe::{
+    obs_emit, CorruptionCause, Meter, VizEvent, WalEvt, WalKind, DURATION_MS_BUCKETS,
+};
+use serde::Serialize;
+use std::ops::{ControlFlow, Range};
 use std::path::Path;
 use std::sync::Arc;
-use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
 
-/// Result of WAL recovery.
+/// Name of the subdirectory that receives quarantined bytes.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// How recovery reacts to records that fail to decode.
+///
+/// In every mode, a tail consisting only of zero bytes is treated as
+/// pre-allocated space that was never written: it is trimmed, never reported
+/// as an error.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Return `SegmentError::Corruption` on the first damaged record without
+    /// discarding any data, leaving the decision to an operator.
+    FailOnCorruption,
+    /// Truncate each segment at its first damaged record (prefix-valid recovery).
+    #[default]
+    TruncateTail,
+    /// Cut out damaged ranges, resynchronizing on the next record that decodes,
+    /// and report each removed range in [`RecoveryInfo::gaps`].
+    ///
+    /// Records after a gap move to lower offsets in their segment, so a
+    /// [`Position`] taken before recovery, such as a saved cursor or a
+    /// follower's replication position, no longer names the same record
+    /// there. Look such records up again by LSN.
+    SkipBadRecords,
+}
+
+/// Point at which point-in-time recovery stops applying records.
+///
+/// Recovery stops at the first record past the target; records without an
+/// LSN or timestamp (written before those were recorded) never stop it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Keep records up to and including this LSN.
+    Lsn(u64),
+    /// Keep records appended at or before this time.
+    Timestamp(SystemTime),
+}
+
+impl RecoveryTarget {
+    fn is_passed_by(&self, record: &Record) -> bool {
+        match *self {
+            RecoveryTarget::Lsn(lsn) => record.lsn.is_some_and(|l| l > lsn),
+            RecoveryTarget::Timestamp(at) => record.timestamp.is_some_and(|t| t > at),
+        }
+    }
+}
+
+/// Limits on how much replay work a single recovery call performs.
+///
+/// Checked after each replayed record, so a call overruns by at most one
+/// record, and every call replays at least one. If the budget runs out
+/// partway through a segment that turned out to be damaged, the rest of that
+/// segment is still scanned, though not replayed, so the damage is cut out in
+/// a single rewrite. The default is unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryBudget {
+    /// Stop once this many segment bytes have been replayed.
+    pub max_bytes: Option<u64>,
+    /// Stop once this much time has been spent replaying.
+    pub max_duration: Option<Duration>,
+}
+
+impl RecoveryBudget {
+    /// Returns true if no limit is set.
+    pub fn is_unbounded(&self) -> bool {
+        self.max_bytes.is_none() && self.max_duration.is_none()
+    }
+
+    fn is_exhausted(&self, bytes: u64, elapsed: Duration) -> bool {
+        self.max_bytes.is_some_and(|max| bytes >= max)
+            || self.max_duration.is_some_and(|max| elapsed >= max)
+    }
+}
+
+/// Replay work left over when a [`RecoveryBudget`] ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PendingRecovery {
+    /// First segment that has not been fully replayed yet.
+    pub next_segment: u64,
+    /// Offset in `next_segment` of the first record not replayed yet.
+    pub next_offset: u64,
+    /// End of the log when recovery started. Records appended after it are
+    /// not replayed by [`resume_recovery`].
+    pub end: Position,
+}
+
+/// Options controlling how recovery treats damaged segments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryOptions {
+    /// Copy bytes that are about to be truncated into `quarantine/` before
+    /// truncating, alongside a JSON sidecar describing the failure.
+    pub quarantine: bool,
+    /// Corruption handling strategy.
+    pub mode: RecoveryMode,
+    /// Stop replaying records once this point is passed.
+    pub target: Option<RecoveryTarget>,
+    /// Remove everything after `target` from disk instead of only skipping
+    /// it during replay. Without this, the skipped records are still in the
+    /// log and reappear when it is next opened without a target.
+    pub truncate_after_target: bool,
+    /// Skip replaying records whose TTL, measured from their timestamp, has
+    /// already elapsed. They stay on disk; only the replay callback is spared.
+    pub skip_expired: bool,
+    /// Time TTLs are measured against when `skip_expired` is set. The
+    /// current time when `None`.
+    pub expire_as_of: Option<SystemTime>,
+    /// Bound on replay work. When limited, the newest segments are repaired
+    /// first so the log can accept appends, and replay stops once the budget
+    /// is spent, leaving [`RecoveryInfo::pending`] for [`resume_recovery`].
+    /// Cannot be combined with `target`.
+    pub budget: RecoveryBudget,
+    /// Accept a sealed segment whose seal sidecar matches its length without
+    /// decoding its records. Only applies when there is no replay callback
+    /// and no `target`, since both need every record. The segment's bytes are
+    /// not read, so content damage that keeps its length is not detected.
+    pub trust_seals: bool,
+}
+
+/// A range of bytes removed from the middle of a segment by
+/// [`RecoveryMode::SkipBadRecords`].
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryGap {
+    /// Segment the bytes were removed from.
+    pub segment_id: u64,
+    /// Offset of the first removed byte, in the segment as it was before recovery.
+    pub offset: u64,
+    /// Number of bytes removed.
+    pub len: u64,
+}
+
+/// Result of WAL recovery.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct RecoveryInfo {
     /// Total number of valid records recovered.
     pub valid_records: u64,
     /// Number of segments scanned.
     pub segments_scanned: u64,
+    /// Bytes read and decoded; sealed segments accepted from their seal
+    /// contribute nothing.
+    pub bytes_scanned: u64,
     /// Total bytes truncated due to corruption.
     pub bytes_truncated: u64,
     /// Position of the last valid record.
     pub last_valid_position: Option<Position>,
     /// Whether any corruption was detected and truncated.
     pub corruption_detected: bool,
+    /// Bytes copied into the quarantine directory before truncation.
+    pub quarantined_bytes: u64,
+    /// Damaged ranges skipped over in [`RecoveryMode::SkipBadRecords`].
+    pub gaps: Vec<RecoveryGap>,
+    /// Highest LSN among the records left in the log.
+    pub last_lsn: Option<u64>,
+    /// Position of the first record past [`RecoveryOptions::target`], if the
+    /// target was reached.
+    pub stopped_at: Option<Position>,
+    /// Records not replayed because their TTL had expired.
+    pub expired_records: u64,
+    /// Replay work deferred because the [`RecoveryBudget`] ran out.
+    pub pending: Option<PendingRecovery>,
+    /// Where each truncated segment was cut, as offsets in the segment
+    /// before recovery.
+    pub truncation_points: Vec<Position>,
+    /// Wall-clock time spent in recovery.
+    pub duration: Duration,
+    /// Sealed segments accepted on the strength of their seal, without
+    /// decoding their records.
+    pub sealed_segments_trusted: u64,
+}
+
+impl RecoveryInfo {
+    fn absorb(&mut self, segment: &SegmentRecoveryInfo) {
+        self.bytes_scanned += segment.bytes_scanned;
+        self.bytes_truncated += segment.bytes_truncated;
+        self.quarantined_bytes += segment.quarantined_bytes;
+        self.gaps.extend(segment.gaps.iter().cloned());
+        self.truncation_points.extend(segment.truncated_at);
+
+        if segment.bytes_truncated > 0 {
+            self.corruption_detected = true;
+        }
+    }
 }
 
 /// Recovers WAL segments from a directory.
@@ -38,78 +221,791 @@ pub async fn recover(
     meter: Arc<dyn Meter>,
     node_id: u32,
 ) -> Result<RecoveryInfo, SegmentError> {
-    let mut segments = find_all_segments(wal_dir).await?;
-    segments.sort_unstable(); // Process in order
+    recover_with_options(wal_dir, meter, node_id, &RecoveryOptions::default()).await
+}
 
-    let mut info = RecoveryInfo {
-        valid_records: 0,
-        segments_scanned: 0,
-        bytes_truncated: 0,
-        last_valid_position: None,
-        corruption_detected: false,
+/// Recovers WAL segments from a directory using the given options.
+pub async fn recover_with_options(
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+) -> Result<RecoveryInfo, SegmentError> {
+    let store = FsSegmentStore::new(Arc::new(LocalFs));
+    run_recovery(&LocalFs, &store, wal_dir, meter, node_id, options, None).await
+}
+
+/// Recovers WAL segments, handing every surviving record to `replay`.
+///
+/// Records are delivered in log order as they are validated, with the
+/// position they occupy once recovery has finished rewriting the segment, so
+/// callers can rebuild in-memory state without a second pass over the data.
+/// If recovery returns an error, state built from the records seen so far
+/// should be discarded.
+pub async fn recover_with_replay(
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    replay: &mut (dyn FnMut(Record, Position) + Send),
+) -> Result<RecoveryInfo, SegmentError> {
+    let store = FsSegmentStore::new(Arc::new(LocalFs));
+    run_recovery(
+        &LocalFs,
+        &store,
+        wal_dir,
+        meter,
+        node_id,
+        options,
+        Some(replay),
+    )
+    .await
+}
+
+/// Reports the outcome of a recovery pass through the meter.
+///
+/// Corruption is counted both when it was repaired and when it made
+/// recovery fail, so alerts fire in every recovery mode.
+fn record_metrics(meter: &dyn Meter, result: &Result<RecoveryInfo, SegmentError>) {
+    meter.counter("wal_recoveries_total", &[]).inc(1);
+    match result {
+        Ok(info) => {
+            meter
+                .histo("wal_recovery_duration_ms", DURATION_MS_BUCKETS, &[])
+                .observe(info.duration.as_secs_f64() * 1000.0);
+            meter
+                .counter("wal_recovery_bytes_scanned_total", &[])
+                .inc(info.bytes_scanned);
+            meter
+                .counter("wal_recovery_records_total", &[])
+                .inc(info.valid_records);
+            meter
+                .counter("wal_recovery_bytes_truncated_total", &[])
+                .inc(info.bytes_truncated);
+            if info.corruption_detected {
+                meter.counter("wal_recovery_corruption_total", &[]).inc(1);
+            }
+        }
+        Err(e) => {
+            meter.counter("wal_recovery_failures_total", &[]).inc(1);
+            if matches!(e, SegmentError::Corruption { .. }) {
+                meter.counter("wal_recovery_corruption_total", &[]).inc(1);
+            }
+        }
+    }
+}
+
+/// Recovers WAL segments, replaying records only if a callback is given.
+pub(crate) async fn run_recovery(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    replay: Option<&mut (dyn FnMut(Record, Position) + Send)>,
+) -> Result<RecoveryInfo, SegmentError> {
+    let result = recover_all(fs, store, wal_dir, meter.clone(), node_id, options, replay).await;
+    record_metrics(meter.as_ref(), &result);
+    if let Ok(info) = &result {
+        obs_emit!(
+            meter,
+            VizEvent::Wal(WalEvt {
+                node: node_id,
+                seg: info.last_valid_position.map_or(0, |p| p.segment_id),
+                kind: WalKind::RecoveryCompleted {
+                    ms: info.duration.as_millis() as u64,
+                    records: info.valid_records,
+                },
+            })
+        );
+    }
+    result
+}
+
+async fn recover_all(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    replay: Option<&mut (dyn FnMut(Record, Position) + Send)>,
+) -> Result<RecoveryInfo, SegmentError> {
+    if options.target.is_some() && !options.budget.is_unbounded() {
+        return Err(SegmentError::InvalidConfig(
+            "a recovery budget cannot be combined with a recovery target".to_string(),
+        ));
+    }
+
+    let started = Instant::now();
+    let segments = store.list(wal_dir).await?;
+
+    let mut info = RecoveryInfo::default();
+    let mut end = None;
+    let mut tail_lsn = None;
+    if !options.budget.is_unbounded() {
+        if let Some((tail_end, lsn)) = repair_tail(
+            fs,
+            store,
+            wal_dir,
+            &segments,
+            meter.clone(),
+            node_id,
+            options,
+            &mut info,
+        )
+        .await?
+        {
+            end = Some(tail_end);
+            tail_lsn = lsn;
+        }
+    }
+
+    let mut replayer = Replayer::new(replay, options);
+    info.pending = replay_segments(
+        fs,
+        store,
+        wal_dir,
+        &segments,
+        end,
+        None,
+        meter,
+        node_id,
+        options,
+        &mut replayer,
+        &mut info,
+    )
+    .await?;
+
+    info.last_lsn = replayer.last_lsn.max(tail_lsn);
+    info.stopped_at = replayer.stopped_at;
+    info.expired_records = replayer.expired;
+    info.duration = started.elapsed();
+    Ok(info)
+}
+
+/// Continues a recovery that stopped early because its budget ran out.
+///
+/// Replays the log from `pending.next_segment` and `pending.next_offset` up
+/// to `pending.end`, again within `options.budget`; call it until the returned
+/// [`RecoveryInfo::pending`] is `None`.
+pub async fn resume_recovery(
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    pending: &PendingRecovery,
+    replay: &mut (dyn FnMut(Record, Position) + Send),
+) -> Result<RecoveryInfo, SegmentError> {
+    let store = FsSegmentStore::new(Arc::new(LocalFs));
+    resume_recovery_on(
+        &LocalFs, &store, wal_dir, meter, node_id, options, pending, replay,
+    )
+    .await
+}
+
+/// Like [`resume_recovery`], for a WAL whose segments are kept in `store`
+/// and whose sidecar files are on `fs`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn resume_recovery_on(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    pending: &PendingRecovery,
+    replay: &mut (dyn FnMut(Record, Position) + Send),
+) -> Result<RecoveryInfo, SegmentError> {
+    let result = resume_from(
+        fs,
+        store,
+        wal_dir,
+        meter.clone(),
+        node_id,
+        options,
+        pending,
+        replay,
+    )
+    .await;
+    record_metrics(meter.as_ref(), &result);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn resume_from(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    pending: &PendingRecovery,
+    replay: &mut (dyn FnMut(Record, Position) + Send),
+) -> Result<RecoveryInfo, SegmentError> {
+    let started = Instant::now();
+    let mut segments = store.list(wal_dir).await?;
+    segments.retain(|&id| id >= pending.next_segment);
+
+    let mut info = RecoveryInfo::default();
+    let mut replayer = Replayer::new(Some(replay), options);
+    info.pending = replay_segments(
+        fs,
+        store,
+        wal_dir,
+        &segments,
+        Some(pending.end),
+        Some(Position {
+            segment_id: pending.next_segment,
+            offset: pending.next_offset,
+        }),
+        meter,
+        node_id,
+        options,
+        &mut replayer,
+        &mut info,
+    )
+    .await?;
+
+    info.last_lsn = replayer.last_lsn;
+    info.expired_records = replayer.expired;
+    info.duration = started.elapsed();
+    Ok(info)
+}
+
+/// Repairs the newest segments without replaying them, so the log can take
+/// appends before replay has finished.
+///
+/// Walks backwards from the last segment until one holds a record with an
+/// LSN, so numbering can resume. Returns the end of the log and that LSN.
+#[allow(clippy::too_many_arguments)]
+async fn repair_tail(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    segments: &[u64],
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    info: &mut RecoveryInfo,
+) -> Result<Option<(Position, Option<u64>)>, SegmentError> {
+    let Some(&last) = segments.last() else {
+        return Ok(None);
     };
 
-    for segment_id in segments {
-        let segment_info = recover_segment(wal_dir, segment_id, meter.clone(), node_id).await?;
+    let mut replayer = Replayer::new(None, options);
+    let mut end = None;
+    for &segment_id in segments.iter().rev() {
+        let segment_info =
+            match trust_seal(fs, store, wal_dir, segment_id, options, &mut replayer).await? {
+                Some(sealed) => sealed,
+                None => {
+                    recover_segment(
+                        fs,
+                        store,
+                        wal_dir,
+                        segment_id,
+                        0,
+                        meter.clone(),
+                        node_id,
+                        options,
+                        &mut replayer,
+                    )
+                    .await?
+                }
+            };
+        info.absorb(&segment_info);
+
+        if segment_id == last {
+            end = Some(Position {
+                segment_id,
+                offset: segment_info.len,
+            });
+        }
+        if replayer.last_lsn.is_some() {
+            break;
+        }
+    }
 
-        info.valid_records += segment_info.valid_records;
-        info.segments_scanned += 1;
-        info.bytes_truncated += segment_info.bytes_truncated;
+    Ok(end.map(|end| (end, replayer.last_lsn)))
+}
+
+/// Recovers and replays `segments` in order until the budget runs out.
+///
+/// When `end` is given, the segments from there on were already repaired
+/// and may be receiving appends: segments after it are skipped and only
+/// its prefix up to `end.offset` is replayed. The budget applies only then.
+/// Replay of the segment named by `resume_at` starts at its offset.
+#[allow(clippy::too_many_arguments)]
+async fn replay_segments(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    segments: &[u64],
+    end: Option<Position>,
+    resume_at: Option<Position>,
+    meter: Arc<dyn Meter>,
+    node_id: u32,
+    options: &RecoveryOptions,
+    replayer: &mut Replayer<'_>,
+    info: &mut RecoveryInfo,
+) -> Result<Option<PendingRecovery>, SegmentError> {
+    if end.is_some() {
+        replayer.limit(options.budget);
+    }
 
-        if segment_info.bytes_truncated > 0 {
-            info.corruption_detected = true;
+    for &segment_id in segments {
+        if let Some(end) = end {
+            if segment_id > end.segment_id {
+                break;
+            }
+            if replayer.out_of_budget() {
+                return Ok(Some(PendingRecovery {
+                    next_segment: segment_id,
+                    next_offset: 0,
+                    end,
+                }));
+            }
+        }
+        let start = resume_at
+            .filter(|p| p.segment_id == segment_id)
+            .map_or(0, |p| p.offset);
+
+        if options.truncate_after_target && replayer.stopped_at.is_some() {
+            // Everything in this segment follows the target
+            store.delete(wal_dir, segment_id).await?;
+            seal::remove_seal(fs, wal_dir, segment_id).await?;
+            fs.sync_dir(wal_dir).await?;
+            continue;
         }
 
+        let segment_info = match end {
+            Some(end) if end.segment_id == segment_id => {
+                replay_prefix(store, wal_dir, start, end, replayer).await?
+            }
+            _ => {
+                // A seal covers the whole segment, not a resumed remainder
+                let sealed = if start == 0 {
+                    trust_seal(fs, store, wal_dir, segment_id, options, replayer).await?
+                } else {
+                    None
+                };
+                match sealed {
+                    Some(sealed) => {
+                        info.sealed_segments_trusted += 1;
+                        let _ = replayer.charge(sealed.len);
+                        sealed
+                    }
+                    None => {
+                        recover_segment(
+                            fs,
+                            store,
+                            wal_dir,
+                            segment_id,
+                            start,
+                            meter.clone(),
+                            node_id,
+                            options,
+                            replayer,
+                        )
+                        .await?
+                    }
+                }
+            }
+        };
+
+        info.absorb(&segment_info);
+        info.valid_records += segment_info.valid_records;
+        info.segments_scanned += 1;
+
         if let Some(pos) = segment_info.last_valid_position {
             info.last_valid_position = Some(pos);
         }
+        if let (Some(next_offset), Some(end)) = (segment_info.resume_at, end) {
+            return Ok(Some(PendingRecovery {
+                next_segment: segment_id,
+                next_offset,
+                end,
+            }));
+        }
     }
 
-    Ok(info)
+    Ok(None)
+}
+
+/// Accepts a sealed segment from its seal sidecar when that is allowed and
+/// the seal matches the file's length. A seal that does not match is stale
+/// and is removed.
+///
+/// Only the length is checked; the seal's `data_crc` is not compared, since
+/// that would mean reading the whole segment, which trusting seals avoids.
+async fn trust_seal(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    segment_id: u64,
+    options: &RecoveryOptions,
+    replayer: &mut Replayer<'_>,
+) -> Result<Option<SegmentRecoveryInfo>, SegmentError> {
+    if !options.trust_seals || replayer.needs_records() {
+        return Ok(None);
+    }
+    let Some(seal) = seal::read_seal(fs, wal_dir, segment_id).await else {
+        return Ok(None);
+    };
+
+    let len = store
+        .len(wal_dir, segment_id)
+        .await
+        .map_err(SegmentError::io_at(
+            IoOp::Read,
+            segment_id,
+            &segment_path(wal_dir, segment_id),
+            0,
+        ))?;
+    if seal.len != len {
+        seal::remove_seal(fs, wal_dir, segment_id).await?;
+        return Ok(None);
+    }
+
+    if let Some(lsn) = seal.last_lsn {
+        replayer.note_lsn(lsn);
+    }
+    Ok(Some(SegmentRecoveryInfo {
+        valid_records: seal.records,
+        bytes_scanned: 0,
+        bytes_truncated: 0,
+        quarantined_bytes: 0,
+        gaps: Vec::new(),
+        truncated_at: None,
+        last_valid_position: (seal.records > 0).then_some(Position {
+            segment_id,
+            offset: len,
+        }),
+        len,
+        resume_at: None,
+    }))
+}
+
+/// Replays the records of an already-repaired segment from `start` up to
+/// `end.offset`.
+async fn replay_prefix(
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    start: u64,
+    end: Position,
+    replayer: &mut Replayer<'_>,
+) -> Result<SegmentRecoveryInfo, SegmentError> {
+    let segment_id = end.segment_id;
+    let path = segment_path(wal_dir, segment_id);
+    let read_error = || SegmentError::io_at(IoOp::Read, segment_id, &path, start);
+    let file = store
+        .open_read(wal_dir, segment_id)
+        .await
+        .map_err(read_error())?;
+    let buffer = file
+        .read_at(start, end.offset.saturating_sub(start) as usize)
+        .await
+        .map_err(read_error())?;
+
+    let scan = scan_segment(
+        &buffer,
+        0,
+        RecoveryMode::TruncateTail,
+        &mut |record, offset, size| {
+            let offset = start + offset;
+            replayer.offer(record, Position { segment_id, offset });
+            replayer.charge(size)
+        },
+    );
+    let resume_at = scan.resume_at.map(|offset| start + offset);
+
+    Ok(SegmentRecoveryInfo {
+        valid_records: scan.valid_records,
+        bytes_scanned: buffer.len() as u64,
+        bytes_truncated: 0,
+        quarantined_bytes: 0,
+        gaps: Vec::new(),
+        truncated_at: None,
+        last_valid_position: (scan.valid_records > 0).then(|| Position {
+            segment_id,
+            offset: resume_at.unwrap_or(end.offset),
+        }),
+        len: end.offset,
+        resume_at,
+    })
+}
+
+/// Forwards recovered records to the caller's replay callback, holding back
+/// those past the recovery target.
+struct Replayer<'a> {
+    replay: Option<&'a mut (dyn FnMut(Record, Position) + Send)>,
+    target: Option<RecoveryTarget>,
+    /// Records past the target are being removed, so they do not count
+    /// towards `last_lsn`.
+    discard_past_target: bool,
+    stopped_at: Option<Position>,
+    past_target: u64,
+    last_lsn: Option<u64>,
+    /// When set, records whose TTL ran out before this instant are skipped.
+    expire_before: Option<SystemTime>,
+    expired: u64,
+    /// Bound on the replay work charged since `limit` was called.
+    budget: Option<RecoveryBudget>,
+    started: Instant,
+    bytes_charged: u64,
+}
+
+impl<'a> Replayer<'a> {
+    fn new(
+        replay: Option<&'a mut (dyn FnMut(Record, Position) + Send)>,
+        options: &RecoveryOptions,
+    ) -> Self {
+        Self {
+            replay,
+            target: options.target,
+            discard_past_target: options.truncate_after_target,
+            stopped_at: None,
+            past_target: 0,
+            last_lsn: None,
+            expire_before: options
+                .skip_expired
+                .then(|| options.expire_as_of.unwrap_or_else(SystemTime::now)),
+            expired: 0,
+            budget: None,
+            started: Instant::now(),
+            bytes_charged: 0,
+        }
+    }
+
+    /// Bounds the replay work from here on by `budget`.
+    fn limit(&mut self, budget: RecoveryBudget) {
+        self.budget = Some(budget);
+        self.started = Instant::now();
+        self.bytes_charged = 0;
+    }
+
+    /// Counts `bytes` of replayed log against the budget, breaking once it
+    /// has run out.
+    fn charge(&mut self, bytes: u64) -> ControlFlow<()> {
+        self.bytes_charged += bytes;
+        if self.out_of_budget() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    /// Whether the budget has run out. It never has before anything was
+    /// charged, so each call makes progress.
+    fn out_of_budget(&self) -> bool {
+        self.bytes_charged > 0
+            && self
+                .budget
+                .is_some_and(|b| b.is_exhausted(self.bytes_charged, self.started.elapsed()))
+    }
+
+    fn offer(&mut self, record: Record, position: Position) {
+        if self.stopped_at.is_none() && self.target.is_some_and(|t| t.is_passed_by(&record)) {
+            self.stopped_at = Some(position);
+        }
+
+        if self.stopped_at.is_some() {
+            self.past_target += 1;
+            if self.discard_past_target {
+                return;
+            }
+        }
+        if let Some(lsn) = record.lsn {
+            self.note_lsn(lsn);
+        }
+        if self.stopped_at.is_some() {
+            return;
+        }
+        if let Some(now) = self.expire_before {
+            if is_expired(&record, now) {
+                self.expired += 1;
+                return;
+            }
+        }
+        if let Some(replay) = self.replay.as_mut() {
+            replay(record, position);
+        }
+    }
+
+    /// Whether every record must be decoded, rather than summarized by a seal.
+    fn needs_records(&self) -> bool {
+        self.replay.is_some() || self.target.is_some()
+    }
+
+    fn note_lsn(&mut self, lsn: u64) {
+        self.last_lsn = Some(self.last_lsn.map_or(lsn, |last| last.max(lsn)));
+    }
+}
+
+/// Whether `record` carries a TTL and timestamp and expired at or before `now`.
+fn is_expired(record: &Record, now: SystemTime) -> bool {
+    match (record.ttl, record.timestamp) {
+        (Some(ttl), Some(written)) => written
+            .checked_add(ttl)
+            .is_some_and(|expires_at| expires_at <= now),
+        _ => false,
+    }
 }
 
 /// Information about a single segment's recovery.
 struct SegmentRecoveryInfo {
     valid_records: u64,
+    bytes_scanned: u64,
     bytes_truncated: u64,
+    quarantined_bytes: u64,
+    gaps: Vec<RecoveryGap>,
+    truncated_at: Option<Position>,
     last_valid_position: Option<Position>,
+    /// Length of the segment once recovery is done with it.
+    len: u64,
+    /// Offset of the first record left unreplayed when the budget ran out.
+    resume_at: Option<u64>,
 }
 
-/// Recovers a single segment file.
+/// Recovers a single segment file, replaying its records from `start`.
+///
+/// The bytes before `start` must already have been checked and repaired.
+#[allow(clippy::too_many_arguments)]
 async fn recover_segment(
+    fs: &dyn Fs,
+    store: &dyn SegmentStore,
     wal_dir: &Path,
     segment_id: u64,
+    start: u64,
     meter: Arc<dyn Meter>,
     node_id: u32,
+    options: &RecoveryOptions,
+    replayer: &mut Replayer<'_>,
 ) -> Result<SegmentRecoveryInfo, SegmentError> {
     let path = segment_path(wal_dir, segment_id);
-    let mut file = File::open(&path).await?;
+    let buffer = read_segment(store, wal_dir, segment_id)
+        .await
+        .map_err(SegmentError::io_at(IoOp::Read, segment_id, &path, 0))?;
+    let file_size = buffer.len() as u64;
+
+    let past_target_before = replayer.past_target;
+    let scan = scan_segment(&buffer, start, options.mode, &mut |record, offset, size| {
+        replayer.offer(record, Position { segment_id, offset });
+        replayer.charge(size)
+    });
+    if let Some(offset) = scan.resume_at.filter(|_| scan.bad_ranges.is_empty()) {
+        // The rest of the segment is checked once replay resumes
+        return Ok(SegmentRecoveryInfo {
+            valid_records: scan.valid_records,
+            bytes_scanned: offset - start,
+            bytes_truncated: 0,
+            quarantined_bytes: 0,
+            gaps: Vec::new(),
+            truncated_at: None,
+            last_valid_position: Some(Position { segment_id, offset }),
+            len: offset,
+            resume_at: Some(offset),
+        });
+    }
+    let tail_start = scan.bad_ranges.last().map_or(file_size, |(r, _)| {
+        if r.end == file_size {
+            r.start
+        } else {
+            file_size
+        }
+    });
+
+    if options.mode == RecoveryMode::FailOnCorruption {
+        if let Some((range, _)) = scan
+            .bad_ranges
+            .iter()
+            .find(|(r, _)| evidence_len(&buffer[r.start as usize..r.end as usize]) > 0)
+        {
+            return Err(SegmentError::Corruption {
+                segment_id,
+                offset: range.start,
+            });
+        }
+    }
 
-    let file_size = file.metadata().await?.len();
-    let mut buffer = vec![0u8; file_size as usize];
-    file.read_exact(&mut buffer).await?;
+    let bytes_truncated: u64 = scan.bad_ranges.iter().map(|(r, _)| r.end - r.start).sum();
+    let mut quarantined_bytes = 0;
+    let mut gaps = Vec::new();
 
-    // Scan for valid records
-    let (valid_records, last_valid_offset) = scan_valid_records(&buffer, file_size);
+    // Remove corrupted data if needed
+    if bytes_truncated > 0 {
+        for (range, failure) in &scan.bad_ranges {
+            if options.quarantine {
+                quarantined_bytes += quarantine_bytes(
+                    fs,
+                    wal_dir,
+                    segment_id,
+                    &buffer[range.start as usize..range.end as usize],
+                    range.start,
+                    Some(failure),
+                )
+                .await?;
+            }
 
-    let bytes_truncated = file_size - last_valid_offset;
+            if range.start < tail_start {
+                gaps.push(RecoveryGap {
+                    segment_id,
+                    offset: range.start,
+                    len: range.end - range.start,
+                });
+            }
+        }
 
-    // Truncate corrupted data if needed
-    if bytes_truncated > 0 {
-        truncate_segment_atomically(&path, &buffer, last_valid_offset).await?;
+        let kept = complement(&scan.bad_ranges, file_size);
+        rewrite_segment_atomically(store, wal_dir, segment_id, &buffer, &kept).await?;
+        seal::remove_seal(fs, wal_dir, segment_id).await?;
+
+        for (range, failure) in &scan.bad_ranges {
+            let cause = match failure {
+                RecordError::CrcMismatch { .. } => CorruptionCause::Crc,
+                _ => CorruptionCause::Framing,
+            };
+            obs_emit!(
+                meter,
+                VizEvent::Wal(WalEvt {
+                    node: node_id,
+                    seg: segment_id,
+                    kind: WalKind::CorruptionTruncated {
+                        offset: range.start,
+                        bytes: range.end - range.start,
+                        cause,
+                    },
+                })
+            );
+        }
+    }
 
-        // Emit corruption event
-        meter.emit(VizEvent::Wal(WalEvt {
-            node: node_id,
-            seg: segment_id,
-            kind: WalKind::CorruptionTruncated,
-        }));
+    let mut valid_records = scan.valid_records;
+    let mut end = file_size - bytes_truncated;
+
+    // Cut the log at the recovery target
+    if let Some(stop) = replayer.stopped_at {
+        if options.truncate_after_target && stop.segment_id == segment_id {
+            let truncate_error =
+                || SegmentError::io_at(IoOp::Truncate, segment_id, &path, stop.offset);
+            let file = store
+                .open_append(wal_dir, segment_id, false)
+                .await
+                .map_err(truncate_error())?;
+            file.set_len(stop.offset).await.map_err(truncate_error())?;
+            file.sync_all().await.map_err(truncate_error())?;
+            seal::remove_seal(fs, wal_dir, segment_id).await?;
+            valid_records -= replayer.past_target - past_target_before;
+            end = stop.offset;
+        }
     }
 
     let last_valid_position = if valid_records > 0 {
         Some(Position {
             segment_id,
-            offset: last_valid_offset,
+            offset: end,
         })
     } else {
         None
@@ -117,98 +1013,233 @@ async fn recover_segment(
 
     Ok(SegmentRecoveryInfo {
         valid_records,
+        bytes_scanned: file_size - start,
         bytes_truncated,
+        quarantined_bytes,
+        gaps,
+        truncated_at: (tail_start < file_size).then_some(Position {
+            segment_id,
+            offset: tail_start,
+        }),
         last_valid_position,
+        len: end,
+        resume_at: scan.resume_at,
     })
 }
 
-/// Scans a buffer for valid records, returning the count and last valid offset.
+/// Outcome of scanning one segment's bytes.
+struct SegmentScan {
+    /// Records passed to `on_record`.
+    valid_records: u64,
+    /// Byte ranges that failed to decode, in order, with the error that began each.
+    bad_ranges: Vec<(Range<u64>, RecordError)>,
+    /// Where to resume, once bad ranges are cut out, if `on_record` broke.
+    resume_at: Option<u64>,
+}
+
+/// Scans a buffer for valid records, starting at the record boundary `start`.
 ///
-/// Stops scanning when corruption or incomplete records are detected.
-fn scan_valid_records(buffer: &[u8], file_size: u64) -> (u64, u64) {
-    let mut offset = 0u64;
-    let mut valid_records = 0u64;
-    let mut last_valid_offset = 0u64;
-
-    while offset < file_size {
-        let remaining = &buffer[offset as usize..];
-
-        match Record::decode(remaining) {
-            Ok((_record, size)) => {
-                // Valid record
-                valid_records += 1;
-                offset += size as u64;
-                last_valid_offset = offset;
-            }
-            Err(RecordError::Incomplete) => {
-                // Partial record at tail - this is expected during recovery
-                break;
+/// In [`RecoveryMode::SkipBadRecords`], a decode failure starts a bad range that
+/// extends to the next offset at which a record decodes; otherwise the first
+/// failure ends the scan and everything after it is one bad range.
+///
+/// Each valid record is passed to `on_record` with its offset after the bad
+/// ranges preceding it have been cut out, and its encoded size. Once
+/// `on_record` breaks, no more records are passed on. The scan then stops,
+/// unless a bad range was already found: it carries on to find the rest, so
+/// the segment can be repaired in one go.
+fn scan_segment(
+    buffer: &[u8],
+    start: u64,
+    mode: RecoveryMode,
+    on_record: &mut dyn FnMut(Record, u64, u64) -> ControlFlow<()>,
+) -> SegmentScan {
+    let len = buffer.len() as u64;
+    let mut offset = start;
+    let mut removed = 0u64;
+    let mut scan = SegmentScan {
+        valid_records: 0,
+        bad_ranges: Vec::new(),
+        resume_at: None,
+    };
+
+    while offset < len {
+        match Record::decode(&buffer[offset as usize..]) {
+            Ok((record, size)) => {
+                let size = size as u64;
+                offset += size;
+                if scan.resume_at.is_some() {
+                    continue;
+                }
+                scan.valid_records += 1;
+                if on_record(record, offset - size - removed, size).is_break() {
+                    scan.resume_at = Some(offset - removed);
+                    if scan.bad_ranges.is_empty() {
+                        break;
+                    }
+                }
             }
-            Err(RecordError::CrcMismatch { .. }) | Err(_) => {
-                // Corruption or other error - truncate here
-                break;
+            Err(e) => {
+                let resync = match mode {
+                    RecoveryMode::SkipBadRecords => find_next_record(buffer, offset + 1),
+                    _ => None,
+                };
+                let end = resync.unwrap_or(len);
+                scan.bad_ranges.push((offset..end, e));
+                removed += end - offset;
+                offset = end;
             }
         }
     }
 
-    (valid_records, last_valid_offset)
+    scan
 }
 
-/// Atomically truncates a segment file using temp file + rename pattern.
+/// Finds the first offset at or after `from` where a complete record decodes.
 ///
-/// This ensures the original file is unchanged if a crash occurs during truncation.
-async fn truncate_segment_atomically(
-    path: &std::path::PathBuf,
-    buffer: &[u8],
-    last_valid_offset: u64,
-) -> Result<(), SegmentError> {
-    let temp_path = path.with_extension("wal.tmp");
-
-    // Write valid data to temp file
-    let mut temp_file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(&temp_path)
-        .await?;
+/// Only offsets before the trailing run of zeros are candidates, so resyncing
+/// never walks through pre-allocated space byte by byte.
+fn find_next_record(buffer: &[u8], from: u64) -> Option<u64> {
+    (from as usize..evidence_len(buffer))
+        .find(|&i| Record::decode(&buffer[i..]).is_ok())
+        .map(|i| i as u64)
+}
 
-    temp_file.write_all(&buffer[..last_valid_offset as usize]).await?;
-    temp_file.sync_all().await?;
-    drop(temp_file);
+/// Returns the ranges of `[0, len)` not covered by the sorted `bad_ranges`.
+fn complement(bad_ranges: &[(Range<u64>, RecordError)], len: u64) -> Vec<Range<u64>> {
+    let mut kept = Vec::new();
+    let mut start = 0;
+    for (range, _) in bad_ranges {
+        if range.start > start {
+            kept.push(start..range.start);
+        }
+        start = range.end;
+    }
+    if start < len {
+        kept.push(start..len);
+    }
+    kept
+}
 
-    // Atomic rename: if this succeeds, the old file is replaced atomically
-    // If we crash before this, the original file is unchanged
-    tokio::fs::rename(&temp_path, path).await?;
+/// Length of `bytes` once trailing zeros (never-written pre-allocated space)
+/// are ignored.
+fn evidence_len(bytes: &[u8]) -> usize {
+    bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1)
+}
 
-    Ok(())
+/// The `.json` sidecar written next to quarantined bytes.
+#[derive(Serialize)]
+struct QuarantineSidecar {
+    segment_id: u64,
+    segment_path: String,
+    first_bad_offset: u64,
+    bytes_discarded: u64,
+    bytes_quarantined: u64,
+    /// `crc_mismatch`, `incomplete` or `framing`.
+    reason: &'static str,
+    crc_expected: Option<u32>,
+    crc_actual: Option<u32>,
+    quarantined_at_ms: u64,
 }
 
-/// Finds all segment files in a directory.
-async fn find_all_segments(dir: &Path) -> Result<Vec<u64>, SegmentError> {
-    let mut entries = tokio::fs::read_dir(dir).await?;
-    let mut segment_ids = Vec::new();
+/// Copies bytes about to be discarded into the quarantine directory.
+///
+/// Writes `<segment>-<offset>.bad` with the raw bytes and a `.json` sidecar
+/// describing why the scan stopped. Trailing zeros (pre-allocated space that
+/// was never written) carry no evidence and are not copied; a range consisting
+/// only of zeros is not quarantined at all. Returns the number of bytes copied.
+async fn quarantine_bytes(
+    fs: &dyn Fs,
+    wal_dir: &Path,
+    segment_id: u64,
+    bytes: &[u8],
+    offset: u64,
+    failure: Option<&RecordError>,
+) -> Result<u64, SegmentError> {
+    let evidence_len = evidence_len(bytes);
+    if evidence_len == 0 {
+        return Ok(0);
+    }
+
+    let dir = wal_dir.join(QUARANTINE_DIR);
+    fs.create_dir_all(&dir).await?;
+
+    let stem = format!("{:06}-{}", segment_id, offset);
+    fs::write_synced(
+        fs,
+        &dir.join(format!("{}.bad", stem)),
+        &bytes[..evidence_len],
+    )
+    .await?;
 
-    while let Some(entry) = entries.next_entry().await? {
-        if let Some(id) = parse_segment_id_from_path(&entry.path()) {
-            segment_ids.push(id);
+    let (reason, crc_expected, crc_actual) = match failure {
+        Some(RecordError::CrcMismatch { expected, actual }) => {
+            ("crc_mismatch", Some(*expected), Some(*actual))
         }
-    }
+        Some(RecordError::Incomplete) => ("incomplete", None, None),
+        _ => ("framing", None, None),
+    };
+    let sidecar = QuarantineSidecar {
+        segment_id,
+        segment_path: segment_path(wal_dir, segment_id)
+            .to_string_lossy()
+            .into_owned(),
+        first_bad_offset: offset,
+        bytes_discarded: bytes.len() as u64,
+        bytes_quarantined: evidence_len as u64,
+        reason,
+        crc_expected,
+        crc_actual,
+        quarantined_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+    };
+    let mut json = serde_json::to_vec(&sidecar).expect("sidecar serializes");
+    json.push(b'\n');
+    fs::write_synced(fs, &dir.join(format!("{}.json", stem)), &json).await?;
+
+    fs.sync_dir(&dir).await?;
 
-    Ok(segment_ids)
+    Ok(evidence_len as u64)
 }
 
-/// Parses a segment ID from a .wal file path.
+/// Atomically rewrites segment `segment_id` to contain only the `kept` byte
+/// ranges of `buffer`.
 ///
-/// Returns None if the path is not a valid .wal file or cannot be parsed.
-fn parse_segment_id_from_path(path: &Path) -> Option<u64> {
-    // Check extension is "wal"
-    if path.extension()?.to_str()? != "wal" {
-        return None;
+/// The store replaces the segment as a whole, so the original is unchanged
+/// if a crash occurs during the rewrite.
+async fn rewrite_segment_atomically(
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    segment_id: u64,
+    buffer: &[u8],
+    kept: &[Range<u64>],
+) -> Result<(), SegmentError> {
+    let mut data = Vec::with_capacity(kept.iter().map(|r| (r.end - r.start) as usize).sum());
+    for range in kept {
+        data.extend_from_slice(&buffer[range.start as usize..range.end as usize]);
     }
+    store
+        .replace(wal_dir, segment_id, &data)
+        .await
+        .map_err(SegmentError::io_at(
+            IoOp::Truncate,
+            segment_id,
+            &segment_path(wal_dir, segment_id),
+            0,
+        ))
+}
 
-    // Parse the filename stem as a u64
-    let stem_str = path.file_stem()?.to_str()?;
-    stem_str.parse::<u64>().ok()
+/// Reads the whole of segment `segment_id` from `store`.
+async fn read_segment(
+    store: &dyn SegmentStore,
+    wal_dir: &Path,
+    segment_id: u64,
+) -> std::io::Result<Vec<u8>> {
+    let file = store.open_read(wal_dir, segment_id).await?;
+    let len = store.len(wal_dir, segment_id).await?;
+    file.read_at(0, len as usize).await
 }
 
 /// Generates the path for a segment file.
@@ -221,8 +1252,9 @@ mod tests {
     use super::*;
     use crate::record::Record;
     use crate::segment::{SegmentConfig, SegmentManager};
-    use nori_observe::NoopMeter;
+    use nori_observe::{NoopMeter, TestMeter};
     use tempfile::TempDir;
+    use tokio::fs::{File, OpenOptions};
     use tokio::io::AsyncWriteExt;
 
     #[tokio::test]
@@ -405,4 +1437,553 @@ mod tests {
         assert_eq!(info.bytes_truncated, 0);
         assert!(!info.corruption_detected);
     }
+
+    #[tokio::test]
+    async fn test_recovery_quarantines_corrupt_tail() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+        for i in 0..5 {
+            let key = format!("key{}", i);
+            let record = Record::put(bytes::Bytes::from(key), b"value".as_slice());
+            manager.append(&record).await.unwrap();
+        }
+        manager.sync().await.unwrap();
+        drop(manager);
+
+        // Corrupt the last record's payload
+        let seg_path = temp_dir.path().join("000000.wal");
+        let mut file_data = tokio::fs::read(&seg_path).await.unwrap();
+        let corrupt_pos = file_data.len() - 6;
+        file_data[corrupt_pos] ^= 0xFF;
+        tokio::fs::write(&seg_path, &file_data).await.unwrap();
+
+        let options = RecoveryOptions {
+            quarantine: true,
+            ..Default::default()
+        };
+        let info = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(info.valid_records, 4);
+        assert!(info.corruption_detected);
+        assert_eq!(info.quarantined_bytes, info.bytes_truncated);
+
+        let offset = file_data.len() as u64 - info.bytes_truncated;
+        let quarantine = temp_dir.path().join(QUARANTINE_DIR);
+        let bad = tokio::fs::read(quarantine.join(format!("000000-{}.bad", offset)))
+            .await
+            .unwrap();
+        assert_eq!(bad, &file_data[offset as usize..]);
+
+        let sidecar = tokio::fs::read_to_string(quarantine.join(format!("000000-{}.json", offset)))
+            .await
+            .unwrap();
+        assert!(sidecar.contains("\"reason\":\"crc_mismatch\""));
+        assert!(sidecar.contains(&format!("\"first_bad_offset\":{}", offset)));
+
+        // The quarantine directory is invisible to subsequent recoveries
+        let info = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 4);
+        assert!(!info.corruption_detected);
+    }
+
+    #[tokio::test]
+    async fn test_budget_stops_between_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 200,
+            preallocate: false,
+            ..Default::default()
+        };
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+        for i in 0..20 {
+            let key = format!("key{}", i);
+            let record = Record::put(bytes::Bytes::from(key), b"value".as_slice());
+            manager.append(&record).await.unwrap();
+        }
+        manager.sync().await.unwrap();
+        drop(manager);
+
+        let mut expected = Vec::new();
+        let options = RecoveryOptions::default();
+        recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |_, position| expected.push(position),
+        )
+        .await
+        .unwrap();
+
+        // Each call replays exactly one record, wherever it sits in a segment
+        let options = RecoveryOptions {
+            budget: RecoveryBudget {
+                max_bytes: Some(1),
+                max_duration: None,
+            },
+            ..Default::default()
+        };
+        let mut positions = Vec::new();
+        let mut info = recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |_, position| positions.push(position),
+        )
+        .await
+        .unwrap();
+        assert_eq!(positions.len(), 1);
+        while let Some(pending) = info.pending {
+            let replayed = positions.len();
+            info = resume_recovery(
+                temp_dir.path(),
+                Arc::new(NoopMeter),
+                1,
+                &options,
+                &pending,
+                &mut |_, position| positions.push(position),
+            )
+            .await
+            .unwrap();
+            assert!(positions.len() <= replayed + 1);
+        }
+        assert_eq!(positions, expected);
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_sidecar_escapes_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let wal_dir = temp_dir.path().join("wal \"quoted\" \\ dir");
+        tokio::fs::create_dir(&wal_dir).await.unwrap();
+        let (_, offset) = write_segment_with_corrupt_middle(&wal_dir).await;
+
+        let options = RecoveryOptions {
+            quarantine: true,
+            ..Default::default()
+        };
+        let info = recover_with_options(&wal_dir, Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+        assert!(info.quarantined_bytes > 0);
+
+        let sidecar = tokio::fs::read(
+            wal_dir
+                .join(QUARANTINE_DIR)
+                .join(format!("000000-{}.json", offset)),
+        )
+        .await
+        .unwrap();
+        let sidecar: serde_json::Value = serde_json::from_slice(&sidecar).unwrap();
+        assert_eq!(
+            sidecar["segment_path"],
+            wal_dir.join("000000.wal").to_str().unwrap()
+        );
+        assert_eq!(sidecar["reason"], "crc_mismatch");
+        assert_eq!(sidecar["first_bad_offset"], offset);
+    }
+
+    /// Writes five records and flips a byte inside the third one.
+    /// Returns the original segment bytes and the third record's offset.
+    async fn write_segment_with_corrupt_middle(dir: &Path) -> (Vec<u8>, u64) {
+        let mut data = Vec::new();
+        let mut third_offset = 0;
+        for i in 0..5 {
+            if i == 2 {
+                third_offset = data.len() as u64;
+            }
+            let key = format!("key{}", i);
+            let record = Record::put(bytes::Bytes::from(key), b"value".as_slice());
+            data.extend_from_slice(&record.encode());
+        }
+        data[third_offset as usize + 4] ^= 0xFF;
+        tokio::fs::write(segment_path(dir, 0), &data).await.unwrap();
+        (data, third_offset)
+    }
+
+    #[tokio::test]
+    async fn test_recovery_fail_on_corruption_touches_nothing() {
+        let temp_dir = TempDir::new().unwrap();
+        let (data, third_offset) = write_segment_with_corrupt_middle(temp_dir.path()).await;
+
+        let options = RecoveryOptions {
+            mode: RecoveryMode::FailOnCorruption,
+            ..Default::default()
+        };
+        let result = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options).await;
+
+        match result {
+            Err(SegmentError::Corruption { segment_id, offset }) => {
+                assert_eq!(segment_id, 0);
+                assert_eq!(offset, third_offset);
+            }
+            other => panic!("expected corruption error, got {:?}", other),
+        }
+        let on_disk = tokio::fs::read(segment_path(temp_dir.path(), 0))
+            .await
+            .unwrap();
+        assert_eq!(on_disk, data);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_fail_on_corruption_trims_preallocated_zeros() {
+        let temp_dir = TempDir::new().unwrap();
+        let record = Record::put(b"key".as_slice(), b"value".as_slice()).encode();
+        let mut data = record.to_vec();
+        data.resize(data.len() + 4096, 0);
+        tokio::fs::write(segment_path(temp_dir.path(), 0), &data)
+            .await
+            .unwrap();
+
+        let options = RecoveryOptions {
+            mode: RecoveryMode::FailOnCorruption,
+            ..Default::default()
+        };
+        let info = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(info.valid_records, 1);
+        assert_eq!(info.bytes_truncated, 4096);
+        let on_disk = tokio::fs::read(segment_path(temp_dir.path(), 0))
+            .await
+            .unwrap();
+        assert_eq!(on_disk, record.as_ref());
+    }
+
+    #[tokio::test]
+    async fn test_recovery_skip_bad_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let (data, third_offset) = write_segment_with_corrupt_middle(temp_dir.path()).await;
+
+        let options = RecoveryOptions {
+            mode: RecoveryMode::SkipBadRecords,
+            ..Default::default()
+        };
+        let info = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(info.valid_records, 4);
+        assert!(info.corruption_detected);
+        assert_eq!(info.gaps.len(), 1);
+        assert_eq!(info.gaps[0].offset, third_offset);
+        assert_eq!(info.gaps[0].len, info.bytes_truncated);
+
+        // The surviving records are readable back to back
+        let on_disk = tokio::fs::read(segment_path(temp_dir.path(), 0))
+            .await
+            .unwrap();
+        assert_eq!(
+            on_disk.len() as u64,
+            data.len() as u64 - info.bytes_truncated
+        );
+        let mut cursor = &on_disk[..];
+        let mut keys = Vec::new();
+        while !cursor.is_empty() {
+            let (record, size) = Record::decode(cursor).unwrap();
+            keys.push(record.key);
+            cursor = &cursor[size..];
+        }
+        assert_eq!(keys, vec!["key0", "key1", "key3", "key4"]);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_replay_positions_after_skip() {
+        let temp_dir = TempDir::new().unwrap();
+        write_segment_with_corrupt_middle(temp_dir.path()).await;
+
+        let options = RecoveryOptions {
+            mode: RecoveryMode::SkipBadRecords,
+            ..Default::default()
+        };
+        let mut replayed = Vec::new();
+        recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |record, pos| replayed.push((record, pos)),
+        )
+        .await
+        .unwrap();
+
+        // Every replayed position points at that record in the rewritten segment
+        let on_disk = tokio::fs::read(segment_path(temp_dir.path(), 0))
+            .await
+            .unwrap();
+        assert_eq!(replayed.len(), 4);
+        for (record, pos) in &replayed {
+            assert_eq!(pos.segment_id, 0);
+            let (decoded, _) = Record::decode(&on_disk[pos.offset as usize..]).unwrap();
+            assert_eq!(&decoded, record);
+        }
+    }
+
+    /// Writes LSNs 1..=10 as two segments of five records each and returns
+    /// the offset of every record within its segment.
+    async fn write_numbered_segments(dir: &Path) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        for segment_id in 0..2u64 {
+            let mut data = Vec::new();
+            for i in 0..5u64 {
+                let lsn = segment_id * 5 + i + 1;
+                offsets.push(data.len() as u64);
+                let record =
+                    Record::put(bytes::Bytes::from(format!("key{}", lsn)), "v").with_lsn(lsn);
+                data.extend_from_slice(&record.encode());
+            }
+            tokio::fs::write(segment_path(dir, segment_id), &data)
+                .await
+                .unwrap();
+        }
+        offsets
+    }
+
+    #[tokio::test]
+    async fn test_recovery_to_lsn_truncates_after_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let offsets = write_numbered_segments(temp_dir.path()).await;
+
+        let options = RecoveryOptions {
+            target: Some(RecoveryTarget::Lsn(3)),
+            truncate_after_target: true,
+            ..Default::default()
+        };
+        let mut replayed = Vec::new();
+        let info = recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |record, _| replayed.push(record.lsn.unwrap()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(replayed, vec![1, 2, 3]);
+        assert_eq!(info.valid_records, 3);
+        assert_eq!(info.last_lsn, Some(3));
+        assert_eq!(
+            info.stopped_at,
+            Some(Position {
+                segment_id: 0,
+                offset: offsets[3],
+            })
+        );
+
+        // The tail is gone from disk, so a plain recovery sees the same log
+        let on_disk = tokio::fs::metadata(segment_path(temp_dir.path(), 0))
+            .await
+            .unwrap();
+        assert_eq!(on_disk.len(), offsets[3]);
+        assert!(!segment_path(temp_dir.path(), 1).exists());
+        let info = recover(temp_dir.path(), Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 3);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_to_lsn_ignores_after_target() {
+        let temp_dir = TempDir::new().unwrap();
+        write_numbered_segments(temp_dir.path()).await;
+
+        let options = RecoveryOptions {
+            target: Some(RecoveryTarget::Lsn(7)),
+            ..Default::default()
+        };
+        let mut replayed = Vec::new();
+        let info = recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |record, _| replayed.push(record.lsn.unwrap()),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(replayed, (1..=7).collect::<Vec<_>>());
+        assert_eq!(info.stopped_at.map(|p| p.segment_id), Some(1));
+        // Skipped records stay on disk, so new LSNs must continue past them
+        assert_eq!(info.valid_records, 10);
+        assert_eq!(info.last_lsn, Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_skips_expired_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let long_ago = SystemTime::now() - std::time::Duration::from_secs(3600);
+        let ttl = std::time::Duration::from_secs(60);
+        let records = [
+            Record::put_with_ttl("expired", "v", ttl).with_timestamp(long_ago),
+            Record::put_with_ttl("live", "v", ttl).with_timestamp(SystemTime::now()),
+            Record::put("forever", "v").with_timestamp(long_ago),
+            // Without a timestamp the expiry cannot be judged
+            Record::put_with_ttl("unstamped", "v", ttl),
+        ];
+        let data: Vec<u8> = records.iter().flat_map(|r| r.encode()).collect();
+        tokio::fs::write(segment_path(temp_dir.path(), 0), &data)
+            .await
+            .unwrap();
+
+        let options = RecoveryOptions {
+            skip_expired: true,
+            ..Default::default()
+        };
+        let mut keys = Vec::new();
+        let info = recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |record, _| keys.push(record.key),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(keys, vec!["live", "forever", "unstamped"]);
+        assert_eq!(info.expired_records, 1);
+        assert_eq!(info.valid_records, 4);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_trusts_matching_seals() {
+        let temp_dir = TempDir::new().unwrap();
+        write_numbered_segments(temp_dir.path()).await;
+        let len = |id| {
+            std::fs::metadata(segment_path(temp_dir.path(), id))
+                .unwrap()
+                .len()
+        };
+
+        // The seal's record count is taken on trust, so a distinct value shows it was used
+        let good = seal::SegmentSeal {
+            len: len(0),
+            records: 4,
+            first_lsn: Some(1),
+            last_lsn: Some(5),
+            data_crc: 0,
+        };
+        let stale = seal::SegmentSeal {
+            len: len(1) + 1,
+            ..good
+        };
+        seal::write_seal(&LocalFs, temp_dir.path(), 0, &good)
+            .await
+            .unwrap();
+        seal::write_seal(&LocalFs, temp_dir.path(), 1, &stale)
+            .await
+            .unwrap();
+
+        let options = RecoveryOptions {
+            trust_seals: true,
+            ..Default::default()
+        };
+        let info = recover_with_options(temp_dir.path(), Arc::new(NoopMeter), 1, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(info.sealed_segments_trusted, 1);
+        assert_eq!(info.valid_records, 4 + 5);
+        assert_eq!(info.last_lsn, Some(10));
+        assert!(seal::read_seal(&LocalFs, temp_dir.path(), 0)
+            .await
+            .is_some());
+        assert!(!seal::seal_path(temp_dir.path(), 1).exists());
+
+        // A replay callback needs every record, so seals are not used
+        let mut replayed = 0;
+        let info = recover_with_replay(
+            temp_dir.path(),
+            Arc::new(NoopMeter),
+            1,
+            &options,
+            &mut |_, _| replayed += 1,
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.sealed_segments_trusted, 0);
+        assert_eq!(replayed, 10);
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_recovery_metrics() {
+        let temp_dir = TempDir::new().unwrap();
+        let (data, third_offset) = write_segment_with_corrupt_middle(temp_dir.path()).await;
+
+        let meter = TestMeter::new();
+        let info = recover(temp_dir.path(), Arc::new(meter.clone()), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(info.bytes_scanned, data.len() as u64);
+        assert_eq!(info.bytes_truncated, data.len() as u64 - third_offset);
+        assert_eq!(meter.counter_total("wal_recoveries_total"), 1);
+        assert_eq!(meter.histo_samples("wal_recovery_duration_ms").len(), 1);
+        assert_eq!(
+            meter.counter_total("wal_recovery_bytes_scanned_total"),
+            data.len() as u64
+        );
+        assert_eq!(meter.counter_total("wal_recovery_records_total"), 2);
+        assert_eq!(
+            meter.counter_total("wal_recovery_bytes_truncated_total"),
+            info.bytes_truncated
+        );
+        assert_eq!(meter.counter_total("wal_recovery_corruption_total"), 1);
+        let events = meter.events();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                kind: WalKind::RecoveryCompleted { records: 2, .. },
+                ..
+            })
+        )));
+        // The corrupt record and everything after it is cut
+        assert!(events.iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                seg: 0,
+                kind: WalKind::CorruptionTruncated {
+                    offset,
+                    bytes,
+                    cause: CorruptionCause::Crc,
+                },
+                ..
+            }) if *offset == third_offset && *bytes == info.bytes_truncated
+        )));
+
+        // A recovery that refuses to repair still counts the corruption
+        write_segment_with_corrupt_middle(temp_dir.path()).await;
+        let options = RecoveryOptions {
+            mode: RecoveryMode::FailOnCorruption,
+            ..Default::default()
+        };
+        let meter = TestMeter::new();
+        assert!(
+            recover_with_options(temp_dir.path(), Arc::new(meter.clone()), 1, &options)
+                .await
+                .is_err()
+        );
+        assert_eq!(meter.counter_total("wal_recovery_failures_total"), 1);
+        assert_eq!(meter.counter_total("wal_recovery_corruption_total"), 1);
+        assert_eq!(meter.counter_total("wal_recovery_records_total"), 0);
+    }
 }
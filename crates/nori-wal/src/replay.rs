@@ -0,0 +1,488 @@
+//! Parallel replay of sealed segments.
+//!
+//! A [`WalReader`](crate::WalReader) reads one segment at a time, which leaves
+//! most of the disk's bandwidth idle during full replays for analytics or
+//! rebuilding derived state. [`replay_parallel`] reads several sealed segments
+//! at once and hands the records to a channel in batches.
+//!
+//! With [`ReplayOrder::Global`] batches arrive in log order: later segments are
+//! read ahead into a few buffered batches while the earliest one drains. With
+//! [`ReplayOrder::PerSegment`] every segment sends as fast as it is read, so
+//! batches from different segments interleave and only the records within a
+//! segment keep their order.
+
+use crate::record::{Record, RecordHeader};
+use crate::runtime::{spawn_with_output, Task};
+use crate::segment::{Position, ReaderConfig, SegmentError, SegmentManager};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use tokio::sync::{mpsc, oneshot};
+
+/// Batches a segment may read ahead of the one being delivered under
+/// [`ReplayOrder::Global`].
+const PREFETCH_BATCHES: usize = 4;
+
+/// How batches from different segments are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayOrder {
+    /// Batches arrive in log order.
+    #[default]
+    Global,
+    /// Batches of one segment arrive in order, but segments interleave.
+    PerSegment,
+}
+
+/// Configuration for [`replay_parallel`].
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Segments read at the same time (default: 4).
+    pub concurrency: usize,
+    /// Records per batch (default: 1024).
+    pub batch_size: usize,
+    /// Ordering of batches across segments (default: global).
+    pub order: ReplayOrder,
+    /// Buffering of each segment reader.
+    pub reader: ReaderConfig,
+    /// Only replay records in this namespace; others are skipped without
+    /// decoding their values (default: None, replay everything).
+    pub namespace: Option<u32>,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            batch_size: 1024,
+            order: ReplayOrder::default(),
+            reader: ReaderConfig::default(),
+            namespace: None,
+        }
+    }
+}
+
+impl ReplayConfig {
+    fn validate(&self) -> Result<(), SegmentError> {
+        if self.concurrency == 0 {
+            return Err(SegmentError::InvalidConfig(
+                "replay concurrency must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size == 0 {
+            return Err(SegmentError::InvalidConfig(
+                "replay batch size must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Consecutive records from one segment.
+#[derive(Debug, Clone)]
+pub struct ReplayBatch {
+    pub segment_id: u64,
+    pub records: Vec<(Record, Position)>,
+    /// True for the final batch of the segment.
+    pub last: bool,
+}
+
+/// Outcome of [`replay_parallel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplaySummary {
+    /// Segments delivered in full.
+    pub segments: u64,
+    /// Records delivered.
+    pub records: u64,
+    /// Where the replay stopped: the start of the segment that was active when
+    /// it began. A [`WalReader`](crate::WalReader) from here picks up the rest.
+    pub end: Position,
+}
+
+/// Replays the sealed segments from `from` onwards, reading up to
+/// `config.concurrency` of them at a time and sending their records to `sink`
+/// in batches.
+///
+/// Dropping the receiving end stops the replay early; the summary then counts
+/// what was delivered before it closed.
+pub async fn replay_parallel(
+    manager: Arc<SegmentManager>,
+    from: Position,
+    config: ReplayConfig,
+    sink: mpsc::Sender<ReplayBatch>,
+) -> Result<ReplaySummary, SegmentError> {
+    config.validate()?;
+
+    let active = manager.current_position().await.segment_id;
+    let mut pending: VecDeque<Position> = manager
+        .sealed_segment_ids()
+        .await?
+        .into_iter()
+        .filter(|&id| id >= from.segment_id && id < active)
+        .map(|segment_id| {
+            if segment_id == from.segment_id {
+                from
+            } else {
+                Position {
+                    segment_id,
+                    offset: 0,
+                }
+            }
+        })
+        .collect();
+    let mut summary = ReplaySummary {
+        segments: 0,
+        records: 0,
+        end: if from.segment_id < active {
+            Position {
+                segment_id: active,
+                offset: 0,
+            }
+        } else {
+            from
+        },
+    };
+
+    match config.order {
+        ReplayOrder::Global => {
+            // Each segment reads into its own small channel; they are drained
+            // into the sink one after another
+            let mut in_flight: VecDeque<(oneshot::Receiver<_>, mpsc::Receiver<ReplayBatch>)> =
+                VecDeque::new();
+            loop {
+                while in_flight.len() < config.concurrency {
+                    let Some(start) = pending.pop_front() else {
+                        break;
+                    };
+                    let (tx, rx) = mpsc::channel(PREFETCH_BATCHES);
+                    let task = replay_segment(manager.clone(), start, config.clone(), tx);
+                    let (_, done) = spawn_with_output(manager.runtime().as_ref(), task);
+                    in_flight.push_back((done, rx));
+                }
+                let Some((task, mut rx)) = in_flight.pop_front() else {
+                    break;
+                };
+                while let Some(batch) = rx.recv().await {
+                    let len = batch.records.len() as u64;
+                    if sink.send(batch).await.is_err() {
+                        // Readers still in flight stop when their channels close
+                        return Ok(summary);
+                    }
+                    summary.records += len;
+                }
+                join(task).await?;
+                summary.segments += 1;
+            }
+        }
+        ReplayOrder::PerSegment => {
+            let mut running = Vec::new();
+            loop {
+                while running.len() < config.concurrency && !sink.is_closed() {
+                    let Some(start) = pending.pop_front() else {
+                        break;
+                    };
+                    let task = replay_segment(manager.clone(), start, config.clone(), sink.clone());
+                    running.push(spawn_with_output(manager.runtime().as_ref(), task));
+                }
+                // Once the sink closes, the tasks left finish on their next send
+                if running.is_empty() {
+                    break;
+                }
+                let sent = match join_any(&mut running).await {
+                    Ok(sent) => sent,
+                    Err(e) => {
+                        // The other segments would keep sending into the sink
+                        for (task, _) in &running {
+                            task.abort();
+                        }
+                        return Err(e);
+                    }
+                };
+                summary.records += sent.records;
+                if sent.complete {
+                    summary.segments += 1;
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// A running segment task and the receiver for its outcome.
+type SegmentTask = (Box<dyn Task>, oneshot::Receiver<Result<Sent, SegmentError>>);
+
+/// Records a segment task managed to send.
+struct Sent {
+    records: u64,
+    /// False if the channel closed before the segment was done.
+    complete: bool,
+}
+
+/// Reads one segment from `start` to its end, sending batches to `tx`.
+async fn replay_segment(
+    manager: Arc<SegmentManager>,
+    start: Position,
+    config: ReplayConfig,
+    tx: mpsc::Sender<ReplayBatch>,
+) -> Result<Sent, SegmentError> {
+    let mut reader = manager.read_from_with(start, config.reader).await?;
+    if let Some(namespace) = config.namespace {
+        reader.set_filter(Some(Arc::new(move |header: &RecordHeader<'_>| {
+            header.namespace == Some(namespace)
+        })));
+    }
+    let mut sent = Sent {
+        records: 0,
+        complete: false,
+    };
+    let mut batch = Vec::with_capacity(config.batch_size);
+
+    while let Some(entry) = reader.next_record().await? {
+        // Hold a full batch back until another record shows it is not the last
+        if batch.len() == config.batch_size {
+            let records = std::mem::replace(&mut batch, Vec::with_capacity(config.batch_size));
+            let len = records.len() as u64;
+            let full = ReplayBatch {
+                segment_id: start.segment_id,
+                records,
+                last: false,
+            };
+            if tx.send(full).await.is_err() {
+                return Ok(sent);
+            }
+            sent.records += len;
+        }
+        batch.push(entry);
+    }
+
+    if !batch.is_empty() {
+        let len = batch.len() as u64;
+        let last = ReplayBatch {
+            segment_id: start.segment_id,
+            records: batch,
+            last: true,
+        };
+        if tx.send(last).await.is_err() {
+            return Ok(sent);
+        }
+        sent.records += len;
+    }
+    sent.complete = true;
+    Ok(sent)
+}
+
+async fn join(task: oneshot::Receiver<Result<Sent, SegmentError>>) -> Result<Sent, SegmentError> {
+    task.await.map_err(|_| task_lost())?
+}
+
+/// Waits for whichever of `tasks` finishes first and removes it.
+async fn join_any(tasks: &mut Vec<SegmentTask>) -> Result<Sent, SegmentError> {
+    let (i, result) = std::future::poll_fn(|cx| {
+        for (i, (_, done)) in tasks.iter_mut().enumerate() {
+            if let Poll::Ready(result) = Pin::new(done).poll(cx) {
+                return Poll::Ready((i, result));
+            }
+        }
+        Poll::Pending
+    })
+    .await;
+    tasks.swap_remove(i);
+    result.map_err(|_| task_lost())?
+}
+
+/// The error for a segment task that ended without reporting, which happens
+/// if it panicked or the runtime dropped it.
+fn task_lost() -> SegmentError {
+    SegmentError::Io(std::io::Error::other("replay task ended without a result"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::FsyncPolicy;
+    use crate::wal::{Wal, WalConfig};
+    use std::collections::HashMap;
+    use tempfile::TempDir;
+
+    async fn populated_wal(temp_dir: &TempDir) -> (Wal, Vec<Position>) {
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let value = vec![1u8; 16 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..300 {
+            let record = Record::put(format!("key{:03}", i), value.clone()).with_namespace(i % 3);
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+        (wal, positions)
+    }
+
+    async fn collect(
+        wal: &Wal,
+        from: Position,
+        config: ReplayConfig,
+    ) -> (ReplaySummary, Vec<ReplayBatch>) {
+        let (tx, mut rx) = mpsc::channel(2);
+        let collector = tokio::spawn(async move {
+            let mut batches = Vec::new();
+            while let Some(batch) = rx.recv().await {
+                batches.push(batch);
+            }
+            batches
+        });
+        let summary = wal.replay_parallel(from, config, tx).await.unwrap();
+        (summary, collector.await.unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_replay_global_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, positions) = populated_wal(&temp_dir).await;
+        let active = wal.current_position().await.segment_id;
+        assert!(active >= 4);
+
+        let config = ReplayConfig {
+            batch_size: 10,
+            ..Default::default()
+        };
+        let (summary, batches) = collect(&wal, positions[5], config).await;
+
+        let replayed: Vec<Position> = batches
+            .iter()
+            .flat_map(|batch| batch.records.iter().map(|(_, position)| *position))
+            .collect();
+        let expected: Vec<Position> = positions[5..]
+            .iter()
+            .copied()
+            .filter(|position| position.segment_id < active)
+            .collect();
+        assert_eq!(replayed, expected);
+        assert_eq!(summary.records, expected.len() as u64);
+        assert_eq!(summary.segments, active);
+        assert_eq!(
+            summary.end,
+            Position {
+                segment_id: active,
+                offset: 0
+            }
+        );
+        assert_eq!(
+            batches.iter().filter(|batch| batch.last).count() as u64,
+            active
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_replay_namespace() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, positions) = populated_wal(&temp_dir).await;
+        let active = wal.current_position().await.segment_id;
+
+        let config = ReplayConfig {
+            namespace: Some(1),
+            ..Default::default()
+        };
+        let (summary, batches) = collect(&wal, positions[0], config).await;
+
+        let replayed: Vec<_> = batches
+            .iter()
+            .flat_map(|batch| batch.records.iter())
+            .map(|(record, position)| {
+                assert_eq!(record.namespace, Some(1));
+                *position
+            })
+            .collect();
+        let expected: Vec<_> = positions
+            .iter()
+            .enumerate()
+            .filter(|&(i, position)| i % 3 == 1 && position.segment_id < active)
+            .map(|(_, position)| *position)
+            .collect();
+        assert_eq!(replayed, expected);
+        assert_eq!(summary.records, expected.len() as u64);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_replay_per_segment_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, positions) = populated_wal(&temp_dir).await;
+        let active = wal.current_position().await.segment_id;
+
+        let config = ReplayConfig {
+            batch_size: 7,
+            order: ReplayOrder::PerSegment,
+            ..Default::default()
+        };
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let (summary, batches) = collect(&wal, start, config).await;
+
+        let mut by_segment: HashMap<u64, Vec<Position>> = HashMap::new();
+        for batch in &batches {
+            assert!(batch.records.len() <= 7);
+            let seen = by_segment.entry(batch.segment_id).or_default();
+            seen.extend(batch.records.iter().map(|(_, position)| *position));
+        }
+        for segment_id in 0..active {
+            let expected: Vec<Position> = positions
+                .iter()
+                .copied()
+                .filter(|position| position.segment_id == segment_id)
+                .collect();
+            assert_eq!(by_segment[&segment_id], expected);
+        }
+        assert_eq!(summary.segments, active);
+    }
+
+    #[tokio::test]
+    async fn test_replay_stops_when_sink_closes() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, _) = populated_wal(&temp_dir).await;
+
+        for order in [ReplayOrder::Global, ReplayOrder::PerSegment] {
+            let (tx, mut rx) = mpsc::channel(1);
+            let config = ReplayConfig {
+                batch_size: 5,
+                order,
+                ..Default::default()
+            };
+            let start = Position {
+                segment_id: 0,
+                offset: 0,
+            };
+            let replay = wal.replay_parallel(start, config, tx);
+            let first = async move {
+                let batch = rx.recv().await.unwrap();
+                drop(rx);
+                batch
+            };
+            let (summary, batch) = tokio::join!(replay, first);
+            let summary = summary.unwrap();
+            assert_eq!(batch.records.len(), 5);
+            assert!(summary.records < 300);
+        }
+
+        let invalid = ReplayConfig {
+            concurrency: 0,
+            ..Default::default()
+        };
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let (tx, _rx) = mpsc::channel(1);
+        assert!(matches!(
+            wal.replay_parallel(start, invalid, tx).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+    }
+}
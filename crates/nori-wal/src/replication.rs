@@ -0,0 +1,712 @@
+//! Streaming replication of the log to followers.
+//!
+//! A [`ReplicationServer`] on the leader accepts follower connections over
+//! TCP and streams each one the durable records from the LSN it asks for,
+//! following the log with a [`WalTail`](crate::WalTail) as new records
+//! become durable. Records keep their LSNs and timestamps, so a follower's
+//! log lines up with the leader's by LSN even where segment boundaries
+//! differ. Followers ack the LSN up to which they have made the stream
+//! durable, and the server tracks that per follower as a
+//! [`FollowerStatus`], with the lag reported as the
+//! `wal_replication_lag_records` gauge.
+//!
+//! Replication is asynchronous: appends on the leader never wait for
+//! followers. Batches being sent are charged to the WAL's
+//! [memory budget](crate::memory), if it has one. Truncating the leader's log (`truncate_from`) is not
+//! replicated; followers must be re-seeded after one.
+//!
+//! # Protocol
+//!
+//! Every message is a frame: a big-endian u32 length, then a tag byte and
+//! its fields (integers big-endian).
+//!
+//! | Frame     | Direction         | Fields                                          |
+//! |-----------|-------------------|-------------------------------------------------|
+//! | `Hello`   | follower → leader | version: u8, start LSN: u64, name (u16 + UTF-8) |
+//! | `Records` | leader → follower | leader next LSN: u64, count: u32, records       |
+//! | `Ack`     | follower → leader | durable LSN: u64                                |
+//! | `Error`   | leader → follower | message (u16 + UTF-8)                           |
+//!
+//! Records are in their on-disk encoding, CRC included. A `Records` frame
+//! with no records is a heartbeat, sent when the log is idle.
+
+use crate::handle::WalReadHandle;
+use crate::memory::MemoryComponent;
+use crate::record::Record;
+use crate::segment::{Position, SegmentError};
+use bytes::{Buf, BufMut, BytesMut};
+use nori_observe::{Label, Meter};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Version sent in `Hello`; the leader refuses any other.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Largest frame either side accepts.
+pub const MAX_FRAME_LEN: u32 = 256 * 1024 * 1024;
+
+const HELLO_TAG: u8 = b'h';
+const RECORDS_TAG: u8 = b'r';
+const ACK_TAG: u8 = b'a';
+const ERROR_TAG: u8 = b'e';
+
+/// A replication protocol message.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Frame {
+    Hello {
+        version: u8,
+        start_lsn: u64,
+        name: String,
+    },
+    Records {
+        leader_next_lsn: u64,
+        records: Vec<Record>,
+    },
+    Ack {
+        durable_lsn: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl Frame {
+    fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(64);
+        match self {
+            Frame::Hello {
+                version,
+                start_lsn,
+                name,
+            } => {
+                buf.put_u8(HELLO_TAG);
+                buf.put_u8(*version);
+                buf.put_u64(*start_lsn);
+                put_str(&mut buf, name);
+            }
+            Frame::Records {
+                leader_next_lsn,
+                records,
+            } => {
+                buf.put_u8(RECORDS_TAG);
+                buf.put_u64(*leader_next_lsn);
+                buf.put_u32(records.len() as u32);
+                for record in records {
+                    buf.put_slice(&record.encode());
+                }
+            }
+            Frame::Ack { durable_lsn } => {
+                buf.put_u8(ACK_TAG);
+                buf.put_u64(*durable_lsn);
+            }
+            Frame::Error { message } => {
+                buf.put_u8(ERROR_TAG);
+                put_str(&mut buf, message);
+            }
+        }
+        buf
+    }
+
+    fn decode(mut data: &[u8]) -> Result<Self, SegmentError> {
+        let buf = &mut data;
+        let frame = match get_u8(buf)? {
+            HELLO_TAG => Frame::Hello {
+                version: get_u8(buf)?,
+                start_lsn: get_u64(buf)?,
+                name: get_str(buf)?,
+            },
+            RECORDS_TAG => {
+                let leader_next_lsn = get_u64(buf)?;
+                let count = get_u32(buf)?;
+                let mut records = Vec::with_capacity(count.min(4096) as usize);
+                for _ in 0..count {
+                    let (record, len) = Record::decode(buf)?;
+                    buf.advance(len);
+                    records.push(record);
+                }
+                Frame::Records {
+                    leader_next_lsn,
+                    records,
+                }
+            }
+            ACK_TAG => Frame::Ack {
+                durable_lsn: get_u64(buf)?,
+            },
+            ERROR_TAG => Frame::Error {
+                message: get_str(buf)?,
+            },
+            tag => return Err(protocol_error(format!("unknown frame tag {:#x}", tag))),
+        };
+        if buf.has_remaining() {
+            return Err(protocol_error(format!(
+                "{} trailing bytes in frame",
+                buf.remaining()
+            )));
+        }
+        Ok(frame)
+    }
+}
+
+pub(crate) fn protocol_error(message: impl Into<String>) -> SegmentError {
+    SegmentError::Replication(message.into())
+}
+
+fn put_str(buf: &mut BytesMut, s: &str) {
+    let len = s.len().min(u16::MAX as usize);
+    buf.put_u16(len as u16);
+    buf.put_slice(&s.as_bytes()[..len]);
+}
+
+fn need(buf: &&[u8], len: usize) -> Result<(), SegmentError> {
+    if buf.remaining() < len {
+        return Err(protocol_error("frame is truncated"));
+    }
+    Ok(())
+}
+
+fn get_u8(buf: &mut &[u8]) -> Result<u8, SegmentError> {
+    need(buf, 1)?;
+    Ok(buf.get_u8())
+}
+
+fn get_u32(buf: &mut &[u8]) -> Result<u32, SegmentError> {
+    need(buf, 4)?;
+    Ok(buf.get_u32())
+}
+
+fn get_u64(buf: &mut &[u8]) -> Result<u64, SegmentError> {
+    need(buf, 8)?;
+    Ok(buf.get_u64())
+}
+
+fn get_str(buf: &mut &[u8]) -> Result<String, SegmentError> {
+    need(buf, 2)?;
+    let len = buf.get_u16() as usize;
+    need(buf, len)?;
+    let s = String::from_utf8_lossy(&buf[..len]).into_owned();
+    buf.advance(len);
+    Ok(s)
+}
+
+/// Writes `frame` with its length prefix.
+pub(crate) async fn write_frame<W>(writer: &mut W, frame: &Frame) -> Result<(), SegmentError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let body = frame.encode();
+    writer.write_u32(body.len() as u32).await?;
+    writer.write_all(&body).await?;
+    Ok(writer.flush().await?)
+}
+
+/// Reads one frame, or `None` if the peer closed the connection cleanly
+/// between frames.
+pub(crate) async fn read_frame<R>(reader: &mut R) -> Result<Option<Frame>, SegmentError>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(protocol_error(format!("frame of {} bytes", len)));
+    }
+    let mut body = vec![0; len as usize];
+    reader.read_exact(&mut body).await?;
+    Frame::decode(&body).map(Some)
+}
+
+/// Settings for a [`ReplicationServer`].
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// Most records sent in one frame.
+    pub max_batch_records: usize,
+    /// A frame stops growing once its records reach this many bytes.
+    pub max_batch_bytes: usize,
+    /// How often an idle stream sends a heartbeat, which carries the
+    /// leader's next LSN and detects dead connections.
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_records: 512,
+            max_batch_bytes: 1024 * 1024,
+            heartbeat_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Replication progress of one connected follower.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FollowerStatus {
+    /// Name the follower gave in its handshake.
+    pub name: String,
+    pub addr: SocketAddr,
+    pub connected_at: SystemTime,
+    /// LSN the follower asked to start from.
+    pub start_lsn: u64,
+    /// LSN of the last record sent.
+    pub sent_lsn: Option<u64>,
+    /// LSN up to which the follower has made the stream durable.
+    pub acked_lsn: Option<u64>,
+    /// Position in the leader's log just past the last acked record; the
+    /// leader may purge segments before it without losing the follower.
+    pub acked_position: Option<Position>,
+    pub last_ack_at: Option<SystemTime>,
+    /// Records assigned on the leader that the follower has not acked.
+    pub lag_records: u64,
+}
+
+struct Follower {
+    status: FollowerStatus,
+    /// LSNs sent but not yet acked, with the position just past each.
+    in_flight: VecDeque<(u64, Position)>,
+}
+
+impl Follower {
+    fn lag(&self, leader_next_lsn: u64) -> u64 {
+        let durable_next = self
+            .status
+            .acked_lsn
+            .map_or(self.status.start_lsn, |lsn| lsn + 1);
+        leader_next_lsn.saturating_sub(durable_next)
+    }
+}
+
+struct Inner {
+    read: WalReadHandle,
+    config: ReplicationConfig,
+    meter: Arc<dyn Meter>,
+    followers: Mutex<HashMap<u64, Follower>>,
+    next_id: AtomicU64,
+}
+
+/// Streams the log to followers over TCP.
+///
+/// Cloning is cheap and clones share the follower table, so one clone can
+/// serve connections while another reports status.
+#[derive(Clone)]
+pub struct ReplicationServer {
+    inner: Arc<Inner>,
+}
+
+impl ReplicationServer {
+    /// Creates a server that streams the log behind `read`.
+    pub fn new(read: WalReadHandle, config: ReplicationConfig, meter: Arc<dyn Meter>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                read,
+                config,
+                meter,
+                followers: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Accepts followers on `listener`, serving each on its own task, until
+    /// accepting fails.
+    pub async fn serve(&self, listener: TcpListener) -> Result<(), SegmentError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                let _ = server.serve_connection(stream).await;
+            });
+        }
+    }
+
+    /// Serves one follower connection until it closes or fails.
+    ///
+    /// A follower asking for records that have been purged is sent an
+    /// `Error` frame, and the error is returned.
+    pub async fn serve_connection(&self, stream: TcpStream) -> Result<(), SegmentError> {
+        let addr = stream.peer_addr()?;
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+
+        let (start_lsn, name) = match read_frame(&mut reader).await? {
+            Some(Frame::Hello {
+                version: PROTOCOL_VERSION,
+                start_lsn,
+                name,
+            }) => (start_lsn, name),
+            Some(Frame::Hello { version, .. }) => {
+                let message = format!("unsupported protocol version {}", version);
+                write_frame(
+                    &mut writer,
+                    &Frame::Error {
+                        message: message.clone(),
+                    },
+                )
+                .await?;
+                return Err(protocol_error(message));
+            }
+            other => return Err(protocol_error(format!("expected a hello, got {:?}", other))),
+        };
+        let mut tail = match self.inner.read.tail_from_lsn(start_lsn).await {
+            Ok(tail) => tail,
+            Err(e) => {
+                let message = e.to_string();
+                write_frame(&mut writer, &Frame::Error { message }).await?;
+                return Err(e);
+            }
+        };
+
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        self.inner.followers().insert(
+            id,
+            Follower {
+                status: FollowerStatus {
+                    name,
+                    addr,
+                    connected_at: SystemTime::now(),
+                    start_lsn,
+                    sent_lsn: None,
+                    acked_lsn: None,
+                    acked_position: None,
+                    last_ack_at: None,
+                    lag_records: 0,
+                },
+                in_flight: VecDeque::new(),
+            },
+        );
+        let mut acks = tokio::spawn(self.clone().read_acks(reader, id));
+
+        let result = async {
+            let config = &self.inner.config;
+            loop {
+                let first = tokio::select! {
+                    next = tail.next_record() => Some(next?),
+                    _ = tokio::time::sleep(config.heartbeat_interval) => None,
+                    // The follower hung up or broke the protocol
+                    _ = &mut acks => return Ok(()),
+                };
+                let mut records = Vec::new();
+                let mut bytes = 0;
+                // The batch is charged to the memory budget until it is sent
+                let mut memory = None;
+                let mut within_budget = true;
+                if let Some((record, _)) = first {
+                    bytes += record.key.len() + record.value.len();
+                    if let Some(budget) = self.inner.read.memory_budget() {
+                        memory = Some(
+                            budget
+                                .reserve(MemoryComponent::Replication, bytes as u64)
+                                .await,
+                        );
+                        within_budget = budget.fits(0);
+                    }
+                    records.push(record);
+                }
+                while !records.is_empty()
+                    && records.len() < config.max_batch_records
+                    && bytes < config.max_batch_bytes
+                    && within_budget
+                {
+                    // A zero timeout still polls once, taking only what is
+                    // already durable
+                    match tokio::time::timeout(Duration::ZERO, tail.next_record()).await {
+                        Ok(next) => {
+                            let (record, _) = next?;
+                            let size = record.key.len() + record.value.len();
+                            bytes += size;
+                            if let Some(memory) = &mut memory {
+                                within_budget = memory.grow(size as u64);
+                            }
+                            records.push(record);
+                        }
+                        Err(_) => break,
+                    }
+                }
+
+                let last_lsn = records.last().and_then(|record| record.lsn);
+                let count = records.len();
+                let frame = Frame::Records {
+                    leader_next_lsn: self.inner.read.next_lsn(),
+                    records,
+                };
+                write_frame(&mut writer, &frame).await?;
+                drop(memory);
+                self.inner
+                    .meter
+                    .counter("wal_replication_records_sent_total", &[])
+                    .inc(count as u64);
+                if let Some(lsn) = last_lsn {
+                    let mut followers = self.inner.followers();
+                    if let Some(follower) = followers.get_mut(&id) {
+                        follower.status.sent_lsn = Some(lsn);
+                        follower.in_flight.push_back((lsn, tail.position()));
+                    }
+                }
+            }
+        }
+        .await;
+
+        acks.abort();
+        if let Some(follower) = self.inner.followers().remove(&id) {
+            self.inner.report_lag(&follower.status.name, 0);
+        }
+        result
+    }
+
+    /// Returns the status of every connected follower.
+    pub fn followers(&self) -> Vec<FollowerStatus> {
+        let leader_next_lsn = self.inner.read.next_lsn();
+        let followers = self.inner.followers();
+        let mut statuses: Vec<FollowerStatus> = followers
+            .values()
+            .map(|follower| FollowerStatus {
+                lag_records: follower.lag(leader_next_lsn),
+                ..follower.status.clone()
+            })
+            .collect();
+        statuses.sort_by_key(|status| status.connected_at);
+        statuses
+    }
+
+    /// Applies acks from a follower until it disconnects.
+    async fn read_acks(self, mut reader: OwnedReadHalf, id: u64) {
+        while let Ok(Some(Frame::Ack { durable_lsn })) = read_frame(&mut reader).await {
+            let leader_next_lsn = self.inner.read.next_lsn();
+            let mut followers = self.inner.followers();
+            let Some(follower) = followers.get_mut(&id) else {
+                return;
+            };
+            if follower
+                .status
+                .acked_lsn
+                .is_some_and(|acked| acked >= durable_lsn)
+            {
+                continue;
+            }
+            follower.status.acked_lsn = Some(durable_lsn);
+            follower.status.last_ack_at = Some(SystemTime::now());
+            while let Some(&(lsn, position)) = follower.in_flight.front() {
+                if lsn > durable_lsn {
+                    break;
+                }
+                follower.status.acked_position = Some(position);
+                follower.in_flight.pop_front();
+            }
+            let lag = follower.lag(leader_next_lsn);
+            let name = follower.status.name.clone();
+            drop(followers);
+            self.inner.report_lag(&name, lag);
+        }
+    }
+}
+
+impl Inner {
+    fn followers(&self) -> MutexGuard<'_, HashMap<u64, Follower>> {
+        self.followers.lock().expect("follower table poisoned")
+    }
+
+    fn report_lag(&self, name: &str, lag: u64) {
+        let labels: [Label; 1] = [("follower", Cow::Owned(name.to_string()))];
+        self.meter
+            .gauge_with("wal_replication_lag_records", &labels)
+            .set(lag as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{Wal, WalConfig};
+    use tempfile::TempDir;
+
+    async fn open(dir: &TempDir) -> Wal {
+        let config = WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        Wal::open(config).await.unwrap().0
+    }
+
+    async fn serve(wal: &Wal) -> (ReplicationServer, SocketAddr) {
+        let config = ReplicationConfig {
+            max_batch_records: 3,
+            heartbeat_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let server = wal.replication_server(config);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let background = server.clone();
+        tokio::spawn(async move { background.serve(listener).await });
+        (server, addr)
+    }
+
+    /// Polls `done` until it holds, failing after five seconds.
+    async fn wait_until(mut done: impl FnMut() -> bool) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(tokio::time::Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    async fn hello(addr: SocketAddr, start_lsn: u64) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let hello = Frame::Hello {
+            version: PROTOCOL_VERSION,
+            start_lsn,
+            name: "replica-1".to_string(),
+        };
+        write_frame(&mut stream, &hello).await.unwrap();
+        stream
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frames = [
+            Frame::Hello {
+                version: 1,
+                start_lsn: 9,
+                name: "a".to_string(),
+            },
+            Frame::Records {
+                leader_next_lsn: 12,
+                records: vec![
+                    Record::put(b"k".as_slice(), b"v".as_slice()).with_lsn(10),
+                    Record::delete(b"k".as_slice()).with_lsn(11),
+                ],
+            },
+            Frame::Ack { durable_lsn: 11 },
+            Frame::Error {
+                message: "purged".to_string(),
+            },
+        ];
+        for frame in frames {
+            let encoded = frame.encode();
+            assert_eq!(Frame::decode(&encoded).unwrap(), frame);
+            assert!(Frame::decode(&encoded[..encoded.len() - 1]).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streams_from_lsn_and_tracks_acks() {
+        let dir = TempDir::new().unwrap();
+        let wal = open(&dir).await;
+        for i in 0..5u32 {
+            wal.append(&Record::put(i.to_be_bytes().to_vec(), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+        wal.sync().await.unwrap();
+        let (server, addr) = serve(&wal).await;
+
+        let mut stream = hello(addr, 2).await;
+        let mut lsns = Vec::new();
+        while lsns.len() < 4 {
+            match read_frame(&mut stream).await.unwrap().unwrap() {
+                Frame::Records {
+                    leader_next_lsn,
+                    records,
+                } => {
+                    assert_eq!(leader_next_lsn, 6);
+                    assert!(records.len() <= 3);
+                    lsns.extend(records.iter().map(|r| r.lsn.unwrap()));
+                }
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+        assert_eq!(lsns, [2, 3, 4, 5]);
+
+        // The server records what it sent just after sending it
+        wait_until(|| server.followers()[0].sent_lsn == Some(5)).await;
+        let status = server.followers();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].name, "replica-1");
+        assert_eq!(status[0].lag_records, 4);
+
+        // New appends are streamed as they become durable
+        wal.append(&Record::put(b"late".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        write_frame(&mut stream, &Frame::Ack { durable_lsn: 5 })
+            .await
+            .unwrap();
+        loop {
+            if let Frame::Records { records, .. } = read_frame(&mut stream).await.unwrap().unwrap()
+            {
+                if let Some(record) = records.first() {
+                    assert_eq!(record.lsn, Some(6));
+                    break;
+                }
+            }
+        }
+        write_frame(&mut stream, &Frame::Ack { durable_lsn: 6 })
+            .await
+            .unwrap();
+
+        let durable = wal.durable_position().await;
+        wait_until(|| server.followers()[0].acked_position == Some(durable)).await;
+        assert_eq!(server.followers()[0].lag_records, 0);
+
+        drop(stream);
+        wait_until(|| server.followers().is_empty()).await;
+    }
+
+    #[tokio::test]
+    async fn test_refuses_lsns_past_the_log() {
+        let dir = TempDir::new().unwrap();
+        let wal = open(&dir).await;
+        let (server, addr) = serve(&wal).await;
+
+        let mut stream = hello(addr, 50).await;
+        assert!(matches!(
+            read_frame(&mut stream).await.unwrap(),
+            Some(Frame::Error { .. })
+        ));
+        assert!(server.followers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batches_stay_within_memory_budget() {
+        let dir = TempDir::new().unwrap();
+        let budget = crate::memory::MemoryBudget::new(16);
+        let config = WalConfig {
+            dir: dir.path().to_path_buf(),
+            memory_budget: Some(budget.clone()),
+            ..Default::default()
+        };
+        let wal = Wal::open(config).await.unwrap().0;
+        for i in 0..6u32 {
+            wal.append(&Record::put(
+                i.to_be_bytes().to_vec(),
+                [b'v'; 10].as_slice(),
+            ))
+            .await
+            .unwrap();
+        }
+        wal.sync().await.unwrap();
+        let (_server, addr) = serve(&wal).await;
+
+        // Reading the log alone takes the budget, so batches stop at one record
+        let mut stream = hello(addr, 1).await;
+        let mut lsns = Vec::new();
+        while lsns.len() < 6 {
+            if let Frame::Records { records, .. } = read_frame(&mut stream).await.unwrap().unwrap()
+            {
+                assert!(records.len() <= 1);
+                lsns.extend(records.iter().map(|r| r.lsn.unwrap()));
+            }
+        }
+        assert_eq!(lsns, [1, 2, 3, 4, 5, 6]);
+        wait_until(|| budget.component_used(MemoryComponent::Replication) == 0).await;
+    }
+}
@@ -0,0 +1,259 @@
+//! Machine-readable recovery reports.
+//!
+//! When enabled, every recovery performed by `Wal::open` writes a JSON file
+//! under `recovery/` in the WAL directory describing what was scanned, where
+//! segments were cut, and how long it took. Only the newest reports are kept.
+
+use crate::fs::{self, Fs, LocalFs};
+use crate::recovery::{RecoveryInfo, RecoveryMode};
+use crate::segment::{Position, SegmentError};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the subdirectory that receives recovery reports.
+pub const REPORT_DIR: &str = "recovery";
+
+/// Writes a report for one recovery and prunes all but the newest `keep`.
+///
+/// `outcome` is the recovery result; failed recoveries are reported too, with
+/// the error message and, for corruption, where it was found. Returns the
+/// path of the new report.
+pub async fn write_recovery_report(
+    wal_dir: &Path,
+    mode: RecoveryMode,
+    started_at: SystemTime,
+    duration: Duration,
+    outcome: Result<&RecoveryInfo, &SegmentError>,
+    keep: usize,
+) -> Result<PathBuf, SegmentError> {
+    write_recovery_report_on(&LocalFs, wal_dir, mode, started_at, duration, outcome, keep).await
+}
+
+/// Like [`write_recovery_report`], for a WAL stored on `fs`.
+pub(crate) async fn write_recovery_report_on(
+    fs: &dyn Fs,
+    wal_dir: &Path,
+    mode: RecoveryMode,
+    started_at: SystemTime,
+    duration: Duration,
+    outcome: Result<&RecoveryInfo, &SegmentError>,
+    keep: usize,
+) -> Result<PathBuf, SegmentError> {
+    let dir = wal_dir.join(REPORT_DIR);
+    fs.create_dir_all(&dir).await?;
+
+    let since_epoch = started_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let empty = RecoveryInfo::default();
+    let (info, error) = match outcome {
+        Ok(info) => (info, None),
+        Err(e) => (&empty, Some(e)),
+    };
+
+    let mut json = String::new();
+    let _ = write!(
+        json,
+        "{{\"started_at_ms\":{},\"duration_ms\":{},\"mode\":\"{}\",\"outcome\":\"{}\"",
+        since_epoch.as_millis(),
+        duration.as_millis(),
+        mode_name(mode),
+        if error.is_some() { "error" } else { "ok" },
+    );
+    match error {
+        Some(e) => {
+            let _ = write!(json, ",\"error\":{}", json_string(&e.to_string()));
+            if let SegmentError::Corruption { segment_id, offset } = e {
+                let _ = write!(
+                    json,
+                    ",\"corruption\":{}",
+                    json_position(Some(Position {
+                        segment_id: *segment_id,
+                        offset: *offset,
+                    }))
+                );
+            }
+        }
+        None => json.push_str(",\"error\":null"),
+    }
+    let _ = write!(
+        json,
+        ",\"segments_scanned\":{},\"bytes_scanned\":{},\"valid_records\":{},\"bytes_truncated\":{},\"corruption_detected\":{},\"quarantined_bytes\":{},\"expired_records\":{}",
+        info.segments_scanned,
+        info.bytes_scanned,
+        info.valid_records,
+        info.bytes_truncated,
+        info.corruption_detected,
+        info.quarantined_bytes,
+        info.expired_records,
+    );
+    let _ = write!(
+        json,
+        ",\"last_valid_position\":{},\"last_lsn\":{},\"stopped_at\":{},\"pending_from_segment\":{}",
+        json_position(info.last_valid_position),
+        info.last_lsn
+            .map_or("null".to_string(), |lsn| lsn.to_string()),
+        json_position(info.stopped_at),
+        info.pending
+            .map_or("null".to_string(), |p| p.next_segment.to_string()),
+    );
+    let points: Vec<String> = info
+        .truncation_points
+        .iter()
+        .map(|p| json_position(Some(*p)))
+        .collect();
+    let gaps: Vec<String> = info
+        .gaps
+        .iter()
+        .map(|g| {
+            format!(
+                "{{\"segment_id\":{},\"offset\":{},\"len\":{}}}",
+                g.segment_id, g.offset, g.len
+            )
+        })
+        .collect();
+    let _ = writeln!(
+        json,
+        ",\"truncation_points\":[{}],\"gaps\":[{}]}}",
+        points.join(","),
+        gaps.join(",")
+    );
+
+    // Nanosecond names sort chronologically and do not collide in practice
+    let path = dir.join(format!("{:020}.json", since_epoch.as_nanos()));
+    fs::write_synced(fs, &path, json.as_bytes()).await?;
+
+    prune_reports(fs, &dir, keep).await?;
+    fs.sync_dir(&dir).await?;
+    Ok(path)
+}
+
+/// Removes the oldest reports so that at most `keep` remain.
+async fn prune_reports(fs: &dyn Fs, dir: &Path, keep: usize) -> Result<(), SegmentError> {
+    let mut reports: Vec<PathBuf> = fs
+        .read_dir(dir)
+        .await?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    reports.sort();
+
+    let excess = reports.len().saturating_sub(keep);
+    for path in &reports[..excess] {
+        fs.remove_file(path).await?;
+    }
+    Ok(())
+}
+
+fn mode_name(mode: RecoveryMode) -> &'static str {
+    match mode {
+        RecoveryMode::FailOnCorruption => "fail_on_corruption",
+        RecoveryMode::TruncateTail => "truncate_tail",
+        RecoveryMode::SkipBadRecords => "skip_bad_records",
+    }
+}
+
+fn json_position(position: Option<Position>) -> String {
+    match position {
+        Some(p) => format!(
+            "{{\"segment_id\":{},\"offset\":{}}}",
+            p.segment_id, p.offset
+        ),
+        None => "null".to_string(),
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recovery::RecoveryGap;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_report_contents_and_pruning() {
+        let temp_dir = TempDir::new().unwrap();
+        let info = RecoveryInfo {
+            segments_scanned: 2,
+            valid_records: 10,
+            bytes_truncated: 7,
+            corruption_detected: true,
+            truncation_points: vec![Position {
+                segment_id: 1,
+                offset: 99,
+            }],
+            gaps: vec![RecoveryGap {
+                segment_id: 0,
+                offset: 5,
+                len: 3,
+            }],
+            ..Default::default()
+        };
+
+        let mut paths = Vec::new();
+        for i in 0..4u64 {
+            let started_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000 + i);
+            let path = write_recovery_report(
+                temp_dir.path(),
+                RecoveryMode::TruncateTail,
+                started_at,
+                Duration::from_millis(12),
+                Ok(&info),
+                2,
+            )
+            .await
+            .unwrap();
+            paths.push(path);
+        }
+
+        // Only the two newest survive
+        assert!(!paths[0].exists());
+        assert!(!paths[1].exists());
+        let report = std::fs::read_to_string(&paths[3]).unwrap();
+        assert!(report.starts_with("{\"started_at_ms\":1700000003000,\"duration_ms\":12,"));
+        assert!(report.contains("\"outcome\":\"ok\""));
+        assert!(report.contains("\"truncation_points\":[{\"segment_id\":1,\"offset\":99}]"));
+        assert!(report.contains("\"gaps\":[{\"segment_id\":0,\"offset\":5,\"len\":3}]"));
+    }
+
+    #[tokio::test]
+    async fn test_report_for_failed_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let error = SegmentError::Corruption {
+            segment_id: 3,
+            offset: 128,
+        };
+
+        let path = write_recovery_report(
+            temp_dir.path(),
+            RecoveryMode::FailOnCorruption,
+            SystemTime::now(),
+            Duration::ZERO,
+            Err(&error),
+            5,
+        )
+        .await
+        .unwrap();
+
+        let report = std::fs::read_to_string(path).unwrap();
+        assert!(report.contains("\"outcome\":\"error\""));
+        assert!(report.contains("\"error\":\"Corruption in segment 3 at offset 128\""));
+        assert!(report.contains("\"corruption\":{\"segment_id\":3,\"offset\":128}"));
+    }
+}
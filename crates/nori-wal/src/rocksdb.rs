@@ -0,0 +1,781 @@
+//! Importing RocksDB and LevelDB write-ahead logs.
+//!
+//! Both keep their WAL as `NNNNNN.log` files of 32 KiB blocks holding
+//! checksummed fragments, which join into write batches. [`RocksLogReader`]
+//! reads the batches of one file and [`import`] appends their writes to a
+//! nori WAL, file by file in number order:
+//!
+//! ```no_run
+//! use nori_wal::{RocksImportConfig, Wal, WalConfig};
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let summary = nori_wal::rocksdb::import(&wal, "/var/lib/app/rocksdb", RocksImportConfig::default()).await?;
+//! println!("imported {} writes up to sequence {:?}", summary.records, summary.last_sequence);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Writes map onto records as follows:
+//! - A put becomes a put, and a delete or single delete a delete
+//! - The column family ID becomes the namespace; the default family (0) has
+//!   none
+//! - With [`RocksImportConfig::keep_sequences`], the sequence number becomes
+//!   the LSN
+//! - Merges have no nori equivalent; [`RocksImportConfig::merges`] says
+//!   whether to fail, skip them, or take the operand as the new value
+//!
+//! Range deletes, BlobDB references, wide-column entities and the markers of
+//! two-phase-commit transactions fail the import unless
+//! [`RocksImportConfig::skip_unsupported`] is set. Log data blobs carry no
+//! writes and are always skipped.
+//!
+//! Reading stops quietly at a record cut short by the end of the file, the
+//! torn tail left by a crash, and fails on a checksum mismatch anywhere
+//! else. Only the files are read, so they can be copied from a live store,
+//! but the last one may be torn mid-write.
+
+use crate::import::IterStream;
+use crate::record::Record;
+use crate::segment::SegmentError;
+use crate::wal::Wal;
+use bytes::{Buf, Bytes};
+use std::path::{Path, PathBuf};
+
+/// Size of a log block.
+const BLOCK_SIZE: usize = 32 * 1024;
+
+/// Checksum, length and type.
+const HEADER_SIZE: usize = 7;
+
+/// Checksum, length, type and log number, for recycled log files.
+const RECYCLABLE_HEADER_SIZE: usize = 11;
+
+/// Added to rotated checksums, as LevelDB masks CRCs of stored data.
+const CRC_MASK_DELTA: u32 = 0xa282_ead8;
+
+/// Fragment types. The recyclable ones (5 to 8) are the same plus 4.
+const FULL: u8 = 1;
+const FIRST: u8 = 2;
+const MIDDLE: u8 = 3;
+const LAST: u8 = 4;
+
+/// Write batch entry tags.
+const TAG_DELETION: u8 = 0x0;
+const TAG_VALUE: u8 = 0x1;
+const TAG_MERGE: u8 = 0x2;
+const TAG_LOG_DATA: u8 = 0x3;
+const TAG_CF_DELETION: u8 = 0x4;
+const TAG_CF_VALUE: u8 = 0x5;
+const TAG_CF_MERGE: u8 = 0x6;
+const TAG_SINGLE_DELETION: u8 = 0x7;
+const TAG_CF_SINGLE_DELETION: u8 = 0x8;
+const TAG_BEGIN_PREPARE: u8 = 0x9;
+const TAG_END_PREPARE: u8 = 0xA;
+const TAG_COMMIT: u8 = 0xB;
+const TAG_ROLLBACK: u8 = 0xC;
+const TAG_NOOP: u8 = 0xD;
+const TAG_CF_RANGE_DELETION: u8 = 0xE;
+const TAG_RANGE_DELETION: u8 = 0xF;
+const TAG_CF_BLOB_INDEX: u8 = 0x10;
+const TAG_BLOB_INDEX: u8 = 0x11;
+const TAG_BEGIN_PERSISTED_PREPARE: u8 = 0x12;
+const TAG_BEGIN_UNPREPARE: u8 = 0x13;
+const TAG_COMMIT_WITH_TIMESTAMP: u8 = 0x15;
+const TAG_WIDE_COLUMN_ENTITY: u8 = 0x16;
+const TAG_CF_WIDE_COLUMN_ENTITY: u8 = 0x17;
+
+/// What to do with merge operands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeHandling {
+    /// Fail the import (the default).
+    #[default]
+    Reject,
+    /// Leave them out.
+    Skip,
+    /// Write the operand as the key's new value, right for merge operators
+    /// that replace the value and wrong for any that combine.
+    AsPut,
+}
+
+/// Settings for [`import`].
+#[derive(Debug, Clone, Default)]
+pub struct RocksImportConfig {
+    /// What to do with merges (default: fail).
+    pub merges: MergeHandling,
+    /// Leave out writes nori cannot represent instead of failing
+    /// (default: false).
+    pub skip_unsupported: bool,
+    /// Use sequence numbers as LSNs instead of numbering records from the
+    /// end of the WAL. Only sensible for an empty WAL (default: false).
+    pub keep_sequences: bool,
+}
+
+/// One entry of a write batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RocksOp {
+    Put {
+        column_family: u32,
+        key: Bytes,
+        value: Bytes,
+    },
+    Delete {
+        column_family: u32,
+        key: Bytes,
+    },
+    SingleDelete {
+        column_family: u32,
+        key: Bytes,
+    },
+    Merge {
+        column_family: u32,
+        key: Bytes,
+        value: Bytes,
+    },
+    /// Deletes the keys from `begin` up to, not including, `end`.
+    DeleteRange {
+        column_family: u32,
+        begin: Bytes,
+        end: Bytes,
+    },
+    /// A BlobDB reference or a wide-column entity, with its tag.
+    Indirect {
+        tag: u8,
+        column_family: u32,
+        key: Bytes,
+        value: Bytes,
+    },
+    /// Application data logged with the batch; not a write.
+    LogData(Bytes),
+    /// A two-phase-commit marker, with its tag.
+    Marker(u8),
+}
+
+impl RocksOp {
+    /// Returns true if the entry takes a sequence number.
+    fn is_write(&self) -> bool {
+        !matches!(self, RocksOp::LogData(_) | RocksOp::Marker(_))
+    }
+}
+
+/// A write batch: entries applied atomically, numbered from `sequence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RocksBatch {
+    /// Sequence number of the first write.
+    pub sequence: u64,
+    pub ops: Vec<RocksOp>,
+}
+
+impl RocksBatch {
+    /// Parses a batch as stored in a log record.
+    pub fn decode(mut data: &[u8]) -> Result<Self, SegmentError> {
+        if data.len() < 12 {
+            return Err(foreign("write batch shorter than its header"));
+        }
+        let sequence = data.get_u64_le();
+        let count = data.get_u32_le();
+        let mut ops = Vec::new();
+        while data.has_remaining() {
+            let tag = data.get_u8();
+            let column_family = match tag {
+                TAG_CF_DELETION
+                | TAG_CF_VALUE
+                | TAG_CF_MERGE
+                | TAG_CF_SINGLE_DELETION
+                | TAG_CF_RANGE_DELETION
+                | TAG_CF_BLOB_INDEX
+                | TAG_CF_WIDE_COLUMN_ENTITY => get_varint32(&mut data)?,
+                _ => 0,
+            };
+            let op = match tag {
+                TAG_VALUE | TAG_CF_VALUE => RocksOp::Put {
+                    column_family,
+                    key: get_slice(&mut data)?,
+                    value: get_slice(&mut data)?,
+                },
+                TAG_DELETION | TAG_CF_DELETION => RocksOp::Delete {
+                    column_family,
+                    key: get_slice(&mut data)?,
+                },
+                TAG_SINGLE_DELETION | TAG_CF_SINGLE_DELETION => RocksOp::SingleDelete {
+                    column_family,
+                    key: get_slice(&mut data)?,
+                },
+                TAG_MERGE | TAG_CF_MERGE => RocksOp::Merge {
+                    column_family,
+                    key: get_slice(&mut data)?,
+                    value: get_slice(&mut data)?,
+                },
+                TAG_RANGE_DELETION | TAG_CF_RANGE_DELETION => RocksOp::DeleteRange {
+                    column_family,
+                    begin: get_slice(&mut data)?,
+                    end: get_slice(&mut data)?,
+                },
+                TAG_BLOB_INDEX
+                | TAG_CF_BLOB_INDEX
+                | TAG_WIDE_COLUMN_ENTITY
+                | TAG_CF_WIDE_COLUMN_ENTITY => RocksOp::Indirect {
+                    tag,
+                    column_family,
+                    key: get_slice(&mut data)?,
+                    value: get_slice(&mut data)?,
+                },
+                TAG_LOG_DATA => RocksOp::LogData(get_slice(&mut data)?),
+                TAG_NOOP
+                | TAG_BEGIN_PREPARE
+                | TAG_BEGIN_PERSISTED_PREPARE
+                | TAG_BEGIN_UNPREPARE => RocksOp::Marker(tag),
+                TAG_END_PREPARE | TAG_COMMIT | TAG_ROLLBACK => {
+                    get_slice(&mut data)?;
+                    RocksOp::Marker(tag)
+                }
+                TAG_COMMIT_WITH_TIMESTAMP => {
+                    get_slice(&mut data)?;
+                    get_slice(&mut data)?;
+                    RocksOp::Marker(tag)
+                }
+                tag => return Err(foreign(format!("unknown write batch tag {:#x}", tag))),
+            };
+            ops.push(op);
+        }
+
+        let writes = ops.iter().filter(|op| op.is_write()).count();
+        if writes != count as usize {
+            return Err(foreign(format!(
+                "write batch at sequence {} holds {} writes, header says {}",
+                sequence, writes, count
+            )));
+        }
+        Ok(Self { sequence, ops })
+    }
+
+    /// Converts the batch's writes to records, following `config`.
+    /// Returns the records and how many writes were left out.
+    pub fn to_records(
+        &self,
+        config: &RocksImportConfig,
+    ) -> Result<(Vec<Record>, u64), SegmentError> {
+        let mut records = Vec::with_capacity(self.ops.len());
+        let mut skipped = 0;
+        let mut sequence = self.sequence;
+        for op in &self.ops {
+            let record = match op {
+                RocksOp::Put {
+                    column_family,
+                    key,
+                    value,
+                } => Some((Record::put(key.clone(), value.clone()), *column_family)),
+                RocksOp::Delete { column_family, key }
+                | RocksOp::SingleDelete { column_family, key } => {
+                    Some((Record::delete(key.clone()), *column_family))
+                }
+                RocksOp::Merge {
+                    column_family,
+                    key,
+                    value,
+                } => match config.merges {
+                    MergeHandling::Reject => {
+                        return Err(foreign(format!("merge at sequence {}", sequence)))
+                    }
+                    MergeHandling::Skip => None,
+                    MergeHandling::AsPut => {
+                        Some((Record::put(key.clone(), value.clone()), *column_family))
+                    }
+                },
+                RocksOp::LogData(_) => continue,
+                RocksOp::DeleteRange { .. } | RocksOp::Indirect { .. } | RocksOp::Marker(_) => {
+                    if !config.skip_unsupported {
+                        return Err(foreign(format!(
+                            "unsupported write at sequence {}: {:?}",
+                            sequence, op
+                        )));
+                    }
+                    None
+                }
+            };
+            match record {
+                Some((mut record, column_family)) => {
+                    record.namespace = (column_family != 0).then_some(column_family);
+                    if config.keep_sequences {
+                        record.lsn = Some(sequence);
+                    }
+                    records.push(record);
+                }
+                None => skipped += op.is_write() as u64,
+            }
+            if op.is_write() {
+                sequence += 1;
+            }
+        }
+        Ok((records, skipped))
+    }
+}
+
+/// Reads the write batches of one RocksDB or LevelDB log file.
+///
+/// An iterator of batches; stops at the end of the file or a torn tail, and
+/// yields an error, then nothing, at a damaged record.
+#[derive(Debug)]
+pub struct RocksLogReader {
+    data: Vec<u8>,
+    offset: usize,
+    /// Log number of a recycled file's records, from the first one read.
+    log_number: Option<u32>,
+    torn: bool,
+    done: bool,
+}
+
+impl RocksLogReader {
+    /// Reads the log file at `path` into memory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SegmentError> {
+        Ok(Self::from_bytes(std::fs::read(path)?))
+    }
+
+    /// Reads a log held in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            offset: 0,
+            log_number: None,
+            torn: false,
+            done: false,
+        }
+    }
+
+    /// Returns true if reading stopped at a record cut short by the end of
+    /// the file.
+    pub fn is_torn(&self) -> bool {
+        self.torn
+    }
+
+    /// Reads the next fragment, or `None` at the end of the log.
+    fn next_fragment(&mut self) -> Result<Option<(u8, &[u8])>, SegmentError> {
+        loop {
+            let left_in_block = BLOCK_SIZE - self.offset % BLOCK_SIZE;
+            if left_in_block < HEADER_SIZE {
+                // Trailer padding
+                self.offset += left_in_block;
+                continue;
+            }
+            if self.offset + HEADER_SIZE > self.data.len() {
+                self.torn |= self.offset < self.data.len();
+                return Ok(None);
+            }
+
+            let header = &self.data[self.offset..];
+            let length = u16::from_le_bytes([header[4], header[5]]) as usize;
+            let kind = header[6];
+            if kind == 0 && length == 0 {
+                // Preallocated space, or padding a writer left for a
+                // recyclable header that did not fit
+                if self.data[self.offset..].iter().all(|&b| b == 0) {
+                    return Ok(None);
+                }
+                self.offset += left_in_block;
+                continue;
+            }
+            let header_size = match kind {
+                FULL..=LAST => HEADER_SIZE,
+                5..=8 => RECYCLABLE_HEADER_SIZE,
+                kind => return Err(self.damaged(format!("unknown record type {}", kind))),
+            };
+            if header_size + length > left_in_block {
+                return Err(self.damaged("record crosses a block boundary"));
+            }
+            if self.offset + header_size + length > self.data.len() {
+                self.torn = true;
+                return Ok(None);
+            }
+
+            let stored = u32::from_le_bytes(header[..4].try_into().unwrap());
+            let checked = &header[6..header_size + length];
+            if unmask(stored) != crc32c::crc32c(checked) {
+                return Err(self.damaged("checksum mismatch"));
+            }
+            let start = self.offset;
+            self.offset += header_size + length;
+            if header_size == RECYCLABLE_HEADER_SIZE {
+                // Records from the file's previous use end this one
+                let number = u32::from_le_bytes(header[7..11].try_into().unwrap());
+                if *self.log_number.get_or_insert(number) != number {
+                    return Ok(None);
+                }
+            }
+            let payload = &self.data[start + header_size..start + header_size + length];
+            return Ok(Some((if kind > LAST { kind - 4 } else { kind }, payload)));
+        }
+    }
+
+    /// Reads the next batch's bytes, joining fragments.
+    fn next_record(&mut self) -> Result<Option<Vec<u8>>, SegmentError> {
+        let mut record: Option<Vec<u8>> = None;
+        loop {
+            let Some((kind, payload)) = self.next_fragment()? else {
+                self.torn |= record.is_some();
+                return Ok(None);
+            };
+            match (kind, record.as_mut()) {
+                (FULL, None) => return Ok(Some(payload.to_vec())),
+                (FIRST, None) => record = Some(payload.to_vec()),
+                (MIDDLE, Some(partial)) => partial.extend_from_slice(payload),
+                (LAST, Some(partial)) => {
+                    partial.extend_from_slice(payload);
+                    return Ok(record);
+                }
+                _ => return Err(self.damaged(format!("unexpected fragment type {}", kind))),
+            }
+        }
+    }
+
+    fn damaged(&mut self, message: impl std::fmt::Display) -> SegmentError {
+        self.done = true;
+        foreign(format!("{} at offset {}", message, self.offset))
+    }
+}
+
+impl Iterator for RocksLogReader {
+    type Item = Result<RocksBatch, SegmentError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = match self.next_record() {
+            Ok(Some(record)) => RocksBatch::decode(&record).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(Some(batch)) => Some(Ok(batch)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Outcome of an [`import`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RocksImportSummary {
+    /// Log files read.
+    pub files: u64,
+    /// Write batches read.
+    pub batches: u64,
+    /// Records appended to the WAL.
+    pub records: u64,
+    /// Writes left out: merges, and unsupported writes when skipping them.
+    pub skipped: u64,
+    /// Sequence number of the last write read.
+    pub last_sequence: Option<u64>,
+    /// True if the last file ended in a torn record.
+    pub torn_tail: bool,
+}
+
+/// Returns the log files in `dir` in number order, or `path` itself if it
+/// is a file.
+pub fn log_files(path: &Path) -> Result<Vec<PathBuf>, SegmentError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|number| number.parse::<u64>().ok());
+        if let Some(number) = number {
+            files.push((number, path));
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Appends the writes of the RocksDB or LevelDB logs at `path`, a file or
+/// a database directory, to `wal`.
+///
+/// Each file is read into memory and imported with [`Wal::import`] in turn.
+/// On error, the files before the failing one are in the WAL, and that
+/// one's writes are not.
+pub async fn import(
+    wal: &Wal,
+    path: impl AsRef<Path>,
+    config: RocksImportConfig,
+) -> Result<RocksImportSummary, SegmentError> {
+    let mut summary = RocksImportSummary::default();
+    for file in log_files(path.as_ref())? {
+        let mut reader = RocksLogReader::open(&file)?;
+        let mut records = Vec::new();
+        for batch in reader.by_ref() {
+            let batch = batch?;
+            let (batch_records, skipped) = batch.to_records(&config)?;
+            let writes = batch.ops.iter().filter(|op| op.is_write()).count() as u64;
+            if writes > 0 {
+                summary.last_sequence = Some(batch.sequence + writes - 1);
+            }
+            records.extend(batch_records);
+            summary.batches += 1;
+            summary.skipped += skipped;
+        }
+        summary.records += wal.import(IterStream(records.into_iter())).await?.records;
+        summary.files += 1;
+        summary.torn_tail = reader.is_torn();
+    }
+    Ok(summary)
+}
+
+/// Undoes LevelDB's CRC masking.
+fn unmask(masked: u32) -> u32 {
+    let rot = masked.wrapping_sub(CRC_MASK_DELTA);
+    rot.rotate_left(15)
+}
+
+fn get_varint32(data: &mut &[u8]) -> Result<u32, SegmentError> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        if !data.has_remaining() {
+            break;
+        }
+        let byte = data.get_u8();
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(foreign("bad varint in write batch"))
+}
+
+fn get_slice(data: &mut &[u8]) -> Result<Bytes, SegmentError> {
+    let len = get_varint32(data)? as usize;
+    if data.len() < len {
+        return Err(foreign("truncated write batch"));
+    }
+    let slice = Bytes::copy_from_slice(&data[..len]);
+    data.advance(len);
+    Ok(slice)
+}
+
+fn foreign(message: impl Into<String>) -> SegmentError {
+    SegmentError::ForeignLog(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalConfig;
+    use futures::StreamExt;
+    use tempfile::TempDir;
+
+    fn mask(crc: u32) -> u32 {
+        crc.rotate_right(15).wrapping_add(CRC_MASK_DELTA)
+    }
+
+    fn put_varint32(buf: &mut Vec<u8>, mut value: u32) {
+        while value >= 0x80 {
+            buf.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn put_slice(buf: &mut Vec<u8>, data: &[u8]) {
+        put_varint32(buf, data.len() as u32);
+        buf.extend_from_slice(data);
+    }
+
+    /// Encodes a batch entry the way RocksDB's WriteBatch does.
+    fn entry(tag: u8, column_family: Option<u32>, slices: &[&str]) -> Vec<u8> {
+        let mut buf = vec![tag];
+        if let Some(cf) = column_family {
+            put_varint32(&mut buf, cf);
+        }
+        for slice in slices {
+            put_slice(&mut buf, slice.as_bytes());
+        }
+        buf
+    }
+
+    fn batch(sequence: u64, count: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&sequence.to_le_bytes());
+        buf.extend_from_slice(&count.to_le_bytes());
+        buf.extend(entries.concat());
+        buf
+    }
+
+    /// Writes records as the LevelDB log writer does, fragmenting them
+    /// across blocks and padding block trailers.
+    fn write_log(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut log = Vec::new();
+        for record in records {
+            let mut rest = record.as_slice();
+            let mut first = true;
+            loop {
+                let left = BLOCK_SIZE - log.len() % BLOCK_SIZE;
+                if left < HEADER_SIZE {
+                    log.resize(log.len() + left, 0);
+                    continue;
+                }
+                let len = rest.len().min(left - HEADER_SIZE);
+                let last = len == rest.len();
+                let kind = match (first, last) {
+                    (true, true) => FULL,
+                    (true, false) => FIRST,
+                    (false, false) => MIDDLE,
+                    (false, true) => LAST,
+                };
+                let mut checked = vec![kind];
+                checked.extend_from_slice(&rest[..len]);
+                log.extend_from_slice(&mask(crc32c::crc32c(&checked)).to_le_bytes());
+                log.extend_from_slice(&(len as u16).to_le_bytes());
+                log.extend_from_slice(&checked);
+                rest = &rest[len..];
+                first = false;
+                if last {
+                    break;
+                }
+            }
+        }
+        log
+    }
+
+    #[test]
+    fn test_reads_fragmented_batches_and_torn_tail() {
+        let big = "x".repeat(70_000);
+        let records = vec![
+            batch(
+                1,
+                3,
+                &[
+                    entry(TAG_VALUE, None, &["a", "1"]),
+                    entry(TAG_CF_VALUE, Some(2), &["b", "2"]),
+                    entry(TAG_LOG_DATA, None, &["blob"]),
+                    entry(TAG_DELETION, None, &["c"]),
+                ],
+            ),
+            batch(4, 1, &[entry(TAG_VALUE, None, &["big", &big])]),
+            batch(5, 1, &[entry(TAG_CF_SINGLE_DELETION, Some(2), &["b"])]),
+        ];
+        let log = write_log(&records);
+        assert!(log.len() > 2 * BLOCK_SIZE);
+
+        let batches: Vec<_> = RocksLogReader::from_bytes(log.clone())
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(
+            batches[0].ops[1],
+            RocksOp::Put {
+                column_family: 2,
+                key: Bytes::from("b"),
+                value: Bytes::from("2"),
+            }
+        );
+        assert_eq!(
+            batches[1].ops[0],
+            RocksOp::Put {
+                column_family: 0,
+                key: Bytes::from("big"),
+                value: Bytes::from(big.clone()),
+            }
+        );
+
+        // Cut inside the last record: the first two still read
+        let mut reader = RocksLogReader::from_bytes(log[..log.len() - 3].to_vec());
+        assert_eq!(reader.by_ref().count(), 2);
+        assert!(reader.is_torn());
+
+        // A flipped bit inside the big record is an error
+        let mut damaged = log.clone();
+        damaged[BLOCK_SIZE + 100] ^= 1;
+        let results: Vec<_> = RocksLogReader::from_bytes(damaged).collect();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[1], Err(SegmentError::ForeignLog(_))));
+    }
+
+    #[test]
+    fn test_merges_and_unsupported_writes() {
+        let merge = batch(
+            10,
+            2,
+            &[
+                entry(TAG_MERGE, None, &["counter", "+1"]),
+                entry(TAG_RANGE_DELETION, None, &["a", "b"]),
+            ],
+        );
+        let batch = RocksBatch::decode(&merge).unwrap();
+        let strict = RocksImportConfig::default();
+        assert!(batch.to_records(&strict).is_err());
+
+        let lenient = RocksImportConfig {
+            merges: MergeHandling::AsPut,
+            skip_unsupported: true,
+            keep_sequences: true,
+        };
+        let (records, skipped) = batch.to_records(&lenient).unwrap();
+        assert_eq!(skipped, 1);
+        let mut expected = Record::put(b"counter".as_slice(), b"+1".as_slice());
+        expected.lsn = Some(10);
+        assert_eq!(records, [expected]);
+
+        let miscounted = self::batch(1, 2, &[entry(TAG_VALUE, None, &["a", "1"])]);
+        assert!(RocksBatch::decode(&miscounted).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_imports_log_directory() {
+        let rocks = TempDir::new().unwrap();
+        let first = write_log(&[batch(
+            1,
+            2,
+            &[
+                entry(TAG_VALUE, None, &["k1", "v1"]),
+                entry(TAG_VALUE, None, &["k2", "v2"]),
+            ],
+        )]);
+        let second = write_log(&[batch(3, 1, &[entry(TAG_CF_DELETION, Some(1), &["k1"])])]);
+        std::fs::write(rocks.path().join("000012.log"), second).unwrap();
+        std::fs::write(rocks.path().join("000009.log"), first).unwrap();
+        std::fs::write(rocks.path().join("MANIFEST-000001"), b"ignored").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::open(WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let start = wal.current_position().await;
+        let config = RocksImportConfig {
+            keep_sequences: true,
+            ..Default::default()
+        };
+        let summary = import(&wal, rocks.path(), config).await.unwrap();
+        assert_eq!(
+            summary,
+            RocksImportSummary {
+                files: 2,
+                batches: 2,
+                records: 3,
+                skipped: 0,
+                last_sequence: Some(3),
+                torn_tail: false,
+            }
+        );
+
+        let records: Vec<_> = wal.reader(start).map(|r| r.unwrap().0).collect().await;
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].key.as_ref(), b"k1");
+        assert_eq!(records[2].lsn, Some(3));
+        assert!(records[2].tombstone);
+        assert_eq!(records[2].namespace, Some(1));
+        assert_eq!(wal.next_lsn(), 4);
+    }
+}
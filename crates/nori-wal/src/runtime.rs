@@ -0,0 +1,181 @@
+//! Pluggable async runtime for the WAL's own tasks and timers.
+//!
+//! The WAL starts background work (scrubbing, seal verification, parallel
+//! replay), sleeps between scrub passes, and runs some blocking file work.
+//! All of it goes through a [`Runtime`], so a deterministic-simulation
+//! framework or another executor can take control of it. [`TokioRuntime`] is
+//! the default.
+//!
+//! Files are reached through a separate [`Fs`](crate::fs::Fs). The default
+//! [`LocalFs`](crate::fs::LocalFs) hands its work to tokio's blocking pool, so
+//! with it the WAL must run inside a tokio context whatever runtime is plugged
+//! in here.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// A boxed future that can be sent to another thread.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// Spawning, sleeping and blocking work, as the WAL needs them.
+pub trait Runtime: Send + Sync + 'static {
+    /// Runs `future` in the background.
+    fn spawn(&self, future: BoxFuture<()>) -> Box<dyn Task>;
+
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture<()>;
+
+    /// Runs blocking work, such as file I/O, without stalling async tasks.
+    /// The returned future completes once `work` has run.
+    fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send + 'static>) -> BoxFuture<()>;
+}
+
+/// Handle to a task started with [`Runtime::spawn`].
+pub trait Task: Send + Sync {
+    /// Asks the task to stop at its next await point.
+    fn abort(&self);
+
+    /// Returns true once the task has completed or been aborted.
+    fn is_finished(&self) -> bool;
+
+    /// Waits for the task to end, whether it completed or was aborted.
+    fn join(self: Box<Self>) -> BoxFuture<()>;
+}
+
+/// The default runtime, backed by the ambient tokio runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, future: BoxFuture<()>) -> Box<dyn Task> {
+        Box::new(tokio::spawn(future))
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send + 'static>) -> BoxFuture<()> {
+        let task = tokio::task::spawn_blocking(work);
+        Box::pin(async move {
+            let _ = task.await;
+        })
+    }
+}
+
+impl Task for tokio::task::JoinHandle<()> {
+    fn abort(&self) {
+        tokio::task::JoinHandle::abort(self)
+    }
+
+    fn is_finished(&self) -> bool {
+        tokio::task::JoinHandle::is_finished(self)
+    }
+
+    fn join(self: Box<Self>) -> BoxFuture<()> {
+        Box::pin(async move {
+            let _ = (*self).await;
+        })
+    }
+}
+
+/// Spawns `future` on `runtime`, returning its handle and a receiver for its
+/// output. The receiver fails if the task is aborted or panics.
+pub(crate) fn spawn_with_output<F>(
+    runtime: &dyn Runtime,
+    future: F,
+) -> (Box<dyn Task>, oneshot::Receiver<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let task = runtime.spawn(Box::pin(async move {
+        let _ = tx.send(future.await);
+    }));
+    (task, rx)
+}
+
+/// Runs blocking `work` on `runtime` and returns its result.
+pub(crate) async fn run_blocking<T, F>(runtime: &dyn Runtime, work: F) -> std::io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    runtime
+        .spawn_blocking(Box::new(move || {
+            let _ = tx.send(work());
+        }))
+        .await;
+    rx.await
+        .map_err(|_| std::io::Error::other("blocking task did not complete"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::wal::Wal;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    /// Tokio runtime that counts what the WAL asks of it.
+    #[derive(Default)]
+    struct CountingRuntime {
+        spawns: AtomicUsize,
+        sleeps: AtomicUsize,
+        blocking: AtomicUsize,
+    }
+
+    impl Runtime for CountingRuntime {
+        fn spawn(&self, future: BoxFuture<()>) -> Box<dyn Task> {
+            self.spawns.fetch_add(1, Ordering::SeqCst);
+            TokioRuntime.spawn(future)
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+            self.sleeps.fetch_add(1, Ordering::SeqCst);
+            TokioRuntime.sleep(duration)
+        }
+
+        fn spawn_blocking(&self, work: Box<dyn FnOnce() + Send + 'static>) -> BoxFuture<()> {
+            self.blocking.fetch_add(1, Ordering::SeqCst);
+            TokioRuntime.spawn_blocking(work)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wal_uses_plugged_in_runtime() {
+        let temp_dir = TempDir::new().unwrap();
+        let runtime = Arc::new(CountingRuntime::default());
+        let (wal, _) = Wal::builder()
+            .runtime(runtime.clone())
+            .dir(temp_dir.path())
+            .segment_size(1024 * 1024)
+            .preallocate(false)
+            .verify_on_seal(true)
+            .scrub_interval(Duration::from_millis(5))
+            .open()
+            .await
+            .unwrap();
+        // The directory lock is taken as blocking work
+        assert!(runtime.blocking.load(Ordering::SeqCst) >= 1);
+
+        // Rotating starts a seal verification next to the scrubber
+        let value = vec![0u8; 600 * 1024];
+        for _ in 0..2 {
+            wal.append(&Record::put(b"k".as_slice(), value.clone()))
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        wal.close().await.unwrap();
+
+        assert!(runtime.spawns.load(Ordering::SeqCst) >= 2);
+        assert!(runtime.sleeps.load(Ordering::SeqCst) >= 1);
+    }
+}
@@ -0,0 +1,168 @@
+//! Seal sidecars for verified, sealed segments.
+//!
+//! After rotation seals a segment, a background task verifies every record and
+//! writes `NNNNNN.seal` next to it, recording the segment's length, record
+//! count, LSN range and checksum. Recovery trusts a seal whose length matches
+//! the segment file instead of decoding every record again, so startup cost
+//! follows the size of the unsealed tail rather than the whole log.
+//!
+//! Trusting a seal checks only that length: the segment's bytes are not read,
+//! so `data_crc` is not compared and damage that leaves the length intact goes
+//! unnoticed until the segment is read or scrubbed. Disable
+//! [`trust_seals`](crate::RecoveryOptions::trust_seals) to have recovery
+//! decode every sealed segment again.
+//!
+//! Seal layout (little-endian, 49 bytes):
+//! - magic: `NORISEAL`
+//! - version: u8
+//! - len: u64
+//! - records: u64
+//! - first_lsn: u64 (0 if none)
+//! - last_lsn: u64 (0 if none)
+//! - data_crc: u32 (CRC32C of the segment bytes)
+//! - crc32c: u32 (of all preceding seal bytes)
+
+use crate::failpoint;
+use crate::fs::{self, Fs};
+use crate::scrub::SegmentVerification;
+use crate::segment::SegmentError;
+use bytes::{Buf, BufMut, BytesMut};
+use std::path::{Path, PathBuf};
+
+const SEAL_MAGIC: &[u8; 8] = b"NORISEAL";
+const SEAL_VERSION: u8 = 1;
+pub(crate) const SEAL_LEN: usize = 8 + 1 + 8 * 4 + 4 + 4;
+
+/// Summary of a sealed segment whose records have all been verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentSeal {
+    /// Length of the segment file in bytes.
+    pub len: u64,
+    /// Number of records in the segment.
+    pub records: u64,
+    /// LSN of the first record that carries one.
+    pub first_lsn: Option<u64>,
+    /// LSN of the last record that carries one.
+    pub last_lsn: Option<u64>,
+    /// CRC32C of the segment's bytes.
+    pub data_crc: u32,
+}
+
+impl SegmentSeal {
+    /// Builds a seal from a clean verification, or `None` if it found corruption.
+    pub fn from_verification(verification: &SegmentVerification) -> Option<Self> {
+        verification.is_clean().then_some(Self {
+            len: verification.bytes,
+            records: verification.records,
+            first_lsn: verification.first_lsn,
+            last_lsn: verification.last_lsn,
+            data_crc: verification.crc,
+        })
+    }
+
+    fn encode(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(SEAL_LEN);
+        buf.put_slice(SEAL_MAGIC);
+        buf.put_u8(SEAL_VERSION);
+        buf.put_u64_le(self.len);
+        buf.put_u64_le(self.records);
+        buf.put_u64_le(self.first_lsn.unwrap_or(0));
+        buf.put_u64_le(self.last_lsn.unwrap_or(0));
+        buf.put_u32_le(self.data_crc);
+        let crc = crc32c::crc32c(&buf);
+        buf.put_u32_le(crc);
+        buf
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() != SEAL_LEN || &data[..8] != SEAL_MAGIC || data[8] != SEAL_VERSION {
+            return None;
+        }
+        let (body, mut crc) = data.split_at(SEAL_LEN - 4);
+        if crc.get_u32_le() != crc32c::crc32c(body) {
+            return None;
+        }
+
+        let mut cursor = &body[9..];
+        let lsn = |v: u64| (v != 0).then_some(v);
+        Some(Self {
+            len: cursor.get_u64_le(),
+            records: cursor.get_u64_le(),
+            first_lsn: lsn(cursor.get_u64_le()),
+            last_lsn: lsn(cursor.get_u64_le()),
+            data_crc: cursor.get_u32_le(),
+        })
+    }
+}
+
+/// Returns the path of a segment's seal sidecar.
+pub(crate) fn seal_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:06}.seal", id))
+}
+
+/// Durably writes a segment's seal, replacing any previous one atomically.
+pub(crate) async fn write_seal(
+    fs: &dyn Fs,
+    dir: &Path,
+    id: u64,
+    seal: &SegmentSeal,
+) -> Result<(), SegmentError> {
+    let path = seal_path(dir, id);
+    let temp_path = path.with_extension("seal.tmp");
+
+    fs::write_synced(fs, &temp_path, &seal.encode()).await?;
+    failpoint::check(failpoint::SEAL_BEFORE_RENAME)?;
+
+    fs.rename(&temp_path, &path).await?;
+    Ok(fs.sync_dir(dir).await?)
+}
+
+/// Reads a segment's seal. A missing or damaged seal yields `None`.
+pub(crate) async fn read_seal(fs: &dyn Fs, dir: &Path, id: u64) -> Option<SegmentSeal> {
+    let data = fs::read(fs, &seal_path(dir, id)).await.ok()?;
+    SegmentSeal::decode(&data)
+}
+
+/// Removes a segment's seal if it has one.
+pub(crate) async fn remove_seal(fs: &dyn Fs, dir: &Path, id: u64) -> Result<(), SegmentError> {
+    match fs.remove_file(&seal_path(dir, id)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::LocalFs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_seal_roundtrip_and_damage() {
+        let temp_dir = TempDir::new().unwrap();
+        let seal = SegmentSeal {
+            len: 4096,
+            records: 17,
+            first_lsn: Some(3),
+            last_lsn: Some(19),
+            data_crc: 0xDEAD_BEEF,
+        };
+
+        write_seal(&LocalFs, temp_dir.path(), 2, &seal)
+            .await
+            .unwrap();
+        assert_eq!(read_seal(&LocalFs, temp_dir.path(), 2).await, Some(seal));
+        assert_eq!(read_seal(&LocalFs, temp_dir.path(), 3).await, None);
+
+        // A flipped bit invalidates the seal rather than misreporting it
+        let path = seal_path(temp_dir.path(), 2);
+        let mut data = std::fs::read(&path).unwrap();
+        data[12] ^= 0x01;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read_seal(&LocalFs, temp_dir.path(), 2).await, None);
+
+        remove_seal(&LocalFs, temp_dir.path(), 2).await.unwrap();
+        remove_seal(&LocalFs, temp_dir.path(), 2).await.unwrap();
+        assert!(!path.exists());
+    }
+}
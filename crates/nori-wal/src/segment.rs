@@ -3,39 +3,184 @@
 //! Segments are numbered sequentially (e.g., 000000.wal, 000001.wal) and rotated
 //! when they reach the configured size limit (default 128MB).
 
-use crate::record::Record;
-use nori_observe::{Meter, VizEvent, WalEvt, WalKind};
-use std::collections::HashMap;
+use crate::audit::{self, AuditEntry, AuditOperation};
+use crate::chain::{record_hash, ChainHead};
+use crate::clock::{Clock, SystemClock};
+use crate::coalesce;
+use crate::encode_pool::EncodePool;
+use crate::error::IoOp;
+use crate::expiry::ExpiryIndex;
+use crate::failpoint;
+use crate::fs::{self, Fs, FsFile, LocalFs};
+use crate::lease::LeaseState;
+use crate::lsn_index::LsnIndex;
+use crate::memory::{MemoryBudget, MemoryCharge, MemoryComponent, SHRUNK_READ_AHEAD};
+use crate::metrics::{NamespaceMetrics, WalGauges, WalMetrics, WalStats};
+use crate::moved;
+use crate::quota::QuotaTracker;
+use crate::record::{Durability, Record, RecordHeader};
+use crate::record_cache::RecordCache;
+use crate::runtime::{Runtime, Task, TokioRuntime};
+use crate::seal::{self, SegmentSeal};
+use crate::slo::{LatencySlo, SloMonitor};
+use crate::store::{FsSegmentStore, SegmentStore};
+use crate::trace::{self, FsyncSpan};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use nori_observe::{obs_count, obs_emit, LatencyOp, Meter, VizEvent, WalEvt, WalKind};
+use nori_wal_format::CHAIN_LINK_LEN;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::Duration;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
-use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
-use tokio::sync::Mutex;
-use tokio::time::Instant;
+use tokio::sync::{Mutex, Notify, RwLock, RwLockReadGuard};
 
 const DEFAULT_SEGMENT_SIZE: u64 = 134_217_728; // 128 MiB
 
+/// Bytes of appends waiting for the active segment at which
+/// `AppendBackpressure` is emitted.
+const BACKPRESSURE_BYTES: u64 = 8 * 1024 * 1024; // 8 MiB
+
 #[derive(Debug, Error)]
 pub enum SegmentError {
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("I/O error during {op} of segment {segment_id} ({}) at offset {offset}: {source}", .path.display())]
+    SegmentIo {
+        op: IoOp,
+        segment_id: u64,
+        path: PathBuf,
+        offset: u64,
+        #[source]
+        source: std::io::Error,
+    },
     #[error("Record error: {0}")]
     Record(#[from] crate::record::RecordError),
     #[error("Segment not found: {0}")]
     NotFound(u64),
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+    #[error("Corruption in segment {segment_id} at offset {offset}")]
+    Corruption { segment_id: u64, offset: u64 },
+    #[error("Gap in log: segment {missing} is missing (next segment: {resume_at:?})")]
+    Gap {
+        missing: u64,
+        resume_at: Option<u64>,
+    },
+    #[error("Cursor position {0} is no longer in the log")]
+    CursorGone(Position),
+    #[error("Segment {0} has been purged")]
+    Purged(u64),
+    #[error("Record of {size} bytes exceeds the limit of {max} bytes")]
+    RecordTooLarge { size: u64, max: u64 },
+    #[error(transparent)]
+    Config(#[from] crate::config::ConfigError),
+    #[error("WAL directory {0} is locked by another instance")]
+    Locked(PathBuf),
+    #[error("WAL is closed")]
+    Closed,
+    #[error("Replication error: {0}")]
+    Replication(String),
+    #[error("Metadata blob {0:?} is corrupt")]
+    CorruptMeta(String),
+    #[error("Foreign log error: {0}")]
+    ForeignLog(String),
+    #[error("Log tail moved: expected {expected}, found {actual}")]
+    TailMoved {
+        expected: Position,
+        actual: Position,
+    },
+    #[error("Lease is held by {0}")]
+    LeaseHeld(String),
+    #[error("Lease for epoch {0} has expired")]
+    LeaseExpired(u64),
+    #[error("Lease epoch {epoch} is older than epoch {current}, already attached to the WAL")]
+    Fenced { epoch: u64, current: u64 },
+    #[error("Namespace {namespace} is at its quota ({records} records, {bytes} bytes)")]
+    QuotaExceeded {
+        namespace: u32,
+        records: u64,
+        bytes: u64,
+    },
+    #[error("Transaction {id} is in doubt: {reason}")]
+    InDoubt { id: u64, reason: String },
 }
 
 /// Position in the WAL (segment ID + byte offset).
+///
+/// Displays as `SEGMENT:OFFSET` with the segment ID zero-padded like segment
+/// file names (`000012:48739`), and parses back from the same form. With the
+/// `serde` feature it serializes as a struct and deserializes from either a
+/// struct or that string.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Position {
     pub segment_id: u64,
     pub offset: u64,
 }
 
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:06}:{}", self.segment_id, self.offset)
+    }
+}
+
+/// Error returned when parsing a [`Position`] from a string fails.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid position {0:?}: expected SEGMENT:OFFSET")]
+pub struct ParsePositionError(String);
+
+impl FromStr for Position {
+    type Err = ParsePositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = s.split_once(':').and_then(|(segment_id, offset)| {
+            Some(Position {
+                segment_id: segment_id.parse().ok()?,
+                offset: offset.parse().ok()?,
+            })
+        });
+        parsed.ok_or_else(|| ParsePositionError(s.to_string()))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Position {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Text(String),
+            Fields { segment_id: u64, offset: u64 },
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Text(s) => s.parse().map_err(serde::de::Error::custom),
+            Repr::Fields { segment_id, offset } => Ok(Position { segment_id, offset }),
+        }
+    }
+}
+
+/// Summary of a completed backup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Number of segment files written to the backup directory.
+    pub segments: u64,
+    /// Total bytes copied.
+    pub bytes: u64,
+    /// Durable position captured at the start of the backup; the backup
+    /// contains every record before it and nothing after it.
+    pub position: Position,
+}
+
 /// Fsync policy for durability vs performance tradeoff.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FsyncPolicy {
@@ -75,6 +220,30 @@ pub struct SegmentConfig {
     ///
     /// Default: true
     pub preallocate: bool,
+    /// Re-read and CRC-verify each segment in the background right after it is
+    /// sealed by rotation, emitting `SegmentVerified` or `CorruptionDetected`.
+    ///
+    /// Default: false
+    pub verify_on_seal: bool,
+    /// Write a seal sidecar for each segment sealed by rotation once its
+    /// records verify, letting recovery skip decoding it. See [`crate::seal`].
+    ///
+    /// Default: false
+    pub seal_segments: bool,
+    /// Largest encoded record accepted by `append`; larger ones fail with
+    /// [`SegmentError::RecordTooLarge`].
+    ///
+    /// Default: None (no limit)
+    pub max_record_size: Option<u64>,
+    /// Rotate a segment that has held records for this long, even if it is
+    /// not full. Age counts from when the segment was created or reopened.
+    ///
+    /// Default: None (rotate on size only)
+    pub max_segment_age: Option<Duration>,
+    /// Fsyncs taking at least this long also emit `SlowFsync`.
+    ///
+    /// Default: None (never reported)
+    pub slow_fsync_threshold: Option<Duration>,
 }
 
 impl Default for SegmentConfig {
@@ -84,16 +253,55 @@ impl Default for SegmentConfig {
             dir: PathBuf::from("wal"),
             fsync_policy: FsyncPolicy::default(),
             preallocate: true,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         }
     }
 }
 
+/// Takes an append's bytes off [`SegmentManager::queued_bytes`] once it
+/// stops waiting, including when it is cancelled.
+struct Queued<'a> {
+    queued_bytes: &'a AtomicU64,
+    bytes: u64,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queued_bytes
+            .fetch_sub(self.bytes, AtomicOrdering::Relaxed);
+    }
+}
+
+/// Limits read once per append, deciding on rotation and record size.
+struct AppendLimits {
+    max_segment_size: u64,
+    max_record_size: Option<u64>,
+    max_segment_age: Option<Duration>,
+    /// The manager's clock when the limits were read, for age rotation.
+    now: Instant,
+}
+
+/// An encoded record with the LSN and timestamp it was stamped with.
+type Stamped = (bytes::Bytes, u64, SystemTime);
+
 /// A single WAL segment file.
 struct SegmentFile {
     id: u64,
-    file: File,
+    file: Arc<dyn FsFile>,
     size: u64,
-    #[allow(dead_code)]
+    /// Bytes known to be fsynced (always <= size).
+    synced_size: u64,
+    /// Position of the last record appended to the log, if known. It lies in
+    /// an earlier segment until this one receives its first record.
+    last_record: Option<Position>,
+    /// `last_record` as of the last fsync.
+    synced_last_record: Option<Position>,
+    /// When this segment was created or reopened, for age-based rotation.
+    opened_at: Instant,
     path: PathBuf,
 }
 
@@ -102,43 +310,33 @@ impl SegmentFile {
     ///
     /// If `preallocate_size` is Some and this is a new file, it will be pre-allocated
     /// to the given size to prevent "no space left" errors and improve filesystem locality.
-    async fn open(dir: &Path, id: u64, create: bool, preallocate_size: Option<u64>) -> Result<Self, SegmentError> {
+    /// `opened_at` is the time its age is measured from.
+    async fn open(
+        store: &dyn SegmentStore,
+        dir: &Path,
+        id: u64,
+        create: bool,
+        preallocate_size: Option<u64>,
+        opened_at: Instant,
+    ) -> Result<Self, SegmentError> {
         let path = segment_path(dir, id);
-
-        let mut file = if create {
-            OpenOptions::new()
-                .create(true)
-                .truncate(false) // Don't truncate - append to existing segments
-                .write(true)
-                .read(true)
-                .open(&path)
-                .await?
-        } else {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(&path)
-                .await?
-        };
-
-        let metadata = file.metadata().await?;
-        let actual_data_size = metadata.len();
+        let open_error = || SegmentError::io_at(IoOp::Open, id, &path, 0);
+        let file = store
+            .open_append(dir, id, create)
+            .await
+            .map_err(open_error())?;
+        let actual_data_size = file.len().await.map_err(open_error())?;
 
         // Pre-allocate space for new files (but track actual data written separately)
         let logical_size = if actual_data_size == 0 && create {
             if let Some(target_size) = preallocate_size {
                 // Use platform-specific pre-allocation for better performance
-                crate::prealloc::preallocate(&file, target_size).await?;
-                file.sync_all().await?;
-                // Seek back to beginning since we'll be writing from offset 0
-                file.seek(std::io::SeekFrom::Start(0)).await?;
+                file.allocate(target_size).await.map_err(open_error())?;
+                file.sync_all().await.map_err(open_error())?;
             }
             0 // Logical size is still 0, we've just reserved space
         } else {
-            // Existing file: seek to end and use actual size
-            if actual_data_size > 0 {
-                file.seek(std::io::SeekFrom::End(0)).await?;
-            }
+            // Existing file: appends go after its data
             actual_data_size
         };
 
@@ -146,50 +344,175 @@ impl SegmentFile {
             id,
             file,
             size: logical_size,
+            synced_size: logical_size,
+            last_record: None,
+            synced_last_record: None,
+            opened_at,
             path,
         })
     }
 
-    /// Appends a record to the segment.
-    async fn append(&mut self, record: &Record) -> Result<u64, SegmentError> {
-        let encoded = record.encode();
+    /// Wraps an I/O error from `op` on this segment at `offset`.
+    fn io_error(&self, op: IoOp, offset: u64) -> impl FnOnce(std::io::Error) -> SegmentError + '_ {
+        SegmentError::io_at(op, self.id, &self.path, offset)
+    }
+
+    /// Appends an encoded record to the segment.
+    async fn append(&mut self, encoded: &[u8]) -> Result<u64, SegmentError> {
         let offset = self.size;
 
-        self.file.write_all(&encoded).await?;
+        failpoint::check(failpoint::APPEND_BEFORE_WRITE)?;
+        if failpoint::fired(failpoint::APPEND_TORN_WRITE) {
+            self.file
+                .write_all_at(offset, &encoded[..encoded.len() / 2])
+                .await
+                .map_err(self.io_error(IoOp::Append, offset))?;
+            return Err(failpoint::injected(failpoint::APPEND_TORN_WRITE));
+        }
+
+        self.file
+            .write_all_at(offset, encoded)
+            .await
+            .map_err(self.io_error(IoOp::Append, offset))?;
         self.size += encoded.len() as u64;
+        self.last_record = Some(Position {
+            segment_id: self.id,
+            offset,
+        });
+
+        Ok(offset)
+    }
+
+    /// Appends several encoded records with a single write, returning the
+    /// offset of the first.
+    async fn append_all(&mut self, encoded: &[Stamped]) -> Result<u64, SegmentError> {
+        let offset = self.size;
+        failpoint::check(failpoint::APPEND_BEFORE_WRITE)?;
+
+        let len = encoded.iter().map(|(bytes, _, _)| bytes.len()).sum();
+        let mut buf = Vec::with_capacity(len);
+        for (bytes, _, _) in encoded {
+            buf.extend_from_slice(bytes);
+        }
+        self.file
+            .write_all_at(offset, &buf)
+            .await
+            .map_err(self.io_error(IoOp::Append, offset))?;
+        self.size += len as u64;
+        if let Some((last, _, _)) = encoded.last() {
+            self.last_record = Some(Position {
+                segment_id: self.id,
+                offset: self.size - last.len() as u64,
+            });
+        }
 
         Ok(offset)
     }
 
+    /// Position just past the last byte written.
+    fn tail(&self) -> Position {
+        Position {
+            segment_id: self.id,
+            offset: self.size,
+        }
+    }
+
+    /// Fails with [`SegmentError::TailMoved`] unless the segment ends at
+    /// `expected`.
+    fn check_tail(&self, expected: Position) -> Result<(), SegmentError> {
+        let actual = self.tail();
+        if actual != expected {
+            return Err(SegmentError::TailMoved { expected, actual });
+        }
+        Ok(())
+    }
+
     /// Returns true if appending this record would exceed the size limit.
     fn would_exceed(&self, record_size: usize, max_size: u64) -> bool {
         self.size + record_size as u64 > max_size
     }
 
-    /// Flushes data to disk.
-    async fn flush(&mut self) -> Result<(), SegmentError> {
-        self.file.flush().await?;
-        Ok(())
+    /// Returns true if the segment holds records and is older than `max_age`.
+    fn is_older_than(&self, max_age: Option<Duration>, now: Instant) -> bool {
+        self.size > 0
+            && max_age
+                .is_some_and(|max_age| now.saturating_duration_since(self.opened_at) >= max_age)
+    }
+
+    /// Returns true if the segment should be rotated before appending
+    /// `record_size` more bytes.
+    fn needs_rotation(&self, record_size: usize, limits: &AppendLimits) -> bool {
+        self.would_exceed(record_size, limits.max_segment_size)
+            || self.is_older_than(limits.max_segment_age, limits.now)
     }
 
     /// Syncs data to disk (fsync).
     async fn sync(&mut self) -> Result<(), SegmentError> {
-        self.file.sync_data().await?;
+        failpoint::check(failpoint::FSYNC)?;
+        self.file
+            .sync_data()
+            .await
+            .map_err(self.io_error(IoOp::Sync, self.synced_size))?;
+        self.synced_size = self.size;
+        self.synced_last_record = self.last_record;
         Ok(())
     }
 
-    /// Finalizes the segment by truncating it to actual written size.
-    /// This is important when pre-allocation is used.
-    async fn finalize(&mut self) -> Result<(), SegmentError> {
-        self.file.set_len(self.size).await?;
-        self.file.sync_all().await?;
+    /// Finalizes the segment by sealing it in `store` at its actual written
+    /// size. This is important when pre-allocation is used.
+    async fn finalize(&mut self, store: &dyn SegmentStore, dir: &Path) -> Result<(), SegmentError> {
+        store
+            .seal(dir, self.id, &self.file, self.size)
+            .await
+            .map_err(self.io_error(IoOp::Rotate, self.size))?;
+        self.synced_size = self.size;
+        self.synced_last_record = self.last_record;
         Ok(())
     }
 }
 
+/// Counts of readers using each segment, which purging leaves in place.
+#[derive(Default)]
+struct SegmentPins {
+    counts: std::sync::Mutex<HashMap<u64, usize>>,
+}
+
+impl SegmentPins {
+    fn pin(self: &Arc<Self>, segment_id: u64) -> SegmentPin {
+        *self.counts.lock().unwrap().entry(segment_id).or_insert(0) += 1;
+        SegmentPin {
+            pins: Arc::clone(self),
+            segment_id,
+        }
+    }
+
+    /// Returns the lowest pinned segment ID.
+    fn oldest(&self) -> Option<u64> {
+        self.counts.lock().unwrap().keys().min().copied()
+    }
+}
+
+/// Keeps a segment from being purged for as long as it is held.
+struct SegmentPin {
+    pins: Arc<SegmentPins>,
+    segment_id: u64,
+}
+
+impl Drop for SegmentPin {
+    fn drop(&mut self) {
+        let mut counts = self.pins.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.segment_id) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.segment_id);
+            }
+        }
+    }
+}
+
 /// Simple LRU cache for segment file descriptors.
 struct FdCache {
-    cache: HashMap<u64, Arc<Mutex<File>>>,
+    cache: HashMap<u64, Arc<dyn FsFile>>,
     max_size: usize,
     access_order: Vec<u64>,
 }
@@ -203,7 +526,12 @@ impl FdCache {
         }
     }
 
-    async fn get_or_open(&mut self, segment_id: u64, dir: &Path) -> Result<Arc<Mutex<File>>, SegmentError> {
+    async fn get_or_open(
+        &mut self,
+        segment_id: u64,
+        store: &dyn SegmentStore,
+        dir: &Path,
+    ) -> Result<Arc<dyn FsFile>, SegmentError> {
         // Check if already in cache
         if let Some(file) = self.cache.get(&segment_id) {
             // Update access order
@@ -213,17 +541,14 @@ impl FdCache {
         }
 
         // Open new file
-        let path = segment_path(dir, segment_id);
-        let file = File::open(&path).await.map_err(|e| {
+        let file_arc = store.open_read(dir, segment_id).await.map_err(|e| {
             if e.kind() == std::io::ErrorKind::NotFound {
                 SegmentError::NotFound(segment_id)
             } else {
-                SegmentError::Io(e)
+                SegmentError::io_at(IoOp::Open, segment_id, &segment_path(dir, segment_id), 0)(e)
             }
         })?;
 
-        let file_arc = Arc::new(Mutex::new(file));
-
         // Evict LRU if at capacity
         if self.cache.len() >= self.max_size {
             if let Some(&lru_id) = self.access_order.first() {
@@ -238,17 +563,86 @@ impl FdCache {
 
         Ok(file_arc)
     }
+
+    /// Drops the cached descriptor for `segment_id`, if any.
+    fn remove(&mut self, segment_id: u64) {
+        self.cache.remove(&segment_id);
+        self.access_order.retain(|&id| id != segment_id);
+    }
+
+    /// Drops all cached file descriptors.
+    fn clear(&mut self) {
+        self.cache.clear();
+        self.access_order.clear();
+    }
 }
 
 /// Manages WAL segments with automatic rotation.
 pub struct SegmentManager {
-    config: SegmentConfig,
+    config: Arc<Mutex<SegmentConfig>>,
     current: Arc<Mutex<SegmentFile>>,
     current_id: Arc<Mutex<u64>>,
     meter: Arc<dyn Meter>,
     node_id: u32,
     last_fsync: Arc<Mutex<Option<Instant>>>,
     fd_cache: Arc<Mutex<FdCache>>,
+    /// Held for reading by operations that must not observe segment deletion
+    /// (e.g. backups), and for writing by garbage collection.
+    purge_lock: Arc<RwLock<()>>,
+    /// LSN given to the next appended record that does not carry one.
+    /// Only advanced while holding the `current` lock, so LSNs follow file order.
+    next_lsn: Arc<AtomicU64>,
+    /// Woken whenever appended data may have become durable, for tailers.
+    durable_advanced: Arc<Notify>,
+    /// Segments held by open readers.
+    pins: Arc<SegmentPins>,
+    /// Where to start looking for a given LSN.
+    lsn_index: Arc<LsnIndex>,
+    /// Seal verifications still running, awaited by `wait_for_background`.
+    background: std::sync::Mutex<Vec<Box<dyn Task>>>,
+    /// Runs background tasks and blocking work.
+    runtime: Arc<dyn Runtime>,
+    /// Where sidecar files live, and segment files for recovery and
+    /// maintenance.
+    fs: Arc<dyn Fs>,
+    /// Where segments are created, read, sealed and deleted.
+    store: Arc<dyn SegmentStore>,
+    /// Held for a whole compaction pass, so passes do not overlap.
+    compaction_lock: Mutex<()>,
+    /// Times segment ages, the batch-fsync window and record timestamps.
+    clock: Arc<dyn Clock>,
+    /// Built-in counters behind `metrics()`.
+    stats: Arc<WalStats>,
+    /// Size, segment count and staleness gauges kept on `meter`.
+    gauges: WalGauges,
+    /// Set once the owning WAL is closed or dropped; appends fail after.
+    closed: AtomicBool,
+    /// Lease appends require, if one is attached.
+    lease: std::sync::Mutex<Option<Arc<LeaseState>>>,
+    /// Key and value bytes of appends waiting for the `current` lock.
+    queued_bytes: AtomicU64,
+    /// What each namespace has in the log, against its quota.
+    quotas: QuotaTracker,
+    /// Whether administrative operations are written to the audit log.
+    audit: bool,
+    /// Whether appended records link to the record before them.
+    hash_chain: bool,
+    /// The last appended record, for the next one to link to. Only advanced
+    /// while holding the `current` lock, like `next_lsn`.
+    chain_head: std::sync::Mutex<Option<ChainHead>>,
+    /// Records readers decoded recently, for readers coming back to them.
+    record_cache: Arc<RecordCache>,
+    /// Budget that appends, readers and the record cache are charged to.
+    memory: Option<MemoryBudget>,
+    /// Compresses large appends on the runtime's blocking pool.
+    encode_pool: EncodePool,
+    /// Latency objectives for appends and fsyncs, if set.
+    append_slo: Option<SloMonitor>,
+    fsync_slo: Option<SloMonitor>,
+    /// Whether batches leave out puts replaced later in the batch.
+    coalesce_keys: bool,
+    /// Keys whose latest write carries a TTL, if tracked.
+    expiry: Option<ExpiryIndex>,
 }
 
 impl Drop for SegmentManager {
@@ -256,18 +650,7 @@ impl Drop for SegmentManager {
         // Best-effort finalization on drop
         // We can't do async work here, so we use blocking operations
         if let Ok(current) = self.current.try_lock() {
-            // Use std::fs to do synchronous truncation
-            if let Ok(metadata) = std::fs::metadata(&current.path) {
-                if metadata.len() != current.size {
-                    let _ = std::fs::OpenOptions::new()
-                        .write(true)
-                        .open(&current.path)
-                        .and_then(|f| {
-                            f.set_len(current.size)?;
-                            f.sync_all()
-                        });
-                }
-            }
+            let _ = current.file.truncate_blocking(current.size);
         }
     }
 }
@@ -278,12 +661,35 @@ impl SegmentManager {
         config: SegmentConfig,
         meter: Arc<dyn Meter>,
         node_id: u32,
+    ) -> Result<Self, SegmentError> {
+        Self::new_with_fs(config, meter, node_id, Arc::new(LocalFs)).await
+    }
+
+    /// Creates a new segment manager keeping its segments on `fs`.
+    pub async fn new_with_fs(
+        config: SegmentConfig,
+        meter: Arc<dyn Meter>,
+        node_id: u32,
+        fs: Arc<dyn Fs>,
+    ) -> Result<Self, SegmentError> {
+        let store = Arc::new(FsSegmentStore::new(Arc::clone(&fs)));
+        Self::new_with_store(config, meter, node_id, fs, store).await
+    }
+
+    /// Creates a new segment manager keeping its segments in `store` and
+    /// its sidecar files on `fs` (see [`crate::store`]).
+    pub async fn new_with_store(
+        config: SegmentConfig,
+        meter: Arc<dyn Meter>,
+        node_id: u32,
+        fs: Arc<dyn Fs>,
+        store: Arc<dyn SegmentStore>,
     ) -> Result<Self, SegmentError> {
         // Create directory if it doesn't exist
-        tokio::fs::create_dir_all(&config.dir).await?;
+        fs.create_dir_all(&config.dir).await?;
 
         // Find the latest segment ID
-        let latest_id = find_latest_segment_id(&config.dir).await?;
+        let latest_id = store.list(&config.dir).await?.last().copied().unwrap_or(0);
 
         // Open or create the current segment with optional pre-allocation
         let preallocate_size = if config.preallocate {
@@ -291,253 +697,1743 @@ impl SegmentManager {
         } else {
             None
         };
-        let segment = SegmentFile::open(&config.dir, latest_id, true, preallocate_size).await?;
+        let segment = SegmentFile::open(
+            store.as_ref(),
+            &config.dir,
+            latest_id,
+            true,
+            preallocate_size,
+            SystemClock.now(),
+        )
+        .await?;
+
+        let gauges = WalGauges::new(meter.as_ref());
+        let (sealed_segments, sealed_bytes) =
+            sealed_usage(store.as_ref(), &config.dir, latest_id).await?;
+        gauges.set_sealed(sealed_segments, sealed_bytes);
+        gauges.active(segment.size, config.max_segment_size);
 
         Ok(Self {
-            config,
+            config: Arc::new(Mutex::new(config)),
             current: Arc::new(Mutex::new(segment)),
             current_id: Arc::new(Mutex::new(latest_id)),
-            meter,
+            meter: meter.clone(),
             node_id,
             last_fsync: Arc::new(Mutex::new(None)),
             fd_cache: Arc::new(Mutex::new(FdCache::new(32))), // Cache up to 32 segment FDs
+            purge_lock: Arc::new(RwLock::new(())),
+            next_lsn: Arc::new(AtomicU64::new(1)),
+            durable_advanced: Arc::new(Notify::new()),
+            pins: Arc::new(SegmentPins::default()),
+            lsn_index: Arc::new(LsnIndex::default()),
+            background: std::sync::Mutex::new(Vec::new()),
+            runtime: Arc::new(TokioRuntime),
+            fs,
+            store,
+            compaction_lock: Mutex::new(()),
+            clock: Arc::new(SystemClock),
+            stats: Arc::default(),
+            gauges,
+            closed: AtomicBool::new(false),
+            lease: std::sync::Mutex::new(None),
+            queued_bytes: AtomicU64::new(0),
+            quotas: QuotaTracker::new(meter.clone()),
+            audit: false,
+            hash_chain: false,
+            chain_head: std::sync::Mutex::new(None),
+            record_cache: RecordCache::new(0, None),
+            memory: None,
+            encode_pool: EncodePool::new(0, 0),
+            append_slo: None,
+            fsync_slo: None,
+            coalesce_keys: false,
+            expiry: None,
         })
     }
 
-    /// Deletes all segments before the given position.
-    ///
-    /// This is used for garbage collection after data has been compacted or
-    /// replicated. Any segment with ID < position.segment_id will be deleted.
-    ///
-    /// # Safety
-    ///
-    /// The caller must ensure that the data in these segments is no longer needed
-    /// (e.g., it has been compacted into SSTables or safely replicated).
-    pub async fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
-        let current_id = *self.current_id.lock().await;
+    /// Runs the manager's background tasks on `runtime` instead of tokio.
+    pub fn with_runtime(mut self, runtime: Arc<dyn Runtime>) -> Self {
+        self.runtime = runtime;
+        self
+    }
 
-        // Don't delete the current segment
-        if position.segment_id >= current_id {
-            return Ok(0);
+    /// Reads the time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        // The active segment's age is measured on the new clock
+        if let Some(current) = Arc::get_mut(&mut self.current) {
+            current.get_mut().opened_at = clock.now();
         }
+        self.clock = clock;
+        self
+    }
 
-        let mut deleted_count = 0u64;
-        let mut entries = tokio::fs::read_dir(&self.config.dir).await?;
-
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
-
-            // Parse segment ID from filename
-            if let Some(id) = parse_segment_id_from_path(&path) {
-                // Delete if this segment is before the cutoff position
-                if id < position.segment_id {
-                    tokio::fs::remove_file(&path).await?;
-                    deleted_count += 1;
-
-                    self.meter.emit(VizEvent::Wal(WalEvt {
-                        node: self.node_id,
-                        seg: id,
-                        kind: WalKind::SegmentGc,
-                    }));
-                }
-            }
-        }
+    /// Records administrative operations in the audit log (see
+    /// [`crate::audit`]).
+    pub fn with_audit(mut self, enabled: bool) -> Self {
+        self.audit = enabled;
+        self
+    }
 
-        Ok(deleted_count)
+    /// Links each appended record to the one before it (see
+    /// [`crate::chain`]). [`load_chain_head`](Self::load_chain_head) must
+    /// run before the first append to a log that already holds records.
+    pub fn with_hash_chain(mut self, enabled: bool) -> Self {
+        self.hash_chain = enabled;
+        self
     }
 
-    /// Appends a record to the WAL, rotating if necessary.
-    /// Applies the configured fsync policy.
-    pub async fn append(&self, record: &Record) -> Result<Position, SegmentError> {
-        let encoded_size = record.encode().len();
+    /// Keeps up to `bytes` of recently read records in memory for readers
+    /// to find again (see [`crate::record_cache`]). Zero disables the cache.
+    pub fn with_record_cache(mut self, bytes: u64) -> Self {
+        self.record_cache = RecordCache::new(bytes, self.memory.as_ref());
+        self
+    }
 
-        let mut current = self.current.lock().await;
+    /// Charges append buffers, read-ahead and the record cache to `budget`,
+    /// holding them back when it runs out (see [`crate::memory`]).
+    pub fn with_memory_budget(mut self, budget: Option<MemoryBudget>) -> Self {
+        self.memory = budget;
+        self.record_cache = RecordCache::new(self.record_cache.capacity(), self.memory.as_ref());
+        self
+    }
 
-        // Check if we need to rotate
-        if current.would_exceed(encoded_size, self.config.max_segment_size) {
-            drop(current); // Release lock before rotating
-            self.rotate().await?;
-            current = self.current.lock().await;
-        }
+    /// Returns the memory budget, if one is set.
+    pub(crate) fn memory_budget(&self) -> Option<&MemoryBudget> {
+        self.memory.as_ref()
+    }
 
-        let offset = current.append(record).await?;
-        let segment_id = current.id;
+    /// Waits for room in the memory budget for appending `records`.
+    async fn reserve_append(&self, records: &[Record]) -> Option<MemoryCharge> {
+        let budget = self.memory.as_ref()?;
+        Some(
+            budget
+                .reserve(MemoryComponent::AppendBuffers, payload_len(records))
+                .await,
+        )
+    }
 
-        // Apply fsync policy
-        self.apply_fsync_policy(&mut current, segment_id).await?;
+    /// Returns the cache of recently read records.
+    pub(crate) fn record_cache(&self) -> &RecordCache {
+        &self.record_cache
+    }
 
-        Ok(Position { segment_id, offset })
+    /// Compresses the values of appends with at least `min_bytes` to
+    /// compress on the runtime's blocking pool, up to `workers` appends at
+    /// once (see [`crate::encode_pool`]). Zero workers compresses inline.
+    pub fn with_encode_pool(mut self, workers: usize, min_bytes: u64) -> Self {
+        self.encode_pool = EncodePool::new(workers, min_bytes);
+        self
     }
 
-    /// Appends a batch of records to the WAL.
-    ///
-    /// More efficient than individual appends because:
-    /// - Lock held only once for entire batch
-    /// - Single fsync for entire batch (if policy is Always)
-    /// - No interleaving with other writers
-    pub async fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
-        if records.is_empty() {
-            return Ok(Vec::new());
-        }
+    /// Leaves puts replaced by a later put in the same batch out of
+    /// [`append_batch`](Self::append_batch) (see [`crate::coalesce`]).
+    pub fn with_key_coalescing(mut self, enabled: bool) -> Self {
+        self.coalesce_keys = enabled;
+        self
+    }
 
-        // Calculate total size needed
-        let total_size: usize = records.iter().map(|r| r.encode().len()).sum();
+    /// Tracks keys whose latest write carries a TTL, for listing those
+    /// that expired (see [`crate::expiry`]). Call
+    /// [`load_expiry_index`](Self::load_expiry_index) before the first
+    /// append to a log that already holds records.
+    pub fn with_expiry_tracking(mut self, enabled: bool) -> Self {
+        self.expiry = enabled.then(ExpiryIndex::default);
+        self
+    }
 
-        let mut current = self.current.lock().await;
-        let mut positions = Vec::with_capacity(records.len());
+    /// Returns the index of expiring keys, if tracked.
+    pub(crate) fn expiry(&self) -> Option<&ExpiryIndex> {
+        self.expiry.as_ref()
+    }
 
-        // Check if we need to rotate before starting batch
-        if current.would_exceed(total_size, self.config.max_segment_size) {
-            drop(current);
-            self.rotate().await?;
-            current = self.current.lock().await;
+    /// Adds every record already in the log to the index of expiring keys.
+    pub(crate) async fn load_expiry_index(&self) -> Result<(), SegmentError> {
+        let Some(expiry) = &self.expiry else {
+            return Ok(());
+        };
+        let dir = self.dir().await;
+        for id in self.store.list(&dir).await? {
+            let mut reader = self
+                .read_from(Position {
+                    segment_id: id,
+                    offset: 0,
+                })
+                .await?;
+            while let Some((record, position)) = reader.next_record().await? {
+                if let (Some(lsn), Some(timestamp)) = (record.lsn, record.timestamp) {
+                    expiry.note(&record, lsn, timestamp, position);
+                }
+            }
         }
+        Ok(())
+    }
 
-        // Append all records
-        for record in records {
-            let offset = current.append(record).await?;
-            positions.push(Position {
-                segment_id: current.id,
-                offset,
-            });
+    fn note_expiry(&self, record: &Record, lsn: u64, timestamp: SystemTime, position: Position) {
+        if let Some(expiry) = &self.expiry {
+            expiry.note(record, lsn, timestamp, position);
         }
-
-        let segment_id = current.id;
-
-        // Apply fsync policy once for entire batch
-        self.apply_fsync_policy(&mut current, segment_id).await?;
-
-        Ok(positions)
     }
 
-    /// Flushes the current segment to disk.
-    pub async fn flush(&self) -> Result<(), SegmentError> {
-        let mut current = self.current.lock().await;
-        current.flush().await
+    /// Alerts when appends or fsyncs miss their latency objectives too
+    /// often (see [`crate::slo`]).
+    pub fn with_latency_slos(
+        mut self,
+        append: Option<LatencySlo>,
+        fsync: Option<LatencySlo>,
+    ) -> Self {
+        self.append_slo = append.map(|slo| SloMonitor::new(LatencyOp::Append, slo));
+        self.fsync_slo = fsync.map(|slo| SloMonitor::new(LatencyOp::Fsync, slo));
+        self
     }
 
-    /// Syncs the current segment to disk (fsync).
-    pub async fn sync(&self) -> Result<(), SegmentError> {
-        let start = std::time::Instant::now();
-        let mut current = self.current.lock().await;
-        current.sync().await?;
-        let elapsed_ms = start.elapsed().as_millis() as u32;
-
-        // Emit fsync observability event
-        self.meter.emit(VizEvent::Wal(WalEvt {
-            node: self.node_id,
-            seg: current.id,
-            kind: WalKind::Fsync { ms: elapsed_ms },
-        }));
-
+    /// Finds the last record of the log for the next append to link to.
+    pub(crate) async fn load_chain_head(&self) -> Result<(), SegmentError> {
+        let dir = self.dir().await;
+        let current = self.current.lock().await;
+        let head = self.find_chain_head(&dir, current.id, current.size).await?;
+        *self.chain_head() = head;
         Ok(())
     }
 
-    /// Rotates to a new segment file.
-    async fn rotate(&self) -> Result<(), SegmentError> {
-        let mut current_id = self.current_id.lock().await;
-        let new_id = *current_id + 1;
-
-        // Finalize and get metadata from old segment
-        let mut old_segment = self.current.lock().await;
-        let old_size = old_segment.size;
-        let old_id = old_segment.id;
-
-        // Truncate old segment to actual written size (important for pre-allocated files)
-        old_segment.finalize().await?;
-        drop(old_segment);
-
-        self.meter.emit(VizEvent::Wal(WalEvt {
-            node: self.node_id,
-            seg: old_id,
-            kind: WalKind::SegmentRoll { bytes: old_size },
-        }));
+    fn chain_head(&self) -> std::sync::MutexGuard<'_, Option<ChainHead>> {
+        self.chain_head
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
 
-        // Create new segment with optional pre-allocation
-        let preallocate_size = if self.config.preallocate {
-            Some(self.config.max_segment_size)
+    /// The last appended record, if the log is hash-chained and has one.
+    pub(crate) fn last_chain_head(&self) -> Option<ChainHead> {
+        if self.hash_chain {
+            *self.chain_head()
         } else {
             None
-        };
-        let new_segment = SegmentFile::open(&self.config.dir, new_id, true, preallocate_size).await?;
+        }
+    }
 
-        // Swap in the new segment
-        let mut current = self.current.lock().await;
-        *current = new_segment;
-        *current_id = new_id;
+    /// The hash the next appended record links to, if the log is
+    /// hash-chained: that of the last record, or zeroes for the first.
+    fn chain_link(&self) -> Option<[u8; CHAIN_LINK_LEN]> {
+        self.hash_chain.then(|| {
+            self.chain_head()
+                .map_or([0; CHAIN_LINK_LEN], |head| head.hash)
+        })
+    }
 
-        Ok(())
+    /// Makes the record `encoded` at `position` the one the next append
+    /// links to.
+    fn advance_chain(&self, position: Position, encoded: &[u8]) {
+        if self.hash_chain {
+            let hash = record_hash(encoded);
+            *self.chain_head() = Some(ChainHead { position, hash });
+        }
     }
 
-    /// Applies the configured fsync policy to the current segment.
-    ///
-    /// Handles all three fsync policies (Always, Batch, Os) and emits
-    /// appropriate observability events.
-    async fn apply_fsync_policy(
+    /// Locates the last record below `end` in the active segment, or in an
+    /// earlier one, and hashes it.
+    async fn find_chain_head(
         &self,
-        current: &mut SegmentFile,
-        segment_id: u64,
-    ) -> Result<(), SegmentError> {
-        match self.config.fsync_policy {
-            FsyncPolicy::Always => {
-                self.fsync_with_timing(current, segment_id).await
-            }
-            FsyncPolicy::Batch(window) => {
-                self.fsync_if_window_elapsed(current, segment_id, window).await
-            }
-            FsyncPolicy::Os => {
-                // No fsync - let OS handle it
-                Ok(())
-            }
+        dir: &Path,
+        current_id: u64,
+        end: u64,
+    ) -> Result<Option<ChainHead>, SegmentError> {
+        let Some(position) = self.find_last_record(dir, current_id, end).await? else {
+            return Ok(None);
+        };
+        let file = self
+            .fd_cache
+            .lock()
+            .await
+            .get_or_open(position.segment_id, self.store.as_ref(), dir)
+            .await?;
+        let mut reader = SegmentReader::new(
+            file.clone(),
+            segment_path(dir, position.segment_id),
+            position,
+            None,
+            ReaderConfig::default(),
+        );
+        if reader.next_record().await?.is_none() {
+            return Err(SegmentError::Corruption {
+                segment_id: position.segment_id,
+                offset: position.offset,
+            });
         }
+        let record_end = reader.position().offset;
+        let encoded = file
+            .read_at(position.offset, (record_end - position.offset) as usize)
+            .await?;
+        let hash = record_hash(&encoded);
+        Ok(Some(ChainHead { position, hash }))
     }
 
-    /// Performs fsync and emits timing event.
-    async fn fsync_with_timing(
+    /// Adds an entry for `operation`, made through `source`, to the audit
+    /// log if it is kept.
+    pub(crate) async fn audit(
         &self,
-        current: &mut SegmentFile,
-        segment_id: u64,
+        operation: AuditOperation,
+        source: &str,
+        detail: String,
     ) -> Result<(), SegmentError> {
-        let start = Instant::now();
-        current.sync().await?;
-        let elapsed_ms = start.elapsed().as_millis() as u32;
+        if !self.audit {
+            return Ok(());
+        }
+        let entry = AuditEntry {
+            at: self.clock.system_time(),
+            ..AuditEntry::now(operation, source, self.node_id, detail)
+        };
+        audit::append_entry_on(self.fs.as_ref(), &self.dir().await, &entry).await
+    }
 
-        self.meter.emit(VizEvent::Wal(WalEvt {
-            node: self.node_id,
-            seg: segment_id,
-            kind: WalKind::Fsync { ms: elapsed_ms },
-        }));
+    /// Returns the clock the manager reads the time from.
+    pub(crate) fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
 
-        Ok(())
+    /// Returns the runtime background tasks are spawned on.
+    pub(crate) fn runtime(&self) -> &Arc<dyn Runtime> {
+        &self.runtime
     }
 
-    /// Performs fsync if the time window has elapsed since last sync.
-    async fn fsync_if_window_elapsed(
-        &self,
-        current: &mut SegmentFile,
-        segment_id: u64,
-        window: Duration,
+    /// Keeps segments from being purged until the guard is dropped.
+    pub(crate) async fn hold_purges(&self) -> RwLockReadGuard<'_, ()> {
+        self.purge_lock.read().await
+    }
+
+    /// Returns the lock a compaction pass holds throughout.
+    pub(crate) fn compaction_lock(&self) -> &Mutex<()> {
+        &self.compaction_lock
+    }
+
+    /// Returns the filesystem segments are stored on.
+    pub(crate) fn fs(&self) -> &Arc<dyn Fs> {
+        &self.fs
+    }
+
+    /// Returns the store segment bytes are kept in.
+    pub(crate) fn store(&self) -> &Arc<dyn SegmentStore> {
+        &self.store
+    }
+
+    /// Fails unless the store keeps segments as files on this manager's
+    /// `Fs`, which `operation` works on directly.
+    pub(crate) fn require_segment_files(&self, operation: &str) -> Result<(), SegmentError> {
+        let on_fs = self
+            .store
+            .files()
+            .is_some_and(|fs| Arc::as_ptr(fs) as *const () == Arc::as_ptr(&self.fs) as *const ());
+        if on_fs {
+            Ok(())
+        } else {
+            Err(SegmentError::InvalidConfig(format!(
+                "{} needs segment files on the WAL's Fs, not a custom segment store",
+                operation
+            )))
+        }
+    }
+
+    /// Sets the LSN assigned to the next appended record.
+    ///
+    /// A fresh manager starts at 1; `Wal::open` resumes after the highest LSN
+    /// found during recovery.
+    pub fn set_next_lsn(&self, lsn: u64) {
+        self.next_lsn.store(lsn, AtomicOrdering::SeqCst);
+    }
+
+    /// Returns the LSN the next appended record will receive.
+    pub fn next_lsn(&self) -> u64 {
+        self.next_lsn.load(AtomicOrdering::SeqCst)
+    }
+
+    /// Encodes `records` with an LSN and timestamp filled in where missing,
+    /// numbering from the current `next_lsn`, and linked into the hash chain
+    /// if the log keeps one. Returns the encodings with
+    /// their LSNs and timestamps, and the value `next_lsn` should take once
+    /// they are written. Fails if an encoding is larger than `max_size`.
+    ///
+    /// `compressed` holds the records' values already compressed by the
+    /// encode pool, if it took them.
+    fn stamp_and_encode(
+        &self,
+        records: &[Record],
+        compressed: Option<&[Bytes]>,
+        max_size: Option<u64>,
+    ) -> Result<(Vec<Stamped>, u64), SegmentError> {
+        let now = self.clock.system_time();
+        let mut next = self.next_lsn();
+        let mut link = self.chain_link();
+        let encoded = records
+            .iter()
+            .enumerate()
+            .map(|(i, record)| {
+                let lsn = record.lsn.unwrap_or(next);
+                next = next.max(lsn.saturating_add(1));
+                let mut stamped = record.clone();
+                stamped.lsn = Some(lsn);
+                let timestamp = record.timestamp.unwrap_or(now);
+                stamped.timestamp = Some(timestamp);
+                if link.is_some() {
+                    stamped.chain = link;
+                }
+                let bytes = match compressed {
+                    Some(values) => stamped.encode_compressed(&values[i]),
+                    None => stamped.encode(),
+                };
+                if link.is_some() {
+                    link = Some(record_hash(&bytes));
+                }
+                match max_size {
+                    Some(max) if bytes.len() as u64 > max => Err(SegmentError::RecordTooLarge {
+                        size: bytes.len() as u64,
+                        max,
+                    }),
+                    _ => Ok((bytes, lsn, timestamp)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((encoded, next))
+    }
+
+    /// Deletes segments as [`delete_segments_before`](Self::delete_segments_before)
+    /// does, auditing the purge as made by `source` if anything went.
+    pub(crate) async fn purge_before(
+        &self,
+        position: Position,
+        source: &str,
+    ) -> Result<u64, SegmentError> {
+        let deleted = self.delete_segments_before(position).await?;
+        if deleted > 0 {
+            let detail = format!("segments before {}, {} deleted", position, deleted);
+            self.audit(AuditOperation::Purge, source, detail).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes all segments before the given position.
+    ///
+    /// This is used for garbage collection after data has been compacted or
+    /// replicated. Any segment with ID < position.segment_id will be deleted,
+    /// except that a segment pinned by an open reader is kept along with every
+    /// segment after it, so the log stays contiguous. Those segments go on a
+    /// later call, once the readers are done with them.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the data in these segments is no longer needed
+    /// (e.g., it has been compacted into SSTables or safely replicated).
+    pub async fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
+        let current_id = *self.current_id.lock().await;
+
+        // Only whole segments before `position` go; the current one never does
+        if position.segment_id > current_id {
+            return Ok(0);
+        }
+
+        let _purge_guard = self.purge_lock.write().await;
+        let cutoff = match self.pins.oldest() {
+            Some(pinned) => position.segment_id.min(pinned),
+            None => position.segment_id,
+        };
+        let dir = self.config.lock().await.dir.clone();
+        let mut deleted_count = 0u64;
+
+        for id in self.store.list(&dir).await? {
+            // Delete if this segment is before the cutoff position
+            if id < cutoff {
+                let bytes = self.store.len(&dir, id).await.unwrap_or(0);
+                self.store.delete(&dir, id).await?;
+                seal::remove_seal(self.fs.as_ref(), &dir, id).await?;
+                // Readers must not keep finding the segment through a cached descriptor
+                self.fd_cache.lock().await.remove(id);
+                self.lsn_index.remove(id);
+                self.record_cache.remove_segment(id);
+                self.quotas.release_segment(id);
+                self.gauges.purged(bytes);
+                deleted_count += 1;
+
+                obs_emit!(
+                    self.meter,
+                    VizEvent::Wal(WalEvt {
+                        node: self.node_id,
+                        seg: id,
+                        kind: WalKind::SegmentPurged { seg: id, bytes },
+                    })
+                );
+            }
+        }
+
+        Ok(deleted_count)
+    }
+
+    /// Replaces sealed segment `id` with `encoded`, the records compaction
+    /// kept from it. The namespaces and sizes of those records are `kept`.
+    ///
+    /// Returns the segment's previous size, or `None` if it was left alone
+    /// because it is no longer sealed, or an open reader is at or before it.
+    ///
+    /// The store replaces the segment atomically (the default store writes and
+    /// fsyncs the replacement beside the segment and renames it over it), so a
+    /// crash leaves either the old or the new segment. The seal sidecar is
+    /// removed first and rewritten afterwards if seals are on.
+    pub(crate) async fn replace_sealed_segment(
+        &self,
+        id: u64,
+        encoded: &[u8],
+        kept: &[(Option<u32>, u64)],
+    ) -> Result<Option<u64>, SegmentError> {
+        let _purge_guard = self.purge_lock.write().await;
+        let current_id = *self.current_id.lock().await;
+        if id >= current_id || self.pins.oldest().is_some_and(|pinned| pinned <= id) {
+            return Ok(None);
+        }
+
+        let (dir, seal_segments) = {
+            let config = self.config.lock().await;
+            (config.dir.clone(), config.seal_segments)
+        };
+        let old_len = match self.store.len(&dir, id).await {
+            Ok(len) => len,
+            // Purged since it was read
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        seal::remove_seal(self.fs.as_ref(), &dir, id).await?;
+        self.store.replace(&dir, id, encoded).await?;
+
+        self.forget_segment_contents(id).await;
+        self.quotas.replace_segment(id, kept.iter().copied());
+        self.stats.record_physical(encoded.len() as u64);
+        let (sealed_segments, sealed_bytes) =
+            sealed_usage(self.store.as_ref(), &dir, current_id).await?;
+        self.gauges.set_sealed(sealed_segments, sealed_bytes);
+        if seal_segments {
+            self.spawn_seal_verification(dir, id, false, true);
+        }
+        Ok(Some(old_len))
+    }
+
+    /// Drops what is cached about segment `id` after it was rewritten in
+    /// place: descriptors and indexed offsets refer to the old file.
+    pub(crate) async fn forget_segment_contents(&self, id: u64) {
+        self.fd_cache.lock().await.remove(id);
+        self.lsn_index.remove(id);
+        self.record_cache.remove_segment(id);
+    }
+
+    /// Discards every record at and after `position`, across segment boundaries.
+    ///
+    /// Segments after `position.segment_id` are deleted and the segment holding
+    /// `position` is cut at `position.offset`, which must be a record boundary
+    /// (a position returned by an append or a reader). That segment becomes the
+    /// active one and the result is fsynced before returning. `next_lsn` rewinds
+    /// to the LSN of the first discarded record, so the log stays dense.
+    ///
+    /// Returns the number of bytes discarded; a position at or past the current
+    /// write position discards nothing.
+    pub async fn truncate_from(&self, position: Position) -> Result<u64, SegmentError> {
+        let _purge_guard = self.purge_lock.write().await;
+        let mut current_id = self.current_id.lock().await;
+        let mut current = self.current.lock().await;
+
+        if position
+            >= (Position {
+                segment_id: current.id,
+                offset: current.size,
+            })
+        {
+            return Ok(0);
+        }
+
+        let (dir, max_segment_size) = {
+            let config = self.config.lock().await;
+            (config.dir.clone(), config.max_segment_size)
+        };
+
+        let target_len = if position.segment_id == current.id {
+            current.size
+        } else {
+            match self.store.len(&dir, position.segment_id).await {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(SegmentError::NotFound(position.segment_id));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        if position.offset > target_len {
+            return Err(SegmentError::InvalidConfig(format!(
+                "truncation point {} is past the end of segment {} ({} bytes)",
+                position.offset, position.segment_id, target_len
+            )));
+        }
+
+        // The first discarded record both proves the cut is on a record boundary
+        // and tells us which LSN to hand out next
+        let later_ids: Vec<u64> = self
+            .store
+            .list(&dir)
+            .await?
+            .into_iter()
+            .filter(|&id| id > position.segment_id)
+            .collect();
+        let mut first_discarded = None;
+        if position.offset < target_len {
+            let (record, _) = self
+                .read_unlocked(&dir, position, target_len)
+                .await?
+                .ok_or(SegmentError::Corruption {
+                    segment_id: position.segment_id,
+                    offset: position.offset,
+                })?;
+            first_discarded = Some(record);
+        } else {
+            for &id in &later_ids {
+                let len = if id == current.id {
+                    current.size
+                } else {
+                    self.store.len(&dir, id).await?
+                };
+                let start = Position {
+                    segment_id: id,
+                    offset: 0,
+                };
+                // Only an empty segment is skipped; read errors must not
+                // leave the next LSN pointing past the cut
+                if let Some((record, _)) = self.read_unlocked(&dir, start, len).await? {
+                    first_discarded = Some(record);
+                    break;
+                }
+            }
+        }
+
+        let mut discarded = target_len - position.offset;
+        for &id in &later_ids {
+            discarded += if id == current.id {
+                current.size
+            } else {
+                self.store.len(&dir, id).await?
+            };
+        }
+
+        if position.segment_id != current.id {
+            *current = SegmentFile::open(
+                self.store.as_ref(),
+                &dir,
+                position.segment_id,
+                false,
+                None,
+                self.clock.now(),
+            )
+            .await?;
+            *current_id = position.segment_id;
+        }
+        for &id in &later_ids {
+            self.store.delete(&dir, id).await?;
+            seal::remove_seal(self.fs.as_ref(), &dir, id).await?;
+            self.quotas.release_segment(id);
+        }
+        failpoint::check(failpoint::TRUNCATE_AFTER_DELETE)?;
+
+        current
+            .file
+            .set_len(position.offset)
+            .await
+            .map_err(current.io_error(IoOp::Truncate, position.offset))?;
+        current.size = position.offset;
+        current.file.sync_all().await?;
+        current.synced_size = position.offset;
+        // Found again by scanning on the next `last_record` call
+        current.last_record = None;
+        current.synced_last_record = None;
+        self.lsn_index.truncate(position);
+        self.record_cache.truncate(position);
+        if let Some(expiry) = &self.expiry {
+            expiry.truncate(position);
+        }
+        seal::remove_seal(self.fs.as_ref(), &dir, position.segment_id).await?;
+        self.fs.sync_dir(&dir).await?;
+
+        // Cached descriptors may point at deleted segments
+        self.fd_cache.lock().await.clear();
+        if self.hash_chain {
+            // The next append links to the record now at the end
+            *self.chain_head() = self
+                .find_chain_head(&dir, current.id, position.offset)
+                .await?;
+        }
+        let (sealed_segments, sealed_bytes) =
+            sealed_usage(self.store.as_ref(), &dir, current.id).await?;
+        self.gauges.set_sealed(sealed_segments, sealed_bytes);
+        self.gauges.active(current.size, max_segment_size);
+
+        if let Some(lsn) = first_discarded.and_then(|record| record.lsn) {
+            self.set_next_lsn(lsn);
+        }
+
+        Ok(discarded)
+    }
+
+    /// Returns the position of the first record whose LSN is at least `lsn`,
+    /// or `None` if every record in the log is older.
+    ///
+    /// An in-memory index of LSN samples narrows the search to a short scan.
+    pub async fn position_of_lsn(&self, lsn: u64) -> Result<Option<Position>, SegmentError> {
+        let ids = self.refresh_index().await?;
+        let Some(&oldest) = ids.first() else {
+            return Ok(None);
+        };
+        // LSNs grow with the log, so scan forward from the nearest sample
+        let start = self.lsn_index.seek(lsn).unwrap_or(Position {
+            segment_id: oldest,
+            offset: 0,
+        });
+        for &id in ids.iter().filter(|&&id| id >= start.segment_id) {
+            let from = if id == start.segment_id {
+                start
+            } else {
+                Position {
+                    segment_id: id,
+                    offset: 0,
+                }
+            };
+            let mut reader = self.read_from(from).await?;
+            while let Some((record, pos)) = reader.next_record().await? {
+                if record.lsn.is_some_and(|l| l >= lsn) {
+                    return Ok(Some(pos));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns where a reader should start to read from `lsn` onwards: the
+    /// first record with an LSN at least `lsn`, or the end of the log if
+    /// there is none yet.
+    ///
+    /// Fails with [`SegmentError::Purged`] if the log starts past `lsn`
+    /// because the segments that held it were purged.
+    pub(crate) async fn seek_lsn(&self, lsn: u64) -> Result<Position, SegmentError> {
+        let next = self.next_lsn();
+        if lsn > next {
+            return Err(SegmentError::InvalidConfig(format!(
+                "LSN {} has not been assigned yet (next is {})",
+                lsn, next
+            )));
+        }
+
+        let Some(position) = self.position_of_lsn(lsn).await? else {
+            return Ok(self.current_position().await);
+        };
+        let dir = self.dir().await;
+        let oldest = self.store.list(&dir).await?.first().copied();
+        if position.offset == 0 && position.segment_id > 0 && oldest == Some(position.segment_id) {
+            let first = self.first_lsn(&dir, position.segment_id).await?;
+            if first.is_some_and(|first| first > lsn) {
+                return Err(SegmentError::Purged(position.segment_id - 1));
+            }
+        }
+        Ok(position)
+    }
+
+    /// Returns the range of positions that can hold records appended in
+    /// `[since, until)`: the first from the time index, and the second only
+    /// if the index shows the log has already moved past `until`.
+    pub(crate) async fn time_bounds(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> Result<(Position, Option<Position>), SegmentError> {
+        let ids = self.refresh_index().await?;
+        let start = match (self.lsn_index.seek_time(since), ids.first()) {
+            (Some(position), _) => position,
+            (None, Some(&oldest)) => Position {
+                segment_id: oldest,
+                offset: 0,
+            },
+            (None, None) => self.current_position().await,
+        };
+        Ok((start, self.lsn_index.end_time(until)))
+    }
+
+    /// Drops deleted segments from the LSN index and adds the first record
+    /// of any it has not seen yet, returning the IDs of the live segments.
+    async fn refresh_index(&self) -> Result<Vec<u64>, SegmentError> {
+        let dir = self.dir().await;
+        let ids = self.store.list(&dir).await?;
+        self.lsn_index.retain(&ids);
+        for &id in &ids {
+            if !self.lsn_index.contains(id) {
+                if let Some((lsn, timestamp)) = self.first_stamp(&dir, id).await? {
+                    self.lsn_index.note_first(id, lsn, timestamp);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Returns the first LSN in a segment, if it has one, from the seal
+    /// sidecar when there is one.
+    async fn first_lsn(&self, dir: &Path, segment_id: u64) -> Result<Option<u64>, SegmentError> {
+        if let Some(seal) = seal::read_seal(self.fs.as_ref(), dir, segment_id).await {
+            return Ok(seal.first_lsn);
+        }
+        Ok(self
+            .first_record(segment_id)
+            .await?
+            .and_then(|record| record.lsn))
+    }
+
+    /// Returns the first LSN in a segment and the timestamp of its first
+    /// record, which records from before the WAL stamped them lack.
+    async fn first_stamp(
+        &self,
+        dir: &Path,
+        segment_id: u64,
+    ) -> Result<Option<(u64, Option<SystemTime>)>, SegmentError> {
+        let first = self.first_record(segment_id).await?;
+        let timestamp = first.as_ref().and_then(|record| record.timestamp);
+        let lsn = match first.and_then(|record| record.lsn) {
+            Some(lsn) => Some(lsn),
+            // The seal has the first LSN even if a later record carries it
+            None => seal::read_seal(self.fs.as_ref(), dir, segment_id)
+                .await
+                .and_then(|seal| seal.first_lsn),
+        };
+        Ok(lsn.map(|lsn| (lsn, timestamp)))
+    }
+
+    /// Reads the first record in a segment, if it has any.
+    async fn first_record(&self, segment_id: u64) -> Result<Option<Record>, SegmentError> {
+        let mut reader = self
+            .read_from(Position {
+                segment_id,
+                offset: 0,
+            })
+            .await?;
+        Ok(reader.next_record().await?.map(|(record, _)| record))
+    }
+
+    /// Reads the record at `position` while the caller holds the writer locks.
+    async fn read_unlocked(
+        &self,
+        dir: &Path,
+        position: Position,
+        logical_end: u64,
+    ) -> Result<Option<(Record, Position)>, SegmentError> {
+        let file = self
+            .fd_cache
+            .lock()
+            .await
+            .get_or_open(position.segment_id, self.store.as_ref(), dir)
+            .await?;
+        let mut reader = SegmentReader::new(
+            file,
+            segment_path(dir, position.segment_id),
+            position,
+            Some(logical_end),
+            ReaderConfig::default(),
+        );
+        reader.next_record().await
+    }
+
+    /// Relocates all segments to `new_dir` and switches new appends there.
+    ///
+    /// Sealed segments are hard-linked when `new_dir` is on the same filesystem
+    /// and copied otherwise, without blocking writers. The active segment is then
+    /// synced and copied while the writer lock is held, so no append can land in
+    /// the old directory after the switch. Copies are staged in `new_dir` until
+    /// they are all durable, so a migration that fails before then can simply be
+    /// retried. Then a `MOVED` marker naming `new_dir` is written to the old
+    /// directory, the copies are moved into place and the segment files in the
+    /// old directory are removed; opening a [`Wal`](crate::Wal) at the old
+    /// directory follows the marker, finishing the move if a crash cut it short.
+    ///
+    /// Returns the number of segments migrated.
+    pub async fn migrate_to(&self, new_dir: &Path) -> Result<u64, SegmentError> {
+        let old_dir = self.config.lock().await.dir.clone();
+        if new_dir == old_dir {
+            return Err(SegmentError::InvalidConfig(
+                "migration target is the current WAL directory".to_string(),
+            ));
+        }
+
+        self.require_segment_files("migration")?;
+        let marker = moved::encode(new_dir)?;
+        let fs = self.fs.as_ref();
+        fs.create_dir_all(new_dir).await?;
+        if !list_segment_ids(fs, new_dir).await?.is_empty() {
+            return Err(SegmentError::InvalidConfig(format!(
+                "migration target {} already contains WAL segments",
+                new_dir.display()
+            )));
+        }
+        // Left from an earlier migration away from `new_dir`
+        moved::remove_moved(fs, new_dir).await?;
+        // Left by an attempt that failed before writing its marker
+        moved::discard_staged(fs, new_dir).await?;
+
+        // Move sealed segments first, without holding the writer lock
+        let mut migrated = HashSet::new();
+        let current_id = *self.current_id.lock().await;
+        for id in list_segment_ids(fs, &old_dir).await? {
+            if id < current_id {
+                let copied = link_or_copy_sealed(fs, &old_dir, new_dir, id).await?;
+                self.stats.record_physical(copied);
+                migrated.insert(id);
+            }
+        }
+
+        // Block appends, then pick up segments sealed in the meantime and the active one
+        let mut current = self.current.lock().await;
+        current.sync().await?;
+
+        for id in list_segment_ids(fs, &old_dir).await? {
+            if id < current.id && !migrated.contains(&id) {
+                let copied = link_or_copy_sealed(fs, &old_dir, new_dir, id).await?;
+                self.stats.record_physical(copied);
+                migrated.insert(id);
+            }
+        }
+
+        // Only the logical prefix of the active segment is copied, never pre-allocated zeros
+        fs::copy_prefix(
+            fs,
+            &current.path,
+            &moved::staged_path(&segment_path(new_dir, current.id)),
+            current.size,
+        )
+        .await?;
+        self.stats.record_physical(current.size);
+        migrated.insert(current.id);
+        fs.sync_dir(new_dir).await?;
+        failpoint::check(failpoint::MIGRATE_BEFORE_MARKER)?;
+
+        // From here on, opening the old directory continues in the new one
+        moved::write_moved(fs, &old_dir, &marker).await?;
+        failpoint::check(failpoint::MIGRATE_AFTER_MARKER)?;
+        moved::promote_staged(fs, new_dir).await?;
+
+        let mut config = self.config.lock().await;
+        let preallocate_size = if config.preallocate {
+            Some(config.max_segment_size)
+        } else {
+            None
+        };
+        let last_record = current.synced_last_record;
+        let opened_at = current.opened_at;
+        *current = SegmentFile::open(
+            self.store.as_ref(),
+            new_dir,
+            current.id,
+            true,
+            preallocate_size,
+            opened_at,
+        )
+        .await?;
+        // The segment was synced above, so its last record is durable
+        current.last_record = last_record;
+        current.synced_last_record = last_record;
+        config.dir = new_dir.to_path_buf();
+        drop(config);
+        drop(current);
+
+        // Cached descriptors still point at the old files
+        self.fd_cache.lock().await.clear();
+
+        for id in &migrated {
+            fs.remove_file(&segment_path(&old_dir, *id)).await?;
+            seal::remove_seal(fs, &old_dir, *id).await?;
+        }
+        fs.sync_dir(&old_dir).await?;
+
+        Ok(migrated.len() as u64)
+    }
+
+    /// Copies a consistent snapshot of the WAL into `dest_dir` while appends continue.
+    ///
+    /// The snapshot consists of every sealed segment plus the prefix of the active
+    /// segment up to the durable position at the time of the call. Segment garbage
+    /// collection is held off until the copy completes, so rotation and
+    /// `delete_segments_before` cannot race with the backup.
+    pub async fn backup_to(&self, dest_dir: &Path) -> Result<BackupInfo, SegmentError> {
+        self.require_segment_files("backup")?;
+        let _purge_guard = self.purge_lock.read().await;
+        let dir = self.config.lock().await.dir.clone();
+        let durable = self.durable_position().await;
+
+        let fs = self.fs.as_ref();
+        fs.create_dir_all(dest_dir).await?;
+        if !list_segment_ids(fs, dest_dir).await?.is_empty() {
+            return Err(SegmentError::InvalidConfig(format!(
+                "backup target {} already contains WAL segments",
+                dest_dir.display()
+            )));
+        }
+
+        let mut info = BackupInfo {
+            segments: 0,
+            bytes: 0,
+            position: durable,
+        };
+
+        for id in list_segment_ids(fs, &dir).await? {
+            let src = segment_path(&dir, id);
+            let len = match id.cmp(&durable.segment_id) {
+                // Sealed segments are immutable and already truncated to their data
+                Ordering::Less => fs.len(&src).await?,
+                Ordering::Equal => durable.offset,
+                // Rotated into after the snapshot was taken
+                Ordering::Greater => continue,
+            };
+
+            fs::copy_prefix(fs, &src, &segment_path(dest_dir, id), len).await?;
+            info.segments += 1;
+            info.bytes += len;
+        }
+
+        fs.sync_dir(dest_dir).await?;
+        Ok(info)
+    }
+
+    /// Returns the configured limits that appends check.
+    async fn append_limits(&self) -> AppendLimits {
+        let config = self.config.lock().await;
+        AppendLimits {
+            max_segment_size: config.max_segment_size,
+            max_record_size: config.max_record_size,
+            max_segment_age: config.max_segment_age,
+            now: self.clock.now(),
+        }
+    }
+
+    /// Changes the size at which the active segment rotates, from the next
+    /// append on. Segments created afterwards are pre-allocated to it.
+    pub async fn set_max_segment_size(&self, bytes: u64) {
+        self.config.lock().await.max_segment_size = bytes;
+    }
+
+    /// Changes the age at which the active segment rotates, from the next
+    /// append on.
+    pub async fn set_max_segment_age(&self, age: Option<Duration>) {
+        self.config.lock().await.max_segment_age = age;
+    }
+
+    /// Rejects appends from now on, for when the owning WAL closes while
+    /// writer handles are still around.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, AtomicOrdering::Release);
+    }
+
+    /// Makes appends fail once `lease` is lost, or lifts the requirement.
+    pub(crate) fn set_lease(&self, lease: Option<Arc<LeaseState>>) {
+        *self.lease.lock().unwrap_or_else(PoisonError::into_inner) = lease;
+    }
+
+    fn check_open(&self) -> Result<(), SegmentError> {
+        if self.closed.load(AtomicOrdering::Acquire) {
+            return Err(SegmentError::Closed);
+        }
+        if let Some(lease) = &*self.lease.lock().unwrap_or_else(PoisonError::into_inner) {
+            lease.check()?;
+        }
+        Ok(())
+    }
+
+    /// Locks the active segment to append `records`. While waiting, their
+    /// key and value bytes count as queued, and `AppendBackpressure` is
+    /// emitted when the queue grows past [`BACKPRESSURE_BYTES`].
+    async fn lock_for_append(
+        &self,
+        records: &[Record],
+    ) -> tokio::sync::MutexGuard<'_, SegmentFile> {
+        if let Ok(current) = self.current.try_lock() {
+            return current;
+        }
+        let bytes: u64 = records
+            .iter()
+            .map(|r| (r.key.len() + r.value.len()) as u64)
+            .sum();
+        let before = self.queued_bytes.fetch_add(bytes, AtomicOrdering::Relaxed);
+        let queued = Queued {
+            queued_bytes: &self.queued_bytes,
+            bytes,
+        };
+        let current = self.current.lock().await;
+        drop(queued);
+
+        if before < BACKPRESSURE_BYTES && before + bytes >= BACKPRESSURE_BYTES {
+            obs_emit!(
+                self.meter,
+                VizEvent::Wal(WalEvt {
+                    node: self.node_id,
+                    seg: current.id,
+                    kind: WalKind::AppendBackpressure {
+                        queued_bytes: before + bytes,
+                    },
+                })
+            );
+        }
+        current
+    }
+
+    /// Checks `records` against their namespaces' quotas, failing or
+    /// waiting as the quota says.
+    async fn enforce_quotas(&self, records: &[Record]) -> Result<(), SegmentError> {
+        if let Some(delay) = self.quotas.check(records)? {
+            self.runtime.sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Returns the usage of every namespace and the quotas it is held to.
+    pub(crate) fn quotas(&self) -> &QuotaTracker {
+        &self.quotas
+    }
+
+    /// Appends a record to the WAL, rotating if necessary.
+    /// Applies the configured fsync policy.
+    pub async fn append(&self, record: &Record) -> Result<Position, SegmentError> {
+        self.append_at(record, None).await
+    }
+
+    /// Appends a record only if the log currently ends at `expected_tail`,
+    /// failing with [`SegmentError::TailMoved`] otherwise.
+    ///
+    /// A record that has to go to a new segment is still accepted as long as
+    /// nothing else was appended before the rotation.
+    pub async fn append_if(
+        &self,
+        expected_tail: Position,
+        record: &Record,
+    ) -> Result<Position, SegmentError> {
+        self.append_at(record, Some(expected_tail)).await
+    }
+
+    async fn append_at(
+        &self,
+        record: &Record,
+        expected_tail: Option<Position>,
+    ) -> Result<Position, SegmentError> {
+        self.check_open()?;
+        let records = std::slice::from_ref(record);
+        self.enforce_quotas(records).await?;
+        let start = std::time::Instant::now();
+        let _memory = self.reserve_append(records).await;
+        let limits = self.append_limits().await;
+        let compressed = self
+            .encode_pool
+            .compress(self.runtime.as_ref(), records)
+            .await?;
+
+        let mut current = self.lock_for_append(records).await;
+        if let Some(expected) = expected_tail {
+            current.check_tail(expected)?;
+        }
+        let (mut encoded, mut next_lsn) =
+            self.stamp_and_encode(records, compressed.as_deref(), limits.max_record_size)?;
+
+        // Check if we need to rotate
+        if current.needs_rotation(encoded[0].0.len(), &limits) {
+            drop(current); // Release lock before rotating
+            self.rotate().await?;
+            current = self.current.lock().await;
+            if let Some(expected) = expected_tail {
+                // Only the rotation itself may have moved the tail
+                let rotated = Position {
+                    segment_id: expected.segment_id + 1,
+                    offset: 0,
+                };
+                if current.tail() != rotated {
+                    return Err(SegmentError::TailMoved {
+                        expected,
+                        actual: current.tail(),
+                    });
+                }
+            }
+            // Other writers may have appended while the lock was released
+            (encoded, next_lsn) =
+                self.stamp_and_encode(records, compressed.as_deref(), limits.max_record_size)?;
+        }
+
+        let (bytes, lsn, timestamp) = &encoded[0];
+        let offset = current.append(bytes).await?;
+        self.set_next_lsn(next_lsn);
+        let segment_id = current.id;
+        self.advance_chain(Position { segment_id, offset }, bytes);
+        self.lsn_index
+            .note(Position { segment_id, offset }, *lsn, *timestamp);
+        self.note_expiry(record, *lsn, *timestamp, Position { segment_id, offset });
+
+        // Apply fsync policy
+        let fsyncs = self.stats.fsync_count();
+        self.apply_fsync_policy(&mut current, segment_id, records)
+            .await?;
+        self.durable_advanced.notify_waiters();
+        if trace::any_sampled(records) {
+            trace::report_append(
+                self.meter.as_ref(),
+                self.node_id,
+                [(record, Position { segment_id, offset }, *lsn)],
+                start.elapsed(),
+                self.fsync_since(fsyncs),
+            );
+        }
+        self.stats
+            .record_append(1, payload_len(records), bytes.len() as u64, start.elapsed());
+        self.observe_latency(&self.append_slo, segment_id, start.elapsed());
+        self.stats
+            .record_namespaces([(record.namespace, bytes.len() as u64)]);
+        self.quotas
+            .record(segment_id, [(record.namespace, bytes.len() as u64)]);
+        self.gauges.active(current.size, limits.max_segment_size);
+        self.gauges.efficiency(self.stats.write_efficiency());
+
+        Ok(Position { segment_id, offset })
+    }
+
+    /// Appends a batch of records to the WAL.
+    ///
+    /// More efficient than individual appends because:
+    /// - Lock held only once for entire batch
+    /// - Single fsync for entire batch (if policy is Always)
+    /// - No interleaving with other writers
+    ///
+    /// With key coalescing, puts replaced by a later put in the batch are
+    /// left out (see [`crate::coalesce`]).
+    pub async fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        if self.coalesce_keys {
+            if let Some(batch) = coalesce::coalesce(records) {
+                let written = self.write_batch(&batch.records).await?;
+                self.stats.record_coalesced(batch.dropped());
+                return Ok(batch.positions(&written));
+            }
+        }
+        self.write_batch(records).await
+    }
+
+    async fn write_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        self.check_open()?;
+        if records.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.enforce_quotas(records).await?;
+
+        let start = std::time::Instant::now();
+        let _memory = self.reserve_append(records).await;
+        let limits = self.append_limits().await;
+        let compressed = self
+            .encode_pool
+            .compress(self.runtime.as_ref(), records)
+            .await?;
+
+        let mut current = self.lock_for_append(records).await;
+        let mut positions = Vec::with_capacity(records.len());
+        let (mut encoded, mut next_lsn) =
+            self.stamp_and_encode(records, compressed.as_deref(), limits.max_record_size)?;
+
+        // Check if we need to rotate before starting batch
+        let total_size: usize = encoded.iter().map(|(e, _, _)| e.len()).sum();
+        if current.needs_rotation(total_size, &limits) {
+            drop(current);
+            self.rotate().await?;
+            current = self.current.lock().await;
+            (encoded, next_lsn) =
+                self.stamp_and_encode(records, compressed.as_deref(), limits.max_record_size)?;
+        }
+
+        // Append all records
+        for (record, (bytes, lsn, timestamp)) in records.iter().zip(&encoded) {
+            let offset = current.append(bytes).await?;
+            let position = Position {
+                segment_id: current.id,
+                offset,
+            };
+            self.lsn_index.note(position, *lsn, *timestamp);
+            self.note_expiry(record, *lsn, *timestamp, position);
+            self.advance_chain(position, bytes);
+            positions.push(position);
+        }
+        self.set_next_lsn(next_lsn);
+
+        let segment_id = current.id;
+
+        // Apply fsync policy once for entire batch
+        let fsyncs = self.stats.fsync_count();
+        self.apply_fsync_policy(&mut current, segment_id, records)
+            .await?;
+        self.durable_advanced.notify_waiters();
+        if trace::any_sampled(records) {
+            let appended = records
+                .iter()
+                .zip(&positions)
+                .zip(&encoded)
+                .map(|((record, position), (_, lsn, _))| (record, *position, *lsn));
+            trace::report_append(
+                self.meter.as_ref(),
+                self.node_id,
+                appended,
+                start.elapsed(),
+                self.fsync_since(fsyncs),
+            );
+        }
+        let bytes = encoded.iter().map(|(e, _, _)| e.len() as u64).sum();
+        self.stats
+            .record_append(records.len(), payload_len(records), bytes, start.elapsed());
+        self.observe_latency(&self.append_slo, segment_id, start.elapsed());
+        let appended = || {
+            records
+                .iter()
+                .zip(&encoded)
+                .map(|(record, (bytes, _, _))| (record.namespace, bytes.len() as u64))
+        };
+        self.stats.record_namespaces(appended());
+        self.quotas.record(segment_id, appended());
+        self.gauges.active(current.size, limits.max_segment_size);
+        self.gauges.efficiency(self.stats.write_efficiency());
+
+        Ok(positions)
+    }
+
+    /// Writes `records` for a bulk import: each run that fits in the active
+    /// segment goes out as one write, rotating between runs, and the fsync
+    /// policy is not applied. Namespace quotas are enforced as for appends.
+    /// Returns the bytes written.
+    pub(crate) async fn import_chunk(&self, records: &[Record]) -> Result<u64, SegmentError> {
+        self.check_open()?;
+        self.enforce_quotas(records).await?;
+        let start = std::time::Instant::now();
+        let _memory = self.reserve_append(records).await;
+        let limits = self.append_limits().await;
+        let compressed = self
+            .encode_pool
+            .compress(self.runtime.as_ref(), records)
+            .await?;
+        let mut written = 0;
+        let mut rest = records;
+
+        while !rest.is_empty() {
+            let mut current = self.current.lock().await;
+            let done = records.len() - rest.len();
+            let rest_compressed = compressed.as_deref().map(|values| &values[done..]);
+            let (encoded, _) =
+                self.stamp_and_encode(rest, rest_compressed, limits.max_record_size)?;
+            let mut run = 0;
+            let mut run_size = 0;
+            for (bytes, _, _) in &encoded {
+                if current.needs_rotation(run_size + bytes.len(), &limits) {
+                    break;
+                }
+                run += 1;
+                run_size += bytes.len();
+            }
+            if run == 0 && current.size == 0 {
+                // A record larger than a segment gets one to itself
+                run = 1;
+            }
+            if run == 0 {
+                drop(current);
+                self.rotate().await?;
+                continue;
+            }
+
+            let encoded = &encoded[..run];
+            let mut offset = current.append_all(encoded).await?;
+            let segment_id = current.id;
+            let mut next_lsn = self.next_lsn();
+            for (record, (bytes, lsn, timestamp)) in rest.iter().zip(encoded) {
+                self.lsn_index
+                    .note(Position { segment_id, offset }, *lsn, *timestamp);
+                self.note_expiry(record, *lsn, *timestamp, Position { segment_id, offset });
+                self.advance_chain(Position { segment_id, offset }, bytes);
+                offset += bytes.len() as u64;
+                next_lsn = next_lsn.max(lsn.saturating_add(1));
+            }
+            self.set_next_lsn(next_lsn);
+            self.durable_advanced.notify_waiters();
+
+            let bytes: u64 = encoded.iter().map(|(e, _, _)| e.len() as u64).sum();
+            let logical = payload_len(&rest[..run]);
+            self.stats
+                .record_append(run, logical, bytes, start.elapsed());
+            let appended = || {
+                rest.iter()
+                    .zip(encoded)
+                    .map(|(record, (bytes, _, _))| (record.namespace, bytes.len() as u64))
+            };
+            self.stats.record_namespaces(appended());
+            self.quotas.record(segment_id, appended());
+            self.gauges.active(current.size, limits.max_segment_size);
+            self.gauges.efficiency(self.stats.write_efficiency());
+            written += bytes;
+            rest = &rest[run..];
+        }
+
+        Ok(written)
+    }
+
+    /// Flushes the current segment to the OS.
+    ///
+    /// Appends hand their bytes to the filesystem before they return, so this
+    /// only waits for appends in progress.
+    pub async fn flush(&self) -> Result<(), SegmentError> {
+        let _current = self.current.lock().await;
+        Ok(())
+    }
+
+    /// Returns a snapshot of the built-in statistics.
+    pub async fn metrics(&self) -> WalMetrics {
+        let current = self.current.lock().await;
+        let mut metrics = self
+            .stats
+            .snapshot(current.id, current.size, current.synced_size);
+        (metrics.record_cache_hits, metrics.record_cache_misses) = self.record_cache.counts();
+        metrics.offloaded_encodes = self.encode_pool.offloaded();
+        metrics.expiring_keys = self.expiry.as_ref().map_or(0, |e| e.len() as u64);
+        metrics
+    }
+
+    /// Returns what has been appended to `namespace` since the WAL opened,
+    /// and what of it is still in the log.
+    pub fn namespace_metrics(&self, namespace: u32) -> NamespaceMetrics {
+        let (usage, quota_exceeded) = self.quotas.usage(namespace);
+        NamespaceMetrics {
+            records_in_log: usage.records,
+            bytes_in_log: usage.bytes,
+            quota_exceeded,
+            ..self.stats.namespace(namespace)
+        }
+    }
+
+    /// Syncs the current segment to disk (fsync).
+    pub async fn sync(&self) -> Result<(), SegmentError> {
+        let start = std::time::Instant::now();
+        let mut current = self.current.lock().await;
+        current.sync().await?;
+        self.record_fsync(current.id, start.elapsed()).await;
+        self.durable_advanced.notify_waiters();
+
+        Ok(())
+    }
+
+    /// Rotates to a new segment file.
+    async fn rotate(&self) -> Result<(), SegmentError> {
+        let mut current_id = self.current_id.lock().await;
+        let new_id = *current_id + 1;
+
+        // Held until the new segment is swapped in: `migrate_to` switches the
+        // directory under this lock, so the one read below stays current
+        let mut current = self.current.lock().await;
+        let config = self.config.lock().await.clone();
+        let old_size = current.size;
+        let old_id = current.id;
+        let last_record = current.last_record;
+
+        // Truncate old segment to actual written size (important for pre-allocated files)
+        current.finalize(self.store.as_ref(), &config.dir).await?;
+        failpoint::check(failpoint::ROTATE_AFTER_FINALIZE)?;
+        self.stats.record_rotation();
+        self.gauges.sealed(old_size);
+
+        obs_emit!(
+            self.meter,
+            VizEvent::Wal(WalEvt {
+                node: self.node_id,
+                seg: old_id,
+                kind: WalKind::SegmentRoll { bytes: old_size },
+            })
+        );
+
+        if config.verify_on_seal || config.seal_segments {
+            self.spawn_seal_verification(
+                config.dir.clone(),
+                old_id,
+                config.verify_on_seal,
+                config.seal_segments,
+            );
+        }
+
+        // Create new segment with optional pre-allocation
+        let preallocate_size = if config.preallocate {
+            Some(config.max_segment_size)
+        } else {
+            None
+        };
+        let mut new_segment = SegmentFile::open(
+            self.store.as_ref(),
+            &config.dir,
+            new_id,
+            true,
+            preallocate_size,
+            self.clock.now(),
+        )
+        .await?;
+        // The finalized segment holds the last record until the next append
+        new_segment.last_record = last_record;
+        new_segment.synced_last_record = last_record;
+
+        // Swap in the new segment
+        *current = new_segment;
+        *current_id = new_id;
+        self.gauges.active(0, config.max_segment_size);
+
+        Ok(())
+    }
+
+    /// Verifies a freshly sealed segment on a background task, reporting the
+    /// outcome if `report` is set and writing a seal sidecar if `write_seal` is.
+    fn spawn_seal_verification(
+        &self,
+        dir: PathBuf,
+        segment_id: u64,
+        report: bool,
+        write_seal: bool,
+    ) {
+        let meter = self.meter.clone();
+        let node_id = self.node_id;
+        let fs = Arc::clone(&self.fs);
+        let store = Arc::clone(&self.store);
+        let stats = Arc::clone(&self.stats);
+
+        let task = self.runtime.spawn(Box::pin(async move {
+            let verification =
+                match crate::scrub::verify_segment_in(store.as_ref(), &dir, segment_id).await {
+                    Ok(v) => v,
+                    // Deleted (or migrated) before verification could run
+                    Err(e)
+                        if e.io_error().map(|e| e.kind()) == Some(std::io::ErrorKind::NotFound) =>
+                    {
+                        return
+                    }
+                    // A segment that cannot be read back failed verification
+                    Err(e) => {
+                        if report {
+                            obs_count!(meter, "wal_seal_verify_failures_total", &[], 1);
+                            obs_emit!(
+                                meter,
+                                VizEvent::Wal(WalEvt {
+                                    node: node_id,
+                                    seg: segment_id,
+                                    kind: WalKind::SegmentVerifyFailed {
+                                        error: e.to_string(),
+                                    },
+                                })
+                            );
+                        }
+                        return;
+                    }
+                };
+
+            if write_seal {
+                if let Some(seal) = SegmentSeal::from_verification(&verification) {
+                    if seal::write_seal(fs.as_ref(), &dir, segment_id, &seal)
+                        .await
+                        .is_ok()
+                    {
+                        stats.record_physical(seal::SEAL_LEN as u64);
+                    }
+                }
+            }
+            if !report {
+                return;
+            }
+
+            let kind = match verification.corrupt_offset {
+                None => WalKind::SegmentVerified,
+                Some(offset) => WalKind::CorruptionDetected { offset },
+            };
+
+            if matches!(kind, WalKind::CorruptionDetected { .. }) {
+                obs_count!(meter, "wal_seal_verify_failures_total", &[], 1);
+            }
+            obs_emit!(
+                meter,
+                VizEvent::Wal(WalEvt {
+                    node: node_id,
+                    seg: segment_id,
+                    kind,
+                })
+            );
+        }));
+
+        let mut background = self.background.lock().unwrap();
+        background.retain(|task| !task.is_finished());
+        background.push(task);
+    }
+
+    /// Reports appended bytes that were never fsynced, for a WAL dropped
+    /// without being closed or synced, and syncs them if `sync` is set.
+    ///
+    /// Runs from `Drop`, so the sync is blocking and best-effort: a write the
+    /// runtime has not yet handed to the OS can still be lost. Returns the
+    /// number of unsynced bytes found.
+    pub(crate) fn check_unsynced_on_drop(&self, sync: bool) -> u64 {
+        let Ok(mut current) = self.current.try_lock() else {
+            return 0;
+        };
+        let unsynced = current.size - current.synced_size;
+        if unsynced == 0 {
+            return 0;
+        }
+
+        self.meter.counter("wal_unsynced_drops_total", &[]).inc(1);
+        self.meter
+            .counter("wal_unsynced_drop_bytes_total", &[])
+            .inc(unsynced);
+        obs_emit!(
+            self.meter,
+            VizEvent::Wal(WalEvt {
+                node: self.node_id,
+                seg: current.id,
+                kind: WalKind::UnsyncedDrop { bytes: unsynced },
+            })
+        );
+
+        if sync && current.file.sync_data_blocking().is_ok() {
+            current.synced_size = current.size;
+            current.synced_last_record = current.last_record;
+        }
+        unsynced
+    }
+
+    /// Waits for seal verifications started so far to finish.
+    pub async fn wait_for_background(&self) {
+        let tasks = std::mem::take(&mut *self.background.lock().unwrap());
+        for task in tasks {
+            task.join().await;
+        }
+    }
+
+    /// Applies the configured fsync policy to the current segment, or fsyncs
+    /// regardless of it if the appended records include a
+    /// [`Durability::Critical`] one.
+    ///
+    /// Handles all three fsync policies (Always, Batch, Os) and emits
+    /// appropriate observability events.
+    async fn apply_fsync_policy(
+        &self,
+        current: &mut SegmentFile,
+        segment_id: u64,
+        records: &[Record],
+    ) -> Result<(), SegmentError> {
+        if records
+            .iter()
+            .any(|record| record.durability == Durability::Critical)
+        {
+            return self.fsync_with_timing(current, segment_id).await;
+        }
+        let fsync_policy = self.config.lock().await.fsync_policy;
+        match fsync_policy {
+            FsyncPolicy::Always => {
+                self.fsync_with_timing(current, segment_id).await
+            }
+            FsyncPolicy::Batch(window) => {
+                self.fsync_if_window_elapsed(current, segment_id, window).await
+            }
+            FsyncPolicy::Os => {
+                // No fsync - let OS handle it
+                Ok(())
+            }
+        }
+    }
+
+    /// Changes the fsync policy applied by subsequent appends.
+    pub async fn set_fsync_policy(&self, policy: FsyncPolicy) {
+        self.config.lock().await.fsync_policy = policy;
+    }
+
+    /// Returns the fsync policy appends currently apply.
+    pub async fn fsync_policy(&self) -> FsyncPolicy {
+        self.config.lock().await.fsync_policy
+    }
+
+    /// Returns the latest fsync if any was made since the count was
+    /// `before`, for spans of the appends it covered.
+    fn fsync_since(&self, before: u64) -> Option<FsyncSpan> {
+        let seq = self.stats.fsync_count();
+        (seq != before).then(|| FsyncSpan {
+            seq,
+            duration: self.stats.last_fsync(),
+        })
+    }
+
+    /// Performs fsync and emits timing event.
+    async fn fsync_with_timing(
+        &self,
+        current: &mut SegmentFile,
+        segment_id: u64,
+    ) -> Result<(), SegmentError> {
+        let start = Instant::now();
+        current.sync().await?;
+        self.record_fsync(segment_id, start.elapsed()).await;
+        Ok(())
+    }
+
+    /// Feeds an operation on `segment_id` that took `elapsed` to `slo`'s
+    /// monitor, if set.
+    fn observe_latency(&self, slo: &Option<SloMonitor>, segment_id: u64, elapsed: Duration) {
+        if let Some(monitor) = slo {
+            monitor.observe(
+                self.meter.as_ref(),
+                self.node_id,
+                segment_id,
+                elapsed,
+                self.clock.now(),
+            );
+        }
+    }
+
+    /// Counts an fsync of `segment_id` that took `elapsed` and emits `Fsync`,
+    /// plus `SlowFsync` if it reached the configured threshold.
+    async fn record_fsync(&self, segment_id: u64, elapsed: Duration) {
+        self.stats.record_fsync(elapsed);
+        self.observe_latency(&self.fsync_slo, segment_id, elapsed);
+        self.gauges.fsynced();
+        let ms = elapsed.as_millis() as u32;
+        obs_emit!(
+            self.meter,
+            VizEvent::Wal(WalEvt {
+                node: self.node_id,
+                seg: segment_id,
+                kind: WalKind::Fsync { ms },
+            })
+        );
+
+        let threshold = self.config.lock().await.slow_fsync_threshold;
+        if let Some(threshold) = threshold.filter(|t| elapsed >= *t) {
+            obs_emit!(
+                self.meter,
+                VizEvent::Wal(WalEvt {
+                    node: self.node_id,
+                    seg: segment_id,
+                    kind: WalKind::SlowFsync {
+                        ms,
+                        threshold_ms: threshold.as_millis() as u32,
+                    },
+                })
+            );
+        }
+    }
+
+    /// Performs fsync if the time window has elapsed since last sync.
+    async fn fsync_if_window_elapsed(
+        &self,
+        current: &mut SegmentFile,
+        segment_id: u64,
+        window: Duration,
     ) -> Result<(), SegmentError> {
         let mut last_sync = self.last_fsync.lock().await;
         let should_sync = match *last_sync {
             None => true,
-            Some(last) => last.elapsed() >= window,
+            Some(last) => self.clock.now().saturating_duration_since(last) >= window,
         };
 
         if should_sync {
             // Update timestamp BEFORE fsync to prevent multiple concurrent fsyncs
+            *last_sync = Some(self.clock.now());
             let fsync_start = Instant::now();
-            *last_sync = Some(fsync_start);
             drop(last_sync); // Release lock before expensive fsync
 
             current.sync().await?;
-            let elapsed_ms = fsync_start.elapsed().as_millis() as u32;
-
-            self.meter.emit(VizEvent::Wal(WalEvt {
-                node: self.node_id,
-                seg: segment_id,
-                kind: WalKind::Fsync { ms: elapsed_ms },
-            }));
+            self.record_fsync(segment_id, fsync_start.elapsed()).await;
         }
 
         Ok(())
@@ -545,158 +2441,521 @@ impl SegmentManager {
 
     /// Reads records from a segment starting at the given position.
     pub async fn read_from(&self, position: Position) -> Result<SegmentReader, SegmentError> {
+        self.read_from_with(position, ReaderConfig::default()).await
+    }
+
+    /// Like [`read_from`](Self::read_from), with the given reader configuration.
+    pub async fn read_from_with(
+        &self,
+        position: Position,
+        config: ReaderConfig,
+    ) -> Result<SegmentReader, SegmentError> {
+        // Pin first: a purge that has not yet started will now leave the segment
+        let pin = self.pins.pin(position.segment_id);
+
         // Get file from cache (or open if not cached)
+        let dir = self.config.lock().await.dir.clone();
         let mut cache = self.fd_cache.lock().await;
-        let file_arc = cache.get_or_open(position.segment_id, &self.config.dir).await?;
+        let file_arc = match cache
+            .get_or_open(position.segment_id, self.store.as_ref(), &dir)
+            .await
+        {
+            Err(SegmentError::NotFound(id)) if id < *self.current_id.lock().await => {
+                return Err(SegmentError::Purged(id))
+            }
+            result => result?,
+        };
         drop(cache); // Release cache lock
 
         // For the current segment, get the logical size to avoid reading pre-allocated zeros
         let logical_size = if position.segment_id == *self.current_id.lock().await {
-            Some(self.current.lock().await.size)
+            let current = self.current.lock().await;
+            Some(if config.durable_only {
+                current.synced_size
+            } else {
+                current.size
+            })
         } else {
             // For finalized segments, use actual file size
             None
         };
 
-        Ok(SegmentReader {
-            file: file_arc,
-            position: position.offset,
-            segment_id: position.segment_id,
-            logical_end: logical_size,
-        })
+        let path = segment_path(&dir, position.segment_id);
+        let mut reader = SegmentReader::new(file_arc, path, position, logical_size, config);
+        reader.pin = Some(pin);
+        reader.memory = self
+            .memory
+            .as_ref()
+            .map(|budget| budget.charge(MemoryComponent::ReadAhead));
+        Ok(reader)
     }
 
     /// Returns the current write position.
     pub async fn current_position(&self) -> Position {
+        self.current.lock().await.tail()
+    }
+
+    /// Returns the directory segments are currently stored in.
+    pub async fn dir(&self) -> PathBuf {
+        self.config.lock().await.dir.clone()
+    }
+
+    /// Returns the IDs of all sealed segments (every segment before the active one),
+    /// in ascending order.
+    pub async fn sealed_segment_ids(&self) -> Result<Vec<u64>, SegmentError> {
+        let dir = self.dir().await;
+        let current_id = *self.current_id.lock().await;
+        let mut ids = self.store.list(&dir).await?;
+        ids.retain(|&id| id < current_id);
+        Ok(ids)
+    }
+
+    /// Returns the ID of the first segment after `id` that exists on disk.
+    pub(crate) async fn next_segment_id_after(&self, id: u64) -> Result<Option<u64>, SegmentError> {
+        let dir = self.dir().await;
+        Ok(self
+            .store
+            .list(&dir)
+            .await?
+            .into_iter()
+            .find(|&next| next > id))
+    }
+
+    /// Returns the position up to which appended data is known to be fsynced.
+    ///
+    /// Everything before this position survives a crash; records between it and
+    /// `current_position()` may be lost.
+    pub async fn durable_position(&self) -> Position {
         let current = self.current.lock().await;
         Position {
             segment_id: current.id,
-            offset: current.size,
+            offset: current.synced_size,
+        }
+    }
+
+    /// Checks that `position` still lies within the log, failing with
+    /// [`SegmentError::CursorGone`] if its segment was purged or the log was
+    /// truncated below it.
+    pub(crate) async fn check_position(&self, position: Position) -> Result<(), SegmentError> {
+        let dir = self.dir().await;
+        let current = self.current.lock().await;
+        let len = match position.segment_id.cmp(&current.id) {
+            Ordering::Equal => current.size,
+            Ordering::Greater => return Err(SegmentError::CursorGone(position)),
+            Ordering::Less => match self.store.len(&dir, position.segment_id).await {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(SegmentError::CursorGone(position))
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+        if position.offset > len {
+            return Err(SegmentError::CursorGone(position));
+        }
+        Ok(())
+    }
+
+    /// Returns the last durable record and its position, or `None` if the log
+    /// holds no durable records.
+    ///
+    /// The position of the last record is tracked as records are appended and
+    /// synced, so this is a single read. Only the first call after opening or
+    /// truncating the log has to scan for it.
+    pub async fn last_record(&self) -> Result<Option<(Record, Position)>, SegmentError> {
+        let _purge = self.purge_lock.read().await;
+        let dir = self.dir().await;
+        let mut current = self.current.lock().await;
+
+        let position = match current.synced_last_record {
+            Some(position) => Some(position),
+            None => {
+                let found = self
+                    .find_last_record(&dir, current.id, current.synced_size)
+                    .await?;
+                current.synced_last_record = found;
+                if current.last_record.is_none() {
+                    current.last_record = found;
+                }
+                found
+            }
+        };
+
+        match position {
+            Some(position) => {
+                let end = if position.segment_id == current.id {
+                    current.synced_size
+                } else {
+                    u64::MAX
+                };
+                match self.read_unlocked(&dir, position, end).await {
+                    // Purged along with the segment that held it
+                    Err(SegmentError::NotFound(_)) => Ok(None),
+                    result => result,
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Scans for the last record below `end` in the active segment, falling
+    /// back to earlier segments if it has none.
+    async fn find_last_record(
+        &self,
+        dir: &Path,
+        current_id: u64,
+        end: u64,
+    ) -> Result<Option<Position>, SegmentError> {
+        let mut ids = self.store.list(dir).await?;
+        ids.retain(|&id| id < current_id);
+        ids.push(current_id);
+
+        for &segment_id in ids.iter().rev() {
+            let file = self
+                .fd_cache
+                .lock()
+                .await
+                .get_or_open(segment_id, self.store.as_ref(), dir)
+                .await?;
+            let logical_end = (segment_id == current_id).then_some(end);
+            let config = ReaderConfig {
+                read_ahead_bytes: 1024 * 1024,
+                ..Default::default()
+            };
+            let start = Position {
+                segment_id,
+                offset: 0,
+            };
+            let path = segment_path(dir, segment_id);
+            let mut reader = SegmentReader::new(file, path, start, logical_end, config);
+
+            let mut last = None;
+            while let Some((_, position)) = reader.next_record().await? {
+                last = Some(position);
+            }
+            if last.is_some() {
+                return Ok(last);
+            }
         }
+        Ok(None)
     }
 
     /// Finalizes the current segment by truncating to actual written size.
     /// Should be called before closing the WAL.
     pub async fn finalize_current(&self) -> Result<(), SegmentError> {
+        let dir = self.dir().await;
         let mut current = self.current.lock().await;
-        current.finalize().await
+        current.finalize(self.store.as_ref(), &dir).await?;
+        self.durable_advanced.notify_waiters();
+        Ok(())
+    }
+
+    /// Returns the notifier woken when appended data may have become durable.
+    pub(crate) fn durable_notify(&self) -> Arc<Notify> {
+        Arc::clone(&self.durable_advanced)
+    }
+}
+
+/// Default size of each read a `SegmentReader` issues to refill its buffer.
+const READ_BUFFER_SIZE: usize = 65536; // 64KB buffer for better performance
+
+/// Configuration for segment and log readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderConfig {
+    /// Bytes fetched from the file each time the read buffer runs dry
+    /// (default: 64KB).
+    ///
+    /// Large values (several MB) speed up sequential replay of cold segments;
+    /// small values keep memory low for long-lived tailers. A single record
+    /// larger than this is still read whole. Zero is treated as one byte.
+    pub read_ahead_bytes: usize,
+
+    /// Stop at `durable_position()` instead of the last written byte
+    /// (default: false).
+    ///
+    /// Records past the durable position have been written but not fsynced,
+    /// and a crash can still take them back; anything shipping records
+    /// elsewhere, such as replication, should not see them. The bound is
+    /// taken when the reader is opened. A [`WalReader`](crate::WalReader)
+    /// always reads durable records only.
+    pub durable_only: bool,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        Self {
+            read_ahead_bytes: READ_BUFFER_SIZE,
+            durable_only: false,
+        }
     }
 }
 
 /// Iterator for reading records from a segment.
+///
+/// Records are decoded out of an internal buffer that is refilled from the
+/// file only when it runs dry, so sequential reads cost one read per refill
+/// rather than per record. Records of any size are handled: the buffer
+/// grows until the whole record is in memory.
 pub struct SegmentReader {
-    file: Arc<Mutex<File>>,
+    file: Arc<dyn FsFile>,
+    /// Where the segment is stored, for error messages.
+    path: PathBuf,
     position: u64,
     segment_id: u64,
     /// Logical end of data (for pre-allocated segments that haven't been finalized).
     /// If None, reads until actual EOF.
     logical_end: Option<u64>,
+    /// Bytes read from the file starting at `position`, not yet decoded.
+    buffer: BytesMut,
+    read_ahead: usize,
+    /// Refill in flight, if any.
+    fill: Option<FillFuture>,
+    /// Records whose header fails this predicate are skipped.
+    filter: Option<RecordFilter>,
+    /// Keeps the segment from being purged while it is read.
+    pin: Option<SegmentPin>,
+    /// Buffered and in-flight bytes, charged to the memory budget.
+    memory: Option<MemoryCharge>,
 }
 
+/// Predicate on record headers used to filter what a reader returns.
+pub(crate) type RecordFilter = Arc<dyn Fn(&RecordHeader<'_>) -> bool + Send + Sync>;
+
+/// A pending read of the next chunk of a segment.
+type FillFuture = Pin<Box<dyn Future<Output = std::io::Result<Vec<u8>>> + Send>>;
+
 impl SegmentReader {
-    /// Reads the next record from the segment.
-    pub async fn next_record(&mut self) -> Result<Option<(Record, Position)>, SegmentError> {
-        const READ_BUFFER_SIZE: usize = 65536; // 64KB buffer for better performance
+    fn new(
+        file: Arc<dyn FsFile>,
+        path: PathBuf,
+        position: Position,
+        logical_end: Option<u64>,
+        config: ReaderConfig,
+    ) -> Self {
+        Self {
+            file,
+            path,
+            position: position.offset,
+            segment_id: position.segment_id,
+            logical_end,
+            buffer: BytesMut::new(),
+            read_ahead: config.read_ahead_bytes.max(1),
+            fill: None,
+            filter: None,
+            pin: None,
+            memory: None,
+        }
+    }
 
-        // Check if we've reached the logical end of data
-        if let Some(logical_end) = self.logical_end {
-            if self.position >= logical_end {
-                return Ok(None); // Reached logical EOF
-            }
+    /// Returns the position just past the last record read.
+    pub fn position(&self) -> Position {
+        Position {
+            segment_id: self.segment_id,
+            offset: self.position,
         }
+    }
 
-        let mut file = self.file.lock().await;
+    /// Skips records whose header does not satisfy `filter`.
+    pub(crate) fn set_filter(&mut self, filter: Option<RecordFilter>) {
+        self.filter = filter;
+    }
 
-        // Seek to the current position
-        file.seek(std::io::SeekFrom::Start(self.position)).await?;
+    /// Stops the reader at `end` even if the segment holds more data.
+    pub(crate) fn limit_to(&mut self, end: u64) {
+        self.logical_end = Some(self.logical_end.map_or(end, |e| e.min(end)));
+    }
 
-        // Read initial buffer
-        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
-        let n = file.read(&mut buffer).await?;
+    /// Reads the next record from the segment.
+    ///
+    /// Returns `None` at the end of the data, including when the segment ends
+    /// in a partially written record. Cancelling the returned future loses
+    /// nothing: a refill in flight is kept and finished by the next call.
+    pub async fn next_record(&mut self) -> Result<Option<(Record, Position)>, SegmentError> {
+        std::future::poll_fn(|cx| self.poll_next_record(cx)).await
+    }
 
-        if n == 0 {
-            return Ok(None); // EOF
-        }
+    /// Polls for the next record; shared by `next_record` and the `Stream` impl.
+    pub(crate) fn poll_next_record(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<(Record, Position)>, SegmentError>> {
+        loop {
+            if let Some(fill) = self.fill.as_mut() {
+                let chunk = ready!(fill.as_mut().poll(cx));
+                self.fill = None;
+                let read_from = self.position + self.buffer.len() as u64;
+                let chunk = chunk.map_err(SegmentError::io_at(
+                    IoOp::Read,
+                    self.segment_id,
+                    &self.path,
+                    read_from,
+                ))?;
+                self.buffer.extend_from_slice(&chunk);
+                if let Some(memory) = &mut self.memory {
+                    memory.set(self.buffer.len() as u64);
+                }
+                if chunk.is_empty() {
+                    // Incomplete at EOF - this is fine during recovery
+                    return Poll::Ready(Ok(None));
+                }
+            }
 
-        buffer.truncate(n);
+            // Check if we've reached the logical end of data
+            if let Some(logical_end) = self.logical_end {
+                if self.position >= logical_end {
+                    return Poll::Ready(Ok(None)); // Reached logical EOF
+                }
+            }
 
-        // Try to decode the record, reading more data if needed
-        match self.try_decode_with_retry(&mut buffer, &mut file, n, READ_BUFFER_SIZE).await? {
-            Some((record, size)) => {
-                let pos = Position {
-                    segment_id: self.segment_id,
-                    offset: self.position,
+            if let Some(filter) = &self.filter {
+                // Rejected records are checksummed but never fully decoded
+                let skip = match Record::peek_header(&self.buffer) {
+                    Ok((header, size)) if !filter(&header) => Some(size),
+                    _ => None,
                 };
-                self.position += size as u64;
-                Ok(Some((record, pos)))
+                if let Some(size) = skip {
+                    self.buffer.advance(size);
+                    self.position += size as u64;
+                    continue;
+                }
+            }
+
+            match Record::decode(&self.buffer) {
+                Ok((record, size)) => {
+                    let pos = self.position();
+                    self.buffer.advance(size);
+                    self.position += size as u64;
+                    return Poll::Ready(Ok(Some((record, pos))));
+                }
+                Err(crate::record::RecordError::Incomplete) => match self.start_fill() {
+                    Some(fill) => self.fill = Some(fill),
+                    None => return Poll::Ready(Ok(None)),
+                },
+                Err(e) => return Poll::Ready(Err(SegmentError::Record(e))),
             }
-            None => Ok(None),
         }
     }
 
-    /// Attempts to decode a record, reading more data if the initial buffer is incomplete.
-    async fn try_decode_with_retry(
-        &self,
-        buffer: &mut Vec<u8>,
-        file: &mut File,
-        bytes_read: usize,
-        buffer_size: usize,
-    ) -> Result<Option<(Record, usize)>, SegmentError> {
-        match Record::decode(buffer) {
-            Ok((record, size)) => Ok(Some((record, size))),
-            Err(crate::record::RecordError::Incomplete) if bytes_read == buffer_size => {
-                // Buffer was full but record is incomplete - read more data
-                self.read_more_and_decode(buffer, file, buffer_size).await
-            }
-            Err(crate::record::RecordError::Incomplete) => {
-                // Incomplete at EOF - this is fine during recovery
-                Ok(None)
+    /// Starts reading the next chunk of the segment, or returns `None` if
+    /// there is nothing left to read.
+    ///
+    /// The read owns its handle on the file, so it survives the caller's
+    /// future being dropped.
+    fn start_fill(&mut self) -> Option<FillFuture> {
+        let read_from = self.position + self.buffer.len() as u64;
+        let mut read_ahead = self.read_ahead;
+        if let Some(memory) = &self.memory {
+            // Read ahead less while the memory budget is spent
+            if read_ahead > SHRUNK_READ_AHEAD && !memory.budget().fits(read_ahead as u64) {
+                memory.budget().note_shrink();
+                read_ahead = SHRUNK_READ_AHEAD;
             }
-            Err(e) => Err(SegmentError::Record(e)),
         }
+        // Grow geometrically while a large record is still incomplete
+        let mut want = read_ahead.max(self.buffer.len()) as u64;
+        if let Some(logical_end) = self.logical_end {
+            want = want.min(logical_end.saturating_sub(read_from));
+        }
+        if want == 0 {
+            return None;
+        }
+        if let Some(memory) = &mut self.memory {
+            memory.set(self.buffer.len() as u64 + want);
+        }
+
+        let file = Arc::clone(&self.file);
+        Some(Box::pin(async move {
+            file.read_at(read_from, want as usize).await
+        }))
     }
+}
 
-    /// Reads additional data and attempts to decode again.
-    async fn read_more_and_decode(
-        &self,
-        buffer: &mut Vec<u8>,
-        file: &mut File,
-        buffer_size: usize,
-    ) -> Result<Option<(Record, usize)>, SegmentError> {
-        let mut more_data = vec![0u8; buffer_size];
-        let additional = file.read(&mut more_data).await?;
-        buffer.extend_from_slice(&more_data[..additional]);
+impl Stream for SegmentReader {
+    type Item = Result<(Record, Position), SegmentError>;
 
-        match Record::decode(buffer) {
-            Ok((record, size)) => Ok(Some((record, size))),
-            Err(e) => Err(SegmentError::Record(e)),
-        }
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_record(cx).map(Result::transpose)
     }
 }
 
 /// Generates the path for a segment file.
-fn segment_path(dir: &Path, id: u64) -> PathBuf {
+pub(crate) fn segment_path(dir: &Path, id: u64) -> PathBuf {
     dir.join(format!("{:06}.wal", id))
 }
 
-/// Finds the latest segment ID in a directory.
-async fn find_latest_segment_id(dir: &Path) -> Result<u64, SegmentError> {
-    let mut entries = tokio::fs::read_dir(dir).await?;
-    let mut max_id = 0u64;
+/// Lists the IDs of all segment files in a directory, in ascending order.
+pub(crate) async fn list_segment_ids(fs: &dyn Fs, dir: &Path) -> Result<Vec<u64>, SegmentError> {
+    let mut ids: Vec<u64> = fs
+        .read_dir(dir)
+        .await?
+        .iter()
+        .filter_map(|path| parse_segment_id_from_path(path))
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
 
-    while let Some(entry) = entries.next_entry().await? {
-        if let Some(id) = parse_segment_id_from_path(&entry.path()) {
-            max_id = max_id.max(id);
+/// Counts the segments in `dir` other than the active one, and their bytes.
+async fn sealed_usage(
+    store: &dyn SegmentStore,
+    dir: &Path,
+    active_id: u64,
+) -> Result<(u64, u64), SegmentError> {
+    let mut segments = 0;
+    let mut bytes = 0;
+    for id in store.list(dir).await? {
+        if id != active_id {
+            segments += 1;
+            bytes += store.len(dir, id).await?;
         }
     }
+    Ok((segments, bytes))
+}
+
+/// Hard-links `src` to `dst`, falling back to a durable copy across filesystems.
+/// Returns the bytes copied, which are 0 if the link succeeded.
+async fn link_or_copy(fs: &dyn Fs, src: &Path, dst: &Path) -> Result<u64, SegmentError> {
+    if fs.hard_link(src, dst).await.is_ok() {
+        return Ok(0);
+    }
+
+    let len = fs.len(src).await?;
+    fs::copy_prefix(fs, src, dst, len).await?;
+    Ok(len)
+}
+
+/// Links or copies sealed segment `id`, and its seal sidecar if present, from
+/// `src_dir` to where a migration stages them in `dst_dir`. Returns the bytes
+/// copied.
+async fn link_or_copy_sealed(
+    fs: &dyn Fs,
+    src_dir: &Path,
+    dst_dir: &Path,
+    id: u64,
+) -> Result<u64, SegmentError> {
+    let staged = moved::staged_path(&segment_path(dst_dir, id));
+    let mut copied = link_or_copy(fs, &segment_path(src_dir, id), &staged).await?;
+
+    let seal = seal::seal_path(src_dir, id);
+    if fs.exists(&seal).await? {
+        let staged = moved::staged_path(&seal::seal_path(dst_dir, id));
+        copied += link_or_copy(fs, &seal, &staged).await?;
+    }
+    Ok(copied)
+}
 
-    Ok(max_id)
+/// Key and value bytes of `records`, before compression.
+fn payload_len(records: &[Record]) -> u64 {
+    records
+        .iter()
+        .map(|record| (record.key.len() + record.value.len()) as u64)
+        .sum()
 }
 
 /// Parses a segment ID from a .wal file path.
 ///
 /// Returns None if the path is not a valid .wal file or cannot be parsed.
-fn parse_segment_id_from_path(path: &Path) -> Option<u64> {
+pub(crate) fn parse_segment_id_from_path(path: &Path) -> Option<u64> {
     // Check extension is "wal"
     if path.extension()?.to_str()? != "wal" {
         return None;
@@ -710,9 +2969,32 @@ fn parse_segment_id_from_path(path: &Path) -> Option<u64> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fs::OpenMode;
+    use crate::sim::{SimFault, SimFs};
     use nori_observe::NoopMeter;
     use tempfile::TempDir;
 
+    #[test]
+    fn test_position_display_round_trip() {
+        let position = Position {
+            segment_id: 12,
+            offset: 48739,
+        };
+        assert_eq!(position.to_string(), "000012:48739");
+        assert_eq!("000012:48739".parse::<Position>(), Ok(position));
+        assert_eq!("12:48739".parse::<Position>(), Ok(position));
+
+        let wide = Position {
+            segment_id: 12_345_678,
+            offset: 0,
+        };
+        assert_eq!(wide.to_string().parse::<Position>(), Ok(wide));
+
+        for bad in ["", "12", "12:", ":5", "12:5:1", "a:5", "-1:5"] {
+            assert!(bad.parse::<Position>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
     #[tokio::test]
     async fn test_segment_creation() {
         let temp_dir = TempDir::new().unwrap();
@@ -721,6 +3003,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Os, // Fast for tests
             preallocate: false, // Disable for faster tests
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -742,6 +3029,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Os,
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -775,6 +3067,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Os,
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -809,9 +3106,14 @@ mod tests {
         }
 
         assert_eq!(read_records.len(), 3);
-        assert_eq!(read_records[0], records[0]);
-        assert_eq!(read_records[1], records[1]);
-        assert_eq!(read_records[2], records[2]);
+        for (i, (read, original)) in read_records.iter().zip(&records).enumerate() {
+            // The manager stamps each record with an LSN and timestamp
+            let expected = original
+                .clone()
+                .with_lsn(i as u64 + 1)
+                .with_timestamp(read.timestamp.unwrap());
+            assert_eq!(read, &expected);
+        }
         assert!(read_records[2].tombstone);
     }
 
@@ -823,6 +3125,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Os,
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = Arc::new(
@@ -877,6 +3184,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Always,
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -912,6 +3224,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Batch(Duration::from_millis(10)),
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -957,6 +3274,11 @@ mod tests {
             dir: temp_dir.path().to_path_buf(),
             fsync_policy: FsyncPolicy::Os,
             preallocate: false,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
         };
 
         let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
@@ -985,4 +3307,532 @@ mod tests {
         }
         assert_eq!(count, 2);
     }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_unsynced_drop_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let meter = nori_observe::TestMeter::new();
+        let manager = SegmentManager::new(config, Arc::new(meter.clone()), 1)
+            .await
+            .unwrap();
+        assert_eq!(manager.check_unsynced_on_drop(false), 0);
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        manager.append(&record).await.unwrap();
+        manager.flush().await.unwrap();
+        let unsynced = manager.check_unsynced_on_drop(true);
+        assert!(unsynced > 0);
+        assert!(meter.events().iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                kind: WalKind::UnsyncedDrop { bytes },
+                ..
+            }) if *bytes == unsynced
+        )));
+
+        // The best-effort sync leaves nothing to report
+        assert_eq!(manager.check_unsynced_on_drop(false), 0);
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_verify_on_seal() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            verify_on_seal: true,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
+        };
+
+        let meter = nori_observe::TestMeter::new();
+        let manager = SegmentManager::new(config, Arc::new(meter.clone()), 1)
+            .await
+            .unwrap();
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        while manager.current_position().await.segment_id == 0 {
+            manager.append(&record).await.unwrap();
+        }
+
+        // Verification runs off the append path; wait for it to report
+        let mut verified = false;
+        for _ in 0..100 {
+            verified = meter.events().iter().any(|e| {
+                matches!(
+                    e,
+                    VizEvent::Wal(WalEvt {
+                        seg: 0,
+                        kind: WalKind::SegmentVerified,
+                        ..
+                    })
+                )
+            });
+            if verified {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(verified, "sealed segment 0 should have been verified");
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_verify_on_seal_reports_read_errors() {
+        let fs = Arc::new(SimFs::new());
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: PathBuf::from("/sim/wal"),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            verify_on_seal: true,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
+        };
+
+        let meter = nori_observe::TestMeter::new();
+        let manager = SegmentManager::new_with_fs(config, Arc::new(meter.clone()), 1, fs.clone())
+            .await
+            .unwrap();
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        manager.append(&record).await.unwrap();
+
+        // The segment is there, but reading it back fails
+        fs.fail_next(SimFault::Read, 1);
+        manager.spawn_seal_verification(PathBuf::from("/sim/wal"), 0, true, false);
+
+        let failed = |e: &VizEvent| {
+            matches!(
+                e,
+                VizEvent::Wal(WalEvt {
+                    seg: 0,
+                    kind: WalKind::SegmentVerifyFailed { .. },
+                    ..
+                })
+            )
+        };
+        for _ in 0..100 {
+            if meter.events().iter().any(failed) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(meter.events().iter().any(failed));
+        assert_eq!(meter.counter_total("wal_seal_verify_failures_total"), 1);
+        assert!(!meter.events().iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                kind: WalKind::SegmentVerified,
+                ..
+            })
+        )));
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_slow_fsync_and_purge_are_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            // Every fsync counts as slow
+            slow_fsync_threshold: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let meter = nori_observe::TestMeter::new();
+        let manager = SegmentManager::new(config, Arc::new(meter.clone()), 1)
+            .await
+            .unwrap();
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        while manager.current_position().await.segment_id == 0 {
+            manager.append(&record).await.unwrap();
+        }
+        manager.sync().await.unwrap();
+        let segment_size = std::fs::metadata(segment_path(temp_dir.path(), 0))
+            .unwrap()
+            .len();
+
+        let deleted = manager
+            .delete_segments_before(Position {
+                segment_id: 1,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(deleted, 1);
+
+        let events = meter.events();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                kind: WalKind::SlowFsync {
+                    threshold_ms: 0,
+                    ..
+                },
+                ..
+            })
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            VizEvent::Wal(WalEvt {
+                seg: 0,
+                kind: WalKind::SegmentPurged { seg: 0, bytes },
+                ..
+            }) if *bytes == segment_size
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_gauges_track_size_and_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let meter = nori_observe::TestMeter::new();
+        let manager = SegmentManager::new(config, Arc::new(meter.clone()), 1)
+            .await
+            .unwrap();
+        assert_eq!(meter.gauge_value("wal_segments"), Some(1));
+        assert_eq!(meter.gauge_value("wal_size_bytes"), Some(0));
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        while manager.current_position().await.segment_id == 0 {
+            manager.append(&record).await.unwrap();
+        }
+        let sealed = std::fs::metadata(segment_path(temp_dir.path(), 0))
+            .unwrap()
+            .len();
+        let active = manager.current_position().await.offset;
+        assert_eq!(meter.gauge_value("wal_segments"), Some(2));
+        assert_eq!(
+            meter.gauge_value("wal_size_bytes"),
+            Some((sealed + active) as i64)
+        );
+        assert_eq!(
+            meter.gauge_value("wal_active_segment_fill_percent"),
+            Some(active as i64)
+        );
+
+        manager.sync().await.unwrap();
+        assert_eq!(meter.gauge_value("wal_seconds_since_fsync"), Some(0));
+
+        manager
+            .delete_segments_before(Position {
+                segment_id: 1,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(meter.gauge_value("wal_segments"), Some(1));
+        assert_eq!(meter.gauge_value("wal_size_bytes"), Some(active as i64));
+    }
+
+    #[tokio::test]
+    async fn test_truncate_from_across_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 200,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: true,
+            verify_on_seal: false,
+            seal_segments: false,
+            max_record_size: None,
+            max_segment_age: None,
+            slow_fsync_threshold: None,
+        };
+
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+
+        let mut positions = Vec::new();
+        for i in 0..20 {
+            let record = Record::put(format!("key{}", i), b"value".as_slice());
+            positions.push(manager.append(&record).await.unwrap());
+        }
+        assert!(manager.current_position().await.segment_id >= 2);
+
+        // Cut at the fourth record, which lives in the first segment
+        let cut = positions[3];
+        assert_eq!(cut.segment_id, 0);
+        let discarded = manager.truncate_from(cut).await.unwrap();
+        assert!(discarded > 0);
+        assert_eq!(manager.current_position().await, cut);
+        assert_eq!(manager.durable_position().await, cut);
+        assert_eq!(manager.next_lsn(), 4);
+        assert_eq!(
+            list_segment_ids(&LocalFs, temp_dir.path()).await.unwrap(),
+            vec![0]
+        );
+
+        // Truncating past the end is a no-op
+        assert_eq!(manager.truncate_from(positions[10]).await.unwrap(), 0);
+
+        // A position inside a record is rejected
+        let inside = Position {
+            segment_id: 0,
+            offset: positions[1].offset + 1,
+        };
+        assert!(manager.truncate_from(inside).await.is_err());
+
+        let pos = manager
+            .append(&Record::put(b"after".as_slice(), b"truncate".as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(pos, cut);
+
+        let mut reader = manager
+            .read_from(Position {
+                segment_id: 0,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        let mut lsns = Vec::new();
+        while let Some((record, _)) = reader.next_record().await.unwrap() {
+            lsns.push(record.lsn.unwrap());
+        }
+        assert_eq!(lsns, vec![1, 2, 3, 4]);
+        assert_eq!(manager.position_of_lsn(4).await.unwrap(), Some(cut));
+        assert_eq!(manager.position_of_lsn(5).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_from_fails_on_unreadable_later_segment() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 200,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        let mut last = manager.append(&record).await.unwrap();
+        while last.segment_id < 2 {
+            last = manager.append(&record).await.unwrap();
+        }
+        let next_lsn = manager.next_lsn();
+
+        // Corrupt the first record of segment 1, then cut at the end of
+        // segment 0 so the first discarded record has to come from it
+        let path = segment_path(temp_dir.path(), 1);
+        let mut bytes = std::fs::read(&path).unwrap();
+        for byte in &mut bytes[12..16] {
+            *byte ^= 0xff;
+        }
+        std::fs::write(&path, bytes).unwrap();
+        let end_of_first = Position {
+            segment_id: 0,
+            offset: std::fs::metadata(segment_path(temp_dir.path(), 0))
+                .unwrap()
+                .len(),
+        };
+
+        assert!(manager.truncate_from(end_of_first).await.is_err());
+        assert_eq!(manager.next_lsn(), next_lsn);
+        assert_eq!(manager.current_position().await.segment_id, 2);
+    }
+
+    #[tokio::test]
+    async fn test_purge_skips_pinned_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        let mut last = manager.append(&record).await.unwrap();
+        while last.segment_id < 5 {
+            last = manager.append(&record).await.unwrap();
+        }
+        let segment = |segment_id| Position {
+            segment_id,
+            offset: 0,
+        };
+
+        // A reader on segment 2 holds it and everything after it in place
+        let mut reader = manager.read_from(segment(2)).await.unwrap();
+        assert_eq!(manager.delete_segments_before(segment(4)).await.unwrap(), 2);
+        let mut read = 0;
+        while reader.next_record().await.unwrap().is_some() {
+            read += 1;
+        }
+        assert!(read > 0);
+        assert!(segment_path(temp_dir.path(), 3).exists());
+
+        // Once the reader is gone the rest of the purge goes through
+        drop(reader);
+        assert_eq!(manager.delete_segments_before(segment(4)).await.unwrap(), 2);
+        assert!(matches!(
+            manager.read_from(segment(3)).await,
+            Err(SegmentError::Purged(3))
+        ));
+        assert!(matches!(
+            manager.read_from(segment(9)).await,
+            Err(SegmentError::NotFound(9))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reader_durable_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            let record = Record::put(format!("synced{}", i), b"v".as_slice());
+            manager.append(&record).await.unwrap();
+        }
+        manager.sync().await.unwrap();
+        for i in 0..2 {
+            let record = Record::put(format!("unsynced{}", i), b"v".as_slice());
+            manager.append(&record).await.unwrap();
+        }
+
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let count = |durable_only| {
+            let manager = &manager;
+            async move {
+                let config = ReaderConfig {
+                    durable_only,
+                    ..Default::default()
+                };
+                let mut reader = manager.read_from_with(start, config).await.unwrap();
+                let mut count = 0;
+                while reader.next_record().await.unwrap().is_some() {
+                    count += 1;
+                }
+                count
+            }
+        };
+        assert_eq!(count(false).await, 5);
+        assert_eq!(count(true).await, 3);
+
+        manager.sync().await.unwrap();
+        assert_eq!(count(true).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_reader_handles_large_records_and_torn_tail() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = SegmentConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let manager = SegmentManager::new(config, Arc::new(NoopMeter), 1)
+            .await
+            .unwrap();
+
+        // Sizes straddle the read buffer in both directions
+        let sizes = [
+            10,
+            3 * READ_BUFFER_SIZE + 17,
+            5,
+            READ_BUFFER_SIZE,
+            1 << 20,
+            1,
+        ];
+        for (i, size) in sizes.iter().enumerate() {
+            let record = Record::put(format!("key{}", i), vec![i as u8; *size]);
+            manager.append(&record).await.unwrap();
+        }
+        manager.finalize_current().await.unwrap();
+
+        // Leave half of a record at the end of the segment
+        let torn = Record::put(b"torn".as_slice(), vec![9u8; 1000]).encode();
+        let path = segment_path(temp_dir.path(), 0);
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&torn[..torn.len() / 2]);
+        std::fs::write(&path, &data).unwrap();
+
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        // Whatever the read-ahead, every record comes back whole
+        for read_ahead_bytes in [0, 7, READ_BUFFER_SIZE, 4 << 20] {
+            let mut reader = SegmentReader::new(
+                LocalFs.open(&path, OpenMode::Read).await.unwrap(),
+                path.clone(),
+                start,
+                None,
+                ReaderConfig {
+                    read_ahead_bytes,
+                    ..Default::default()
+                },
+            );
+            for (i, size) in sizes.iter().enumerate() {
+                let (record, _) = reader.next_record().await.unwrap().unwrap();
+                assert_eq!(record.key, format!("key{}", i));
+                assert_eq!(record.value.len(), *size);
+            }
+            assert!(reader.next_record().await.unwrap().is_none());
+            assert_eq!(
+                reader.position().offset,
+                (data.len() - torn.len() / 2) as u64
+            );
+        }
+
+        // The same records come back through the Stream impl
+        use futures::StreamExt;
+        let reader = SegmentReader::new(
+            LocalFs.open(&path, OpenMode::Read).await.unwrap(),
+            path.clone(),
+            start,
+            None,
+            ReaderConfig::default(),
+        );
+        let lengths: Vec<_> = reader
+            .map(|item| item.unwrap().0.value.len())
+            .collect()
+            .await;
+        assert_eq!(lengths, sizes);
+    }
 }
@@ -0,0 +1,92 @@
+//! Read-your-writes across replicas with session tokens.
+//!
+//! Replication is asynchronous, so a client that writes through the leader
+//! and then reads from a follower may not see its own write. A
+//! [`SessionToken`] closes that gap without sending every read to the
+//! leader: after writing, the client takes a token from the leader with
+//! [`Wal::session_token`], which holds the LSN of the leader's last durable
+//! record, and passes it along with its next read. The follower serving the
+//! read first waits with [`WalFollower::wait_for`] until it has made that LSN
+//! durable itself, so its readers see everything the client wrote.
+//!
+//! ```no_run
+//! # use nori_wal::{Record, SessionToken, Wal, WalFollower};
+//! # async fn example(leader: Wal, follower: WalFollower) -> Result<(), nori_wal::SegmentError> {
+//! leader.append(&Record::put("cart", "3 items")).await?;
+//! leader.sync().await?;
+//! let token = leader.session_token().await?.to_bytes();
+//!
+//! // On the follower, with the token the client sent along
+//! let token = SessionToken::from_bytes(&token).expect("valid token");
+//! tokio::time::timeout(std::time::Duration::from_secs(1), follower.wait_for(&token))
+//!     .await
+//!     .expect("follower caught up");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Tokens only order reads after writes made durable on the leader before the
+//! token was taken, so `sync()` first unless the fsync policy already has.
+//! They compare LSNs, so they only mean something to followers of the leader
+//! that issued them.
+//!
+//! [`Wal::session_token`]: crate::Wal::session_token
+//! [`WalFollower::wait_for`]: crate::WalFollower::wait_for
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+const TOKEN_VERSION: u8 = 1;
+const TOKEN_LEN: usize = 1 + 8;
+
+/// A point in the leader's log that a read must come after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionToken {
+    lsn: u64,
+}
+
+impl SessionToken {
+    /// A token for the record with `lsn`, or for an empty log if 0.
+    pub fn new(lsn: u64) -> Self {
+        Self { lsn }
+    }
+
+    /// Returns the LSN a follower must have made durable to serve the read.
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// Encodes the token into a compact, versioned byte string.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(TOKEN_LEN);
+        buf.put_u8(TOKEN_VERSION);
+        buf.put_u64_le(self.lsn);
+        buf.freeze()
+    }
+
+    /// Decodes a token produced by [`SessionToken::to_bytes`], or returns
+    /// `None` if `data` is not one.
+    pub fn from_bytes(mut data: &[u8]) -> Option<Self> {
+        if data.len() != TOKEN_LEN || data.get_u8() != TOKEN_VERSION {
+            return None;
+        }
+        Some(Self {
+            lsn: data.get_u64_le(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = SessionToken::new(42);
+        assert_eq!(SessionToken::from_bytes(&token.to_bytes()), Some(token));
+        assert_eq!(SessionToken::from_bytes(b""), None);
+        let mut other = token.to_bytes().to_vec();
+        other[0] = 9;
+        assert_eq!(SessionToken::from_bytes(&other), None);
+    }
+}
@@ -0,0 +1,745 @@
+//! In-memory filesystem for deterministic crash testing.
+//!
+//! [`SimFs`] is an [`Fs`] that keeps files in memory and remembers, for
+//! each one, what it held at its last fsync. [`SimFs::crash`] rolls every
+//! file back to what a power failure would leave, as chosen by a
+//! [`CrashMode`], and [`SimFs::fail_next`] makes upcoming operations fail
+//! or tear. A real [`Wal`](crate::Wal) runs on it unchanged, so appends,
+//! batching windows, rotation and recovery can be exercised without a disk:
+//!
+//! ```
+//! use nori_wal::sim::{CrashMode, SimFs};
+//! use nori_wal::{FsyncPolicy, Record, Wal};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! # let mut runtime = tokio::runtime::Builder::new_current_thread();
+//! # runtime.enable_all().start_paused(true).build().unwrap().block_on(async {
+//! let fs = Arc::new(SimFs::new());
+//! let builder = Wal::builder()
+//!     .fs(fs.clone())
+//!     .dir("/wal")
+//!     .fsync(FsyncPolicy::Batch(Duration::from_secs(1)));
+//!
+//! let (wal, _) = builder.clone().open().await.unwrap();
+//! // The first append in a window is fsynced, the second is not
+//! wal.append(&Record::put(b"a".as_slice(), b"1".as_slice())).await.unwrap();
+//! wal.append(&Record::put(b"b".as_slice(), b"2".as_slice())).await.unwrap();
+//! fs.crash(CrashMode::LoseUnsynced);
+//! drop(wal);
+//!
+//! let (_wal, info) = builder.open().await.unwrap();
+//! assert_eq!(info.valid_records, 1);
+//! # });
+//! ```
+//!
+//! Crash first, then drop the WAL: handles opened before a crash fail from
+//! then on, as a process that died would not run its destructors.
+//!
+//! Operations complete as soon as they are polled, so on a current-thread
+//! tokio runtime with the clock paused (`#[tokio::test(start_paused = true)]`)
+//! a run is repeatable: the WAL's timers (batch fsync windows, age-based
+//! rotation, scrub intervals) only fire as the test advances time. Record
+//! timestamps and TTLs read wall-clock time, which tokio does not pause;
+//! plug in a [`MockClock`](crate::clock::MockClock) to control them too.
+//!
+//! Directory changes (creating, removing and renaming files) are durable as
+//! soon as they are made; only file contents wait for an fsync. The
+//! directory lock is not simulated.
+
+use crate::fs::{Fs, FsFile, FsFuture, OpenMode};
+use crate::lock::DirLock;
+use crate::segment::SegmentError;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// What [`SimFs::crash`] keeps of data written since the last fsync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashMode {
+    /// None of it; files hold what they did at their last fsync.
+    LoseUnsynced,
+    /// All of it, as if the OS wrote everything back just before the crash.
+    KeepUnsynced,
+    /// Each file keeps its current contents up to a cut drawn from `seed`,
+    /// and its last fsynced contents past it, as if writeback had got that
+    /// far in file order. The cut can fall in the middle of a record.
+    Torn { seed: u64 },
+}
+
+/// Operations that [`SimFs::fail_next`] can make fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SimFault {
+    /// Opening a file fails.
+    Open,
+    /// A write fails before anything is written.
+    Write,
+    /// A write stores the first half of its bytes, then fails.
+    TornWrite,
+    /// An fsync fails; nothing more becomes durable.
+    Sync,
+    /// A read fails.
+    Read,
+}
+
+/// A file's bytes; everything from `bytes.len()` up to `len` reads as
+/// zeros, so pre-allocated segments cost nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Contents {
+    bytes: Vec<u8>,
+    len: u64,
+}
+
+impl Contents {
+    fn read(&self, offset: u64, len: usize) -> Vec<u8> {
+        let end = self.len.min(offset.saturating_add(len as u64));
+        if offset >= end {
+            return Vec::new();
+        }
+        let mut out = vec![0; (end - offset) as usize];
+        let stored = self.bytes.len() as u64;
+        if offset < stored {
+            let n = (stored.min(end) - offset) as usize;
+            out[..n].copy_from_slice(&self.bytes[offset as usize..offset as usize + n]);
+        }
+        out
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let end = offset as usize + data.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, 0);
+        }
+        self.bytes[offset as usize..end].copy_from_slice(data);
+        self.len = self.len.max(end as u64);
+    }
+
+    fn set_len(&mut self, len: u64) {
+        self.bytes.truncate(len as usize);
+        self.len = len;
+    }
+
+    /// `self` up to `cut`, then `synced` past it.
+    fn torn(&self, synced: &Contents, cut: u64) -> Contents {
+        let mut out = Contents {
+            bytes: self.read(0, cut.min(self.len) as usize),
+            len: cut.min(self.len),
+        };
+        if cut < synced.len {
+            let rest = synced.read(cut, (synced.len - cut) as usize);
+            out.bytes.resize(cut as usize, 0);
+            out.bytes.extend_from_slice(&rest);
+            out.len = synced.len;
+        }
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inode {
+    current: Contents,
+    /// Contents as of the last fsync.
+    synced: Contents,
+}
+
+type InodeRef = Arc<Mutex<Inode>>;
+
+#[derive(Default)]
+struct State {
+    dirs: BTreeSet<PathBuf>,
+    /// Hard links share an inode.
+    files: BTreeMap<PathBuf, InodeRef>,
+    /// Bumped by every crash; handles from an earlier generation are dead.
+    generation: u64,
+    faults: HashMap<SimFault, usize>,
+    syncs: u64,
+}
+
+impl State {
+    /// Consumes one pending failure of `fault`, if any.
+    fn take_fault(&mut self, fault: SimFault) -> bool {
+        match self.faults.get_mut(&fault) {
+            Some(remaining) if *remaining > 0 => {
+                *remaining -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check(&mut self, fault: SimFault) -> io::Result<()> {
+        if self.take_fault(fault) {
+            return Err(injected(fault));
+        }
+        Ok(())
+    }
+
+    fn has_dir(&self, dir: &Path) -> bool {
+        dir.as_os_str().is_empty() || self.dirs.contains(dir)
+    }
+
+    fn check_parent(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.has_dir(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    fn file(&self, path: &Path) -> io::Result<InodeRef> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+}
+
+fn injected(fault: SimFault) -> io::Error {
+    io::Error::other(format!("injected {:?} failure", fault))
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+/// In-memory filesystem with simulated crashes. See the [module docs](self).
+#[derive(Clone, Default)]
+pub struct SimFs {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimFs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Simulates a power failure: every file is rolled back according to
+    /// `mode`, and every open handle fails from now on.
+    pub fn crash(&self, mode: CrashMode) {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        let mut seed = match mode {
+            CrashMode::Torn { seed } => seed,
+            _ => 0,
+        };
+        let mut seen = Vec::new();
+        for inode in state.files.values() {
+            // Once per inode, however many names it has
+            if seen.iter().any(|other| Arc::ptr_eq(other, inode)) {
+                continue;
+            }
+            seen.push(Arc::clone(inode));
+
+            let mut inode = inode.lock().unwrap();
+            if inode.current == inode.synced {
+                continue;
+            }
+            let survived = match mode {
+                CrashMode::LoseUnsynced => inode.synced.clone(),
+                CrashMode::KeepUnsynced => inode.current.clone(),
+                CrashMode::Torn { .. } => {
+                    let max = inode.current.len.max(inode.synced.len);
+                    let cut = next_random(&mut seed) % (max + 1);
+                    inode.current.torn(&inode.synced, cut)
+                }
+            };
+            inode.synced = survived.clone();
+            inode.current = survived;
+        }
+    }
+
+    /// Makes the next `count` operations of kind `fault` fail with an
+    /// injected I/O error, on any file.
+    pub fn fail_next(&self, fault: SimFault, count: usize) {
+        *self.state.lock().unwrap().faults.entry(fault).or_default() += count;
+    }
+
+    /// Returns the number of fsyncs of files made so far.
+    pub fn syncs(&self) -> u64 {
+        self.state.lock().unwrap().syncs
+    }
+
+    /// Returns the current contents of the file at `path`, unsynced writes
+    /// included.
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        let inode = self.state.lock().unwrap().files.get(path.as_ref())?.clone();
+        let inode = inode.lock().unwrap();
+        Some(inode.current.read(0, inode.current.len as usize))
+    }
+
+    /// Replaces the file at `path` with `data`, durably, creating it and its
+    /// directory if needed. For setting up damaged logs.
+    pub fn write(&self, path: impl AsRef<Path>, data: &[u8]) {
+        let path = path.as_ref();
+        let mut state = self.state.lock().unwrap();
+        if let Some(parent) = path.parent() {
+            create_dirs(&mut state, parent);
+        }
+        let mut contents = Contents::default();
+        contents.write(0, data);
+        let inode = Inode {
+            current: contents.clone(),
+            synced: contents,
+        };
+        state
+            .files
+            .insert(path.to_path_buf(), Arc::new(Mutex::new(inode)));
+    }
+
+    /// Returns the paths of every file, in order.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.state.lock().unwrap().files.keys().cloned().collect()
+    }
+}
+
+/// xorshift64*, so torn crashes repeat for a seed.
+fn next_random(state: &mut u64) -> u64 {
+    if *state == 0 {
+        *state = 0x9E37_79B9_7F4A_7C15;
+    }
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+fn create_dirs(state: &mut State, dir: &Path) {
+    for ancestor in dir.ancestors() {
+        if !ancestor.as_os_str().is_empty() {
+            state.dirs.insert(ancestor.to_path_buf());
+        }
+    }
+}
+
+impl Fs for SimFs {
+    fn open<'a>(&'a self, path: &'a Path, mode: OpenMode) -> FsFuture<'a, Arc<dyn FsFile>> {
+        let mut state = self.state.lock().unwrap();
+        let result = state.check(SimFault::Open).and_then(|()| {
+            let existing = state.files.get(path).cloned();
+            let inode = match (existing, mode) {
+                (Some(_), OpenMode::CreateNew) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} already exists", path.display()),
+                    ))
+                }
+                (Some(inode), OpenMode::Truncate) => {
+                    inode.lock().unwrap().current.set_len(0);
+                    inode
+                }
+                (Some(inode), _) => inode,
+                (None, OpenMode::Read | OpenMode::Write) => return Err(not_found(path)),
+                (None, _) => {
+                    state.check_parent(path)?;
+                    let inode = InodeRef::default();
+                    state.files.insert(path.to_path_buf(), Arc::clone(&inode));
+                    inode
+                }
+            };
+            Ok(Arc::new(SimFile {
+                state: Arc::clone(&self.state),
+                inode,
+                generation: state.generation,
+                writable: mode != OpenMode::Read,
+            }) as Arc<dyn FsFile>)
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn create_dir_all<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()> {
+        create_dirs(&mut self.state.lock().unwrap(), dir);
+        Box::pin(std::future::ready(Ok(())))
+    }
+
+    fn read_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<PathBuf>> {
+        let state = self.state.lock().unwrap();
+        let result = if state.has_dir(dir) {
+            let files = state.files.keys();
+            let dirs = state.dirs.iter();
+            Ok(files
+                .chain(dirs)
+                .filter(|path| path.parent() == Some(dir))
+                .cloned()
+                .collect())
+        } else {
+            Err(not_found(dir))
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn len<'a>(&'a self, path: &'a Path) -> FsFuture<'a, u64> {
+        let result = self
+            .state
+            .lock()
+            .unwrap()
+            .file(path)
+            .map(|inode| inode.lock().unwrap().current.len);
+        Box::pin(std::future::ready(result))
+    }
+
+    fn exists<'a>(&'a self, path: &'a Path) -> FsFuture<'a, bool> {
+        let state = self.state.lock().unwrap();
+        let exists = state.files.contains_key(path) || state.dirs.contains(path);
+        Box::pin(std::future::ready(Ok(exists)))
+    }
+
+    fn remove_file<'a>(&'a self, path: &'a Path) -> FsFuture<'a, ()> {
+        let result = match self.state.lock().unwrap().files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> FsFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        let result = state.check_parent(to).and_then(|()| {
+            let inode = state.files.remove(from).ok_or_else(|| not_found(from))?;
+            state.files.insert(to.to_path_buf(), inode);
+            Ok(())
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn hard_link<'a>(&'a self, src: &'a Path, dst: &'a Path) -> FsFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        let result = state.check_parent(dst).and_then(|()| {
+            let inode = state.file(src)?;
+            if state.files.contains_key(dst) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", dst.display()),
+                ));
+            }
+            state.files.insert(dst.to_path_buf(), inode);
+            Ok(())
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn sync_dir<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, ()> {
+        let state = self.state.lock().unwrap();
+        let result = if state.has_dir(dir) {
+            Ok(())
+        } else {
+            Err(not_found(dir))
+        };
+        Box::pin(std::future::ready(result))
+    }
+
+    fn lock_dir(&self, _dir: &Path) -> Result<Option<DirLock>, SegmentError> {
+        Ok(None)
+    }
+}
+
+/// An open [`SimFs`] file.
+struct SimFile {
+    state: Arc<Mutex<State>>,
+    inode: InodeRef,
+    /// Generation of the filesystem when opened.
+    generation: u64,
+    writable: bool,
+}
+
+impl SimFile {
+    /// Runs `op` on the inode unless the file predates a crash or `fault`
+    /// is injected.
+    fn with<T>(
+        &self,
+        fault: SimFault,
+        op: impl FnOnce(&mut Inode, &mut State) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != self.generation {
+            return Err(io::Error::other("file was opened before a simulated crash"));
+        }
+        state.check(fault)?;
+        op(&mut self.inode.lock().unwrap(), &mut state)
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if !self.writable {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file was opened read-only",
+            ));
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.with(SimFault::Sync, |inode, state| {
+            state.syncs += 1;
+            inode.synced = inode.current.clone();
+            Ok(())
+        })
+    }
+}
+
+impl FsFile for SimFile {
+    fn read_at(&self, offset: u64, len: usize) -> FsFuture<'_, Vec<u8>> {
+        let result = self.with(SimFault::Read, |inode, _| {
+            Ok(inode.current.read(offset, len))
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn write_all_at<'a>(&'a self, offset: u64, data: &'a [u8]) -> FsFuture<'a, ()> {
+        let result = self.check_writable().and_then(|()| {
+            self.with(SimFault::Write, |inode, state| {
+                if state.take_fault(SimFault::TornWrite) {
+                    inode.current.write(offset, &data[..data.len() / 2]);
+                    return Err(injected(SimFault::TornWrite));
+                }
+                inode.current.write(offset, data);
+                Ok(())
+            })
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn len(&self) -> FsFuture<'_, u64> {
+        let result = self.with(SimFault::Read, |inode, _| Ok(inode.current.len));
+        Box::pin(std::future::ready(result))
+    }
+
+    fn set_len(&self, len: u64) -> FsFuture<'_, ()> {
+        let result = self.check_writable().and_then(|()| {
+            self.with(SimFault::Write, |inode, _| {
+                inode.current.set_len(len);
+                Ok(())
+            })
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn allocate(&self, len: u64) -> FsFuture<'_, ()> {
+        let result = self.check_writable().and_then(|()| {
+            self.with(SimFault::Write, |inode, _| {
+                inode.current.len = inode.current.len.max(len);
+                Ok(())
+            })
+        });
+        Box::pin(std::future::ready(result))
+    }
+
+    fn sync_data(&self) -> FsFuture<'_, ()> {
+        Box::pin(std::future::ready(self.sync()))
+    }
+
+    fn sync_all(&self) -> FsFuture<'_, ()> {
+        Box::pin(std::future::ready(self.sync()))
+    }
+
+    fn sync_data_blocking(&self) -> io::Result<()> {
+        self.sync()
+    }
+
+    fn truncate_blocking(&self, len: u64) -> io::Result<()> {
+        let longer = self.with(SimFault::Write, |inode, _| {
+            let longer = inode.current.len > len;
+            if longer {
+                inode.current.set_len(len);
+            }
+            Ok(longer)
+        })?;
+        if longer {
+            self.sync()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::WalBuilder;
+    use crate::error::IoOp;
+    use crate::record::{Durability, Record};
+    use crate::segment::{FsyncPolicy, SegmentError};
+    use crate::wal::Wal;
+    use std::time::Duration;
+
+    fn builder(fs: &Arc<SimFs>, policy: FsyncPolicy) -> WalBuilder {
+        Wal::builder()
+            .fs(fs.clone())
+            .dir("/wal")
+            .fsync(policy)
+            .segment_size(1024 * 1024)
+            .recovery_reports_kept(0)
+    }
+
+    fn record(i: u64) -> Record {
+        Record::put(format!("key{}", i).into_bytes(), vec![i as u8; 100])
+    }
+
+    async fn recovered_keys(builder: WalBuilder) -> Vec<bytes::Bytes> {
+        let mut keys = Vec::new();
+        builder
+            .open_with_replay(|record, _| keys.push(record.key))
+            .await
+            .unwrap();
+        keys
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crash_loses_appends_inside_the_batch_window() {
+        let fs = Arc::new(SimFs::new());
+        let window = Duration::from_millis(10);
+        let builder = builder(&fs, FsyncPolicy::Batch(window));
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        wal.append(&record(0)).await.unwrap();
+        wal.append(&record(1)).await.unwrap();
+        tokio::time::advance(window).await;
+        // Fsyncs everything so far
+        wal.append(&record(2)).await.unwrap();
+        wal.append(&record(3)).await.unwrap();
+        fs.crash(CrashMode::LoseUnsynced);
+        drop(wal);
+
+        let keys = recovered_keys(builder).await;
+        assert_eq!(keys, vec!["key0", "key1", "key2"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_critical_records_survive_a_crash_inside_the_batch_window() {
+        let fs = Arc::new(SimFs::new());
+        let builder = builder(&fs, FsyncPolicy::Batch(Duration::from_secs(1)));
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        wal.append(&record(0)).await.unwrap();
+        wal.append(&record(1)).await.unwrap();
+        // Fsyncs the pending best-effort record along with itself
+        let critical = record(2).with_durability(Durability::Critical);
+        wal.append(&critical).await.unwrap();
+        wal.append(&record(3)).await.unwrap();
+        fs.crash(CrashMode::LoseUnsynced);
+        drop(wal);
+
+        let keys = recovered_keys(builder).await;
+        assert_eq!(keys, vec!["key0", "key1", "key2"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crash_keeps_synced_rotated_segments() {
+        let fs = Arc::new(SimFs::new());
+        let builder = builder(&fs, FsyncPolicy::Os).preallocate(true);
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let value = vec![7u8; 200 * 1024];
+        for i in 0..12u64 {
+            let record = Record::put(format!("key{}", i).into_bytes(), value.clone());
+            wal.append(&record).await.unwrap();
+        }
+        wal.sync().await.unwrap();
+        wal.append(&record(12)).await.unwrap();
+        let segments = fs
+            .files()
+            .iter()
+            .filter(|p| p.extension().is_some_and(|e| e == "wal"))
+            .count();
+        assert!(
+            segments >= 3,
+            "expected rotation, got {} segments",
+            segments
+        );
+        fs.crash(CrashMode::LoseUnsynced);
+        drop(wal);
+
+        let (wal, info) = builder.open().await.unwrap();
+        assert_eq!(info.valid_records, 12);
+        let position = wal.append(&record(13)).await.unwrap();
+        assert_eq!(position.segment_id as usize, segments - 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_torn_crashes_recover_a_prefix() {
+        for seed in 1..=50 {
+            let fs = Arc::new(SimFs::new());
+            let builder = builder(&fs, FsyncPolicy::Os);
+
+            let (wal, _) = builder.clone().open().await.unwrap();
+            for i in 0..5 {
+                wal.append(&record(i)).await.unwrap();
+            }
+            wal.sync().await.unwrap();
+            for i in 5..20 {
+                wal.append(&record(i)).await.unwrap();
+            }
+            fs.crash(CrashMode::Torn { seed });
+            drop(wal);
+
+            let keys = recovered_keys(builder).await;
+            assert!(keys.len() >= 5, "seed {} lost synced records", seed);
+            for (i, key) in keys.iter().enumerate() {
+                assert_eq!(key, format!("key{}", i).as_bytes(), "seed {}", seed);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_injected_faults_surface_as_errors() {
+        let fs = Arc::new(SimFs::new());
+        let builder = builder(&fs, FsyncPolicy::Always);
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let first = wal.append(&record(0)).await.unwrap();
+        let second = wal.current_position().await;
+
+        // Errors say which operation failed, on which segment and where
+        fs.fail_next(SimFault::Sync, 1);
+        let err = wal.append(&record(1)).await.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                SegmentError::SegmentIo { op: IoOp::Sync, segment_id: 0, path, offset, .. }
+                    if path == Path::new("/wal/000000.wal") && *offset == second.offset
+            ),
+            "{}",
+            err
+        );
+
+        fs.fail_next(SimFault::Read, 1);
+        let mut reader = wal.read_from(first).await.unwrap();
+        let err = reader.next_record().await.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                SegmentError::SegmentIo { op: IoOp::Read, offset, .. } if *offset == first.offset
+            ),
+            "{}",
+            err
+        );
+        drop(reader);
+
+        fs.fail_next(SimFault::TornWrite, 1);
+        let err = wal.append(&record(2)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SegmentError::SegmentIo {
+                op: IoOp::Append,
+                ..
+            }
+        ));
+        fs.crash(CrashMode::KeepUnsynced);
+        drop(wal);
+
+        // The half-written record is cut off by recovery
+        let keys = recovered_keys(builder).await;
+        assert_eq!(keys, vec!["key0", "key1"]);
+    }
+
+    #[tokio::test]
+    async fn test_handles_fail_after_a_crash() {
+        let fs = SimFs::new();
+        fs.create_dir_all(Path::new("/d")).await.unwrap();
+        let file = fs.open(Path::new("/d/f"), OpenMode::Create).await.unwrap();
+        file.write_all_at(0, b"hello").await.unwrap();
+        fs.crash(CrashMode::LoseUnsynced);
+
+        assert!(file.write_all_at(0, b"x").await.is_err());
+        assert_eq!(fs.read("/d/f"), Some(Vec::new()));
+        assert!(fs.open(Path::new("/e/f"), OpenMode::Create).await.is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Latency SLOs on appends and fsyncs, with alerts when they burn.
+//!
+//! A [`LatencySlo`] says what share of operations should finish within a
+//! threshold, e.g. 99% of fsyncs within 10ms. The other 1% is the SLO's error
+//! budget. The WAL counts the operations slower than the threshold over a
+//! sliding window, and divides their share by the budget to get the burn
+//! rate: at 1 the budget is being used up exactly as fast as the SLO allows,
+//! at 10 ten times as fast. A disk that is starting to fail shows up here
+//! well before callers time out.
+//!
+//! SLOs are set with [`WalConfig::append_slo`] and [`WalConfig::fsync_slo`].
+//! Once the window holds enough operations and the burn rate reaches
+//! [`LatencySlo::max_burn_rate`], the WAL emits one
+//! [`WalKind::LatencySloBurn`] event and counts it in
+//! `wal_slo_burn_alerts_total`, labelled with the `op`. It emits
+//! [`WalKind::LatencySloRecovered`] when the burn rate falls back below, and
+//! only then alerts again.
+//!
+//! [`WalConfig::append_slo`]: crate::WalConfig::append_slo
+//! [`WalConfig::fsync_slo`]: crate::WalConfig::fsync_slo
+
+use nori_observe::{obs_emit, LatencyOp, Meter, VizEvent, WalEvt, WalKind};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Slots the window is split into; a slot's operations leave the window
+/// together.
+const SLOTS: usize = 10;
+
+/// A latency objective for one kind of operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySlo {
+    /// Operations taking longer than this count against the SLO.
+    pub threshold: Duration,
+    /// Share of operations that should finish within `threshold`, between 0
+    /// and 1 (default: 0.99).
+    pub objective: f64,
+    /// How far back operations are counted (default: 5 minutes).
+    pub window: Duration,
+    /// Burn rate at which to alert (default: 10).
+    pub max_burn_rate: f64,
+    /// Operations the window must hold before the burn rate is judged, so a
+    /// few slow ones in a quiet period do not alert (default: 100).
+    pub min_samples: u64,
+}
+
+impl LatencySlo {
+    /// An SLO that 99% of operations finish within `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for LatencySlo {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_millis(10),
+            objective: 0.99,
+            window: Duration::from_secs(5 * 60),
+            max_burn_rate: 10.0,
+            min_samples: 100,
+        }
+    }
+}
+
+/// Operations counted in one slot of the window.
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    /// Number of the slot since the first operation.
+    epoch: u64,
+    total: u64,
+    slow: u64,
+}
+
+#[derive(Default)]
+struct Window {
+    /// When the first operation was seen; slots are counted from here.
+    origin: Option<Instant>,
+    slots: [Slot; SLOTS],
+    burning: bool,
+}
+
+/// A change in whether an SLO is burning, with the burn rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SloAlert {
+    Burn(f64),
+    Recovered(f64),
+}
+
+/// Tracks the burn rate of one [`LatencySlo`].
+pub(crate) struct SloMonitor {
+    op: LatencyOp,
+    slo: LatencySlo,
+    window: Mutex<Window>,
+}
+
+impl SloMonitor {
+    pub(crate) fn new(op: LatencyOp, slo: LatencySlo) -> Self {
+        Self {
+            op,
+            slo,
+            window: Mutex::default(),
+        }
+    }
+
+    /// Counts an operation that took `elapsed`, finishing at `now`, and
+    /// reports the SLO starting or stopping to burn on `meter`.
+    pub(crate) fn observe(
+        &self,
+        meter: &dyn Meter,
+        node_id: u32,
+        segment_id: u64,
+        elapsed: Duration,
+        now: Instant,
+    ) {
+        let kind = match self.record(elapsed, now) {
+            None => return,
+            Some(SloAlert::Burn(burn_rate)) => {
+                meter
+                    .counter_with(
+                        "wal_slo_burn_alerts_total",
+                        &[("op", self.op.as_str().into())],
+                    )
+                    .inc(1);
+                WalKind::LatencySloBurn {
+                    op: self.op,
+                    threshold_ms: self.slo.threshold.as_millis() as u32,
+                    burn_rate,
+                }
+            }
+            Some(SloAlert::Recovered(burn_rate)) => WalKind::LatencySloRecovered {
+                op: self.op,
+                burn_rate,
+            },
+        };
+        obs_emit!(
+            meter,
+            VizEvent::Wal(WalEvt {
+                node: node_id,
+                seg: segment_id,
+                kind,
+            })
+        );
+    }
+
+    /// Counts an operation that took `elapsed`, finishing at `now`, and
+    /// returns whether the SLO started or stopped burning.
+    fn record(&self, elapsed: Duration, now: Instant) -> Option<SloAlert> {
+        let mut window = self.window.lock().unwrap();
+        let origin = *window.origin.get_or_insert(now);
+        let slot_len = (self.slo.window / SLOTS as u32).as_nanos().max(1);
+        let epoch = (now.saturating_duration_since(origin).as_nanos() / slot_len) as u64;
+
+        let slot = &mut window.slots[epoch as usize % SLOTS];
+        if slot.epoch != epoch {
+            *slot = Slot {
+                epoch,
+                ..Default::default()
+            };
+        }
+        slot.total += 1;
+        if elapsed > self.slo.threshold {
+            slot.slow += 1;
+        }
+
+        let (total, slow) = window
+            .slots
+            .iter()
+            .filter(|slot| slot.epoch + SLOTS as u64 > epoch)
+            .fold((0, 0), |(total, slow), slot| {
+                (total + slot.total, slow + slot.slow)
+            });
+        if total < self.slo.min_samples.max(1) {
+            return None;
+        }
+        let burn_rate = (slow as f64 / total as f64) / (1.0 - self.slo.objective);
+        match (window.burning, burn_rate >= self.slo.max_burn_rate) {
+            (false, true) => {
+                window.burning = true;
+                Some(SloAlert::Burn(burn_rate))
+            }
+            (true, false) => {
+                window.burning = false;
+                Some(SloAlert::Recovered(burn_rate))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(1);
+    const SLOW: Duration = Duration::from_millis(50);
+
+    fn monitor() -> SloMonitor {
+        SloMonitor::new(
+            LatencyOp::Fsync,
+            LatencySlo {
+                window: Duration::from_secs(10),
+                min_samples: 10,
+                ..LatencySlo::new(Duration::from_millis(10))
+            },
+        )
+    }
+
+    #[test]
+    fn test_alerts_once_while_burning() {
+        let monitor = monitor();
+        let start = Instant::now();
+
+        // Too few operations to judge, however slow
+        for _ in 0..9 {
+            assert_eq!(monitor.record(SLOW, start), None);
+        }
+        // 10 slow out of 10 is 100 times the 1% budget
+        assert!(matches!(
+            monitor.record(SLOW, start),
+            Some(SloAlert::Burn(rate)) if rate > 99.0
+        ));
+        assert_eq!(monitor.record(SLOW, start), None);
+
+        // Once the slow ones leave the window, fast ones bring it back
+        let later = start + Duration::from_secs(11);
+        for _ in 0..9 {
+            assert_eq!(monitor.record(FAST, later), None);
+        }
+        assert_eq!(monitor.record(FAST, later), Some(SloAlert::Recovered(0.0)));
+        for _ in 0..90 {
+            assert_eq!(monitor.record(FAST, later), None);
+        }
+        // 11 slow out of 111 is still under 10 times the budget
+        for _ in 0..11 {
+            assert_eq!(monitor.record(SLOW, later), None);
+        }
+        assert!(matches!(
+            monitor.record(SLOW, later),
+            Some(SloAlert::Burn(rate)) if rate >= 10.0
+        ));
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_reports_alerts_on_the_meter() {
+        use nori_observe::TestMeter;
+
+        let meter = TestMeter::new();
+        let monitor = monitor();
+        let now = Instant::now();
+        for _ in 0..10 {
+            monitor.observe(&meter, 7, 3, SLOW, now);
+        }
+        assert_eq!(meter.counter_total("wal_slo_burn_alerts_total"), 1);
+        let events = meter.events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            &events[0],
+            VizEvent::Wal(WalEvt {
+                node: 7,
+                seg: 3,
+                kind: WalKind::LatencySloBurn {
+                    op: LatencyOp::Fsync,
+                    threshold_ms: 10,
+                    ..
+                },
+            })
+        ));
+    }
+}
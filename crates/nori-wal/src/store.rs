@@ -0,0 +1,286 @@
+//! Pluggable storage for segment bytes.
+//!
+//! A [`SegmentManager`](crate::SegmentManager) creates, appends to, reads,
+//! seals and deletes its segments through a [`SegmentStore`], addressing each
+//! by the WAL directory and its segment ID rather than by path. The default,
+//! [`FsSegmentStore`], keeps each segment in a `{id:06}.wal` file on an
+//! [`Fs`]; over [`SimFs`](crate::SimFs) that is an in-memory store. Other
+//! stores, such as one that uploads sealed segments to an object store, only
+//! need to hand out [`FsFile`] handles for the segments they hold.
+//!
+//! Appends write through the handle returned by
+//! [`open_append`](SegmentStore::open_append), at the end of what the segment
+//! already holds. Once a segment receives no more appends it is
+//! [sealed](SegmentStore::seal), after which it is only read.
+//!
+//! A [`Wal`](crate::Wal) opened with
+//! [`WalBuilder::segment_store`](crate::WalBuilder::segment_store) also
+//! recovers through its store: recovery lists, reads, repairs and deletes
+//! segments there, while seal and quarantine sidecars stay on the [`Fs`].
+//! Compaction, scrubbing, seal verification and hash-chain verification also
+//! go through the store. Backups, copies and directory migration write whole
+//! directories of segment files, so they fail with
+//! [`SegmentError::InvalidConfig`](crate::SegmentError::InvalidConfig) unless
+//! the store keeps its segments as files on the WAL's [`Fs`] (see
+//! [`SegmentStore::files`]). The offline tools read segment files directly.
+
+use crate::failpoint;
+use crate::fs::{Fs, FsFile, FsFuture, OpenMode};
+use crate::segment::{parse_segment_id_from_path, segment_path};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Where segment bytes live.
+pub trait SegmentStore: Send + Sync + 'static {
+    /// Opens segment `id` in `dir` for appending, creating it empty if
+    /// `create` is set and it does not exist yet.
+    fn open_append<'a>(
+        &'a self,
+        dir: &'a Path,
+        id: u64,
+        create: bool,
+    ) -> FsFuture<'a, Arc<dyn FsFile>>;
+
+    /// Opens segment `id` in `dir` for reading, failing with
+    /// [`std::io::ErrorKind::NotFound`] if it does not exist.
+    fn open_read<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, Arc<dyn FsFile>>;
+
+    /// Returns the IDs of the segments in `dir`, in ascending order.
+    fn list<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<u64>>;
+
+    /// Returns the length of segment `id` in `dir`.
+    fn len<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, u64>;
+
+    /// Marks segment `id`, open as `file`, as complete at `len` bytes and
+    /// makes it durable. Called on rotation and when the WAL closes; a
+    /// segment may be sealed again if the WAL reopens and appends to it.
+    fn seal<'a>(
+        &'a self,
+        dir: &'a Path,
+        id: u64,
+        file: &'a Arc<dyn FsFile>,
+        len: u64,
+    ) -> FsFuture<'a, ()>;
+
+    /// Replaces the contents of segment `id` in `dir` with `data`. Used by
+    /// recovery to cut corrupted ranges out and by compaction, so it must be
+    /// atomic and durable: after a crash the segment holds either its old or
+    /// its new bytes.
+    fn replace<'a>(&'a self, dir: &'a Path, id: u64, data: &'a [u8]) -> FsFuture<'a, ()>;
+
+    /// Deletes segment `id` from `dir`.
+    fn delete<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, ()>;
+
+    /// The [`Fs`] holding the segments as `{id:06}.wal` files, if the store
+    /// keeps them that way. Backups, copies and migration need it.
+    fn files(&self) -> Option<&Arc<dyn Fs>> {
+        None
+    }
+}
+
+/// Segments kept as files on an [`Fs`], the default store.
+pub struct FsSegmentStore {
+    fs: Arc<dyn Fs>,
+}
+
+impl FsSegmentStore {
+    /// A store keeping segment files on `fs`.
+    pub fn new(fs: Arc<dyn Fs>) -> Self {
+        Self { fs }
+    }
+}
+
+impl SegmentStore for FsSegmentStore {
+    fn open_append<'a>(
+        &'a self,
+        dir: &'a Path,
+        id: u64,
+        create: bool,
+    ) -> FsFuture<'a, Arc<dyn FsFile>> {
+        Box::pin(async move {
+            // Never truncate: appends go after existing data
+            let mode = if create {
+                OpenMode::Create
+            } else {
+                OpenMode::Write
+            };
+            self.fs.open(&segment_path(dir, id), mode).await
+        })
+    }
+
+    fn open_read<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, Arc<dyn FsFile>> {
+        Box::pin(async move { self.fs.open(&segment_path(dir, id), OpenMode::Read).await })
+    }
+
+    fn list<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<u64>> {
+        Box::pin(async move {
+            let mut ids: Vec<u64> = self
+                .fs
+                .read_dir(dir)
+                .await?
+                .iter()
+                .filter_map(|path| parse_segment_id_from_path(path))
+                .collect();
+            ids.sort_unstable();
+            Ok(ids)
+        })
+    }
+
+    fn len<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, u64> {
+        Box::pin(async move { self.fs.len(&segment_path(dir, id)).await })
+    }
+
+    fn seal<'a>(
+        &'a self,
+        _dir: &'a Path,
+        _id: u64,
+        file: &'a Arc<dyn FsFile>,
+        len: u64,
+    ) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            // Drop any preallocated tail
+            file.set_len(len).await?;
+            file.sync_all().await
+        })
+    }
+
+    fn replace<'a>(&'a self, dir: &'a Path, id: u64, data: &'a [u8]) -> FsFuture<'a, ()> {
+        Box::pin(async move {
+            // Write a temp file and rename it over the segment, so a crash
+            // before the rename leaves the original untouched
+            let path = segment_path(dir, id);
+            let temp_path = path.with_extension("wal.tmp");
+            let temp_file = self.fs.open(&temp_path, OpenMode::Truncate).await?;
+            temp_file.write_all_at(0, data).await?;
+            temp_file.sync_all().await?;
+            drop(temp_file);
+            if failpoint::fired(failpoint::RECOVERY_BEFORE_RENAME) {
+                return Err(io::Error::other(format!(
+                    "injected failure at {}",
+                    failpoint::RECOVERY_BEFORE_RENAME
+                )));
+            }
+            self.fs.rename(&temp_path, &path).await?;
+            self.fs.sync_dir(dir).await
+        })
+    }
+
+    fn delete<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, ()> {
+        Box::pin(async move { self.fs.remove_file(&segment_path(dir, id)).await })
+    }
+
+    fn files(&self) -> Option<&Arc<dyn Fs>> {
+        Some(&self.fs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Record;
+    use crate::segment::{FsyncPolicy, Position, SegmentConfig, SegmentManager};
+    use crate::sim::SimFs;
+    use nori_observe::NoopMeter;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    /// Segments kept on a `SimFs`, noting which were sealed and deleted.
+    struct RecordingStore {
+        inner: FsSegmentStore,
+        sealed: Mutex<Vec<(u64, u64)>>,
+        deleted: Mutex<Vec<u64>>,
+    }
+
+    impl SegmentStore for RecordingStore {
+        fn open_append<'a>(
+            &'a self,
+            dir: &'a Path,
+            id: u64,
+            create: bool,
+        ) -> FsFuture<'a, Arc<dyn FsFile>> {
+            self.inner.open_append(dir, id, create)
+        }
+
+        fn open_read<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, Arc<dyn FsFile>> {
+            self.inner.open_read(dir, id)
+        }
+
+        fn list<'a>(&'a self, dir: &'a Path) -> FsFuture<'a, Vec<u64>> {
+            self.inner.list(dir)
+        }
+
+        fn len<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, u64> {
+            self.inner.len(dir, id)
+        }
+
+        fn seal<'a>(
+            &'a self,
+            dir: &'a Path,
+            id: u64,
+            file: &'a Arc<dyn FsFile>,
+            len: u64,
+        ) -> FsFuture<'a, ()> {
+            self.sealed.lock().unwrap().push((id, len));
+            self.inner.seal(dir, id, file, len)
+        }
+
+        fn replace<'a>(&'a self, dir: &'a Path, id: u64, data: &'a [u8]) -> FsFuture<'a, ()> {
+            self.inner.replace(dir, id, data)
+        }
+
+        fn delete<'a>(&'a self, dir: &'a Path, id: u64) -> FsFuture<'a, ()> {
+            self.deleted.lock().unwrap().push(id);
+            self.inner.delete(dir, id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manager_keeps_segments_in_its_store() {
+        let fs = Arc::new(SimFs::new());
+        let store = Arc::new(RecordingStore {
+            inner: FsSegmentStore::new(fs.clone()),
+            sealed: Mutex::default(),
+            deleted: Mutex::default(),
+        });
+        let dir = PathBuf::from("/sim/wal");
+        let config = SegmentConfig {
+            max_segment_size: 100,
+            dir: dir.clone(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let manager =
+            SegmentManager::new_with_store(config, Arc::new(NoopMeter), 1, fs, store.clone())
+                .await
+                .unwrap();
+
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
+        let mut last = manager.append(&record).await.unwrap();
+        while last.segment_id < 2 {
+            last = manager.append(&record).await.unwrap();
+        }
+        assert_eq!(store.list(&dir).await.unwrap(), vec![0, 1, 2]);
+        let sealed: Vec<u64> = store.sealed.lock().unwrap().iter().map(|s| s.0).collect();
+        assert_eq!(sealed, vec![0, 1]);
+        let (_, sealed_len) = store.sealed.lock().unwrap()[0];
+        assert_eq!(store.len(&dir, 0).await.unwrap(), sealed_len);
+
+        // Reads and purges go through the store too
+        let mut reader = manager
+            .read_from(Position {
+                segment_id: 0,
+                offset: 0,
+            })
+            .await
+            .unwrap();
+        let (first, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(first.key, record.key);
+        drop(reader);
+
+        assert_eq!(manager.delete_segments_before(last).await.unwrap(), 2);
+        assert_eq!(*store.deleted.lock().unwrap(), vec![0, 1]);
+        assert_eq!(store.list(&dir).await.unwrap(), vec![2]);
+    }
+}
@@ -0,0 +1,113 @@
+//! Trace context propagation from callers into the log.
+//!
+//! A caller serving a traced request attaches its W3C trace context to the
+//! records it appends with [`Record::with_trace`], parsing the incoming
+//! `traceparent` header with [`TraceContext::parse`]. The context is stored
+//! in the record's header, so consumers tailing or replaying the log can
+//! carry the trace on. For sampled contexts the WAL also reports spans
+//! through its meter's [`record_span`](nori_observe::Meter::record_span):
+//!
+//! - `wal.append`, a child of the caller's span, from the append call to its
+//!   return (waiting for other writers, writing and any fsync), with the
+//!   record's segment, offset and LSN
+//! - `wal.fsync`, a child of `wal.append`, when the append made an fsync
+//!   before returning, with the fsync's sequence number
+//!
+//! Every append made durable by the same fsync carries the same `fsync`
+//! attribute, so a slow request can be followed down to the fsync batch that
+//! held it up, and to the other appends in that batch. With
+//! `nori-observe-tracing` the spans become `tracing` spans.
+//!
+//! [`TraceContext::parse`]: crate::TraceContext::parse
+
+use crate::record::Record;
+use crate::segment::Position;
+use nori_observe::{Meter, SpanRecord};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An fsync an append made before returning.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FsyncSpan {
+    /// Number of the fsync since the WAL was opened.
+    pub(crate) seq: u64,
+    pub(crate) duration: Duration,
+}
+
+/// Returns true if any of `records` carries a sampled trace context.
+pub(crate) fn any_sampled(records: &[Record]) -> bool {
+    records
+        .iter()
+        .any(|record| record.trace.is_some_and(|trace| trace.flags & 1 != 0))
+}
+
+/// Reports spans for the records among `appended` (each with its position
+/// and LSN) whose trace context is sampled. `elapsed` is how long the append
+/// call took and `fsync` the fsync it made, if any.
+pub(crate) fn report_append<'a>(
+    meter: &dyn Meter,
+    node_id: u32,
+    appended: impl IntoIterator<Item = (&'a Record, Position, u64)>,
+    elapsed: Duration,
+    fsync: Option<FsyncSpan>,
+) {
+    for (record, position, lsn) in appended {
+        let Some(trace) = record.trace.filter(|trace| trace.flags & 1 != 0) else {
+            continue;
+        };
+        let append_span = new_span_id();
+        let mut attributes = vec![
+            ("node", node_id.to_string().into()),
+            ("segment", position.segment_id.to_string().into()),
+            ("offset", position.offset.to_string().into()),
+            ("lsn", lsn.to_string().into()),
+        ];
+        if let Some(fsync) = fsync {
+            attributes.push(("fsync", fsync.seq.to_string().into()));
+        }
+        meter.record_span(&SpanRecord {
+            name: "wal.append",
+            trace_id: trace.trace_id,
+            span_id: append_span,
+            parent_span_id: trace.span_id,
+            duration: elapsed,
+            attributes,
+        });
+        if let Some(fsync) = fsync {
+            meter.record_span(&SpanRecord {
+                name: "wal.fsync",
+                trace_id: trace.trace_id,
+                span_id: new_span_id(),
+                parent_span_id: append_span,
+                duration: fsync.duration,
+                attributes: vec![
+                    ("fsync", fsync.seq.to_string().into()),
+                    ("segment", position.segment_id.to_string().into()),
+                ],
+            });
+        }
+    }
+}
+
+/// Returns a span ID unlikely to collide with any other process's.
+fn new_span_id() -> [u8; 8] {
+    static SEED: OnceLock<u64> = OnceLock::new();
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let seed = *SEED.get_or_init(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.as_nanos() as u64 ^ u64::from(std::process::id()).rotate_left(32)
+    });
+    // splitmix64 over a counter: distinct for every call in this process
+    let mut x = seed.wrapping_add(
+        NEXT.fetch_add(1, Ordering::Relaxed)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    );
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+    // All zeros is not a valid span ID
+    x.max(1).to_be_bytes()
+}
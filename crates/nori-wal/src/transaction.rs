@@ -0,0 +1,637 @@
+//! Atomic writes across the shards of a [`WalSet`].
+//!
+//! Each shard of a [`WalSet`] is an independent log, so of two records
+//! appended to different shards, a crash can keep one and lose the other.
+//! [`WalSet::commit_transaction`] writes records to several shards so that
+//! either every shard gets its records or none does, with a two-phase commit
+//! coordinated through a small log of its own in `root/coordinator/`:
+//!
+//! 1. The coordinator logs which shards the transaction involves.
+//! 2. Each shard gets a prepare marker holding its records, and is fsynced.
+//! 3. The coordinator logs the commit decision and fsyncs it. From here on
+//!    the transaction is committed.
+//! 4. Each shard gets a commit marker followed by its records, in one batch,
+//!    and is fsynced.
+//! 5. The coordinator logs that the transaction is done.
+//!
+//! If a prepare fails, the decision is abort instead and the prepared shards
+//! get an abort marker. A crash can leave transactions in doubt, which
+//! [`WalSet::open`] resolves before returning: those with a commit decision
+//! have their records applied to every shard still missing them, and the
+//! others are aborted. [`WalSet::recovered_transactions`] lists them.
+//!
+//! Markers are records in the shard logs whose keys start with
+//! [`MARKER_PREFIX`]; consumers of a shard should skip them, and
+//! [`parse_marker`] tells them apart. The records of a transaction only
+//! appear in a shard after its commit marker, whose value holds how many
+//! follow as a big-endian `u32`. Transactions are atomic, not isolated:
+//! each shard applies its records at its own time, and other appends to the
+//! same shards carry on meanwhile.
+//!
+//! [`WalSet`]: crate::WalSet
+//! [`WalSet::commit_transaction`]: crate::WalSet::commit_transaction
+//! [`WalSet::open`]: crate::WalSet::open
+//! [`WalSet::recovered_transactions`]: crate::WalSet::recovered_transactions
+
+use crate::record::{Record, RecordError};
+use crate::segment::{Position, SegmentError};
+use crate::wal::Wal;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Start of the key of every transaction marker in a shard log.
+pub const MARKER_PREFIX: &[u8] = b"\xffnori-txn/";
+
+/// Directory of the coordinator log under the root of a `WalSet`.
+pub(crate) const COORDINATOR_DIR: &str = "coordinator";
+
+/// Metadata blob holding the next transaction ID, kept before the
+/// coordinator purges entries that carry IDs.
+const NEXT_ID_META: &str = "next_transaction_id";
+
+/// What a marker in a shard log records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerKind {
+    /// The shard's records, waiting for the decision.
+    Prepare,
+    /// The transaction committed; its records follow.
+    Commit,
+    /// The transaction was aborted.
+    Abort,
+}
+
+impl MarkerKind {
+    fn tag(self) -> u8 {
+        match self {
+            MarkerKind::Prepare => b'p',
+            MarkerKind::Commit => b'c',
+            MarkerKind::Abort => b'a',
+        }
+    }
+}
+
+/// Returns the transaction ID and kind of a marker, or `None` if `record`
+/// is not one.
+pub fn parse_marker(record: &Record) -> Option<(u64, MarkerKind)> {
+    let rest = record.key.strip_prefix(MARKER_PREFIX)?;
+    let id = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+    // The tag must be the whole remainder, exactly one byte
+    let kind = match &rest[8..] {
+        [b'p'] => MarkerKind::Prepare,
+        [b'c'] => MarkerKind::Commit,
+        [b'a'] => MarkerKind::Abort,
+        _ => return None,
+    };
+    Some((id, kind))
+}
+
+fn marker_key_prefix(id: u64) -> Vec<u8> {
+    let mut key = MARKER_PREFIX.to_vec();
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+fn marker(id: u64, kind: MarkerKind, value: impl Into<Bytes>) -> Record {
+    let mut key = marker_key_prefix(id);
+    key.push(kind.tag());
+    Record::put(key, value)
+}
+
+/// A transaction written by [`WalSet::commit_transaction`](crate::WalSet::commit_transaction).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommittedTransaction {
+    pub id: u64,
+    /// Where each shard's records were appended, in the order given.
+    pub positions: BTreeMap<u32, Vec<Position>>,
+}
+
+/// Transactions a crash left in doubt, resolved when the set was opened.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionRecovery {
+    /// Transactions that had committed, now applied to every shard.
+    pub committed: Vec<u64>,
+    /// Transactions that had not, now aborted.
+    pub aborted: Vec<u64>,
+}
+
+/// An entry of the coordinator log, keyed by transaction ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    /// The shards involved, with their tail before the transaction.
+    Begin(Vec<(u32, Position)>),
+    Commit,
+    Abort,
+    /// Every shard has the outcome.
+    End,
+}
+
+impl Entry {
+    fn to_record(&self, id: u64) -> Record {
+        let mut value = BytesMut::new();
+        match self {
+            Entry::Begin(shards) => {
+                value.put_u8(b'b');
+                for (shard, start) in shards {
+                    value.put_u32(*shard);
+                    value.put_u64(start.segment_id);
+                    value.put_u64(start.offset);
+                }
+            }
+            Entry::Commit => value.put_u8(b'c'),
+            Entry::Abort => value.put_u8(b'a'),
+            Entry::End => value.put_u8(b'e'),
+        }
+        Record::put(id.to_be_bytes().to_vec(), value.freeze())
+    }
+
+    fn parse(record: &Record) -> Option<(u64, Entry)> {
+        let id = u64::from_be_bytes(record.key.as_ref().try_into().ok()?);
+        let mut value = record.value.as_ref();
+        if !value.has_remaining() {
+            return None;
+        }
+        let entry = match value.get_u8() {
+            b'b' if value.len() % 20 == 0 => {
+                let mut shards = Vec::new();
+                while value.has_remaining() {
+                    let shard = value.get_u32();
+                    let start = Position {
+                        segment_id: value.get_u64(),
+                        offset: value.get_u64(),
+                    };
+                    shards.push((shard, start));
+                }
+                Entry::Begin(shards)
+            }
+            b'c' if value.is_empty() => Entry::Commit,
+            b'a' if value.is_empty() => Entry::Abort,
+            b'e' if value.is_empty() => Entry::End,
+            _ => return None,
+        };
+        Some((id, entry))
+    }
+}
+
+/// A transaction the coordinator log does not show as done.
+pub(crate) struct InDoubt {
+    id: u64,
+    shards: Vec<(u32, Position)>,
+    /// The decision, if one was logged.
+    committed: Option<bool>,
+}
+
+/// The coordinator log and the transactions in flight.
+pub(crate) struct Coordinator {
+    wal: Wal,
+    next_id: AtomicU64,
+    /// Where the begin entry of each transaction not yet done is.
+    pending: Mutex<BTreeMap<u64, Position>>,
+    /// Segments before this one have been purged.
+    purged_before: AtomicU64,
+}
+
+impl Coordinator {
+    /// Reads the coordinator log in `wal`, returning the coordinator and the
+    /// transactions left in doubt, oldest first.
+    pub(crate) async fn open(wal: Wal) -> Result<(Self, Vec<InDoubt>), SegmentError> {
+        let mut next_id = match wal.meta().get(NEXT_ID_META).await? {
+            Some(value) => u64::from_be_bytes(
+                value
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| SegmentError::CorruptMeta(NEXT_ID_META.to_string()))?,
+            ),
+            None => 1,
+        };
+        let mut begun: BTreeMap<u64, (Position, InDoubt)> = BTreeMap::new();
+        let mut reader = wal.reader(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        loop {
+            let (record, position) = match reader.next_record().await {
+                Ok(Some(next)) => next,
+                Ok(None) => break,
+                // Segments of finished transactions are purged
+                Err(SegmentError::Gap {
+                    resume_at: Some(_), ..
+                }) => continue,
+                Err(e) => return Err(e),
+            };
+            let (id, entry) = Entry::parse(&record).ok_or(SegmentError::Corruption {
+                segment_id: position.segment_id,
+                offset: position.offset,
+            })?;
+            next_id = next_id.max(id + 1);
+            match entry {
+                Entry::Begin(shards) => {
+                    let txn = InDoubt {
+                        id,
+                        shards,
+                        committed: None,
+                    };
+                    begun.insert(id, (position, txn));
+                }
+                Entry::Commit | Entry::Abort => {
+                    if let Some((_, txn)) = begun.get_mut(&id) {
+                        txn.committed = Some(entry == Entry::Commit);
+                    }
+                }
+                Entry::End => {
+                    begun.remove(&id);
+                }
+            }
+        }
+
+        let pending = begun.iter().map(|(&id, (position, _))| (id, *position));
+        let coordinator = Self {
+            next_id: AtomicU64::new(next_id),
+            pending: Mutex::new(pending.collect()),
+            purged_before: AtomicU64::new(0),
+            wal,
+        };
+        Ok((
+            coordinator,
+            begun.into_values().map(|(_, txn)| txn).collect(),
+        ))
+    }
+
+    async fn log(&self, id: u64, entry: &Entry) -> Result<Position, SegmentError> {
+        let position = self.wal.append(&entry.to_record(id)).await?;
+        self.wal.sync().await?;
+        Ok(position)
+    }
+
+    /// Starts a transaction on `shards`, returning its ID.
+    async fn begin(&self, shards: Vec<(u32, Position)>) -> Result<u64, SegmentError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let position = self.wal.append(&Entry::Begin(shards).to_record(id)).await?;
+        self.pending.lock().unwrap().insert(id, position);
+        self.wal.sync().await?;
+        Ok(id)
+    }
+
+    /// Marks a transaction done, then purges the segments holding only
+    /// transactions that are done too.
+    async fn finish(&self, id: u64) -> Result<(), SegmentError> {
+        // Not synced: a transaction found in doubt again resolves to the same outcome
+        let end = self.wal.append(&Entry::End.to_record(id)).await?;
+        let keep = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.remove(&id);
+            pending.values().copied().chain([end]).min().unwrap_or(end)
+        };
+        if keep.segment_id > self.purged_before.load(Ordering::Relaxed) {
+            // Purged entries no longer tell which IDs were used
+            let next_id = self.next_id.load(Ordering::Relaxed);
+            self.wal
+                .meta()
+                .put(NEXT_ID_META, &next_id.to_be_bytes())
+                .await?;
+            let before = Position {
+                segment_id: keep.segment_id,
+                offset: 0,
+            };
+            self.wal
+                .purge_segments_before(before, "WalSet::commit_transaction")
+                .await?;
+            self.purged_before
+                .fetch_max(keep.segment_id, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn close(self) -> Result<(), SegmentError> {
+        self.wal.close().await
+    }
+}
+
+fn in_doubt(id: u64, reason: String) -> SegmentError {
+    SegmentError::InDoubt { id, reason }
+}
+
+fn encode_records(records: &[Record]) -> Bytes {
+    let mut encoded = BytesMut::new();
+    for record in records {
+        encoded.extend_from_slice(&record.encode());
+    }
+    encoded.freeze()
+}
+
+fn decode_records(mut encoded: &[u8]) -> Result<Vec<Record>, RecordError> {
+    let mut records = Vec::new();
+    while !encoded.is_empty() {
+        let (record, size) = Record::decode(encoded)?;
+        records.push(record);
+        encoded = &encoded[size..];
+    }
+    Ok(records)
+}
+
+async fn append_synced(wal: &Wal, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+    let positions = wal.append_batch(records).await?;
+    wal.sync().await?;
+    Ok(positions)
+}
+
+/// Writes `writes`, each shard's records with its WAL, as one transaction.
+pub(crate) async fn commit(
+    coordinator: &Coordinator,
+    writes: &[(u32, Arc<Wal>, Vec<Record>)],
+) -> Result<CommittedTransaction, SegmentError> {
+    let mut starts = Vec::with_capacity(writes.len());
+    for (shard, wal, _) in writes {
+        starts.push((*shard, wal.current_position().await));
+    }
+    let id = coordinator.begin(starts).await?;
+
+    let mut prepared = Vec::new();
+    for (_, wal, records) in writes {
+        let prepare = marker(id, MarkerKind::Prepare, encode_records(records));
+        if let Err(e) = append_synced(wal, &[prepare]).await {
+            // Left without a decision, it is aborted when the set next opens
+            if coordinator.log(id, &Entry::Abort).await.is_ok() {
+                let mut aborted = true;
+                for wal in prepared {
+                    let abort = marker(id, MarkerKind::Abort, Bytes::new());
+                    aborted &= append_synced(wal, &[abort]).await.is_ok();
+                }
+                if aborted {
+                    let _ = coordinator.finish(id).await;
+                }
+            }
+            return Err(e);
+        }
+        prepared.push(wal);
+    }
+
+    coordinator
+        .log(id, &Entry::Commit)
+        .await
+        .map_err(|e| in_doubt(id, format!("logging the commit decision failed: {}", e)))?;
+
+    let mut positions = BTreeMap::new();
+    for (shard, wal, records) in writes {
+        let count = (records.len() as u32).to_be_bytes().to_vec();
+        let mut batch = vec![marker(id, MarkerKind::Commit, count)];
+        batch.extend(records.iter().cloned());
+        let mut applied = append_synced(wal, &batch).await.map_err(|e| {
+            in_doubt(
+                id,
+                format!(
+                    "committed, but applying it to shard {} failed: {}",
+                    shard, e
+                ),
+            )
+        })?;
+        applied.remove(0);
+        positions.insert(*shard, applied);
+    }
+    coordinator.finish(id).await?;
+    Ok(CommittedTransaction { id, positions })
+}
+
+/// The markers of one transaction found in a shard.
+#[derive(Default)]
+struct Markers {
+    prepared: Option<Vec<Record>>,
+    commit: Option<Position>,
+    aborted: bool,
+}
+
+async fn find_markers(wal: &Wal, id: u64, start: Position) -> Result<Markers, SegmentError> {
+    let mut markers = Markers::default();
+    let mut reader = wal.reader(start).with_key_prefix(marker_key_prefix(id));
+    while let Some((record, position)) = reader.next_record().await? {
+        match parse_marker(&record) {
+            Some((_, MarkerKind::Prepare)) => {
+                markers.prepared = Some(decode_records(&record.value)?);
+            }
+            Some((_, MarkerKind::Commit)) => markers.commit = Some(position),
+            Some((_, MarkerKind::Abort)) => markers.aborted = true,
+            None => {}
+        }
+    }
+    Ok(markers)
+}
+
+/// Counts the records of `records` that follow the commit marker at
+/// `commit`, which a crash may have cut short.
+async fn count_applied(
+    wal: &Wal,
+    commit: Position,
+    records: &[Record],
+) -> Result<usize, SegmentError> {
+    let mut reader = wal.reader(commit);
+    reader.next_record().await?; // The marker itself
+    let mut applied = 0;
+    for expected in records {
+        match reader.next_record().await? {
+            Some((record, _))
+                if record.key == expected.key
+                    && record.value == expected.value
+                    && record.tombstone == expected.tombstone
+                    && record.namespace == expected.namespace =>
+            {
+                applied += 1
+            }
+            _ => break,
+        }
+    }
+    Ok(applied)
+}
+
+/// Gives every shard of the transactions in doubt their outcome, deciding
+/// to abort those that had no decision yet.
+pub(crate) async fn resolve(
+    coordinator: &Coordinator,
+    shards: &BTreeMap<u32, Arc<Wal>>,
+    txns: Vec<InDoubt>,
+) -> Result<TransactionRecovery, SegmentError> {
+    let mut recovery = TransactionRecovery::default();
+    for txn in txns {
+        let commit = match txn.committed {
+            Some(commit) => commit,
+            None => {
+                coordinator.log(txn.id, &Entry::Abort).await?;
+                false
+            }
+        };
+        for &(shard, start) in &txn.shards {
+            let missing_prepare = || {
+                in_doubt(
+                    txn.id,
+                    format!("committed, but shard {} has no prepare marker", shard),
+                )
+            };
+            let Some(wal) = shards.get(&shard) else {
+                if commit {
+                    return Err(missing_prepare());
+                }
+                continue;
+            };
+            let markers = find_markers(wal, txn.id, start).await?;
+            if commit {
+                let records = markers.prepared.ok_or_else(missing_prepare)?;
+                let (applied, mut batch) = match markers.commit {
+                    Some(position) => (count_applied(wal, position, &records).await?, vec![]),
+                    None => {
+                        let count = (records.len() as u32).to_be_bytes().to_vec();
+                        (0, vec![marker(txn.id, MarkerKind::Commit, count)])
+                    }
+                };
+                batch.extend(records.into_iter().skip(applied));
+                if !batch.is_empty() {
+                    append_synced(wal, &batch).await?;
+                }
+            } else if markers.prepared.is_some() && !markers.aborted {
+                append_synced(wal, &[marker(txn.id, MarkerKind::Abort, Bytes::new())]).await?;
+            }
+        }
+        coordinator.finish(txn.id).await?;
+        if commit {
+            recovery.committed.push(txn.id);
+        } else {
+            recovery.aborted.push(txn.id);
+        }
+    }
+    Ok(recovery)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::FsyncPolicy;
+    use crate::wal::WalConfig;
+    use crate::wal_set::{WalSet, WalSetConfig};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_entries_and_markers_round_trip() {
+        let begin = Entry::Begin(vec![
+            (
+                3,
+                Position {
+                    segment_id: 1,
+                    offset: 4096,
+                },
+            ),
+            (
+                7,
+                Position {
+                    segment_id: 0,
+                    offset: 0,
+                },
+            ),
+        ]);
+        for entry in [begin, Entry::Commit, Entry::Abort, Entry::End] {
+            let record = entry.to_record(42);
+            assert_eq!(Entry::parse(&record), Some((42, entry)));
+        }
+        assert_eq!(Entry::parse(&Record::put("k", "v")), None);
+
+        let records = vec![Record::put("a", "1"), Record::delete("b")];
+        let prepare = marker(9, MarkerKind::Prepare, encode_records(&records));
+        assert_eq!(parse_marker(&prepare), Some((9, MarkerKind::Prepare)));
+        assert_eq!(decode_records(&prepare.value).unwrap(), records);
+        assert_eq!(
+            parse_marker(&marker(9, MarkerKind::Abort, Bytes::new())),
+            Some((9, MarkerKind::Abort))
+        );
+        assert_eq!(parse_marker(&records[0]), None);
+
+        // A marker key is the prefix, an 8-byte ID and a one-byte tag
+        let mut key = marker_key_prefix(9);
+        assert_eq!(parse_marker(&Record::put(key.clone(), "")), None);
+        key.extend_from_slice(b"cc");
+        assert_eq!(parse_marker(&Record::put(key, "")), None);
+    }
+
+    async fn shard_keys(wals: &WalSet, shard: u32) -> Vec<Bytes> {
+        let wal = wals.shard(shard).await.unwrap();
+        let mut reader = wal.reader(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        let mut keys = Vec::new();
+        while let Some((record, _)) = reader.next_record().await.unwrap() {
+            if parse_marker(&record).is_none() {
+                keys.push(record.key);
+            }
+        }
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_open_resolves_transactions_in_doubt() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalSetConfig {
+            root: temp_dir.path().to_path_buf(),
+            wal: WalConfig {
+                max_segment_size: 1024 * 1024,
+                fsync_policy: FsyncPolicy::Os,
+                preallocate: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (wals, _) = WalSet::open(config.clone()).await.unwrap();
+        let (one, two) = (
+            wals.open_shard(1).await.unwrap(),
+            wals.open_shard(2).await.unwrap(),
+        );
+        let coordinator = wals.coordinator().await.unwrap();
+        let records = [Record::put("a", "1"), Record::put("b", "2")];
+        let prepare = |id| marker(id, MarkerKind::Prepare, encode_records(&records));
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+
+        // Committed, and applied to shard 1 only as far as its first record
+        let committed = coordinator
+            .begin(vec![(1, start), (2, start)])
+            .await
+            .unwrap();
+        append_synced(&one, &[prepare(committed)]).await.unwrap();
+        append_synced(&two, &[prepare(committed)]).await.unwrap();
+        coordinator.log(committed, &Entry::Commit).await.unwrap();
+        let commit = marker(committed, MarkerKind::Commit, 2u32.to_be_bytes().to_vec());
+        append_synced(&one, &[commit, records[0].clone()])
+            .await
+            .unwrap();
+
+        // Prepared on shard 2 only, without a decision
+        let undecided = coordinator
+            .begin(vec![(2, start), (3, start)])
+            .await
+            .unwrap();
+        append_synced(&two, &[prepare(undecided)]).await.unwrap();
+        drop((one, two));
+        wals.close().await.unwrap();
+
+        let (wals, _) = WalSet::open(config.clone()).await.unwrap();
+        let recovered = wals.recovered_transactions();
+        assert_eq!(recovered.committed, [committed]);
+        assert_eq!(recovered.aborted, [undecided]);
+        assert_eq!(shard_keys(&wals, 1).await, ["a", "b"]);
+        assert_eq!(shard_keys(&wals, 2).await, ["a", "b"]);
+        assert!(wals.shard(3).await.is_none());
+        let two = wals.shard(2).await.unwrap();
+        let (last, _) = two.last_record().await.unwrap().unwrap();
+        assert_eq!(parse_marker(&last), Some((undecided, MarkerKind::Abort)));
+        drop(two);
+        wals.close().await.unwrap();
+
+        // Resolving again finds nothing left
+        let (wals, _) = WalSet::open(config).await.unwrap();
+        assert_eq!(
+            wals.recovered_transactions(),
+            &TransactionRecovery::default()
+        );
+        assert_eq!(shard_keys(&wals, 1).await, ["a", "b"]);
+        wals.close().await.unwrap();
+    }
+}
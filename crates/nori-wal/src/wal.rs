@@ -3,13 +3,49 @@
 //! Provides a simple interface for append-only logging with automatic
 //! recovery, rotation, and configurable durability guarantees.
 
+use crate::advisor::{WorkloadObserver, WorkloadProfile};
+use crate::audit::{self, AuditOperation};
+use crate::builder::WalBuilder;
+use crate::chain::{self, ChainHead, ChainReport};
+use crate::checkpoint::{self, Checkpoint};
+use crate::clock::{Clock, SystemClock};
+use crate::compaction::{self, CompactionReport};
+use crate::config;
+use crate::copy::{self, CopyOptions, CopyReport};
+use crate::expiry::{ExpiredKey, ExpiryIndex};
+use crate::fs::{Fs, LocalFs};
+use crate::handle::{WalReadHandle, WalWriter};
+use crate::import::{self, ImportConfig, ImportSummary};
+use crate::lease::{self, Lease};
+use crate::lock::DirLock;
+use crate::memory::MemoryBudget;
+use crate::meta::MetaStore;
+use crate::metrics::{NamespaceMetrics, WalMetrics};
+use crate::moved;
+use crate::quota::NamespaceQuota;
+use crate::reader::{Cursor, WalReader, WalTail};
 use crate::record::Record;
-use crate::recovery::{self, RecoveryInfo};
-use crate::segment::{FsyncPolicy, Position, SegmentConfig, SegmentError, SegmentManager};
-use nori_observe::{Meter, NoopMeter};
-use std::path::PathBuf;
+use crate::recovery::{
+    self, PendingRecovery, RecoveryBudget, RecoveryInfo, RecoveryMode, RecoveryOptions,
+    RecoveryTarget,
+};
+use crate::replay::{self, ReplayBatch, ReplayConfig, ReplaySummary};
+use crate::report;
+use crate::runtime::{self, Runtime, Task, TokioRuntime};
+use crate::scrub::{self, ScrubReport};
+use crate::segment::{
+    list_segment_ids, BackupInfo, FsyncPolicy, Position, ReaderConfig, SegmentConfig, SegmentError,
+    SegmentManager,
+};
+use crate::slo::LatencySlo;
+use crate::store::{FsSegmentStore, SegmentStore};
+use bytes::Bytes;
+use nori_observe::{obs_emit, Meter, NoopMeter, VizEvent, WalEvt, WalKind};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
 
 /// Configuration for the WAL.
 #[derive(Debug, Clone)]
@@ -27,6 +63,105 @@ pub struct WalConfig {
     pub preallocate: bool,
     /// Node ID for observability events.
     pub node_id: u32,
+    /// Preserve bytes discarded by recovery in a `quarantine/` subdirectory
+    /// instead of destroying them (default: false).
+    pub quarantine_corrupted: bool,
+    /// How recovery handles records that fail to decode (default: TruncateTail).
+    pub recovery_mode: RecoveryMode,
+    /// Interval between background integrity scrubs of sealed segments
+    /// (default: None, scrubbing disabled).
+    pub scrub_interval: Option<Duration>,
+    /// CRC-verify each segment in the background right after rotation seals it
+    /// (default: false).
+    pub verify_on_seal: bool,
+    /// Write a seal sidecar for each verified sealed segment and let recovery
+    /// accept sealed segments from it instead of decoding every record
+    /// (default: false). Recovery then checks only each sealed segment's
+    /// length, not its bytes. Opening with a replay callback still decodes them.
+    pub seal_segments: bool,
+    /// Point-in-time recovery: stop replaying at this LSN or timestamp
+    /// (default: None, replay everything).
+    pub recovery_target: Option<RecoveryTarget>,
+    /// Remove records after `recovery_target` from disk rather than only
+    /// skipping them during replay (default: false).
+    pub truncate_after_target: bool,
+    /// Do not pass records whose TTL has expired to the replay callback
+    /// (default: false).
+    pub skip_expired_on_replay: bool,
+    /// Bound on replay work done before `open` returns; the remainder is
+    /// finished with [`Wal::resume_recovery`] (default: unbounded).
+    pub recovery_budget: RecoveryBudget,
+    /// Finish a bounded recovery in a background task on the WAL's runtime
+    /// instead of through [`Wal::resume_recovery`] (default: false). The task
+    /// repairs the remaining segments but has no callback to replay their
+    /// records to, so it cannot be combined with [`Wal::open_with_replay`].
+    pub resume_recovery_in_background: bool,
+    /// Number of JSON recovery reports to keep under `recovery/` in the WAL
+    /// directory (default: 0, reports disabled).
+    pub recovery_reports_kept: usize,
+    /// Delete segments that lie wholly before each new checkpoint
+    /// (default: false).
+    pub purge_on_checkpoint: bool,
+    /// Largest encoded record `append` accepts; it cannot exceed
+    /// `max_segment_size` (default: None, no limit).
+    pub max_record_size: Option<u64>,
+    /// Rotate the active segment once it has held records for this long, even
+    /// if it is not full (default: None, rotate on size only).
+    pub max_segment_age: Option<Duration>,
+    /// If the WAL is dropped without `close()` or `sync()` while holding
+    /// unsynced appends, fsync them on a best-effort basis; the drop is
+    /// reported either way (default: false).
+    pub sync_on_drop: bool,
+    /// Emit `SlowFsync` for fsyncs taking at least this long, besides the
+    /// usual `Fsync` event (default: 100ms).
+    pub slow_fsync_threshold: Option<Duration>,
+    /// Interval between background compactions, which rewrite old sealed
+    /// segments without the records later ones superseded (default: None,
+    /// compaction disabled). See [`Wal::compact`].
+    pub compaction_interval: Option<Duration>,
+    /// Newest sealed segments compaction leaves alone (default: 4).
+    pub compaction_retain_segments: u64,
+    /// Share of a segment's bytes, in percent, that must be superseded
+    /// before compaction rewrites it (default: 25).
+    pub compaction_min_dead_percent: u8,
+    /// Limits on what each namespace keeps in the log, by namespace
+    /// (default: none). See [`crate::quota`].
+    pub namespace_quotas: HashMap<u32, NamespaceQuota>,
+    /// Record purges, truncations and other administrative operations in
+    /// `audit.log` in the WAL directory (default: false). See [`crate::audit`].
+    pub audit_log: bool,
+    /// Link each appended record to the one before it, so that later changes
+    /// to the log can be detected (default: false). Cannot be combined with
+    /// compaction. See [`crate::chain`].
+    pub hash_chain: bool,
+    /// Memory for recently read records, which readers and tails coming
+    /// back to the same positions get without reading the segment again
+    /// (default: 1 MiB). Zero disables the cache. See [`crate::record_cache`].
+    pub record_cache_bytes: u64,
+    /// Appends whose values are compressed on the blocking pool at once;
+    /// zero compresses on the appending task (default: 2). See
+    /// [`crate::encode_pool`].
+    pub encode_workers: usize,
+    /// Bytes of values to compress that an append needs before they go to
+    /// the blocking pool rather than being compressed inline (default: 64 KiB).
+    pub encode_offload_bytes: u64,
+    /// Latency objective for `append` and `append_batch` calls, alerting
+    /// when it burns too fast (default: None). See [`crate::slo`].
+    pub append_slo: Option<LatencySlo>,
+    /// Latency objective for fsyncs, alerting when it burns too fast
+    /// (default: None).
+    pub fsync_slo: Option<LatencySlo>,
+    /// Leave puts that a later put to the same key in the same batch
+    /// replaces out of the log (default: false). See [`crate::coalesce`].
+    pub coalesce_keys: bool,
+    /// Keep an index of keys whose latest write carries a TTL, rebuilt by
+    /// reading the log on open, for listing and deleting those that expired
+    /// (default: false). See [`crate::expiry`].
+    pub track_expiry: bool,
+    /// Memory budget shared with other WALs, charged with append buffers,
+    /// read-ahead, the record cache and replication batches (default:
+    /// None). See [`crate::memory`].
+    pub memory_budget: Option<MemoryBudget>,
 }
 
 impl Default for WalConfig {
@@ -37,42 +172,52 @@ impl Default for WalConfig {
             fsync_policy: FsyncPolicy::Batch(Duration::from_millis(5)),
             preallocate: true,
             node_id: 0,
+            quarantine_corrupted: false,
+            recovery_mode: RecoveryMode::default(),
+            scrub_interval: None,
+            verify_on_seal: false,
+            seal_segments: false,
+            recovery_target: None,
+            truncate_after_target: false,
+            skip_expired_on_replay: false,
+            recovery_budget: RecoveryBudget::default(),
+            resume_recovery_in_background: false,
+            recovery_reports_kept: 0,
+            purge_on_checkpoint: false,
+            max_record_size: None,
+            max_segment_age: None,
+            sync_on_drop: false,
+            slow_fsync_threshold: Some(Duration::from_millis(100)),
+            compaction_interval: None,
+            compaction_retain_segments: 4,
+            compaction_min_dead_percent: 25,
+            namespace_quotas: HashMap::new(),
+            audit_log: false,
+            hash_chain: false,
+            record_cache_bytes: 1024 * 1024,
+            encode_workers: 2,
+            encode_offload_bytes: 64 * 1024,
+            append_slo: None,
+            fsync_slo: None,
+            coalesce_keys: false,
+            track_expiry: false,
+            memory_budget: None,
         }
     }
 }
 
 impl WalConfig {
-    /// Validates the configuration, returning an error if invalid.
-    fn validate(&self) -> Result<(), SegmentError> {
-        // Validate max_segment_size
-        if self.max_segment_size == 0 {
-            return Err(SegmentError::InvalidConfig(
-                "max_segment_size must be greater than 0".to_string(),
-            ));
-        }
-
-        // Warn if segment size is too small (less than 1MB)
-        if self.max_segment_size < 1024 * 1024 {
-            return Err(SegmentError::InvalidConfig(
-                "max_segment_size should be at least 1MB for reasonable performance".to_string(),
-            ));
-        }
-
-        // Validate fsync_policy batch window is reasonable
-        if let FsyncPolicy::Batch(duration) = self.fsync_policy {
-            if duration > Duration::from_secs(1) {
-                return Err(SegmentError::InvalidConfig(
-                    "fsync batch window should be less than 1 second to avoid excessive data loss risk".to_string(),
-                ));
-            }
-            if duration.is_zero() {
-                return Err(SegmentError::InvalidConfig(
-                    "fsync batch window cannot be zero - use FsyncPolicy::Always instead".to_string(),
-                ));
-            }
+    fn recovery_options(&self, now: SystemTime) -> RecoveryOptions {
+        RecoveryOptions {
+            quarantine: self.quarantine_corrupted,
+            mode: self.recovery_mode,
+            target: self.recovery_target,
+            truncate_after_target: self.truncate_after_target,
+            skip_expired: self.skip_expired_on_replay,
+            expire_as_of: Some(now),
+            budget: self.recovery_budget,
+            trust_seals: self.seal_segments,
         }
-
-        Ok(())
     }
 }
 
@@ -101,9 +246,39 @@ impl WalConfig {
 pub struct Wal {
     manager: Arc<SegmentManager>,
     config: WalConfig,
+    meter: Arc<dyn Meter>,
+    /// Background tasks owned by this WAL; aborted when it is dropped.
+    tasks: Vec<Box<dyn Task>>,
+    /// Replay work left over from a bounded recovery, shared with the task
+    /// finishing it when there is one.
+    pending_recovery: Arc<Mutex<Option<PendingRecovery>>>,
+    /// Most recent durable checkpoint, shared with writer handles.
+    checkpoint: Arc<Mutex<Option<Checkpoint>>>,
+    /// Metadata blobs stored next to the segments.
+    meta: MetaStore,
+    /// Exclusive lock on the WAL directory, released on close or drop.
+    pub(crate) lock: Option<DirLock>,
+}
+
+impl Drop for Wal {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.manager.close();
+        // Appends since the last fsync would be lost in a crash from here on
+        self.manager
+            .check_unsynced_on_drop(self.config.sync_on_drop);
+    }
 }
 
 impl Wal {
+    /// Returns a builder for opening a WAL, starting from the default
+    /// configuration.
+    pub fn builder() -> WalBuilder {
+        WalBuilder::default()
+    }
+
     /// Opens a WAL, performing recovery if needed.
     ///
     /// This will scan all existing segments, validate records, and truncate
@@ -116,15 +291,103 @@ impl Wal {
     pub async fn open_with_meter(
         config: WalConfig,
         meter: Arc<dyn Meter>,
+    ) -> Result<(Self, RecoveryInfo), SegmentError> {
+        Self::open_inner(
+            config,
+            meter,
+            Arc::new(TokioRuntime),
+            Arc::new(LocalFs),
+            None,
+            Arc::new(SystemClock),
+            None,
+        )
+        .await
+    }
+
+    /// Opens a WAL, passing every recovered record to `replay` in log order.
+    ///
+    /// The callback runs during the recovery scan, so state such as a memtable
+    /// can be rebuilt without reading the segments a second time. Positions
+    /// reflect the segments after any corruption has been cut out.
+    pub async fn open_with_replay<F>(
+        config: WalConfig,
+        mut replay: F,
+    ) -> Result<(Self, RecoveryInfo), SegmentError>
+    where
+        F: FnMut(Record, Position) + Send,
+    {
+        Self::open_inner(
+            config,
+            Arc::new(NoopMeter),
+            Arc::new(TokioRuntime),
+            Arc::new(LocalFs),
+            None,
+            Arc::new(SystemClock),
+            Some(&mut replay),
+        )
+        .await
+    }
+
+    pub(crate) async fn open_inner(
+        mut config: WalConfig,
+        meter: Arc<dyn Meter>,
+        runtime: Arc<dyn Runtime>,
+        fs: Arc<dyn Fs>,
+        store: Option<Arc<dyn SegmentStore>>,
+        clock: Arc<dyn Clock>,
+        replay: Option<&mut (dyn FnMut(Record, Position) + Send)>,
     ) -> Result<(Self, RecoveryInfo), SegmentError> {
         // Validate configuration
         config.validate()?;
+        if config.resume_recovery_in_background
+            && !config.recovery_budget.is_unbounded()
+            && replay.is_some()
+        {
+            return Err(SegmentError::InvalidConfig(
+                "records recovered in the background cannot be replayed; \
+                 call Wal::resume_recovery instead"
+                    .to_string(),
+            ));
+        }
 
         // Create directory if it doesn't exist
-        tokio::fs::create_dir_all(&config.dir).await?;
+        fs.create_dir_all(&config.dir).await?;
+        // Continue where a migration moved the log, finishing it if needed
+        config.dir = moved::follow(&fs, &config.dir).await?;
+        let lock = acquire_lock(runtime.as_ref(), &fs, &config.dir).await?;
+        let store = store.unwrap_or_else(|| Arc::new(FsSegmentStore::new(fs.clone())));
 
         // Perform recovery
-        let recovery_info = recovery::recover(&config.dir, meter.clone(), config.node_id).await?;
+        let started_at = clock.system_time();
+        let started = Instant::now();
+        let recovery_result = recovery::run_recovery(
+            fs.as_ref(),
+            store.as_ref(),
+            &config.dir,
+            meter.clone(),
+            config.node_id,
+            &config.recovery_options(started_at),
+            replay,
+        )
+        .await;
+        if config.recovery_reports_kept > 0 {
+            let report = report::write_recovery_report_on(
+                fs.as_ref(),
+                &config.dir,
+                config.recovery_mode,
+                started_at,
+                started.elapsed(),
+                recovery_result.as_ref(),
+                config.recovery_reports_kept,
+            )
+            .await;
+            // A failed recovery takes precedence over a failed report
+            if recovery_result.is_ok() {
+                report?;
+            }
+        }
+        let recovery_info = recovery_result?;
+        let last_checkpoint = checkpoint::read_checkpoint(fs.as_ref(), &config.dir).await;
 
         // Create segment manager
         let segment_config = SegmentConfig {
@@ -132,19 +395,110 @@ impl Wal {
             max_segment_size: config.max_segment_size,
             fsync_policy: config.fsync_policy,
             preallocate: config.preallocate,
+            verify_on_seal: config.verify_on_seal,
+            seal_segments: config.seal_segments,
+            max_record_size: config.max_record_size,
+            max_segment_age: config.max_segment_age,
+            slow_fsync_threshold: config.slow_fsync_threshold,
         };
 
-        let manager = SegmentManager::new(segment_config, meter, config.node_id).await?;
+        let meta = MetaStore::with_fs(fs.clone(), config.dir.clone());
+        let manager = Arc::new(
+            SegmentManager::new_with_store(
+                segment_config,
+                meter.clone(),
+                config.node_id,
+                fs,
+                store,
+            )
+            .await?
+            .with_runtime(runtime.clone())
+            .with_clock(clock)
+            .with_audit(config.audit_log)
+            .with_hash_chain(config.hash_chain)
+            .with_record_cache(config.record_cache_bytes)
+            .with_encode_pool(config.encode_workers, config.encode_offload_bytes)
+            .with_latency_slos(config.append_slo, config.fsync_slo)
+            .with_key_coalescing(config.coalesce_keys)
+            .with_expiry_tracking(config.track_expiry)
+            .with_memory_budget(config.memory_budget.clone()),
+        );
+        if config.hash_chain {
+            manager.load_chain_head().await?;
+        }
+        manager.load_expiry_index().await?;
+        manager.set_next_lsn(recovery_info.last_lsn.map_or(1, |lsn| lsn + 1));
+        for (&namespace, &quota) in &config.namespace_quotas {
+            manager.quotas().set_quota(namespace, Some(quota));
+        }
+
+        let pending_recovery = Arc::new(Mutex::new(recovery_info.pending));
+        let mut tasks = Vec::new();
+        if config.resume_recovery_in_background && recovery_info.pending.is_some() {
+            tasks.push(runtime.spawn(Box::pin(finish_recovery(
+                manager.clone(),
+                pending_recovery.clone(),
+                config.clone(),
+                meter.clone(),
+            ))));
+        }
+        if let Some(interval) = config.scrub_interval {
+            tasks.push(runtime.spawn(Box::pin(scrub::run_scrubber(
+                manager.clone(),
+                meter.clone(),
+                config.node_id,
+                interval,
+            ))));
+        }
+        if let Some(interval) = config.compaction_interval {
+            tasks.push(runtime.spawn(Box::pin(compaction::run_compactor(
+                manager.clone(),
+                interval,
+                config.compaction_retain_segments,
+                config.compaction_min_dead_percent,
+            ))));
+        }
 
         Ok((
             Self {
-                manager: Arc::new(manager),
+                manager,
                 config,
+                meter,
+                tasks,
+                pending_recovery,
+                checkpoint: Arc::new(Mutex::new(last_checkpoint)),
+                meta,
+                lock,
             },
             recovery_info,
         ))
     }
 
+    /// Returns the replay work deferred by a bounded recovery, if any.
+    pub async fn pending_recovery(&self) -> Option<PendingRecovery> {
+        *self.pending_recovery.lock().await
+    }
+
+    /// Continues replaying records left over by a bounded recovery.
+    ///
+    /// Each call does at most one `recovery_budget` worth of work, so it can be
+    /// driven lazily or looped until the returned `pending` is `None`; see
+    /// also [`WalConfig::resume_recovery_in_background`]. Records appended
+    /// since open are not replayed.
+    pub async fn resume_recovery<F>(&self, mut replay: F) -> Result<RecoveryInfo, SegmentError>
+    where
+        F: FnMut(Record, Position) + Send,
+    {
+        resume_pending(
+            &self.manager,
+            &self.pending_recovery,
+            &self.config,
+            self.meter.clone(),
+            &mut replay,
+        )
+        .await
+    }
+
     /// Appends a record to the WAL.
     ///
     /// Returns the position where the record was written.
@@ -154,6 +508,21 @@ impl Wal {
         self.manager.append(record).await
     }
 
+    /// Appends a record only if the log still ends at `expected_tail`, as
+    /// returned by [`current_position`](Self::current_position).
+    ///
+    /// Fails with [`SegmentError::TailMoved`], carrying the actual tail, if
+    /// anything was appended since, so writers sharing the log can serialize
+    /// themselves optimistically: read the tail, decide, append, and on a
+    /// conflict catch up from the old tail and try again.
+    pub async fn append_if(
+        &self,
+        expected_tail: Position,
+        record: &Record,
+    ) -> Result<Position, SegmentError> {
+        self.manager.append_if(expected_tail, record).await
+    }
+
     /// Appends a batch of records to the WAL.
     ///
     /// This is more efficient than calling `append()` repeatedly because:
@@ -166,6 +535,28 @@ impl Wal {
         self.manager.append_batch(records).await
     }
 
+    /// Bulk-loads a stream of records with default settings, fsyncing once
+    /// at the end. See [`import`](crate::import).
+    pub async fn import<S>(&self, records: S) -> Result<ImportSummary, SegmentError>
+    where
+        S: futures_core::Stream<Item = Record>,
+    {
+        self.import_with(records, ImportConfig::default()).await
+    }
+
+    /// Bulk-loads a stream of records in chunks, for backfills where
+    /// per-record appends are too slow.
+    pub async fn import_with<S>(
+        &self,
+        records: S,
+        config: ImportConfig,
+    ) -> Result<ImportSummary, SegmentError>
+    where
+        S: futures_core::Stream<Item = Record>,
+    {
+        import::import(&self.manager, records, config).await
+    }
+
     /// Flushes buffered data to the OS (but doesn't fsync).
     pub async fn flush(&self) -> Result<(), SegmentError> {
         self.manager.flush().await
@@ -183,9 +574,187 @@ impl Wal {
         self.manager.current_position().await
     }
 
+    /// Returns the LSN that the next appended record will be assigned.
+    pub fn next_lsn(&self) -> u64 {
+        self.manager.next_lsn()
+    }
+
+    /// Returns a cloneable handle for appending, syncing and checkpointing.
+    /// See [`handle`](crate::handle).
+    pub fn writer(&self) -> WalWriter {
+        WalWriter::new(
+            self.manager.clone(),
+            self.checkpoint.clone(),
+            self.config.purge_on_checkpoint,
+        )
+    }
+
+    /// Returns a cloneable handle for reading and tailing the log. See
+    /// [`handle`](crate::handle).
+    pub fn read_handle(&self) -> WalReadHandle {
+        WalReadHandle::new(self.manager.clone())
+    }
+
+    /// Returns a server that streams this WAL to followers, reporting
+    /// through the WAL's meter. See [`replication`](crate::replication).
+    #[cfg(feature = "replication")]
+    pub fn replication_server(
+        &self,
+        config: crate::replication::ReplicationConfig,
+    ) -> crate::replication::ReplicationServer {
+        crate::replication::ReplicationServer::new(self.read_handle(), config, self.meter.clone())
+    }
+
+    /// Returns a token covering every record durable so far, for a follower
+    /// to wait on before serving a read. See [`session`](crate::session).
+    #[cfg(feature = "replication")]
+    pub async fn session_token(&self) -> Result<crate::session::SessionToken, SegmentError> {
+        let last = self.manager.last_record().await?;
+        let lsn = last.and_then(|(record, _)| record.lsn).unwrap_or(0);
+        Ok(crate::session::SessionToken::new(lsn))
+    }
+
+    /// Returns counts, sizes and latency percentiles collected since the WAL
+    /// was opened, without needing a [`Meter`].
+    pub async fn metrics(&self) -> WalMetrics {
+        self.manager.metrics().await
+    }
+
+    /// Watches appends for `window` and profiles them, for
+    /// [`WorkloadProfile::advise`] to recommend settings from.
+    ///
+    /// The records appended in the window are read back once it ends, which
+    /// first syncs the log so that all of them are durable. Fsync latency
+    /// is taken from [`metrics`](Self::metrics), so it covers everything
+    /// since the WAL was opened rather than just the window.
+    pub async fn observe_workload(
+        &self,
+        window: Duration,
+    ) -> Result<WorkloadProfile, SegmentError> {
+        let start = self.current_position().await;
+        self.manager.runtime().sleep(window).await;
+        let end = self.current_position().await;
+        self.sync().await?;
+
+        let mut observer = WorkloadObserver::new();
+        let mut reader = self.reader(start);
+        while let Some((record, position)) = reader.next_record().await? {
+            if position >= end {
+                break;
+            }
+            observer.observe(&record, record.encode().len() as u64);
+        }
+        let mut profile = observer.finish(window);
+        profile.fsync_latency = self.metrics().await.fsync_latency;
+        Ok(profile)
+    }
+
+    /// Returns how many records and bytes were appended to `namespace` since
+    /// the WAL was opened, how much of that is still in the log, and how
+    /// often the namespace was found at its quota. Read a single namespace
+    /// with [`WalReader::with_namespace`].
+    pub fn namespace_metrics(&self, namespace: u32) -> NamespaceMetrics {
+        self.manager.namespace_metrics(namespace)
+    }
+
+    /// Switches the fsync policy for subsequent appends, without reopening
+    /// the WAL.
+    ///
+    /// Switching to [`FsyncPolicy::Always`] also syncs whatever earlier
+    /// appends left pending, so every record appended so far is durable once
+    /// this returns. The policy is checked like [`WalConfig::fsync_policy`]
+    /// is on open.
+    pub async fn set_fsync_policy(&self, policy: FsyncPolicy) -> Result<(), SegmentError> {
+        config::check_fsync_policy(policy)?;
+        self.manager.set_fsync_policy(policy).await;
+        if policy == FsyncPolicy::Always {
+            self.manager.sync().await?;
+        }
+        self.reconfigured(
+            "Wal::set_fsync_policy",
+            format!("fsync_policy = {:?}", policy),
+        )
+        .await
+    }
+
+    /// Returns the fsync policy appends currently apply.
+    pub async fn fsync_policy(&self) -> FsyncPolicy {
+        self.manager.fsync_policy().await
+    }
+
+    /// Changes the size at which segments rotate, from the next append on.
+    ///
+    /// A smaller size can make the active segment rotate on the next append;
+    /// segments created afterwards are pre-allocated to the new size. The size
+    /// is checked like [`WalConfig::max_segment_size`] is on open.
+    pub async fn set_max_segment_size(&self, bytes: u64) -> Result<(), SegmentError> {
+        config::check_segment_size(bytes, self.config.max_record_size)?;
+        self.manager.set_max_segment_size(bytes).await;
+        self.reconfigured(
+            "Wal::set_max_segment_size",
+            format!("max_segment_size = {}", bytes),
+        )
+        .await
+    }
+
+    /// Sets the quota of `namespace` from the next append on, or removes it
+    /// with `None`. The quota is checked like those in
+    /// [`WalConfig::namespace_quotas`] are on open.
+    pub async fn set_namespace_quota(
+        &self,
+        namespace: u32,
+        quota: Option<NamespaceQuota>,
+    ) -> Result<(), SegmentError> {
+        if let Some(quota) = quota {
+            config::check_namespace_quota(&quota)?;
+        }
+        self.manager.quotas().set_quota(namespace, quota);
+        self.reconfigured(
+            "Wal::set_namespace_quota",
+            format!("namespace {} quota = {:?}", namespace, quota),
+        )
+        .await
+    }
+
+    /// Returns the quota of `namespace`, if it has one.
+    pub fn namespace_quota(&self, namespace: u32) -> Option<NamespaceQuota> {
+        self.manager.quotas().quota(namespace)
+    }
+
+    /// Changes how long the active segment may hold records before it rotates,
+    /// from the next append on. `None` rotates on size only.
+    pub async fn set_max_segment_age(&self, age: Option<Duration>) -> Result<(), SegmentError> {
+        config::check_segment_age(age)?;
+        self.manager.set_max_segment_age(age).await;
+        self.reconfigured(
+            "Wal::set_max_segment_age",
+            format!("max_segment_age = {:?}", age),
+        )
+        .await
+    }
+
+    /// Audits a setting changed through `source`.
+    async fn reconfigured(&self, source: &str, detail: String) -> Result<(), SegmentError> {
+        self.manager
+            .audit(AuditOperation::Reconfigure, source, detail)
+            .await
+    }
+
+    /// Returns the position up to which appended records are known to be durable.
+    pub async fn durable_position(&self) -> Position {
+        self.manager.durable_position().await
+    }
+
+    /// Returns the last durable record and its position, without scanning
+    /// the log in the common case.
+    pub async fn last_record(&self) -> Result<Option<(Record, Position)>, SegmentError> {
+        self.manager.last_record().await
+    }
+
     /// Reads records starting from the given position.
     ///
-    /// Returns an iterator that can be used to scan records.
+    /// Returns an iterator that can be used to scan records. It stops at the
+    /// end of that segment; use [`Wal::reader`] to read across segments.
     pub async fn read_from(
         &self,
         position: Position,
@@ -193,7 +762,115 @@ impl Wal {
         self.manager.read_from(position).await
     }
 
-    /// Returns the WAL configuration.
+    /// Like [`Wal::read_from`], with the given reader configuration.
+    pub async fn read_from_with(
+        &self,
+        position: Position,
+        config: ReaderConfig,
+    ) -> Result<crate::segment::SegmentReader, SegmentError> {
+        self.manager.read_from_with(position, config).await
+    }
+
+    /// Returns a reader that follows the log across segments from `position`
+    /// up to its durable end.
+    pub fn reader(&self, position: Position) -> WalReader {
+        self.reader_with(position, ReaderConfig::default())
+    }
+
+    /// Like [`Wal::reader`], with the given reader configuration.
+    pub fn reader_with(&self, position: Position, config: ReaderConfig) -> WalReader {
+        self.read_handle().reader_with(position, config)
+    }
+
+    /// Returns a reader starting at the first record whose LSN is at least
+    /// `lsn`, located through an in-memory LSN index rather than a scan of
+    /// the log. If no such record exists yet, the reader waits at the end of
+    /// the log for it.
+    ///
+    /// Fails with [`SegmentError::Purged`] if records from `lsn` on are no
+    /// longer complete because older segments were purged, and with
+    /// [`SegmentError::InvalidConfig`] if `lsn` is past `next_lsn()`.
+    pub async fn read_from_lsn(&self, lsn: u64) -> Result<WalReader, SegmentError> {
+        self.read_handle().read_from_lsn(lsn).await
+    }
+
+    /// Returns a reader over the records appended at or after `since` and
+    /// before `until`, for replaying the log around a point in time.
+    ///
+    /// An in-memory index of append timestamps picks where to start and,
+    /// once the log has moved past `until`, where to stop. This assumes
+    /// timestamps grow with the log, as they do when the WAL assigns them;
+    /// records stamped out of order by the caller or by a clock step may be
+    /// missed near the edges of the window.
+    pub async fn read_range(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> Result<WalReader, SegmentError> {
+        self.read_handle().read_range(since, until).await
+    }
+
+    /// Returns a reader over the records in `[from, to)` whose key starts
+    /// with `prefix`, for questions like "what happened to keys under
+    /// `user/42/`".
+    ///
+    /// Other records are skipped after a checksum check, without copying or
+    /// decompressing their values. As with [`Wal::reader`], only durable
+    /// records are returned.
+    pub fn scan_prefix(&self, prefix: impl Into<Bytes>, from: Position, to: Position) -> WalReader {
+        self.read_handle().scan_prefix(prefix, from, to)
+    }
+
+    /// Replays the sealed segments from `from` onwards several at a time,
+    /// sending their records to `sink` in batches.
+    ///
+    /// Meant for full replays where throughput matters more than latency; see
+    /// [`replay`](crate::replay) for how ordering can be traded for speed.
+    /// The summary's `end` is where a [`Wal::reader`] takes over.
+    pub async fn replay_parallel(
+        &self,
+        from: Position,
+        config: ReplayConfig,
+        sink: tokio::sync::mpsc::Sender<ReplayBatch>,
+    ) -> Result<ReplaySummary, SegmentError> {
+        replay::replay_parallel(self.manager.clone(), from, config, sink).await
+    }
+
+    /// Returns a reader that continues from a saved cursor.
+    ///
+    /// Fails with [`SegmentError::CursorGone`] if the cursor's position has
+    /// been purged or truncated away.
+    pub async fn resume_reader(&self, cursor: &Cursor) -> Result<WalReader, SegmentError> {
+        self.read_handle().resume_reader(cursor).await
+    }
+
+    /// Returns a tail that continues from a saved cursor.
+    ///
+    /// Fails with [`SegmentError::CursorGone`] if the cursor's position has
+    /// been purged or truncated away.
+    pub async fn resume_tail(&self, cursor: &Cursor) -> Result<WalTail, SegmentError> {
+        self.read_handle().resume_tail(cursor).await
+    }
+
+    /// Returns a tail that reads the log from `position` and then follows
+    /// new records as they become durable, like `tail -f`.
+    pub fn tail(&self, position: Position) -> WalTail {
+        self.read_handle().tail(position)
+    }
+
+    /// Returns a tail starting at the first record whose LSN is at least
+    /// `lsn`, then following new records as they become durable.
+    ///
+    /// Fails like [`Wal::read_from_lsn`] if records from `lsn` on have been
+    /// purged or `lsn` is past `next_lsn()`.
+    pub async fn tail_from_lsn(&self, lsn: u64) -> Result<WalTail, SegmentError> {
+        self.read_handle().tail_from_lsn(lsn).await
+    }
+
+    /// Returns the configuration the WAL was opened with.
+    ///
+    /// Settings changed on the open WAL, such as the fsync policy, are not
+    /// reflected here; they have their own getters.
     pub fn config(&self) -> &WalConfig {
         &self.config
     }
@@ -201,37 +878,506 @@ impl Wal {
     /// Deletes all segments before the given position.
     ///
     /// This is used for garbage collection after data has been compacted or
-    /// replicated. Returns the number of segments deleted.
+    /// replicated. Segments that open readers are still using are kept, along
+    /// with the segments after them, until a later call. Returns the number
+    /// of segments deleted.
     ///
     /// # Safety
     ///
     /// The caller must ensure that the data in these segments is no longer needed
     /// (e.g., it has been compacted into SSTables or safely replicated).
     pub async fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
-        self.manager.delete_segments_before(position).await
+        self.purge_segments_before(position, "Wal::delete_segments_before")
+            .await
+    }
+
+    /// Deletes segments as `delete_segments_before` does, on behalf of
+    /// `source` in the audit log.
+    pub(crate) async fn purge_segments_before(
+        &self,
+        position: Position,
+        source: &str,
+    ) -> Result<u64, SegmentError> {
+        self.manager.purge_before(position, source).await
+    }
+
+    /// Reports a retention pass that deleted `deleted_segments` segments
+    /// before `cutoff`.
+    pub(crate) fn emit_retention_enforced(&self, cutoff: u64, deleted_segments: u64) {
+        obs_emit!(
+            self.meter,
+            VizEvent::Wal(WalEvt {
+                node: self.config.node_id,
+                seg: cutoff,
+                kind: WalKind::RetentionEnforced { deleted_segments },
+            })
+        );
+    }
+
+    /// Discards every record at and after `position`, across segment boundaries.
+    ///
+    /// This resolves log conflicts in a consensus layer, or rolls back a bad
+    /// tail. `position` must be a record boundary, such as one returned by
+    /// `append` or a reader, and may not precede the last checkpoint. The
+    /// truncated log is fsynced before this returns, and `next_lsn()` rewinds
+    /// to the LSN of the first discarded record.
+    ///
+    /// Returns the number of bytes discarded.
+    pub async fn truncate_from(&self, position: Position) -> Result<u64, SegmentError> {
+        self.truncate(position, "Wal::truncate_from").await
+    }
+
+    async fn truncate(&self, position: Position, source: &str) -> Result<u64, SegmentError> {
+        let last_checkpoint = self.checkpoint.lock().await;
+        if last_checkpoint.is_some_and(|c| position < c.position) {
+            return Err(SegmentError::InvalidConfig(
+                "cannot truncate before the last checkpoint".to_string(),
+            ));
+        }
+
+        let mut pending = self.pending_recovery.lock().await;
+        let discarded = self.manager.truncate_from(position).await?;
+
+        // Deferred replay must not reach past the new end of the log
+        if let Some(resume_from) = pending.as_mut() {
+            let next = Position {
+                segment_id: resume_from.next_segment,
+                offset: resume_from.next_offset,
+            };
+            if position <= next {
+                *pending = None;
+            } else if position < resume_from.end {
+                resume_from.end = position;
+            }
+        }
+        drop(pending);
+        drop(last_checkpoint);
+        if discarded > 0 {
+            let detail = format!("from {}, {} bytes discarded", position, discarded);
+            self.manager
+                .audit(AuditOperation::Truncate, source, detail)
+                .await?;
+        }
+        Ok(discarded)
+    }
+
+    /// Discards the record with LSN `lsn` and everything after it.
+    ///
+    /// Locates the first record whose LSN is at least `lsn` and truncates
+    /// there, as `truncate_from` does. Returns 0 if no such record exists.
+    pub async fn truncate_from_lsn(&self, lsn: u64) -> Result<u64, SegmentError> {
+        match self.manager.position_of_lsn(lsn).await? {
+            Some(position) => self.truncate(position, "Wal::truncate_from_lsn").await,
+            None => Ok(0),
+        }
+    }
+
+    /// Durably records a checkpoint at `position`.
+    ///
+    /// A checkpoint marks every record before `position` as reflected in the
+    /// caller's own durable state, so replay can resume there after a restart.
+    /// It survives reopening and is returned by `last_checkpoint()`. With
+    /// `purge_on_checkpoint`, segments lying wholly before `position` are
+    /// deleted once the checkpoint is durable.
+    ///
+    /// Checkpoints only move forward, and cannot point past the current write
+    /// position.
+    pub async fn checkpoint(&self, position: Position) -> Result<Checkpoint, SegmentError> {
+        self.writer().checkpoint(position).await
+    }
+
+    /// Returns the most recent checkpoint, if one has been recorded.
+    pub async fn last_checkpoint(&self) -> Option<Checkpoint> {
+        *self.checkpoint.lock().await
+    }
+
+    /// Returns the metadata blobs stored in the WAL directory.
+    ///
+    /// These hold small values that must be replaced atomically and survive
+    /// crashes, like a consensus layer's current term and vote. They move
+    /// with `migrate_to()` and are copied by `backup_to()`.
+    pub fn meta(&self) -> &MetaStore {
+        &self.meta
+    }
+
+    /// Makes appends require `lease`: once it expires or is lost, they fail
+    /// with [`SegmentError::LeaseExpired`].
+    ///
+    /// The lease's epoch is recorded as the WAL's fencing epoch, in the
+    /// `fencing-epoch` metadata blob. A lease from an older epoch than the
+    /// one recorded fails with [`SegmentError::Fenced`]: its holder has been
+    /// superseded. See [`crate::lease`].
+    pub async fn attach_lease(&self, lease: &Lease) -> Result<(), SegmentError> {
+        lease.check()?;
+        let epoch = lease.epoch();
+        match self.fencing_epoch().await? {
+            Some(current) if epoch < current => {
+                return Err(SegmentError::Fenced { epoch, current })
+            }
+            Some(current) if epoch == current => {}
+            _ => {
+                self.meta
+                    .put(lease::FENCING_EPOCH_META, &epoch.to_le_bytes())
+                    .await?
+            }
+        }
+        self.manager.set_lease(Some(lease.state().clone()));
+        Ok(())
+    }
+
+    /// Lets appends go ahead without a lease again, for example after
+    /// handing the WAL over with [`Lease::release`].
+    pub fn detach_lease(&self) {
+        self.manager.set_lease(None);
+    }
+
+    /// Returns the epoch of the newest lease ever attached, or `None` if the
+    /// WAL has never had one.
+    pub async fn fencing_epoch(&self) -> Result<Option<u64>, SegmentError> {
+        match self.meta.get(lease::FENCING_EPOCH_META).await? {
+            Some(value) => lease::decode_epoch(&value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Verifies the CRC of every record in every sealed segment.
+    ///
+    /// This is the same pass the background scrubber runs when
+    /// `scrub_interval` is configured. Corrupt segments are reported (and
+    /// emitted as `CorruptionDetected` events) but never modified.
+    pub async fn scrub(&self) -> Result<ScrubReport, SegmentError> {
+        scrub::scrub_sealed_segments(&self.manager, self.meter.as_ref(), self.config.node_id).await
+    }
+
+    /// Rewrites old sealed segments without the records that later ones
+    /// overwrote or deleted.
+    ///
+    /// This is the same pass the background compactor runs when
+    /// `compaction_interval` is configured. Segments up to
+    /// `compaction_retain_segments` before the active one are considered,
+    /// and those in which at least `compaction_min_dead_percent` of the bytes
+    /// are superseded are rewritten. Positions inside a rewritten segment no
+    /// longer point at records; see [`compaction`] for the details.
+    pub async fn compact(&self) -> Result<CompactionReport, SegmentError> {
+        if self.config.hash_chain {
+            return Err(SegmentError::InvalidConfig(
+                "compaction would break the hash chain".to_string(),
+            ));
+        }
+        let horizon =
+            compaction::compaction_horizon(&self.manager, self.config.compaction_retain_segments)
+                .await;
+        compaction::compact_sealed_segments(
+            &self.manager,
+            horizon,
+            self.config.compaction_min_dead_percent,
+            "Wal::compact",
+        )
+        .await
+    }
+
+    /// Returns the keys whose TTL has run out by the WAL's clock, oldest
+    /// expiry first, leaving out keys written again since without a TTL or
+    /// deleted. Needs `track_expiry`; see [`expiry`](crate::expiry).
+    pub fn expired_keys(&self) -> Result<Vec<ExpiredKey>, SegmentError> {
+        let expiry = self.expiry_index()?;
+        Ok(expiry.expired(self.manager.clock().system_time()))
+    }
+
+    /// Appends a delete for each expired key, as listed by
+    /// [`expired_keys`](Self::expired_keys), that `hook` returns true for,
+    /// and returns the keys deleted.
+    ///
+    /// The hook runs before the key's delete is appended, so the embedder
+    /// can drop the key from its own state or skip it. A key written again
+    /// while this runs is not deleted.
+    pub async fn tombstone_expired<F>(&self, mut hook: F) -> Result<Vec<ExpiredKey>, SegmentError>
+    where
+        F: FnMut(&ExpiredKey) -> bool,
+    {
+        let expiry = self.expiry_index()?;
+        let mut deleted = Vec::new();
+        for expired in expiry.expired(self.manager.clock().system_time()) {
+            if !expiry.is_current(&expired) || !hook(&expired) {
+                continue;
+            }
+            let mut tombstone = Record::delete(expired.key.clone());
+            tombstone.namespace = expired.namespace;
+            // Only delete if nothing was appended since the key was checked
+            loop {
+                let tail = self.manager.current_position().await;
+                if !expiry.is_current(&expired) {
+                    break;
+                }
+                match self.manager.append_if(tail, &tombstone).await {
+                    Ok(_) => {
+                        deleted.push(expired);
+                        break;
+                    }
+                    Err(SegmentError::TailMoved { .. }) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    fn expiry_index(&self) -> Result<&ExpiryIndex, SegmentError> {
+        self.manager.expiry().ok_or_else(|| {
+            SegmentError::InvalidConfig("expiry tracking needs track_expiry".to_string())
+        })
+    }
+
+    /// Returns the last appended record of a hash-chained log and its
+    /// SHA-256, which stands for every record up to it. `None` if
+    /// `hash_chain` is off or the log is empty.
+    ///
+    /// Call `sync()` first if the head is attested, so that it cannot be
+    /// lost in a crash. See [`chain`] for how the head is used.
+    pub fn chain_head(&self) -> Option<ChainHead> {
+        self.manager.last_chain_head()
+    }
+
+    /// Checks the hash chain of the durable records, and that the log still
+    /// holds `attested`, a head returned by `chain_head()` earlier.
+    ///
+    /// Reads every segment, but only decodes record framing, not values.
+    pub async fn verify_chain(
+        &self,
+        attested: Option<ChainHead>,
+    ) -> Result<ChainReport, SegmentError> {
+        chain::verify_chain(&self.manager, attested).await
+    }
+
+    /// Writes a consistent backup of the WAL into `dest_dir` while appends continue.
+    ///
+    /// The backup holds all sealed segments plus the active segment up to
+    /// `durable_position()`, so it never contains records that could still be
+    /// lost in a crash. Call `sync()` first to include everything appended so far.
+    /// The backup directory can be opened directly with `Wal::open`, and
+    /// carries the last checkpoint if the backup reaches it, and the current
+    /// metadata blobs.
+    pub async fn backup_to(&self, dest_dir: impl AsRef<Path>) -> Result<BackupInfo, SegmentError> {
+        let dest_dir = dest_dir.as_ref();
+        let info = self.manager.backup_to(dest_dir).await?;
+        if let Some(last) = *self.checkpoint.lock().await {
+            if last.position <= info.position {
+                checkpoint::write_checkpoint(self.manager.fs().as_ref(), dest_dir, &last).await?;
+            }
+        }
+        self.meta.copy_to(dest_dir).await?;
+        Ok(info)
+    }
+
+    /// Writes the durable records of the WAL into a new WAL in `dest_dir`,
+    /// with segments numbered from zero, dropping superseded records or
+    /// recompressing as `options` asks. See [`copy`] for what is kept.
+    ///
+    /// Call `sync()` first to include everything appended so far.
+    /// `dest_dir` must not already contain segments.
+    pub async fn copy_to(
+        &self,
+        dest_dir: impl AsRef<Path>,
+        options: CopyOptions,
+    ) -> Result<CopyReport, SegmentError> {
+        self.manager.require_segment_files("copy")?;
+        let dest_dir = dest_dir.as_ref();
+        let fs = self.manager.fs().clone();
+        fs.create_dir_all(dest_dir).await?;
+        if !list_segment_ids(fs.as_ref(), dest_dir).await?.is_empty() {
+            return Err(SegmentError::InvalidConfig(format!(
+                "copy target {} already contains WAL segments",
+                dest_dir.display()
+            )));
+        }
+        let config = WalConfig {
+            dir: dest_dir.to_path_buf(),
+            max_segment_size: self.config.max_segment_size,
+            max_record_size: self.config.max_record_size,
+            preallocate: self.config.preallocate,
+            hash_chain: self.config.hash_chain,
+            node_id: self.config.node_id,
+            ..WalConfig::default()
+        };
+        let (dest, _) = Self::open_inner(
+            config,
+            Arc::new(NoopMeter),
+            self.manager.runtime().clone(),
+            fs,
+            None,
+            self.manager.clock().clone(),
+            None,
+        )
+        .await?;
+        let copied = copy::copy_records(&self.manager, &dest.manager, &options).await;
+        let closed = dest.close().await;
+        let report = copied?;
+        closed?;
+        Ok(report)
+    }
+
+    /// Moves the WAL to `new_dir` while it stays open for appends.
+    ///
+    /// Sealed segments are hard-linked (or copied when `new_dir` is on another
+    /// volume), then the active segment is synced and copied under the writer lock
+    /// and subsequent appends go to `new_dir`. The old segment files are removed
+    /// once the new directory is durable, and the last checkpoint and metadata
+    /// blobs move with them, as does the directory lock. `new_dir` must not
+    /// already contain segments or be locked by another WAL. If the move fails
+    /// before the new directory is complete, the WAL stays where it was and
+    /// the move can be retried.
+    ///
+    /// The old directory keeps a `MOVED` marker naming `new_dir`, so opening
+    /// the WAL there, whether after a crash partway through the move or with a
+    /// configuration that was never updated, continues in `new_dir`. The
+    /// marker is only meant to bridge the move: once configuration names
+    /// `new_dir`, remove it with [`Wal::clear_migration_marker`].
+    ///
+    /// Returns the number of segments migrated.
+    pub async fn migrate_to(&mut self, new_dir: impl AsRef<Path>) -> Result<u64, SegmentError> {
+        let new_dir = new_dir.as_ref();
+        let fs = self.manager.fs().clone();
+        fs.create_dir_all(new_dir).await?;
+        let new_lock = acquire_lock(self.manager.runtime().as_ref(), &fs, new_dir).await?;
+        // Held throughout so writer handles cannot checkpoint mid-move
+        let last_checkpoint = self.checkpoint.lock().await;
+        let migrated = self.manager.migrate_to(new_dir).await?;
+        if let Some(last) = *last_checkpoint {
+            checkpoint::write_checkpoint(fs.as_ref(), new_dir, &last).await?;
+            checkpoint::remove_checkpoint(fs.as_ref(), &self.config.dir).await?;
+        }
+        self.meta.move_to(new_dir).await?;
+        audit::move_log(fs.as_ref(), &self.config.dir, new_dir).await?;
+        let detail = format!(
+            "from {} to {}, {} segments",
+            self.config.dir.display(),
+            new_dir.display(),
+            migrated
+        );
+        self.manager
+            .audit(AuditOperation::Migrate, "Wal::migrate_to", detail)
+            .await?;
+        if let Some(old_lock) = std::mem::replace(&mut self.lock, new_lock) {
+            old_lock.remove()?;
+        }
+        self.config.dir = new_dir.to_path_buf();
+        Ok(migrated)
+    }
+
+    /// Removes the `MOVED` marker [`Wal::migrate_to`] left in `old_dir`,
+    /// first finishing the migration it records if a crash cut it short.
+    /// Returns whether there was a marker.
+    ///
+    /// Afterwards `old_dir` holds nothing of the WAL and can be deleted;
+    /// opening a WAL there starts a new, empty log rather than following the
+    /// move, so update configuration before clearing.
+    pub async fn clear_migration_marker(old_dir: impl AsRef<Path>) -> Result<bool, SegmentError> {
+        let fs: Arc<dyn Fs> = Arc::new(LocalFs);
+        moved::clear(&fs, old_dir.as_ref()).await
     }
 
     /// Gracefully closes the WAL, ensuring all data is synced and finalized.
     ///
     /// This performs:
-    /// 1. Final fsync of any pending data
-    /// 2. Finalization of the current segment (truncate to actual size)
+    /// 1. Stopping the background scrubber and compactor
+    /// 2. Final flush and fsync of any pending data
+    /// 3. Finalization of the current segment (truncate to actual size)
+    /// 4. Waiting for in-flight seal verifications
+    /// 5. Releasing the directory lock
     ///
-    /// After calling this, the WAL should not be used anymore.
-    pub async fn close(self) -> Result<(), SegmentError> {
-        // Sync any pending data
-        self.manager.sync().await?;
+    /// Every step runs even if an earlier one fails, and the first error is
+    /// returned. Readers and tailers created from this WAL keep working on the
+    /// segments that exist, but see no further appends; appends through
+    /// [`WalWriter`] handles fail with [`SegmentError::Closed`].
+    pub async fn close(mut self) -> Result<(), SegmentError> {
+        for task in self.tasks.drain(..) {
+            task.abort();
+            // The scrubber only reads, and the compactor and recovery swap
+            // each segment in with a rename, so cancelling any mid-pass is
+            // harmless
+            task.join().await;
+        }
+        self.manager.close();
 
-        // Finalize current segment (truncate to actual written size)
-        self.manager.finalize_current().await?;
+        let synced = self.manager.sync().await;
+        let finalized = self.manager.finalize_current().await;
+        self.manager.wait_for_background().await;
+        drop(self.lock.take());
 
-        Ok(())
+        synced.and(finalized)
+    }
+}
+
+/// Does one budget's worth of the replay left in `pending`, if any.
+async fn resume_pending(
+    manager: &SegmentManager,
+    pending: &Mutex<Option<PendingRecovery>>,
+    config: &WalConfig,
+    meter: Arc<dyn Meter>,
+    replay: &mut (dyn FnMut(Record, Position) + Send),
+) -> Result<RecoveryInfo, SegmentError> {
+    let mut pending = pending.lock().await;
+    let Some(resume_from) = *pending else {
+        return Ok(RecoveryInfo::default());
+    };
+
+    let info = recovery::resume_recovery_on(
+        manager.fs().as_ref(),
+        manager.store().as_ref(),
+        &config.dir,
+        meter,
+        config.node_id,
+        &config.recovery_options(manager.clock().system_time()),
+        &resume_from,
+        replay,
+    )
+    .await?;
+    // Repaired segments were rewritten under the open WAL
+    let rewritten = info
+        .gaps
+        .iter()
+        .map(|gap| gap.segment_id)
+        .chain(info.truncation_points.iter().map(|p| p.segment_id));
+    for segment_id in rewritten {
+        manager.forget_segment_contents(segment_id).await;
+    }
+    *pending = info.pending;
+    Ok(info)
+}
+
+/// Resumes a bounded recovery until nothing is left pending.
+///
+/// A failed step ends the task and stays pending, so the next call to
+/// [`Wal::resume_recovery`] retries it and reports the error.
+async fn finish_recovery(
+    manager: Arc<SegmentManager>,
+    pending: Arc<Mutex<Option<PendingRecovery>>>,
+    config: WalConfig,
+    meter: Arc<dyn Meter>,
+) {
+    loop {
+        match resume_pending(&manager, &pending, &config, meter.clone(), &mut |_, _| {}).await {
+            Ok(info) if info.pending.is_some() => {}
+            _ => return,
+        }
     }
 }
 
+/// Locks `dir` as blocking work on `runtime`, if `fs` supports locking.
+async fn acquire_lock(
+    runtime: &dyn Runtime,
+    fs: &Arc<dyn Fs>,
+    dir: &Path,
+) -> Result<Option<DirLock>, SegmentError> {
+    let fs = fs.clone();
+    let dir = dir.to_path_buf();
+    runtime::run_blocking(runtime, move || fs.lock_dir(&dir)).await?
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::quota::QuotaEnforcement;
     use crate::record::Record;
     use tempfile::TempDir;
 
@@ -453,25 +1599,194 @@ mod tests {
             ..Default::default()
         };
         assert!(Wal::open(config).await.is_err());
+
+        // Errors name the setting at fault
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            max_record_size: Some(2 * 1024 * 1024),
+            ..Default::default()
+        };
+        match Wal::open(config).await {
+            Err(SegmentError::Config(err)) => assert_eq!(err.field(), Some("max_segment_size")),
+            other => panic!("expected a config error, got {:?}", other.map(|_| ())),
+        }
     }
 
     #[tokio::test]
-    async fn test_wal_graceful_close() {
+    async fn test_wal_set_fsync_policy() {
         let temp_dir = TempDir::new().unwrap();
         let config = WalConfig {
             dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
             ..Default::default()
         };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let record = Record::put(b"key".as_slice(), b"value".as_slice());
 
-        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        wal.append(&record).await.unwrap();
+        assert!(wal.durable_position().await < wal.current_position().await);
 
-        // Write some records
-        for i in 0..5 {
-            let key = format!("key{}", i);
+        // Tightening the policy syncs what is pending, and later appends too
+        wal.set_fsync_policy(FsyncPolicy::Always).await.unwrap();
+        assert_eq!(wal.fsync_policy().await, FsyncPolicy::Always);
+        assert_eq!(wal.durable_position().await, wal.current_position().await);
+        wal.append(&record).await.unwrap();
+        assert_eq!(wal.durable_position().await, wal.current_position().await);
+
+        wal.set_fsync_policy(FsyncPolicy::Os).await.unwrap();
+        wal.append(&record).await.unwrap();
+        assert!(wal.durable_position().await < wal.current_position().await);
+
+        let zero_window = FsyncPolicy::Batch(Duration::ZERO);
+        match wal.set_fsync_policy(zero_window).await {
+            Err(SegmentError::Config(err)) => assert_eq!(err.field(), Some("fsync_policy")),
+            other => panic!("expected a config error, got {:?}", other),
+        }
+        assert_eq!(wal.fsync_policy().await, FsyncPolicy::Os);
+    }
+
+    #[tokio::test]
+    async fn test_wal_runtime_rotation_settings() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let value = vec![0u8; 100 * 1024];
+
+        // Shrinking the segment size rotates on the next append that overflows
+        for i in 0..5 {
+            let record = Record::put(format!("key{}", i), value.clone());
+            wal.append(&record).await.unwrap();
+        }
+        assert_eq!(wal.current_position().await.segment_id, 0);
+        wal.set_max_segment_size(1024 * 1024).await.unwrap();
+        for i in 5..15 {
+            let record = Record::put(format!("key{}", i), value.clone());
+            wal.append(&record).await.unwrap();
+        }
+        assert_eq!(wal.current_position().await.segment_id, 1);
+
+        // An old enough segment rotates even though it has room
+        wal.set_max_segment_age(Some(Duration::from_millis(50)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let position = wal
+            .append(&Record::put(b"aged".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(position.segment_id, 2);
+
+        wal.set_max_segment_age(None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let position = wal
+            .append(&Record::put(b"young".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(position.segment_id, 2);
+
+        assert!(matches!(
+            wal.set_max_segment_size(512).await,
+            Err(SegmentError::Config(_))
+        ));
+        assert!(matches!(
+            wal.set_max_segment_age(Some(Duration::ZERO)).await,
+            Err(SegmentError::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wal_mock_clock_drives_age_rotation_and_ttls() {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let builder = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Always)
+            .preallocate(false)
+            .max_segment_age(Duration::from_secs(60))
+            .skip_expired_on_replay(true)
+            .clock(clock.clone());
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let record = Record::put_with_ttl("short", "v", Duration::from_secs(10));
+        wal.append(&record).await.unwrap();
+        wal.append(&Record::put("long", "v")).await.unwrap();
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let mut reader = wal.read_from(start).await.unwrap();
+        let (stamped, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(stamped.timestamp, Some(clock.system_time()));
+
+        // Real time passing does not age the segment, the clock does
+        assert_eq!(wal.current_position().await.segment_id, 0);
+        clock.advance(Duration::from_secs(60));
+        let position = wal.append(&Record::put("aged", "v")).await.unwrap();
+        assert_eq!(position.segment_id, 1);
+        wal.close().await.unwrap();
+
+        let mut keys = Vec::new();
+        builder
+            .open_with_replay(|record, _| keys.push(record.key))
+            .await
+            .unwrap();
+        assert_eq!(keys, vec!["long", "aged"]);
+    }
+
+    #[tokio::test]
+    async fn test_wal_rejects_oversized_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_record_size: Some(4096),
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let small = Record::put(b"small".as_slice(), vec![0u8; 1024]);
+        let large = Record::put(b"large".as_slice(), vec![0u8; 8192]);
+        wal.append(&small).await.unwrap();
+        assert!(matches!(
+            wal.append(&large).await,
+            Err(SegmentError::RecordTooLarge { max: 4096, .. })
+        ));
+        // A batch with one oversized record writes nothing
+        assert!(matches!(
+            wal.append_batch(&[small.clone(), large]).await,
+            Err(SegmentError::RecordTooLarge { .. })
+        ));
+        assert_eq!(wal.next_lsn(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wal_graceful_close() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+
+        // Write some records
+        for i in 0..5 {
+            let key = format!("key{}", i);
             let record = Record::put(bytes::Bytes::from(key), b"value".as_slice());
             wal.append(&record).await.unwrap();
         }
 
+        // The directory is locked while the WAL is open
+        assert!(matches!(
+            Wal::open(config.clone()).await,
+            Err(SegmentError::Locked(_))
+        ));
+
         // Gracefully close
         wal.close().await.unwrap();
 
@@ -516,4 +1831,1899 @@ mod tests {
         let (_wal2, recovery_info) = Wal::open(config).await.unwrap();
         assert_eq!(recovery_info.valid_records, 100);
     }
+
+    #[tokio::test]
+    async fn test_wal_open_with_replay() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let mut written = Vec::new();
+        {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![7u8; 100 * 1024];
+            for i in 0..15 {
+                let key = format!("key{}", i);
+                let record = Record::put(bytes::Bytes::from(key), value.clone());
+                written.push(wal.append(&record).await.unwrap());
+            }
+            wal.close().await.unwrap();
+        }
+
+        let mut replayed = Vec::new();
+        let (_wal, info) = Wal::open_with_replay(config, |record, pos| {
+            replayed.push((record.key, pos));
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(info.valid_records, 15);
+        let positions: Vec<Position> = replayed.iter().map(|(_, pos)| *pos).collect();
+        assert_eq!(positions, written);
+        assert_eq!(replayed[0].0, "key0");
+        assert_eq!(replayed[14].0, "key14");
+    }
+
+    #[tokio::test]
+    async fn test_wal_compact_survives_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            compaction_retain_segments: 1,
+            ..Default::default()
+        };
+
+        let report = {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![7u8; 100 * 1024];
+            for round in 0..10 {
+                for key in ["a", "b", "c"] {
+                    let record = Record::put(key, value.clone());
+                    wal.append(&record.with_namespace(round % 2)).await.unwrap();
+                }
+            }
+            let report = wal.compact().await.unwrap();
+            wal.close().await.unwrap();
+            report
+        };
+        assert!(report.segments_compacted > 0);
+        assert!(report.bytes_reclaimed > 0);
+
+        let mut lsns = Vec::new();
+        let (_wal, info) = Wal::open_with_replay(config, |record, _| {
+            lsns.push(record.lsn.unwrap());
+        })
+        .await
+        .unwrap();
+        assert_eq!(info.valid_records, 30 - report.records_dropped);
+        assert!(lsns.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(lsns.last(), Some(&30));
+    }
+
+    #[tokio::test]
+    async fn test_wal_point_in_time_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        for i in 0..3 {
+            let key = format!("good{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), "v"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(wal.next_lsn(), 4);
+        // Records stamped after the cutoff represent the bad deploy
+        let cutoff = std::time::SystemTime::now();
+        for i in 0..2 {
+            let key = format!("bad{}", i);
+            let record = Record::put(bytes::Bytes::from(key), "v")
+                .with_timestamp(cutoff + Duration::from_secs(60));
+            wal.append(&record).await.unwrap();
+        }
+        wal.close().await.unwrap();
+
+        let config = WalConfig {
+            recovery_target: Some(RecoveryTarget::Timestamp(cutoff)),
+            truncate_after_target: true,
+            ..config
+        };
+        let mut keys = Vec::new();
+        let (wal, info) = Wal::open_with_replay(config, |record, _| keys.push(record.key))
+            .await
+            .unwrap();
+
+        assert_eq!(keys, vec!["good0", "good1", "good2"]);
+        assert_eq!(info.last_lsn, Some(3));
+        assert_eq!(wal.next_lsn(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_wal_bounded_recovery_resumes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![1u8; 100 * 1024];
+            for i in 0..25 {
+                let key = format!("key{}", i);
+                wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap();
+            }
+            wal.close().await.unwrap();
+        }
+
+        let config = WalConfig {
+            recovery_budget: RecoveryBudget {
+                max_bytes: Some(1),
+                max_duration: None,
+            },
+            ..config
+        };
+        let mut lsns = Vec::new();
+        let (wal, info) = Wal::open_with_replay(config, |record, _| lsns.push(record.lsn.unwrap()))
+            .await
+            .unwrap();
+
+        // Only the first record was replayed, but the tail is ready for appends
+        let pending = info.pending.unwrap();
+        assert_eq!(pending.next_segment, 0);
+        assert!(pending.next_offset > 0);
+        assert_eq!(lsns, [1]);
+        assert_eq!(wal.next_lsn(), 26);
+        wal.append(&Record::put("new", "v")).await.unwrap();
+
+        while wal.pending_recovery().await.is_some() {
+            wal.resume_recovery(|record, _| lsns.push(record.lsn.unwrap()))
+                .await
+                .unwrap();
+        }
+        assert_eq!(lsns, (1..=25).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_wal_resumed_skip_moves_later_records() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let mut positions = Vec::new();
+        {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![1u8; 100 * 1024];
+            for i in 0..25 {
+                let key = format!("key{}", i);
+                let position = wal
+                    .append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap();
+                positions.push(position);
+            }
+            wal.close().await.unwrap();
+        }
+
+        // Damage the second record of segment 1
+        let first = positions.iter().position(|p| p.segment_id == 1).unwrap();
+        let (bad, after) = (positions[first + 1], positions[first + 2]);
+        let path = crate::segment::segment_path(temp_dir.path(), 1);
+        let mut data = std::fs::read(&path).unwrap();
+        data[bad.offset as usize + 64] ^= 0xFF;
+        std::fs::write(&path, &data).unwrap();
+
+        let config = WalConfig {
+            recovery_mode: RecoveryMode::SkipBadRecords,
+            recovery_budget: RecoveryBudget {
+                max_bytes: Some(1),
+                max_duration: None,
+            },
+            ..config
+        };
+        let (wal, info) = Wal::open(config).await.unwrap();
+        assert_eq!(info.pending.unwrap().next_segment, 0);
+
+        // Cache a descriptor for the damaged file before it is rewritten
+        let mut reader = wal.read_from(positions[first]).await.unwrap();
+        reader.next_record().await.unwrap().unwrap();
+
+        let mut resumed = RecoveryInfo::default();
+        while wal.pending_recovery().await.is_some() {
+            let info = wal.resume_recovery(|_, _| {}).await.unwrap();
+            resumed.gaps.extend(info.gaps);
+        }
+        assert_eq!(resumed.gaps.len(), 1);
+        let gap = &resumed.gaps[0];
+        assert_eq!(gap.segment_id, 1);
+
+        // The pre-recovery position no longer names the record after the gap
+        let mut reader = wal.read_from(after).await.unwrap();
+        if let Some((record, _)) = reader.next_record().await.unwrap() {
+            assert_ne!(record.lsn, Some(first as u64 + 3));
+        }
+
+        // Looked up again by LSN, it sits `gap.len` bytes earlier
+        let mut reader = wal.read_from_lsn(first as u64 + 3).await.unwrap();
+        let (record, position) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.lsn, Some(first as u64 + 3));
+        assert_eq!(position.segment_id, 1);
+        assert_eq!(position.offset, after.offset - gap.len);
+    }
+
+    #[tokio::test]
+    async fn test_wal_finishes_recovery_in_background() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let mut positions = Vec::new();
+        {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![1u8; 100 * 1024];
+            for i in 0..25 {
+                let key = format!("key{}", i);
+                let position = wal
+                    .append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap();
+                positions.push(position);
+            }
+            wal.close().await.unwrap();
+        }
+
+        // Damage a record in segment 1, which only deferred recovery reaches
+        let bad = positions.iter().find(|p| p.segment_id == 1).unwrap();
+        let path = crate::segment::segment_path(temp_dir.path(), 1);
+        let mut data = std::fs::read(&path).unwrap();
+        data[bad.offset as usize + 64] ^= 0xFF;
+        std::fs::write(&path, &data).unwrap();
+
+        let config = WalConfig {
+            recovery_mode: RecoveryMode::SkipBadRecords,
+            recovery_budget: RecoveryBudget {
+                max_bytes: Some(1),
+                max_duration: None,
+            },
+            resume_recovery_in_background: true,
+            ..config
+        };
+
+        // The task's records would never reach a replay callback
+        assert!(matches!(
+            Wal::open_with_replay(config.clone(), |_, _| {}).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+
+        let (wal, info) = Wal::open(config).await.unwrap();
+        assert!(info.pending.is_some());
+        for _ in 0..200 {
+            if wal.pending_recovery().await.is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(wal.pending_recovery().await, None);
+        assert!(std::fs::metadata(&path).unwrap().len() < data.len() as u64);
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_writes_recovery_reports() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            recovery_reports_kept: 2,
+            ..Default::default()
+        };
+
+        for _ in 0..3 {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            wal.append(&Record::put("k", "v")).await.unwrap();
+            wal.close().await.unwrap();
+        }
+
+        let reports: Vec<_> = std::fs::read_dir(temp_dir.path().join(report::REPORT_DIR))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(reports.len(), 2);
+        let newest = reports.iter().max().unwrap();
+        let contents = std::fs::read_to_string(newest).unwrap();
+        assert!(contents.contains("\"valid_records\":2"));
+    }
+
+    #[tokio::test]
+    async fn test_wal_recovery_uses_seals() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            seal_segments: true,
+            ..Default::default()
+        };
+
+        {
+            let (wal, _) = Wal::open(config.clone()).await.unwrap();
+            let value = vec![2u8; 100 * 1024];
+            for i in 0..25 {
+                let key = format!("key{}", i);
+                wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap();
+            }
+            wal.close().await.unwrap();
+
+            // Seals are written in the background after rotation
+            for _ in 0..200 {
+                if (0..2).all(|id| crate::seal::seal_path(temp_dir.path(), id).exists()) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        let (wal, info) = Wal::open(config).await.unwrap();
+        assert_eq!(info.sealed_segments_trusted, 2);
+        assert_eq!(info.valid_records, 25);
+        assert_eq!(wal.next_lsn(), 26);
+    }
+
+    #[tokio::test]
+    async fn test_wal_migrate_to() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_dir = temp_dir.path().join("old");
+        let new_dir = temp_dir.path().join("new");
+        let config = WalConfig {
+            dir: old_dir.clone(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let (mut wal, _) = Wal::open(config).await.unwrap();
+
+        // Spread records over several segments
+        let value = vec![0u8; 100 * 1024];
+        for i in 0..25 {
+            let key = format!("key{}", i);
+            let record = Record::put(bytes::Bytes::from(key), value.clone());
+            wal.append(&record).await.unwrap();
+        }
+
+        let migrated = wal.migrate_to(&new_dir).await.unwrap();
+        assert!(migrated >= 3);
+        assert_eq!(wal.config().dir, new_dir);
+
+        // New appends land in the new directory
+        let record = Record::put(b"after".as_slice(), b"migration".as_slice());
+        wal.append(&record).await.unwrap();
+        wal.close().await.unwrap();
+
+        // Only the forwarding marker is left behind
+        let old_entries: Vec<_> = std::fs::read_dir(&old_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(old_entries, [moved::MOVED_FILE]);
+
+        let config = WalConfig {
+            dir: new_dir.clone(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, recovery_info) = Wal::open(config).await.unwrap();
+        assert_eq!(recovery_info.valid_records, 26);
+        assert!(!recovery_info.corruption_detected);
+        wal.close().await.unwrap();
+
+        // A configuration still naming the old directory follows the marker
+        let config = WalConfig {
+            dir: old_dir.clone(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, recovery_info) = Wal::open(config).await.unwrap();
+        assert_eq!(wal.config().dir, new_dir);
+        assert_eq!(recovery_info.valid_records, 26);
+        wal.close().await.unwrap();
+
+        // Clearing the marker empties the old directory for good
+        assert!(Wal::clear_migration_marker(&old_dir).await.unwrap());
+        assert!(!Wal::clear_migration_marker(&old_dir).await.unwrap());
+        assert_eq!(std::fs::read_dir(&old_dir).unwrap().count(), 0);
+        std::fs::remove_dir(&old_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_migrate_to_rejects_non_empty_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join("target");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("000000.wal"), b"").unwrap();
+
+        let config = WalConfig {
+            dir: temp_dir.path().join("wal"),
+            ..Default::default()
+        };
+        let (mut wal, _) = Wal::open(config.clone()).await.unwrap();
+
+        assert!(wal.migrate_to(&target).await.is_err());
+        assert!(wal.migrate_to(&config.dir).await.is_err());
+        assert_eq!(wal.config().dir, config.dir);
+    }
+
+    #[tokio::test]
+    async fn test_wal_backup_to_durable_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().join("wal"),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        // Durable records spanning a rotation
+        let value = vec![7u8; 100 * 1024];
+        for i in 0..15 {
+            let key = format!("key{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                .await
+                .unwrap();
+        }
+        wal.sync().await.unwrap();
+        let durable = wal.durable_position().await;
+        assert_eq!(durable, wal.current_position().await);
+
+        // Written but not yet synced - must not appear in the backup
+        for i in 0..3 {
+            let key = format!("unsynced{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+
+        let backup_dir = temp_dir.path().join("backup");
+        let info = wal.backup_to(&backup_dir).await.unwrap();
+        assert_eq!(info.position, durable);
+        assert!(info.segments >= 2);
+
+        let backup_config = WalConfig {
+            dir: backup_dir,
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (_backup, recovery_info) = Wal::open(backup_config).await.unwrap();
+        assert_eq!(recovery_info.valid_records, 15);
+        assert!(!recovery_info.corruption_detected);
+    }
+
+    #[tokio::test]
+    async fn test_wal_truncate_from_lsn() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let value = vec![7u8; 100 * 1024];
+        for i in 0..30 {
+            let key = format!("key{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                .await
+                .unwrap();
+        }
+        assert!(wal.current_position().await.segment_id >= 2);
+
+        // Roll back a conflicting suffix, then append replacements
+        assert!(wal.truncate_from_lsn(12).await.unwrap() > 0);
+        assert_eq!(wal.next_lsn(), 12);
+        assert_eq!(wal.truncate_from_lsn(100).await.unwrap(), 0);
+        wal.append(&Record::put(b"replacement".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        drop(wal);
+
+        let mut recovered = Vec::new();
+        let (wal, info) = Wal::open_with_replay(config, |record, _| recovered.push(record))
+            .await
+            .unwrap();
+        assert_eq!(info.valid_records, 12);
+        assert_eq!(info.last_lsn, Some(12));
+        assert_eq!(wal.next_lsn(), 13);
+        assert_eq!(recovered[10].key.as_ref(), b"key10");
+        assert_eq!(recovered[11].key.as_ref(), b"replacement");
+    }
+
+    #[tokio::test]
+    async fn test_wal_checkpoint_persists_and_purges() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            purge_on_checkpoint: true,
+            ..Default::default()
+        };
+
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        assert_eq!(wal.last_checkpoint().await, None);
+
+        let value = vec![7u8; 100 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..30 {
+            let key = format!("key{}", i);
+            positions.push(
+                wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap(),
+            );
+        }
+        let cut = positions[25];
+        assert!(cut.segment_id >= 2);
+
+        let checkpoint = wal.checkpoint(cut).await.unwrap();
+        assert_eq!(checkpoint.position, cut);
+        assert_eq!(wal.last_checkpoint().await, Some(checkpoint));
+
+        // Segments wholly before the checkpoint are gone, the one holding it is not
+        assert!(!temp_dir.path().join("000000.wal").exists());
+        assert!(crate::segment::segment_path(temp_dir.path(), cut.segment_id).exists());
+
+        // Checkpoints only move forward, and never truncate away
+        assert!(wal.checkpoint(positions[24]).await.is_err());
+        assert!(wal.truncate_from(positions[24]).await.is_err());
+        let past_end = Position {
+            segment_id: cut.segment_id + 10,
+            offset: 0,
+        };
+        assert!(wal.checkpoint(past_end).await.is_err());
+        wal.close().await.unwrap();
+
+        let (wal, _) = Wal::open(config).await.unwrap();
+        assert_eq!(wal.last_checkpoint().await, Some(checkpoint));
+    }
+
+    #[tokio::test]
+    async fn test_wal_reader_spans_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: true,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let value = vec![7u8; 100 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..25 {
+            let key = format!("key{}", i);
+            positions.push(
+                wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                    .await
+                    .unwrap(),
+            );
+        }
+        wal.sync().await.unwrap();
+        assert!(wal.current_position().await.segment_id >= 2);
+
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let mut reader = wal.reader(start);
+        let mut read = Vec::new();
+        while let Some((record, pos)) = reader.next_record().await.unwrap() {
+            assert_eq!(record.key, format!("key{}", read.len()));
+            read.push(pos);
+        }
+        assert_eq!(read, positions);
+        assert_eq!(reader.position(), wal.durable_position().await);
+
+        // Records become visible once they are durable
+        wal.append(&Record::put(b"late".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        assert!(reader.next_record().await.unwrap().is_none());
+        wal.sync().await.unwrap();
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"late");
+
+        // A purged segment is reported, then skipped
+        wal.delete_segments_before(positions[15]).await.unwrap();
+        let mut reader = wal.reader(start);
+        match reader.next_record().await {
+            Err(SegmentError::Gap { missing, resume_at }) => {
+                assert_eq!(missing, 0);
+                assert_eq!(resume_at, Some(positions[15].segment_id));
+            }
+            other => panic!("expected a gap, got {:?}", other.map(|r| r.map(|(_, p)| p))),
+        }
+        let (_, pos) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(pos.offset, 0);
+        assert_eq!(pos.segment_id, positions[15].segment_id);
+    }
+
+    #[tokio::test]
+    async fn test_wal_reader_stream() {
+        use futures::{FutureExt, StreamExt};
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let value = vec![3u8; 100 * 1024];
+        for i in 0..25 {
+            let key = format!("key{}", i);
+            wal.append(&Record::put(bytes::Bytes::from(key), value.clone()))
+                .await
+                .unwrap();
+        }
+        wal.sync().await.unwrap();
+        assert!(wal.current_position().await.segment_id >= 2);
+
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let keys: Vec<_> = wal
+            .reader(start)
+            .map(|item| item.unwrap().0.key)
+            .collect()
+            .await;
+        let expected: Vec<_> = (0..25).map(|i| format!("key{}", i)).collect();
+        assert_eq!(keys, expected);
+
+        // Reads abandoned mid-flight neither skip nor repeat records
+        let mut reader = wal.reader_with(
+            start,
+            ReaderConfig {
+                read_ahead_bytes: 4096,
+                ..Default::default()
+            },
+        );
+        let mut keys = Vec::new();
+        let mut cancelled = 0;
+        loop {
+            match reader.next().now_or_never() {
+                Some(Some(item)) => keys.push(item.unwrap().0.key),
+                Some(None) => break,
+                None => {
+                    cancelled += 1;
+                    tokio::task::yield_now().await;
+                }
+            }
+        }
+        assert!(cancelled > 0);
+        assert_eq!(keys, expected);
+    }
+
+    #[tokio::test]
+    async fn test_wal_tail_follows_appends() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let wal = Arc::new(wal);
+
+        for i in 0..3 {
+            wal.append(&Record::put(format!("old{}", i), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+
+        let mut tail = wal.tail(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        for i in 0..3 {
+            let (record, _) = tail.next_record().await.unwrap();
+            assert_eq!(record.key, format!("old{}", i));
+        }
+
+        // Caught up: the tail waits rather than ending
+        let wait = tokio::time::timeout(Duration::from_millis(50), tail.next_record()).await;
+        assert!(wait.is_err());
+
+        // New appends wake it, including ones that rotate into new segments
+        let writer = {
+            let wal = wal.clone();
+            tokio::spawn(async move {
+                let value = vec![1u8; 100 * 1024];
+                for i in 0..25 {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    wal.append(&Record::put(format!("new{}", i), value.clone()))
+                        .await
+                        .unwrap();
+                }
+            })
+        };
+        for i in 0..25 {
+            let (record, _) = tokio::time::timeout(Duration::from_secs(5), tail.next_record())
+                .await
+                .expect("tail missed an append")
+                .unwrap();
+            assert_eq!(record.key, format!("new{}", i));
+        }
+        writer.await.unwrap();
+        assert!(tail.position().segment_id >= 2);
+        assert_eq!(tail.position(), wal.durable_position().await);
+    }
+
+    #[tokio::test]
+    async fn test_wal_last_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        assert!(wal.last_record().await.unwrap().is_none());
+
+        let value = vec![5u8; 100 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..12 {
+            let record = Record::put(format!("key{}", i), value.clone());
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        // Rotation made segment 0 durable; the tail of segment 1 is not yet
+        assert_eq!(positions[11].segment_id, 1);
+        let (record, position) = wal.last_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"key9");
+        assert_eq!(position, positions[9]);
+
+        wal.sync().await.unwrap();
+        let (record, position) = wal.last_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"key11");
+        assert_eq!(position, positions[11]);
+        wal.close().await.unwrap();
+
+        // After reopening, the last record is found once and then tracked
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let (_, position) = wal.last_record().await.unwrap().unwrap();
+        assert_eq!(position, positions[11]);
+        wal.append(&Record::put(b"key12".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        let (record, _) = wal.last_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"key12");
+
+        wal.truncate_from(positions[5]).await.unwrap();
+        let (record, position) = wal.last_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"key4");
+        assert_eq!(position, positions[4]);
+    }
+
+    #[tokio::test]
+    async fn test_wal_resume_from_cursor() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let value = vec![6u8; 100 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..25 {
+            let record = Record::put(format!("key{}", i), value.clone());
+            positions.push(wal.append(&record).await.unwrap());
+        }
+
+        let mut reader = wal.reader(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        for _ in 0..12 {
+            reader.next_record().await.unwrap().unwrap();
+        }
+        let saved = reader.cursor().to_bytes();
+        wal.close().await.unwrap();
+
+        // Progress survives a restart
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let cursor = Cursor::from_bytes(&saved).unwrap();
+        assert_eq!(cursor.position(), positions[12]);
+        let mut reader = wal.resume_reader(&cursor).await.unwrap();
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.key.as_ref(), b"key12");
+        assert!(Cursor::from_bytes(&saved[1..]).is_none());
+        // An open reader would keep its segment from being purged
+        drop(reader);
+
+        // Purged or truncated positions are reported, not silently skipped
+        wal.delete_segments_before(positions[24]).await.unwrap();
+        match wal.resume_tail(&cursor).await {
+            Err(SegmentError::CursorGone(position)) => assert_eq!(position, positions[12]),
+            other => panic!("expected CursorGone, got {:?}", other.map(|t| t.position())),
+        }
+        let late = wal.tail(positions[24]).cursor();
+        wal.resume_tail(&late).await.unwrap();
+        wal.truncate_from(positions[23]).await.unwrap();
+        assert!(matches!(
+            wal.resume_reader(&late).await,
+            Err(SegmentError::CursorGone(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wal_read_range() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let at = |secs: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(1_000 + secs);
+        let value = vec![3u8; 30 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..100 {
+            let record = Record::put(format!("key{}", i), value.clone()).with_timestamp(at(i));
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+
+        async fn keys(mut reader: WalReader) -> Vec<String> {
+            let mut keys = Vec::new();
+            while let Some((record, _)) = reader.next_record().await.unwrap() {
+                keys.push(String::from_utf8(record.key.to_vec()).unwrap());
+            }
+            keys
+        }
+        let expected = |range: std::ops::Range<u64>| -> Vec<String> {
+            range.map(|i| format!("key{}", i)).collect()
+        };
+
+        let reader = wal.read_range(at(60), at(70)).await.unwrap();
+        // The index skips most of the log rather than scanning from the start
+        assert!(reader.position() > positions[50] && reader.position() <= positions[60]);
+        assert_eq!(keys(reader).await, expected(60..70));
+
+        let reader = wal.read_range(at(0), at(3)).await.unwrap();
+        assert_eq!(keys(reader).await, expected(0..3));
+        let reader = wal.read_range(at(95), at(500)).await.unwrap();
+        assert_eq!(keys(reader).await, expected(95..100));
+        let reader = wal.read_range(at(500), at(600)).await.unwrap();
+        assert!(keys(reader).await.is_empty());
+        wal.close().await.unwrap();
+
+        // Segments from before the reopen are found by their first records
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let reader = wal.read_range(at(33), at(66)).await.unwrap();
+        assert_eq!(keys(reader).await, expected(33..66));
+    }
+
+    #[tokio::test]
+    async fn test_wal_read_from_lsn() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            seal_segments: true,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        let value = vec![2u8; 30 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..100 {
+            let record = Record::put(format!("key{}", i), value.clone());
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+        assert!(positions[99].segment_id >= 2);
+
+        for lsn in [1, 17, 34, 35, 99, 100] {
+            let mut reader = wal.read_from_lsn(lsn).await.unwrap();
+            let (record, position) = reader.next_record().await.unwrap().unwrap();
+            assert_eq!(record.lsn, Some(lsn));
+            assert_eq!(position, positions[lsn as usize - 1]);
+        }
+        wal.close().await.unwrap();
+
+        // After reopening, older segments are indexed by their first records
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let mut reader = wal.read_from_lsn(60).await.unwrap();
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.lsn, Some(60));
+
+        // The next LSN is waited for; later ones are an error
+        let mut reader = wal.read_from_lsn(101).await.unwrap();
+        assert!(reader.next_record().await.unwrap().is_none());
+        wal.append(&Record::put(b"key100".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.lsn, Some(101));
+        assert!(matches!(
+            wal.read_from_lsn(103).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+
+        // LSNs in purged segments are reported as gone
+        wal.delete_segments_before(positions[99]).await.unwrap();
+        assert!(matches!(
+            wal.read_from_lsn(1).await,
+            Err(SegmentError::Purged(_))
+        ));
+        let mut reader = wal.read_from_lsn(99).await.unwrap();
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.lsn, Some(99));
+    }
+
+    #[tokio::test]
+    async fn test_wal_read_from_lsn_without_timestamps() {
+        // Segments 1 and 2 of a log whose records carry LSNs but no
+        // timestamps, with segment 0 purged
+        let temp_dir = TempDir::new().unwrap();
+        for (segment_id, lsns) in [(1, 10..20), (2, 20..30)] {
+            let mut data = Vec::new();
+            for lsn in lsns {
+                let mut record = Record::put(format!("key{}", lsn), "v");
+                record.lsn = Some(lsn);
+                data.extend_from_slice(&record.encode());
+            }
+            let path = crate::segment::segment_path(temp_dir.path(), segment_id);
+            std::fs::write(path, data).unwrap();
+        }
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        assert!(matches!(
+            wal.read_from_lsn(5).await,
+            Err(SegmentError::Purged(0))
+        ));
+        for lsn in [10, 25] {
+            let mut reader = wal.read_from_lsn(lsn).await.unwrap();
+            let (record, _) = reader.next_record().await.unwrap().unwrap();
+            assert_eq!(record.lsn, Some(lsn));
+        }
+        // Nor do they fall in any window of time
+        let now = std::time::SystemTime::now();
+        let mut reader = wal.read_range(std::time::UNIX_EPOCH, now).await.unwrap();
+        assert!(reader.next_record().await.unwrap().is_none());
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_scan_prefix() {
+        use futures::StreamExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let value = vec![8u8; 50 * 1024];
+        let mut positions = Vec::new();
+        for i in 0..60 {
+            let user = if i % 4 == 0 { "user/42/" } else { "user/7/" };
+            let record = Record::put(format!("{}{}", user, i), value.clone());
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+        assert!(positions[59].segment_id >= 2);
+
+        // The range spans segments and stops short of the end of the log
+        let keys: Vec<_> = wal
+            .scan_prefix("user/42/", positions[10], positions[50])
+            .map(|item| item.unwrap().0.key)
+            .collect()
+            .await;
+        let expected: Vec<_> = (12..50)
+            .step_by(4)
+            .map(|i| format!("user/42/{}", i))
+            .collect();
+        assert_eq!(keys, expected);
+
+        let none = wal
+            .scan_prefix("user/9/", positions[0], wal.durable_position().await)
+            .next()
+            .await;
+        assert!(none.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_wal_tail_key_filters() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        for i in 0..10 {
+            let space = if i % 3 == 0 { "orders" } else { "users" };
+            wal.append(&Record::put(format!("{}/{}", space, i), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        let mut orders = wal.tail(start).with_key_prefix("orders/");
+        for i in [0, 3, 6, 9] {
+            let (record, _) = orders.next_record().await.unwrap();
+            assert_eq!(record.key, format!("orders/{}", i));
+        }
+
+        // Skipped records still advance the tail, and new matches wake it
+        let wait = tokio::time::timeout(Duration::from_millis(20), orders.next_record()).await;
+        assert!(wait.is_err());
+        assert_eq!(orders.position(), wal.durable_position().await);
+        wal.append(&Record::put(b"users/10".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        wal.append(&Record::put(b"orders/11".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        let (record, _) = orders.next_record().await.unwrap();
+        assert_eq!(record.key.as_ref(), b"orders/11");
+
+        let mut even = wal
+            .tail(start)
+            .with_key_filter(|key| key.last().is_some_and(|b| b"02468".contains(b)));
+        let mut keys = Vec::new();
+        for _ in 0..5 {
+            keys.push(even.next_record().await.unwrap().0.key);
+        }
+        assert_eq!(
+            keys,
+            ["orders/0", "users/2", "users/4", "orders/6", "users/8"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wal_metrics() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        assert_eq!(wal.metrics().await.appends, 0);
+
+        wal.append(&Record::put(b"k".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+        let batch: Vec<_> = (0..3)
+            .map(|_| Record::put(b"k".as_slice(), vec![0u8; 200 * 1024]))
+            .collect();
+        wal.append_batch(&batch).await.unwrap();
+        wal.append(&Record::put(b"k".as_slice(), vec![0u8; 600 * 1024]))
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        wal.append(&Record::put(b"k".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+
+        let metrics = wal.metrics().await;
+        let position = wal.current_position().await;
+        assert_eq!(metrics.appends, 6);
+        assert!(metrics.bytes_appended > 1200 * 1024);
+        assert_eq!(metrics.fsyncs, 1);
+        assert_eq!(metrics.rotations, 1);
+        assert_eq!(metrics.active_segment_id, position.segment_id);
+        assert_eq!(metrics.active_segment_bytes, position.offset);
+        assert!(metrics.unsynced_bytes > 0);
+        assert_eq!(metrics.append_latency.count, 4);
+        assert!(metrics.append_latency.p50 <= metrics.append_latency.max);
+        assert_eq!(metrics.fsync_latency.count, 1);
+
+        // Framing makes up the difference between payload and disk bytes
+        let efficiency = metrics.write_efficiency;
+        assert_eq!(
+            efficiency.logical_bytes,
+            2 + 3 * (1 + 200 * 1024) + 1 + 600 * 1024 + 2
+        );
+        assert!(efficiency.physical_bytes >= metrics.bytes_appended);
+        assert!(efficiency.amplification().unwrap() > 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_wal_observe_workload() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Always,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        // Appends before the window are not counted
+        wal.append(&Record::put("early", "v")).await.unwrap();
+
+        let window = Duration::from_millis(200);
+        let (profile, ()) = tokio::join!(wal.observe_workload(window), async {
+            for i in 0..50 {
+                let record = Record::put(format!("key{}", i), vec![b'a'; 1000]);
+                wal.append(&record).await.unwrap();
+            }
+        });
+        let profile = profile.unwrap();
+        assert_eq!(profile.window, window);
+        assert_eq!(profile.appends, 50);
+        assert!(profile.record_size.p50 > 1000);
+        assert!(profile.fsync_latency.count >= 51);
+        let advice = profile.advise();
+        assert!(advice.fsync_policy.is_some());
+        assert_eq!(advice.compression, crate::record::Compression::Lz4);
+    }
+
+    #[tokio::test]
+    async fn test_wal_namespaces() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let start = wal.current_position().await;
+
+        // Raft entries in namespace 1 interleaved with state machine ops in 2
+        for i in 0..6u32 {
+            let record = Record::put(format!("entry/{}", i), b"v".as_slice());
+            wal.append(&record.with_namespace(1 + i % 2)).await.unwrap();
+        }
+        wal.append(&Record::put(b"plain".as_slice(), b"v".as_slice()))
+            .await
+            .unwrap();
+
+        let mut raft = wal.reader(start).with_namespace(1);
+        let mut keys = Vec::new();
+        while let Some((record, _)) = raft.next_record().await.unwrap() {
+            assert_eq!(record.namespace, Some(1));
+            keys.push(record.key);
+        }
+        assert_eq!(keys, ["entry/0", "entry/2", "entry/4"]);
+
+        let mut ops = wal.tail(start).with_namespace(2);
+        for i in [1, 3, 5] {
+            let (record, _) = ops.next_record().await.unwrap();
+            assert_eq!(record.key, format!("entry/{}", i));
+        }
+        wal.append(&Record::delete(b"entry/1".as_slice()).with_namespace(2))
+            .await
+            .unwrap();
+        let (record, _) = ops.next_record().await.unwrap();
+        assert!(record.tombstone);
+
+        assert_eq!(wal.namespace_metrics(1).appends, 3);
+        assert_eq!(wal.namespace_metrics(2).appends, 4);
+        assert!(wal.namespace_metrics(2).bytes_appended > 0);
+        assert_eq!(wal.namespace_metrics(3), NamespaceMetrics::default());
+        assert_eq!(wal.metrics().await.appends, 8);
+    }
+
+    #[tokio::test]
+    async fn test_wal_namespace_quotas() {
+        let temp_dir = TempDir::new().unwrap();
+        let quota = NamespaceQuota {
+            max_records: Some(3),
+            max_bytes: None,
+            enforcement: QuotaEnforcement::Reject,
+        };
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            namespace_quotas: HashMap::from([(1, quota)]),
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let tenant = |ns| Record::put("k", vec![0u8; 300 * 1024]).with_namespace(ns);
+
+        for _ in 0..3 {
+            wal.append(&tenant(1)).await.unwrap();
+        }
+        let err = wal.append(&tenant(1)).await.unwrap_err();
+        assert!(matches!(
+            err,
+            SegmentError::QuotaExceeded {
+                namespace: 1,
+                records: 3,
+                ..
+            }
+        ));
+        assert!(err.is_retriable());
+        // Other tenants are unaffected
+        wal.append(&tenant(2)).await.unwrap();
+        let metrics = wal.namespace_metrics(1);
+        assert_eq!((metrics.records_in_log, metrics.quota_exceeded), (3, 1));
+
+        // Purging the tenant's segments gives its quota back
+        let position = wal.current_position().await;
+        assert!(position.segment_id > 0);
+        wal.delete_segments_before(position).await.unwrap();
+        assert!(wal.namespace_metrics(1).records_in_log < 3);
+        wal.append(&tenant(1)).await.unwrap();
+        assert_eq!(wal.namespace_metrics(1).appends, 4);
+
+        // Reporting lets appends through
+        let report = NamespaceQuota {
+            enforcement: QuotaEnforcement::Report,
+            ..quota
+        };
+        wal.set_namespace_quota(1, Some(report)).await.unwrap();
+        for _ in 0..3 {
+            wal.append(&tenant(1)).await.unwrap();
+        }
+        assert!(wal.namespace_metrics(1).quota_exceeded > 1);
+        assert_eq!(wal.namespace_quota(1), Some(report));
+
+        let throttle = NamespaceQuota {
+            enforcement: QuotaEnforcement::Throttle(Duration::ZERO),
+            ..quota
+        };
+        assert!(wal.set_namespace_quota(1, Some(throttle)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wal_hash_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            hash_chain: true,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        assert_eq!(wal.chain_head(), None);
+        let mut positions = Vec::new();
+        for i in 0..6 {
+            let record = Record::put(format!("key{}", i), vec![b'v'; 300 * 1024]);
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        let batch = [Record::put("a", "1"), Record::delete("b")];
+        positions.extend(wal.append_batch(&batch).await.unwrap());
+        wal.sync().await.unwrap();
+        assert!(positions.last().unwrap().segment_id > 0);
+
+        let head = wal.chain_head().unwrap();
+        assert_eq!(head.position, *positions.last().unwrap());
+        let report = wal.verify_chain(Some(head)).await.unwrap();
+        assert!(report.is_intact(), "{:?}", report);
+        assert_eq!((report.records, report.unchained), (8, 0));
+        assert_eq!(report.head, Some(head));
+        assert_eq!(report.attested, Some(true));
+        assert!(wal.compact().await.is_err());
+        wal.close().await.unwrap();
+
+        // The chain carries on after reopening and truncating
+        let (wal, _) = Wal::open(config.clone()).await.unwrap();
+        assert_eq!(wal.chain_head(), Some(head));
+        wal.truncate_from(positions[7]).await.unwrap();
+        assert_eq!(wal.chain_head().unwrap().position, positions[6]);
+        wal.append(&Record::put("c", "3")).await.unwrap();
+        wal.sync().await.unwrap();
+        let report = wal.verify_chain(None).await.unwrap();
+        assert!(report.is_intact(), "{:?}", report);
+        assert_eq!(report.records, 8);
+        // Truncation removed the attested head
+        let report = wal.verify_chain(Some(head)).await.unwrap();
+        assert_eq!(report.attested, Some(false));
+        let head = wal.chain_head().unwrap();
+        wal.close().await.unwrap();
+
+        // Editing a record, checksum and all, breaks the link after it
+        let path = crate::segment::segment_path(temp_dir.path(), positions[1].segment_id);
+        let mut data = std::fs::read(&path).unwrap();
+        let start = positions[1].offset as usize;
+        let (mut record, size) = Record::decode(&data[start..]).unwrap();
+        record.value = Bytes::from(vec![b'x'; 300 * 1024]);
+        let forged = record.encode();
+        assert_eq!(forged.len(), size);
+        data[start..start + size].copy_from_slice(&forged);
+        std::fs::write(&path, &data).unwrap();
+
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let report = wal.verify_chain(Some(head)).await.unwrap();
+        assert_eq!(report.breaks, [positions[2]]);
+        assert_eq!(report.attested, Some(true));
+        assert!(!report.is_intact());
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_rereads_come_from_the_record_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let mut positions = Vec::new();
+        for i in 0..10 {
+            let record = Record::put(format!("key{}", i), format!("value{}", i));
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+
+        async fn read_all(reader: &mut WalReader) -> Vec<(Record, Position)> {
+            let mut records = Vec::new();
+            while let Some(next) = reader.next_record().await.unwrap() {
+                records.push(next);
+            }
+            records
+        }
+        let start = positions[0];
+        let first = read_all(&mut wal.reader(start)).await;
+        assert_eq!(first.len(), 10);
+        let before = wal.metrics().await;
+        assert_eq!(before.record_cache_hits, 0);
+
+        // A second subscriber gets the same records without the disk
+        let second = read_all(&mut wal.reader(start)).await;
+        assert_eq!(second, first);
+        let after = wal.metrics().await;
+        assert_eq!(after.record_cache_hits, 10);
+        let filtered = read_all(&mut wal.reader(start).with_key_prefix("key3")).await;
+        assert_eq!(filtered, [first[3].clone()]);
+        let bounded = read_all(&mut wal.reader(start).until(positions[4])).await;
+        assert_eq!(bounded.len(), 4);
+
+        // A tail picks up where the cached records end
+        let mut tail = wal.tail(positions[8]);
+        assert_eq!(tail.next_record().await.unwrap(), first[8]);
+        assert_eq!(tail.next_record().await.unwrap(), first[9]);
+        wal.append(&Record::put("key10", "value10")).await.unwrap();
+        wal.sync().await.unwrap();
+        let (record, _) = tail.next_record().await.unwrap();
+        assert_eq!(record.key, Bytes::from("key10"));
+
+        // Truncated records are not served from the cache
+        wal.truncate_from(positions[5]).await.unwrap();
+        wal.append(&Record::put("new", "value")).await.unwrap();
+        wal.sync().await.unwrap();
+        let rest = read_all(&mut wal.reader(positions[5])).await;
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].0.key, Bytes::from("new"));
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wals_share_a_memory_budget() {
+        let budget = MemoryBudget::new(256 * 1024);
+        let dirs = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+        let mut wals = Vec::new();
+        for dir in &dirs {
+            let (wal, _) = Wal::builder()
+                .dir(dir.path())
+                .fsync(FsyncPolicy::Os)
+                .preallocate(false)
+                .memory_budget(budget.clone())
+                .open()
+                .await
+                .unwrap();
+            wals.push(wal);
+        }
+        let start = Position {
+            segment_id: 0,
+            offset: 0,
+        };
+        for wal in &wals {
+            for i in 0..200 {
+                let record = Record::put(format!("key{}", i), vec![b'v'; 4000]);
+                wal.append(&record).await.unwrap();
+            }
+            wal.sync().await.unwrap();
+        }
+        assert_eq!(budget.stats().append_buffers, 0);
+
+        // The first WAL's record cache fills the budget, then stops growing
+        let mut reader = wals[0].reader(start);
+        reader.next_record().await.unwrap().unwrap();
+        assert!(budget.stats().read_ahead > 0);
+        let mut read = 1;
+        while reader.next_record().await.unwrap().is_some() {
+            read += 1;
+        }
+        assert_eq!(read, 200);
+        drop(reader);
+        let stats = budget.stats();
+        assert!(stats.record_cache > 128 * 1024, "{:?}", stats);
+        assert!(stats.used <= stats.limit, "{:?}", stats);
+        assert_eq!(stats.read_ahead, 0);
+        let shrinks = stats.shrinks;
+        assert!(shrinks > 0);
+
+        // With no room left, readers of the other WAL read ahead little
+        let config = ReaderConfig {
+            read_ahead_bytes: 4 << 20,
+            ..Default::default()
+        };
+        let mut reader = wals[1].read_handle().reader_with(start, config);
+        reader.next_record().await.unwrap().unwrap();
+        let stats = budget.stats();
+        assert!(stats.read_ahead < 64 * 1024, "{:?}", stats);
+        assert!(stats.shrinks > shrinks);
+        drop(reader);
+
+        // Appends still go ahead, taking memory back from the cache
+        let cached = budget.stats().record_cache;
+        let batch: Vec<_> = (0..20)
+            .map(|i| Record::put(format!("more{}", i), vec![b'v'; 4000]))
+            .collect();
+        wals[1].append_batch(&batch).await.unwrap();
+        let stats = budget.stats();
+        assert_eq!(stats.append_buffers, 0);
+        assert!(stats.record_cache < cached, "{:?}", stats);
+        for wal in wals {
+            wal.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wal_reports_spans_for_traced_appends() {
+        use crate::record::TraceContext;
+        use nori_observe::TestMeter;
+
+        let temp_dir = TempDir::new().unwrap();
+        let meter = TestMeter::new();
+        let (wal, _) = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Always)
+            .preallocate(false)
+            .meter(Arc::new(meter.clone()))
+            .open()
+            .await
+            .unwrap();
+
+        let sampled =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        let unsampled = TraceContext {
+            flags: 0,
+            ..sampled
+        };
+        let position = wal
+            .append(&Record::put("a", "1").with_trace(sampled))
+            .await
+            .unwrap();
+        wal.append_batch(&[
+            Record::put("b", "2").with_trace(unsampled),
+            Record::put("c", "3"),
+            Record::put("d", "4").with_trace(sampled),
+        ])
+        .await
+        .unwrap();
+
+        let spans = meter.spans();
+        let names: Vec<_> = spans.iter().map(|span| span.name).collect();
+        assert_eq!(
+            names,
+            ["wal.append", "wal.fsync", "wal.append", "wal.fsync"]
+        );
+        assert!(spans.iter().all(|span| span.trace_id == sampled.trace_id));
+        let attribute = |span: &nori_observe::SpanRecord, key: &str| {
+            span.attributes
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        };
+
+        // The append is a child of the caller's span, the fsync of the append
+        assert_eq!(spans[0].parent_span_id, sampled.span_id);
+        assert_eq!(spans[1].parent_span_id, spans[0].span_id);
+        assert_ne!(spans[0].span_id, spans[2].span_id);
+        assert_eq!(
+            attribute(&spans[0], "offset"),
+            Some(position.offset.to_string())
+        );
+        assert_eq!(attribute(&spans[0], "lsn"), Some("1".to_string()));
+        assert_eq!(attribute(&spans[2], "lsn"), Some("4".to_string()));
+        // Each call made its own fsync
+        assert_eq!(attribute(&spans[0], "fsync"), attribute(&spans[1], "fsync"));
+        assert_ne!(attribute(&spans[1], "fsync"), attribute(&spans[3], "fsync"));
+
+        // The context is kept in the log
+        let mut reader = wal.reader(position);
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.trace, Some(sampled));
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.trace, Some(unsampled));
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_coalesces_puts_within_a_batch() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wal, _) = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Os)
+            .preallocate(false)
+            .coalesce_keys(true)
+            .open()
+            .await
+            .unwrap();
+
+        let mut batch: Vec<Record> = (0..100)
+            .map(|i| Record::put("counter", i.to_string()))
+            .collect();
+        batch.push(Record::put("other", "x"));
+        let positions = wal.append_batch(&batch).await.unwrap();
+        assert_eq!(positions.len(), 101);
+        assert!(positions[..100].iter().all(|p| *p == positions[99]));
+        assert_ne!(positions[100], positions[99]);
+        wal.sync().await.unwrap();
+
+        let mut reader = wal.reader(positions[0]);
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.value, "99");
+        assert_eq!(record.coalesced, Some(99));
+        let (record, _) = reader.next_record().await.unwrap().unwrap();
+        assert_eq!(record.key, "other");
+        assert_eq!(record.coalesced, None);
+        assert!(reader.next_record().await.unwrap().is_none());
+
+        let metrics = wal.metrics().await;
+        assert_eq!(metrics.appends, 2);
+        assert_eq!(metrics.coalesced_puts, 99);
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_tombstones_expired_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let clock = Arc::new(crate::clock::MockClock::new());
+        let builder = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Os)
+            .preallocate(false)
+            .track_expiry(true)
+            .clock(clock.clone());
+
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let ttl = Duration::from_secs(10);
+        wal.append(&Record::put_with_ttl("session", "a", ttl))
+            .await
+            .unwrap();
+        wal.append(&Record::put_with_ttl("cart", "b", ttl))
+            .await
+            .unwrap();
+        wal.append(&Record::put_with_ttl("token", "c", ttl * 6))
+            .await
+            .unwrap();
+        assert!(wal.expired_keys().unwrap().is_empty());
+        assert_eq!(wal.metrics().await.expiring_keys, 3);
+
+        clock.advance(ttl);
+        let keys: Vec<_> = wal
+            .expired_keys()
+            .unwrap()
+            .into_iter()
+            .map(|expired| expired.key)
+            .collect();
+        assert_eq!(keys, ["session", "cart"]);
+
+        // The hook skips the cart, so only the session is deleted
+        let deleted = wal
+            .tombstone_expired(|expired| expired.key != "cart")
+            .await
+            .unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].key, "session");
+        assert_eq!(wal.expired_keys().unwrap().len(), 1);
+        wal.close().await.unwrap();
+
+        // Reopening rebuilds the index from the log
+        let (wal, _) = builder.clone().open().await.unwrap();
+        let expired = wal.expired_keys().unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].key, "cart");
+        assert_eq!(wal.metrics().await.expiring_keys, 2);
+        wal.close().await.unwrap();
+
+        let (wal, _) = Wal::builder()
+            .dir(temp_dir.path())
+            .preallocate(false)
+            .open()
+            .await
+            .unwrap();
+        assert!(matches!(
+            wal.expired_keys(),
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        wal.close().await.unwrap();
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_wal_alerts_when_latency_slo_burns() {
+        use crate::slo::LatencySlo;
+        use nori_observe::{LatencyOp, TestMeter};
+
+        let temp_dir = TempDir::new().unwrap();
+        let meter = TestMeter::new();
+        // Every fsync misses a 1ns threshold; no append takes an hour
+        let (wal, _) = Wal::builder()
+            .dir(temp_dir.path())
+            .fsync(FsyncPolicy::Always)
+            .preallocate(false)
+            .meter(Arc::new(meter.clone()))
+            .fsync_slo(LatencySlo {
+                min_samples: 3,
+                ..LatencySlo::new(Duration::from_nanos(1))
+            })
+            .append_slo(LatencySlo {
+                min_samples: 3,
+                ..LatencySlo::new(Duration::from_secs(3600))
+            })
+            .open()
+            .await
+            .unwrap();
+
+        for i in 0..5 {
+            wal.append(&Record::put(format!("k{}", i), "v"))
+                .await
+                .unwrap();
+        }
+
+        let alerts: Vec<_> = meter
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                VizEvent::Wal(WalEvt {
+                    kind: kind @ WalKind::LatencySloBurn { .. },
+                    ..
+                }) => Some(kind),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(
+            alerts[0],
+            WalKind::LatencySloBurn {
+                op: LatencyOp::Fsync,
+                threshold_ms: 0,
+                ..
+            }
+        ));
+        assert_eq!(
+            meter.counter_value("wal_slo_burn_alerts_total", &[("op", "fsync")]),
+            1
+        );
+        assert_eq!(
+            meter.counter_value("wal_slo_burn_alerts_total", &[("op", "append")]),
+            0
+        );
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_offloads_large_compressed_appends() {
+        use crate::record::Compression;
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            encode_offload_bytes: 8 * 1024,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let large =
+            |i: u8| Record::put("big", vec![i; 16 * 1024]).with_compression(Compression::Zstd);
+        let small = Record::put("small", vec![7u8; 100]).with_compression(Compression::Zstd);
+        let first = wal.append(&large(1)).await.unwrap();
+        wal.append(&small).await.unwrap();
+        wal.append_batch(&[large(2), small.clone(), large(3)])
+            .await
+            .unwrap();
+        wal.sync().await.unwrap();
+        assert_eq!(wal.metrics().await.offloaded_encodes, 2);
+
+        let mut reader = wal.reader(first);
+        let mut values = Vec::new();
+        while let Some((record, _)) = reader.next_record().await.unwrap() {
+            values.push(record.value[0]);
+        }
+        assert_eq!(values, [1, 7, 2, 7, 3]);
+        wal.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wal_audits_administrative_operations() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().join("wal"),
+            max_segment_size: 1024 * 1024,
+            fsync_policy: FsyncPolicy::Os,
+            preallocate: false,
+            node_id: 9,
+            audit_log: true,
+            ..Default::default()
+        };
+        let (mut wal, _) = Wal::open(config).await.unwrap();
+        let mut positions = Vec::new();
+        for i in 0..40 {
+            let record = Record::put(format!("key{}", i), vec![b'v'; 100 * 1024]);
+            positions.push(wal.append(&record).await.unwrap());
+        }
+        wal.sync().await.unwrap();
+
+        // Nothing to delete, nothing recorded
+        assert_eq!(wal.delete_segments_before(positions[0]).await.unwrap(), 0);
+        assert!(wal.delete_segments_before(positions[20]).await.unwrap() > 0);
+        assert!(wal.truncate_from(positions[30]).await.unwrap() > 0);
+        wal.set_fsync_policy(FsyncPolicy::Always).await.unwrap();
+        let new_dir = temp_dir.path().join("moved");
+        wal.migrate_to(&new_dir).await.unwrap();
+        wal.close().await.unwrap();
+
+        assert!(!temp_dir.path().join("wal").join(audit::AUDIT_FILE).exists());
+        let log = std::fs::read_to_string(new_dir.join(audit::AUDIT_FILE)).unwrap();
+        let entries: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let operations: Vec<&str> = entries
+            .iter()
+            .map(|entry| entry["operation"].as_str().unwrap())
+            .collect();
+        assert_eq!(operations, ["purge", "truncate", "reconfigure", "migrate"]);
+        assert_eq!(entries[0]["source"], "Wal::delete_segments_before");
+        assert_eq!(entries[0]["node_id"], 9);
+        assert_eq!(entries[0]["pid"], std::process::id());
+        assert!(entries[1]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("from {}", positions[30])));
+
+        // Auditing can be turned off
+        let config = WalConfig {
+            dir: new_dir.clone(),
+            audit_log: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        wal.truncate_from(positions[25]).await.unwrap();
+        wal.close().await.unwrap();
+        let after = std::fs::read_to_string(new_dir.join(audit::AUDIT_FILE)).unwrap();
+        assert_eq!(after, log);
+    }
+
+    #[tokio::test]
+    async fn test_append_if() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            max_segment_size: 1024 * 1024,
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let tail = wal.current_position().await;
+        let pos = wal
+            .append_if(tail, &Record::put(b"a".as_slice(), b"1".as_slice()))
+            .await
+            .unwrap();
+        assert_eq!(pos, tail);
+        let err = wal
+            .append_if(tail, &Record::put(b"b".as_slice(), b"1".as_slice()))
+            .await
+            .unwrap_err();
+        let actual = wal.current_position().await;
+        assert!(
+            matches!(err, SegmentError::TailMoved { expected, actual: a } if expected == tail && a == actual)
+        );
+
+        // Writers that retry on conflict never lose an update
+        let wal = Arc::new(wal);
+        let mut tasks = Vec::new();
+        for _ in 0..4 {
+            let wal = wal.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..25 {
+                    loop {
+                        let tail = wal.current_position().await;
+                        let record = Record::put(b"counter".as_slice(), b"+1".as_slice());
+                        match wal.append_if(tail, &record).await {
+                            Ok(_) => break,
+                            Err(SegmentError::TailMoved { .. }) => continue,
+                            Err(e) => panic!("{}", e),
+                        }
+                    }
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(wal.next_lsn(), 102);
+
+        // A rotation alone doesn't count as the tail moving
+        let big = vec![0u8; 600 * 1024];
+        let tail = wal.current_position().await;
+        wal.append_if(tail, &Record::put(b"big".as_slice(), big.clone()))
+            .await
+            .unwrap();
+        let tail = wal.current_position().await;
+        let pos = wal
+            .append_if(tail, &Record::put(b"big".as_slice(), big))
+            .await
+            .unwrap();
+        assert_eq!(
+            pos,
+            Position {
+                segment_id: tail.segment_id + 1,
+                offset: 0
+            }
+        );
+    }
 }
@@ -0,0 +1,169 @@
+//! The [`WalLog`] trait: the log operations code embedding the WAL relies on.
+//!
+//! [`Wal`] implements it over segment files, and [`MemWal`](crate::mem::MemWal)
+//! keeps the log in memory, so components written against `WalLog` can be
+//! unit-tested without a temporary directory or real I/O.
+
+use crate::reader::WalReader;
+use crate::record::Record;
+use crate::segment::{Position, SegmentError};
+use crate::wal::Wal;
+use std::future::Future;
+
+/// An append-only log of records, addressed by [`Position`] and numbered by
+/// LSN.
+pub trait WalLog: Send + Sync {
+    /// Reader returned by [`WalLog::read_from`].
+    type Reader: LogReader;
+
+    /// Appends a record, returning the position it was written at.
+    fn append(
+        &self,
+        record: &Record,
+    ) -> impl Future<Output = Result<Position, SegmentError>> + Send;
+
+    /// Appends records as one write, returning their positions.
+    fn append_batch(
+        &self,
+        records: &[Record],
+    ) -> impl Future<Output = Result<Vec<Position>, SegmentError>> + Send;
+
+    /// Makes every record appended so far durable.
+    fn sync(&self) -> impl Future<Output = Result<(), SegmentError>> + Send;
+
+    /// Returns the position the next record will be written at.
+    fn current_position(&self) -> impl Future<Output = Position> + Send;
+
+    /// Returns the position up to which appended records are durable.
+    fn durable_position(&self) -> impl Future<Output = Position> + Send;
+
+    /// Returns the LSN that the next appended record will be assigned.
+    fn next_lsn(&self) -> u64;
+
+    /// Returns a reader that follows the log from `position` up to its
+    /// durable end.
+    fn read_from(
+        &self,
+        position: Position,
+    ) -> impl Future<Output = Result<Self::Reader, SegmentError>> + Send;
+
+    /// Discards every record at and after `position`, which must be a record
+    /// boundary, and rewinds `next_lsn()` to the first discarded LSN.
+    /// Returns the number of bytes discarded.
+    fn truncate_from(
+        &self,
+        position: Position,
+    ) -> impl Future<Output = Result<u64, SegmentError>> + Send;
+
+    /// Deletes whole segments before `position`, returning how many went.
+    fn delete_segments_before(
+        &self,
+        position: Position,
+    ) -> impl Future<Output = Result<u64, SegmentError>> + Send;
+}
+
+/// Sequential reader over a [`WalLog`].
+pub trait LogReader: Send {
+    /// Returns the next record and its position, or `None` at the durable end
+    /// of the log.
+    fn next_record(
+        &mut self,
+    ) -> impl Future<Output = Result<Option<(Record, Position)>, SegmentError>> + Send;
+}
+
+/// `read_from` here follows the log across segments, like [`Wal::reader`];
+/// the inherent [`Wal::read_from`] stops at the end of one segment.
+impl WalLog for Wal {
+    type Reader = WalReader;
+
+    async fn append(&self, record: &Record) -> Result<Position, SegmentError> {
+        Wal::append(self, record).await
+    }
+
+    async fn append_batch(&self, records: &[Record]) -> Result<Vec<Position>, SegmentError> {
+        Wal::append_batch(self, records).await
+    }
+
+    async fn sync(&self) -> Result<(), SegmentError> {
+        Wal::sync(self).await
+    }
+
+    async fn current_position(&self) -> Position {
+        Wal::current_position(self).await
+    }
+
+    async fn durable_position(&self) -> Position {
+        Wal::durable_position(self).await
+    }
+
+    fn next_lsn(&self) -> u64 {
+        Wal::next_lsn(self)
+    }
+
+    async fn read_from(&self, position: Position) -> Result<WalReader, SegmentError> {
+        Ok(self.reader(position))
+    }
+
+    async fn truncate_from(&self, position: Position) -> Result<u64, SegmentError> {
+        Wal::truncate_from(self, position).await
+    }
+
+    async fn delete_segments_before(&self, position: Position) -> Result<u64, SegmentError> {
+        Wal::delete_segments_before(self, position).await
+    }
+}
+
+impl LogReader for WalReader {
+    async fn next_record(&mut self) -> Result<Option<(Record, Position)>, SegmentError> {
+        WalReader::next_record(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::MemWal;
+    use crate::wal::WalConfig;
+    use tempfile::TempDir;
+
+    /// Exercises a log only through the trait.
+    async fn append_and_read<L: WalLog>(log: &L) -> Vec<(Record, Position)> {
+        let start = log.current_position().await;
+        for i in 0..3 {
+            let key = format!("key{}", i);
+            log.append(&Record::put(bytes::Bytes::from(key), b"v".as_slice()))
+                .await
+                .unwrap();
+        }
+        log.sync().await.unwrap();
+        assert_eq!(log.durable_position().await, log.current_position().await);
+        assert_eq!(log.next_lsn(), 4);
+
+        let mut reader = log.read_from(start).await.unwrap();
+        let mut records = Vec::new();
+        while let Some(entry) = reader.next_record().await.unwrap() {
+            records.push(entry);
+        }
+        records
+    }
+
+    #[tokio::test]
+    async fn test_wal_and_mem_wal_agree() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalConfig {
+            dir: temp_dir.path().to_path_buf(),
+            preallocate: false,
+            ..Default::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+
+        let from_disk = append_and_read(&wal).await;
+        let from_memory = append_and_read(&MemWal::new()).await;
+        assert_eq!(from_disk.len(), 3);
+        for ((disk, disk_pos), (mem, mem_pos)) in from_disk.iter().zip(&from_memory) {
+            assert_eq!(disk_pos, mem_pos);
+            assert_eq!(disk.key, mem.key);
+            assert_eq!(disk.lsn, mem.lsn);
+        }
+    }
+}
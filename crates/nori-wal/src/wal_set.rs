@@ -0,0 +1,623 @@
+//! Many WALs, one per shard, under a single root directory.
+//!
+//! A sharded system keeps an independent log per shard so shards can move,
+//! truncate and recover on their own. [`WalSet`] owns those logs: each shard
+//! gets its own [`Wal`] in `root/shard-NNNNNN/`, created on first use, and
+//! all of them share one [`Meter`], one background sync task and one
+//! [`Retention`] policy. [`WalSet::commit_transaction`] writes to several
+//! shards atomically (see [`crate::transaction`]).
+//!
+//! ```no_run
+//! use nori_wal::{FsyncPolicy, Record, WalConfig, WalSet, WalSetConfig};
+//! use std::time::Duration;
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let config = WalSetConfig {
+//!     root: "/var/lib/app/wal".into(),
+//!     wal: WalConfig {
+//!         fsync_policy: FsyncPolicy::Os,
+//!         ..Default::default()
+//!     },
+//!     sync_interval: Some(Duration::from_millis(5)),
+//!     ..Default::default()
+//! };
+//! let (wals, _recovered) = WalSet::open(config).await?;
+//! wals.append(7, &Record::put(b"key".as_slice(), b"value".as_slice()))
+//!     .await?;
+//! wals.close().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::clock::SystemClock;
+use crate::fs::LocalFs;
+use crate::record::Record;
+use crate::recovery::RecoveryInfo;
+use crate::runtime::{Runtime, Task, TokioRuntime};
+use crate::segment::{Position, SegmentError};
+use crate::transaction::{
+    self, CommittedTransaction, Coordinator, TransactionRecovery, COORDINATOR_DIR, MARKER_PREFIX,
+};
+use crate::wal::{Wal, WalConfig};
+use nori_observe::{Meter, NoopMeter};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{OnceCell, RwLock};
+
+const SHARD_DIR_PREFIX: &str = "shard-";
+
+type Shards = RwLock<BTreeMap<u32, Arc<Wal>>>;
+
+/// Which segments a [`WalSet`] deletes from each shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retention {
+    /// Never delete segments (default).
+    #[default]
+    KeepAll,
+    /// Delete segments wholly before each shard's last checkpoint.
+    Checkpointed,
+    /// Keep this many of each shard's most recent segments, counting the
+    /// active one, whether or not they are checkpointed.
+    Segments(u64),
+}
+
+/// Configuration for a [`WalSet`].
+#[derive(Debug, Clone)]
+pub struct WalSetConfig {
+    /// Directory holding one subdirectory per shard.
+    pub root: PathBuf,
+    /// Settings for every shard's WAL. `dir` is replaced with the shard's
+    /// own directory.
+    pub wal: WalConfig,
+    /// Fsync every shard with unsynced appends at this interval from a
+    /// single background task, usually paired with [`FsyncPolicy::Os`] in
+    /// `wal` (default: None, each shard follows its own fsync policy).
+    ///
+    /// [`FsyncPolicy::Os`]: crate::FsyncPolicy::Os
+    pub sync_interval: Option<Duration>,
+    /// Segment retention, applied after each background sync and by
+    /// [`WalSet::enforce_retention`] (default: keep everything).
+    pub retention: Retention,
+}
+
+impl Default for WalSetConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("wal"),
+            wal: WalConfig::default(),
+            sync_interval: None,
+            retention: Retention::KeepAll,
+        }
+    }
+}
+
+/// A set of per-shard WALs. See the [module docs](self).
+pub struct WalSet {
+    config: WalSetConfig,
+    meter: Arc<dyn Meter>,
+    runtime: Arc<dyn Runtime>,
+    shards: Arc<Shards>,
+    task: Option<Box<dyn Task>>,
+    /// Log of cross-shard transactions, opened on first use.
+    coordinator: OnceCell<Coordinator>,
+    recovered_transactions: TransactionRecovery,
+}
+
+impl WalSet {
+    /// Opens every shard found under the root directory, performing
+    /// recovery on each, and returns their recovery info by shard ID.
+    pub async fn open(
+        config: WalSetConfig,
+    ) -> Result<(Self, BTreeMap<u32, RecoveryInfo>), SegmentError> {
+        Self::open_with_meter(config, Arc::new(NoopMeter)).await
+    }
+
+    /// Opens the set with a meter shared by every shard.
+    pub async fn open_with_meter(
+        config: WalSetConfig,
+        meter: Arc<dyn Meter>,
+    ) -> Result<(Self, BTreeMap<u32, RecoveryInfo>), SegmentError> {
+        tokio::fs::create_dir_all(&config.root).await?;
+        let runtime: Arc<dyn Runtime> = Arc::new(TokioRuntime);
+
+        let mut ids = Vec::new();
+        let mut entries = tokio::fs::read_dir(&config.root).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(SHARD_DIR_PREFIX))
+                .and_then(|id| id.parse::<u32>().ok());
+            if let Some(id) = id {
+                if entry.file_type().await?.is_dir() {
+                    ids.push(id);
+                }
+            }
+        }
+
+        let mut set = Self {
+            config,
+            meter,
+            runtime,
+            shards: Arc::new(RwLock::new(BTreeMap::new())),
+            task: None,
+            coordinator: OnceCell::new(),
+            recovered_transactions: TransactionRecovery::default(),
+        };
+        let mut recovered = BTreeMap::new();
+        {
+            let mut shards = set.shards.write().await;
+            for id in ids {
+                let (wal, info) = set.open_wal(id).await?;
+                shards.insert(id, Arc::new(wal));
+                recovered.insert(id, info);
+            }
+        }
+
+        // Transactions in doubt are resolved before anything else is appended
+        if tokio::fs::try_exists(set.config.root.join(COORDINATOR_DIR)).await? {
+            let (coordinator, in_doubt) =
+                Coordinator::open(set.open_coordinator_wal().await?).await?;
+            let shards = set.shards.read().await;
+            let resolved = transaction::resolve(&coordinator, &shards, in_doubt).await?;
+            drop(shards);
+            set.recovered_transactions = resolved;
+            let _ = set.coordinator.set(coordinator);
+        }
+
+        if let Some(interval) = set.config.sync_interval {
+            set.task = Some(set.runtime.spawn(Box::pin(run_maintenance(
+                Arc::downgrade(&set.shards),
+                set.runtime.clone(),
+                interval,
+                set.config.retention,
+            ))));
+        }
+        Ok((set, recovered))
+    }
+
+    /// Returns the directory holding `shard`'s segments.
+    pub fn shard_dir(&self, shard: u32) -> PathBuf {
+        self.config
+            .root
+            .join(format!("{}{:06}", SHARD_DIR_PREFIX, shard))
+    }
+
+    /// Returns the WAL for `shard` if it exists.
+    pub async fn shard(&self, shard: u32) -> Option<Arc<Wal>> {
+        self.shards.read().await.get(&shard).cloned()
+    }
+
+    /// Returns the WAL for `shard`, creating it if needed.
+    pub async fn open_shard(&self, shard: u32) -> Result<Arc<Wal>, SegmentError> {
+        if let Some(wal) = self.shard(shard).await {
+            return Ok(wal);
+        }
+        let mut shards = self.shards.write().await;
+        // Another caller may have created it while we waited for the lock
+        if let Some(wal) = shards.get(&shard) {
+            return Ok(wal.clone());
+        }
+        let (wal, _) = self.open_wal(shard).await?;
+        let wal = Arc::new(wal);
+        shards.insert(shard, wal.clone());
+        Ok(wal)
+    }
+
+    /// Returns the IDs of every open shard, in ascending order.
+    pub async fn shard_ids(&self) -> Vec<u32> {
+        self.shards.read().await.keys().copied().collect()
+    }
+
+    /// Appends a record to `shard`'s WAL, creating it if needed.
+    pub async fn append(&self, shard: u32, record: &Record) -> Result<Position, SegmentError> {
+        self.open_shard(shard).await?.append(record).await
+    }
+
+    /// Appends records to `shard`'s WAL as one write, creating it if needed.
+    pub async fn append_batch(
+        &self,
+        shard: u32,
+        records: &[Record],
+    ) -> Result<Vec<Position>, SegmentError> {
+        self.open_shard(shard).await?.append_batch(records).await
+    }
+
+    /// Appends each shard's records from `writes`, given as `(shard,
+    /// record)`, so that either every shard gets its records or none does,
+    /// creating shards as needed. See [`crate::transaction`].
+    ///
+    /// A failure before the commit decision aborts the transaction. Once it
+    /// is committed, a failure returns [`SegmentError::InDoubt`]: the records
+    /// are applied to the remaining shards when the set is next opened.
+    pub async fn commit_transaction(
+        &self,
+        writes: &[(u32, Record)],
+    ) -> Result<CommittedTransaction, SegmentError> {
+        if writes.is_empty() {
+            return Err(SegmentError::InvalidConfig(
+                "a transaction needs at least one record".into(),
+            ));
+        }
+        let mut by_shard: BTreeMap<u32, Vec<Record>> = BTreeMap::new();
+        for (shard, record) in writes {
+            if record.key.starts_with(MARKER_PREFIX) {
+                return Err(SegmentError::InvalidConfig(
+                    "keys starting with the transaction marker prefix are reserved".into(),
+                ));
+            }
+            by_shard.entry(*shard).or_default().push(record.clone());
+        }
+        let mut shards = Vec::with_capacity(by_shard.len());
+        for (shard, records) in by_shard {
+            shards.push((shard, self.open_shard(shard).await?, records));
+        }
+        transaction::commit(self.coordinator().await?, &shards).await
+    }
+
+    /// Returns the transactions that [`open`](Self::open) found in doubt and
+    /// resolved.
+    pub fn recovered_transactions(&self) -> &TransactionRecovery {
+        &self.recovered_transactions
+    }
+
+    /// Returns the transaction coordinator, opening its log if needed.
+    pub(crate) async fn coordinator(&self) -> Result<&Coordinator, SegmentError> {
+        self.coordinator
+            .get_or_try_init(|| async {
+                // Anything left in doubt was resolved when the set opened
+                let (coordinator, _) =
+                    Coordinator::open(self.open_coordinator_wal().await?).await?;
+                Ok(coordinator)
+            })
+            .await
+    }
+
+    /// Syncs every shard to disk (fsync).
+    pub async fn sync(&self) -> Result<(), SegmentError> {
+        for wal in self.snapshot().await {
+            wal.sync().await?;
+        }
+        Ok(())
+    }
+
+    /// Applies the retention policy to every shard now. Returns the number
+    /// of segments deleted.
+    pub async fn enforce_retention(&self) -> Result<u64, SegmentError> {
+        let mut deleted = 0;
+        for wal in self.snapshot().await {
+            deleted += apply_retention(&wal, self.config.retention).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Closes `shard`'s WAL and deletes its directory. Returns false if the
+    /// shard did not exist.
+    ///
+    /// Fails without removing anything if a handle returned by
+    /// [`WalSet::shard`] is still alive.
+    pub async fn remove_shard(&self, shard: u32) -> Result<bool, SegmentError> {
+        let mut shards = self.shards.write().await;
+        let Some(wal) = shards.remove(&shard) else {
+            return Ok(false);
+        };
+        let wal = match Arc::try_unwrap(wal) {
+            Ok(wal) => wal,
+            Err(wal) => {
+                shards.insert(shard, wal);
+                return Err(SegmentError::InvalidConfig(format!(
+                    "shard {} is still in use",
+                    shard
+                )));
+            }
+        };
+        wal.close().await?;
+        tokio::fs::remove_dir_all(self.shard_dir(shard)).await?;
+        Ok(true)
+    }
+
+    /// Stops the background task and closes every shard.
+    ///
+    /// Shards whose handles are still held elsewhere are synced instead, and
+    /// close when the last handle is dropped. Every shard is attempted even
+    /// if one fails, and the first error is returned.
+    pub async fn close(mut self) -> Result<(), SegmentError> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            task.join().await;
+        }
+
+        let shards = std::mem::take(&mut *self.shards.write().await);
+        let mut result = match self.coordinator.take() {
+            Some(coordinator) => coordinator.close().await,
+            None => Ok(()),
+        };
+        for wal in shards.into_values() {
+            let closed = match Arc::try_unwrap(wal) {
+                Ok(wal) => wal.close().await,
+                Err(wal) => wal.sync().await,
+            };
+            result = result.and(closed);
+        }
+        result
+    }
+
+    async fn open_wal(&self, shard: u32) -> Result<(Wal, RecoveryInfo), SegmentError> {
+        let config = WalConfig {
+            dir: self.shard_dir(shard),
+            ..self.config.wal.clone()
+        };
+        Wal::open_inner(
+            config,
+            self.meter.clone(),
+            self.runtime.clone(),
+            Arc::new(LocalFs),
+            None,
+            Arc::new(SystemClock),
+            None,
+        )
+        .await
+    }
+
+    async fn open_coordinator_wal(&self) -> Result<Wal, SegmentError> {
+        let config = WalConfig {
+            dir: self.config.root.join(COORDINATOR_DIR),
+            max_segment_size: 1024 * 1024,
+            // Compaction would keep only the newest entry of each transaction
+            compaction_interval: None,
+            namespace_quotas: Default::default(),
+            record_cache_bytes: 0,
+            ..self.config.wal.clone()
+        };
+        let (wal, _) = Wal::open_inner(
+            config,
+            self.meter.clone(),
+            self.runtime.clone(),
+            Arc::new(LocalFs),
+            None,
+            Arc::new(SystemClock),
+            None,
+        )
+        .await?;
+        Ok(wal)
+    }
+
+    async fn snapshot(&self) -> Vec<Arc<Wal>> {
+        self.shards.read().await.values().cloned().collect()
+    }
+}
+
+impl Drop for WalSet {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn apply_retention(wal: &Wal, retention: Retention) -> Result<u64, SegmentError> {
+    let before = match retention {
+        Retention::KeepAll => return Ok(0),
+        Retention::Checkpointed => match wal.last_checkpoint().await {
+            Some(checkpoint) => checkpoint.position,
+            None => return Ok(0),
+        },
+        Retention::Segments(keep) => {
+            let active = wal.current_position().await.segment_id;
+            Position {
+                segment_id: (active + 1).saturating_sub(keep.max(1)),
+                offset: 0,
+            }
+        }
+    };
+    let deleted = wal.purge_segments_before(before, "retention").await?;
+    if deleted > 0 {
+        wal.emit_retention_enforced(before.segment_id, deleted);
+    }
+    Ok(deleted)
+}
+
+/// Background task: syncs shards with unsynced appends, then applies
+/// retention, once per `interval` until the set is gone.
+async fn run_maintenance(
+    shards: Weak<Shards>,
+    runtime: Arc<dyn Runtime>,
+    interval: Duration,
+    retention: Retention,
+) {
+    loop {
+        runtime.sleep(interval).await;
+        let Some(shards) = shards.upgrade() else {
+            return;
+        };
+        let wals: Vec<_> = shards.read().await.values().cloned().collect();
+        drop(shards);
+
+        for wal in wals {
+            // Errors resurface on the next explicit sync or append
+            if wal.durable_position().await < wal.current_position().await {
+                let _ = wal.sync().await;
+            }
+            let _ = apply_retention(&wal, retention).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segment::FsyncPolicy;
+    use tempfile::TempDir;
+
+    fn config(root: &std::path::Path) -> WalSetConfig {
+        WalSetConfig {
+            root: root.to_path_buf(),
+            wal: WalConfig {
+                max_segment_size: 1024 * 1024,
+                fsync_policy: FsyncPolicy::Os,
+                preallocate: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_and_recovers_shards() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wals, recovered) = WalSet::open(config(temp_dir.path())).await.unwrap();
+        assert!(recovered.is_empty());
+
+        for shard in [3, 1, 2] {
+            for i in 0..shard {
+                let key = format!("{}/{}", shard, i);
+                wals.append(shard, &Record::put(key, b"v".as_slice()))
+                    .await
+                    .unwrap();
+            }
+        }
+        assert_eq!(wals.shard_ids().await, [1, 2, 3]);
+        assert!(wals.shard_dir(2).ends_with("shard-000002"));
+        assert!(wals.shard(4).await.is_none());
+        wals.close().await.unwrap();
+
+        let (wals, recovered) = WalSet::open(config(temp_dir.path())).await.unwrap();
+        let counts: Vec<_> = recovered
+            .iter()
+            .map(|(&shard, info)| (shard, info.valid_records))
+            .collect();
+        assert_eq!(counts, [(1, 1), (2, 2), (3, 3)]);
+
+        // Removing a shard deletes it, but not while a handle is alive
+        let handle = wals.shard(2).await.unwrap();
+        assert!(wals.remove_shard(2).await.is_err());
+        drop(handle);
+        assert!(wals.remove_shard(2).await.unwrap());
+        assert!(!wals.shard_dir(2).exists());
+        assert_eq!(wals.shard_ids().await, [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_background_sync_and_retention() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = WalSetConfig {
+            sync_interval: Some(Duration::from_millis(5)),
+            retention: Retention::Segments(2),
+            ..config(temp_dir.path())
+        };
+        let (wals, _) = WalSet::open(config).await.unwrap();
+
+        let value = vec![0u8; 600 * 1024];
+        for _ in 0..6 {
+            wals.append(0, &Record::put(b"k".as_slice(), value.clone()))
+                .await
+                .unwrap();
+        }
+        let wal = wals.shard(0).await.unwrap();
+
+        // The Os policy never syncs by itself, so only the background task can
+        for _ in 0..100 {
+            if wal.durable_position().await == wal.current_position().await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(wal.durable_position().await, wal.current_position().await);
+        // Retention runs right after the sync in the same pass
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let active = wal.current_position().await.segment_id;
+        assert!(active >= 2);
+        assert!(!wals
+            .shard_dir(0)
+            .join(format!("{:06}.wal", active - 2))
+            .exists());
+        assert!(wals
+            .shard_dir(0)
+            .join(format!("{:06}.wal", active - 1))
+            .exists());
+
+        drop(wal);
+        wals.close().await.unwrap();
+    }
+
+    async fn read_shard(wals: &WalSet, shard: u32) -> Vec<Record> {
+        let wal = wals.shard(shard).await.unwrap();
+        let mut reader = wal.reader(Position {
+            segment_id: 0,
+            offset: 0,
+        });
+        let mut records = Vec::new();
+        while let Some((record, _)) = reader.next_record().await.unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    #[tokio::test]
+    async fn test_transactions_span_shards() {
+        let temp_dir = TempDir::new().unwrap();
+        let (wals, _) = WalSet::open(config(temp_dir.path())).await.unwrap();
+        wals.append(1, &Record::put("before", "v")).await.unwrap();
+
+        let writes = [
+            (1, Record::put("from", "-10")),
+            (2, Record::put("to", "+10")),
+            (1, Record::put("log", "transfer")),
+        ];
+        let committed = wals.commit_transaction(&writes).await.unwrap();
+        assert_eq!(committed.positions[&1].len(), 2);
+        assert_eq!(committed.positions[&2].len(), 1);
+        assert!(matches!(
+            wals.commit_transaction(&[]).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+        let reserved = Record::put([MARKER_PREFIX, b"x"].concat(), "v");
+        assert!(wals.commit_transaction(&[(1, reserved)]).await.is_err());
+
+        // Each shard holds its prepare and commit markers, then its records
+        let keys = |records: Vec<Record>| -> Vec<String> {
+            records
+                .iter()
+                .map(|r| match transaction::parse_marker(r) {
+                    Some((id, kind)) => format!("{:?} {}", kind, id),
+                    None => String::from_utf8_lossy(&r.key).into_owned(),
+                })
+                .collect()
+        };
+        let id = committed.id;
+        assert_eq!(
+            keys(read_shard(&wals, 1).await),
+            [
+                "before".to_string(),
+                format!("Prepare {}", id),
+                format!("Commit {}", id),
+                "from".into(),
+                "log".into()
+            ]
+        );
+        assert_eq!(
+            keys(read_shard(&wals, 2).await),
+            [
+                format!("Prepare {}", id),
+                format!("Commit {}", id),
+                "to".into()
+            ]
+        );
+        wals.close().await.unwrap();
+
+        // Nothing was left in doubt, and IDs carry on after reopening
+        let (wals, _) = WalSet::open(config(temp_dir.path())).await.unwrap();
+        assert_eq!(
+            wals.recovered_transactions(),
+            &TransactionRecovery::default()
+        );
+        let next = wals
+            .commit_transaction(&[(3, Record::put("k", "v"))])
+            .await
+            .unwrap();
+        assert!(next.id > id);
+        wals.close().await.unwrap();
+    }
+}
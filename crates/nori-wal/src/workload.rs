@@ -0,0 +1,346 @@
+//! Synthetic append workloads, for benchmarks and `nori-wal bench`.
+//!
+//! A [`Workload`] says how many records to append, how big they are and
+//! how many writers append them; [`run`] drives it against a WAL and
+//! reports throughput along with the WAL's own latency percentiles:
+//!
+//! ```no_run
+//! use nori_wal::workload::{self, SizeDistribution, Workload};
+//! use nori_wal::{Wal, WalConfig};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> Result<(), nori_wal::SegmentError> {
+//! let (wal, _) = Wal::open(WalConfig::default()).await?;
+//! let workload = Workload {
+//!     records: 50_000,
+//!     writers: 4,
+//!     sizes: "100:9,8192:1".parse().unwrap(),
+//!     ..Workload::default()
+//! };
+//! let report = workload::run(Arc::new(wal), &workload).await?;
+//! println!("{:.0} records/s, p99 {:?}", report.records_per_sec(), report.append_latency.p99);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Keys and values come from a seeded generator, so a workload appends the
+//! same records every time it runs.
+
+use crate::metrics::LatencySummary;
+use crate::record::Record;
+use crate::segment::SegmentError;
+use crate::wal::Wal;
+use bytes::Bytes;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Value sizes a workload draws from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SizeDistribution {
+    /// Every value is this many bytes. Parsed from `"256"`.
+    Fixed(usize),
+    /// Sizes spread evenly from `min` to `max`, both included. Parsed from
+    /// `"64..4096"`.
+    Uniform { min: usize, max: usize },
+    /// Sizes picked in proportion to their weights. Parsed from
+    /// `"100:9,8192:1"` (size:weight, comma separated).
+    Weighted(Vec<(usize, u32)>),
+}
+
+impl Default for SizeDistribution {
+    fn default() -> Self {
+        SizeDistribution::Fixed(256)
+    }
+}
+
+impl SizeDistribution {
+    /// Largest size the distribution can produce.
+    pub fn max(&self) -> usize {
+        match self {
+            SizeDistribution::Fixed(size) => *size,
+            SizeDistribution::Uniform { max, .. } => *max,
+            SizeDistribution::Weighted(sizes) => sizes.iter().map(|&(s, _)| s).max().unwrap_or(0),
+        }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> usize {
+        match self {
+            SizeDistribution::Fixed(size) => *size,
+            SizeDistribution::Uniform { min, max } => {
+                min + (rng.next() % (max - min + 1) as u64) as usize
+            }
+            SizeDistribution::Weighted(sizes) => {
+                let total: u64 = sizes.iter().map(|&(_, w)| u64::from(w)).sum();
+                let mut pick = rng.next() % total;
+                for &(size, weight) in sizes {
+                    if pick < u64::from(weight) {
+                        return size;
+                    }
+                    pick -= u64::from(weight);
+                }
+                unreachable!("pick is below the total weight")
+            }
+        }
+    }
+}
+
+impl fmt::Display for SizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeDistribution::Fixed(size) => write!(f, "{}", size),
+            SizeDistribution::Uniform { min, max } => write!(f, "{}..{}", min, max),
+            SizeDistribution::Weighted(sizes) => {
+                for (i, (size, weight)) in sizes.iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    write!(f, "{}{}:{}", sep, size, weight)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Error returned when parsing a [`SizeDistribution`] fails.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid size distribution {0:?}: expected SIZE, MIN..MAX or SIZE:WEIGHT,...")]
+pub struct ParseSizesError(String);
+
+impl FromStr for SizeDistribution {
+    type Err = ParseSizesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSizesError(s.to_string());
+        if let Some((min, max)) = s.split_once("..") {
+            let min = min.trim().parse().map_err(|_| err())?;
+            let max = max.trim().parse().map_err(|_| err())?;
+            if min > max {
+                return Err(err());
+            }
+            return Ok(SizeDistribution::Uniform { min, max });
+        }
+        if s.contains(':') {
+            let sizes = s
+                .split(',')
+                .map(|part| {
+                    let (size, weight) = part.split_once(':')?;
+                    Some((size.trim().parse().ok()?, weight.trim().parse().ok()?))
+                })
+                .collect::<Option<Vec<(usize, u32)>>>()
+                .ok_or_else(err)?;
+            if sizes.iter().all(|&(_, w)| w == 0) {
+                return Err(err());
+            }
+            return Ok(SizeDistribution::Weighted(sizes));
+        }
+        s.trim()
+            .parse()
+            .map(SizeDistribution::Fixed)
+            .map_err(|_| err())
+    }
+}
+
+/// What to append. The fsync policy and segment size are the WAL's own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Workload {
+    /// Records to append across all writers (default: 100,000).
+    pub records: u64,
+    /// Concurrent writers, each appending its share (default: 1).
+    pub writers: usize,
+    /// Records per `append_batch` call; 1 uses `append` (default: 1).
+    pub batch: usize,
+    /// Value sizes (default: 256 bytes each).
+    pub sizes: SizeDistribution,
+    /// Distinct keys, drawn at random (default: 1,000,000).
+    pub key_space: u64,
+    /// Seed for keys and sizes (default: 0).
+    pub seed: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            records: 100_000,
+            writers: 1,
+            batch: 1,
+            sizes: SizeDistribution::default(),
+            key_space: 1_000_000,
+            seed: 0,
+        }
+    }
+}
+
+/// Outcome of [`run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkloadReport {
+    pub records: u64,
+    /// Key and value bytes appended.
+    pub bytes: u64,
+    /// Wall-clock time from the first append to the last.
+    pub elapsed: Duration,
+    /// Fsyncs the WAL made while the workload ran.
+    pub fsyncs: u64,
+    /// Per-call latency, from the WAL's metrics. With `batch` above 1 this
+    /// is per batch.
+    pub append_latency: LatencySummary,
+    pub fsync_latency: LatencySummary,
+}
+
+impl WorkloadReport {
+    pub fn records_per_sec(&self) -> f64 {
+        self.records as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// xorshift64*: fast, and good enough to spread keys and sizes.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+/// Appends `workload` to `wal` and reports how it went.
+///
+/// Latency percentiles come from [`Wal::metrics`], which covers everything
+/// appended since the WAL was opened, so run workloads against a fresh WAL
+/// with nothing else writing to it. Writers are spawned as tasks, so they
+/// only run in parallel on a multi-threaded runtime.
+pub async fn run(wal: Arc<Wal>, workload: &Workload) -> Result<WorkloadReport, SegmentError> {
+    if workload.writers == 0 || workload.batch == 0 || workload.key_space == 0 {
+        return Err(SegmentError::InvalidConfig(
+            "workload writers, batch and key space must be at least 1".to_string(),
+        ));
+    }
+
+    // Values are slices of one random buffer, so generating them costs
+    // nothing and they don't compress
+    let mut rng = Rng::new(workload.seed);
+    let pool: Bytes = (0..workload.sizes.max())
+        .map(|_| rng.next() as u8)
+        .collect::<Vec<_>>()
+        .into();
+    let fsyncs_before = wal.metrics().await.fsyncs;
+
+    let started = Instant::now();
+    let mut writers = tokio::task::JoinSet::new();
+    for writer in 0..workload.writers as u64 {
+        let share = workload.records / workload.writers as u64
+            + u64::from(writer < workload.records % workload.writers as u64);
+        let wal = Arc::clone(&wal);
+        let workload = workload.clone();
+        let pool = pool.clone();
+        writers.spawn(async move {
+            let mut rng = Rng::new(workload.seed.wrapping_add(writer + 1));
+            let mut bytes = 0;
+            let mut batch = Vec::with_capacity(workload.batch);
+            for i in 0..share {
+                let key = format!("key{:016}", rng.next() % workload.key_space);
+                let size = workload.sizes.sample(&mut rng);
+                bytes += (key.len() + size) as u64;
+                batch.push(Record::put(key, pool.slice(..size)));
+                if batch.len() == workload.batch || i + 1 == share {
+                    if let [record] = batch.as_slice() {
+                        wal.append(record).await?;
+                    } else {
+                        wal.append_batch(&batch).await?;
+                    }
+                    batch.clear();
+                }
+            }
+            Ok::<_, SegmentError>(bytes)
+        });
+    }
+    let mut bytes = 0;
+    while let Some(joined) = writers.join_next().await {
+        bytes += joined.map_err(|e| SegmentError::Io(std::io::Error::other(e)))??;
+    }
+    let elapsed = started.elapsed();
+
+    let metrics = wal.metrics().await;
+    Ok(WorkloadReport {
+        records: workload.records,
+        bytes,
+        elapsed,
+        fsyncs: metrics.fsyncs - fsyncs_before,
+        append_latency: metrics.append_latency,
+        fsync_latency: metrics.fsync_latency,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WalConfig;
+
+    #[test]
+    fn test_size_distributions_parse_and_sample() {
+        for s in ["256", "64..4096", "100:9,8192:1"] {
+            let sizes: SizeDistribution = s.parse().unwrap();
+            assert_eq!(sizes.to_string(), s);
+        }
+        for bad in ["", "abc", "10..5", "100:0", "100:x,5:1", "1..2..3"] {
+            assert!(bad.parse::<SizeDistribution>().is_err(), "{}", bad);
+        }
+
+        let mut rng = Rng::new(7);
+        let uniform = SizeDistribution::Uniform { min: 10, max: 12 };
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            seen[uniform.sample(&mut rng) - 10] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+
+        let weighted: SizeDistribution = "1:3,2:1".parse().unwrap();
+        let ones = (0..4000).filter(|_| weighted.sample(&mut rng) == 1).count();
+        assert!((2700..3300).contains(&ones), "{}", ones);
+    }
+
+    #[tokio::test]
+    async fn test_run_appends_the_workload() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = WalConfig {
+            dir: dir.path().to_path_buf(),
+            ..WalConfig::default()
+        };
+        let (wal, _) = Wal::open(config).await.unwrap();
+        let wal = Arc::new(wal);
+        let workload = Workload {
+            records: 101,
+            writers: 3,
+            batch: 4,
+            sizes: "0..100".parse().unwrap(),
+            key_space: 10,
+            ..Workload::default()
+        };
+        let report = run(Arc::clone(&wal), &workload).await.unwrap();
+        assert_eq!(report.records, 101);
+        assert_eq!(wal.metrics().await.appends, 101);
+        assert!(report.append_latency.count > 0);
+        assert!(report.records_per_sec() > 0.0);
+
+        let bad = Workload {
+            writers: 0,
+            ..Workload::default()
+        };
+        assert!(matches!(
+            run(wal, &bad).await,
+            Err(SegmentError::InvalidConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+//! Crash-injection harness for recovery.
+//!
+//! Each scenario arms a failpoint, drives a workload until the point fires,
+//! then abandons the WAL without closing it, so no finalization or fsync runs
+//! after the "crash". The WAL is reopened and checked against the recovery
+//! invariants:
+//!
+//! - every acknowledged, fsynced record survives;
+//! - the recovered log is a prefix of what was attempted, in order;
+//! - LSNs are dense from 1;
+//! - the recovered WAL accepts appends and reopens to the same state.
+//!
+//! Run with `cargo test -p nori-wal --features failpoints --test crash_recovery`.
+
+use bytes::Bytes;
+use fail::FailScenario;
+use nori_wal::failpoint;
+use nori_wal::{FsyncPolicy, Position, Record, RecoveryMode, Wal, WalConfig};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Records per workload; with 64 KiB values this spans several segments.
+const RECORDS: usize = 40;
+
+fn config(dir: &Path) -> WalConfig {
+    WalConfig {
+        dir: dir.to_path_buf(),
+        max_segment_size: 1024 * 1024,
+        fsync_policy: FsyncPolicy::Always,
+        seal_segments: true,
+        ..Default::default()
+    }
+}
+
+fn record(i: usize) -> Record {
+    Record::put(
+        Bytes::from(format!("key{:04}", i)),
+        vec![i as u8; 64 * 1024],
+    )
+}
+
+/// Simulates a crash: the WAL is leaked rather than dropped, so nothing
+/// finalizes the active segment or cancels in-flight background work.
+fn crash(wal: Wal) {
+    failpoint::crash(wal);
+}
+
+/// Appends records until one fails, returning how many were acknowledged.
+async fn run_workload(wal: &Wal) -> usize {
+    for i in 0..RECORDS {
+        if wal.append(&record(i)).await.is_err() {
+            return i;
+        }
+    }
+    RECORDS
+}
+
+/// Reopens the WAL and returns the recovered records.
+async fn reopen(dir: &Path) -> (Wal, Vec<Record>) {
+    let mut recovered = Vec::new();
+    let (wal, _) = Wal::open_with_replay(config(dir), |record, _| recovered.push(record))
+        .await
+        .expect("recovery after crash");
+    (wal, recovered)
+}
+
+/// Checks the recovery invariants for a log where `acked` records were
+/// acknowledged and at most `attempted` were written.
+async fn assert_recovered(dir: &Path, acked: usize, attempted: usize, context: &str) {
+    let (wal, recovered) = reopen(dir).await;
+    assert!(
+        recovered.len() >= acked && recovered.len() <= attempted,
+        "{}: recovered {} records, acked {}, attempted {}",
+        context,
+        recovered.len(),
+        acked,
+        attempted
+    );
+    for (i, recovered_record) in recovered.iter().enumerate() {
+        let expected = record(i);
+        assert_eq!(
+            recovered_record.key, expected.key,
+            "{}: record {} differs",
+            context, i
+        );
+        assert_eq!(
+            recovered_record.value, expected.value,
+            "{}: record {} differs",
+            context, i
+        );
+        assert_eq!(
+            recovered_record.lsn,
+            Some(i as u64 + 1),
+            "{}: LSN gap at {}",
+            context,
+            i
+        );
+    }
+
+    // The repaired log accepts appends and reopens cleanly
+    let n = recovered.len();
+    assert_eq!(wal.next_lsn(), n as u64 + 1, "{}", context);
+    wal.append(&record(n)).await.unwrap();
+    wal.close().await.unwrap();
+
+    let (wal, again) = reopen(dir).await;
+    assert_eq!(again.len(), n + 1, "{}: second reopen", context);
+    assert_eq!(again[n].lsn, Some(n as u64 + 1), "{}", context);
+    wal.close().await.unwrap();
+}
+
+/// Crashes the workload at the `nth` hit of `point` and checks recovery.
+async fn crash_at(point: &str, nth: usize) {
+    let temp_dir = TempDir::new().unwrap();
+    let context = format!("{} after {} hits", point, nth);
+
+    let (wal, _) = Wal::open(config(temp_dir.path())).await.unwrap();
+    fail::cfg(point, &format!("{}*off->return", nth)).unwrap();
+    let acked = run_workload(&wal).await;
+    fail::remove(point);
+    crash(wal);
+
+    // The failed append may or may not have reached the disk
+    let attempted = (acked + 1).min(RECORDS);
+    assert_recovered(temp_dir.path(), acked, attempted, &context).await;
+}
+
+#[tokio::test]
+async fn crash_during_appends() {
+    let scenario = FailScenario::setup();
+    for point in [
+        failpoint::APPEND_BEFORE_WRITE,
+        failpoint::APPEND_TORN_WRITE,
+        failpoint::FSYNC,
+        failpoint::ROTATE_AFTER_FINALIZE,
+        failpoint::SEAL_BEFORE_RENAME,
+    ] {
+        for nth in [0, 1, 7, 15, 16, 17, 33] {
+            crash_at(point, nth).await;
+        }
+    }
+    scenario.teardown();
+}
+
+#[tokio::test]
+async fn crash_during_truncate_from() {
+    let scenario = FailScenario::setup();
+    let temp_dir = TempDir::new().unwrap();
+
+    let (wal, _) = Wal::open(config(temp_dir.path())).await.unwrap();
+    assert_eq!(run_workload(&wal).await, RECORDS);
+    assert!(wal.current_position().await.segment_id >= 2);
+
+    fail::cfg(failpoint::TRUNCATE_AFTER_DELETE, "return").unwrap();
+    assert!(wal.truncate_from_lsn(10).await.is_err());
+    fail::remove(failpoint::TRUNCATE_AFTER_DELETE);
+    crash(wal);
+
+    // Later segments are gone but the cut segment is whole: still a valid prefix
+    let (wal, recovered) = reopen(temp_dir.path()).await;
+    assert!(recovered.len() >= 9 && recovered.len() < RECORDS);
+
+    // Retrying the truncation reaches the intended state
+    wal.truncate_from_lsn(10).await.unwrap();
+    crash(wal);
+    assert_recovered(temp_dir.path(), 9, 9, "retried truncate_from").await;
+    scenario.teardown();
+}
+
+#[tokio::test]
+async fn crash_during_recovery_rewrite() {
+    let scenario = FailScenario::setup();
+    let temp_dir = TempDir::new().unwrap();
+    let skip_config = WalConfig {
+        recovery_mode: RecoveryMode::SkipBadRecords,
+        // A seal would let recovery accept the damaged segment without decoding it
+        seal_segments: false,
+        ..config(temp_dir.path())
+    };
+
+    let (wal, _) = Wal::open(skip_config.clone()).await.unwrap();
+    let mut positions: Vec<Position> = Vec::new();
+    for i in 0..RECORDS {
+        positions.push(wal.append(&record(i)).await.unwrap());
+    }
+    wal.close().await.unwrap();
+
+    // Damage the value of record 3 so it fails its checksum
+    let segment = temp_dir.path().join("000000.wal");
+    let mut data = std::fs::read(&segment).unwrap();
+    data[positions[3].offset as usize + 1024] ^= 0xFF;
+    std::fs::write(&segment, &data).unwrap();
+
+    // Crash between writing the repaired segment and renaming it into place
+    fail::cfg(failpoint::RECOVERY_BEFORE_RENAME, "return").unwrap();
+    assert!(Wal::open(skip_config.clone()).await.is_err());
+    fail::remove(failpoint::RECOVERY_BEFORE_RENAME);
+    assert_eq!(std::fs::read(&segment).unwrap(), data);
+
+    // The interrupted repair is redone from the untouched original
+    let mut keys = Vec::new();
+    let (wal, info) = Wal::open_with_replay(skip_config.clone(), |record, _| keys.push(record.key))
+        .await
+        .unwrap();
+    assert!(info.corruption_detected);
+    assert_eq!(keys.len(), RECORDS - 1);
+    assert!(!keys.contains(&Bytes::from("key0003")));
+    wal.close().await.unwrap();
+
+    let (_wal, info) = Wal::open(skip_config).await.unwrap();
+    assert!(!info.corruption_detected);
+    assert_eq!(info.valid_records, RECORDS as u64 - 1);
+    scenario.teardown();
+}
+
+#[tokio::test]
+async fn crash_during_migration() {
+    let scenario = FailScenario::setup();
+    let temp_dir = TempDir::new().unwrap();
+    let old_dir = temp_dir.path().join("old");
+    let new_dir = temp_dir.path().join("new");
+
+    let (mut wal, _) = Wal::open(config(&old_dir)).await.unwrap();
+    assert_eq!(run_workload(&wal).await, RECORDS);
+
+    // Crash once the new directory is complete, before the old one is emptied
+    fail::cfg(failpoint::MIGRATE_AFTER_MARKER, "return").unwrap();
+    assert!(wal.migrate_to(&new_dir).await.is_err());
+    fail::remove(failpoint::MIGRATE_AFTER_MARKER);
+    crash(wal);
+
+    // Opening the old directory finishes the move and continues in the new one
+    assert_recovered(&old_dir, RECORDS, RECORDS, "migrate_to").await;
+    let leftover = std::fs::read_dir(&old_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("wal".as_ref()))
+        .count();
+    assert_eq!(leftover, 0);
+    scenario.teardown();
+}
+
+#[tokio::test]
+async fn crash_before_migration_marker() {
+    let scenario = FailScenario::setup();
+    let temp_dir = TempDir::new().unwrap();
+    let old_dir = temp_dir.path().join("old");
+    let new_dir = temp_dir.path().join("new");
+
+    let (mut wal, _) = Wal::open(config(&old_dir)).await.unwrap();
+    assert_eq!(run_workload(&wal).await, RECORDS);
+
+    // Crash with every segment copied, before the move is committed
+    fail::cfg(failpoint::MIGRATE_BEFORE_MARKER, "return").unwrap();
+    assert!(wal.migrate_to(&new_dir).await.is_err());
+    fail::remove(failpoint::MIGRATE_BEFORE_MARKER);
+    crash(wal);
+
+    // The log is still in the old directory, and moving it again succeeds
+    let (mut wal, info) = Wal::open(config(&old_dir)).await.unwrap();
+    assert_eq!(info.valid_records, RECORDS as u64);
+    assert!(wal.migrate_to(&new_dir).await.unwrap() > 0);
+    wal.close().await.unwrap();
+
+    assert_recovered(&new_dir, RECORDS, RECORDS, "migrate_to retry").await;
+    let staged = std::fs::read_dir(&new_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("migrating".as_ref()))
+        .count();
+    assert_eq!(staged, 0);
+    scenario.teardown();
+}
@@ -0,0 +1,18 @@
+//! Placement errors.
+
+use thiserror::Error;
+
+/// Errors from planning and migrating shards.
+#[derive(Debug, Error)]
+pub enum PlacementError {
+    #[error("Store error: {0}")]
+    Db(#[from] nori_db::DbError),
+    #[error("WAL error: {0}")]
+    Wal(#[from] nori_wal::SegmentError),
+    #[error("Migration of shard {shard} is {phase:?}, cannot {action}")]
+    Phase {
+        shard: u32,
+        phase: crate::migration::MigrationPhase,
+        action: &'static str,
+    },
+}
@@ -1,5 +1,19 @@
 //! Shard planning + minimal-move diff.
+//!
+//! Shards own contiguous [`KeyRange`]s. The [`SplitPlanner`] decides which
+//! shards have grown too large and where to split them, and a
+//! [`Migration`] moves a range from one store to another without stopping
+//! writes: it copies a snapshot, catches up from the source WAL's tail, and
+//! cuts over once the caller has fenced writes to the range. Each step is
+//! reported as a `ShardEvt` (`Plan`, `SnapshotStart`, `SnapshotDone`,
+//! `Cutover`).
 
-pub fn internal_placeholder() -> &'static str {
-    "norikv-placement"
-}
+pub mod error;
+pub mod migration;
+pub mod range;
+pub mod split;
+
+pub use error::PlacementError;
+pub use migration::{Migration, MigrationPhase, MigrationStats};
+pub use range::KeyRange;
+pub use split::{ShardInfo, SplitConfig, SplitPlan, SplitPlanner};
@@ -0,0 +1,309 @@
+//! Moving a key range between stores.
+//!
+//! A [`Migration`] copies one shard's range from a source [`Db`] to a
+//! target, snapshot first and then catch-up, while the source keeps taking
+//! writes:
+//!
+//! 1. [`Migration::snapshot`] takes a snapshot of the source, opens a tail
+//!    of the source WAL just after it, and copies the range as of the
+//!    snapshot to the target.
+//! 2. [`Migration::catch_up`] applies the writes to the range that the tail
+//!    has seen since, and can be repeated until the lag is small.
+//! 3. Once the caller has stopped sending writes for the range to the
+//!    source, [`Migration::cutover`] applies the rest; the target then holds
+//!    the range exactly as the source does, and can take over.
+//!
+//! The tail is opened before the copy starts, which keeps the source WAL
+//! from purging the records catch-up will need. Each step emits the
+//! matching [`ShardEvt`].
+
+use crate::error::PlacementError;
+use crate::range::KeyRange;
+use nori_db::Db;
+use nori_observe::{obs_emit, Meter, NoopMeter, ShardEvt, ShardKind, VizEvent};
+use nori_wal::{Record, WalTail};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio_stream::StreamExt;
+
+/// Where a [`Migration`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationPhase {
+    /// Nothing copied yet.
+    Planned,
+    /// The snapshot is copied; writes since are being applied.
+    CatchingUp,
+    /// The target holds the range; the migration is over.
+    CutOver,
+    /// A step failed; the target holds a partial copy to discard.
+    Failed,
+}
+
+/// Counts of what a [`Migration`] has copied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    /// Sequence number of the source snapshot.
+    pub snapshot_seq: u64,
+    /// Keys copied from the snapshot.
+    pub snapshot_keys: u64,
+    /// Writes to the range applied from the WAL tail.
+    pub tail_applied: u64,
+    /// Writes outside the range skipped in the WAL tail.
+    pub tail_skipped: u64,
+}
+
+/// Snapshot-then-catch-up copy of one shard's range.
+pub struct Migration {
+    shard: u32,
+    range: KeyRange,
+    phase: MigrationPhase,
+    tail: Option<WalTail>,
+    /// Highest source sequence applied to the target.
+    applied_seq: u64,
+    stats: MigrationStats,
+    meter: Arc<dyn Meter>,
+}
+
+impl Migration {
+    /// Plans moving `range`, owned by `shard`.
+    pub fn new(shard: u32, range: KeyRange) -> Self {
+        Self::with_meter(shard, range, Arc::new(NoopMeter))
+    }
+
+    /// Like [`Migration::new`], reporting progress through `meter`.
+    pub fn with_meter(shard: u32, range: KeyRange, meter: Arc<dyn Meter>) -> Self {
+        Self {
+            shard,
+            range,
+            phase: MigrationPhase::Planned,
+            tail: None,
+            applied_seq: 0,
+            stats: MigrationStats::default(),
+            meter,
+        }
+    }
+
+    pub fn shard(&self) -> u32 {
+        self.shard
+    }
+
+    pub fn range(&self) -> &KeyRange {
+        &self.range
+    }
+
+    pub fn phase(&self) -> MigrationPhase {
+        self.phase
+    }
+
+    pub fn stats(&self) -> MigrationStats {
+        self.stats
+    }
+
+    /// Returns how many source writes the target has yet to see, counting
+    /// writes outside the range.
+    pub fn lag(&self, source: &Db) -> u64 {
+        source.sequence().saturating_sub(self.applied_seq)
+    }
+
+    /// Copies the range as of a fresh snapshot of `source` to `target`.
+    pub async fn snapshot(&mut self, source: &Db, target: &Db) -> Result<(), PlacementError> {
+        self.expect(MigrationPhase::Planned, "snapshot")?;
+        self.emit(ShardKind::SnapshotStart);
+        let result = self.copy_snapshot(source, target).await;
+        self.settle(result, MigrationPhase::CatchingUp)?;
+        self.emit(ShardKind::SnapshotDone);
+        Ok(())
+    }
+
+    /// Applies the source writes made up to now, returning the remaining
+    /// lag.
+    pub async fn catch_up(&mut self, source: &Db, target: &Db) -> Result<u64, PlacementError> {
+        self.expect(MigrationPhase::CatchingUp, "catch up")?;
+        let result = self.apply_tail(source, target).await;
+        self.settle(result, MigrationPhase::CatchingUp)?;
+        Ok(self.lag(source))
+    }
+
+    /// Applies the last source writes and hands the range to the target.
+    ///
+    /// The caller must have stopped sending writes for the range to the
+    /// source; any that arrive later are not copied.
+    pub async fn cutover(&mut self, source: &Db, target: &Db) -> Result<(), PlacementError> {
+        self.expect(MigrationPhase::CatchingUp, "cut over")?;
+        let result = self.apply_tail(source, target).await;
+        self.settle(result, MigrationPhase::CutOver)?;
+        self.tail = None;
+        self.emit(ShardKind::Cutover);
+        Ok(())
+    }
+
+    async fn copy_snapshot(&mut self, source: &Db, target: &Db) -> Result<(), PlacementError> {
+        let snapshot = source.snapshot().await;
+        let seq = snapshot.seq();
+        self.tail = Some(source.wal().tail_from_lsn(seq + 1).await?);
+
+        let (start, end) = self.range.bounds();
+        let now = SystemTime::now();
+        for (key, entry) in snapshot.scan_entries(start, end)? {
+            let Some(value) = entry.value else { continue };
+            // Written as of now with what is left of the TTL
+            let record = match entry.expires_at {
+                Some(expires_at) => {
+                    let ttl = expires_at.duration_since(now).unwrap_or_default();
+                    Record::put_with_ttl(key, value, ttl)
+                }
+                None => Record::put(key, value),
+            };
+            target.apply(record.with_timestamp(now)).await?;
+            self.stats.snapshot_keys += 1;
+        }
+        self.applied_seq = seq;
+        self.stats.snapshot_seq = seq;
+        Ok(())
+    }
+
+    /// Applies tail records to the range up to the source's current
+    /// sequence.
+    async fn apply_tail(&mut self, source: &Db, target: &Db) -> Result<(), PlacementError> {
+        // The tail only sees durable records, and the WAL may be holding
+        // some back until its next batched fsync
+        let goal = source.sequence();
+        source.sync().await?;
+        let tail = self.tail.as_mut().expect("tail opened by snapshot");
+        while self.applied_seq < goal {
+            let Some(next) = tail.next().await else { break };
+            let (record, _) = next?;
+            let Some(lsn) = record.lsn else { continue };
+            self.applied_seq = self.applied_seq.max(lsn);
+            if self.range.contains(&record.key) {
+                target.apply(record).await?;
+                self.stats.tail_applied += 1;
+            } else {
+                self.stats.tail_skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn expect(&self, phase: MigrationPhase, action: &'static str) -> Result<(), PlacementError> {
+        if self.phase == phase {
+            Ok(())
+        } else {
+            Err(PlacementError::Phase {
+                shard: self.shard,
+                phase: self.phase,
+                action,
+            })
+        }
+    }
+
+    fn settle<T>(
+        &mut self,
+        result: Result<T, PlacementError>,
+        next: MigrationPhase,
+    ) -> Result<T, PlacementError> {
+        self.phase = if result.is_ok() {
+            next
+        } else {
+            self.tail = None;
+            MigrationPhase::Failed
+        };
+        result
+    }
+
+    fn emit(&self, kind: ShardKind) {
+        obs_emit!(
+            self.meter,
+            VizEvent::Shard(ShardEvt {
+                shard: self.shard,
+                kind,
+            })
+        );
+    }
+}
+
+impl std::fmt::Debug for Migration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Migration")
+            .field("shard", &self.shard)
+            .field("range", &self.range)
+            .field("phase", &self.phase)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use nori_observe::TestMeter;
+    use std::ops::Bound;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn key(i: u32) -> Bytes {
+        Bytes::from(format!("key{:04}", i))
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[tokio::test]
+    async fn test_snapshot_then_catch_up_then_cutover() {
+        let dir = TempDir::new().unwrap();
+        let source = Db::open(dir.path().join("source")).await.unwrap();
+        let target = Db::open(dir.path().join("target")).await.unwrap();
+        for i in 0..200 {
+            source.put(key(i), "v1").await.unwrap();
+        }
+        source
+            .put_with_ttl(key(60), "short", Duration::from_secs(3_600))
+            .await
+            .unwrap();
+
+        let range = KeyRange::new(key(50), Some(key(150)));
+        let meter = Arc::new(TestMeter::new());
+        let mut migration = Migration::with_meter(7, range.clone(), meter.clone());
+        assert!(matches!(
+            migration.cutover(&source, &target).await,
+            Err(PlacementError::Phase { shard: 7, .. })
+        ));
+        migration.snapshot(&source, &target).await.unwrap();
+        assert_eq!(migration.stats().snapshot_keys, 100);
+        assert_eq!(migration.lag(&source), 0);
+
+        // Writes during the migration, in and out of the range
+        for i in 40..60 {
+            source.put(key(i), "v2").await.unwrap();
+        }
+        source.delete(key(100)).await.unwrap();
+        assert_eq!(migration.catch_up(&source, &target).await.unwrap(), 0);
+        source.put(key(149), "v3").await.unwrap();
+        source.put(key(150), "v3").await.unwrap();
+        assert_eq!(migration.lag(&source), 2);
+
+        migration.cutover(&source, &target).await.unwrap();
+        assert_eq!(migration.phase(), MigrationPhase::CutOver);
+        let stats = migration.stats();
+        assert_eq!((stats.tail_applied, stats.tail_skipped), (12, 11));
+
+        let (start, end) = range.bounds();
+        let copied = target.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(copied, source.scan(start, end).unwrap());
+        assert_eq!(copied.len(), 99);
+        assert_eq!(target.get(&key(149)).unwrap(), Some(Bytes::from("v3")));
+        let ttl = target.snapshot().await.scan_entries(start, end).unwrap();
+        assert!(ttl
+            .iter()
+            .any(|(k, e)| *k == key(60) && e.expires_at.is_some()));
+
+        let kinds: Vec<_> = meter
+            .events()
+            .into_iter()
+            .filter_map(|e| match e {
+                VizEvent::Shard(ShardEvt { shard: 7, kind }) => Some(format!("{:?}", kind)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds, ["SnapshotStart", "SnapshotDone", "Cutover"]);
+    }
+}
@@ -0,0 +1,53 @@
+//! Key ranges owned by shards.
+
+use bytes::Bytes;
+use std::ops::Bound;
+
+/// The keys from `start` (inclusive) to `end` (exclusive), or to the end of
+/// the key space if `end` is `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyRange {
+    pub start: Bytes,
+    pub end: Option<Bytes>,
+}
+
+impl KeyRange {
+    /// Creates the range `[start, end)`.
+    pub fn new(start: impl Into<Bytes>, end: Option<Bytes>) -> Self {
+        Self {
+            start: start.into(),
+            end,
+        }
+    }
+
+    /// The whole key space.
+    pub fn full() -> Self {
+        Self::new(Bytes::new(), None)
+    }
+
+    /// Returns true if `key` is in the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.start.as_ref() && self.end.as_ref().map_or(true, |end| key < end.as_ref())
+    }
+
+    /// Splits the range at `key` into `[start, key)` and `[key, end)`, or
+    /// returns `None` if either half would be empty.
+    pub fn split_at(&self, key: &[u8]) -> Option<(KeyRange, KeyRange)> {
+        if key <= self.start.as_ref() || !self.contains(key) {
+            return None;
+        }
+        let key = Bytes::copy_from_slice(key);
+        let left = KeyRange::new(self.start.clone(), Some(key.clone()));
+        let right = KeyRange::new(key, self.end.clone());
+        Some((left, right))
+    }
+
+    /// Returns the range as bounds, for scans.
+    pub fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        let end = match &self.end {
+            Some(end) => Bound::Excluded(end.as_ref()),
+            None => Bound::Unbounded,
+        };
+        (Bound::Included(self.start.as_ref()), end)
+    }
+}
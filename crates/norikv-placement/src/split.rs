@@ -0,0 +1,205 @@
+//! Planning key-range splits.
+//!
+//! A shard whose data has grown past [`SplitConfig::max_shard_bytes`] is
+//! split in two at a key near the middle of its data. Sizes and candidate
+//! keys come from the shard's tables: [`ShardInfo::from_version`] sums the
+//! sizes of the tables overlapping the range and samples their boundary
+//! keys, which is cheap and good enough to balance the halves roughly.
+
+use bytes::Bytes;
+use nori_lsm::Version;
+use nori_observe::{obs_emit, Meter, NoopMeter, ShardEvt, ShardKind, VizEvent};
+use std::sync::Arc;
+
+use crate::range::KeyRange;
+
+/// Settings for a [`SplitPlanner`].
+#[derive(Debug, Clone)]
+pub struct SplitConfig {
+    /// Shards larger than this are split (default: 512 MiB).
+    pub max_shard_bytes: u64,
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        Self {
+            max_shard_bytes: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// What the planner knows about a shard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardInfo {
+    pub id: u32,
+    pub range: KeyRange,
+    /// Approximate bytes stored in the range.
+    pub size_bytes: u64,
+    /// Keys in the range, sorted, roughly evenly spread over its data.
+    pub samples: Vec<Bytes>,
+}
+
+impl ShardInfo {
+    /// Describes the part of `version`'s tables that falls in `range`.
+    ///
+    /// Tables partly in the range count in full, and the memtable is not
+    /// counted, so the size is approximate.
+    pub fn from_version(id: u32, range: KeyRange, version: &Version) -> Self {
+        let mut size_bytes = 0;
+        let mut samples = Vec::new();
+        for table in version.tables() {
+            let below = range
+                .end
+                .as_ref()
+                .is_some_and(|end| table.smallest_key >= end);
+            if below || table.largest_key < range.start {
+                continue;
+            }
+            size_bytes += table.file_size;
+            for key in [&table.smallest_key, &table.largest_key] {
+                if range.contains(key) {
+                    samples.push(key.clone());
+                }
+            }
+        }
+        samples.sort();
+        samples.dedup();
+        Self {
+            id,
+            range,
+            size_bytes,
+            samples,
+        }
+    }
+}
+
+/// A planned split of one shard into two.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitPlan {
+    pub shard: u32,
+    /// ID of the shard taking the upper half.
+    pub new_shard: u32,
+    pub split_key: Bytes,
+    /// What `shard` keeps.
+    pub left: KeyRange,
+    /// What `new_shard` takes.
+    pub right: KeyRange,
+}
+
+/// Decides which shards to split, and where.
+pub struct SplitPlanner {
+    config: SplitConfig,
+    meter: Arc<dyn Meter>,
+}
+
+impl SplitPlanner {
+    /// Creates a planner.
+    pub fn new(config: SplitConfig) -> Self {
+        Self::with_meter(config, Arc::new(NoopMeter))
+    }
+
+    /// Like [`SplitPlanner::new`], reporting each plan through `meter`.
+    pub fn with_meter(config: SplitConfig, meter: Arc<dyn Meter>) -> Self {
+        Self { config, meter }
+    }
+
+    /// Plans a split for every shard over the size limit, giving the new
+    /// shards IDs from `next_shard_id` on.
+    ///
+    /// A shard is split at the median of its samples; one with no sample
+    /// strictly inside its range cannot be split and is skipped.
+    pub fn plan(&self, shards: &[ShardInfo], next_shard_id: u32) -> Vec<SplitPlan> {
+        let mut plans = Vec::new();
+        for shard in shards {
+            if shard.size_bytes <= self.config.max_shard_bytes {
+                continue;
+            }
+            let candidates: Vec<_> = shard
+                .samples
+                .iter()
+                .filter(|key| shard.range.split_at(key).is_some())
+                .collect();
+            let Some(split_key) = candidates.get(candidates.len() / 2) else {
+                continue;
+            };
+            let (left, right) = shard.range.split_at(split_key).expect("candidate splits");
+            let plan = SplitPlan {
+                shard: shard.id,
+                new_shard: next_shard_id + plans.len() as u32,
+                split_key: (*split_key).clone(),
+                left,
+                right,
+            };
+            obs_emit!(
+                self.meter,
+                VizEvent::Shard(ShardEvt {
+                    shard: plan.shard,
+                    kind: ShardKind::Plan,
+                })
+            );
+            plans.push(plan);
+        }
+        plans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nori_observe::TestMeter;
+
+    fn shard(id: u32, range: KeyRange, size_bytes: u64, samples: &[&'static str]) -> ShardInfo {
+        ShardInfo {
+            id,
+            range,
+            size_bytes,
+            samples: samples
+                .iter()
+                .map(|s| Bytes::from_static(s.as_bytes()))
+                .collect(),
+        }
+    }
+
+    #[cfg_attr(feature = "obs-off", ignore = "relies on reported telemetry")]
+    #[test]
+    fn test_splits_large_shards_at_median() {
+        let meter = Arc::new(TestMeter::new());
+        let config = SplitConfig {
+            max_shard_bytes: 100,
+        };
+        let planner = SplitPlanner::with_meter(config, meter.clone());
+        let m = Bytes::from("m");
+        let shards = [
+            shard(
+                1,
+                KeyRange::new("", Some(m.clone())),
+                500,
+                &["", "b", "d", "f"],
+            ),
+            shard(2, KeyRange::new(m.clone(), None), 50, &["n", "p"]),
+            // Too big, but nothing to split at
+            shard(3, KeyRange::new("x", None), 500, &["x"]),
+        ];
+
+        let plans = planner.plan(&shards, 10);
+        assert_eq!(
+            plans,
+            vec![SplitPlan {
+                shard: 1,
+                new_shard: 10,
+                split_key: Bytes::from("d"),
+                left: KeyRange::new("", Some(Bytes::from("d"))),
+                right: KeyRange::new("d", Some(m)),
+            }]
+        );
+        let events = meter.events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            VizEvent::Shard(ShardEvt {
+                shard: 1,
+                kind: ShardKind::Plan
+            })
+        ));
+    }
+}